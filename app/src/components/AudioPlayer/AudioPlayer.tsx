@@ -279,7 +279,8 @@ export function AudioPlayer() {
                 // Play via native audio
                 debug.log('Invoking play_audio_to_devices...');
                 try {
-                  await platform.audio.playToDevices(audioData, deviceIds);
+                  const nowPlaying = usePlayerStore.getState().title ?? undefined;
+                  await platform.audio.playToDevices(audioData, deviceIds, nowPlaying);
                   debug.log('play_audio_to_devices completed successfully');
 
                   // Mark that we're using native playback
@@ -737,7 +738,7 @@ export function AudioPlayer() {
           const audioData = new Uint8Array(await response.arrayBuffer());
 
           // Play via native audio
-          await platform.audio.playToDevices(audioData, deviceIds);
+          await platform.audio.playToDevices(audioData, deviceIds, title ?? undefined);
 
           // Mark that we're using native playback
           isUsingNativePlaybackRef.current = true;
@@ -0,0 +1,66 @@
+import { Circle, Square } from 'lucide-react';
+import { useEffect, useState } from 'react';
+import { useSystemAudioCapture } from '@/lib/hooks/useSystemAudioCapture';
+import { usePlatform } from '@/platform/PlatformContext';
+
+const MAX_DURATION_SECONDS = 300;
+
+function formatElapsed(seconds: number) {
+  const minutes = Math.floor(seconds / 60);
+  const secs = Math.floor(seconds % 60);
+  return `${minutes}:${secs.toString().padStart(2, '0')}`;
+}
+
+/**
+ * Floating always-on-top recorder opened with `open_mini_recorder`.
+ * The finished capture stays available to the main window by session id.
+ */
+export function MiniRecorder() {
+  const platform = usePlatform();
+  const [level, setLevel] = useState(0);
+  const { isRecording, duration, error, startRecording, stopRecording } = useSystemAudioCapture({
+    maxDurationSeconds: MAX_DURATION_SECONDS,
+  });
+
+  useEffect(() => {
+    let unsubscribe: (() => void) | undefined;
+    platform.audio
+      .subscribeCaptureLevel((event) => setLevel(event.peak))
+      .then((fn) => {
+        unsubscribe = fn;
+      });
+    return () => unsubscribe?.();
+  }, [platform]);
+
+  return (
+    <div
+      data-tauri-drag-region
+      className="flex h-screen items-center gap-3 rounded-lg border bg-background px-3 select-none"
+    >
+      <button
+        type="button"
+        onClick={() => void (isRecording ? stopRecording() : startRecording())}
+        className="h-9 w-9 rounded-full flex items-center justify-center bg-muted hover:bg-muted/80"
+        aria-label={isRecording ? 'Stop recording' : 'Start recording'}
+      >
+        {isRecording ? (
+          <Square className="h-4 w-4 fill-current" />
+        ) : (
+          <Circle className="h-4 w-4 fill-red-500 text-red-500" />
+        )}
+      </button>
+
+      <div data-tauri-drag-region className="flex flex-1 flex-col gap-1">
+        <div className="h-1.5 w-full overflow-hidden rounded-full bg-muted">
+          <div
+            className="h-full bg-green-500 transition-[width] duration-100"
+            style={{ width: `${isRecording ? Math.min(level, 1) * 100 : 0}%` }}
+          />
+        </div>
+        <span className="text-xs text-muted-foreground truncate">
+          {error ?? (isRecording ? formatElapsed(duration) : 'Ready')}
+        </span>
+      </div>
+    </div>
+  );
+}
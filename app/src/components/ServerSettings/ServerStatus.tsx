@@ -1,13 +1,38 @@
-import { Loader2, XCircle } from 'lucide-react';
+import { useQuery } from '@tanstack/react-query';
+import { FolderOpen, Loader2, XCircle } from 'lucide-react';
 import { Badge } from '@/components/ui/badge';
+import { Button } from '@/components/ui/button';
 import { Card, CardContent, CardHeader, CardTitle } from '@/components/ui/card';
 import { useServerHealth } from '@/lib/hooks/useServer';
+import { usePlatform } from '@/platform/PlatformContext';
+import type { AppFolder, DataPathsMode } from '@/platform/types';
 import { useServerStore } from '@/stores/serverStore';
 import { ModelProgress } from './ModelProgress';
 
+const FOLDERS: { folder: AppFolder; label: string }[] = [
+  { folder: 'data_dir', label: 'Data' },
+  { folder: 'logs_dir', label: 'Logs' },
+  { folder: 'models_dir', label: 'Models' },
+];
+
+const PATHS_MODE_LABELS: Record<DataPathsMode, string> = {
+  default: 'Default',
+  argument: 'Custom (--data-dir)',
+  portable: 'Portable',
+};
+
 export function ServerStatus() {
+  const platform = usePlatform();
   const { data: health, isLoading, error } = useServerHealth();
   const serverUrl = useServerStore((state) => state.serverUrl);
+  const openFolder = platform.filesystem.openFolder;
+  const getDataPaths = platform.metadata.getDataPaths;
+  const { data: dataPaths } = useQuery({
+    queryKey: ['dataPaths'],
+    queryFn: async () => (getDataPaths ? getDataPaths() : null),
+    enabled: !!getDataPaths,
+    staleTime: Infinity,
+  });
 
   return (
     <Card>
@@ -20,6 +45,33 @@ export function ServerStatus() {
           <div className="font-mono text-sm">{serverUrl}</div>
         </div>
 
+        {dataPaths && (
+          <div>
+            <div className="text-sm text-muted-foreground mb-1">
+              Data Directory ({PATHS_MODE_LABELS[dataPaths.mode]})
+            </div>
+            <div className="font-mono text-sm break-all">{dataPaths.appDataDir}</div>
+          </div>
+        )}
+
+        {openFolder && (
+          <div className="flex flex-wrap gap-2">
+            {FOLDERS.map(({ folder, label }) => (
+              <Button
+                key={folder}
+                variant="outline"
+                size="sm"
+                onClick={() =>
+                  openFolder(folder).catch((e) => console.error(`Failed to open ${folder}:`, e))
+                }
+              >
+                <FolderOpen className="h-4 w-4 mr-2" />
+                Open {label} Folder
+              </Button>
+            ))}
+          </div>
+        )}
+
         {/* Model download progress */}
         <div className="space-y-2">
           <ModelProgress modelName="qwen-tts-1.7B" displayName="Qwen TTS 1.7B" />
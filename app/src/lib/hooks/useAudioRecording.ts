@@ -149,14 +149,24 @@ export function useAudioRecording({
         }
       }, 100);
     } catch (err) {
-      const errorMessage =
+      let errorMessage =
         err instanceof Error
           ? err.message
           : 'Failed to access microphone. Please check permissions.';
+      if (err instanceof DOMException && err.name === 'NotAllowedError') {
+        const status = await platform.permissions
+          .getStatus()
+          .then((statuses) => statuses.microphone)
+          .catch(() => undefined);
+        if (status === 'denied' || status === 'not_determined') {
+          errorMessage =
+            'Microphone permission is not granted. Allow Voicebox to use the microphone in your system privacy settings and try again.';
+        }
+      }
       setError(errorMessage);
       setIsRecording(false);
     }
-  }, [maxDurationSeconds, onRecordingComplete]);
+  }, [maxDurationSeconds, onRecordingComplete, platform]);
 
   const stopRecording = useCallback(() => {
     if (mediaRecorderRef.current && isRecording) {
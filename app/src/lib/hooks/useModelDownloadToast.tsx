@@ -3,6 +3,7 @@ import { useCallback, useEffect, useRef } from 'react';
 import { Progress } from '@/components/ui/progress';
 import { useToast } from '@/components/ui/use-toast';
 import type { ModelProgress } from '@/lib/api/types';
+import { usePlatform } from '@/platform/PlatformContext';
 import { useServerStore } from '@/stores/serverStore';
 
 interface UseModelDownloadToastOptions {
@@ -25,6 +26,7 @@ export function useModelDownloadToast({
   onError,
 }: UseModelDownloadToastOptions) {
   const { toast } = useToast();
+  const platform = usePlatform();
   const serverUrl = useServerStore((state) => state.serverUrl);
   const toastIdRef = useRef<string | null>(null);
   // biome-ignore lint: Using any for toast update ref to handle complex toast types
@@ -68,6 +70,16 @@ export function useModelDownloadToast({
     toastIdRef.current = toastResult.id;
     toastUpdateRef.current = toastResult.update;
 
+    // Mirror progress on the dock/taskbar
+    const reportProgress = (progress: ModelProgress) => {
+      const isComplete = progress.status === 'complete' || progress.progress >= 100;
+      platform.lifecycle.reportModelDownload({
+        modelName,
+        status: isComplete ? 'complete' : progress.status === 'error' ? 'error' : 'downloading',
+        fraction: progress.total > 0 ? progress.progress / 100 : 0,
+      });
+    };
+
     // Subscribe to progress updates via Server-Sent Events
     const eventSourceUrl = `${serverUrl}/models/progress/${modelName}`;
     console.log('[useModelDownloadToast] Creating EventSource to:', eventSourceUrl);
@@ -81,6 +93,7 @@ export function useModelDownloadToast({
       console.log('[useModelDownloadToast] Received SSE message:', event.data);
       try {
         const progress = JSON.parse(event.data) as ModelProgress;
+        reportProgress(progress);
 
         // Update toast with progress
         if (toastIdRef.current && toastUpdateRef.current) {
@@ -183,6 +196,7 @@ export function useModelDownloadToast({
       console.log('[useModelDownloadToast] EventSource readyState:', eventSource.readyState);
       eventSource.close();
       eventSourceRef.current = null;
+      platform.lifecycle.reportModelDownload({ modelName, status: 'aborted' });
 
       // Show error toast
       if (toastIdRef.current && toastUpdateRef.current) {
@@ -205,10 +219,11 @@ export function useModelDownloadToast({
       if (eventSourceRef.current) {
         eventSourceRef.current.close();
         eventSourceRef.current = null;
+        platform.lifecycle.reportModelDownload({ modelName, status: 'aborted' });
       }
       // Note: We don't dismiss the toast here as it might still be showing completion state
     };
-  }, [enabled, serverUrl, modelName, displayName, toast, formatBytes, onComplete, onError]);
+  }, [enabled, serverUrl, modelName, displayName, toast, formatBytes, onComplete, onError, platform]);
 
   return {
     isTracking: enabled && eventSourceRef.current !== null,
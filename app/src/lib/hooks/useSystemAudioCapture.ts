@@ -54,16 +54,10 @@ export function useSystemAudioCapture({
       isRecordingRef.current = true;
       startTimeRef.current = Date.now();
 
-      // Start timer
+      // Start timer; the capture itself stops at max duration
       timerRef.current = window.setInterval(() => {
         if (startTimeRef.current) {
-          const elapsed = (Date.now() - startTimeRef.current) / 1000;
-          setDuration(elapsed);
-
-          // Auto-stop at max duration
-          if (elapsed >= maxDurationSeconds && stopRecordingRef.current) {
-            void stopRecordingRef.current();
-          }
+          setDuration((Date.now() - startTimeRef.current) / 1000);
         }
       }, 100);
     } catch (err) {
@@ -107,11 +101,27 @@ export function useSystemAudioCapture({
     }
   }, [isRecording, onRecordingComplete, platform]);
 
-  // Store stopRecording in ref for use in timer
+  // Store stopRecording in ref for use in the capture-stopped listener
   useEffect(() => {
     stopRecordingRef.current = stopRecording;
   }, [stopRecording]);
 
+  // Collect the recording when the capture hits its limit or fails on its
+  // own, rather than trusting a timer here to match the native one
+  useEffect(() => {
+    let unsubscribe: (() => void) | undefined;
+    platform.audio
+      .subscribeCaptureStopped((stopped) => {
+        if (stopped.reason !== 'manual' && isRecordingRef.current && stopRecordingRef.current) {
+          void stopRecordingRef.current();
+        }
+      })
+      .then((fn) => {
+        unsubscribe = fn;
+      });
+    return () => unsubscribe?.();
+  }, [platform]);
+
   const cancelRecording = useCallback(async () => {
     if (isRecordingRef.current) {
       await stopRecording();
@@ -8,8 +8,12 @@ export interface FileFilter {
   extensions: string[];
 }
 
+export type AppFolder = 'data_dir' | 'logs_dir' | 'recordings_dir' | 'models_dir';
+
 export interface PlatformFilesystem {
   saveFile(filename: string, blob: Blob, filters?: FileFilter[]): Promise<void>;
+  /** Opens the folder in the system file manager; resolves to its absolute path. */
+  openFolder?(folder: AppFolder): Promise<string>;
 }
 
 export interface UpdateStatus {
@@ -37,6 +41,66 @@ export interface AudioDevice {
   id: string;
   name: string;
   is_default: boolean;
+  state: 'active' | 'disabled' | 'unplugged';
+  // 0 when the device isn't active
+  default_sample_rate: number;
+  channels: number;
+  // The OS's own id: the endpoint ID on Windows, the device UID on macOS
+  endpoint_id: string | null;
+}
+
+export interface CaptureLevel {
+  session: number;
+  rms: number;
+  peak: number;
+  elapsed_ms: number;
+}
+
+export interface CaptureStopped {
+  session: number;
+  reason: 'timeout' | 'manual' | 'error';
+  elapsed_secs: number;
+}
+
+export interface PlaybackProgress {
+  playback_id: number;
+  position_secs: number;
+  duration_secs: number;
+  device_ids: string[];
+  paused: boolean;
+}
+
+/** What one device was sent over the last ~100 ms, 0 to 1. */
+export interface PlaybackLevel {
+  playback_id: number;
+  device_id: string;
+  peak: number;
+  rms: number;
+  position_secs: number;
+}
+
+export interface PlaybackFinished {
+  playback_id: number;
+  reason: 'completed' | 'stopped' | 'error';
+  position_secs: number;
+  /** Devices that went away while it played on them. */
+  lost_device_ids: string[];
+}
+
+export interface AudioDevicesChanged {
+  devices: AudioDevice[];
+  added: string[];
+  removed: string[];
+  default_changed: boolean;
+}
+
+export interface PlaybackStatus {
+  playing: boolean;
+  paused: boolean;
+  title: string | null;
+  position_secs: number;
+  duration_secs: number;
+  device_ids: string[];
 }
 
 export interface PlatformAudio {
@@ -44,20 +108,109 @@ export interface PlatformAudio {
   startSystemAudioCapture(maxDurationSecs: number): Promise<void>;
   stopSystemAudioCapture(): Promise<Blob>;
   listOutputDevices(): Promise<AudioDevice[]>;
-  playToDevices(audioData: Uint8Array, deviceIds: string[]): Promise<void>;
+  /** In milliseconds, as the device last reported it; null until it has played. */
+  getDeviceLatency(deviceId: string): Promise<number | null>;
+  /** Plays a sine on one device, 440 Hz by default; resolves once it has played. */
+  playTestTone(deviceId: string, durationSecs: number, frequencyHz?: number): Promise<'completed' | 'cancelled'>;
+  /** `title` is what the system's now-playing UI shows. */
+  playToDevices(audioData: Uint8Array, deviceIds: string[], title?: string): Promise<void>;
+  /** Plays a file in the data dir, read from disk as it plays. */
+  playFileToDevices(path: string, deviceIds: string[], title?: string): Promise<void>;
   stopPlayback(): void;
+  /** What is playing to devices, e.g. still after a reload. */
+  getPlaybackStatus(): Promise<PlaybackStatus>;
+  subscribeCaptureLevel(callback: (level: CaptureLevel) => void): Promise<() => void>;
+  subscribeCaptureStopped(callback: (stopped: CaptureStopped) => void): Promise<() => void>;
+  subscribePlaybackProgress(callback: (progress: PlaybackProgress) => void): Promise<() => void>;
+  subscribePlaybackLevel(callback: (level: PlaybackLevel) => void): Promise<() => void>;
+  subscribePlaybackFinished(callback: (finished: PlaybackFinished) => void): Promise<() => void>;
+  /** Output devices came or went, or the default moved. */
+  subscribeDevicesChanged(callback: (changed: AudioDevicesChanged) => void): Promise<() => void>;
+}
+
+export interface ModelDownloadReport {
+  modelName: string;
+  status: 'downloading' | 'complete' | 'error' | 'aborted';
+  fraction?: number; // 0-1
+}
+
+/** Extra sidecar launch options; only allow-listed flags and variable prefixes are accepted. */
+export interface ServerLaunchOptions {
+  env?: Record<string, string>;
+  /** e.g. `--offline` or `--idle-timeout-minutes=30`. */
+  extra_args?: string[];
+  models_dir?: string | null;
+  log_level?: string | null;
+}
+
+/** Sent while startServer waits: for each line the server prints, and every few seconds while it's quiet. */
+export interface StartupProgress {
+  phase:
+    | 'launching'
+    | 'importing_modules'
+    | 'initializing_database'
+    | 'applying_migrations'
+    | 'starting_http_server';
+  line: string | null;
+  elapsed_secs: number;
 }
 
 export interface PlatformLifecycle {
-  startServer(remote?: boolean): Promise<string>;
+  /**
+   * Rejects with `restart_required` if the server is already running with other options. `startupTimeoutSecs`
+   * overrides the server startup timeout setting, e.g. on slow hardware.
+   */
+  startServer(remote?: boolean, options?: ServerLaunchOptions, startupTimeoutSecs?: number): Promise<string>;
+  /** Rejects with a `server` error naming the PID if the server is still running after being killed. */
   stopServer(): Promise<void>;
+  /**
+   * Stops the server, waits for its port to be free and starts it again; `remote` and `options` keep
+   * what it had unless given.
+   */
+  restartServer(remote?: boolean, options?: ServerLaunchOptions): Promise<string>;
+  /**
+   * Uses a Voicebox server running elsewhere instead of the bundled one, which is stopped. Rejects with a
+   * `server` error saying whether the host is unreachable or isn't a Voicebox server.
+   */
+  connectToServer(url: string, authToken?: string): Promise<string>;
+  /** The bearer token the server requires from other devices, or null when it requires none. */
+  getServerAuthToken(): Promise<string | null>;
   setKeepServerRunning(keep: boolean): Promise<void>;
+  /** Whether a crashed server is started again on its own; on by default, for this session. */
+  setAutoRestart(enabled: boolean): Promise<void>;
+  subscribeStartupProgress(callback: (progress: StartupProgress) => void): Promise<() => void>;
   setupWindowCloseHandler(): Promise<void>;
+  reportModelDownload(report: ModelDownloadReport): void;
   onServerReady?: () => void;
 }
 
+export type PermissionKind = 'screen_recording' | 'microphone' | 'notifications';
+
+export type PermissionStatus = 'granted' | 'denied' | 'not_determined' | 'not_applicable';
+
+export interface PlatformPermissions {
+  getStatus(): Promise<Record<PermissionKind, PermissionStatus>>;
+  /** Shows the native prompt, or opens the settings pane if it was already denied. */
+  request(kind: PermissionKind): Promise<PermissionStatus>;
+  /** Whether system audio capture may run; 'granted' where no permission gates it. */
+  checkCapture(): Promise<Exclude<PermissionStatus, 'not_applicable'>>;
+  /** Asks for the permission system audio capture needs, as request() does. */
+  requestCapture(): Promise<Exclude<PermissionStatus, 'not_applicable'>>;
+}
+
+export type DataPathsMode = 'default' | 'argument' | 'portable';
+
+export interface DataPaths {
+  /** 'argument' for a --data-dir launch, 'portable' for a portable.txt install. */
+  mode: DataPathsMode;
+  appDataDir: string;
+  logsDir: string;
+}
+
 export interface PlatformMetadata {
   getVersion(): Promise<string>;
+  /** Where the desktop app keeps its data; absent on the web. */
+  getDataPaths?(): Promise<DataPaths>;
   isTauri: boolean;
 }
 
@@ -67,4 +220,5 @@ export interface Platform {
   audio: PlatformAudio;
   lifecycle: PlatformLifecycle;
   metadata: PlatformMetadata;
+  permissions: PlatformPermissions;
 }
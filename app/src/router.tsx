@@ -1,7 +1,14 @@
-import { createRootRoute, createRoute, createRouter, Outlet } from '@tanstack/react-router';
+import {
+  createRootRoute,
+  createRoute,
+  createRouter,
+  Outlet,
+  useRouterState,
+} from '@tanstack/react-router';
 import { AppFrame } from '@/components/AppFrame/AppFrame';
 import { AudioTab } from '@/components/AudioTab/AudioTab';
 import { MainEditor } from '@/components/MainEditor/MainEditor';
+import { MiniRecorder } from '@/components/MiniRecorder/MiniRecorder';
 import { ModelsTab } from '@/components/ModelsTab/ModelsTab';
 import { ServerTab } from '@/components/ServerTab/ServerTab';
 import { Sidebar } from '@/components/Sidebar';
@@ -15,6 +22,15 @@ const isMacOS = () => navigator.platform.toLowerCase().includes('mac');
 
 // Root layout component
 function RootLayout() {
+  const pathname = useRouterState({ select: (state) => state.location.pathname });
+  // The mini recorder window renders without the app chrome
+  if (pathname === '/mini-recorder') {
+    return <Outlet />;
+  }
+  return <AppLayout />;
+}
+
+function AppLayout() {
   // Monitor active downloads/generations and show toasts for them
   const activeDownloads = useRestoreActiveTasks();
 
@@ -114,6 +130,13 @@ const serverRoute = createRoute({
   component: ServerTab,
 });
 
+// Mini recorder route (its own always-on-top window)
+const miniRecorderRoute = createRoute({
+  getParentRoute: () => rootRoute,
+  path: '/mini-recorder',
+  component: MiniRecorder,
+});
+
 // Route tree
 const routeTree = rootRoute.addChildren([
   indexRoute,
@@ -122,6 +145,7 @@ const routeTree = rootRoute.addChildren([
   audioRoute,
   modelsRoute,
   serverRoute,
+  miniRecorderRoute,
 ]);
 
 // Create router
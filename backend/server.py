@@ -64,9 +64,43 @@ if __name__ == "__main__":
             default=None,
             help="Data directory for database, profiles, and generated audio",
         )
+        parser.add_argument(
+            "--idle-timeout-minutes",
+            type=int,
+            default=None,
+            help="Shut down after this many minutes without HTTP requests",
+        )
+        parser.add_argument(
+            "--log-level",
+            type=str,
+            default="info",
+            choices=["critical", "error", "warning", "info", "debug", "trace"],
+            help="Log level for the server and uvicorn",
+        )
+        parser.add_argument(
+            "--no-access-log",
+            action="store_true",
+            help="Disable uvicorn's per-request access log",
+        )
+        parser.add_argument(
+            "--offline",
+            action="store_true",
+            help="Skip Hugging Face network calls and use cached models only",
+        )
         args = parser.parse_args()
         logger.info(f"Parsed arguments: host={args.host}, port={args.port}, data_dir={args.data_dir}")
 
+        # uvicorn's "trace" has no stdlib equivalent; treat it as debug for our own loggers
+        logging.getLogger().setLevel("DEBUG" if args.log_level == "trace" else args.log_level.upper())
+
+        if args.offline:
+            # The desktop app also sets HF_HUB_OFFLINE in the environment, which is
+            # what huggingface_hub reads at import time; this covers manual runs
+            import os
+            os.environ["HF_HUB_OFFLINE"] = "1"
+            os.environ["TRANSFORMERS_OFFLINE"] = "1"
+            logger.info("Offline mode: using cached models only")
+
         # Set data directory if provided
         if args.data_dir:
             logger.info(f"Setting data directory to: {args.data_dir}")
@@ -77,12 +111,25 @@ if __name__ == "__main__":
         database.init_db()
         logger.info("Database initialized successfully")
 
+        import os
+        from backend.utils.auth import AUTH_TOKEN_ENV, install_token_auth
+        auth_token = os.environ.get(AUTH_TOKEN_ENV)
+        if auth_token:
+            logger.info("Requests from other machines need the bearer token")
+            install_token_auth(app, auth_token)
+
+        if args.idle_timeout_minutes:
+            from backend.utils.idle import install_idle_shutdown
+            logger.info(f"Idle shutdown after {args.idle_timeout_minutes} minutes")
+            install_idle_shutdown(app, args.idle_timeout_minutes * 60)
+
         logger.info(f"Starting uvicorn server on {args.host}:{args.port}...")
         uvicorn.run(
             app,
             host=args.host,
             port=args.port,
-            log_level="info",
+            log_level=args.log_level,
+            access_log=not args.no_access_log,
         )
     except Exception as e:
         logger.error(f"Server startup failed: {e}", exc_info=True)
@@ -0,0 +1,46 @@
+"""
+Bearer-token auth for servers the desktop app exposes on the LAN.
+"""
+
+import hmac
+import ipaddress
+import logging
+
+from fastapi.responses import JSONResponse
+
+logger = logging.getLogger(__name__)
+
+# The desktop app passes the token here rather than as an argument, which
+# other users on the machine could read from the process list
+AUTH_TOKEN_ENV = "VOICEBOX_AUTH_TOKEN"
+
+
+def _is_loopback(host) -> bool:
+    try:
+        return ipaddress.ip_address(host).is_loopback
+    except ValueError:
+        return False
+
+
+def install_token_auth(app, token: str):
+    """Refuse requests from other machines unless they send `token` as a bearer token.
+
+    Clients on this machine, the desktop app among them, are trusted as they
+    are when the server only listens on 127.0.0.1.
+    """
+    expected = f"Bearer {token}".encode()
+
+    @app.middleware("http")
+    async def require_token(request, call_next):
+        client = request.client.host if request.client else None
+        if request.method == "OPTIONS" or _is_loopback(client):
+            return await call_next(request)
+        supplied = request.headers.get("authorization", "").encode()
+        if not hmac.compare_digest(supplied, expected):
+            logger.warning(f"Refused {request.method} {request.url.path} from {client}: missing or wrong token")
+            return JSONResponse(
+                status_code=401,
+                content={"detail": "Missing or wrong bearer token"},
+                headers={"WWW-Authenticate": "Bearer"},
+            )
+        return await call_next(request)
@@ -0,0 +1,44 @@
+"""
+Idle shutdown for servers left running after the desktop app closes.
+"""
+
+import asyncio
+import logging
+import os
+import signal
+import time
+
+logger = logging.getLogger(__name__)
+
+
+def install_idle_shutdown(app, timeout_secs: int, check_interval_secs: int = 30):
+    """Terminate the process after `timeout_secs` without HTTP requests.
+
+    Active generations and downloads count as activity so a long job is never
+    interrupted.
+    """
+    last_activity = {"at": time.monotonic()}
+
+    @app.middleware("http")
+    async def track_activity(request, call_next):
+        last_activity["at"] = time.monotonic()
+        return await call_next(request)
+
+    async def watch():
+        from .tasks import get_task_manager
+
+        while True:
+            await asyncio.sleep(check_interval_secs)
+            task_manager = get_task_manager()
+            if task_manager.get_active_generations() or task_manager.get_active_downloads():
+                last_activity["at"] = time.monotonic()
+                continue
+            idle_for = time.monotonic() - last_activity["at"]
+            if idle_for >= timeout_secs:
+                logger.info(f"No requests for {int(idle_for)}s, shutting down idle server")
+                os.kill(os.getpid(), signal.SIGTERM)
+                return
+
+    @app.on_event("startup")
+    async def start_idle_watch():
+        asyncio.create_task(watch())
@@ -0,0 +1,258 @@
+use crate::audio_capture::AudioCaptureState;
+use crate::settings::{RecentItem, SettingsState};
+use crate::tray::TrayServerStatus;
+use std::path::{Path, PathBuf};
+use tauri::menu::{AboutMetadata, Menu, MenuEvent, MenuItem, PredefinedMenuItem, Submenu};
+use tauri::{AppHandle, Manager, Wry};
+use tracing::{error, warn};
+
+/// Menu ids of Open Recent entries are this followed by the file's path.
+const RECENT_FILE_PREFIX: &str = "recent_file:";
+
+/// Longest capture started from the menu, same as the hotkey's.
+const MENU_CAPTURE_MAX_SECS: u32 = 300;
+
+/// Events after which item enabled-states are recomputed.
+const REFRESH_EVENTS: &[&str] = &[
+    "server-started",
+    "server-stopped",
+    "server-crashed",
+    "server-health-changed",
+    "capture-started",
+    "capture-stopped",
+];
+
+/// Items whose enabled state follows the app state.
+pub struct AppMenu {
+    server_restart: MenuItem<Wry>,
+    capture_start: MenuItem<Wry>,
+    capture_stop: MenuItem<Wry>,
+    open_recent: Submenu<Wry>,
+}
+
+/// Replace the default macOS menu, which lacks Settings and Capture and
+/// leaves shortcuts like Cmd+, dead. Called from setup.
+///
+/// Windows and Linux get no menu bar: the window draws its own chrome and the
+/// tray carries the same actions.
+pub fn create(app: &AppHandle) -> tauri::Result<()> {
+    let items = AppMenu {
+        server_restart: MenuItem::with_id(app, "menu_server_restart", "Restart Server", false, None::<&str>)?,
+        capture_start: MenuItem::with_id(
+            app,
+            "menu_capture_start",
+            "Start System Audio Capture",
+            true,
+            None::<&str>,
+        )?,
+        capture_stop: MenuItem::with_id(
+            app,
+            "menu_capture_stop",
+            "Stop System Audio Capture",
+            false,
+            None::<&str>,
+        )?,
+        open_recent: Submenu::new(app, "Open Recent", true)?,
+    };
+
+    let version = app.package_info().version.to_string();
+    let about = AboutMetadata {
+        name: Some("Voicebox".to_string()),
+        version: Some(version),
+        ..Default::default()
+    };
+    let app_submenu = Submenu::with_items(
+        app,
+        "Voicebox",
+        true,
+        &[
+            &PredefinedMenuItem::about(app, Some("About Voicebox"), Some(about))?,
+            &MenuItem::with_id(app, "check_updates", "Check for Updates…", true, None::<&str>)?,
+            &PredefinedMenuItem::separator(app)?,
+            &MenuItem::with_id(app, "settings", "Settings…", true, Some("CmdOrCtrl+,"))?,
+            &items.server_restart,
+            &PredefinedMenuItem::separator(app)?,
+            &PredefinedMenuItem::services(app, None)?,
+            &PredefinedMenuItem::separator(app)?,
+            &PredefinedMenuItem::hide(app, None)?,
+            &PredefinedMenuItem::hide_others(app, None)?,
+            &PredefinedMenuItem::show_all(app, None)?,
+            &PredefinedMenuItem::separator(app)?,
+            &PredefinedMenuItem::quit(app, None)?,
+        ],
+    )?;
+
+    // Without these, Cmd+C/Cmd+V never reach text fields in the webview
+    let edit_submenu = Submenu::with_items(
+        app,
+        "Edit",
+        true,
+        &[
+            &PredefinedMenuItem::undo(app, None)?,
+            &PredefinedMenuItem::redo(app, None)?,
+            &PredefinedMenuItem::separator(app)?,
+            &PredefinedMenuItem::cut(app, None)?,
+            &PredefinedMenuItem::copy(app, None)?,
+            &PredefinedMenuItem::paste(app, None)?,
+            &PredefinedMenuItem::select_all(app, None)?,
+        ],
+    )?;
+
+    let file_submenu = Submenu::with_items(app, "File", true, &[&items.open_recent])?;
+
+    let capture_submenu = Submenu::with_items(app, "Capture", true, &[&items.capture_start, &items.capture_stop])?;
+
+    let window_submenu = Submenu::with_items(
+        app,
+        "Window",
+        true,
+        &[
+            &PredefinedMenuItem::minimize(app, None)?,
+            &PredefinedMenuItem::maximize(app, None)?,
+            &PredefinedMenuItem::fullscreen(app, None)?,
+            &PredefinedMenuItem::separator(app)?,
+            &MenuItem::with_id(app, "menu_mini_recorder", "Mini Recorder", true, None::<&str>)?,
+            &PredefinedMenuItem::separator(app)?,
+            &PredefinedMenuItem::close_window(app, None)?,
+        ],
+    )?;
+
+    let menu = Menu::with_items(app, &[&app_submenu, &file_submenu, &edit_submenu, &capture_submenu, &window_submenu])?;
+    app.set_menu(menu)?;
+    app.on_menu_event(handle_menu_event);
+    app.manage(items);
+    let recent: Vec<RecentItem> = app
+        .state::<SettingsState>()
+        .get()
+        .recent_items
+        .into_iter()
+        .filter(|item| item.is_file)
+        .collect();
+    set_recent_files(app, &recent)?;
+
+    for event in REFRESH_EVENTS {
+        let handle = app.clone();
+        crate::event_bus::listen(app, *event, move |_| refresh(&handle));
+    }
+    refresh(app);
+    Ok(())
+}
+
+fn refresh(app: &AppHandle) {
+    let Some(items) = app.try_state::<AppMenu>() else {
+        return;
+    };
+    let capturing = app.state::<AudioCaptureState>().is_capturing();
+    let capture_supported = crate::audio_capture::is_supported();
+    let _ = items
+        .server_restart
+        .set_enabled(TrayServerStatus::current(app) == TrayServerStatus::Running);
+    let _ = items.capture_start.set_enabled(capture_supported && !capturing);
+    let _ = items.capture_stop.set_enabled(capture_supported && capturing);
+}
+
+/// Fill Open Recent with `files`, newest first, each listed once.
+pub fn set_recent_files(app: &AppHandle, files: &[RecentItem]) -> tauri::Result<()> {
+    let Some(items) = app.try_state::<AppMenu>() else {
+        return Ok(());
+    };
+    let submenu = &items.open_recent;
+    while submenu.remove_at(0)?.is_some() {}
+
+    let mut listed: Vec<&str> = Vec::new();
+    for file in files {
+        if listed.contains(&file.target.as_str()) {
+            continue;
+        }
+        listed.push(&file.target);
+        let id = format!("{}{}", RECENT_FILE_PREFIX, file.target);
+        submenu.append(&MenuItem::with_id(app, id, &file.label, true, None::<&str>)?)?;
+    }
+    if !listed.is_empty() {
+        submenu.append(&PredefinedMenuItem::separator(app)?)?;
+    }
+    submenu.append(&MenuItem::with_id(app, "recent_clear", "Clear Menu", !listed.is_empty(), None::<&str>)?)?;
+    Ok(())
+}
+
+/// Add a file to the system's recent documents (the Dock menu and Recent
+/// Items), alongside our own Open Recent menu.
+pub fn note_recent_document(path: &Path) {
+    use objc::runtime::Object;
+    use objc::{class, msg_send, sel, sel_impl};
+
+    let Ok(path) = std::ffi::CString::new(path.to_string_lossy().as_bytes()) else {
+        return;
+    };
+    unsafe {
+        let string: *mut Object = msg_send![class!(NSString), stringWithUTF8String: path.as_ptr()];
+        let url: *mut Object = msg_send![class!(NSURL), fileURLWithPath: string];
+        let controller: *mut Object = msg_send![class!(NSDocumentController), sharedDocumentController];
+        let _: () = msg_send![controller, noteNewRecentDocumentURL: url];
+    }
+}
+
+pub fn clear_recent_documents() {
+    use objc::runtime::Object;
+    use objc::{class, msg_send, sel, sel_impl};
+
+    unsafe {
+        let controller: *mut Object = msg_send![class!(NSDocumentController), sharedDocumentController];
+        let _: () = msg_send![controller, clearRecentDocuments: std::ptr::null_mut::<Object>()];
+    }
+}
+
+fn handle_menu_event(app: &AppHandle, event: MenuEvent) {
+    if let Some(path) = event.id().as_ref().strip_prefix(RECENT_FILE_PREFIX) {
+        // Opening a recent file imports it again, which lands on its record
+        let path = PathBuf::from(path);
+        if crate::audio_import::is_audio_file(&path) {
+            crate::file_import::open(app, vec![path]);
+        }
+        return;
+    }
+    match event.id().as_ref() {
+        // The frontend owns the updater UI and the settings view
+        id @ ("check_updates" | "settings") => {
+            if let Some(window) = app.get_webview_window("main") {
+                let _ = window.show();
+                let _ = window.set_focus();
+            }
+            if let Err(e) = crate::event_bus::emit(app, "menu-action", serde_json::json!({ "id": id })) {
+                warn!("Failed to emit menu-action event: {}", e);
+            }
+        }
+        "menu_server_restart" => crate::tray::restart_server(app),
+        "menu_capture_start" => {
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = crate::begin_capture(&app, MENU_CAPTURE_MAX_SECS).await {
+                    error!("Menu: failed to start capture: {}", e);
+                }
+            });
+        }
+        "menu_capture_stop" => {
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = crate::end_capture(&app).await {
+                    warn!("Menu: failed to stop capture: {}", e);
+                }
+            });
+        }
+        "recent_clear" => {
+            if let Err(e) = crate::mru::clear(app, None) {
+                warn!("Failed to clear recent items: {}", e);
+            }
+        }
+        "menu_mini_recorder" => {
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = crate::mini_recorder::open(&app) {
+                    warn!("{}", e);
+                }
+            });
+        }
+        // Tray menu items arrive here too; the tray handles those itself
+        _ => {}
+    }
+}
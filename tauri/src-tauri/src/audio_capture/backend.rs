@@ -0,0 +1,744 @@
+use super::spill::Spill;
+use super::{AutoPause, RESUME_LOOKAHEAD_MS};
+use crate::audio_log::{self, AudioEvent};
+use crate::audio_util::{ClipStats, LevelCounters, CLIP_THRESHOLD};
+use crate::error::VoiceboxError;
+use crate::metrics::METRICS;
+use crate::recording_metadata::{CaptureHealth, SourceFormat};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// A source of interleaved f32 frames: ScreenCaptureKit, WASAPI loopback, a
+/// PulseAudio monitor or the synthetic generator. Everything around it (the
+/// stop signal, the duration limit, silence detection and the WAV) is
+/// shared, in mod.rs.
+pub trait CaptureBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Start delivering frames to `sink` until `stop` is set, returning once
+    /// they are flowing. Failures after that go through `sink.fail`.
+    fn start(&self, sink: FrameSink, stop: Arc<AtomicBool>) -> Result<(), VoiceboxError>;
+
+    /// What the capture hears of the app's own playback.
+    fn scope(&self) -> CaptureScope {
+        CaptureScope::DefaultOutput
+    }
+
+    /// How long after `stop` is set the last frames may still arrive.
+    fn drain_time(&self) -> Duration {
+        Duration::from_millis(500)
+    }
+
+    /// Whether `start` records the device in `FrameSink::device_id` when
+    /// one is set.
+    fn selects_device(&self) -> bool {
+        false
+    }
+
+    /// The devices a capture can be pointed at instead of the default
+    /// output. Blocking.
+    fn targets(&self) -> Result<Vec<CaptureTarget>, VoiceboxError> {
+        Ok(Vec::new())
+    }
+
+    /// Whether `start` records only the application in
+    /// `FrameSink::application_pid` when one is set. May depend on the OS
+    /// version, and may block to find it out.
+    fn captures_applications(&self) -> bool {
+        false
+    }
+
+    /// The running applications a capture can be limited to. Blocking.
+    fn applications(&self) -> Result<Vec<CapturableApplication>, VoiceboxError> {
+        Ok(Vec::new())
+    }
+
+    /// Whether `start` can work here, checked against the running system
+    /// rather than the build. Blocking.
+    fn readiness(&self) -> CaptureReadiness {
+        CaptureReadiness::ready()
+    }
+}
+
+/// What a backend found when asked whether it can record here.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureReadiness {
+    /// What stands in the way, or None if nothing does.
+    pub reason: Option<String>,
+    /// The OS is recent enough for the backend.
+    pub min_os_met: bool,
+    /// The OS asks the user before letting the backend record.
+    pub requires_permission: bool,
+    /// Whether the user has allowed it, where that can be told without
+    /// asking.
+    pub permission_granted: Option<bool>,
+}
+
+impl CaptureReadiness {
+    pub fn ready() -> Self {
+        Self {
+            reason: None,
+            min_os_met: true,
+            requires_permission: false,
+            permission_granted: None,
+        }
+    }
+
+    pub fn unavailable(reason: impl Into<String>) -> Self {
+        Self {
+            reason: Some(reason.into()),
+            ..Self::ready()
+        }
+    }
+}
+
+/// An output device a capture can record instead of the default one.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct CaptureTarget {
+    /// The backend's own id, e.g. the IMMDevice id on Windows. Not the id
+    /// audio_output knows the device by.
+    pub id: String,
+    pub name: String,
+    pub is_default: bool,
+}
+
+/// A running application whose audio a capture can be limited to.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct CapturableApplication {
+    pub pid: u32,
+    pub name: String,
+    /// The macOS bundle identifier. None elsewhere.
+    pub bundle_id: Option<String>,
+}
+
+/// Which output devices a backend records, for spotting playback it would
+/// pick up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureScope {
+    /// Everything the system plays, on any device (ScreenCaptureKit).
+    Desktop,
+    /// Loopback of whichever device is the default output (WASAPI), or the
+    /// monitor of the sink that was the default at the start (PulseAudio).
+    DefaultOutput,
+    /// No device at all, like the synthetic tone.
+    Nothing,
+}
+
+/// Why a capture ended without stop_capture being called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoStopReason {
+    Limit,
+    Silence,
+    /// The application a capture was limited to quit.
+    ApplicationExited,
+    /// The buffer held max_buffer_secs of audio, outside rolling mode.
+    BufferFull,
+}
+
+impl AutoStopReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AutoStopReason::Limit => "limit",
+            AutoStopReason::Silence => "silence",
+            AutoStopReason::ApplicationExited => "application_exited",
+            AutoStopReason::BufferFull => "buffer_full",
+        }
+    }
+}
+
+/// Where the current or last capture is, as get_capture_status reports it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureLifecycle {
+    /// Nothing to stop: never started, collected or cancelled.
+    #[default]
+    Idle,
+    Recording,
+    /// No more audio is coming in, stopped by hand or on its own, and
+    /// stop_capture is collecting it or has yet to.
+    Stopping,
+}
+
+/// A capture's lifecycle, with when it started recording and stopped.
+#[derive(Debug, Default)]
+pub(crate) struct Lifecycle {
+    pub(super) phase: CaptureLifecycle,
+    started: Option<Instant>,
+    stopped: Option<Instant>,
+}
+
+impl Lifecycle {
+    pub(super) fn start(&mut self) {
+        self.start_at(Instant::now());
+    }
+
+    /// Recording since `started`, for a capture that began with audio
+    /// recorded before it.
+    pub(super) fn start_at(&mut self, started: Instant) {
+        *self = Self {
+            phase: CaptureLifecycle::Recording,
+            started: Some(started),
+            stopped: None,
+        };
+    }
+
+    /// From Recording only, so a late failure can't reopen a collected capture.
+    pub(super) fn stopping(&mut self) {
+        if self.phase == CaptureLifecycle::Recording {
+            self.phase = CaptureLifecycle::Stopping;
+            self.stopped = Some(Instant::now());
+        }
+    }
+
+    /// Collected: the times stay, for the status of the last capture.
+    pub(super) fn idle(&mut self) {
+        self.stopping();
+        self.phase = CaptureLifecycle::Idle;
+    }
+
+    /// How long it recorded for, or has so far.
+    pub(super) fn elapsed(&self) -> Duration {
+        self.started.map_or(Duration::ZERO, |started| {
+            self.stopped.unwrap_or_else(Instant::now).saturating_duration_since(started)
+        })
+    }
+}
+
+/// The most audio a capture's buffer holds: at this, the capture ends, or
+/// in rolling mode its oldest frames go.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct BufferLimit {
+    pub(super) max_secs: Option<u32>,
+    pub(super) rolling: bool,
+}
+
+/// Drop the oldest frames of `samples` so `keep_frames` are left, counting
+/// them in `trimmed`.
+pub(super) fn trim_front(samples: &mut Vec<f32>, keep_frames: usize, channels: usize, trimmed: &AtomicU64) {
+    let excess = (samples.len() / channels).saturating_sub(keep_frames);
+    if excess > 0 {
+        samples.drain(..excess * channels);
+        trimmed.fetch_add(excess as u64, Ordering::Relaxed);
+    }
+}
+
+/// Takes a routed capture's frames as they arrive, with their sample rate
+/// and channel count.
+pub(crate) type Forward = Arc<dyn Fn(&[f32], u32, u16) + Send + Sync>;
+
+/// The counters behind CaptureHealth. Plain atomics, bumped from the audio
+/// side and read by get_capture_status without taking a lock.
+#[derive(Debug, Default)]
+pub(crate) struct HealthCounters {
+    lock_contentions: AtomicU64,
+    max_lag_frames: AtomicU64,
+    late_wakeups: AtomicU64,
+    callback_gaps: AtomicU64,
+    device_switches: AtomicU64,
+}
+
+impl HealthCounters {
+    pub(crate) fn snapshot(&self) -> CaptureHealth {
+        CaptureHealth {
+            lock_contentions: self.lock_contentions.load(Ordering::Relaxed),
+            max_lag_frames: self.max_lag_frames.load(Ordering::Relaxed),
+            late_wakeups: self.late_wakeups.load(Ordering::Relaxed),
+            callback_gaps: self.callback_gaps.load(Ordering::Relaxed),
+            device_switches: self.device_switches.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        for counter in [
+            &self.lock_contentions,
+            &self.max_lag_frames,
+            &self.late_wakeups,
+            &self.callback_gaps,
+            &self.device_switches,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Where an auto-pausing capture stands. Positions are in frames of the
+/// kept audio.
+#[derive(Debug, Default)]
+pub(crate) struct PauseState {
+    /// Silent frames in a row, while recording.
+    silent_frames: u64,
+    /// While paused: where the pause began, and the frames left out since.
+    paused: Option<(u64, u64)>,
+    /// The last RESUME_LOOKAHEAD_MS of what was left out, interleaved, put
+    /// back in front of the audio that resumes the capture.
+    lookahead: VecDeque<f32>,
+    /// Finished pauses as (at, frames left out).
+    intervals: Vec<(u64, u64)>,
+    /// While paused by hand, like `paused`. Independent of auto-pause,
+    /// which picks up where it was on release.
+    held: Option<(u64, u64)>,
+}
+
+impl PauseState {
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+
+    /// Every pause as (at, frames left out), including ones still going, in
+    /// order.
+    pub(crate) fn intervals(&self) -> Vec<(u64, u64)> {
+        let mut intervals = self.intervals.clone();
+        intervals.extend(self.paused);
+        intervals.extend(self.held);
+        intervals.sort_by_key(|(at, _)| *at);
+        intervals
+    }
+
+    /// Start leaving everything out, after `at` kept frames.
+    pub(crate) fn hold(&mut self, at: u64) {
+        self.held = Some((at, 0));
+    }
+
+    /// Stop leaving everything out, returning how many frames were.
+    pub(crate) fn release(&mut self) -> Option<u64> {
+        let (at, skipped) = self.held.take()?;
+        self.intervals.push((at, skipped));
+        Some(skipped)
+    }
+}
+
+/// Where a backend delivers its frames. Clones share the capture's buffers.
+#[derive(Clone)]
+pub struct FrameSink {
+    pub(super) samples: Arc<Mutex<Vec<f32>>>,
+    pub(super) sample_rate: Arc<Mutex<u32>>,
+    pub(super) channels: Arc<Mutex<u16>>,
+    pub(super) source_format: Arc<Mutex<Option<SourceFormat>>>,
+    pub(super) error: Arc<Mutex<Option<String>>>,
+    pub(super) stop_tx: Arc<Mutex<Option<tokio::sync::mpsc::Sender<Duration>>>>,
+    /// Moved on to Stopping when the capture ends on its own or fails.
+    pub(super) lifecycle: Arc<Mutex<Lifecycle>>,
+    pub(super) auto_stop: Arc<Mutex<Option<AutoStopReason>>>,
+    pub(super) dropouts: Arc<AtomicU64>,
+    pub(super) health: Arc<HealthCounters>,
+    pub(super) level: Arc<LevelCounters>,
+    /// Clipping in what the backend delivered, before any pause.
+    pub(super) clips: Arc<Mutex<ClipStats>>,
+    pub(super) format_set: Arc<AtomicBool>,
+    pub(super) silent_frames: Arc<AtomicU64>,
+    /// Whether the source has been heard yet: silence before that is the
+    /// wait for playback to start, and doesn't stop the capture.
+    pub(super) heard: Arc<AtomicBool>,
+    pub(super) silence_stop_secs: Option<f32>,
+    pub(super) silence_threshold: f32,
+    pub(super) auto_pause: Option<AutoPause>,
+    pub(super) pause: Arc<Mutex<PauseState>>,
+    /// Set while the capture is paused by hand; the frames are then only
+    /// counted. Checked before taking `pause`.
+    pub(super) held: Arc<AtomicBool>,
+    /// Set for an audio route: frames go here instead of the buffer.
+    pub(super) forward: Option<Forward>,
+    pub(super) exclude_own_audio: bool,
+    pub(super) device_id: Option<String>,
+    pub(super) application_pid: Option<u32>,
+    /// Shared with the capture state, which changes it when a capture with
+    /// pre-roll takes over a rolling one.
+    pub(super) buffer_limit: Arc<Mutex<BufferLimit>>,
+    /// Frames rolling mode dropped from the front of the buffer.
+    pub(super) trimmed: Arc<AtomicU64>,
+    /// Set for a capture to a file: frames are written there instead of
+    /// kept in the buffer.
+    pub(super) spill: Option<Arc<Mutex<Spill>>>,
+    /// The capture this sink belongs to, and the one now running: once a
+    /// later capture starts, this sink ignores its backend's late callbacks.
+    pub(super) generation: u64,
+    pub(super) active_generation: Arc<AtomicU64>,
+}
+
+impl FrameSink {
+    /// A sink that hands every frame to `forward` and keeps none, for a
+    /// capture that runs until its stop flag is set.
+    pub(crate) fn forwarding(forward: Forward) -> Self {
+        Self {
+            samples: Arc::new(Mutex::new(Vec::new())),
+            sample_rate: Arc::new(Mutex::new(0)),
+            channels: Arc::new(Mutex::new(0)),
+            source_format: Arc::new(Mutex::new(None)),
+            error: Arc::new(Mutex::new(None)),
+            stop_tx: Arc::new(Mutex::new(None)),
+            lifecycle: Arc::new(Mutex::new(Lifecycle::default())),
+            auto_stop: Arc::new(Mutex::new(None)),
+            dropouts: Arc::new(AtomicU64::new(0)),
+            health: Arc::new(HealthCounters::default()),
+            level: Arc::new(LevelCounters::default()),
+            clips: Arc::new(Mutex::new(ClipStats::default())),
+            format_set: Arc::new(AtomicBool::new(false)),
+            silent_frames: Arc::new(AtomicU64::new(0)),
+            heard: Arc::new(AtomicBool::new(false)),
+            silence_stop_secs: None,
+            silence_threshold: 0.0,
+            auto_pause: None,
+            pause: Arc::new(Mutex::new(PauseState::default())),
+            held: Arc::new(AtomicBool::new(false)),
+            forward: Some(forward),
+            // What the capture feeds is played by this app, so hearing
+            // itself would loop
+            exclude_own_audio: true,
+            device_id: None,
+            application_pid: None,
+            buffer_limit: Arc::new(Mutex::new(BufferLimit::default())),
+            trimmed: Arc::new(AtomicU64::new(0)),
+            spill: None,
+            generation: 0,
+            active_generation: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Let the backend record what this app plays as well, as the app's own
+    /// captures do.
+    pub(crate) fn including_own_audio(mut self) -> Self {
+        self.exclude_own_audio = false;
+        self
+    }
+
+    /// Whether this sink's capture is the latest one started.
+    pub(super) fn is_current(&self) -> bool {
+        self.active_generation.load(Ordering::SeqCst) == self.generation
+    }
+
+    /// Whether the backend should leave out audio this app plays, where it
+    /// can tell it apart.
+    pub fn excludes_own_audio(&self) -> bool {
+        self.exclude_own_audio
+    }
+
+    /// The device to record rather than the default output, for backends
+    /// that select one.
+    pub fn device_id(&self) -> Option<&str> {
+        self.device_id.as_deref()
+    }
+
+    /// The process to record alone, for backends that capture applications.
+    pub fn application_pid(&self) -> Option<u32> {
+        self.application_pid
+    }
+
+    /// End the capture because the application it records quit, keeping
+    /// what was recorded; nothing more will come.
+    pub fn application_exited(&self) {
+        self.end(AutoStopReason::ApplicationExited);
+    }
+
+    /// Announce the format of the frames that follow. A device whose format
+    /// changes mid-capture fails it: the buffer holds a single format.
+    pub fn set_format(&self, sample_rate: u32, channels: u16) {
+        self.set_source_format(SourceFormat {
+            sample_rate,
+            channels,
+            planar: false,
+        });
+    }
+
+    /// set_format, for a backend that can say how its audio came, which the
+    /// recording's metadata keeps. The frames pushed are interleaved either
+    /// way.
+    pub fn set_source_format(&self, format: SourceFormat) {
+        if !self.is_current() {
+            return;
+        }
+        let (sample_rate, channels) = (format.sample_rate, format.channels);
+        let current = (*self.sample_rate.lock().unwrap(), *self.channels.lock().unwrap());
+        if self.format_set.swap(true, Ordering::SeqCst) {
+            if current != (sample_rate, channels) {
+                audio_log::record(AudioEvent::DeviceChanged {
+                    from: current,
+                    to: (sample_rate, channels),
+                });
+                self.fail(format!(
+                    "The audio device changed from {} Hz, {} channels to {} Hz, {} channels during the capture",
+                    current.0, current.1, sample_rate, channels
+                ));
+            }
+            return;
+        }
+        *self.sample_rate.lock().unwrap() = sample_rate;
+        *self.channels.lock().unwrap() = channels;
+        *self.source_format.lock().unwrap() = Some(format);
+    }
+
+    /// Append interleaved frames.
+    pub fn push(&self, frames: &[f32]) {
+        if frames.is_empty() || !self.is_current() {
+            return;
+        }
+        let channels = (*self.channels.lock().unwrap()).max(1) as u64;
+        METRICS.frames_captured.add(frames.len() as u64 / channels);
+        if let Some(forward) = &self.forward {
+            forward(frames, *self.sample_rate.lock().unwrap(), channels as u16);
+            return;
+        }
+        // Before any pause, so the meter shows the source while paused
+        let (rms, peak) = self.level.measure(frames);
+        {
+            let mut clips = self.clips.lock().unwrap();
+            if peak >= CLIP_THRESHOLD {
+                clips.add(frames, channels as u16);
+            } else {
+                clips.add_unclipped(frames.len() as u64 / channels);
+            }
+        }
+        if self.held.load(Ordering::SeqCst) {
+            // Released in the meantime, this delivery is kept after all
+            if let Some((_, skipped)) = &mut self.pause.lock().unwrap().held {
+                *skipped += frames.len() as u64 / channels;
+                return;
+            }
+        }
+        let kept = match self.auto_pause {
+            Some(auto_pause) => self.gate(frames, peak, auto_pause, channels),
+            None => Cow::Borrowed(frames),
+        };
+        if let Some(spill) = self.spill.as_ref().filter(|_| !kept.is_empty()) {
+            if !self.spill_frames(spill, &kept, channels as u16) {
+                return;
+            }
+        } else if !kept.is_empty() {
+            let mut samples = self.samples.try_lock().unwrap_or_else(|_| {
+                self.health.lock_contentions.fetch_add(1, Ordering::Relaxed);
+                METRICS.capture_lock_contentions.inc();
+                self.samples.lock().unwrap()
+            });
+            samples.extend_from_slice(&kept);
+            let limit = *self.buffer_limit.lock().unwrap();
+            if let Some(max_secs) = limit.max_secs {
+                let max_frames = max_secs as usize * *self.sample_rate.lock().unwrap() as usize;
+                if !self.limit_buffer(&mut samples, max_frames, channels as usize, limit.rolling) {
+                    drop(samples);
+                    self.end(AutoStopReason::BufferFull);
+                    return;
+                }
+            }
+        }
+
+        let Some(stop_secs) = self.silence_stop_secs else {
+            return;
+        };
+        if rms >= self.silence_threshold {
+            self.heard.store(true, Ordering::Relaxed);
+            self.silent_frames.store(0, Ordering::Relaxed);
+            return;
+        }
+        if !self.heard.load(Ordering::Relaxed) {
+            return;
+        }
+        let silent = self.silent_frames.fetch_add(frames.len() as u64 / channels, Ordering::Relaxed)
+            + frames.len() as u64 / channels;
+        let limit = (stop_secs * *self.sample_rate.lock().unwrap() as f32) as u64;
+        if silent >= limit {
+            self.end(AutoStopReason::Silence);
+        }
+    }
+
+    /// Write `frames` to the capture's file, false if that failed the
+    /// capture (a full disk, or a file at the WAV size limit).
+    fn spill_frames(&self, spill: &Mutex<Spill>, frames: &[f32], channels: u16) -> bool {
+        let mut spill = spill.lock().unwrap();
+        let Err(e) = spill.write(frames, *self.sample_rate.lock().unwrap(), channels) else {
+            return true;
+        };
+        let path = spill.path().display().to_string();
+        drop(spill);
+        self.fail(format!("Writing the capture to {} failed, keeping what was written: {}", path, e));
+        false
+    }
+
+    /// Hold `samples` to `max_frames`, false once a capture that isn't
+    /// rolling has filled it. Rolling, the oldest frames are dropped an
+    /// eighth of the limit at a time (a second at least), so each delivery
+    /// doesn't shift the whole buffer.
+    fn limit_buffer(&self, samples: &mut Vec<f32>, max_frames: usize, channels: usize, rolling: bool) -> bool {
+        let frames = samples.len() / channels;
+        if !rolling {
+            samples.truncate(max_frames * channels);
+            return frames < max_frames;
+        }
+        let sample_rate = *self.sample_rate.lock().unwrap() as usize;
+        if frames >= max_frames + (max_frames / 8).max(sample_rate) {
+            trim_front(samples, max_frames, channels, &self.trimmed);
+        }
+        true
+    }
+
+    /// What of `frames` an auto-pausing capture keeps: all of it while
+    /// recording, pausing once the source has been silent for `after_ms`;
+    /// none while paused; the lookahead and all of it when the source is
+    /// heard again.
+    fn gate<'a>(&self, frames: &'a [f32], peak: f32, auto_pause: AutoPause, channels: u64) -> Cow<'a, [f32]> {
+        let sample_rate = *self.sample_rate.lock().unwrap() as u64;
+        let count = frames.len() as u64 / channels;
+        let loud = peak >= auto_pause.threshold();
+        let mut pause = self.pause.lock().unwrap();
+        match pause.paused {
+            None if loud => {
+                pause.silent_frames = 0;
+                Cow::Borrowed(frames)
+            }
+            None => {
+                pause.silent_frames += count;
+                if pause.silent_frames >= auto_pause.after_ms as u64 * sample_rate / 1000 {
+                    // After these frames, which are kept. Counted from the
+                    // start, including any rolling mode has dropped since
+                    let kept = match &self.spill {
+                        Some(spill) => spill.lock().unwrap().frames(),
+                        None => self.samples.lock().unwrap().len() as u64 / channels,
+                    };
+                    let at = self.trimmed.load(Ordering::Relaxed) + kept + count;
+                    pause.paused = Some((at, 0));
+                    audio_log::record(AudioEvent::CapturePaused {
+                        at_frames: at,
+                        sample_rate: sample_rate as u32,
+                    });
+                    METRICS.capture_auto_pauses.inc();
+                }
+                Cow::Borrowed(frames)
+            }
+            Some((at, skipped)) if !loud => {
+                pause.paused = Some((at, skipped + count));
+                let capacity = (RESUME_LOOKAHEAD_MS as u64 * sample_rate / 1000 * channels) as usize;
+                pause.lookahead.extend(frames);
+                let excess = pause.lookahead.len().saturating_sub(capacity);
+                pause.lookahead.drain(..excess);
+                Cow::Borrowed(&[])
+            }
+            Some((at, skipped)) => {
+                let mut kept: Vec<f32> = pause.lookahead.drain(..).collect();
+                let restored = kept.len() as u64 / channels;
+                kept.extend_from_slice(frames);
+                pause.intervals.push((at, skipped - restored));
+                pause.paused = None;
+                pause.silent_frames = 0;
+                audio_log::record(AudioEvent::CaptureResumed {
+                    left_out_frames: skipped - restored,
+                    sample_rate: sample_rate as u32,
+                });
+                Cow::Owned(kept)
+            }
+        }
+    }
+
+    /// Note a gap in the delivered audio, of `frames` if the backend knows.
+    /// Only counted, for get_metrics and the recording's metadata; the
+    /// capture carries on.
+    pub fn dropout(&self, frames: Option<u64>) {
+        if !self.is_current() {
+            return;
+        }
+        self.dropouts.fetch_add(1, Ordering::Relaxed);
+        audio_log::record(AudioEvent::Dropout { frames });
+        METRICS.capture_dropouts.inc();
+        METRICS.frames_dropped.add(frames.unwrap_or(0));
+    }
+
+    /// Note how many frames the device had queued when the backend read it.
+    pub fn lag(&self, frames: u64) {
+        if !self.is_current() {
+            return;
+        }
+        self.health.max_lag_frames.fetch_max(frames, Ordering::Relaxed);
+        METRICS.capture_lag_frames.record(frames);
+    }
+
+    /// Note a wait for the device that overran its period with audio waiting.
+    pub fn late_wakeup(&self) {
+        if !self.is_current() {
+            return;
+        }
+        self.health.late_wakeups.fetch_add(1, Ordering::Relaxed);
+        METRICS.capture_late_wakeups.inc();
+    }
+
+    /// Note a delivery that came well after the audio before it ended.
+    pub fn callback_gap(&self) {
+        if !self.is_current() {
+            return;
+        }
+        self.health.callback_gaps.fetch_add(1, Ordering::Relaxed);
+        METRICS.capture_callback_gaps.inc();
+    }
+
+    /// Note the backend carrying on after losing its device, on the same one
+    /// or the new default, `gap` later. The gap is filled with silence so the
+    /// recording keeps roughly to time.
+    pub fn device_switched(&self, gap: Duration, device: Option<String>) {
+        if !self.is_current() {
+            return;
+        }
+        self.health.device_switches.fetch_add(1, Ordering::Relaxed);
+        METRICS.capture_device_switches.inc();
+        audio_log::record(AudioEvent::DeviceSwitched {
+            device,
+            gap_ms: gap.as_millis() as u64,
+        });
+        let sample_rate = *self.sample_rate.lock().unwrap() as f64;
+        let channels = *self.channels.lock().unwrap() as usize;
+        let frames = (sample_rate * gap.as_secs_f64()).round() as usize;
+        self.push(&vec![0.0; frames * channels]);
+    }
+
+    /// End the capture with an error, which stop_capture returns. Usable from
+    /// a panic hook: poisoned locks are taken over rather than unwrapped.
+    pub fn fail(&self, message: impl Into<String>) {
+        if !self.is_current() {
+            return;
+        }
+        let mut error = self.error.lock().unwrap_or_else(PoisonError::into_inner);
+        if error.is_none() {
+            let message = message.into();
+            audio_log::record(AudioEvent::CaptureFailed { error: message.clone() });
+            *error = Some(message);
+        }
+        drop(error);
+        self.stop_tx.lock().unwrap_or_else(PoisonError::into_inner).take();
+        self.lifecycle.lock().unwrap_or_else(PoisonError::into_inner).stopping();
+        self.finish_spill();
+    }
+
+    /// Fill in a capture file's header as soon as the capture ends, so the
+    /// file is whole even if stop_capture never comes.
+    fn finish_spill(&self) {
+        let Some(spill) = &self.spill else {
+            return;
+        };
+        let finished = spill.lock().unwrap_or_else(PoisonError::into_inner).finish();
+        if let Err(e) = finished {
+            self.fail(e);
+        }
+    }
+
+    /// Why the capture failed, if it did.
+    pub(crate) fn failure(&self) -> Option<String> {
+        self.error.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    pub(crate) fn dropouts(&self) -> u64 {
+        self.dropouts.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn end(&self, reason: AutoStopReason) {
+        // A stale timer or backend must not end the capture that followed
+        if !self.is_current() {
+            return;
+        }
+        // Dropping the sender wakes the task that sets the backend's stop flag
+        if self.stop_tx.lock().unwrap().take().is_some() {
+            *self.auto_stop.lock().unwrap() = Some(reason);
+            self.lifecycle.lock().unwrap().stopping();
+            self.finish_spill();
+        }
+    }
+}
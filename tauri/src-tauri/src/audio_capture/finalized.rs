@@ -0,0 +1,57 @@
+use super::CaptureSummary;
+use crate::error::VoiceboxError;
+use base64::{engine::general_purpose, Engine as _};
+
+/// Bytes of the WAV in each chunk get_capture_chunk returns; the last one
+/// has what is left.
+pub const FINALIZED_CHUNK_BYTES: usize = 1 << 20;
+
+/// A finalized capture, for the frontend to fetch in chunks.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct FinalizedCapture {
+    pub session: u64,
+    /// Of the WAV, before base64.
+    pub total_bytes: u64,
+    pub chunk_size: u32,
+    pub num_chunks: u32,
+    pub sample_rate: u32,
+    pub duration_secs: f64,
+}
+
+/// The WAV of the last finalized capture, held until it is released.
+#[derive(Debug)]
+pub(crate) struct FinalizedWav {
+    pub(super) wav: Vec<u8>,
+    pub(super) info: FinalizedCapture,
+}
+
+impl FinalizedWav {
+    /// Decode the base64 WAV stop_capture returned for `session`.
+    pub(super) fn new(session: u64, audio: &str, summary: &CaptureSummary) -> Result<Self, VoiceboxError> {
+        let wav = general_purpose::STANDARD
+            .decode(audio)
+            .map_err(|e| VoiceboxError::internal(format!("Capture {} isn't valid base64: {}", session, e)))?;
+        let info = FinalizedCapture {
+            session,
+            total_bytes: wav.len() as u64,
+            chunk_size: FINALIZED_CHUNK_BYTES as u32,
+            num_chunks: wav.len().div_ceil(FINALIZED_CHUNK_BYTES) as u32,
+            sample_rate: summary.sample_rate,
+            duration_secs: summary.duration_secs,
+        };
+        Ok(Self { wav, info })
+    }
+
+    /// Chunk `index` as base64.
+    pub(super) fn chunk(&self, index: u32) -> Result<String, VoiceboxError> {
+        if index >= self.info.num_chunks {
+            return Err(VoiceboxError::invalid_argument(format!(
+                "Capture {} has {} chunks; there is no chunk {}",
+                self.info.session, self.info.num_chunks, index
+            )));
+        }
+        let start = index as usize * FINALIZED_CHUNK_BYTES;
+        let end = (start + FINALIZED_CHUNK_BYTES).min(self.wav.len());
+        Ok(general_purpose::STANDARD.encode(&self.wav[start..end]))
+    }
+}
@@ -1,16 +1,173 @@
-use crate::audio_capture::AudioCaptureState;
+use super::{CaptureBackend, CaptureReadiness, FrameSink};
+use crate::error::VoiceboxError;
+use libpulse_binding::def::BufferAttr;
+use libpulse_binding::sample::{Format, Spec};
+use libpulse_binding::stream::Direction;
+use libpulse_simple_binding::Simple;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use tracing::{info, warn};
 
-pub async fn start_capture(
-    state: &AudioCaptureState,
-    max_duration_secs: u32,
-) -> Result<(), String> {
-    todo!("implement Linux audio capture")
-}
+/// Used when the monitor's own format can't be read.
+const FALLBACK_RATE: u32 = 48000;
+const FALLBACK_CHANNELS: u8 = 2;
+
+/// Frames per read, and the fragment size asked of the server, so a read
+/// returns (and the stop flag is checked) about this often.
+const CHUNK_MS: u32 = 20;
+
+/// The monitor source of the default sink, through PulseAudio or PipeWire's
+/// pulse server.
+pub struct PlatformBackend;
+
+impl CaptureBackend for PlatformBackend {
+    fn name(&self) -> &'static str {
+        "pulseaudio"
+    }
+
+    fn start(&self, sink: FrameSink, stop_flag: Arc<AtomicBool>) -> Result<(), VoiceboxError> {
+        // Resolve the monitor by name up front: a stream opened on
+        // @DEFAULT_MONITOR@ would be moved along when the default sink
+        // changes, while a named one stays on the sink it started with
+        let monitor = default_monitor();
+        let (rate, channels) = monitor
+            .as_deref()
+            .and_then(monitor_format)
+            .unwrap_or((FALLBACK_RATE, FALLBACK_CHANNELS));
+        let spec = Spec {
+            format: Format::FLOAT32NE,
+            rate,
+            channels,
+        };
+        if !spec.is_valid() {
+            return Err(VoiceboxError::capture(format!(
+                "The monitor reports an unusable format: {} Hz, {} channels",
+                rate, channels
+            )));
+        }
+
+        let panic_sink = sink.clone();
+        // Keep the thread's lines in the caller's capture span
+        let span = tracing::Span::current();
+        let (ready_tx, ready_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _span = span.entered();
+            // A panic here would otherwise leave is_capturing true and stop_capture
+            // waiting on samples that never come
+            crate::crash_log::on_this_thread_panic(move |message| {
+                panic_sink.fail(format!("The capture thread crashed: {}", message));
+            });
+
+            let chunk_frames = (rate * CHUNK_MS / 1000) as usize;
+            let chunk_bytes = chunk_frames * spec.frame_size();
+            let attr = BufferAttr {
+                maxlength: u32::MAX,
+                tlength: u32::MAX,
+                prebuf: u32::MAX,
+                minreq: u32::MAX,
+                fragsize: chunk_bytes as u32,
+            };
+            let device = monitor.as_deref().unwrap_or("@DEFAULT_MONITOR@");
+            let stream = match Simple::new(
+                None,
+                "Voicebox",
+                Direction::Record,
+                Some(device),
+                "System audio",
+                &spec,
+                None,
+                Some(&attr),
+            ) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    let _ = ready_tx.send(Err(format!("Failed to open {}: {}", device, e)));
+                    return;
+                }
+            };
+            info!("Recording from {} at {} Hz, {} channels", device, rate, channels);
+            sink.set_format(rate, channels as u16);
+            let _ = ready_tx.send(Ok(()));
+
+            let mut buffer = vec![0u8; chunk_bytes];
+            while !stop_flag.load(Ordering::Relaxed) {
+                if let Err(e) = stream.read(&mut buffer) {
+                    // The sink went away or the server did
+                    let error_msg = format!("Failed to read from the monitor: {}", e);
+                    warn!("{}", error_msg);
+                    sink.fail(error_msg);
+                    return;
+                }
+                let frames: Vec<f32> = buffer
+                    .chunks_exact(4)
+                    .map(|bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                    .collect();
+                sink.push(&frames);
+                // What the server holds for us that we haven't read yet
+                if let Some(latency) = stream.get_latency() {
+                    sink.lag(latency.0 * rate as u64 / 1_000_000);
+                }
+            }
+        });
+
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(message)) => {
+                warn!("{}", message);
+                Err(VoiceboxError::capture(message))
+            }
+            Err(_) => Err(VoiceboxError::capture("The capture thread exited before the monitor opened")),
+        }
+    }
 
-pub async fn stop_capture(state: &AudioCaptureState) -> Result<String, String> {
-    todo!("implement Linux audio capture stop")
+    fn readiness(&self) -> CaptureReadiness {
+        if is_supported() {
+            CaptureReadiness::ready()
+        } else {
+            CaptureReadiness::unavailable("No PulseAudio or PipeWire server is running to record from")
+        }
+    }
 }
 
+/// Whether a pulse server (PulseAudio's own or PipeWire's) answers.
 pub fn is_supported() -> bool {
-    false
+    pactl(&["info"]).is_some() || pulse_socket().is_some_and(|socket| socket.exists())
+}
+
+fn pactl(args: &[&str]) -> Option<String> {
+    let output = Command::new("pactl").args(args).stdin(Stdio::null()).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The server's socket where clients look for it first, for systems
+/// without pactl installed.
+fn pulse_socket() -> Option<PathBuf> {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")?;
+    Some(PathBuf::from(runtime_dir).join("pulse").join("native"))
+}
+
+/// `<default sink>.monitor`, or None when pactl can't say.
+fn default_monitor() -> Option<String> {
+    let sink = pactl(&["get-default-sink"])?;
+    let sink = sink.trim();
+    (!sink.is_empty()).then(|| format!("{}.monitor", sink))
+}
+
+/// The rate and channel count of `source`, from a `pactl list short sources`
+/// line such as `57  alsa_output.pci.monitor  PipeWire  s32le 2ch 48000Hz  IDLE`.
+fn monitor_format(source: &str) -> Option<(u32, u8)> {
+    let sources = pactl(&["list", "short", "sources"])?;
+    let line = sources.lines().find(|line| line.split('\t').nth(1) == Some(source))?;
+    let mut rate = None;
+    let mut channels = None;
+    for word in line.split_whitespace() {
+        if let Some(value) = word.strip_suffix("Hz") {
+            rate = value.parse().ok();
+        } else if let Some(value) = word.strip_suffix("ch") {
+            channels = value.parse().ok();
+        }
+    }
+    Some((rate?, channels?))
 }
@@ -1,6 +1,6 @@
-use crate::audio_capture::AudioCaptureState;
-use base64::{engine::general_purpose, Engine as _};
-use hound::{WavSpec, WavWriter};
+use super::{CapturableApplication, CaptureBackend, CaptureReadiness, CaptureScope, FrameSink};
+use crate::error::VoiceboxError;
+use crate::recording_metadata::SourceFormat;
 use screencapturekit::{
     cm::CMSampleBuffer,
     shareable_content::SCShareableContent,
@@ -12,152 +12,292 @@ use screencapturekit::{
         sc_stream::SCStream,
     },
 };
-use std::io::Cursor;
-use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
-
-pub async fn start_capture(
-    state: &AudioCaptureState,
-    max_duration_secs: u32,
-) -> Result<(), String> {
-    // Reset previous samples
-    state.reset();
-
-    // Get shareable content
-    let content = SCShareableContent::get()
-        .map_err(|e| format!("Failed to get shareable content: {}", e))?;
-
-    // Get first display
-    let displays = content.displays();
-    if displays.is_empty() {
-        return Err("No displays available".to_string());
-    }
-    let display = &displays[0];
-
-    // Create content filter for desktop audio
-    let filter = SCContentFilter::create()
-        .with_display(display)
-        .with_excluding_windows(&[])
-        .build();
-
-    // Create stream configuration - audio only
-    let mut config = SCStreamConfiguration::default();
-    config.set_captures_audio(true);
-    config.set_excludes_current_process_audio(false);
-    config.set_sample_rate(48000); // Use i32 directly
-    config.set_channel_count(2); // Use i32 directly
-
-    // Create stream using builder
-    let (tx, mut rx) = mpsc::channel::<()>(1);
-    *state.stop_tx.lock().unwrap() = Some(tx);
-
-    let samples = state.samples.clone();
-    let sample_rate = state.sample_rate.clone();
-    let channels = state.channels.clone();
-
-    // Set sample rate and channels
-    *sample_rate.lock().unwrap() = 48000;
-    *channels.lock().unwrap() = 2;
-
-    // Create output handler struct
-    struct AudioHandler {
-        samples: Arc<Mutex<Vec<f32>>>,
-    }
+use objc::runtime::Object;
+use objc::{class, msg_send, sel, sel_impl};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// How often the stop flag is checked while the stream runs.
+const STOP_POLL: Duration = Duration::from_millis(20);
+
+/// A sample buffer arriving this many times its own length after the one
+/// before ended counts as a callback gap.
+const GAP_FACTOR: u64 = 2;
+
+/// What the stream is asked for. Older hardware and some aggregate devices
+/// deliver 44.1 kHz or mono regardless, so the sample buffers have the say.
+const REQUESTED_RATE: u32 = 48000;
+const REQUESTED_CHANNELS: u16 = 2;
+
+/// kAudioFormatFlagIsNonInterleaved: a buffer per channel.
+const NON_INTERLEAVED: u32 = 1 << 5;
+
+/// ScreenCaptureKit's audio capture arrived in macOS 12.3.
+const MIN_MACOS: (isize, isize) = (12, 3);
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGPreflightScreenCaptureAccess() -> bool;
+}
 
-    impl SCStreamOutputTrait for AudioHandler {
-        fn did_output_sample_buffer(
-            &self,
-            sample: CMSampleBuffer,
-            _type: SCStreamOutputType,
-        ) {
-            if _type == SCStreamOutputType::Audio {
-                if let Ok(audio_samples) = extract_audio_samples(sample) {
-                    let mut samples_guard = self.samples.lock().unwrap();
-                    samples_guard.extend_from_slice(&audio_samples);
-                }
-            }
-        }
+/// ScreenCaptureKit audio of the main display, or of one application on it.
+pub struct PlatformBackend;
+
+impl CaptureBackend for PlatformBackend {
+    fn name(&self) -> &'static str {
+        "screencapturekit"
     }
 
-    let handler = AudioHandler {
-        samples: samples.clone(),
-    };
+    fn scope(&self) -> CaptureScope {
+        CaptureScope::Desktop
+    }
 
-    // Create stream
-    let mut stream = SCStream::new(&filter, &config);
-    
-    // Add output handler for audio (order: handler, then output_type)
-    stream.add_output_handler(handler, SCStreamOutputType::Audio);
+    fn start(&self, sink: FrameSink, stop: Arc<AtomicBool>) -> Result<(), VoiceboxError> {
+        // Without the permission the stream starts but delivers no audio,
+        // which would only show once the capture is stopped
+        if !unsafe { CGPreflightScreenCaptureAccess() } {
+            return Err(VoiceboxError::permission(
+                "screen_recording",
+                "Screen Recording permission required: enable Voicebox under System Settings > Privacy & Security \
+                 > Screen Recording, then try again",
+            ));
+        }
 
-    // Store stream reference
-    *state.stream.lock().unwrap() = Some(stream.clone());
+        // Get shareable content
+        let content = SCShareableContent::get()
+            .map_err(|e| VoiceboxError::capture(format!("Failed to get shareable content: {}", e)))?;
 
-    stream.start_capture().map_err(|e| format!("Failed to start capture: {}", e))?;
+        // Get first display
+        let displays = content.displays();
+        if displays.is_empty() {
+            return Err(VoiceboxError::capture("No displays available"));
+        }
+        let display = &displays[0];
+
+        // Create content filter for desktop audio, or for the one
+        // application's
+        let filter = match sink.application_pid() {
+            Some(pid) => {
+                let applications = content.applications();
+                let application = applications
+                    .iter()
+                    .find(|application| application.process_id() as u32 == pid)
+                    .ok_or_else(|| VoiceboxError::not_found(format!("No application with pid {} to capture", pid)))?;
+                SCContentFilter::create()
+                    .with_display(display)
+                    .with_including_applications(&[application], &[])
+                    .build()
+            }
+            None => SCContentFilter::create()
+                .with_display(display)
+                .with_excluding_windows(&[])
+                .build(),
+        };
+        let application_pid = sink.application_pid();
+        let exit_sink = sink.clone();
+
+        // Create stream configuration - audio only
+        let mut config = SCStreamConfiguration::default();
+        config.set_captures_audio(true);
+        config.set_excludes_current_process_audio(sink.excludes_own_audio());
+        config.set_sample_rate(REQUESTED_RATE as i32);
+        config.set_channel_count(REQUESTED_CHANNELS as i32);
+
+        // Create output handler struct
+        struct AudioHandler {
+            sink: FrameSink,
+            started: Instant,
+            /// When the last buffer arrived and its length, in microseconds
+            /// since `started`. Only this handler's callbacks touch them.
+            last_at_us: AtomicU64,
+            last_len_us: AtomicU64,
+            /// The format of the first buffer, which the capture is in.
+            format: Mutex<Option<SourceFormat>>,
+        }
 
-    // Spawn task to stop after max duration
-    let stream_clone = stream.clone();
-    tokio::spawn(async move {
-        tokio::select! {
-            _ = tokio::time::sleep(tokio::time::Duration::from_secs(max_duration_secs as u64)) => {
-                // Timeout reached
+        impl AudioHandler {
+            fn note_arrival(&self, samples: usize, format: SourceFormat) {
+                let now = self.started.elapsed().as_micros() as u64;
+                let last_at = self.last_at_us.swap(now, Ordering::Relaxed);
+                let frames = samples as u64 / format.channels.max(1) as u64;
+                let len = frames * 1_000_000 / format.sample_rate.max(1) as u64;
+                let last_len = self.last_len_us.swap(len, Ordering::Relaxed);
+                if last_len > 0 && now.saturating_sub(last_at) > last_len * GAP_FACTOR {
+                    self.sink.callback_gap();
+                }
             }
-            _ = rx.recv() => {
-                // Manual stop
+        }
+
+        impl SCStreamOutputTrait for AudioHandler {
+            fn did_output_sample_buffer(
+                &self,
+                sample: CMSampleBuffer,
+                _type: SCStreamOutputType,
+            ) {
+                if _type == SCStreamOutputType::Audio {
+                    let format = source_format(&sample);
+                    let mut first = self.format.lock().unwrap();
+                    let changed = |first: SourceFormat| {
+                        (first.sample_rate, first.channels) != (format.sample_rate, format.channels)
+                    };
+                    if first.is_some_and(changed) {
+                        // Fails the capture: the buffer can't hold a second
+                        // format, and this one isn't added to it
+                        self.sink.set_source_format(format);
+                        return;
+                    }
+                    if first.is_none() {
+                        info!(
+                            "ScreenCaptureKit delivers {} Hz, {} channel(s), {}",
+                            format.sample_rate,
+                            format.channels,
+                            if format.planar { "planar" } else { "interleaved" }
+                        );
+                        self.sink.set_source_format(format);
+                        *first = Some(format);
+                    }
+                    drop(first);
+                    if let Ok(audio_samples) = extract_audio_samples(sample, format) {
+                        self.note_arrival(audio_samples.len(), format);
+                        self.sink.push(&audio_samples);
+                    }
+                }
             }
         }
-        let _ = stream_clone.stop_capture();
-    });
 
-    Ok(())
-}
+        // Create stream
+        let mut stream = SCStream::new(&filter, &config);
+
+        // Add output handler for audio (order: handler, then output_type)
+        let handler = AudioHandler {
+            sink,
+            started: Instant::now(),
+            last_at_us: AtomicU64::new(0),
+            last_len_us: AtomicU64::new(0),
+            format: Mutex::new(None),
+        };
+        stream.add_output_handler(handler, SCStreamOutputType::Audio);
+
+        stream
+            .start_capture()
+            .map_err(|e| VoiceboxError::capture(format!("Failed to start capture: {}", e)))?;
+
+        // The stream has no stop signal of its own; stop it once the flag is
+        // set. An application that quits just goes quiet, so watch it too
+        std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                if application_pid.is_some_and(has_exited) {
+                    info!("The captured application quit");
+                    exit_sink.application_exited();
+                    break;
+                }
+                std::thread::sleep(STOP_POLL);
+            }
+            let _ = stream.stop_capture();
+        });
 
-pub async fn stop_capture(state: &AudioCaptureState) -> Result<String, String> {
-    // Signal stop
-    if let Some(tx) = state.stop_tx.lock().unwrap().take() {
-        let _ = tx.send(());
+        Ok(())
     }
 
-    // Stop stream if still active
-    if let Some(stream) = state.stream.lock().unwrap().take() {
-        let _ = stream.stop_capture();
+    /// The content filter takes applications on the same macOS versions as
+    /// displays.
+    fn captures_applications(&self) -> bool {
+        is_supported()
     }
 
-    // Wait a bit for capture to stop
-    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-
-    // Get samples
-    let samples = state.samples.lock().unwrap().clone();
-    let sample_rate = *state.sample_rate.lock().unwrap();
-    let channels = *state.channels.lock().unwrap();
+    fn readiness(&self) -> CaptureReadiness {
+        let (major, minor) = macos_version();
+        let permission_granted = unsafe { CGPreflightScreenCaptureAccess() };
+        let reason = if !is_supported() {
+            Some(format!(
+                "System audio capture needs macOS {}.{} or later; this Mac runs {}.{}",
+                MIN_MACOS.0, MIN_MACOS.1, major, minor
+            ))
+        } else if !permission_granted {
+            Some("Voicebox needs the Screen Recording permission to capture system audio".to_string())
+        } else {
+            None
+        };
+        CaptureReadiness {
+            reason,
+            min_os_met: is_supported(),
+            requires_permission: true,
+            permission_granted: Some(permission_granted),
+        }
+    }
 
-    if samples.is_empty() {
-        return Err("No audio samples captured".to_string());
+    fn applications(&self) -> Result<Vec<CapturableApplication>, VoiceboxError> {
+        let content = SCShareableContent::get()
+            .map_err(|e| VoiceboxError::capture(format!("Failed to get shareable content: {}", e)))?;
+        let own_pid = std::process::id();
+        let mut applications: Vec<CapturableApplication> = content
+            .applications()
+            .iter()
+            .map(|application| CapturableApplication {
+                pid: application.process_id() as u32,
+                name: application.application_name(),
+                bundle_id: Some(application.bundle_identifier()).filter(|bundle_id| !bundle_id.is_empty()),
+            })
+            // Helpers and agents without a name have nothing to pick by
+            .filter(|application| application.pid != own_pid && !application.name.is_empty())
+            .collect();
+        applications.sort_by_key(|application| application.name.to_lowercase());
+        Ok(applications)
     }
+}
 
-    // Convert to WAV
-    let wav_data = samples_to_wav(&samples, sample_rate, channels)?;
-    
-    // Encode to base64
-    let base64_data = general_purpose::STANDARD.encode(&wav_data);
-    
-    Ok(base64_data)
+/// Whether `pid` is gone. One this process may not signal still exists.
+fn has_exited(pid: u32) -> bool {
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    result != 0 && std::io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH)
 }
 
+/// Whether this macOS has ScreenCaptureKit's audio capture.
 pub fn is_supported() -> bool {
-    // ScreenCaptureKit requires macOS 12.3+
-    // Check if we're on a supported version
-    #[cfg(target_os = "macos")]
-    {
-        // Basic check - ScreenCaptureKit should be available on macOS 12.3+
-        true
-    }
-    #[cfg(not(target_os = "macos"))]
-    {
-        false
+    macos_version() >= MIN_MACOS
+}
+
+/// NSOperatingSystemVersion.
+#[repr(C)]
+struct OperatingSystemVersion {
+    major: isize,
+    minor: isize,
+    _patch: isize,
+}
+
+/// The running macOS as (major, minor), from NSProcessInfo. Looked up once.
+fn macos_version() -> (isize, isize) {
+    static VERSION: OnceLock<(isize, isize)> = OnceLock::new();
+    *VERSION.get_or_init(|| {
+        let version: OperatingSystemVersion = unsafe {
+            let info: *mut Object = msg_send![class!(NSProcessInfo), processInfo];
+            msg_send![info, operatingSystemVersion]
+        };
+        (version.major, version.minor)
+    })
+}
+
+/// The format `sample_buffer` says it holds, or what was asked for where it
+/// doesn't say.
+fn source_format(sample_buffer: &CMSampleBuffer) -> SourceFormat {
+    let description = sample_buffer.format_description();
+    let description = description.as_ref();
+    SourceFormat {
+        sample_rate: description
+            .and_then(|description| description.audio_sample_rate())
+            .map_or(REQUESTED_RATE, |rate| rate.round() as u32),
+        channels: description
+            .and_then(|description| description.audio_channel_count())
+            .map_or(REQUESTED_CHANNELS, |channels| channels as u16),
+        planar: description
+            .and_then(|description| description.audio_format_flags())
+            .is_some_and(|flags| flags & NON_INTERLEAVED != 0),
     }
 }
 
-fn extract_audio_samples(sample_buffer: CMSampleBuffer) -> Result<Vec<f32>, String> {
+fn extract_audio_samples(sample_buffer: CMSampleBuffer, format: SourceFormat) -> Result<Vec<f32>, String> {
     // Use the crate's built-in method to get audio buffer list
     let audio_buffer_list = sample_buffer
         .audio_buffer_list()
@@ -173,9 +313,9 @@ fn extract_audio_samples(sample_buffer: CMSampleBuffer) -> Result<Vec<f32>, Stri
     // ScreenCaptureKit on macOS provides audio in Float32 format
     // The audio can be either:
     // - Interleaved (1 buffer with L,R,L,R,... samples)
-    // - Planar (2 buffers, one for L channel, one for R channel)
+    // - Planar (a buffer per channel), as the format description says
     
-    if num_buffers == 1 {
+    if !format.planar && num_buffers == 1 {
         // Interleaved stereo or mono in a single buffer
         let buffer = &buffers[0];
         let data_bytes = buffer.data();
@@ -225,31 +365,3 @@ fn extract_audio_samples(sample_buffer: CMSampleBuffer) -> Result<Vec<f32>, Stri
 
     Ok(Vec::new())
 }
-
-fn samples_to_wav(samples: &[f32], sample_rate: u32, channels: u16) -> Result<Vec<u8>, String> {
-    let mut buffer = Vec::new();
-    let cursor = Cursor::new(&mut buffer);
-    
-    let spec = WavSpec {
-        channels,
-        sample_rate,
-        bits_per_sample: 16,
-        sample_format: hound::SampleFormat::Int,
-    };
-
-    let mut writer = WavWriter::new(cursor, spec)
-        .map_err(|e| format!("Failed to create WAV writer: {}", e))?;
-
-    // Convert f32 samples to i16
-    for sample in samples {
-        let clamped = sample.clamp(-1.0, 1.0);
-        let i16_sample = (clamped * 32767.0) as i16;
-        writer.write_sample(i16_sample)
-            .map_err(|e| format!("Failed to write sample: {}", e))?;
-    }
-
-    writer.finalize()
-        .map_err(|e| format!("Failed to finalize WAV: {}", e))?;
-
-    Ok(buffer)
-}
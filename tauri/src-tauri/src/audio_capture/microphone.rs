@@ -0,0 +1,121 @@
+use super::{CaptureBackend, CaptureScope, FrameSink};
+use crate::error::VoiceboxError;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{SampleFormat, StreamConfig};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// The default input device through cpal, for the microphone leg of a
+/// combined capture.
+pub struct MicrophoneBackend;
+
+impl CaptureBackend for MicrophoneBackend {
+    fn name(&self) -> &'static str {
+        "microphone"
+    }
+
+    fn scope(&self) -> CaptureScope {
+        CaptureScope::Nothing
+    }
+
+    fn start(&self, sink: FrameSink, stop: Arc<AtomicBool>) -> Result<(), VoiceboxError> {
+        let device = cpal::default_host()
+            .default_input_device()
+            .ok_or_else(|| VoiceboxError::not_found("There is no microphone to record from"))?;
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+        // A cpal stream stops when dropped and can't leave the thread that
+        // built it, so this thread holds it until the capture is stopped
+        std::thread::Builder::new()
+            .name("microphone-capture".to_string())
+            .spawn(move || {
+                let stream = match build_stream(&device, sink) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                if let Err(e) = stream.play() {
+                    let _ = ready_tx.send(Err(format!("Failed to start the microphone: {}", e)));
+                    return;
+                }
+                let _ = ready_tx.send(Ok(()));
+                while !stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(Duration::from_millis(20));
+                }
+            })
+            .map_err(|e| VoiceboxError::capture(format!("Failed to start the microphone thread: {}", e)))?;
+        ready_rx
+            .recv()
+            .map_err(|_| VoiceboxError::capture("The microphone thread exited before the stream started"))?
+            .map_err(VoiceboxError::capture)
+    }
+
+    fn drain_time(&self) -> Duration {
+        Duration::from_millis(100)
+    }
+}
+
+fn build_stream(device: &cpal::Device, sink: FrameSink) -> Result<cpal::Stream, String> {
+    let config = device
+        .default_input_config()
+        .map_err(|e| format!("Failed to get the microphone's format: {}", e))?;
+    info!(
+        "Recording from {} at {} Hz, {} channels, {:?}",
+        device.name().unwrap_or_default(),
+        config.sample_rate().0,
+        config.channels(),
+        config.sample_format()
+    );
+    sink.set_format(config.sample_rate().0, config.channels());
+    let stream_config = StreamConfig {
+        channels: config.channels(),
+        sample_rate: config.sample_rate(),
+        buffer_size: cpal::BufferSize::Default,
+    };
+    // An input stream error means the device went away
+    let failed = sink.clone();
+    let err_fn = move |err: cpal::StreamError| {
+        warn!("Microphone error: {}", err);
+        failed.fail(format!("The microphone failed: {}", err));
+    };
+
+    let stream = match config.sample_format() {
+        SampleFormat::F32 => device.build_input_stream(
+            &stream_config,
+            move |data: &[f32], _: &cpal::InputCallbackInfo| sink.push(data),
+            err_fn,
+            None,
+        ),
+        SampleFormat::I16 => {
+            let mut scratch = Vec::new();
+            device.build_input_stream(
+                &stream_config,
+                move |data: &[i16], _: &cpal::InputCallbackInfo| {
+                    scratch.clear();
+                    scratch.extend(data.iter().map(|&sample| sample as f32 / 32768.0));
+                    sink.push(&scratch);
+                },
+                err_fn,
+                None,
+            )
+        }
+        SampleFormat::U16 => {
+            let mut scratch = Vec::new();
+            device.build_input_stream(
+                &stream_config,
+                move |data: &[u16], _: &cpal::InputCallbackInfo| {
+                    scratch.clear();
+                    scratch.extend(data.iter().map(|&sample| sample as f32 / 32767.5 - 1.0));
+                    sink.push(&scratch);
+                },
+                err_fn,
+                None,
+            )
+        }
+        format => return Err(format!("Unsupported microphone sample format {:?}", format)),
+    };
+    stream.map_err(|e| format!("Failed to open the microphone: {}", e))
+}
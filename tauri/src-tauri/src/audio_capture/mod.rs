@@ -1,47 +1,1714 @@
-#[cfg(target_os = "macos")]
+mod backend;
+mod finalized;
+#[cfg(all(feature = "native-backends", target_os = "linux"))]
+mod linux;
+#[cfg(all(feature = "native-backends", target_os = "macos"))]
 mod macos;
-#[cfg(target_os = "windows")]
+#[cfg(feature = "native-backends")]
+mod microphone;
+mod pipeline;
+mod spill;
+mod stream;
+mod synthetic;
+#[cfg(not(all(
+    feature = "native-backends",
+    any(target_os = "macos", target_os = "windows", target_os = "linux")
+)))]
+mod unsupported;
+#[cfg(all(feature = "native-backends", target_os = "windows"))]
 mod windows;
-#[cfg(target_os = "linux")]
-mod linux;
 
-#[cfg(target_os = "macos")]
-pub use macos::*;
-#[cfg(target_os = "windows")]
-pub use windows::*;
-#[cfg(target_os = "linux")]
-pub use linux::*;
+#[cfg(all(feature = "native-backends", target_os = "linux"))]
+use linux as platform;
+#[cfg(all(feature = "native-backends", target_os = "macos"))]
+use macos as platform;
+#[cfg(not(all(
+    feature = "native-backends",
+    any(target_os = "macos", target_os = "windows", target_os = "linux")
+)))]
+use unsupported as platform;
+#[cfg(all(feature = "native-backends", target_os = "windows"))]
+use windows as platform;
+
+pub use backend::{
+    AutoStopReason, CapturableApplication, CaptureBackend, CaptureLifecycle, CaptureReadiness, CaptureScope,
+    CaptureTarget, FrameSink,
+};
+pub use finalized::{FinalizedCapture, FINALIZED_CHUNK_BYTES};
+pub use pipeline::{CaptureSummary, NORMALIZE_PEAK};
+pub use stream::{CaptureChunk, CaptureManifest, STREAM_CHUNK_MS};
+pub use synthetic::{SyntheticBackend, SyntheticEvent, SyntheticPattern};
 
+use crate::audio_log::{self, AudioEvent};
+use crate::audio_util::{self, ClipStats, LevelCounters, WavSampleFormat};
+use crate::error::VoiceboxError;
+use crate::metrics::METRICS;
+use crate::recording_metadata::{
+    CaptureHealth, PauseInterval, PreRoll, Processing, RecordingMetadata, SourceFormat,
+};
+use backend::{trim_front, BufferLimit, HealthCounters, Lifecycle, PauseState};
+use finalized::FinalizedWav;
+use spill::Spill;
+use stream::StreamCursor;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Finished captures kept for export_audio, oldest dropped first.
+const KEPT_RECORDINGS: usize = 5;
+
+/// Set to `synthetic` to capture a generated tone instead of system audio,
+/// e.g. for UI tests on machines without audio devices.
+pub const BACKEND_ENV: &str = "VOICEBOX_CAPTURE_BACKEND";
+
+/// The limit of a default capture, five minutes, as the app's mini recorder
+/// uses.
+pub const DEFAULT_MAX_DURATION_SECS: u32 = 300;
+
+/// How often the limit is checked while a capture is paused by hand.
+const HOLD_POLL: Duration = Duration::from_millis(100);
+
+/// Level under which audio counts as silence, -60 dBFS: a delivery's RMS
+/// for a capture's silence stop, a frame's peak for trimming.
+pub const DEFAULT_SILENCE_THRESHOLD: f32 = 0.001;
+
+/// Audio from just before an auto-paused capture resumes that is kept, so
+/// the sound that resumed it isn't clipped.
+pub const RESUME_LOOKAHEAD_MS: u32 = 250;
+
+/// Pause a capture while its source is silent, leaving that audio out, and
+/// resume when it isn't.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AutoPause {
+    /// Peak level under which the source counts as silent, in dBFS.
+    pub threshold_db: f32,
+    /// Silence after which the capture pauses.
+    pub after_ms: u32,
+}
+
+impl AutoPause {
+    /// threshold_db as a linear peak level.
+    pub fn threshold(&self) -> f32 {
+        10f32.powf(self.threshold_db / 20.0)
+    }
+
+    fn validate(&self) -> Result<(), VoiceboxError> {
+        if !self.threshold_db.is_finite() || self.threshold_db > 0.0 {
+            return Err(VoiceboxError::invalid_argument("threshold_db must be 0 dBFS or lower"));
+        }
+        if self.after_ms == 0 {
+            return Err(VoiceboxError::invalid_argument("after_ms must be at least 1"));
+        }
+        Ok(())
+    }
+}
+
+/// Drop the silence before a capture's first sound and after its last when
+/// it is saved.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TrimSilence {
+    /// Peak level under which a frame is silence, in dBFS.
+    #[serde(default = "TrimSilence::default_threshold_db")]
+    pub threshold_db: f32,
+    /// Silence kept on each side.
+    #[serde(default = "TrimSilence::default_padding_ms")]
+    pub padding_ms: u32,
+}
+
+impl TrimSilence {
+    fn default_threshold_db() -> f32 {
+        -60.0
+    }
+
+    fn default_padding_ms() -> u32 {
+        100
+    }
+
+    /// threshold_db as a linear peak level.
+    pub fn threshold(&self) -> f32 {
+        10f32.powf(self.threshold_db / 20.0)
+    }
+
+    fn validate(&self) -> Result<(), VoiceboxError> {
+        if !self.threshold_db.is_finite() || self.threshold_db > 0.0 {
+            return Err(VoiceboxError::invalid_argument("threshold_db must be 0 dBFS or lower"));
+        }
+        Ok(())
+    }
+}
+
+impl Default for TrimSilence {
+    fn default() -> Self {
+        Self {
+            threshold_db: Self::default_threshold_db(),
+            padding_ms: Self::default_padding_ms(),
+        }
+    }
+}
+
+/// Where normalization brings a capture's level. From a number, a peak in
+/// dBFS, or from "peak:-1" or "lufs:-16".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NormalizeTarget {
+    PeakDb(f32),
+    /// Integrated loudness, held back so the peak stays under full scale.
+    Lufs(f32),
+}
+
+impl NormalizeTarget {
+    /// The most normalization raises a capture by, so a quiet one doesn't
+    /// bring its noise floor up with it.
+    pub const MAX_GAIN_DB: f32 = 30.0;
+
+    fn validate(&self) -> Result<(), VoiceboxError> {
+        match *self {
+            NormalizeTarget::PeakDb(db) if !db.is_finite() || db > 0.0 => {
+                Err(VoiceboxError::invalid_argument("The normalize peak must be 0 dBFS or lower"))
+            }
+            NormalizeTarget::Lufs(lufs) if !lufs.is_finite() || !(-70.0..=0.0).contains(&lufs) => Err(
+                VoiceboxError::invalid_argument("The normalize loudness must be between -70 and 0 LUFS"),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl std::str::FromStr for NormalizeTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s.split_once(':').unwrap_or(("peak", s));
+        let value: f32 = value
+            .trim()
+            .parse()
+            .map_err(|_| format!("'{}' is not a level, like -1 or lufs:-16", s))?;
+        match kind.trim() {
+            "peak" => Ok(NormalizeTarget::PeakDb(value)),
+            "lufs" => Ok(NormalizeTarget::Lufs(value)),
+            other => Err(format!("Unknown normalize target '{}': use peak or lufs", other)),
+        }
+    }
+}
+
+impl serde::Serialize for NormalizeTarget {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            NormalizeTarget::PeakDb(db) => serializer.serialize_f32(*db),
+            NormalizeTarget::Lufs(lufs) => serializer.serialize_str(&format!("lufs:{}", lufs)),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for NormalizeTarget {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Peak(f32),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Peak(db) => Ok(NormalizeTarget::PeakDb(db)),
+            Raw::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// A point in a capture marked while it ran.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct CaptureMarker {
+    /// Into the saved audio, so it stays put when auto-pause leaves
+    /// silence out, and at the rate that audio is saved at.
+    pub at_frames: u64,
+    pub label: Option<String>,
+}
+
+/// How a capture runs and what stop_capture does with the frames.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[non_exhaustive]
+pub struct CaptureOptions {
+    pub max_duration_secs: u32,
+    /// Stop on its own after this many seconds of continuous silence, once
+    /// the source has been heard.
+    pub silence_stop_secs: Option<f32>,
+    /// RMS level under which audio counts as silence, linear.
+    pub silence_threshold: f32,
+    /// Average the channels into one.
+    pub mono: bool,
+    /// Scale so the loudest sample peaks at NORMALIZE_PEAK, or to
+    /// normalize_target when one is set.
+    pub normalize: bool,
+    /// Where normalize brings the level instead. stop_capture_as can set it
+    /// as the capture stops.
+    pub normalize_target: Option<NormalizeTarget>,
+    /// Audio from before the start to include, taken from a running rolling
+    /// capture, which this one then carries on. 0 for none. The
+    /// max_duration_secs limit counts from the start, not the pre-roll.
+    pub pre_roll_ms: u32,
+    /// Fail rather than start without the pre-roll when no rolling capture
+    /// is running to take it from. Otherwise the capture starts without it
+    /// and its metadata says so.
+    pub require_pre_roll: bool,
+    /// Leave out stretches where the source is silent, recording where
+    /// they were in the metadata.
+    pub auto_pause_on_source_silence: Option<AutoPause>,
+    /// Also save the capture to the library cut at its markers, one
+    /// recording per segment.
+    pub split_on_markers: bool,
+    /// Record this device (a CaptureTarget id) instead of the default
+    /// output, where the backend selects devices.
+    pub device_id: Option<String>,
+    /// Record only this process (a CapturableApplication pid), where the
+    /// backend captures applications. It quitting ends the capture.
+    pub application_pid: Option<u32>,
+    /// Hand the audio out in chunks while the capture runs (see
+    /// AudioCaptureState::send_chunk), for callers that can't take it all
+    /// at once. The recording is still kept as usual.
+    pub stream: bool,
+    /// The most audio the capture buffers. Reaching it ends the capture on
+    /// its own, unless `rolling`.
+    pub max_buffer_secs: Option<u32>,
+    /// Keep only the latest max_buffer_secs, dropping the oldest audio
+    /// rather than ending the capture.
+    pub rolling: bool,
+    /// Write the audio to a WAV here as it arrives rather than keep it in
+    /// memory. stop_capture returns this path instead of the audio.
+    pub spill_path: Option<PathBuf>,
+    /// Leave out the silence at either end of the saved audio.
+    pub trim_silence: Option<TrimSilence>,
+    /// Save at this rate rather than the one recorded at, resampled with a
+    /// windowed sinc. stop_capture_as can set it as the capture stops.
+    pub target_sample_rate: Option<u32>,
+    /// How the saved WAV stores its samples: 16-bit by default, or 24-bit or
+    /// float to keep more of what the backend delivered. stop_capture_as
+    /// can set it as the capture stops.
+    pub output_format: WavSampleFormat,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_DURATION_SECS)
+    }
+}
+
+impl CaptureOptions {
+    /// The app's capture: stereo as recorded, stopped only by hand or the limit.
+    pub fn new(max_duration_secs: u32) -> Self {
+        Self {
+            max_duration_secs,
+            silence_stop_secs: None,
+            silence_threshold: DEFAULT_SILENCE_THRESHOLD,
+            mono: false,
+            normalize: false,
+            normalize_target: None,
+            pre_roll_ms: 0,
+            require_pre_roll: false,
+            auto_pause_on_source_silence: None,
+            split_on_markers: false,
+            device_id: None,
+            application_pid: None,
+            stream: false,
+            max_buffer_secs: None,
+            rolling: false,
+            spill_path: None,
+            trim_silence: None,
+            target_sample_rate: None,
+            output_format: WavSampleFormat::Int16,
+        }
+    }
+}
+
+/// A capture as start_system_audio_capture is asked for one. Anything left
+/// out keeps CaptureOptions::new's default.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[non_exhaustive]
+pub struct CaptureRequest {
+    pub max_duration_secs: u32,
+    #[serde(default)]
+    pub pre_roll_ms: u32,
+    #[serde(default)]
+    pub require_pre_roll: bool,
+    /// Refuse to start when playback or a route would be recorded.
+    #[serde(default)]
+    pub block_on_conflict: bool,
+    #[serde(default)]
+    pub auto_pause_on_source_silence: Option<AutoPause>,
+    #[serde(default)]
+    pub split_on_markers: bool,
+    #[serde(default)]
+    pub device_id: Option<String>,
+    #[serde(default)]
+    pub application_pid: Option<u32>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub max_buffer_secs: Option<u32>,
+    #[serde(default)]
+    pub rolling: bool,
+    /// Write the audio to a file in the app data dir as it arrives, which
+    /// the app picks as the options' spill_path.
+    #[serde(default)]
+    pub capture_to_file: bool,
+    #[serde(default)]
+    pub silence_stop_secs: Option<f32>,
+    /// In dBFS, where the options take a linear level.
+    #[serde(default)]
+    pub silence_threshold_db: Option<f32>,
+    #[serde(default)]
+    pub trim_silence: Option<TrimSilence>,
+}
+
+impl Default for CaptureRequest {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_DURATION_SECS)
+    }
+}
+
+impl CaptureRequest {
+    pub fn new(max_duration_secs: u32) -> Self {
+        Self {
+            max_duration_secs,
+            pre_roll_ms: 0,
+            require_pre_roll: false,
+            block_on_conflict: false,
+            auto_pause_on_source_silence: None,
+            split_on_markers: false,
+            device_id: None,
+            application_pid: None,
+            stream: false,
+            max_buffer_secs: None,
+            rolling: false,
+            capture_to_file: false,
+            silence_stop_secs: None,
+            silence_threshold_db: None,
+            trim_silence: None,
+        }
+    }
+
+    /// The options it asks for, all but the spill_path capture_to_file
+    /// wants.
+    pub fn options(&self) -> Result<CaptureOptions, VoiceboxError> {
+        let mut options = CaptureOptions::new(self.max_duration_secs);
+        if let Some(threshold_db) = self.silence_threshold_db {
+            if !threshold_db.is_finite() || threshold_db > 0.0 {
+                return Err(VoiceboxError::invalid_argument("silence_threshold_db must be 0 dBFS or lower"));
+            }
+            options.silence_threshold = 10f32.powf(threshold_db / 20.0);
+        }
+        options.silence_stop_secs = self.silence_stop_secs;
+        options.trim_silence = self.trim_silence;
+        options.pre_roll_ms = self.pre_roll_ms;
+        options.require_pre_roll = self.require_pre_roll;
+        options.auto_pause_on_source_silence = self.auto_pause_on_source_silence;
+        options.split_on_markers = self.split_on_markers;
+        options.device_id = self.device_id.clone();
+        options.application_pid = self.application_pid;
+        options.stream = self.stream;
+        options.max_buffer_secs = self.max_buffer_secs;
+        options.rolling = self.rolling;
+        Ok(options)
+    }
+}
 
-#[cfg(target_os = "macos")]
-use screencapturekit::stream::sc_stream::SCStream;
+/// What get_capture_status reports, enough for a reloaded frontend to pick
+/// up a capture it started.
+#[derive(Debug, Clone, serde::Serialize)]
+#[non_exhaustive]
+pub struct CaptureStatus {
+    /// Whether `state` is Recording.
+    pub capturing: bool,
+    pub state: CaptureLifecycle,
+    /// How long the current or last capture has recorded for, pauses
+    /// included.
+    pub elapsed_secs: f64,
+    /// Interleaved samples kept so far, across channels.
+    pub buffered_samples: u64,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Why the current or last capture failed, if it did.
+    pub last_error: Option<String>,
+    pub session: u64,
+    pub backend: &'static str,
+    /// Of the current or last capture, as is `health`.
+    pub dropouts: u64,
+    pub health: CaptureHealth,
+    /// Auto-paused for source silence.
+    pub paused: bool,
+    /// Paused with pause_capture.
+    pub paused_by_hand: bool,
+}
+
+/// How long a capture has been paused by hand, which its limit leaves out.
+#[derive(Debug, Default)]
+struct HoldClock {
+    held_for: Duration,
+    held_since: Option<Instant>,
+}
 
+impl HoldClock {
+    fn held(&self) -> Duration {
+        self.held_for + self.held_since.map_or(Duration::ZERO, |since| since.elapsed())
+    }
+}
+
+/// What captures can do here, for the UI to offer, and what stands in the
+/// way of the ones it can't.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct CaptureCapabilities {
+    pub system_audio: bool,
+    /// Why system_audio is false, e.g. "ScreenCaptureKit needs macOS 12.3
+    /// or later".
+    pub reason: Option<String>,
+    /// The OS is recent enough for the backend.
+    pub min_os_met: bool,
+    /// Capture needs a permission the user grants (Screen Recording on
+    /// macOS).
+    pub requires_permission: bool,
+    /// Whether it has been granted; None where there is none or it can't
+    /// be told without asking.
+    pub permission_granted: Option<bool>,
+    /// CaptureOptions::device_id can be set.
+    pub device_selection: bool,
+    /// CaptureOptions::application_pid can be set.
+    pub application_capture: bool,
+}
+
+/// One capture at a time through a backend, and the last few finished ones.
+/// Drive it with start_capture and stop_capture.
 pub struct AudioCaptureState {
-    pub samples: Arc<Mutex<Vec<f32>>>,
-    pub sample_rate: Arc<Mutex<u32>>,
-    pub channels: Arc<Mutex<u16>>,
-    pub stop_tx: Arc<Mutex<Option<tokio::sync::mpsc::Sender<()>>>>,
-    pub error: Arc<Mutex<Option<String>>>,
-    #[cfg(target_os = "macos")]
-    pub stream: Arc<Mutex<Option<SCStream>>>,
+    pub(crate) samples: Arc<Mutex<Vec<f32>>>,
+    pub(crate) sample_rate: Arc<Mutex<u32>>,
+    pub(crate) channels: Arc<Mutex<u16>>,
+    /// What the current or last capture's backend said it delivers.
+    source_format: Arc<Mutex<Option<SourceFormat>>>,
+    stop_tx: Arc<Mutex<Option<tokio::sync::mpsc::Sender<Duration>>>>,
+    /// Recording from a successful start until the device is told to stop,
+    /// then stopping until stop_capture has collected the audio.
+    lifecycle: Arc<Mutex<Lifecycle>>,
+    error: Arc<Mutex<Option<String>>>,
+    /// Bumped whenever a capture starts or is stopped by hand, so a pending
+    /// max-duration check can tell whether it still refers to the same capture.
+    session: Arc<AtomicU64>,
+    /// Counts captures started. Each one's sink and max-duration timer keep
+    /// the count they started under, and stand down once it moves on.
+    generation: Arc<AtomicU64>,
+    /// Recent finished captures as (session, base64 WAV, where it came from).
+    recordings: Arc<Mutex<VecDeque<(u64, String, RecordingMetadata)>>>,
+    /// Why the current or last capture ended on its own, if it did.
+    auto_stop: Arc<Mutex<Option<AutoStopReason>>>,
+    /// Frames rolling mode dropped from the front of `samples`. Markers and
+    /// pauses count from the capture's start, so they are shifted by this.
+    trimmed: Arc<AtomicU64>,
+    /// How much audio the current capture's sink keeps.
+    buffer_limit: Arc<Mutex<BufferLimit>>,
+    /// The file the current or last capture is written to, with spill_path.
+    spill: Mutex<Option<Arc<Mutex<Spill>>>>,
+    /// Gaps the backend reported in the current or last capture.
+    dropouts: Arc<AtomicU64>,
+    /// How the current or last capture's audio path held up.
+    health: Arc<HealthCounters>,
+    /// Level of the current capture since the meter last took it.
+    level: Arc<LevelCounters>,
+    /// Clipping in what the backend delivered to the current or last capture.
+    clips: Arc<Mutex<ClipStats>>,
+    /// Auto-pause of the current or last capture.
+    pause: Arc<Mutex<PauseState>>,
+    /// Whether the running capture is paused by hand, and for how long it
+    /// has been.
+    held: Arc<AtomicBool>,
+    hold: Arc<Mutex<HoldClock>>,
+    /// When the current or last capture started, in Unix seconds, counting
+    /// any pre-roll.
+    started_at: Mutex<Option<u64>>,
+    /// The pre-roll the current or last capture asked for and got.
+    pre_roll: Mutex<Option<PreRoll>>,
+    /// Markers added to the current or last capture, in the order added.
+    markers: Mutex<Vec<CaptureMarker>>,
+    /// What of the current or last capture has been sent as chunks.
+    stream: Mutex<StreamCursor>,
+    /// What stop_capture saved of the last capture, once it has.
+    summary: Mutex<Option<CaptureSummary>>,
+    /// The WAV finalize_capture built, until released or replaced by the
+    /// next one. A new capture leaves it alone.
+    finalized: Mutex<Option<FinalizedWav>>,
+    options: Mutex<CaptureOptions>,
+    backend: Arc<dyn CaptureBackend>,
 }
 
 impl AudioCaptureState {
+    /// Captures with default_backend().
     pub fn new() -> Self {
+        Self::with_backend(default_backend())
+    }
+
+    pub fn with_backend(backend: Arc<dyn CaptureBackend>) -> Self {
         Self {
             samples: Arc::new(Mutex::new(Vec::new())),
             sample_rate: Arc::new(Mutex::new(44100)),
             channels: Arc::new(Mutex::new(2)),
+            source_format: Arc::new(Mutex::new(None)),
             stop_tx: Arc::new(Mutex::new(None)),
+            lifecycle: Arc::new(Mutex::new(Lifecycle::default())),
             error: Arc::new(Mutex::new(None)),
-            #[cfg(target_os = "macos")]
-            stream: Arc::new(Mutex::new(None)),
+            session: Arc::new(AtomicU64::new(0)),
+            generation: Arc::new(AtomicU64::new(0)),
+            recordings: Arc::new(Mutex::new(VecDeque::new())),
+            auto_stop: Arc::new(Mutex::new(None)),
+            trimmed: Arc::new(AtomicU64::new(0)),
+            buffer_limit: Arc::new(Mutex::new(BufferLimit::default())),
+            spill: Mutex::new(None),
+            dropouts: Arc::new(AtomicU64::new(0)),
+            health: Arc::new(HealthCounters::default()),
+            level: Arc::new(LevelCounters::default()),
+            clips: Arc::new(Mutex::new(ClipStats::default())),
+            pause: Arc::new(Mutex::new(PauseState::default())),
+            held: Arc::new(AtomicBool::new(false)),
+            hold: Arc::new(Mutex::new(HoldClock::default())),
+            started_at: Mutex::new(None),
+            pre_roll: Mutex::new(None),
+            markers: Mutex::new(Vec::new()),
+            stream: Mutex::new(StreamCursor::default()),
+            summary: Mutex::new(None),
+            finalized: Mutex::new(None),
+            options: Mutex::new(CaptureOptions::new(0)),
+            backend,
+        }
+    }
+
+    pub fn backend_name(&self) -> &'static str {
+        self.backend.name()
+    }
+
+    pub fn scope(&self) -> CaptureScope {
+        self.backend.scope()
+    }
+
+    /// What CaptureOptions::device_id can name. Blocking.
+    pub fn targets(&self) -> Result<Vec<CaptureTarget>, VoiceboxError> {
+        self.backend.targets()
+    }
+
+    /// What CaptureOptions::application_pid can name. Blocking.
+    pub fn applications(&self) -> Result<Vec<CapturableApplication>, VoiceboxError> {
+        self.backend.applications()
+    }
+
+    /// What this state's backend can do. Blocking: the OS version may have
+    /// to be looked up, and the backend's devices or permission checked.
+    pub fn capabilities(&self) -> CaptureCapabilities {
+        let readiness = self.backend.readiness();
+        CaptureCapabilities {
+            system_audio: readiness.reason.is_none(),
+            reason: readiness.reason,
+            min_os_met: readiness.min_os_met,
+            requires_permission: readiness.requires_permission,
+            permission_granted: readiness.permission_granted,
+            device_selection: self.backend.selects_device(),
+            application_capture: self.backend.captures_applications(),
+        }
+    }
+
+    /// True from a successful start_capture until it is stopped or ends on
+    /// its own (limit, silence, the application quitting or a device
+    /// failure).
+    pub fn is_capturing(&self) -> bool {
+        self.lifecycle() == CaptureLifecycle::Recording
+    }
+
+    pub fn lifecycle(&self) -> CaptureLifecycle {
+        self.lifecycle.lock().unwrap().phase
+    }
+
+    /// Why the current or last capture ended on its own, if it did.
+    pub fn auto_stop(&self) -> Option<AutoStopReason> {
+        *self.auto_stop.lock().unwrap()
+    }
+
+    /// Health counters of the current or last capture. Lock-free, so it can
+    /// be polled while the audio side is busy.
+    pub fn health(&self) -> CaptureHealth {
+        self.health.snapshot()
+    }
+
+    pub fn status(&self) -> CaptureStatus {
+        let (state, elapsed) = {
+            let lifecycle = self.lifecycle.lock().unwrap();
+            (lifecycle.phase, lifecycle.elapsed())
+        };
+        CaptureStatus {
+            capturing: state == CaptureLifecycle::Recording,
+            state,
+            elapsed_secs: elapsed.as_secs_f64(),
+            buffered_samples: self.samples.lock().unwrap().len() as u64,
+            sample_rate: *self.sample_rate.lock().unwrap(),
+            channels: *self.channels.lock().unwrap(),
+            last_error: self.error.lock().unwrap().clone(),
+            session: self.current_session(),
+            backend: self.backend_name(),
+            dropouts: self.dropouts.load(Ordering::Relaxed),
+            health: self.health(),
+            paused: self.is_paused(),
+            paused_by_hand: self.is_paused_by_hand(),
+        }
+    }
+
+    /// Whether the running capture is auto-paused for source silence.
+    pub fn is_paused(&self) -> bool {
+        self.is_capturing() && self.pause.lock().unwrap().is_paused()
+    }
+
+    /// Whether the running capture is paused with pause_capture.
+    pub fn is_paused_by_hand(&self) -> bool {
+        self.is_capturing() && self.held.load(Ordering::SeqCst)
+    }
+
+    /// Stop keeping what the running capture records, with the device left
+    /// running so resume_capture picks up at once. The time paused doesn't
+    /// count towards the limit, and the metadata notes the gap like an
+    /// auto-pause's.
+    pub fn pause_capture(&self) -> Result<(), VoiceboxError> {
+        if !self.is_capturing() {
+            return Err(VoiceboxError::not_found("No capture is running"));
+        }
+        let mut hold = self.hold.lock().unwrap();
+        if hold.held_since.is_some() {
+            return Err(VoiceboxError::invalid_argument("The capture is already paused"));
+        }
+        let at = self.recorded_frames();
+        self.pause.lock().unwrap().hold(at);
+        hold.held_since = Some(Instant::now());
+        self.held.store(true, Ordering::SeqCst);
+        audio_log::record(AudioEvent::CapturePausedByHand {
+            at_frames: at,
+            sample_rate: *self.sample_rate.lock().unwrap(),
+        });
+        Ok(())
+    }
+
+    /// Keep what the capture records again, after pause_capture.
+    pub fn resume_capture(&self) -> Result<(), VoiceboxError> {
+        if !self.is_capturing() {
+            return Err(VoiceboxError::not_found("No capture is running"));
+        }
+        let mut hold = self.hold.lock().unwrap();
+        let Some(since) = hold.held_since.take() else {
+            return Err(VoiceboxError::invalid_argument("The capture isn't paused"));
+        };
+        hold.held_for += since.elapsed();
+        self.held.store(false, Ordering::SeqCst);
+        let left_out = self.pause.lock().unwrap().release().unwrap_or(0);
+        audio_log::record(AudioEvent::CaptureResumedByHand {
+            left_out_frames: left_out,
+            sample_rate: *self.sample_rate.lock().unwrap(),
+        });
+        Ok(())
+    }
+
+    /// Mark where the running capture has got to, in its saved frames. None
+    /// when not capturing.
+    pub fn add_marker(&self, label: Option<String>) -> Option<CaptureMarker> {
+        if !self.is_capturing() {
+            return None;
+        }
+        let marker = CaptureMarker {
+            at_frames: self.recorded_frames(),
+            label,
+        };
+        self.markers.lock().unwrap().push(marker.clone());
+        Some(marker)
+    }
+
+    /// Send the whole frames the current or last capture recorded since its
+    /// last chunk to `send`, if there are any. Calls wait for each other, so
+    /// chunks go out in sequence even when a timer and the stop race to send
+    /// them; call once more after stop_capture for the frames that came in
+    /// while it drained.
+    pub fn send_chunk(&self, send: impl FnOnce(CaptureChunk)) -> bool {
+        let mut cursor = self.stream.lock().unwrap();
+        let sample_rate = *self.sample_rate.lock().unwrap();
+        let channels = *self.channels.lock().unwrap();
+        let mono = self.options.lock().unwrap().mono;
+        let pending = {
+            let samples = self.samples.lock().unwrap();
+            let end = samples.len() - samples.len() % channels.max(1) as usize;
+            if end <= cursor.sent_samples {
+                return false;
+            }
+            samples[cursor.sent_samples..end].to_vec()
+        };
+        send(cursor.take(&pending, sample_rate, channels, mono));
+        true
+    }
+
+    /// Rate, length and level of the audio stop_capture returned for the
+    /// last capture. None until it has, and for a capture written to a file.
+    pub fn summary(&self) -> Option<CaptureSummary> {
+        self.summary.lock().unwrap().clone()
+    }
+
+    /// Hold the WAV stop_capture just returned for `session`, replacing the
+    /// one held before, for finalized_chunk to hand out in pieces.
+    pub fn finalize(&self, session: u64, audio: &str) -> Result<FinalizedCapture, VoiceboxError> {
+        let summary = self
+            .summary()
+            .ok_or_else(|| VoiceboxError::busy("Another capture started before this one was finalized"))?;
+        let wav = FinalizedWav::new(session, audio, &summary)?;
+        let info = wav.info.clone();
+        *self.finalized.lock().unwrap() = Some(wav);
+        Ok(info)
+    }
+
+    /// What finalize holds, if it holds anything.
+    pub fn finalized(&self) -> Option<FinalizedCapture> {
+        self.finalized.lock().unwrap().as_ref().map(|wav| wav.info.clone())
+    }
+
+    /// Chunk `index` of the finalized WAV as base64, FINALIZED_CHUNK_BYTES
+    /// of it for all but the last.
+    pub fn finalized_chunk(&self, index: u32) -> Result<String, VoiceboxError> {
+        match self.finalized.lock().unwrap().as_ref() {
+            Some(wav) => wav.chunk(index),
+            None => Err(VoiceboxError::not_found("No capture is finalized")),
+        }
+    }
+
+    /// Drop the finalized WAV. False when there was none.
+    pub fn release_finalized(&self) -> bool {
+        self.finalized.lock().unwrap().take().is_some()
+    }
+
+    /// What the current or last capture has sent as chunks.
+    pub fn stream_manifest(&self) -> CaptureManifest {
+        let options = self.options.lock().unwrap().clone();
+        let channels = *self.channels.lock().unwrap();
+        let cursor = self.stream.lock().unwrap();
+        CaptureManifest {
+            total_frames: cursor.sent_frames,
+            sample_rate: *self.sample_rate.lock().unwrap(),
+            channels: if options.mono && channels > 1 { 1 } else { channels },
+            chunk_count: cursor.chunks,
         }
     }
 
-    pub fn reset(&self) {
+    /// Markers of the current or last capture.
+    pub fn markers(&self) -> Vec<CaptureMarker> {
+        let trimmed = self.trimmed.load(Ordering::Relaxed);
+        let sample_rate = (*self.sample_rate.lock().unwrap()).max(1) as u64;
+        // At the rate the audio is saved at
+        let saved_rate = self.options.lock().unwrap().target_sample_rate.map_or(sample_rate, u64::from);
+        self.markers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|marker| {
+                Some(CaptureMarker {
+                    at_frames: marker.at_frames.checked_sub(trimmed)? * saved_rate / sample_rate,
+                    label: marker.label.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Frames kept since the start, including any rolling mode has dropped.
+    fn recorded_frames(&self) -> u64 {
+        self.trimmed.load(Ordering::Relaxed) + self.kept_frames()
+    }
+
+    /// Frames in the buffer, or written to the file of a capture to one.
+    fn kept_frames(&self) -> u64 {
+        if let Some(spill) = self.spill.lock().unwrap().as_ref() {
+            return spill.lock().unwrap().frames();
+        }
+        let channels = (*self.channels.lock().unwrap()).max(1) as u64;
+        self.samples.lock().unwrap().len() as u64 / channels
+    }
+
+    /// Options the current or last capture started with.
+    pub fn options(&self) -> CaptureOptions {
+        self.options.lock().unwrap().clone()
+    }
+
+    pub fn next_session(&self) -> u64 {
+        self.session.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    pub fn current_session(&self) -> u64 {
+        self.session.load(Ordering::SeqCst)
+    }
+
+    /// RMS and peak of the last `window_ms` of captured audio, for level meters.
+    pub fn level(&self, window_ms: u32) -> (f32, f32) {
+        let frame_samples = *self.sample_rate.lock().unwrap() as usize * *self.channels.lock().unwrap() as usize;
+        let window = frame_samples * window_ms as usize / 1000;
+        let samples = self.samples.lock().unwrap();
+        let tail = &samples[samples.len().saturating_sub(window)..];
+        if tail.is_empty() {
+            return (0.0, 0.0);
+        }
+        let peak = tail.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        let rms = (tail.iter().map(|s| s * s).sum::<f32>() / tail.len() as f32).sqrt();
+        (rms, peak)
+    }
+
+    /// RMS and peak of what the backend delivered since the last call, for
+    /// level meters polling at their own rate. Kept as frames arrive, so
+    /// unlike `level` it doesn't touch the buffer.
+    pub fn take_level(&self) -> (f32, f32) {
+        self.level.take()
+    }
+
+    /// How much of what the backend delivered to the current or last capture
+    /// clipped so far, paused or not, for a warning while it records.
+    pub fn clip_stats(&self) -> ClipStats {
+        *self.clips.lock().unwrap()
+    }
+
+    /// Keep the capture stop_capture just returned, with its metadata.
+    pub fn keep_recording(&self, session: u64, audio: &str) {
+        let metadata = self.metadata();
+        let mut recordings = self.recordings.lock().unwrap();
+        if recordings.len() == KEPT_RECORDINGS {
+            recordings.pop_front();
+        }
+        recordings.push_back((session, audio.to_string(), metadata));
+    }
+
+    pub fn recording(&self, session: u64) -> Option<String> {
+        self.recordings
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(s, _, _)| *s == session)
+            .map(|(_, audio, _)| audio.clone())
+    }
+
+    pub fn recording_metadata(&self, session: u64) -> Option<RecordingMetadata> {
+        self.recordings
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(s, _, _)| *s == session)
+            .map(|(_, _, metadata)| metadata.clone())
+    }
+
+    /// Set the fingerprint in the metadata kept for `session`. False if it is
+    /// no longer kept.
+    pub fn set_fingerprint(&self, session: u64, fingerprint: String) -> bool {
+        let mut recordings = self.recordings.lock().unwrap();
+        match recordings.iter_mut().find(|(s, _, _)| *s == session) {
+            Some((_, _, metadata)) => {
+                metadata.fingerprint = Some(fingerprint);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// A copy of the current or last capture's samples as recorded, before
+    /// processing, with their sample rate and channel count.
+    pub fn captured_audio(&self) -> (Vec<f32>, u32, u16) {
+        let samples = self.samples.lock().unwrap().clone();
+        (samples, *self.sample_rate.lock().unwrap(), *self.channels.lock().unwrap())
+    }
+
+    /// Whether `session` is still among the kept recordings, without copying it.
+    pub fn has_recording(&self, session: u64) -> bool {
+        self.recordings.lock().unwrap().iter().any(|(s, _, _)| *s == session)
+    }
+
+    /// Describe the current or last capture the way stop_capture saves it.
+    pub fn metadata(&self) -> RecordingMetadata {
+        let options = self.options.lock().unwrap().clone();
+        let sample_rate = *self.sample_rate.lock().unwrap();
+        let channels = *self.channels.lock().unwrap();
+        let frames = self.kept_frames();
+        let trimmed = self.trimmed.load(Ordering::Relaxed);
+        let mut processing = Vec::new();
+        if options.trim_silence.is_some() {
+            processing.push(Processing::TrimSilence);
+        }
+        let saved_channels = if options.mono && channels > 1 {
+            processing.push(Processing::Mono);
+            1
+        } else {
+            channels
+        };
+        let saved_rate = match options.target_sample_rate {
+            Some(rate) if rate != sample_rate => {
+                processing.push(Processing::Resample);
+                rate
+            }
+            _ => sample_rate,
+        };
+        if options.normalize {
+            processing.push(Processing::Normalize);
+        }
+        RecordingMetadata {
+            backend: Some(self.backend_name().to_string()),
+            sample_rate: saved_rate,
+            channels: saved_channels,
+            duration_ms: frames * 1000 / sample_rate.max(1) as u64,
+            dropouts: self.dropouts.load(Ordering::Relaxed),
+            processing,
+            started_at: *self.started_at.lock().unwrap(),
+            pre_roll: *self.pre_roll.lock().unwrap(),
+            health: self.health(),
+            app_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            fingerprint: None,
+            // Less those rolling mode dropped along with the audio before them
+            pauses: self
+                .pause
+                .lock()
+                .unwrap()
+                .intervals()
+                .into_iter()
+                .filter_map(|(at, left_out)| {
+                    Some(PauseInterval {
+                        at_ms: at.checked_sub(trimmed)? * 1000 / sample_rate.max(1) as u64,
+                        duration_ms: left_out * 1000 / sample_rate.max(1) as u64,
+                    })
+                })
+                .collect(),
+            source_format: *self.source_format.lock().unwrap(),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
         *self.samples.lock().unwrap() = Vec::new();
         *self.error.lock().unwrap() = None;
+        *self.auto_stop.lock().unwrap() = None;
+        *self.lifecycle.lock().unwrap() = Lifecycle::default();
+        self.trimmed.store(0, Ordering::Relaxed);
+        *self.spill.lock().unwrap() = None;
+        self.dropouts.store(0, Ordering::Relaxed);
+        self.health.reset();
+        self.level.take();
+        *self.clips.lock().unwrap() = ClipStats::default();
+        *self.pause.lock().unwrap() = PauseState::default();
+        self.held.store(false, Ordering::SeqCst);
+        *self.hold.lock().unwrap() = HoldClock::default();
+        self.markers.lock().unwrap().clear();
+        *self.stream.lock().unwrap() = StreamCursor::default();
+        *self.summary.lock().unwrap() = None;
+        *self.source_format.lock().unwrap() = None;
+        *self.pre_roll.lock().unwrap() = None;
+    }
+
+    /// Carry on the running rolling capture as the capture `options` asks
+    /// for, keeping its last pre_roll_ms and its backend stream, so the
+    /// audio runs on without a seam. `running` is its stop sender, which
+    /// takes the new limit.
+    fn take_over(
+        &self,
+        running: &tokio::sync::mpsc::Sender<Duration>,
+        options: CaptureOptions,
+    ) -> Result<(), VoiceboxError> {
+        let rolling = self.options.lock().unwrap().clone();
+        let same_source = options.device_id == rolling.device_id
+            && options.application_pid == rolling.application_pid
+            && options.silence_stop_secs == rolling.silence_stop_secs
+            && options.silence_threshold == rolling.silence_threshold
+            && options.auto_pause_on_source_silence == rolling.auto_pause_on_source_silence;
+        if !same_source {
+            return Err(VoiceboxError::invalid_argument(
+                "A capture with pre-roll carries on the rolling capture, so it records the same source \
+                 with the same silence stop and auto-pause",
+            ));
+        }
+        if options.spill_path.is_some() {
+            return Err(VoiceboxError::invalid_argument(
+                "A capture written to a file can't take pre-roll: the rolling capture's audio is in memory",
+            ));
+        }
+        if self.held.load(Ordering::SeqCst) {
+            return Err(VoiceboxError::invalid_argument(
+                "The rolling capture is paused; resume it before taking pre-roll from it",
+            ));
+        }
+        // Under the buffer's lock, so no delivery lands between the cut and
+        // the new limit
+        let included = {
+            let mut samples = self.samples.lock().unwrap();
+            let sample_rate = (*self.sample_rate.lock().unwrap()).max(1) as u64;
+            let channels = (*self.channels.lock().unwrap()).max(1) as usize;
+            let keep = options.pre_roll_ms as u64 * sample_rate / 1000;
+            trim_front(&mut samples, keep as usize, channels, &self.trimmed);
+            *self.buffer_limit.lock().unwrap() = BufferLimit {
+                max_secs: options.max_buffer_secs,
+                rolling: options.rolling,
+            };
+            Duration::from_millis((samples.len() / channels) as u64 * 1000 / sample_rate)
+        };
+        // Only what describes the new capture starts over; trimmed and the
+        // pauses still place its markers and gaps
+        *self.auto_stop.lock().unwrap() = None;
+        self.dropouts.store(0, Ordering::Relaxed);
+        self.health.reset();
+        self.level.take();
+        *self.clips.lock().unwrap() = ClipStats::default();
+        *self.hold.lock().unwrap() = HoldClock::default();
+        self.markers.lock().unwrap().clear();
+        *self.stream.lock().unwrap() = StreamCursor::default();
+        *self.summary.lock().unwrap() = None;
+        *self.pre_roll.lock().unwrap() = Some(PreRoll {
+            requested_ms: options.pre_roll_ms,
+            included_ms: included.as_millis() as u64,
+        });
+        *self.started_at.lock().unwrap() = unix_secs_before(included);
+        let now = Instant::now();
+        self.lifecycle.lock().unwrap().start_at(now.checked_sub(included).unwrap_or(now));
+        // Can't be full: nothing else sends on it, and its task takes each one
+        let _ = running.try_send(Duration::from_secs(options.max_duration_secs as u64));
+        *self.options.lock().unwrap() = options.clone();
+        METRICS.captures_started.inc();
+        audio_log::record(AudioEvent::CaptureStarted {
+            backend: self.backend.name(),
+            max_duration_secs: options.max_duration_secs,
+            silence_stop_secs: options.silence_stop_secs,
+            mono: options.mono,
+            normalize: options.normalize,
+        });
+        Ok(())
+    }
+
+    fn sink(&self, options: &CaptureOptions, generation: u64) -> FrameSink {
+        FrameSink {
+            samples: self.samples.clone(),
+            sample_rate: self.sample_rate.clone(),
+            channels: self.channels.clone(),
+            source_format: self.source_format.clone(),
+            error: self.error.clone(),
+            stop_tx: self.stop_tx.clone(),
+            lifecycle: self.lifecycle.clone(),
+            auto_stop: self.auto_stop.clone(),
+            dropouts: self.dropouts.clone(),
+            health: self.health.clone(),
+            level: self.level.clone(),
+            clips: self.clips.clone(),
+            format_set: Arc::new(AtomicBool::new(false)),
+            silent_frames: Arc::new(AtomicU64::new(0)),
+            heard: Arc::new(AtomicBool::new(false)),
+            silence_stop_secs: options.silence_stop_secs,
+            silence_threshold: options.silence_threshold,
+            auto_pause: options.auto_pause_on_source_silence,
+            pause: self.pause.clone(),
+            held: self.held.clone(),
+            forward: None,
+            exclude_own_audio: false,
+            device_id: options.device_id.clone(),
+            application_pid: options.application_pid,
+            buffer_limit: self.buffer_limit.clone(),
+            trimmed: self.trimmed.clone(),
+            spill: self.spill.lock().unwrap().clone(),
+            generation,
+            active_generation: self.generation.clone(),
+        }
     }
 }
+
+impl Default for AudioCaptureState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn synthetic_requested() -> bool {
+    std::env::var(BACKEND_ENV).is_ok_and(|value| value.eq_ignore_ascii_case("synthetic"))
+}
+
+/// The platform backend, or the synthetic one if BACKEND_ENV asks for it.
+pub fn default_backend() -> Arc<dyn CaptureBackend> {
+    if synthetic_requested() {
+        tracing::info!("Using the synthetic capture backend ({}=synthetic)", BACKEND_ENV);
+        return Arc::new(SyntheticBackend::default());
+    }
+    Arc::new(platform::PlatformBackend)
+}
+
+/// The default input device, or the synthetic backend if BACKEND_ENV asks
+/// for it.
+pub fn default_microphone_backend() -> Arc<dyn CaptureBackend> {
+    if synthetic_requested() {
+        return Arc::new(SyntheticBackend::new(SyntheticPattern::Noise { amplitude: 0.1 }));
+    }
+    #[cfg(feature = "native-backends")]
+    let backend: Arc<dyn CaptureBackend> = Arc::new(microphone::MicrophoneBackend);
+    #[cfg(not(feature = "native-backends"))]
+    let backend: Arc<dyn CaptureBackend> = Arc::new(unsupported::PlatformBackend);
+    backend
+}
+
+/// Whether system audio can be captured here.
+pub fn is_supported() -> bool {
+    synthetic_requested() || platform::is_supported()
+}
+
+/// Start capturing, stopping on its own after `max_duration_secs`.
+pub async fn start_capture(state: &AudioCaptureState, max_duration_secs: u32) -> Result<(), VoiceboxError> {
+    start_capture_with(state, CaptureOptions::new(max_duration_secs)).await
+}
+
+/// Start capturing. Fails with Busy while another capture is running; that
+/// capture carries on. With pre_roll_ms, a running rolling capture is taken
+/// over instead: the new capture keeps its last pre_roll_ms and its stream.
+pub async fn start_capture_with(state: &AudioCaptureState, options: CaptureOptions) -> Result<(), VoiceboxError> {
+    if let Some(auto_pause) = &options.auto_pause_on_source_silence {
+        auto_pause.validate()?;
+    }
+    check_normalize(&options)?;
+    if let Some(trim) = &options.trim_silence {
+        trim.validate()?;
+        if options.stream {
+            return Err(VoiceboxError::invalid_argument(
+                "A streamed capture can't be trimmed of silence: its chunks go out before its last sound is known",
+            ));
+        }
+    }
+    if options.silence_stop_secs.is_some_and(|secs| !secs.is_finite() || secs <= 0.0) {
+        return Err(VoiceboxError::invalid_argument("silence_stop_secs must be more than 0"));
+    }
+    if !options.silence_threshold.is_finite() || !(0.0..=1.0).contains(&options.silence_threshold) {
+        return Err(VoiceboxError::invalid_argument("The silence threshold must be 0 dBFS or lower"));
+    }
+    check_target_sample_rate(&options)?;
+    if options.max_buffer_secs == Some(0) {
+        return Err(VoiceboxError::invalid_argument("max_buffer_secs must be at least 1"));
+    }
+    if options.rolling {
+        if options.max_buffer_secs.is_none() {
+            return Err(VoiceboxError::invalid_argument(
+                "A rolling capture needs max_buffer_secs, the most recent audio to keep",
+            ));
+        }
+        if options.stream {
+            return Err(VoiceboxError::invalid_argument(
+                "A rolling capture can't be streamed: the audio it drops may not have been sent yet",
+            ));
+        }
+    }
+    if options.spill_path.is_some() {
+        let kept_whole = [
+            (options.stream, "streamed"),
+            (options.split_on_markers, "split at its markers"),
+            (options.max_buffer_secs.is_some(), "held to max_buffer_secs"),
+            (options.trim_silence.is_some(), "trimmed of silence"),
+        ];
+        if let Some((_, what)) = kept_whole.iter().find(|(set, _)| *set) {
+            return Err(VoiceboxError::invalid_argument(format!(
+                "A capture written to a file can't be {}: its audio isn't kept in memory",
+                what
+            )));
+        }
+    }
+    if options.application_pid.is_some() {
+        if options.device_id.is_some() {
+            return Err(VoiceboxError::invalid_argument(
+                "A capture records either a device or an application, not both",
+            ));
+        }
+        if !state.backend.captures_applications() {
+            return Err(VoiceboxError::unsupported(format!(
+                "The {} capture backend can't record a single application here",
+                state.backend.name()
+            )));
+        }
+    }
+    if options.device_id.is_some() && !state.backend.selects_device() {
+        return Err(VoiceboxError::unsupported(format!(
+            "The {} capture backend can't record a chosen device",
+            state.backend.name()
+        )));
+    }
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Duration>(1);
+    let generation = {
+        let mut stop_tx = state.stop_tx.lock().unwrap();
+        if state.is_capturing() {
+            if options.pre_roll_ms > 0 && state.options.lock().unwrap().rolling {
+                if let Some(running) = stop_tx.as_ref() {
+                    return state.take_over(running, options);
+                }
+            }
+            return Err(VoiceboxError::busy("A system audio capture is already running"));
+        }
+        if options.pre_roll_ms > 0 && options.require_pre_roll {
+            return Err(VoiceboxError::unsupported(format!(
+                "No rolling capture is running to take {} ms of pre-roll from",
+                options.pre_roll_ms
+            )));
+        }
+        let spill = match &options.spill_path {
+            Some(path) => Some(Spill::create(path.clone(), options.mono, options.output_format).map_err(|e| {
+                VoiceboxError::io(format!("Can't write the capture to {}: {}", path.display(), e))
+            })?),
+            None => None,
+        };
+        // Before the reset, so the previous capture's sink is already stale
+        let generation = state.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        state.reset();
+        *state.spill.lock().unwrap() = spill.map(|spill| Arc::new(Mutex::new(spill)));
+        *state.buffer_limit.lock().unwrap() = BufferLimit {
+            max_secs: options.max_buffer_secs,
+            rolling: options.rolling,
+        };
+        *state.pre_roll.lock().unwrap() = (options.pre_roll_ms > 0).then_some(PreRoll {
+            requested_ms: options.pre_roll_ms,
+            included_ms: 0,
+        });
+        *stop_tx = Some(tx);
+        state.lifecycle.lock().unwrap().start();
+        generation
+    };
+    *state.options.lock().unwrap() = options.clone();
+    *state.started_at.lock().unwrap() = unix_secs_before(Duration::ZERO);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let sink = state.sink(&options, generation);
+    // Backends return once their stream runs or has failed to, which can
+    // take a while, so the wait is kept off the async runtime
+    let backend = state.backend.clone();
+    let (start_sink, start_stop) = (sink.clone(), stop.clone());
+    let span = tracing::Span::current();
+    let started = tokio::task::spawn_blocking(move || {
+        let _span = span.entered();
+        backend.start(start_sink, start_stop)
+    })
+    .await
+        .unwrap_or_else(|e| Err(e.into()));
+    if let Err(e) = started {
+        stop.store(true, Ordering::Relaxed);
+        state.stop_tx.lock().unwrap().take();
+        state.lifecycle.lock().unwrap().idle();
+        METRICS.captures_failed.inc();
+        audio_log::record(AudioEvent::CaptureFailed {
+            error: e.message().to_string(),
+        });
+        return Err(e);
+    }
+    METRICS.captures_started.inc();
+    audio_log::record(AudioEvent::CaptureStarted {
+        backend: state.backend.name(),
+        max_duration_secs: options.max_duration_secs,
+        silence_stop_secs: options.silence_stop_secs,
+        mono: options.mono,
+        normalize: options.normalize,
+    });
+
+    // The backend stops once the sender is dropped: by stop_capture, a
+    // failure or silence. Or at the limit, handled here; by then a later
+    // capture may own the sender, so the sink checks it is still current.
+    // What is sent on it is the limit of a capture that took this one over.
+    let mut limit = Duration::from_secs(options.max_duration_secs as u64);
+    let hold = state.hold.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                next = rx.recv() => match next {
+                    Some(next) => limit = next,
+                    None => break,
+                },
+                _ = limit_reached(limit, hold.clone()) => {
+                    sink.end(AutoStopReason::Limit);
+                    break;
+                }
+            }
+        }
+        stop.store(true, Ordering::Relaxed);
+    });
+    Ok(())
+}
+
+/// Once `limit` has passed from now. Time paused by hand doesn't count, so
+/// the deadline moves with it.
+async fn limit_reached(limit: Duration, hold: Arc<Mutex<HoldClock>>) {
+    let started = Instant::now();
+    loop {
+        let (held, holding) = {
+            let hold = hold.lock().unwrap();
+            (hold.held(), hold.held_since.is_some())
+        };
+        let remaining = limit.saturating_sub(started.elapsed().saturating_sub(held));
+        if remaining.is_zero() {
+            break;
+        }
+        tokio::time::sleep(if holding { HOLD_POLL } else { remaining }).await;
+    }
+}
+
+/// The Unix time `before` now, in seconds.
+fn unix_secs_before(before: Duration) -> Option<u64> {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok()?;
+    Some(now.saturating_sub(before).as_secs())
+}
+
+/// Stop the capture, or collect one that already ended on its own, and
+/// return it as base64 WAV, or the path of the file it was written to.
+pub async fn stop_capture(state: &AudioCaptureState) -> Result<String, VoiceboxError> {
+    state.stop_tx.lock().unwrap().take();
+    state.lifecycle.lock().unwrap().stopping();
+    let result = collect(state).await;
+    state.lifecycle.lock().unwrap().idle();
+    result
+}
+
+/// How stop_capture_as saves a capture differently from how it was started.
+/// What is None is left as it was.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SaveAs {
+    pub target_sample_rate: Option<u32>,
+    pub output_format: Option<WavSampleFormat>,
+    pub normalize: Option<NormalizeTarget>,
+}
+
+/// Like stop_capture, saving the capture as `save` asks. What can't be done
+/// leaves the capture running.
+pub async fn stop_capture_as(state: &AudioCaptureState, save: SaveAs) -> Result<String, VoiceboxError> {
+    {
+        let mut options = state.options.lock().unwrap();
+        *options = saved_options(&options, save)?;
+    }
+    stop_capture(state).await
+}
+
+/// `options` with what `save` changes.
+fn saved_options(options: &CaptureOptions, save: SaveAs) -> Result<CaptureOptions, VoiceboxError> {
+    let mut saved = options.clone();
+    if let Some(rate) = save.target_sample_rate {
+        saved.target_sample_rate = Some(rate);
+        check_target_sample_rate(&saved)?;
+    }
+    if let Some(format) = save.output_format {
+        // The file's header already says how its samples are stored
+        if options.spill_path.is_some() && format != options.output_format {
+            return Err(VoiceboxError::invalid_argument(
+                "A capture written to a file is saved in the format it was started with",
+            ));
+        }
+        saved.output_format = format;
+    }
+    if let Some(target) = save.normalize {
+        saved.normalize = true;
+        saved.normalize_target = Some(target);
+        check_normalize(&saved)?;
+    }
+    Ok(saved)
+}
+
+/// How save_capture_to_file treats the path it writes to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct SaveToFile {
+    /// Replace a file already at the path, instead of refusing.
+    pub overwrite: bool,
+    /// Create the path's missing parent directories.
+    pub create_dirs: bool,
+}
+
+/// What save_capture_to_file wrote.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct SavedCapture {
+    pub path: String,
+    /// Of the whole WAV, header included.
+    pub bytes: u64,
+    pub duration_secs: f64,
+}
+
+/// Write the last capture to `path` as the WAV stop_capture built from it,
+/// or saved as `save` asks, without it passing through the frontend. For a
+/// stopped capture whose audio is still in memory, so not one written to a
+/// file or a failed one.
+pub async fn save_capture_to_file(
+    state: &AudioCaptureState,
+    path: PathBuf,
+    save: SaveAs,
+    file: SaveToFile,
+) -> Result<SavedCapture, VoiceboxError> {
+    let options = saved_options(&state.options(), save)?;
+    check_in_memory(state, &options, "saving it")?;
+    let (samples, sample_rate, channels) = state.captured_audio();
+    // Encoding and writing a long capture takes a while, so off the async threads
+    tokio::task::spawn_blocking(move || {
+        let (wav, summary) = pipeline::finish_wav(samples, sample_rate, channels, &options)?;
+        write_new(&path, &wav, file)?;
+        Ok(SavedCapture {
+            path: path.display().to_string(),
+            bytes: wav.len() as u64,
+            duration_secs: summary.duration_secs,
+        })
+    })
+    .await?
+}
+
+/// That the last capture stopped without failing and its audio is still in
+/// memory, before `doing` something with it.
+fn check_in_memory(state: &AudioCaptureState, options: &CaptureOptions, doing: &str) -> Result<(), VoiceboxError> {
+    if state.lifecycle() != CaptureLifecycle::Idle {
+        return Err(VoiceboxError::busy(format!("Stop the capture before {}", doing)));
+    }
+    if let Some(spill_path) = &options.spill_path {
+        return Err(VoiceboxError::invalid_argument(format!(
+            "This capture was written to {} as it recorded",
+            spill_path.display()
+        )));
+    }
+    if let Some(error) = state.error.lock().unwrap().clone() {
+        return Err(VoiceboxError::capture(error));
+    }
+    Ok(())
+}
+
+/// One utterance segment_capture found, as a WAV of its own.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct CaptureSegment {
+    pub index: usize,
+    /// Where it starts and ends in the capture.
+    pub start_secs: f64,
+    pub end_secs: f64,
+    pub data_base64: String,
+}
+
+/// Split the stopped capture into its utterances, found by
+/// audio_util::voice_segments, each saved the way stop_capture saved the
+/// whole. Empty when nothing was heard. Like save_capture_to_file, for a
+/// capture whose audio is still in memory.
+pub async fn segment_capture(
+    state: &AudioCaptureState,
+    vad: audio_util::VadOptions,
+) -> Result<Vec<CaptureSegment>, VoiceboxError> {
+    let valid = [vad.min_segment_secs, vad.max_segment_secs, vad.min_gap_secs]
+        .iter()
+        .all(|secs| secs.is_finite() && *secs >= 0.0);
+    if !valid || !vad.silence_threshold_db.is_finite() {
+        return Err(VoiceboxError::invalid_argument(
+            "Segment lengths and gaps must be 0 or more seconds, and the threshold a level in dB",
+        ));
+    }
+    if vad.max_segment_secs > 0.0 && vad.max_segment_secs < vad.min_segment_secs {
+        return Err(VoiceboxError::invalid_argument(format!(
+            "The longest segment ({}s) can't be shorter than the shortest ({}s)",
+            vad.max_segment_secs, vad.min_segment_secs
+        )));
+    }
+    let options = state.options();
+    check_in_memory(state, &options, "segmenting it")?;
+    let (samples, sample_rate, channels) = state.captured_audio();
+    tokio::task::spawn_blocking(move || {
+        let width = channels.max(1) as usize;
+        let ranges = audio_util::voice_segments(&samples, channels, sample_rate, vad);
+        ranges
+            .into_iter()
+            .enumerate()
+            .map(|(index, frames)| {
+                let audio = samples[frames.start * width..frames.end * width].to_vec();
+                let (data_base64, _) = pipeline::finish(audio, sample_rate, channels, &options)?;
+                Ok::<_, VoiceboxError>(CaptureSegment {
+                    index,
+                    start_secs: frames.start as f64 / sample_rate.max(1) as f64,
+                    end_secs: frames.end as f64 / sample_rate.max(1) as f64,
+                    data_base64,
+                })
+            })
+            .collect()
+    })
+    .await?
+}
+
+/// Write `bytes` to `path`, refusing a file already there unless `file`
+/// says to overwrite it.
+fn write_new(path: &Path, bytes: &[u8], file: SaveToFile) -> Result<(), VoiceboxError> {
+    if file.create_dirs {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+    let mut open = std::fs::OpenOptions::new();
+    if file.overwrite {
+        open.write(true).create(true).truncate(true);
+    } else {
+        open.write(true).create_new(true);
+    }
+    let mut out = match open.open(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            return Err(VoiceboxError::invalid_argument(format!("{} already exists", path.display())));
+        }
+        result => result?,
+    };
+    out.write_all(bytes)?;
+    Ok(())
+}
+
+fn check_normalize(options: &CaptureOptions) -> Result<(), VoiceboxError> {
+    if !options.normalize {
+        return Ok(());
+    }
+    if let Some(target) = &options.normalize_target {
+        target.validate()?;
+    }
+    if options.stream {
+        return Err(VoiceboxError::invalid_argument(
+            "A streamed capture can't be normalized: its chunks go out before its peak is known",
+        ));
+    }
+    if options.spill_path.is_some() {
+        return Err(VoiceboxError::invalid_argument(
+            "A capture written to a file can't be normalized: its audio isn't kept in memory",
+        ));
+    }
+    Ok(())
+}
+
+fn check_target_sample_rate(options: &CaptureOptions) -> Result<(), VoiceboxError> {
+    let Some(rate) = options.target_sample_rate else {
+        return Ok(());
+    };
+    if rate == 0 || rate > audio_util::MAX_SAMPLE_RATE {
+        return Err(VoiceboxError::invalid_argument(format!(
+            "The target sample rate must be between 1 and {} Hz",
+            audio_util::MAX_SAMPLE_RATE
+        )));
+    }
+    if options.stream {
+        return Err(VoiceboxError::invalid_argument(
+            "A streamed capture can't be resampled: its chunks go out at the rate it records at",
+        ));
+    }
+    if options.spill_path.is_some() {
+        return Err(VoiceboxError::invalid_argument(
+            "A capture written to a file can't be resampled: its audio isn't kept in memory",
+        ));
+    }
+    Ok(())
+}
+
+/// Wait out the last frames and encode what the capture kept.
+async fn collect(state: &AudioCaptureState) -> Result<String, VoiceboxError> {
+    tokio::time::sleep(state.backend.drain_time()).await;
+    let spill = state.spill.lock().unwrap().clone();
+    if let Some(spill) = spill {
+        return close_spill(state, &mut spill.lock().unwrap());
+    }
+    let options = state.options.lock().unwrap().clone();
+    // Rolling mode lets the buffer run a little over, so cut it to size
+    if let Some(max_secs) = options.max_buffer_secs.filter(|_| options.rolling) {
+        let max_frames = max_secs as usize * *state.sample_rate.lock().unwrap() as usize;
+        let channels = (*state.channels.lock().unwrap()).max(1) as usize;
+        trim_front(&mut state.samples.lock().unwrap(), max_frames, channels, &state.trimmed);
+    }
+    if let Some(trim) = options.trim_silence {
+        trim_silence(state, trim);
+    }
+
+    // Already in the audio log, from FrameSink::fail
+    if let Some(error) = state.error.lock().unwrap().clone() {
+        METRICS.captures_failed.inc();
+        return Err(VoiceboxError::capture(error));
+    }
+    let samples = state.samples.lock().unwrap().clone();
+    let sample_rate = *state.sample_rate.lock().unwrap();
+    let channels = *state.channels.lock().unwrap();
+    audio_log::record(AudioEvent::CaptureStopped {
+        frames: samples.len() as u64 / channels.max(1) as u64,
+        sample_rate,
+        channels,
+        dropouts: state.dropouts.load(Ordering::Relaxed),
+        auto_stop: state.auto_stop().map(|reason| reason.as_str()),
+    });
+    // Resampling a long capture takes a while, so off the async threads
+    let finished = tokio::task::spawn_blocking(move || pipeline::finish(samples, sample_rate, channels, &options));
+    match finished.await? {
+        Ok((audio, summary)) => {
+            *state.summary.lock().unwrap() = Some(summary);
+            Ok(audio)
+        }
+        Err(e) => {
+            METRICS.captures_failed.inc();
+            audio_log::record(AudioEvent::CaptureFailed {
+                error: e.message().to_string(),
+            });
+            Err(e)
+        }
+    }
+}
+
+/// Cut the silence from either end of the buffer, in whole frames. The
+/// leading frames count as dropped, so markers and pauses stay put; a
+/// capture that is all silence is left empty.
+fn trim_silence(state: &AudioCaptureState, trim: TrimSilence) {
+    let sample_rate = *state.sample_rate.lock().unwrap() as u64;
+    let channels = *state.channels.lock().unwrap();
+    let mut samples = state.samples.lock().unwrap();
+    let padding = (sample_rate * trim.padding_ms as u64 / 1000) as usize;
+    let frames = audio_util::loud_frames(&samples, channels, trim.threshold(), padding).unwrap_or(0..0);
+    let channels = channels.max(1) as usize;
+    samples.truncate(frames.end * channels);
+    trim_front(&mut samples, frames.len(), channels, &state.trimmed);
+}
+
+/// Finish the file of a capture written to one, and return its path.
+fn close_spill(state: &AudioCaptureState, spill: &mut Spill) -> Result<String, VoiceboxError> {
+    let finished = spill.finish();
+    // Already in the audio log, from FrameSink::fail
+    if let Some(error) = state.error.lock().unwrap().clone() {
+        METRICS.captures_failed.inc();
+        return Err(VoiceboxError::capture(error));
+    }
+    let result = match finished {
+        Ok(()) if spill.frames() == 0 => {
+            let _ = std::fs::remove_file(spill.path());
+            Err(VoiceboxError::capture(
+                "No audio samples captured. Make sure audio is playing on your system during recording.",
+            ))
+        }
+        Ok(()) => Ok(spill.path().display().to_string()),
+        Err(e) => Err(VoiceboxError::capture(e)),
+    };
+    match &result {
+        Ok(_) => audio_log::record(AudioEvent::CaptureStopped {
+            frames: spill.frames(),
+            sample_rate: *state.sample_rate.lock().unwrap(),
+            channels: *state.channels.lock().unwrap(),
+            dropouts: state.dropouts.load(Ordering::Relaxed),
+            auto_stop: state.auto_stop().map(|reason| reason.as_str()),
+        }),
+        Err(e) => {
+            METRICS.captures_failed.inc();
+            audio_log::record(AudioEvent::CaptureFailed {
+                error: e.message().to_string(),
+            });
+        }
+    }
+    result
+}
+
+/// Stop the capture and throw its audio away, without encoding it. False,
+/// having done nothing, when no capture is running, so it is safe to call
+/// twice.
+pub async fn cancel_capture(state: &AudioCaptureState) -> bool {
+    let frames = {
+        let mut stop_tx = state.stop_tx.lock().unwrap();
+        if !state.is_capturing() {
+            return false;
+        }
+        stop_tx.take();
+        // Stale from here on, so frames still on their way are dropped
+        state.generation.fetch_add(1, Ordering::SeqCst);
+        let frames = state.kept_frames();
+        // A capture to a file leaves no file behind either
+        if let Some(spill) = state.spill.lock().unwrap().as_ref() {
+            let mut spill = spill.lock().unwrap();
+            let _ = spill.finish();
+            let _ = std::fs::remove_file(spill.path());
+        }
+        state.reset();
+        frames
+    };
+    audio_log::record(AudioEvent::CaptureCancelled {
+        frames,
+        sample_rate: *state.sample_rate.lock().unwrap(),
+    });
+    // So the device is let go before another capture can start
+    tokio::time::sleep(state.backend.drain_time()).await;
+    true
+}
@@ -0,0 +1,119 @@
+use super::{CaptureOptions, NormalizeTarget};
+use crate::error::VoiceboxError;
+use crate::audio_util::{self, EncodeSpec};
+use base64::{engine::general_purpose, Engine as _};
+
+/// Peak level normalization scales to, about -1 dBFS.
+pub const NORMALIZE_PEAK: f32 = 0.89;
+
+/// What stop_capture saved, so the frontend needn't read the WAV header.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct CaptureSummary {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub num_frames: u64,
+    pub duration_secs: f64,
+    /// Highest sample magnitude saved, 1.0 at full scale.
+    pub peak_level: f32,
+    /// Whether any sample reached full scale, where the integer formats
+    /// clamp it.
+    pub clipped: bool,
+    /// What normalization changed the level by, in dB. 0 without it.
+    pub gain_db: f32,
+    /// Frames that clipped as captured, before any processing: a channel
+    /// at audio_util::CLIP_THRESHOLD or over.
+    pub clipped_frames: u64,
+    /// clipped_frames as a share of the captured frames, 0 to 100.
+    pub clip_percentage: f64,
+    /// The most clipped frames in a row.
+    pub longest_clip_frames: u64,
+    /// Whether a run reached audio_util::CLIP_RUN_FRAMES: the source was too
+    /// loud, and the recording sounds it.
+    pub clipping_detected: bool,
+}
+
+/// Turn the captured frames into the base64 WAV stop_capture returns,
+/// applying the capture's downmix, resampling and normalization.
+pub fn finish(
+    samples: Vec<f32>,
+    sample_rate: u32,
+    channels: u16,
+    options: &CaptureOptions,
+) -> Result<(String, CaptureSummary), VoiceboxError> {
+    let (wav, summary) = finish_wav(samples, sample_rate, channels, options)?;
+    Ok((general_purpose::STANDARD.encode(&wav), summary))
+}
+
+/// Like finish, returning the WAV's bytes.
+pub fn finish_wav(
+    samples: Vec<f32>,
+    sample_rate: u32,
+    channels: u16,
+    options: &CaptureOptions,
+) -> Result<(Vec<u8>, CaptureSummary), VoiceboxError> {
+    if samples.is_empty() {
+        return Err(VoiceboxError::capture(
+            "No audio samples captured. Make sure audio is playing on your system during recording.",
+        ));
+    }
+    let clips = audio_util::clip_stats(&samples, channels);
+    let (mut samples, channels) = if options.mono && channels > 1 {
+        (audio_util::downmix(&samples, channels), 1)
+    } else {
+        (samples, channels)
+    };
+    let sample_rate = match options.target_sample_rate {
+        Some(rate) if rate != sample_rate => {
+            samples = audio_util::resample_sinc(&samples, channels, sample_rate, rate);
+            rate
+        }
+        _ => sample_rate,
+    };
+    let gain_db = if options.normalize {
+        let gain_db = normalize_gain_db(&samples, channels, sample_rate, options.normalize_target);
+        let gain = 10f32.powf(gain_db / 20.0);
+        samples.iter_mut().for_each(|sample| *sample *= gain);
+        gain_db
+    } else {
+        0.0
+    };
+    let peak_level = audio_util::peak(&samples);
+    let num_frames = (samples.len() / channels.max(1) as usize) as u64;
+    let summary = CaptureSummary {
+        sample_rate,
+        channels,
+        num_frames,
+        duration_secs: num_frames as f64 / sample_rate.max(1) as f64,
+        peak_level,
+        clipped: peak_level >= 1.0,
+        gain_db,
+        clipped_frames: clips.clipped_frames,
+        clip_percentage: clips.percentage(),
+        longest_clip_frames: clips.longest_run,
+        clipping_detected: clips.detected(),
+    };
+    let spec = EncodeSpec::pcm16(sample_rate, channels).with_format(options.output_format);
+    let wav = audio_util::encode_wav(&samples, spec).map_err(VoiceboxError::capture)?;
+    Ok((wav, summary))
+}
+
+/// The gain in dB that brings `samples` to `target`, or to NORMALIZE_PEAK
+/// without one, and at most NormalizeTarget::MAX_GAIN_DB. 0 for silence,
+/// which has no level to bring anywhere.
+fn normalize_gain_db(samples: &[f32], channels: u16, sample_rate: u32, target: Option<NormalizeTarget>) -> f32 {
+    let peak = audio_util::peak(samples);
+    if peak <= f32::EPSILON {
+        return 0.0;
+    }
+    let peak_db = 20.0 * peak.log10();
+    let gain_db = match target {
+        None => 20.0 * NORMALIZE_PEAK.log10() - peak_db,
+        Some(NormalizeTarget::PeakDb(db)) => db - peak_db,
+        // Held back so the peak stays under full scale
+        Some(NormalizeTarget::Lufs(lufs)) => match audio_util::integrated_loudness(samples, channels, sample_rate) {
+            Some(loudness) => (lufs - loudness).min(-peak_db),
+            None => 0.0,
+        },
+    };
+    gain_db.min(NormalizeTarget::MAX_GAIN_DB)
+}
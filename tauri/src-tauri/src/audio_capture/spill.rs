@@ -0,0 +1,81 @@
+use crate::audio_util::{self, EncodeSpec, WavEncoder, WavSampleFormat};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// Where a capture spilled to a file stands.
+enum SpillFile {
+    /// Created at the start; the header waits for the first frames, which
+    /// bring the format.
+    Created(File),
+    Writing(WavEncoder<BufWriter<File>>),
+    Finished,
+}
+
+/// A capture's audio going to a WAV file as it arrives, instead of into
+/// memory.
+pub(crate) struct Spill {
+    path: PathBuf,
+    mono: bool,
+    format: WavSampleFormat,
+    file: SpillFile,
+    frames: u64,
+}
+
+impl Spill {
+    /// Create the file now, so a path that can't be written fails the start.
+    pub(super) fn create(path: PathBuf, mono: bool, format: WavSampleFormat) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = File::create(&path)?;
+        Ok(Self {
+            path,
+            mono,
+            format,
+            file: SpillFile::Created(file),
+            frames: 0,
+        })
+    }
+
+    pub(super) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Frames written so far, per channel.
+    pub(super) fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Append interleaved frames, downmixed for a mono capture. Frames after
+    /// finish are dropped.
+    pub(super) fn write(&mut self, frames: &[f32], sample_rate: u32, channels: u16) -> Result<(), String> {
+        let out_channels = if self.mono { 1 } else { channels };
+        self.file = match std::mem::replace(&mut self.file, SpillFile::Finished) {
+            SpillFile::Created(file) => {
+                let spec = EncodeSpec::pcm16(sample_rate, out_channels).with_format(self.format);
+                SpillFile::Writing(WavEncoder::new(BufWriter::new(file), spec)?)
+            }
+            file => file,
+        };
+        let SpillFile::Writing(encoder) = &mut self.file else {
+            return Ok(());
+        };
+        if self.mono && channels > 1 {
+            encoder.write(&audio_util::downmix(frames, channels))?;
+        } else {
+            encoder.write(frames)?;
+        }
+        self.frames += frames.len() as u64 / channels.max(1) as u64;
+        Ok(())
+    }
+
+    /// Fill in the header, keeping what was written. Later calls do nothing.
+    pub(super) fn finish(&mut self) -> Result<(), String> {
+        match std::mem::replace(&mut self.file, SpillFile::Finished) {
+            SpillFile::Writing(encoder) => encoder.finish().map(drop),
+            // Nothing came, so there is no format for a header
+            SpillFile::Created(_) | SpillFile::Finished => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use crate::audio_util;
+use base64::{engine::general_purpose, Engine as _};
+use std::borrow::Cow;
+
+/// How often a streamed capture's new frames go out as a chunk.
+pub const STREAM_CHUNK_MS: u32 = 250;
+
+/// Frames a streamed capture recorded since its last chunk.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct CaptureChunk {
+    /// 0 for a capture's first chunk, one up for each after it.
+    pub sequence: u64,
+    /// Where the chunk starts in the streamed audio, in frames.
+    pub start_frame: u64,
+    pub frames: u64,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Interleaved 16-bit little-endian PCM, base64.
+    pub pcm: String,
+}
+
+/// What a streamed capture sent, for stop to return instead of its audio.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct CaptureManifest {
+    /// Per channel, across every chunk.
+    pub total_frames: u64,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub chunk_count: u64,
+}
+
+/// How far a streamed capture's buffer has been sent.
+#[derive(Debug, Default)]
+pub(crate) struct StreamCursor {
+    pub(super) chunks: u64,
+    /// Into the capture's interleaved buffer, on a frame boundary.
+    pub(super) sent_samples: usize,
+    pub(super) sent_frames: u64,
+}
+
+impl StreamCursor {
+    /// Turn `pending`, the whole frames after `sent_samples`, into the next
+    /// chunk, downmixed for a mono capture.
+    pub(super) fn take(&mut self, pending: &[f32], sample_rate: u32, channels: u16, mono: bool) -> CaptureChunk {
+        let (samples, out_channels) = if mono && channels > 1 {
+            (Cow::Owned(audio_util::downmix(pending, channels)), 1)
+        } else {
+            (Cow::Borrowed(pending), channels)
+        };
+        let mut pcm = Vec::with_capacity(samples.len() * 2);
+        for sample in samples.iter() {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+            pcm.extend_from_slice(&value.to_le_bytes());
+        }
+        let frames = (pending.len() / channels.max(1) as usize) as u64;
+        let chunk = CaptureChunk {
+            sequence: self.chunks,
+            start_frame: self.sent_frames,
+            frames,
+            sample_rate,
+            channels: out_channels,
+            pcm: general_purpose::STANDARD.encode(&pcm),
+        };
+        self.chunks += 1;
+        self.sent_samples += pending.len();
+        self.sent_frames += frames;
+        chunk
+    }
+}
@@ -0,0 +1,264 @@
+use super::{CapturableApplication, CaptureBackend, CaptureScope, CaptureTarget, FrameSink};
+use crate::error::VoiceboxError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Frames are generated in chunks of this length, in real time.
+const CHUNK: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyntheticPattern {
+    Sine { frequency: f32, amplitude: f32 },
+    /// White noise, uniform within ±amplitude.
+    Noise { amplitude: f32 },
+    Silence,
+}
+
+/// Things that go wrong with real devices, fired at a point in the capture.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntheticEvent {
+    /// Nothing is delivered for a while, like a buffer underrun.
+    Dropout { duration: Duration },
+    /// One chunk of full-scale samples.
+    Clip,
+    /// The default device changed to one with a different format.
+    DeviceChange { sample_rate: u32, channels: u16 },
+    /// The device went away and another took over `gap` later.
+    DeviceSwitch { gap: Duration },
+    /// The device went away.
+    Fail { message: String },
+    /// The application being recorded quit.
+    ApplicationExit,
+}
+
+/// Generated audio for tests and for running the app without audio hardware
+/// (see BACKEND_ENV). The same tone goes to every channel; a fixture can be
+/// replayed instead.
+#[derive(Debug, Clone)]
+pub struct SyntheticBackend {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// (start, pattern), sorted by start; the last one plays until stopped.
+    pub segments: Vec<(Duration, SyntheticPattern)>,
+    /// (at, event), fired once the capture has run that long.
+    pub events: Vec<(Duration, SyntheticEvent)>,
+    /// Devices it pretends to be able to record; the tone is the same
+    /// whichever is chosen.
+    pub targets: Vec<CaptureTarget>,
+    /// Applications it pretends to be able to record alone, likewise.
+    pub applications: Vec<CapturableApplication>,
+    /// Interleaved frames delivered from the start in place of the
+    /// segments, then silence once they run out.
+    pub fixture: Option<Arc<[f32]>>,
+}
+
+impl SyntheticBackend {
+    /// Stereo 48 kHz, playing `pattern` throughout.
+    pub fn new(pattern: SyntheticPattern) -> Self {
+        Self {
+            sample_rate: 48000,
+            channels: 2,
+            segments: vec![(Duration::ZERO, pattern)],
+            events: Vec::new(),
+            targets: Vec::new(),
+            applications: Vec::new(),
+            fixture: None,
+        }
+    }
+
+    /// Deliver `frames`, interleaved, in real time at `sample_rate`, then
+    /// silence. Events still fire.
+    pub fn replaying(frames: Vec<f32>, sample_rate: u32, channels: u16) -> Self {
+        Self {
+            fixture: Some(frames.into()),
+            ..Self::new(SyntheticPattern::Silence).with_format(sample_rate, channels)
+        }
+    }
+
+    pub fn with_format(mut self, sample_rate: u32, channels: u16) -> Self {
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+        self
+    }
+
+    /// Switch to `pattern` once the capture has run for `at`.
+    pub fn then(mut self, at: Duration, pattern: SyntheticPattern) -> Self {
+        self.segments.push((at, pattern));
+        self.segments.sort_by_key(|(start, _)| *start);
+        self
+    }
+
+    pub fn with_event(mut self, at: Duration, event: SyntheticEvent) -> Self {
+        self.events.push((at, event));
+        self.events.sort_by_key(|(at, _)| *at);
+        self
+    }
+
+    pub fn with_targets(mut self, targets: Vec<CaptureTarget>) -> Self {
+        self.targets = targets;
+        self
+    }
+
+    pub fn with_applications(mut self, applications: Vec<CapturableApplication>) -> Self {
+        self.applications = applications;
+        self
+    }
+
+    fn pattern_at(&self, elapsed: Duration) -> SyntheticPattern {
+        self.segments
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= elapsed)
+            .map_or(SyntheticPattern::Silence, |(_, pattern)| *pattern)
+    }
+}
+
+impl Default for SyntheticBackend {
+    /// A 440 Hz tone at half scale.
+    fn default() -> Self {
+        Self::new(SyntheticPattern::Sine {
+            frequency: 440.0,
+            amplitude: 0.5,
+        })
+    }
+}
+
+impl CaptureBackend for SyntheticBackend {
+    fn name(&self) -> &'static str {
+        "synthetic"
+    }
+
+    fn scope(&self) -> CaptureScope {
+        CaptureScope::Nothing
+    }
+
+    fn start(&self, sink: FrameSink, stop: Arc<AtomicBool>) -> Result<(), VoiceboxError> {
+        if self.sample_rate == 0 || self.channels == 0 {
+            return Err(VoiceboxError::invalid_argument("Synthetic capture needs a sample rate and channels"));
+        }
+        if let Some(device_id) = sink.device_id() {
+            if !self.targets.iter().any(|target| target.id == device_id) {
+                return Err(VoiceboxError::not_found(format!("No output device {} to capture", device_id)));
+            }
+        }
+        if let Some(pid) = sink.application_pid() {
+            if !self.applications.iter().any(|application| application.pid == pid) {
+                return Err(VoiceboxError::not_found(format!("No application with pid {} to capture", pid)));
+            }
+        }
+        sink.set_format(self.sample_rate, self.channels);
+        let backend = self.clone();
+        std::thread::Builder::new()
+            .name("synthetic-capture".to_string())
+            .spawn(move || backend.run(&sink, &stop))
+            .map(|_| ())
+            .map_err(|e| VoiceboxError::capture(format!("Failed to start the synthetic capture thread: {}", e)))
+    }
+
+    fn drain_time(&self) -> Duration {
+        CHUNK * 5
+    }
+
+    fn selects_device(&self) -> bool {
+        !self.targets.is_empty()
+    }
+
+    fn targets(&self) -> Result<Vec<CaptureTarget>, VoiceboxError> {
+        Ok(self.targets.clone())
+    }
+
+    fn captures_applications(&self) -> bool {
+        !self.applications.is_empty()
+    }
+
+    fn applications(&self) -> Result<Vec<CapturableApplication>, VoiceboxError> {
+        Ok(self.applications.clone())
+    }
+}
+
+impl SyntheticBackend {
+    fn run(&self, sink: &FrameSink, stop: &AtomicBool) {
+        let chunk_frames = (self.sample_rate as u128 * CHUNK.as_millis() / 1000) as usize;
+        let channels = self.channels as usize;
+        let started = Instant::now();
+        let mut events = self.events.iter().peekable();
+        let mut frame: u64 = 0;
+        let mut noise = Xorshift(0x9E37_79B9_7F4A_7C15);
+        let mut muted_until = Duration::ZERO;
+        let mut chunk_index: u32 = 0;
+
+        while !stop.load(Ordering::Relaxed) {
+            let elapsed = CHUNK * chunk_index;
+            let mut clip = false;
+            while let Some((_, event)) = events.next_if(|(at, _)| *at <= elapsed) {
+                match event {
+                    SyntheticEvent::Dropout { duration } => {
+                        muted_until = elapsed + *duration;
+                        sink.dropout(Some((self.sample_rate as f64 * duration.as_secs_f64()) as u64));
+                    }
+                    SyntheticEvent::Clip => clip = true,
+                    SyntheticEvent::DeviceChange { sample_rate, channels } => sink.set_format(*sample_rate, *channels),
+                    SyntheticEvent::DeviceSwitch { gap } => {
+                        muted_until = elapsed + *gap;
+                        sink.device_switched(*gap, Some("Synthetic output".to_string()));
+                    }
+                    SyntheticEvent::Fail { message } => sink.fail(message.clone()),
+                    SyntheticEvent::ApplicationExit => {
+                        sink.application_exited();
+                        return;
+                    }
+                }
+            }
+
+            if elapsed >= muted_until {
+                let pattern = self.pattern_at(elapsed);
+                let mut chunk = Vec::with_capacity(chunk_frames * channels);
+                for n in frame..frame + chunk_frames as u64 {
+                    if let Some(fixture) = self.fixture.as_deref().filter(|_| !clip) {
+                        let at = n as usize * channels;
+                        match fixture.get(at..at + channels) {
+                            Some(samples) => chunk.extend_from_slice(samples),
+                            None => chunk.extend(std::iter::repeat_n(0.0, channels)),
+                        }
+                        continue;
+                    }
+                    let value = if clip {
+                        if n % 2 == 0 { 1.0 } else { -1.0 }
+                    } else {
+                        match pattern {
+                            SyntheticPattern::Sine { frequency, amplitude } => {
+                                let t = n as f32 / self.sample_rate as f32;
+                                amplitude * (std::f32::consts::TAU * frequency * t).sin()
+                            }
+                            SyntheticPattern::Noise { amplitude } => amplitude * noise.next_signed(),
+                            SyntheticPattern::Silence => 0.0,
+                        }
+                    };
+                    chunk.extend(std::iter::repeat_n(value, channels));
+                }
+                sink.push(&chunk);
+            }
+            frame += chunk_frames as u64;
+            chunk_index += 1;
+
+            // Paced against the start so the rate doesn't drift
+            if let Some(wait) = (CHUNK * chunk_index).checked_sub(started.elapsed()) {
+                std::thread::sleep(wait);
+            }
+        }
+    }
+}
+
+/// Deterministic noise; the exact values don't matter, repeatability does.
+struct Xorshift(u64);
+
+impl Xorshift {
+    /// Uniform in [-1, 1).
+    fn next_signed(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+}
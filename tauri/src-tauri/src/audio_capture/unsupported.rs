@@ -0,0 +1,34 @@
+use super::{CaptureBackend, CaptureReadiness, FrameSink};
+use crate::error::VoiceboxError;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// No system audio capture on other platforms (the BSDs), nor in builds
+/// without the `native-backends` feature; the synthetic backend still works.
+pub struct PlatformBackend;
+
+impl CaptureBackend for PlatformBackend {
+    fn name(&self) -> &'static str {
+        "unsupported"
+    }
+
+    fn start(&self, _sink: FrameSink, _stop: Arc<AtomicBool>) -> Result<(), VoiceboxError> {
+        Err(VoiceboxError::unsupported(reason()))
+    }
+
+    fn readiness(&self) -> CaptureReadiness {
+        CaptureReadiness::unavailable(reason())
+    }
+}
+
+fn reason() -> &'static str {
+    if cfg!(feature = "native-backends") {
+        "System audio capture is not supported on this platform yet"
+    } else {
+        "This build has no native capture backends"
+    }
+}
+
+pub fn is_supported() -> bool {
+    false
+}
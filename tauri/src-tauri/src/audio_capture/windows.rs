@@ -1,251 +1,585 @@
-use crate::audio_capture::AudioCaptureState;
-use base64::{engine::general_purpose, Engine as _};
-use hound::{WavSpec, WavWriter};
-use std::io::Cursor;
+use super::{CapturableApplication, CaptureBackend, CaptureReadiness, CaptureTarget, FrameSink};
+use crate::audio_util::{self, RawSampleFormat};
+use crate::error::VoiceboxError;
+use crate::process_utils;
+use std::os::windows::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, OnceLock};
 use std::thread;
+use std::time::{Duration, Instant};
 use wasapi::*;
-use windows::Win32::System::Com::{CoInitializeEx, CoUninitialize, COINIT_MULTITHREADED};
-
-pub async fn start_capture(
-    state: &AudioCaptureState,
-    max_duration_secs: u32,
-) -> Result<(), String> {
-    // Reset previous samples
-    state.reset();
-
-    let samples = state.samples.clone();
-    let sample_rate_arc = state.sample_rate.clone();
-    let channels_arc = state.channels.clone();
-    let stop_tx = state.stop_tx.clone();
-    let error_arc = state.error.clone();
-
-    // Use AtomicBool for stop signal (works with non-Send types)
-    let stop_flag = Arc::new(AtomicBool::new(false));
-    let stop_flag_clone = stop_flag.clone();
-
-    // Create tokio channel and spawn a task to bridge it to the AtomicBool
-    let (tx, mut rx) = tokio::sync::mpsc::channel::<()>(1);
-    *stop_tx.lock().unwrap() = Some(tx);
-
-    tokio::spawn(async move {
-        rx.recv().await;
-        stop_flag_clone.store(true, Ordering::Relaxed);
-    });
-
-    // Spawn capture task on a dedicated thread (WASAPI COM objects are not Send)
-    // All WASAPI objects must be created and used on the same thread
-    thread::spawn(move || {
-        // Initialize COM for this thread
+use windows::core::Interface;
+use windows::Win32::Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0};
+use windows::Win32::Media::Audio::{
+    eRender, IAudioSessionControl2, IAudioSessionManager2, IMMDeviceEnumerator, MMDeviceEnumerator,
+    AUDCLNT_E_DEVICE_INVALIDATED, DEVICE_STATE_ACTIVE,
+};
+use windows::Win32::System::Com::{CoCreateInstance, CoInitializeEx, CoUninitialize, CLSCTX_ALL, COINIT_MULTITHREADED};
+use windows::Win32::System::Threading::{OpenProcess, WaitForSingleObject, PROCESS_SYNCHRONIZE};
+use tracing::{error, info, warn};
+
+/// Process loopback arrived in Windows 10 2004.
+const PROCESS_LOOPBACK_BUILD: u32 = 19041;
+
+/// Process loopback has no device period to ask for: 10 ms, in 100 ns units.
+const PROCESS_LOOPBACK_PERIOD: i64 = 100_000;
+
+/// How long start waits for the capture thread to get the stream running.
+/// Bluetooth outputs can take a few seconds to wake.
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tries at reopening a capture that lost its device before it fails.
+const REOPEN_ATTEMPTS: u32 = 3;
+
+/// The wait before the first try at reopening; each try after waits that
+/// much longer again.
+const REOPEN_WAIT: Duration = Duration::from_millis(250);
+
+/// How often a capture of the default output checks it still is the default.
+/// Polled rather than registering an IMMNotificationClient, whose callbacks
+/// come on another thread while the stream's COM objects stay on this one.
+const DEFAULT_DEVICE_POLL: Duration = Duration::from_secs(1);
+
+/// WASAPI loopback on the default render device, or the one the sink names,
+/// or process loopback of the application it names.
+pub struct PlatformBackend;
+
+impl CaptureBackend for PlatformBackend {
+    fn name(&self) -> &'static str {
+        "wasapi"
+    }
+
+    fn start(&self, sink: FrameSink, stop_flag: Arc<AtomicBool>) -> Result<(), VoiceboxError> {
+        // Spawn capture task on a dedicated thread (WASAPI COM objects are not Send)
+        // All WASAPI objects must be created and used on the same thread
+        let panic_sink = sink.clone();
+        // Keep the thread's lines in the caller's capture span
+        let span = tracing::Span::current();
+        // Setup failures come back here, so start_capture returns them
+        let (ready_tx, ready_rx) = mpsc::channel::<Result<(), VoiceboxError>>();
+        let start_stop = stop_flag.clone();
+        thread::spawn(move || {
+            let _span = span.entered();
+            // A panic here would otherwise leave is_capturing true and stop_capture
+            // waiting on samples that never come
+            crate::crash_log::on_this_thread_panic(move |message| {
+                panic_sink.fail(format!("The capture thread crashed: {}", message));
+            });
+            let fail_setup = |error: VoiceboxError| {
+                warn!("{}", error.message());
+                let _ = ready_tx.send(Err(error));
+            };
+
+            // Initialize COM for this thread
+            unsafe {
+                let hr = CoInitializeEx(None, COINIT_MULTITHREADED);
+                if hr.is_err() {
+                    fail_setup(VoiceboxError::capture(format!("Failed to initialize COM: {:?}", hr)));
+                    return;
+                }
+            }
+
+            // Ensure COM is uninitialized when thread exits
+            let _com_guard = scopeguard::guard((), |_| unsafe {
+                CoUninitialize();
+            });
+
+            // Opened first, so a pid that names no process is refused
+            let process = match sink.application_pid().map(WatchedProcess::open).transpose() {
+                Ok(process) => process,
+                Err(e) => {
+                    fail_setup(e);
+                    return;
+                }
+            };
+
+            // Initialize WASAPI on this thread
+            let loopback = match loopback_client(&sink) {
+                Ok(loopback) => loopback,
+                Err(e) => {
+                    fail_setup(e);
+                    return;
+                }
+            };
+
+            // Set sample rate and channels. They stay as they are if the
+            // capture moves to another device: WASAPI converts to them
+            let format = loopback.format.clone();
+            let channels = format.get_nchannels() as usize;
+            let sample_format = match raw_format(&format) {
+                Ok(sample_format) => sample_format,
+                Err(e) => {
+                    fail_setup(e);
+                    return;
+                }
+            };
+            sink.set_format(format.get_samplespersec(), format.get_nchannels());
+
+            let mut stream = match loopback.start(&format) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    fail_setup(e);
+                    return;
+                }
+            };
+            let _ = ready_tx.send(Ok(()));
+
+            // A capture of the default output follows it to a new default
+            let follows_default = sink.device_id().is_none() && sink.application_pid().is_none();
+            let mut default_checked = Instant::now();
+            let mut overslept = false;
+            loop {
+                // Check if stop signal was received
+                if stop_flag.load(Ordering::Relaxed) {
+                    break;
+                }
+                // Process loopback just goes quiet when the process quits
+                if process.as_ref().is_some_and(WatchedProcess::has_exited) {
+                    info!("The captured application quit");
+                    sink.application_exited();
+                    break;
+                }
+
+                // How far behind the device the loop is running
+                if let Ok(queued) = stream.client.get_current_padding() {
+                    sink.lag(queued as u64);
+                    if std::mem::take(&mut overslept) && queued as u64 > stream.period_frames {
+                        sink.late_wakeup();
+                    }
+                }
+
+                // Why the stream has to be opened again, if it does
+                let mut lost = None;
+
+                // Try to get available data
+                match stream.capture.get_next_packet_size() {
+                    Ok(Some(frames_available)) => {
+                        if frames_available > 0 {
+                            // Calculate buffer size needed (frames * channels * bytes per sample)
+                            let buffer_size = frames_available as usize * channels * sample_format.bytes();
+
+                            let mut buffer = vec![0u8; buffer_size];
+                            match stream.capture.read_from_device(&mut buffer) {
+                                Ok((frames_read, buffer_info)) => {
+                                    if buffer_info.flags.data_discontinuity {
+                                        sink.dropout(None);
+                                    }
+                                    if frames_read > 0 {
+                                        let read = frames_read as usize * channels * sample_format.bytes();
+                                        let read = read.min(buffer.len());
+                                        let frames = audio_util::decode_samples(&buffer[..read], sample_format);
+                                        sink.push(&frames);
+                                    }
+                                }
+                                Err(e) if is_invalidated(&e) => lost = Some(e.to_string()),
+                                Err(e) => {
+                                    error!("Error reading from device: {}", e);
+                                }
+                            }
+                        }
+                    }
+                    Ok(None) => {
+                        // Exclusive mode - handle differently if needed
+                    }
+                    Err(e) if is_invalidated(&e) => lost = Some(e.to_string()),
+                    Err(e) => {
+                        error!("Error getting next packet size: {}", e);
+                    }
+                }
+
+                if follows_default && lost.is_none() && default_checked.elapsed() >= DEFAULT_DEVICE_POLL {
+                    default_checked = Instant::now();
+                    let default_id = DeviceEnumerator::new()
+                        .and_then(|enumerator| enumerator.get_default_device(&Direction::Render))
+                        .and_then(|device| device.get_id());
+                    if let Ok(id) = default_id {
+                        if stream.device.as_ref().is_some_and(|(current, _)| *current != id) {
+                            lost = Some("the default output device changed".to_string());
+                        }
+                    }
+                }
+
+                if let Some(reason) = lost {
+                    info!("Reopening the capture: {}", reason);
+                    match reopen(&sink, stream, &format, &stop_flag) {
+                        Some(reopened) => stream = reopened,
+                        // Stopped, or failed, with the old stream already stopped
+                        None => return,
+                    }
+                    continue;
+                }
+
+                // Wait for event signal (with timeout to allow checking stop flag)
+                let waited = Instant::now();
+                if stream.event.wait_for_event(100).is_err() {
+                    // Timeout is expected - just continue to check stop flag
+                } else {
+                    // Loopback sends no events while nothing plays, so a long
+                    // wait only counts as late if audio was left waiting
+                    overslept = waited.elapsed() > stream.period;
+                }
+            }
+
+            // Stop the stream when done
+            stream.client.stop_stream().ok();
+        });
+
+        match ready_rx.recv_timeout(START_TIMEOUT) {
+            Ok(ready) => ready,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                // Should the stream start after all, it stops straight away
+                start_stop.store(true, Ordering::Relaxed);
+                Err(VoiceboxError::capture(format!(
+                    "The output device didn't start within {} seconds",
+                    START_TIMEOUT.as_secs()
+                )))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(VoiceboxError::capture("The capture thread exited before the stream started"))
+            }
+        }
+    }
+
+    fn selects_device(&self) -> bool {
+        true
+    }
+
+    fn targets(&self) -> Result<Vec<CaptureTarget>, VoiceboxError> {
         unsafe {
             let hr = CoInitializeEx(None, COINIT_MULTITHREADED);
             if hr.is_err() {
-                eprintln!("Failed to initialize COM: {:?}", hr);
-                return;
+                return Err(VoiceboxError::capture(format!("Failed to initialize COM: {:?}", hr)));
             }
         }
-
-        // Ensure COM is uninitialized when thread exits
         let _com_guard = scopeguard::guard((), |_| unsafe {
             CoUninitialize();
         });
+        let enumerate_failed = |e: WasapiError| VoiceboxError::capture(format!("Failed to list output devices: {}", e));
+        let enumerator = DeviceEnumerator::new().map_err(enumerate_failed)?;
+        let default_id = enumerator
+            .get_default_device(&Direction::Render)
+            .and_then(|device| device.get_id())
+            .ok();
+        // Active endpoints only: disabled and unplugged ones can't be recorded
+        let collection = enumerator.get_device_collection(&Direction::Render).map_err(enumerate_failed)?;
+        let mut targets = Vec::new();
+        for index in 0..collection.get_nbr_devices().map_err(enumerate_failed)? {
+            let device = collection.get_device_at_index(index).map_err(enumerate_failed)?;
+            let id = device.get_id().map_err(enumerate_failed)?;
+            targets.push(CaptureTarget {
+                is_default: default_id.as_deref() == Some(id.as_str()),
+                name: device.get_friendlyname().unwrap_or_else(|_| id.clone()),
+                id,
+            });
+        }
+        Ok(targets)
+    }
 
-        // Initialize WASAPI on this thread
-        let device = match DeviceEnumerator::new()
-            .and_then(|enumerator| enumerator.get_default_device(&Direction::Render))
-        {
-            Ok(d) => d,
-            Err(e) => {
-                let error_msg = format!("Failed to get audio device: {}", e);
-                eprintln!("{}", error_msg);
-                *error_arc.lock().unwrap() = Some(error_msg);
-                return;
-            }
-        };
+    fn captures_applications(&self) -> bool {
+        windows_build().is_some_and(|build| build >= PROCESS_LOOPBACK_BUILD)
+    }
 
-        let mut audio_client = match device.get_iaudioclient() {
-            Ok(client) => client,
-            Err(e) => {
-                let error_msg = format!("Failed to get audio client: {}", e);
-                eprintln!("{}", error_msg);
-                *error_arc.lock().unwrap() = Some(error_msg);
-                return;
+    /// Loopback records the default output, so there has to be one.
+    fn readiness(&self) -> CaptureReadiness {
+        unsafe {
+            let hr = CoInitializeEx(None, COINIT_MULTITHREADED);
+            if hr.is_err() {
+                return CaptureReadiness::unavailable(format!("Failed to initialize COM: {:?}", hr));
             }
-        };
+        }
+        let _com_guard = scopeguard::guard((), |_| unsafe {
+            CoUninitialize();
+        });
+        match DeviceEnumerator::new().and_then(|enumerator| enumerator.get_default_device(&Direction::Render)) {
+            Ok(_) => CaptureReadiness::ready(),
+            Err(e) => CaptureReadiness::unavailable(format!("There is no default output device to capture: {}", e)),
+        }
+    }
 
-        let mix_format = match audio_client.get_mixformat() {
-            Ok(format) => format,
-            Err(e) => {
-                let error_msg = format!("Failed to get mix format: {}", e);
-                eprintln!("{}", error_msg);
-                *error_arc.lock().unwrap() = Some(error_msg);
-                return;
+    /// Processes with an audio session on an active output: the ones that
+    /// play, or have played, something.
+    fn applications(&self) -> Result<Vec<CapturableApplication>, VoiceboxError> {
+        unsafe {
+            let hr = CoInitializeEx(None, COINIT_MULTITHREADED);
+            if hr.is_err() {
+                return Err(VoiceboxError::capture(format!("Failed to initialize COM: {:?}", hr)));
             }
+        }
+        let _com_guard = scopeguard::guard((), |_| unsafe {
+            CoUninitialize();
+        });
+        let list_failed = |e: windows::core::Error| {
+            VoiceboxError::capture(format!("Failed to list the applications playing audio: {}", e))
         };
-
-        // Set sample rate and channels
-        let channels = mix_format.get_nchannels() as usize;
-        let bytes_per_sample = (mix_format.get_bitspersample() / 8) as usize;
-        *sample_rate_arc.lock().unwrap() = mix_format.get_samplespersec();
-        *channels_arc.lock().unwrap() = mix_format.get_nchannels();
-
-        // Get device period
-        let (_def_period, min_period) = match audio_client.get_device_period() {
-            Ok(periods) => periods,
-            Err(e) => {
-                eprintln!("Failed to get device period: {}", e);
-                return;
+        let mut applications: Vec<CapturableApplication> = Vec::new();
+        unsafe {
+            let enumerator = CoCreateInstance::<_, IMMDeviceEnumerator>(&MMDeviceEnumerator, None, CLSCTX_ALL)
+                .map_err(list_failed)?;
+            let devices = enumerator.EnumAudioEndpoints(eRender, DEVICE_STATE_ACTIVE).map_err(list_failed)?;
+            for index in 0..devices.GetCount().map_err(list_failed)? {
+                let manager = devices
+                    .Item(index)
+                    .and_then(|device| device.Activate::<IAudioSessionManager2>(CLSCTX_ALL, None))
+                    .map_err(list_failed)?;
+                let sessions = manager.GetSessionEnumerator().map_err(list_failed)?;
+                for session in 0..sessions.GetCount().map_err(list_failed)? {
+                    let Ok(pid) = sessions
+                        .GetSession(session)
+                        .and_then(|control| control.cast::<IAudioSessionControl2>())
+                        .and_then(|control| control.GetProcessId())
+                    else {
+                        continue;
+                    };
+                    // 0 is the system sounds session
+                    if pid == 0 || pid == std::process::id() || applications.iter().any(|app| app.pid == pid) {
+                        continue;
+                    }
+                    let Some(executable) = process_utils::process_name(pid) else {
+                        continue;
+                    };
+                    // "chrome", not "chrome.exe"
+                    let stem = Path::new(&executable).file_stem().map(|stem| stem.to_string_lossy().into_owned());
+                    applications.push(CapturableApplication {
+                        pid,
+                        name: stem.unwrap_or(executable),
+                        bundle_id: None,
+                    });
+                }
             }
-        };
+        }
+        applications.sort_by_key(|application| application.name.to_lowercase());
+        Ok(applications)
+    }
+}
+
+/// A loopback client, opened but not yet started.
+struct Loopback {
+    client: AudioClient,
+    /// The device's mix format, or what process loopback is asked for.
+    format: WaveFormat,
+    /// In 100 ns units.
+    min_period: i64,
+    /// The render device's id and name; None for process loopback.
+    device: Option<(String, String)>,
+}
 
+impl Loopback {
+    /// Start recording in `format`, which WASAPI converts to.
+    fn start(mut self, format: &WaveFormat) -> Result<LoopbackStream, VoiceboxError> {
         // Initialize audio client for loopback with StreamMode
         // For loopback mode: get Render device, initialize with Capture direction
         // This triggers AUDCLNT_STREAMFLAGS_LOOPBACK in the wasapi crate
         let stream_mode = StreamMode::EventsShared {
             autoconvert: true,  // Enable automatic format conversion
-            buffer_duration_hns: min_period, // Use minimum period
+            buffer_duration_hns: self.min_period, // Use minimum period
         };
-
-        if let Err(e) = audio_client.initialize_client(&mix_format, &Direction::Capture, &stream_mode) {
-            let error_msg = format!("Failed to initialize audio client: {}", e);
-            eprintln!("{}", error_msg);
-            *error_arc.lock().unwrap() = Some(error_msg);
-            return;
-        }
-
+        self.client
+            .initialize_client(format, &Direction::Capture, &stream_mode)
+            .map_err(|e| VoiceboxError::capture(format!("Failed to initialize audio client: {}", e)))?;
         // Set up event handle for EventsShared mode
-        let h_event = match audio_client.set_get_eventhandle() {
-            Ok(event) => event,
-            Err(e) => {
-                eprintln!("Failed to set event handle: {}", e);
-                return;
-            }
-        };
+        let event = self
+            .client
+            .set_get_eventhandle()
+            .map_err(|e| VoiceboxError::capture(format!("Failed to set event handle: {}", e)))?;
+        let capture = self
+            .client
+            .get_audiocaptureclient()
+            .map_err(|e| VoiceboxError::capture(format!("Failed to get capture client: {}", e)))?;
+        self.client
+            .start_stream()
+            .map_err(|e| VoiceboxError::capture(format!("Failed to start stream: {}", e)))?;
+        Ok(LoopbackStream {
+            client: self.client,
+            capture,
+            event,
+            period: Duration::from_nanos(self.min_period as u64 * 100),
+            period_frames: format.get_samplespersec() as u64 * self.min_period as u64 / 10_000_000,
+            device: self.device,
+        })
+    }
+}
 
-        let capture_client = match audio_client.get_audiocaptureclient() {
-            Ok(client) => client,
-            Err(e) => {
-                let error_msg = format!("Failed to get capture client: {}", e);
-                eprintln!("{}", error_msg);
-                *error_arc.lock().unwrap() = Some(error_msg);
-                return;
-            }
-        };
+/// A running loopback stream.
+struct LoopbackStream {
+    client: AudioClient,
+    capture: AudioCaptureClient,
+    event: Handle,
+    /// One period as a duration and in frames, to judge the waits by.
+    period: Duration,
+    period_frames: u64,
+    device: Option<(String, String)>,
+}
 
-        if let Err(e) = audio_client.start_stream() {
-            let error_msg = format!("Failed to start stream: {}", e);
-            eprintln!("{}", error_msg);
-            *error_arc.lock().unwrap() = Some(error_msg);
-            return;
-        }
+/// The loopback client to record with. Process loopback has no mix format
+/// of its own, so it is asked for float stereo at 48 kHz and WASAPI
+/// converts to that.
+fn loopback_client(sink: &FrameSink) -> Result<Loopback, VoiceboxError> {
+    if let Some(pid) = sink.application_pid() {
+        // The whole process tree: browsers play from a child process
+        let client = AudioClient::new_application_loopback_client(pid, true)
+            .map_err(|e| VoiceboxError::capture(format!("Failed to open the loopback of process {}: {}", pid, e)))?;
+        return Ok(Loopback {
+            client,
+            format: WaveFormat::new(32, 32, &SampleType::Float, 48000, 2, None),
+            min_period: PROCESS_LOOPBACK_PERIOD,
+            device: None,
+        });
+    }
+    let device = render_device(sink.device_id())?;
+    let id = device
+        .get_id()
+        .map_err(|e| VoiceboxError::capture(format!("Failed to get audio device: {}", e)))?;
+    let name = device.get_friendlyname().unwrap_or_else(|_| id.clone());
+    let client = device
+        .get_iaudioclient()
+        .map_err(|e| VoiceboxError::capture(format!("Failed to get audio client: {}", e)))?;
+    let format = client
+        .get_mixformat()
+        .map_err(|e| VoiceboxError::capture(format!("Failed to get mix format: {}", e)))?;
+    let (_def_period, min_period) = client
+        .get_device_period()
+        .map_err(|e| VoiceboxError::capture(format!("Failed to get device period: {}", e)))?;
+    Ok(Loopback {
+        client,
+        format,
+        min_period,
+        device: Some((id, name)),
+    })
+}
 
-        loop {
-            // Check if stop signal was received
-            if stop_flag.load(Ordering::Relaxed) {
-                break;
-            }
+/// Whether `error` means the stream's device went away: unplugged, disabled
+/// or reconfigured.
+fn is_invalidated(error: &WasapiError) -> bool {
+    matches!(error, WasapiError::Windows(e) if e.code().0 == AUDCLNT_E_DEVICE_INVALIDATED.0)
+}
 
-            // Try to get available data
-            match capture_client.get_next_packet_size() {
-                Ok(Some(frames_available)) => {
-                    if frames_available > 0 {
-                        // Calculate buffer size needed (frames * channels * bytes_per_sample)
-                        let buffer_size = frames_available as usize * channels * bytes_per_sample;
-
-                        let mut buffer = vec![0u8; buffer_size];
-                        match capture_client.read_from_device(&mut buffer) {
-                            Ok((frames_read, _buffer_info)) => {
-                                if frames_read > 0 {
-                                    // Convert bytes to f32 samples
-                                    let samples_read = (frames_read as usize * channels) as usize;
-                                    let mut samples_guard = samples.lock().unwrap();
-
-                                    // Assuming 32-bit float format
-                                    if bytes_per_sample == 4 {
-                                        for i in 0..samples_read {
-                                            let byte_offset = i * 4;
-                                            if byte_offset + 4 <= buffer.len() {
-                                                let sample = f32::from_le_bytes([
-                                                    buffer[byte_offset],
-                                                    buffer[byte_offset + 1],
-                                                    buffer[byte_offset + 2],
-                                                    buffer[byte_offset + 3],
-                                                ]);
-                                                samples_guard.push(sample);
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!("Error reading from device: {}", e);
-                            }
-                        }
-                    }
-                }
-                Ok(None) => {
-                    // Exclusive mode - handle differently if needed
-                }
-                Err(e) => {
-                    eprintln!("Error getting next packet size: {}", e);
-                }
+/// Stop a stream that lost its device and open the sink's loopback again,
+/// in the capture's `format`: on the same device if one was chosen, or the
+/// new default. The audio missed meanwhile becomes silence. After
+/// REOPEN_ATTEMPTS failures in a row the capture fails instead.
+fn reopen(
+    sink: &FrameSink,
+    lost: LoopbackStream,
+    format: &WaveFormat,
+    stop_flag: &AtomicBool,
+) -> Option<LoopbackStream> {
+    let lost_at = Instant::now();
+    lost.client.stop_stream().ok();
+    drop(lost);
+    let mut last_error = None;
+    for attempt in 1..=REOPEN_ATTEMPTS {
+        // Windows takes a moment to settle on the new default
+        thread::sleep(REOPEN_WAIT * attempt);
+        if stop_flag.load(Ordering::Relaxed) {
+            return None;
+        }
+        match loopback_client(sink).and_then(|loopback| loopback.start(format)) {
+            Ok(stream) => {
+                let name = stream.device.as_ref().map(|(_, name)| name.clone());
+                info!("Capture reopened on {}", name.as_deref().unwrap_or("the application"));
+                sink.device_switched(lost_at.elapsed(), name);
+                return Some(stream);
             }
-
-            // Wait for event signal (with timeout to allow checking stop flag)
-            if h_event.wait_for_event(100).is_err() {
-                // Timeout is expected - just continue to check stop flag
+            Err(e) => {
+                warn!("Reopening the capture failed ({} of {}): {}", attempt, REOPEN_ATTEMPTS, e.message());
+                last_error = Some(e);
             }
         }
-
-        // Stop the stream when done
-        audio_client.stop_stream().ok();
-    });
-
-    // Spawn timeout task
-    let stop_tx_clone = state.stop_tx.clone();
-    tokio::spawn(async move {
-        tokio::time::sleep(tokio::time::Duration::from_secs(max_duration_secs as u64)).await;
-        // Take the sender out of the mutex before awaiting
-        let tx = stop_tx_clone.lock().unwrap().take();
-        if let Some(tx) = tx {
-            let _ = tx.send(()).await;
-        }
-    });
-
-    Ok(())
+    }
+    let reason = last_error.map(|e| e.message().to_string()).unwrap_or_default();
+    sink.fail(format!("The output device went away and the capture couldn't reopen it: {}", reason));
+    None
 }
 
-pub async fn stop_capture(state: &AudioCaptureState) -> Result<String, String> {
-    // Signal stop
-    if let Some(tx) = state.stop_tx.lock().unwrap().take() {
-        let _ = tx.send(());
-    }
+/// How the client's format delivers samples. One that can't be read fails
+/// the start, rather than recording silence.
+fn raw_format(format: &WaveFormat) -> Result<RawSampleFormat, VoiceboxError> {
+    let float = match format.get_subformat() {
+        Ok(SampleType::Float) => true,
+        Ok(SampleType::Int) => false,
+        Err(e) => return Err(VoiceboxError::unsupported(format!("Can't read the device's mix format: {}", e))),
+    };
+    let (bits, valid_bits) = (format.get_bitspersample(), format.get_validbitspersample());
+    RawSampleFormat::from_wave(float, bits, valid_bits).ok_or_else(|| {
+        VoiceboxError::unsupported(format!(
+            "Can't record the device's mix format: {} samples of {} bits ({} valid), {} channels at {} Hz",
+            if float { "float" } else { "integer" },
+            bits,
+            valid_bits,
+            format.get_nchannels(),
+            format.get_samplespersec()
+        ))
+    })
+}
 
-    // Wait a bit for capture to stop
-    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+/// The process a capture records, to notice it quitting.
+struct WatchedProcess(HANDLE);
 
-    // Check if there was an error during capture
-    if let Some(error) = state.error.lock().unwrap().as_ref() {
-        return Err(error.clone());
+impl WatchedProcess {
+    fn open(pid: u32) -> Result<Self, VoiceboxError> {
+        unsafe { OpenProcess(PROCESS_SYNCHRONIZE, false, pid) }
+            .map(Self)
+            .map_err(|e| VoiceboxError::not_found(format!("No application with pid {} to capture: {}", pid, e)))
     }
 
-    // Get samples
-    let samples = state.samples.lock().unwrap().clone();
-    let sample_rate = *state.sample_rate.lock().unwrap();
-    let channels = *state.channels.lock().unwrap();
+    fn has_exited(&self) -> bool {
+        unsafe { WaitForSingleObject(self.0, 0) == WAIT_OBJECT_0 }
+    }
+}
 
-    if samples.is_empty() {
-        return Err("No audio samples captured. Make sure audio is playing on your system during recording.".to_string());
+impl Drop for WatchedProcess {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = CloseHandle(self.0);
+        }
     }
+}
+
+/// The Windows build number, e.g. 19045, from `ver`. Looked up once.
+fn windows_build() -> Option<u32> {
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    static BUILD: OnceLock<Option<u32>> = OnceLock::new();
+    *BUILD.get_or_init(|| {
+        let output = Command::new("cmd")
+            .args(["/C", "ver"])
+            .creation_flags(CREATE_NO_WINDOW)
+            .output()
+            .ok()?;
+        // "Microsoft Windows [Version 10.0.19045.3803]"
+        let text = String::from_utf8_lossy(&output.stdout);
+        let version = text.split("Version ").nth(1)?.split(']').next()?;
+        version.split('.').nth(2)?.trim().parse().ok()
+    })
+}
 
-    // Convert to WAV
-    let wav_data = samples_to_wav(&samples, sample_rate, channels)?;
-    
-    // Encode to base64
-    let base64_data = general_purpose::STANDARD.encode(&wav_data);
-    
-    Ok(base64_data)
+/// The render endpoint with IMMDevice id `device_id`, or the default one.
+/// An id that names no device, or one that is disabled or unplugged, is
+/// refused here rather than failing the capture later.
+fn render_device(device_id: Option<&str>) -> Result<Device, VoiceboxError> {
+    let enumerator = DeviceEnumerator::new()
+        .map_err(|e| VoiceboxError::capture(format!("Failed to get audio device: {}", e)))?;
+    let Some(device_id) = device_id else {
+        return enumerator
+            .get_default_device(&Direction::Render)
+            .map_err(|e| VoiceboxError::capture(format!("Failed to get audio device: {}", e)));
+    };
+    let device = enumerator
+        .get_device(device_id)
+        .map_err(|e| VoiceboxError::not_found(format!("No output device {} to capture: {}", device_id, e)))?;
+    let name = device.get_friendlyname().unwrap_or_else(|_| device_id.to_string());
+    let state = device
+        .get_state()
+        .map_err(|e| VoiceboxError::capture(format!("Failed to get the state of {}: {}", name, e)))?;
+    let unavailable = match state {
+        DeviceState::Active => return Ok(device),
+        DeviceState::Disabled => "disabled",
+        DeviceState::NotPresent => "not present",
+        DeviceState::Unplugged => "unplugged",
+    };
+    Err(VoiceboxError::unsupported(format!("{} can't be captured: it is {}", name, unavailable)))
 }
 
 pub fn is_supported() -> bool {
@@ -258,31 +592,3 @@ pub fn is_supported() -> bool {
         false
     }
 }
-
-fn samples_to_wav(samples: &[f32], sample_rate: u32, channels: u16) -> Result<Vec<u8>, String> {
-    let mut buffer = Vec::new();
-    let cursor = Cursor::new(&mut buffer);
-    
-    let spec = WavSpec {
-        channels,
-        sample_rate,
-        bits_per_sample: 16,
-        sample_format: hound::SampleFormat::Int,
-    };
-
-    let mut writer = WavWriter::new(cursor, spec)
-        .map_err(|e| format!("Failed to create WAV writer: {}", e))?;
-
-    // Convert f32 samples to i16
-    for sample in samples {
-        let clamped = sample.clamp(-1.0, 1.0);
-        let i16_sample = (clamped * 32767.0) as i16;
-        writer.write_sample(i16_sample)
-            .map_err(|e| format!("Failed to write sample: {}", e))?;
-    }
-
-    writer.finalize()
-        .map_err(|e| format!("Failed to finalize WAV: {}", e))?;
-
-    Ok(buffer)
-}
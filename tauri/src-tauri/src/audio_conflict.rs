@@ -0,0 +1,101 @@
+use crate::audio_capture::CaptureScope;
+use crate::audio_output::{AudioOutputDevice, DEFAULT_DEVICE_ID};
+use serde::Serialize;
+
+/// A capture that would record the app's own playback: generated speech in
+/// the recording, or a route feeding back into itself. Payload of the
+/// `audio-conflict` event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AudioConflict {
+    pub capture_scope: CaptureScope,
+    /// The device the capture records, for a DefaultOutput capture.
+    pub capture_device_id: Option<String>,
+    /// Playback devices the capture hears, with `default` resolved.
+    pub playback_device_ids: Vec<String>,
+    pub message: String,
+}
+
+/// What start_system_audio_capture returns, and part of what
+/// play_audio_to_devices does.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConflictCheck {
+    /// Set when the command went ahead despite a conflict.
+    pub conflict_warning: Option<AudioConflict>,
+}
+
+/// The concrete device `id` stands for: the default output for `default`,
+/// otherwise itself.
+pub fn resolve(devices: &[AudioOutputDevice], id: &str) -> Option<String> {
+    if id == DEFAULT_DEVICE_ID {
+        devices.iter().find(|device| device.is_default).map(|device| device.id.clone())
+    } else {
+        Some(id.to_string())
+    }
+}
+
+/// Whether a capture of `scope` would hear playback to `playback_ids`.
+/// `devices` is the current device list, for resolving `default` on either
+/// side.
+pub fn check(scope: CaptureScope, devices: &[AudioOutputDevice], playback_ids: &[String]) -> Option<AudioConflict> {
+    let capture_device_id = match scope {
+        CaptureScope::Nothing => return None,
+        CaptureScope::Desktop => None,
+        CaptureScope::DefaultOutput => Some(resolve(devices, DEFAULT_DEVICE_ID)?),
+    };
+    conflict(scope, capture_device_id, devices, playback_ids)
+}
+
+/// Like `check`, for a capture pointed at the output named `target_name`
+/// instead of the default one. Capture targets and outputs come from
+/// different APIs, so they are matched by name; no output of that name, no
+/// conflict.
+pub fn check_target(
+    devices: &[AudioOutputDevice],
+    target_name: &str,
+    playback_ids: &[String],
+) -> Option<AudioConflict> {
+    let target = devices.iter().find(|device| device.name == target_name)?;
+    conflict(CaptureScope::DefaultOutput, Some(target.id.clone()), devices, playback_ids)
+}
+
+/// What a capture of `scope` recording `capture_device_id` (everything, if
+/// None) hears of `playback_ids`.
+fn conflict(
+    scope: CaptureScope,
+    capture_device_id: Option<String>,
+    devices: &[AudioOutputDevice],
+    playback_ids: &[String],
+) -> Option<AudioConflict> {
+    let mut playback: Vec<String> = playback_ids.iter().filter_map(|id| resolve(devices, id)).collect();
+    playback.sort();
+    playback.dedup();
+    if let Some(captured) = &capture_device_id {
+        playback.retain(|id| id == captured);
+    }
+    if playback.is_empty() {
+        return None;
+    }
+    let name = |id: &str| {
+        devices
+            .iter()
+            .find(|device| device.id == id)
+            .map_or_else(|| id.to_string(), |device| device.name.clone())
+    };
+    let names: Vec<String> = playback.iter().map(|id| name(id)).collect();
+    let message = match &capture_device_id {
+        Some(id) => format!(
+            "System audio is captured from {}, which is also playing the app's audio",
+            name(id)
+        ),
+        None => format!(
+            "System audio capture records every device, including {} the app is playing to",
+            names.join(", ")
+        ),
+    };
+    Some(AudioConflict {
+        capture_scope: scope,
+        capture_device_id,
+        playback_device_ids: playback,
+        message,
+    })
+}
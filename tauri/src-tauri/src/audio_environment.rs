@@ -0,0 +1,157 @@
+use crate::audio_capture::{AudioCaptureState, CaptureOptions, CaptureScope};
+use crate::audio_output::{AudioOutputDevice, AudioOutputState, DeviceFormat, DeviceTransport, PlaybackOptions};
+use crate::diagnostics::Redactor;
+use serde::Serialize;
+use std::sync::{Mutex, OnceLock};
+
+/// An output device as last listed, with the format it reported then.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceSnapshot {
+    pub id: String,
+    pub name: String,
+    pub is_default: bool,
+    pub transport: DeviceTransport,
+    /// None when the device wouldn't say.
+    pub format: Option<DeviceFormat>,
+}
+
+/// The running capture and what it was started with.
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureSnapshot {
+    pub session: u64,
+    pub scope: CaptureScope,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Auto-paused for source silence.
+    pub paused: bool,
+    pub options: CaptureOptions,
+}
+
+/// The playback under way and where it has got to.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaybackSnapshot {
+    pub playback_id: u64,
+    pub device_ids: Vec<String>,
+    pub position_ms: u64,
+    pub duration_ms: u64,
+    pub paused: bool,
+    pub options: PlaybackOptions,
+}
+
+/// The audio side of the app at one moment, attached to failures so an
+/// intermittent one can be set up again.
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioEnvironmentSnapshot {
+    /// Unix seconds.
+    pub taken_at: u64,
+    pub app_version: &'static str,
+    pub os: &'static str,
+    /// None until the first device listing has read it, or where it can't be.
+    pub os_version: Option<String>,
+    pub arch: &'static str,
+    pub capture_backend: &'static str,
+    pub output_backend: &'static str,
+    pub default_output_id: Option<String>,
+    pub output_devices: Vec<DeviceSnapshot>,
+    /// When output_devices were listed, in Unix seconds. None before the
+    /// first listing.
+    pub devices_listed_at: Option<u64>,
+    pub capture: Option<CaptureSnapshot>,
+    pub playback: Option<PlaybackSnapshot>,
+}
+
+impl AudioEnvironmentSnapshot {
+    /// Pass the device names and ids, which can carry the user's name, and
+    /// the OS version through `redactor`.
+    pub fn redact(&mut self, redactor: &Redactor) {
+        for device in &mut self.output_devices {
+            device.id = redactor.redact_str(&device.id);
+            device.name = redactor.redact_str(&device.name);
+        }
+        let device_ids = self
+            .default_output_id
+            .iter_mut()
+            .chain(self.playback.iter_mut().flat_map(|playback| playback.device_ids.iter_mut()));
+        for id in device_ids {
+            *id = redactor.redact_str(id);
+        }
+        if let Some(version) = &mut self.os_version {
+            *version = redactor.redact_str(version);
+        }
+    }
+}
+
+/// What snapshots are put together from without touching a device: the
+/// output devices as the device watcher last listed them, and the OS
+/// version, read once.
+#[derive(Debug, Default)]
+pub struct AudioEnvironment {
+    /// With when they were listed, in Unix seconds.
+    devices: Mutex<Option<(u64, Vec<DeviceSnapshot>)>>,
+    os_version: OnceLock<Option<String>>,
+}
+
+impl AudioEnvironment {
+    /// Keep a fresh listing of the output devices. `format` is only asked of
+    /// devices that weren't in the last one. Blocking, like `format`.
+    pub fn update_devices(&self, devices: &[AudioOutputDevice], format: impl Fn(&str) -> Option<DeviceFormat>) {
+        let known = self.devices.lock().unwrap().take().map(|(_, devices)| devices).unwrap_or_default();
+        let devices = devices
+            .iter()
+            .map(|device| DeviceSnapshot {
+                id: device.id.clone(),
+                name: device.name.clone(),
+                is_default: device.is_default,
+                transport: device.transport,
+                format: match known.iter().find(|known| known.id == device.id) {
+                    Some(known) => known.format,
+                    None => format(&device.id),
+                },
+            })
+            .collect();
+        *self.devices.lock().unwrap() = Some((crate::process_manager::unix_timestamp(), devices));
+        self.os_version.get_or_init(crate::diagnostics::os_version);
+    }
+
+    /// The environment as of now, from the states' own fields and the last
+    /// listing. Cheap: it never enumerates devices.
+    pub fn snapshot(&self, capture: &AudioCaptureState, output: &AudioOutputState) -> AudioEnvironmentSnapshot {
+        let (devices_listed_at, output_devices) = match self.devices.lock().unwrap().clone() {
+            Some((listed_at, devices)) => (Some(listed_at), devices),
+            None => (None, Vec::new()),
+        };
+        let capture_snapshot = capture.is_capturing().then(|| CaptureSnapshot {
+            session: capture.current_session(),
+            scope: capture.scope(),
+            sample_rate: *capture.sample_rate.lock().unwrap(),
+            channels: *capture.channels.lock().unwrap(),
+            paused: capture.is_paused(),
+            options: capture.options(),
+        });
+        let playback = output.current_playback().map(|playback| PlaybackSnapshot {
+            playback_id: playback.id(),
+            device_ids: playback.device_ids().to_vec(),
+            position_ms: playback.position().as_millis() as u64,
+            duration_ms: playback.duration().as_millis() as u64,
+            paused: playback.is_paused(),
+            options: playback.options(),
+        });
+        AudioEnvironmentSnapshot {
+            taken_at: crate::process_manager::unix_timestamp(),
+            app_version: env!("CARGO_PKG_VERSION"),
+            os: std::env::consts::OS,
+            os_version: self.os_version.get().cloned().flatten(),
+            arch: std::env::consts::ARCH,
+            capture_backend: capture.backend_name(),
+            output_backend: output.backend_name(),
+            default_output_id: output_devices
+                .iter()
+                .find(|device| device.is_default)
+                .map(|device| device.id.clone()),
+            output_devices,
+            devices_listed_at,
+            capture: capture_snapshot,
+            playback,
+        }
+    }
+}
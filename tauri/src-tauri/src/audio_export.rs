@@ -0,0 +1,208 @@
+use crate::audio_import;
+use crate::audio_util::{self, EncodeSpec, WavSampleFormat};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Wav,
+    Flac,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Wav => "wav",
+            ExportFormat::Flac => "flac",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Wav => "audio/wav",
+            ExportFormat::Flac => "audio/flac",
+        }
+    }
+
+    pub fn filter_name(&self) -> &'static str {
+        match self {
+            ExportFormat::Wav => "WAV audio",
+            ExportFormat::Flac => "FLAC audio",
+        }
+    }
+
+    /// Whether `data` is already a file of this format, so it can be written as is.
+    fn matches(&self, data: &[u8]) -> bool {
+        match self {
+            ExportFormat::Wav => data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WAVE",
+            ExportFormat::Flac => data.starts_with(b"fLaC"),
+        }
+    }
+}
+
+/// Where the audio to export or process comes from.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportSource {
+    /// A finished system audio capture, by the session id from `capture-stopped`.
+    CaptureSession(u64),
+    /// A generation stored by the server.
+    Recording(String),
+    /// Audio the frontend already holds, base64 encoded.
+    Bytes(String),
+    /// An audio file anywhere on disk.
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ExportOutcome {
+    Saved { path: PathBuf },
+    Cancelled,
+}
+
+/// Why an export failed. Cancelling the dialog is not an error.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ExportError {
+    SourceNotFound { message: String },
+    InvalidSource { message: String },
+    PermissionDenied { path: PathBuf, message: String },
+    DiskFull { path: PathBuf, message: String },
+    WriteFailed { path: PathBuf, message: String },
+    Internal { message: String },
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::SourceNotFound { message }
+            | ExportError::InvalidSource { message }
+            | ExportError::PermissionDenied { message, .. }
+            | ExportError::DiskFull { message, .. }
+            | ExportError::WriteFailed { message, .. }
+            | ExportError::Internal { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for ExportError {
+    fn from(message: String) -> Self {
+        ExportError::Internal { message }
+    }
+}
+
+/// Suggested file name with the extension of `format`, stripped of anything
+/// that would steer the dialog into another directory.
+pub fn file_name_for(suggested_name: &str, format: ExportFormat) -> String {
+    let name: String = suggested_name
+        .chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':') || c.is_control() { '_' } else { c })
+        .collect();
+    let name = name.trim().trim_start_matches('.');
+    let stem = match name.rsplit_once('.') {
+        Some((stem, ext)) if audio_import::AUDIO_EXTENSIONS.contains(&ext.to_lowercase().as_str()) => stem,
+        _ => name,
+    };
+    let stem = if stem.is_empty() { "voicebox" } else { stem };
+    format!("{}.{}", stem, format.extension())
+}
+
+/// Convert `data` to `format` unless it already is. Blocking.
+pub fn transcode(data: Vec<u8>, format: ExportFormat) -> Result<Vec<u8>, ExportError> {
+    if format.matches(&data) {
+        return Ok(data);
+    }
+    let decoded = audio_import::decode(&data, None).map_err(|e| ExportError::InvalidSource {
+        message: format!("Source audio could not be decoded: {}", e),
+    })?;
+    let spec = EncodeSpec::pcm16(decoded.sample_rate, decoded.channels);
+    Ok(encode(&decoded.samples, spec, format)?)
+}
+
+/// Base64 audio, such as a stopped capture, converted to `format` and
+/// encoded as base64 again. Blocking.
+pub fn transcode_base64(audio: &str, format: ExportFormat) -> Result<String, ExportError> {
+    use base64::{engine::general_purpose, Engine as _};
+
+    let data = general_purpose::STANDARD.decode(audio).map_err(|e| ExportError::InvalidSource {
+        message: format!("The audio is not valid base64: {}", e),
+    })?;
+    Ok(general_purpose::STANDARD.encode(transcode(data, format)?))
+}
+
+/// Encode interleaved frames as `format`. FLAC is always 16-bit.
+pub(crate) fn encode(samples: &[f32], spec: EncodeSpec, format: ExportFormat) -> Result<Vec<u8>, String> {
+    match format {
+        ExportFormat::Wav => audio_util::encode_wav(samples, spec),
+        ExportFormat::Flac if spec.format == WavSampleFormat::Int16 => {
+            encode_flac(samples, spec.sample_rate, spec.channels)
+        }
+        ExportFormat::Flac => Err(format!("FLAC exports are 16-bit, not {}-bit", spec.format.bits())),
+    }
+}
+
+fn encode_flac(samples: &[f32], sample_rate: u32, channels: u16) -> Result<Vec<u8>, String> {
+    use flacenc::component::BitRepr;
+    use flacenc::error::Verify;
+
+    let samples: Vec<i32> = samples
+        .iter()
+        .map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i32)
+        .collect();
+    let config = flacenc::config::Encoder::default()
+        .into_verified()
+        .map_err(|(_, e)| format!("Invalid FLAC encoder config: {:?}", e))?;
+    let source = flacenc::source::MemSource::from_samples(&samples, channels as usize, 16, sample_rate as usize);
+    let stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+        .map_err(|e| format!("Failed to encode FLAC: {:?}", e))?;
+    let mut sink = flacenc::bitsink::ByteSink::new();
+    stream
+        .write(&mut sink)
+        .map_err(|e| format!("Failed to write FLAC stream: {:?}", e))?;
+    Ok(sink.as_slice().to_vec())
+}
+
+/// Write `data` to `dest` through a temp file next to it, so a failed write
+/// never leaves a truncated file where the user asked for one. Blocking.
+pub fn write_export(dest: &Path, data: &[u8]) -> Result<(), ExportError> {
+    let file_name = dest
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let tmp = dest.with_file_name(format!(".{}.tmp", file_name));
+    let result = std::fs::write(&tmp, data).and_then(|_| std::fs::rename(&tmp, dest));
+    result.map_err(|e| {
+        let _ = std::fs::remove_file(&tmp);
+        write_error(dest, e)
+    })
+}
+
+fn write_error(path: &Path, e: std::io::Error) -> ExportError {
+    // ENOSPC / ERROR_DISK_FULL
+    #[cfg(unix)]
+    let disk_full = e.raw_os_error() == Some(libc::ENOSPC);
+    #[cfg(windows)]
+    let disk_full = e.raw_os_error() == Some(112);
+    #[cfg(not(any(unix, windows)))]
+    let disk_full = false;
+
+    let path = path.to_path_buf();
+    if disk_full {
+        ExportError::DiskFull {
+            message: format!("Not enough disk space to save {}", path.display()),
+            path,
+        }
+    } else if e.kind() == std::io::ErrorKind::PermissionDenied {
+        ExportError::PermissionDenied {
+            message: format!("No permission to write {}", path.display()),
+            path,
+        }
+    } else {
+        ExportError::WriteFailed {
+            message: format!("Failed to write {}: {}", path.display(), e),
+            path,
+        }
+    }
+}
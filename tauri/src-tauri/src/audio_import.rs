@@ -0,0 +1,290 @@
+use crate::audio_util::{self, EncodeSpec};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use tracing::{debug, info};
+
+/// Subdirectory of the data dir that imported audio is copied into.
+pub const IMPORTS_DIR: &str = "imports";
+
+/// Extensions treated as audio when files are dropped or passed on the command line.
+pub const AUDIO_EXTENSIONS: [&str; 6] = ["wav", "mp3", "flac", "ogg", "m4a", "aac"];
+
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFormat {
+    Wav,
+}
+
+/// Conversion applied before the file is stored. Without one the original
+/// file is copied unchanged.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportTarget {
+    pub format: ImportFormat,
+    /// None keeps the source rate.
+    pub sample_rate: Option<u32>,
+    #[serde(default)]
+    pub mono: bool,
+}
+
+/// Result of import_audio_file; `path` is the stored copy under imports/.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportedAudio {
+    pub path: PathBuf,
+    pub duration_ms: u64,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Codec of the source file, e.g. "mp3" or "pcm_s16le".
+    pub original_format: String,
+}
+
+/// Why an audio file could not be imported.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ImportError {
+    NotFound { message: String },
+    TooLarge { size_bytes: u64, max_bytes: u64, message: String },
+    UnsupportedFormat { message: String },
+    DecodeFailed { message: String },
+    Internal { message: String },
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::NotFound { message }
+            | ImportError::TooLarge { message, .. }
+            | ImportError::UnsupportedFormat { message }
+            | ImportError::DecodeFailed { message }
+            | ImportError::Internal { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for ImportError {
+    fn from(message: String) -> Self {
+        ImportError::Internal { message }
+    }
+}
+
+pub(crate) struct DecodedAudio {
+    /// Interleaved.
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub codec: String,
+}
+
+/// Decode `src`, convert it if asked, and store the result in
+/// `<data_dir>/imports/` under a name derived from its content, so importing
+/// the same file twice yields the same path. Blocking.
+pub fn import_file(
+    src: &Path,
+    data_dir: &Path,
+    target: Option<&ImportTarget>,
+    max_bytes: u64,
+) -> Result<ImportedAudio, ImportError> {
+    let metadata = std::fs::metadata(src).map_err(|e| ImportError::NotFound {
+        message: format!("Cannot read {}: {}", src.display(), e),
+    })?;
+    if !metadata.is_file() {
+        return Err(ImportError::NotFound {
+            message: format!("{} is not a file", src.display()),
+        });
+    }
+    if metadata.len() > max_bytes {
+        return Err(ImportError::TooLarge {
+            size_bytes: metadata.len(),
+            max_bytes,
+            message: format!(
+                "{} is {} MB; the import limit is {} MB",
+                src.display(),
+                metadata.len() / (1024 * 1024),
+                max_bytes / (1024 * 1024)
+            ),
+        });
+    }
+
+    let data = std::fs::read(src).map_err(|e| format!("Failed to read {}: {}", src.display(), e))?;
+    let extension = src
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    let decoded = decode(&data, extension.as_deref())?;
+
+    let (bytes, extension, sample_rate, channels, frames) = match target {
+        Some(target) => {
+            let mut samples = decoded.samples;
+            let mut channels = decoded.channels;
+            if target.mono && channels > 1 {
+                samples = audio_util::downmix(&samples, channels);
+                channels = 1;
+            }
+            let sample_rate = target.sample_rate.unwrap_or(decoded.sample_rate);
+            if sample_rate == 0 {
+                return Err("Target sample rate must be positive".to_string().into());
+            }
+            if sample_rate != decoded.sample_rate {
+                samples = audio_util::resample(&samples, channels, decoded.sample_rate, sample_rate);
+            }
+            let frames = samples.len() as u64 / channels as u64;
+            let bytes = match target.format {
+                ImportFormat::Wav => audio_util::encode_wav(&samples, EncodeSpec::pcm16(sample_rate, channels))?,
+            };
+            (bytes, "wav".to_string(), sample_rate, channels, frames)
+        }
+        None => {
+            let frames = decoded.samples.len() as u64 / decoded.channels as u64;
+            let extension = extension.unwrap_or_else(|| "audio".to_string());
+            (data, extension, decoded.sample_rate, decoded.channels, frames)
+        }
+    };
+
+    let dest = store(data_dir, &bytes, &extension)?;
+    info!("Imported {} as {}", src.display(), dest.display());
+
+    Ok(ImportedAudio {
+        path: dest,
+        duration_ms: frames * 1000 / sample_rate as u64,
+        sample_rate,
+        channels,
+        original_format: decoded.codec,
+    })
+}
+
+/// Write `bytes` into `<data_dir>/imports/` under a name derived from their
+/// content, through a temp file. Blocking.
+pub(crate) fn store(data_dir: &Path, bytes: &[u8], extension: &str) -> Result<PathBuf, String> {
+    let imports_dir = data_dir.join(IMPORTS_DIR);
+    std::fs::create_dir_all(&imports_dir)
+        .map_err(|e| format!("Failed to create {}: {}", imports_dir.display(), e))?;
+    let hash = Sha256::digest(bytes);
+    let name: String = hash.iter().take(16).map(|b| format!("{:02x}", b)).collect();
+    let dest = imports_dir.join(format!("{}.{}", name, extension));
+
+    if !dest.exists() {
+        let tmp = imports_dir.join(format!("{}.{}.tmp", name, extension));
+        std::fs::write(&tmp, bytes).map_err(|e| {
+            let _ = std::fs::remove_file(&tmp);
+            format!("Failed to write {}: {}", tmp.display(), e)
+        })?;
+        std::fs::rename(&tmp, &dest).map_err(|e| {
+            let _ = std::fs::remove_file(&tmp);
+            format!("Failed to store imported audio: {}", e)
+        })?;
+    }
+    Ok(dest)
+}
+
+/// The container `data` looks like from its first bytes, to name it when
+/// decoding fails.
+pub(crate) fn sniff_container(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some("WAV"),
+        [b'f', b'L', b'a', b'C', ..] => Some("FLAC"),
+        [b'O', b'g', b'g', b'S', ..] => Some("Ogg"),
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some("M4A"),
+        [b'I', b'D', b'3', ..] => Some("MP3"),
+        // Frame sync; ADTS (AAC) has a layer of 0 where MPEG audio doesn't
+        [0xFF, second, ..] if second & 0xF6 == 0xF0 => Some("AAC"),
+        [0xFF, second, ..] if second & 0xE0 == 0xE0 => Some("MP3"),
+        _ => None,
+    }
+}
+
+pub(crate) fn decode(data: &[u8], extension: Option<&str>) -> Result<DecodedAudio, ImportError> {
+    let mss = MediaSourceStream::new(Box::new(std::io::Cursor::new(data.to_vec())), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+
+    let mut format = symphonia::default::get_probe()
+        .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| ImportError::UnsupportedFormat {
+            message: format!("Unrecognized audio format: {}", e),
+        })?
+        .format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| ImportError::UnsupportedFormat {
+            message: "The file contains no audio track".to_string(),
+        })?;
+    let track_id = track.id;
+    let n_frames = track.codec_params.n_frames;
+    let codecs = symphonia::default::get_codecs();
+    let codec = codecs
+        .get_codec(track.codec_params.codec)
+        .map(|d| d.short_name.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let mut decoder = codecs
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| ImportError::UnsupportedFormat {
+            message: format!("Unsupported codec {}: {}", codec, e),
+        })?;
+
+    let mut samples = Vec::new();
+    let mut spec = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(SymphoniaError::ResetRequired) => break,
+            Err(e) => {
+                return Err(ImportError::DecodeFailed {
+                    message: format!("Failed to read audio: {}", e),
+                })
+            }
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt packet is skipped, as players do
+            Err(SymphoniaError::DecodeError(e)) => {
+                debug!("Skipping undecodable packet: {}", e);
+                continue;
+            }
+            Err(e) => {
+                return Err(ImportError::DecodeFailed {
+                    message: format!("Failed to decode audio: {}", e),
+                })
+            }
+        };
+        spec.get_or_insert(*decoded.spec());
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+        buffer.copy_interleaved_ref(decoded);
+        samples.extend_from_slice(buffer.samples());
+    }
+
+    let spec = spec.ok_or_else(|| ImportError::DecodeFailed {
+        message: "The file contains no decodable audio".to_string(),
+    })?;
+    // A FLAC encoder may pad its last block; the header has the real length
+    if let Some(frames) = n_frames {
+        samples.truncate(frames as usize * spec.channels.count());
+    }
+    Ok(DecodedAudio {
+        samples,
+        sample_rate: spec.rate,
+        channels: spec.channels.count() as u16,
+        codec,
+    })
+}
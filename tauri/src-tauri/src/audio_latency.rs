@@ -0,0 +1,111 @@
+use crate::audio_capture::{self, AudioCaptureState};
+use crate::audio_output::{AudioOutputState, PlaybackOptions};
+use crate::audio_util::{self, CorrelationPeak};
+use crate::error::VoiceboxError;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// The sweep played: within what a call's voice codec passes, so a route
+/// into one still carries it.
+pub const CHIRP_FROM_HZ: f32 = 300.0;
+pub const CHIRP_TO_HZ: f32 = 6000.0;
+pub const CHIRP_MS: u32 = 500;
+const CHIRP_AMPLITUDE: f32 = 0.5;
+const PLAY_RATE: u32 = 48000;
+
+/// Lets the capture deliver its first frames before the chirp starts, so
+/// the moment playback starts is inside the capture.
+const SETTLE: Duration = Duration::from_millis(300);
+
+/// How long after playback starts to keep listening. A loopback that isn't
+/// actually looped ends the measurement here, as NotDetected.
+pub const LISTEN_TIMEOUT: Duration = Duration::from_secs(3);
+
+const POLL: Duration = Duration::from_millis(100);
+
+/// Least correlation, and confidence, that counts as having heard the chirp.
+pub const MIN_CORRELATION: f32 = 0.3;
+pub const MIN_CONFIDENCE: f32 = 0.15;
+
+/// What measure_route_latency returns.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct LatencyMeasurement {
+    pub device_id: String,
+    pub capture_backend: &'static str,
+    /// From starting playback to the chirp arriving in the capture.
+    pub latency_ms: f32,
+    /// 0 to 1: how far the correlation peak stands above everything else.
+    pub confidence: f32,
+    /// Of the capture, which `peak.lag` counts in.
+    pub sample_rate: u32,
+    pub peak: CorrelationPeak,
+}
+
+/// How far `peak` stands above the runner-up, 0 to 1.
+pub fn confidence(peak: &CorrelationPeak) -> f32 {
+    (peak.correlation - peak.runner_up).clamp(0.0, 1.0)
+}
+
+/// Whether `peak` is the chirp rather than noise.
+pub fn is_detected(peak: &CorrelationPeak) -> bool {
+    peak.correlation >= MIN_CORRELATION && confidence(peak) >= MIN_CONFIDENCE
+}
+
+/// Play a chirp on `device_id` while capturing system audio, and find it in
+/// the capture by cross-correlation. Fails with NotDetected if it doesn't
+/// show up within LISTEN_TIMEOUT, and with the capture's error if that
+/// couldn't start or failed.
+pub async fn measure(
+    output: &AudioOutputState,
+    capture: &AudioCaptureState,
+    device_id: &str,
+) -> Result<LatencyMeasurement, VoiceboxError> {
+    let limit_secs = (SETTLE + LISTEN_TIMEOUT).as_secs() as u32 + 2;
+    audio_capture::start_capture(capture, limit_secs).await?;
+    tokio::time::sleep(SETTLE).await;
+    let offset = capture.samples.lock().unwrap().len();
+
+    let chirp = audio_util::chirp(PLAY_RATE, CHIRP_MS, CHIRP_FROM_HZ, CHIRP_TO_HZ, CHIRP_AMPLITUDE);
+    let started = Instant::now();
+    if let Err(e) = output
+        .play_samples(&chirp, PLAY_RATE, 1, &[device_id.to_string()], PlaybackOptions::default())
+        .await
+    {
+        let _ = audio_capture::stop_capture(capture).await;
+        return Err(e);
+    }
+
+    let mut found = None;
+    let deadline = started + LISTEN_TIMEOUT;
+    while found.is_none() && capture.is_capturing() && Instant::now() < deadline {
+        tokio::time::sleep(POLL).await;
+        let sample_rate = *capture.sample_rate.lock().unwrap();
+        let channels = *capture.channels.lock().unwrap();
+        let captured = audio_util::downmix(&capture.samples.lock().unwrap()[offset..], channels);
+        // The reference at the capture's rate: the same sweep, sample for sample
+        let reference = audio_util::chirp(sample_rate, CHIRP_MS, CHIRP_FROM_HZ, CHIRP_TO_HZ, CHIRP_AMPLITUDE);
+        found = audio_util::correlate(&captured, &reference)
+            // Wait for the whole chirp before trusting the peak
+            .filter(|peak| is_detected(peak) && peak.lag + reference.len() <= captured.len())
+            .map(|peak| (peak, sample_rate));
+    }
+    let stopped = audio_capture::stop_capture(capture).await;
+    let Some((peak, sample_rate)) = found else {
+        // A capture that failed says why; one that heard nothing is NotDetected
+        stopped?;
+        return Err(VoiceboxError::not_detected(LISTEN_TIMEOUT.as_millis() as u64));
+    };
+    let measurement = LatencyMeasurement {
+        device_id: device_id.to_string(),
+        capture_backend: capture.backend_name(),
+        latency_ms: peak.lag as f32 * 1000.0 / sample_rate as f32,
+        confidence: confidence(&peak),
+        sample_rate,
+        peak,
+    };
+    info!(
+        "Route latency on {}: {:.1} ms (confidence {:.2})",
+        device_id, measurement.latency_ms, measurement.confidence
+    );
+    Ok(measurement)
+}
@@ -0,0 +1,566 @@
+use serde::Serialize;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The session's audio activity, for get_audio_activity_log and the
+/// diagnostics bundle. Like METRICS, a process-wide static so capture and
+/// playback code records into it directly.
+pub static AUDIO_LOG: AudioLog = AudioLog::new();
+
+/// Entries kept; older ones are dropped first.
+pub const CAPACITY: usize = 500;
+
+/// Events waiting for the collector. When it falls this far behind, new
+/// events are counted as dropped instead of blocking the recorder.
+const QUEUE: usize = 256;
+
+/// Something that happened to audio. Variants recorded from audio callbacks
+/// hold only plain values: the text is written by the collector.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioEvent {
+    CaptureStarted {
+        backend: &'static str,
+        max_duration_secs: u32,
+        silence_stop_secs: Option<f32>,
+        mono: bool,
+        normalize: bool,
+    },
+    /// Stopped by hand, or on its own for `auto_stop`.
+    CaptureStopped {
+        frames: u64,
+        sample_rate: u32,
+        channels: u16,
+        dropouts: u64,
+        auto_stop: Option<&'static str>,
+    },
+    CaptureFailed {
+        error: String,
+    },
+    /// Stopped by hand with its audio thrown away, `frames` of it.
+    CaptureCancelled {
+        frames: u64,
+        sample_rate: u32,
+    },
+    /// The capture device's format changed under a running capture.
+    DeviceChanged {
+        from: (u32, u16),
+        to: (u32, u16),
+    },
+    /// The capture lost its output device and reopened it, or the new
+    /// default one, `gap_ms` later.
+    DeviceSwitched {
+        device: Option<String>,
+        gap_ms: u64,
+    },
+    /// The capture backend reported a gap, of `frames` if it knows.
+    Dropout {
+        frames: Option<u64>,
+    },
+    /// System audio and the microphone started recording together.
+    CombinedCaptureStarted {
+        system_backend: &'static str,
+        microphone_backend: &'static str,
+        max_duration_secs: u32,
+    },
+    /// A combined capture was mixed: the microphone started
+    /// `microphone_offset_ms` after system audio (before, if negative), and
+    /// each leg's clock ran this far from its nominal rate.
+    CombinedCaptureStopped {
+        duration_ms: u64,
+        microphone_offset_ms: i64,
+        system_drift_ppm: i64,
+        microphone_drift_ppm: i64,
+    },
+    /// Auto-pause: the source went quiet, `at_frames` into the kept audio.
+    CapturePaused {
+        at_frames: u64,
+        sample_rate: u32,
+    },
+    /// The source was heard again, after `left_out_frames` were left out.
+    CaptureResumed {
+        left_out_frames: u64,
+        sample_rate: u32,
+    },
+    /// Paused with pause_capture after `at_frames` kept.
+    CapturePausedByHand {
+        at_frames: u64,
+        sample_rate: u32,
+    },
+    /// Resumed with resume_capture, after `left_out_frames` were left out.
+    CaptureResumedByHand {
+        left_out_frames: u64,
+        sample_rate: u32,
+    },
+    PlaybackStarted {
+        backend: &'static str,
+        device_id: String,
+        sample_rate: u32,
+        channels: u16,
+        duration_ms: u64,
+    },
+    PlaybackStopped,
+    /// The playback moved from one device to another.
+    PlaybackMoved {
+        from_device_id: String,
+        to_device_id: String,
+        sample_rate: u32,
+        channels: u16,
+    },
+    /// A Bluetooth device stayed on its hands-free profile, so the clip
+    /// plays at its low rate.
+    PlaybackDegraded {
+        device_id: String,
+        sample_rate: u32,
+        previous_sample_rate: u32,
+    },
+    /// An output device or its stream failed.
+    DeviceError {
+        device_id: Option<String>,
+        error: String,
+    },
+    RouteStarted {
+        route_id: u64,
+        destination_device_id: String,
+        gain_db: f32,
+    },
+    RouteStopped {
+        route_id: u64,
+        underruns: u64,
+        overruns: u64,
+    },
+    /// A route's output ran dry; its buffer grows to `target_ms`.
+    RouteUnderrun {
+        target_ms: u32,
+    },
+    /// A route's buffer overflowed and dropped `dropped_ms` of audio.
+    RouteOverrun {
+        dropped_ms: u32,
+    },
+    PermissionFailed {
+        permission: &'static str,
+        error: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioLogSource {
+    Capture,
+    Playback,
+    Route,
+    Permission,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioLogEntry {
+    /// Counts every entry this session, so the first one kept shows how
+    /// many were evicted before it.
+    pub seq: u64,
+    /// Unix milliseconds, when the event was recorded.
+    pub at_ms: u64,
+    pub source: AudioLogSource,
+    pub kind: &'static str,
+    pub message: String,
+    pub fields: serde_json::Value,
+}
+
+/// Payload of get_audio_activity_log.
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioActivityLog {
+    /// Oldest first.
+    pub entries: Vec<AudioLogEntry>,
+    /// Events lost because the collector fell behind.
+    pub dropped: u64,
+}
+
+enum Message {
+    Event(u64, AudioEvent),
+    /// Answered once everything sent before it is in the log.
+    Flush(mpsc::Sender<()>),
+}
+
+pub struct AudioLog {
+    tx: OnceLock<SyncSender<Message>>,
+    entries: Mutex<VecDeque<AudioLogEntry>>,
+    dropped: AtomicU64,
+}
+
+impl AudioLog {
+    pub const fn new() -> Self {
+        Self {
+            tx: OnceLock::new(),
+            entries: Mutex::new(VecDeque::new()),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Queue an event without blocking or allocating, once the collector
+    /// runs. The first call starts it.
+    pub fn record(&'static self, event: AudioEvent) {
+        let at_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        match self.sender().try_send(Message::Event(at_ms, event)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// The entries so far, including everything recorded before the call.
+    pub fn snapshot(&'static self) -> AudioActivityLog {
+        let (done_tx, done_rx) = mpsc::channel();
+        if self.sender().send(Message::Flush(done_tx)).is_ok() {
+            let _ = done_rx.recv();
+        }
+        AudioActivityLog {
+            entries: self.entries.lock().unwrap().iter().cloned().collect(),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    fn sender(&'static self) -> &'static SyncSender<Message> {
+        self.tx.get_or_init(|| {
+            let (tx, rx) = mpsc::sync_channel(QUEUE);
+            let started = std::thread::Builder::new()
+                .name("audio-log".to_string())
+                .spawn(move || self.collect(rx));
+            if let Err(e) = started {
+                tracing::warn!("Failed to start the audio log collector: {}", e);
+            }
+            tx
+        })
+    }
+
+    fn collect(&self, rx: Receiver<Message>) {
+        let mut seq = 0;
+        for message in rx {
+            match message {
+                Message::Event(at_ms, event) => {
+                    seq += 1;
+                    let entry = describe(seq, at_ms, event);
+                    let mut entries = self.entries.lock().unwrap();
+                    if entries.len() == CAPACITY {
+                        entries.pop_front();
+                    }
+                    entries.push_back(entry);
+                }
+                Message::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+}
+
+impl Default for AudioLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Record into AUDIO_LOG.
+pub fn record(event: AudioEvent) {
+    AUDIO_LOG.record(event);
+}
+
+fn describe(seq: u64, at_ms: u64, event: AudioEvent) -> AudioLogEntry {
+    use AudioLogSource::*;
+    let (source, kind, message, fields) = match event {
+        AudioEvent::CaptureStarted {
+            backend,
+            max_duration_secs,
+            silence_stop_secs,
+            mono,
+            normalize,
+        } => (
+            Capture,
+            "capture_started",
+            format!("Capture started through {}, for up to {} s", backend, max_duration_secs),
+            json!({
+                "backend": backend,
+                "max_duration_secs": max_duration_secs,
+                "silence_stop_secs": silence_stop_secs,
+                "mono": mono,
+                "normalize": normalize,
+            }),
+        ),
+        AudioEvent::CaptureStopped {
+            frames,
+            sample_rate,
+            channels,
+            dropouts,
+            auto_stop,
+        } => {
+            let how = auto_stop.map_or("by hand".to_string(), |reason| format!("on its own ({})", reason));
+            (
+                Capture,
+                "capture_stopped",
+                format!(
+                    "Capture stopped {} after {} ms at {} Hz, {} channel(s), {} dropout(s)",
+                    how,
+                    frames * 1000 / sample_rate.max(1) as u64,
+                    sample_rate,
+                    channels,
+                    dropouts
+                ),
+                json!({
+                    "frames": frames,
+                    "sample_rate": sample_rate,
+                    "channels": channels,
+                    "dropouts": dropouts,
+                    "auto_stop": auto_stop,
+                }),
+            )
+        }
+        AudioEvent::CombinedCaptureStarted {
+            system_backend,
+            microphone_backend,
+            max_duration_secs,
+        } => (
+            Capture,
+            "combined_capture_started",
+            format!(
+                "Combined capture started through {} and {}, for up to {} s",
+                system_backend, microphone_backend, max_duration_secs
+            ),
+            json!({
+                "system_backend": system_backend,
+                "microphone_backend": microphone_backend,
+                "max_duration_secs": max_duration_secs,
+            }),
+        ),
+        AudioEvent::CombinedCaptureStopped {
+            duration_ms,
+            microphone_offset_ms,
+            system_drift_ppm,
+            microphone_drift_ppm,
+        } => (
+            Capture,
+            "combined_capture_stopped",
+            format!(
+                "Combined capture stopped after {} ms; the microphone was {} ms off, clocks {} and {} ppm",
+                duration_ms, microphone_offset_ms, system_drift_ppm, microphone_drift_ppm
+            ),
+            json!({
+                "duration_ms": duration_ms,
+                "microphone_offset_ms": microphone_offset_ms,
+                "system_drift_ppm": system_drift_ppm,
+                "microphone_drift_ppm": microphone_drift_ppm,
+            }),
+        ),
+        AudioEvent::CaptureFailed { error } => (
+            Capture,
+            "capture_failed",
+            format!("Capture failed: {}", error),
+            json!({ "error": error }),
+        ),
+        AudioEvent::CaptureCancelled { frames, sample_rate } => (
+            Capture,
+            "capture_cancelled",
+            format!(
+                "Capture cancelled after {} ms, its audio discarded",
+                frames * 1000 / sample_rate.max(1) as u64
+            ),
+            json!({ "frames": frames, "sample_rate": sample_rate }),
+        ),
+        AudioEvent::DeviceChanged { from, to } => (
+            Capture,
+            "device_changed",
+            format!(
+                "The capture device changed from {} Hz, {} channel(s) to {} Hz, {} channel(s)",
+                from.0, from.1, to.0, to.1
+            ),
+            json!({
+                "from": { "sample_rate": from.0, "channels": from.1 },
+                "to": { "sample_rate": to.0, "channels": to.1 },
+            }),
+        ),
+        AudioEvent::DeviceSwitched { device, gap_ms } => (
+            Capture,
+            "device_switched",
+            format!(
+                "The capture lost its device and reopened {} after {} ms",
+                device.as_deref().unwrap_or("it"),
+                gap_ms
+            ),
+            json!({ "device": device, "gap_ms": gap_ms }),
+        ),
+        AudioEvent::Dropout { frames } => (
+            Capture,
+            "dropout",
+            match frames {
+                Some(frames) => format!("The capture dropped {} frames", frames),
+                None => "The capture dropped frames".to_string(),
+            },
+            json!({ "frames": frames }),
+        ),
+        AudioEvent::CapturePaused { at_frames, sample_rate } => (
+            Capture,
+            "capture_paused",
+            format!(
+                "Capture paused for source silence after {} ms",
+                at_frames * 1000 / sample_rate.max(1) as u64
+            ),
+            json!({ "at_frames": at_frames, "sample_rate": sample_rate }),
+        ),
+        AudioEvent::CaptureResumed {
+            left_out_frames,
+            sample_rate,
+        } => (
+            Capture,
+            "capture_resumed",
+            format!(
+                "Capture resumed, leaving out {} ms of silence",
+                left_out_frames * 1000 / sample_rate.max(1) as u64
+            ),
+            json!({ "left_out_frames": left_out_frames, "sample_rate": sample_rate }),
+        ),
+        AudioEvent::CapturePausedByHand { at_frames, sample_rate } => (
+            Capture,
+            "capture_paused_by_hand",
+            format!(
+                "Capture paused by hand after {} ms",
+                at_frames * 1000 / sample_rate.max(1) as u64
+            ),
+            json!({ "at_frames": at_frames, "sample_rate": sample_rate }),
+        ),
+        AudioEvent::CaptureResumedByHand {
+            left_out_frames,
+            sample_rate,
+        } => (
+            Capture,
+            "capture_resumed_by_hand",
+            format!(
+                "Capture resumed by hand, leaving out {} ms",
+                left_out_frames * 1000 / sample_rate.max(1) as u64
+            ),
+            json!({ "left_out_frames": left_out_frames, "sample_rate": sample_rate }),
+        ),
+        AudioEvent::PlaybackStarted {
+            backend,
+            device_id,
+            sample_rate,
+            channels,
+            duration_ms,
+        } => (
+            Playback,
+            "playback_started",
+            format!(
+                "Playing {} ms on {} through {} at {} Hz, {} channel(s)",
+                duration_ms, device_id, backend, sample_rate, channels
+            ),
+            json!({
+                "backend": backend,
+                "device_id": device_id,
+                "sample_rate": sample_rate,
+                "channels": channels,
+                "duration_ms": duration_ms,
+            }),
+        ),
+        AudioEvent::PlaybackStopped => (Playback, "playback_stopped", "Playback stopped".to_string(), json!({})),
+        AudioEvent::PlaybackMoved {
+            from_device_id,
+            to_device_id,
+            sample_rate,
+            channels,
+        } => (
+            Playback,
+            "playback_moved",
+            format!(
+                "Moved playback from {} to {} at {} Hz, {} channel(s)",
+                from_device_id, to_device_id, sample_rate, channels
+            ),
+            json!({
+                "from_device_id": from_device_id,
+                "to_device_id": to_device_id,
+                "sample_rate": sample_rate,
+                "channels": channels,
+            }),
+        ),
+        AudioEvent::PlaybackDegraded {
+            device_id,
+            sample_rate,
+            previous_sample_rate,
+        } => (
+            Playback,
+            "playback_degraded",
+            format!(
+                "{} is on its hands-free profile at {} Hz (was {} Hz)",
+                device_id, sample_rate, previous_sample_rate
+            ),
+            json!({
+                "device_id": device_id,
+                "sample_rate": sample_rate,
+                "previous_sample_rate": previous_sample_rate,
+            }),
+        ),
+        AudioEvent::DeviceError { device_id, error } => (
+            Playback,
+            "device_error",
+            match &device_id {
+                Some(device_id) => format!("Output device {} failed: {}", device_id, error),
+                None => format!("An output device failed: {}", error),
+            },
+            json!({ "device_id": device_id, "error": error }),
+        ),
+        AudioEvent::RouteStarted {
+            route_id,
+            destination_device_id,
+            gain_db,
+        } => (
+            Route,
+            "route_started",
+            format!("Route {} started to {} at {} dB", route_id, destination_device_id, gain_db),
+            json!({
+                "route_id": route_id,
+                "destination_device_id": destination_device_id,
+                "gain_db": gain_db,
+            }),
+        ),
+        AudioEvent::RouteStopped {
+            route_id,
+            underruns,
+            overruns,
+        } => (
+            Route,
+            "route_stopped",
+            format!(
+                "Route {} stopped after {} underrun(s), {} overrun(s)",
+                route_id, underruns, overruns
+            ),
+            json!({ "route_id": route_id, "underruns": underruns, "overruns": overruns }),
+        ),
+        AudioEvent::RouteUnderrun { target_ms } => (
+            Route,
+            "route_underrun",
+            format!("A route ran dry; buffering {} ms", target_ms),
+            json!({ "target_ms": target_ms }),
+        ),
+        AudioEvent::RouteOverrun { dropped_ms } => (
+            Route,
+            "route_overrun",
+            format!("A route's buffer overflowed, dropping {} ms", dropped_ms),
+            json!({ "dropped_ms": dropped_ms }),
+        ),
+        AudioEvent::PermissionFailed { permission, error } => (
+            Permission,
+            "permission_failed",
+            format!("The {} permission stopped audio: {}", permission, error),
+            json!({ "permission": permission, "error": error }),
+        ),
+    };
+    AudioLogEntry {
+        seq,
+        at_ms,
+        source,
+        kind,
+        message,
+        fields,
+    }
+}
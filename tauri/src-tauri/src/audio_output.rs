@@ -1,465 +1,3204 @@
+use crate::audio_import;
+use crate::audio_log::{self, AudioEvent};
+use crate::audio_util::{self, LevelCounters};
+use crate::error::VoiceboxError;
+use crate::metrics::METRICS;
+#[cfg(feature = "native-backends")]
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+#[cfg(feature = "native-backends")]
 use cpal::{Device, Host, SampleFormat, StreamConfig};
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, warn};
 
-#[derive(Debug, Clone, serde::Serialize)]
+/// Stands for whichever device is the default output, wherever a device id
+/// is taken.
+pub const DEFAULT_DEVICE_ID: &str = "default";
+
+/// Clips ramp up at their start and down at their end over this long,
+/// unless asked otherwise, so one that doesn't start or end at a zero
+/// crossing doesn't pop.
+pub const FADE_MS: u32 = 5;
+
+/// A stop ramps down over this long, so it doesn't cut the clip off
+/// mid-wave.
+pub const STOP_FADE_MS: u32 = 10;
+
+/// Loudest a device's volume goes, as a linear gain on top of the
+/// playback's. Past 1.0 the peaks are soft-clipped.
+pub const MAX_DEVICE_VOLUME: f32 = 2.0;
+
+/// How far ahead of the device furthest behind a file playing as it
+/// decodes is decoded.
+const STREAM_AHEAD: Duration = Duration::from_secs(2);
+
+/// How much of a file playing as it decodes is decoded before any device
+/// opens.
+const STREAM_PRIME: Duration = Duration::from_millis(250);
+
+/// The silence between clips of a playback queue, unless queued with
+/// another.
+pub const DEFAULT_QUEUE_GAP: Duration = Duration::from_millis(300);
+
+/// How much a playback stream holds that no device has played yet, beyond
+/// which a pushed chunk is refused.
+const MAX_STREAM_BUFFERED: Duration = Duration::from_secs(600);
+
+/// How long the two devices overlap when a playback moves from one to the
+/// other.
+pub const CROSSFADE_MS: u32 = 100;
+
+/// How far past its last device opening a playback to several devices
+/// starts, so that each device's next period comes before then.
+const START_LEAD: Duration = Duration::from_millis(30);
+
+/// Pitch of play_test_tone's tone unless asked for another.
+pub const TEST_TONE_HZ: f32 = 440.0;
+
+/// How long play_test_tone plays at most.
+pub const MAX_TEST_TONE: Duration = Duration::from_secs(30);
+
+/// Peak level of play_test_tone's sine, loud enough to hear on a meter
+/// without startling anyone in headphones.
+const TEST_TONE_AMPLITUDE: f32 = 0.25;
+
+/// How long play_test_tone's tone fades in and out over.
+const TEST_TONE_FADE_MS: u32 = 20;
+
+/// How long a device that failed to open gets before its one retry.
+const OPEN_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// How long a device's stream stays open after its last clip, for the next
+/// clip to join rather than open the device again.
+const STREAM_LINGER: Duration = Duration::from_millis(200);
+
+/// At or below this rate a Bluetooth device is taken to be on its hands-free
+/// (HFP) profile, which it switches to while its microphone is in use.
+pub const HFP_MAX_SAMPLE_RATE: u32 = 16000;
+
+/// Waits before asking a Bluetooth device for its format again, while it
+/// looks stuck on its hands-free profile. Switching back takes a moment.
+const PROFILE_RETRY_DELAYS: [Duration; 2] = [Duration::from_millis(250), Duration::from_millis(500)];
+
+/// How a device is connected, as far as its name tells.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceTransport {
+    Bluetooth,
+    Usb,
+    Hdmi,
+    #[default]
+    Unknown,
+}
+
+impl DeviceTransport {
+    /// Guessed from a device name, which is all cpal offers: PulseAudio and
+    /// PipeWire name Bluetooth sinks after bluez, Windows after the profile
+    /// ("Hands-Free", "A2DP"), and macOS after the product.
+    pub fn from_name(name: &str) -> Self {
+        let name = name.to_lowercase();
+        let has = |words: &[&str]| words.iter().any(|word| name.contains(word));
+        if has(&["bluetooth", "bluez", "hands-free", "handsfree", "a2dp", "airpods"]) {
+            DeviceTransport::Bluetooth
+        } else if has(&["usb"]) {
+            DeviceTransport::Usb
+        } else if has(&["hdmi", "displayport"]) {
+            DeviceTransport::Hdmi
+        } else {
+            DeviceTransport::Unknown
+        }
+    }
+}
+
+/// Whether a device can play. Only Windows lists devices that can't, and
+/// only those get a state other than active.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceState {
+    #[default]
+    Active,
+    /// Turned off in the sound settings.
+    Disabled,
+    /// The jack has nothing plugged into it.
+    Unplugged,
+}
+
+/// How the device list changed from one listing to the next. Devices that
+/// can't play count as gone.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct DeviceListChange {
+    /// Ids of the devices that weren't there before.
+    pub added: Vec<String>,
+    /// Ids of the devices that have gone.
+    pub removed: Vec<String>,
+    /// Whether a different device, or none, is the default now.
+    pub default_changed: bool,
+}
+
+impl DeviceListChange {
+    pub fn between(before: &[AudioOutputDevice], after: &[AudioOutputDevice]) -> Self {
+        let ids = |devices: &[AudioOutputDevice]| {
+            devices.iter().filter(|device| device.is_active()).map(|device| device.id.clone()).collect::<Vec<_>>()
+        };
+        let (before_ids, after_ids) = (ids(before), ids(after));
+        let default = |devices: &[AudioOutputDevice]| {
+            devices.iter().find(|device| device.is_default).map(|device| device.id.clone())
+        };
+        Self {
+            added: after_ids.iter().filter(|id| !before_ids.contains(id)).cloned().collect(),
+            removed: before_ids.iter().filter(|id| !after_ids.contains(id)).cloned().collect(),
+            default_changed: default(before) != default(after),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && !self.default_changed
+    }
+}
+
+/// Whether a device now at `sample_rate` has dropped to its hands-free
+/// profile: a Bluetooth device at HFP_MAX_SAMPLE_RATE or below that earlier
+/// reported `previous_rate`, a higher one.
+pub fn is_degraded_profile(transport: DeviceTransport, sample_rate: u32, previous_rate: Option<u32>) -> bool {
+    transport == DeviceTransport::Bluetooth
+        && sample_rate <= HFP_MAX_SAMPLE_RATE
+        && previous_rate.is_some_and(|rate| rate > HFP_MAX_SAMPLE_RATE)
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
 pub struct AudioOutputDevice {
     pub id: String,
     pub name: String,
     pub is_default: bool,
+    pub transport: DeviceTransport,
+    pub state: DeviceState,
+    /// The rate the device runs at when nothing asks for another, 0 when it
+    /// isn't active or won't say.
+    pub default_sample_rate: u32,
+    /// 0 when the device isn't active or won't say.
+    pub channels: u16,
+    /// The system's own id for the device, which outlives renames and
+    /// reboots: the endpoint ID on Windows, the device UID on macOS. None
+    /// elsewhere, where `id`, from the name, is all there is.
+    pub endpoint_id: Option<String>,
+}
+
+impl AudioOutputDevice {
+    pub fn is_active(&self) -> bool {
+        self.state == DeviceState::Active
+    }
+}
+
+/// What a device plays; clips are converted to it before playback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct DeviceFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[non_exhaustive]
+pub struct PlaybackOptions {
+    /// Linear, 1.0 plays the clip as is.
+    pub gain: f32,
+    /// How long the clip ramps up over from its first frame, and down over
+    /// to its last, in the device's frames. A clip shorter than both
+    /// together gets a triangle, each fade its share of the clip.
+    pub fade_in_ms: u32,
+    pub fade_out_ms: u32,
+    /// Fail a clip's playback when a device fails to open, rather than
+    /// leave that device out.
+    pub strict: bool,
+}
+
+impl Default for PlaybackOptions {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            fade_in_ms: FADE_MS,
+            fade_out_ms: FADE_MS,
+            strict: false,
+        }
+    }
+}
+
+/// A playback as play_audio_to_devices is asked for one, beyond the audio
+/// and the devices. Anything left out takes its default.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct PlaybackRequest {
+    /// One per device id, 0 to MAX_DEVICE_VOLUME.
+    pub volumes: Option<Vec<f32>>,
+    /// What the system's now-playing UI shows.
+    pub title: Option<String>,
+    /// Refuse to play when a running capture would record it.
+    pub block_on_conflict: bool,
+    /// Return only once it has played out or been stopped.
+    pub wait: bool,
+    /// Play over and over until stopped, or repeat_count times.
+    pub loop_playback: bool,
+    pub repeat_count: Option<u32>,
+    pub strict: bool,
+    /// FADE_MS each without.
+    pub fade_in_ms: Option<u32>,
+    pub fade_out_ms: Option<u32>,
+}
+
+impl PlaybackRequest {
+    /// The options it asks a clip to play with.
+    pub fn options(&self) -> PlaybackOptions {
+        PlaybackOptions {
+            fade_in_ms: self.fade_in_ms.unwrap_or(FADE_MS),
+            fade_out_ms: self.fade_out_ms.unwrap_or(FADE_MS),
+            strict: self.strict,
+            ..PlaybackOptions::default()
+        }
+    }
+
+    /// Whether it plays more than once, until stopped or repeat_count times.
+    pub fn looped(&self) -> bool {
+        self.loop_playback || self.repeat_count.is_some()
+    }
+
+    /// Refuse what only a clip can do, for a playback of `what`, such as a
+    /// file, which plays once with the default fades.
+    pub fn check_plays_once(&self, what: &str) -> Result<(), VoiceboxError> {
+        if self.looped() {
+            return Err(VoiceboxError::invalid_argument(format!("{} can't loop", what)));
+        }
+        if self.fade_in_ms.is_some() || self.fade_out_ms.is_some() {
+            return Err(VoiceboxError::invalid_argument(format!("{} plays with the default fades", what)));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackState {
+    Idle,
+    Playing,
+    Paused,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[non_exhaustive]
+pub struct PlaybackStatus {
+    pub state: PlaybackState,
+    /// Playing or paused; state split in two for the UI.
+    pub playing: bool,
+    pub paused: bool,
+    pub title: Option<String>,
+    pub position_ms: u64,
+    pub duration_ms: u64,
+    pub position_secs: f64,
+    pub duration_secs: f64,
+    /// The devices it is on, after any moves. Empty when idle.
+    pub device_ids: Vec<String>,
+    /// Whether media keys and the system's now-playing UI control playback.
+    /// Filled in by get_playback_status from media_controls.
+    pub media_controls: bool,
+}
+
+/// How a playback ended, from PlaybackHandle::finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackEnd {
+    /// Played out on every device.
+    Completed,
+    /// Stopped, or replaced by another playback, before it played out.
+    Cancelled,
+    /// One of its devices went away while it played.
+    Failed,
+}
+
+/// How a playback got onto one of its devices.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct DeviceResult {
+    pub device_id: String,
+    /// Joined the stream already open on the device rather than opening one.
+    pub reused_stream: bool,
+    /// Opening the device failed once and the retry succeeded.
+    pub retried: bool,
+    /// Stopped or replaced by a newer playback while waiting for the device
+    /// to open, so it wasn't opened again for this one.
+    pub superseded: bool,
+    /// A Bluetooth device that stayed on its hands-free profile, so the clip
+    /// plays at its low rate. Freeing its microphone switches it back.
+    pub degraded_profile: bool,
+    /// Why the device couldn't play the clip, while the others did.
+    pub error: Option<String>,
+}
+
+/// Shared by a playback's renderers, one per device.
+#[derive(Debug)]
+struct PlaybackShared {
+    stop: AtomicBool,
+    paused: AtomicBool,
+    /// Furthest any device has got into the clip.
+    position_ms: AtomicU64,
+    /// Where the last seek went, picked up by each device when
+    /// seek_generation moves past the one it has.
+    seek_ms: AtomicU64,
+    seek_generation: AtomicU64,
+    /// Devices still playing.
+    active: AtomicUsize,
+    /// Woken once the last device is done.
+    finished: tokio::sync::Notify,
+    /// Set when a device went away while it played.
+    failed: AtomicBool,
+    /// The devices that went away while it played on them.
+    lost_devices: Mutex<Vec<String>>,
+    /// Times a device ran out of streamed frames and played silence.
+    underruns: AtomicU64,
+    /// The handoff flag of its renderer on each device, by device id.
+    handoffs: Mutex<HashMap<String, Arc<AtomicBool>>>,
+    /// Each device's volume, as f32 bits, by device id.
+    volumes: Mutex<HashMap<String, Arc<AtomicU32>>>,
+    /// The level of what each device has been sent, by device id.
+    levels: Mutex<HashMap<String, Arc<LevelCounters>>>,
+    /// When the first frame is heard on every device, set once they have
+    /// all opened, for a playback to several.
+    starts_at: OnceLock<Instant>,
+}
+
+impl PlaybackShared {
+    fn new(active: usize) -> Arc<Self> {
+        Arc::new(Self {
+            stop: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            position_ms: AtomicU64::new(0),
+            seek_ms: AtomicU64::new(0),
+            seek_generation: AtomicU64::new(0),
+            active: AtomicUsize::new(active),
+            finished: tokio::sync::Notify::new(),
+            failed: AtomicBool::new(false),
+            lost_devices: Mutex::new(Vec::new()),
+            underruns: AtomicU64::new(0),
+            handoffs: Mutex::new(HashMap::new()),
+            volumes: Mutex::new(HashMap::new()),
+            levels: Mutex::new(HashMap::new()),
+            starts_at: OnceLock::new(),
+        })
+    }
+
+    /// Where `device_id`'s renderer counts the level of what it sends.
+    fn level_counters(&self, device_id: &str) -> Arc<LevelCounters> {
+        self.levels.lock().unwrap().entry(device_id.to_string()).or_default().clone()
+    }
+}
+
+/// The clip a playback was started with, kept to render it again for a
+/// device it moves to.
+struct PlaybackSource {
+    samples: Vec<f32>,
+    sample_rate: u32,
+    channels: u16,
+    options: PlaybackOptions,
+    /// A file decoded as it plays, or pushed chunks, in place of `samples`.
+    stream: Option<Arc<SourceStream>>,
+    plays: Plays,
+}
+
+/// How many times a clip plays through, one straight after the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Plays {
+    Once,
+    Times(u32),
+    UntilStopped,
+}
+
+impl std::fmt::Debug for PlaybackSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PlaybackSource")
+            .field("samples", &self.samples.len())
+            .field("sample_rate", &self.sample_rate)
+            .field("channels", &self.channels)
+            .field("options", &self.options)
+            .field("streamed", &self.stream.is_some())
+            .field("plays", &self.plays)
+            .finish()
+    }
+}
+
+/// Frames that arrive as the playback plays rather than up front, shared
+/// by its devices: a file's, which its decoder thread keeps STREAM_AHEAD of
+/// past the device furthest behind, or chunks pushed to a playback stream.
+/// Those every device has played are dropped.
+struct SourceStream {
+    sample_rate: u32,
+    channels: u16,
+    /// Frames in the file, when its header says; None for a pushed stream.
+    frames: Option<u64>,
+    window: Mutex<StreamWindow>,
+    /// Woken when a device moves on, for the decoder to decode more.
+    moved: std::sync::Condvar,
+    /// Handed to the decoder thread once the playback starts.
+    decoder: Mutex<Option<StreamDecoder>>,
+}
+
+#[derive(Default)]
+struct StreamWindow {
+    /// Interleaved frames, from first_frame on.
+    samples: VecDeque<f32>,
+    first_frame: usize,
+    /// The decoder got to the end of the file, or gave up; or the pushed
+    /// stream was ended.
+    ended: bool,
+    /// The frame of the file each device's renderer is at; None once it
+    /// is done.
+    readers: Vec<Option<usize>>,
+    /// For a playback queue, each clip's first frame and the frame after
+    /// its last.
+    items: Vec<(usize, usize)>,
+}
+
+impl StreamWindow {
+    /// Drop the frames every device has played.
+    fn trim(&mut self, channels: usize) {
+        if let Some(slowest) = self.readers.iter().flatten().min().copied() {
+            let played = slowest.saturating_sub(self.first_frame).min(self.samples.len() / channels);
+            self.samples.drain(..played * channels);
+            self.first_frame += played;
+        }
+    }
+}
+
+struct StreamDecoder {
+    format: Box<dyn symphonia::core::formats::FormatReader>,
+    decoder: Box<dyn symphonia::core::codecs::Decoder>,
+    track_id: u32,
+}
+
+impl SourceStream {
+    /// Open `path` and decode the first STREAM_PRIME of its audio, or all of
+    /// it if shorter.
+    fn open(path: &std::path::Path) -> Result<Self, VoiceboxError> {
+        use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+        use symphonia::core::formats::FormatOptions;
+        use symphonia::core::io::MediaSourceStream;
+        use symphonia::core::meta::MetadataOptions;
+        use symphonia::core::probe::Hint;
+
+        let name = path.display();
+        let mut file = std::fs::File::open(path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => VoiceboxError::not_found(format!("There is no file {}", name)),
+            _ => VoiceboxError::io(format!("Couldn't read {}: {}", name, e)),
+        })?;
+        let mut head = [0u8; 12];
+        let read = std::io::Read::read(&mut file, &mut head)
+            .map_err(|e| VoiceboxError::io(format!("Couldn't read {}: {}", name, e)))?;
+        std::io::Seek::rewind(&mut file).map_err(|e| VoiceboxError::io(format!("Couldn't read {}: {}", name, e)))?;
+        let container = audio_import::sniff_container(&head[..read]);
+        let failed = |what: String| {
+            VoiceboxError::invalid_argument(match container {
+                Some(container) => format!("Couldn't play the {} audio in {}: {}", container, name, what),
+                None => format!("Couldn't play {}, which isn't WAV, MP3, FLAC, Ogg or M4A: {}", name, what),
+            })
+        };
+
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+            hint.with_extension(extension);
+        }
+        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+        let format = symphonia::default::get_probe()
+            .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())
+            .map_err(|e| failed(e.to_string()))?
+            .format;
+        let track = format
+            .tracks()
+            .iter()
+            .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| failed("The file contains no audio track".to_string()))?;
+        let params = &track.codec_params;
+        let sample_rate = params.sample_rate.ok_or_else(|| failed("No sample rate found".to_string()))?;
+        let channels = params
+            .channels
+            .ok_or_else(|| failed("No channels found".to_string()))?
+            .count() as u16;
+        let decoder = symphonia::default::get_codecs()
+            .make(params, &DecoderOptions::default())
+            .map_err(|e| failed(format!("Unsupported codec: {}", e)))?;
+        let frames = params.n_frames;
+        let mut decoding = StreamDecoder {
+            track_id: track.id,
+            decoder,
+            format,
+        };
+        let stream = Self {
+            sample_rate,
+            channels,
+            frames,
+            window: Mutex::new(StreamWindow::default()),
+            moved: std::sync::Condvar::new(),
+            decoder: Mutex::new(None),
+        };
+
+        // Enough decoded that devices don't start out waiting on the decoder
+        let primed = (STREAM_PRIME.as_secs_f64() * sample_rate as f64) as usize * channels as usize;
+        while stream.window.lock().unwrap().samples.len() < primed {
+            if !decoding.decode_next(&stream).map_err(failed)? {
+                stream.end_file();
+                return Ok(stream);
+            }
+        }
+        *stream.decoder.lock().unwrap() = Some(decoding);
+        Ok(stream)
+    }
+
+    /// Nothing yet, for chunks to be pushed to.
+    fn pushed(sample_rate: u32, channels: u16) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            frames: None,
+            window: Mutex::new(StreamWindow::default()),
+            moved: std::sync::Condvar::new(),
+            decoder: Mutex::new(None),
+        }
+    }
+
+    /// A new device's place in the stream, at its start.
+    fn add_reader(&self) -> usize {
+        let mut window = self.window.lock().unwrap();
+        window.readers.push(Some(0));
+        window.readers.len() - 1
+    }
+
+    fn move_reader(&self, reader: usize, frame: Option<usize>) {
+        self.window.lock().unwrap().readers[reader] = frame;
+        self.moved.notify_one();
+    }
+
+    /// Decode on a thread of its own until the file ends or `playback`
+    /// does. A decode error fails the playback.
+    fn spawn_decoder(self: &Arc<Self>, playback: Arc<PlaybackShared>) -> Result<(), VoiceboxError> {
+        let Some(mut decoding) = self.decoder.lock().unwrap().take() else {
+            return Ok(());
+        };
+        let stream = self.clone();
+        let ahead = (STREAM_AHEAD.as_secs_f64() * self.sample_rate as f64) as usize;
+        std::thread::Builder::new()
+            .name("audio-file-decoder".to_string())
+            .spawn(move || {
+                let channels = stream.channels as usize;
+                let over = || playback.stop.load(Ordering::Relaxed) || playback.active.load(Ordering::Relaxed) == 0;
+                loop {
+                    {
+                        let mut window = stream.window.lock().unwrap();
+                        loop {
+                            if over() {
+                                return;
+                            }
+                            window.trim(channels);
+                            if window.samples.len() / channels < ahead {
+                                break;
+                            }
+                            window = stream.moved.wait_timeout(window, Duration::from_millis(50)).unwrap().0;
+                        }
+                    }
+                    match decoding.decode_next(&stream) {
+                        Ok(true) => {}
+                        Ok(false) => break,
+                        Err(e) => {
+                            warn!("{}", e);
+                            playback.failed.store(true, Ordering::Relaxed);
+                            break;
+                        }
+                    }
+                }
+                stream.end_file();
+            })
+            .map(|_| ())
+            .map_err(|e| VoiceboxError::playback(format!("Failed to start the decoder thread: {}", e)))
+    }
+
+    /// The decoder got to the end of the file, or gave up.
+    fn end_file(&self) {
+        let mut window = self.window.lock().unwrap();
+        // A FLAC encoder may pad its last block; the header has the real length
+        if let Some(frames) = self.frames {
+            let end = (frames as usize).saturating_sub(window.first_frame) * self.channels as usize;
+            window.samples.truncate(end);
+        }
+        window.ended = true;
+    }
+}
+
+impl StreamDecoder {
+    /// Decode the next packet of the track onto the end of `stream`'s
+    /// window. False at the end of the file.
+    fn decode_next(&mut self, stream: &SourceStream) -> Result<bool, String> {
+        use symphonia::core::audio::SampleBuffer;
+        use symphonia::core::errors::Error as SymphoniaError;
+
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+                Err(SymphoniaError::ResetRequired) => return Ok(false),
+                Err(e) => return Err(format!("Failed to read the audio file: {}", e)),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A corrupt packet is skipped, as players do
+                Err(SymphoniaError::DecodeError(e)) => {
+                    debug!("Skipping undecodable packet: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(format!("Failed to decode the audio file: {}", e)),
+            };
+            let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+            buffer.copy_interleaved_ref(decoded);
+            stream.window.lock().unwrap().samples.extend(buffer.samples());
+            return Ok(true);
+        }
+    }
+}
+/// A renderer's place in a SourceStream.
+struct StreamReader {
+    stream: Arc<SourceStream>,
+    reader: usize,
+}
+
+/// One playback, for following its progress.
+#[derive(Debug, Clone)]
+pub struct PlaybackHandle {
+    id: u64,
+    shared: Arc<PlaybackShared>,
+    source: Arc<PlaybackSource>,
+    duration: Duration,
+    title: Option<String>,
+    /// The devices it plays on.
+    device_ids: Vec<String>,
+    /// Filled in once it has started on all of them.
+    device_results: Vec<DeviceResult>,
+}
+
+impl PlaybackHandle {
+    /// Tells playbacks apart, for move_playback.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Zero for a file played as it decodes whose header doesn't say, and
+    /// for a playback stream.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// How far it has got into the clip; for a looped one, into the time
+    /// through it's on.
+    pub fn position(&self) -> Duration {
+        let position = Duration::from_millis(self.shared.position_ms.load(Ordering::Relaxed));
+        if self.duration.is_zero() {
+            position
+        } else if self.source.plays != Plays::Once {
+            position.saturating_sub(self.duration * self.iteration() as u32)
+        } else {
+            position.min(self.duration)
+        }
+    }
+
+    /// Which time through the clip it's on, from 0. Only a looped clip gets
+    /// past 0.
+    pub fn iteration(&self) -> u64 {
+        if self.source.plays == Plays::Once || self.duration.is_zero() {
+            return 0;
+        }
+        let position = Duration::from_millis(self.shared.position_ms.load(Ordering::Relaxed));
+        (position.as_secs_f64() / self.duration.as_secs_f64()) as u64
+    }
+
+    /// Played over and over, as loop_audio_to_devices plays it.
+    pub fn is_looped(&self) -> bool {
+        self.source.plays != Plays::Once
+    }
+
+    /// Played from a file as it decodes, or from a playback stream's
+    /// chunks, which can't seek or move.
+    pub fn is_streamed(&self) -> bool {
+        self.source.stream.is_some()
+    }
+
+    /// The devices that went away while it played, which ends it as failed
+    /// once the rest are done.
+    pub fn lost_devices(&self) -> Vec<String> {
+        self.shared.lost_devices.lock().unwrap().clone()
+    }
+
+    /// Which clip of a playback queue it has got to, counting from the
+    /// first queued; None when it isn't a queue.
+    pub fn queue_item(&self) -> Option<usize> {
+        let stream = self.source.stream.as_ref()?;
+        let window = stream.window.lock().unwrap();
+        let frame = (self.shared.position_ms.load(Ordering::Relaxed) * stream.sample_rate as u64 / 1000) as usize;
+        window.items.partition_point(|(start, _)| *start <= frame).checked_sub(1)
+    }
+
+    /// Times a device ran out of streamed frames and played silence until
+    /// more came.
+    pub fn underruns(&self) -> u64 {
+        self.shared.underruns.load(Ordering::Relaxed)
+    }
+
+    /// RMS and peak of what `device_id` has been sent since the last call,
+    /// after gain, fades, volume and conversion to its format, for level
+    /// meters. None for a device the playback hasn't been on.
+    pub fn take_level(&self, device_id: &str) -> Option<(f32, f32)> {
+        Some(self.shared.levels.lock().unwrap().get(device_id)?.take())
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn device_ids(&self) -> &[String] {
+        &self.device_ids
+    }
+
+    /// One per device, in the order of device_ids.
+    pub fn device_results(&self) -> &[DeviceResult] {
+        &self.device_results
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.paused.load(Ordering::Relaxed)
+    }
+
+    /// Played out on every device, stopped, or replaced by another playback.
+    pub fn is_finished(&self) -> bool {
+        self.shared.stop.load(Ordering::Relaxed) || self.shared.active.load(Ordering::Relaxed) == 0
+    }
+
+    /// Wait for every device to be done with it: played out, or faded out
+    /// after a stop.
+    pub async fn finished(&self) -> PlaybackEnd {
+        loop {
+            let done = self.shared.finished.notified();
+            if self.shared.active.load(Ordering::Relaxed) == 0 {
+                break;
+            }
+            done.await;
+        }
+        if self.shared.failed.load(Ordering::Relaxed) {
+            PlaybackEnd::Failed
+        } else if self.shared.stop.load(Ordering::Relaxed) {
+            PlaybackEnd::Cancelled
+        } else {
+            PlaybackEnd::Completed
+        }
+    }
+
+    /// The gain and fades it was started with.
+    pub fn options(&self) -> PlaybackOptions {
+        self.source.options
+    }
+
+    /// Whether two handles are the same playback.
+    pub fn same_as(&self, other: &PlaybackHandle) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+
+    /// A renderer of the clip for a device of `format`.
+    fn renderer(&self, format: DeviceFormat) -> Renderer {
+        let source = &self.source;
+        if let Some(stream) = &source.stream {
+            let mut renderer = Renderer::new(Vec::new(), format, source.options, self.shared.clone());
+            let reader = stream.add_reader();
+            renderer.stream = Some(StreamReader {
+                stream: stream.clone(),
+                reader,
+            });
+            return renderer;
+        }
+        let converted = convert(&source.samples, source.channels, source.sample_rate, format);
+        let mut renderer = Renderer::new(converted, format, source.options, self.shared.clone());
+        renderer.plays = source.plays;
+        renderer
+    }
+}
+
+/// A live stream, started by play_live. It plays until stopped, or until its
+/// device goes away.
+#[derive(Debug, Clone)]
+pub struct LiveStream {
+    shared: Arc<PlaybackShared>,
+}
+
+impl LiveStream {
+    /// Fade out and close the stream.
+    pub fn stop(&self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.shared.active.load(Ordering::Relaxed) == 0
+    }
+}
+
+/// Frames made as they are played, such as an audio route's buffer. They
+/// are already in the device's format.
+pub trait LiveSource: Send + Sync {
+    /// Fill all of `out` with interleaved frames, silence for any not there.
+    fn pull(&self, out: &mut [f32]);
+}
+
+/// Where played frames go: cpal device streams in the app, NullSink in
+/// tests. Conversion, gain and fades happen before, in Renderer, so every
+/// backend plays the same frames.
+pub trait OutputBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn list_devices(&self) -> Result<Vec<AudioOutputDevice>, VoiceboxError>;
+
+    fn device_format(&self, device_id: &str) -> Result<DeviceFormat, VoiceboxError>;
+
+    /// Start pulling frames from `renderer` on the device, returning once it
+    /// plays. The stream lives until the renderer is done.
+    fn play(&self, device_id: &str, renderer: Renderer) -> Result<(), VoiceboxError>;
+}
+
+/// Plays decoded clips to devices of a backend. The latest playback can be
+/// paused, resumed and stopped.
+pub struct AudioOutputState {
+    backend: Arc<dyn OutputBackend>,
+    /// The latest playback. Each gets its own flags, so a stop can't be
+    /// undone by the next start.
+    current: Mutex<Option<PlaybackHandle>>,
+    /// Each device's open stream, by device id. The async lock is held while
+    /// the device opens, so clips started meanwhile queue on it in order and
+    /// then join the stream it opened.
+    streams: Mutex<HashMap<String, DeviceStream>>,
+    /// The last rate above HFP_MAX_SAMPLE_RATE each device reported, to tell
+    /// a Bluetooth device that dropped to its hands-free profile.
+    full_rates: Mutex<HashMap<String, u32>>,
+    /// Playback streams still taking chunks, by playback id.
+    pushed: Mutex<HashMap<u64, PlaybackHandle>>,
+    /// The latency each device's stream last reported, in microseconds,
+    /// by device id; u64::MAX until it reports one.
+    latencies: Mutex<HashMap<String, Arc<AtomicU64>>>,
+    /// The playback queue, if there is one. Held while a queued clip
+    /// starts, so two queued at once play in turn.
+    queue: tokio::sync::Mutex<Option<PlaybackQueue>>,
+    next_playback_id: AtomicU64,
+}
+
+/// The playback clips are queued on, and the device ids it was asked for.
+struct PlaybackQueue {
+    playback: PlaybackHandle,
+    device_ids: Vec<String>,
+}
+
+/// A clip queue_audio_for_playback queued.
+#[derive(Debug, Clone)]
+pub struct QueuedClip {
+    /// The queue's playback.
+    pub playback: PlaybackHandle,
+    /// Its place in the queue, counting from the first queued.
+    pub index: usize,
+    /// Whether it started a new queue rather than joining one.
+    pub started: bool,
+}
+
+/// A device's open stream, if any, behind the lock its opens take turns on.
+type DeviceStream = Arc<tokio::sync::Mutex<Option<Arc<Mixer>>>>;
+
+impl AudioOutputState {
+    /// Plays to the system's devices through cpal.
+    #[cfg(feature = "native-backends")]
+    pub fn new() -> Self {
+        Self::with_backend(Arc::new(CpalBackend::new()))
+    }
+
+    pub fn with_backend(backend: Arc<dyn OutputBackend>) -> Self {
+        Self {
+            backend,
+            current: Mutex::new(None),
+            streams: Mutex::new(HashMap::new()),
+            full_rates: Mutex::new(HashMap::new()),
+            pushed: Mutex::new(HashMap::new()),
+            latencies: Mutex::new(HashMap::new()),
+            queue: tokio::sync::Mutex::new(None),
+            next_playback_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Stop the current playback on all its devices. They fade out over
+    /// its fade and drop the rest of the clip; devices it was still waiting
+    /// to open aren't opened. Its finished() resolves as cancelled. Playback
+    /// streams are stopped and take no more chunks. Nothing happens when
+    /// nothing is playing.
+    pub fn stop_all_playback(&self) -> Result<(), VoiceboxError> {
+        for (_, pushed) in self.pushed.lock().unwrap().drain() {
+            pushed.shared.stop.store(true, Ordering::Relaxed);
+        }
+        let Some(current) = self.current_playback() else {
+            debug!("stop_all_playback: Nothing is playing");
+            return Ok(());
+        };
+        debug!("stop_all_playback: Setting stop flag");
+        current.shared.stop.store(true, Ordering::Relaxed);
+        audio_log::record(AudioEvent::PlaybackStopped);
+        debug!("stop_all_playback: Stop flag set - active streams will fade out");
+        Ok(())
+    }
+
+    /// Ramp the current playback down and hold its position.
+    pub fn pause_playback(&self) -> Result<(), VoiceboxError> {
+        self.set_paused(|_| true)
+    }
+
+    pub fn resume_playback(&self) -> Result<(), VoiceboxError> {
+        self.set_paused(|_| false)
+    }
+
+    pub fn toggle_pause(&self) -> Result<(), VoiceboxError> {
+        self.set_paused(|paused| !paused)
+    }
+
+    fn set_paused(&self, paused: impl FnOnce(bool) -> bool) -> Result<(), VoiceboxError> {
+        let playback = self
+            .current_playback()
+            .ok_or_else(|| VoiceboxError::not_found("Nothing is playing"))?;
+        let paused = paused(playback.is_paused());
+        playback.shared.paused.store(paused, Ordering::Relaxed);
+        debug!("Playback {}", if paused { "paused" } else { "resumed" });
+        Ok(())
+    }
+
+    /// Jump the current playback to `position_secs` on all its devices, each
+    /// from the same frame of the clip. A seek to its end or past stops it.
+    /// It stays paused if it was.
+    pub fn seek_playback(&self, position_secs: f64) -> Result<(), VoiceboxError> {
+        if !position_secs.is_finite() || position_secs < 0.0 {
+            return Err(VoiceboxError::invalid_argument(format!(
+                "Can't seek to {} seconds",
+                position_secs
+            )));
+        }
+        let playback = self
+            .current_playback()
+            .ok_or_else(|| VoiceboxError::not_found("Nothing is playing"))?;
+        if playback.is_streamed() {
+            return Err(VoiceboxError::unsupported("Audio played as it decodes or arrives can't seek"));
+        }
+        let position = Duration::from_secs_f64(position_secs);
+        if position >= playback.duration {
+            debug!("Seek to {:?} is past the end, stopping", position);
+            playback.shared.stop.store(true, Ordering::Relaxed);
+            audio_log::record(AudioEvent::PlaybackStopped);
+            return Ok(());
+        }
+        let position_ms = position.as_millis() as u64;
+        let shared = &playback.shared;
+        shared.seek_ms.store(position_ms, Ordering::Relaxed);
+        shared.seek_generation.fetch_add(1, Ordering::Release);
+        // A looped clip stays on the time through it's on
+        let looped_ms = (playback.duration * playback.iteration() as u32).as_millis() as u64;
+        shared.position_ms.store(looped_ms + position_ms, Ordering::Relaxed);
+        debug!("Playback seeked to {:?}", position);
+        Ok(())
+    }
+
+    /// Change the current playback's volume on one of its devices while it
+    /// plays, as play_audio_to_devices' `volumes` sets it.
+    pub fn set_playback_volume(&self, device_id: &str, volume: f32) -> Result<(), VoiceboxError> {
+        check_volume(volume)?;
+        let playback = self
+            .current_playback()
+            .ok_or_else(|| VoiceboxError::not_found("Nothing is playing"))?;
+        let device_id = match device_id {
+            DEFAULT_DEVICE_ID => self
+                .backend
+                .list_devices()?
+                .into_iter()
+                .find(|device| device.is_default)
+                .map_or_else(|| device_id.to_string(), |device| device.id),
+            _ => device_id.to_string(),
+        };
+        let volumes = playback.shared.volumes.lock().unwrap();
+        let level = volumes
+            .get(&device_id)
+            .ok_or_else(|| VoiceboxError::invalid_argument(format!("Playback isn't on {}", device_id)))?;
+        level.store(volume.to_bits(), Ordering::Relaxed);
+        debug!("Playback volume on {} set to {}", device_id, volume);
+        Ok(())
+    }
+
+    /// For a watcher that found `device_id` gone from the device list: its
+    /// stream, if open, closes as if the backend had reported the device
+    /// lost, ending the clips on it as failed while the other devices play
+    /// on. Returns whether there was one.
+    pub fn drop_removed_device(&self, device_id: &str) -> bool {
+        let Some(slot) = self.streams.lock().unwrap().get(device_id).cloned() else {
+            return false;
+        };
+        // A device still opening fails to open by itself
+        let Ok(stream) = slot.try_lock() else {
+            return false;
+        };
+        let Some(mixer) = stream.as_ref() else {
+            return false;
+        };
+        warn!("{} went away, closing its stream", device_id);
+        mixer.abandon();
+        true
+    }
+
+    /// The playback that hasn't finished yet, if any.
+    pub fn current_playback(&self) -> Option<PlaybackHandle> {
+        self.current
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|playback| !playback.is_finished())
+            .cloned()
+    }
+
+    pub fn status(&self) -> PlaybackStatus {
+        let playback = self.current_playback();
+        let state = match &playback {
+            None => PlaybackState::Idle,
+            Some(playback) if playback.is_paused() => PlaybackState::Paused,
+            Some(_) => PlaybackState::Playing,
+        };
+        let position = playback.as_ref().map_or(Duration::ZERO, |playback| playback.position());
+        let duration = playback.as_ref().map_or(Duration::ZERO, |playback| playback.duration);
+        PlaybackStatus {
+            state,
+            playing: state == PlaybackState::Playing,
+            paused: state == PlaybackState::Paused,
+            title: playback.as_ref().and_then(|playback| playback.title.clone()),
+            position_ms: position.as_millis() as u64,
+            duration_ms: duration.as_millis() as u64,
+            position_secs: position.as_secs_f64(),
+            duration_secs: duration.as_secs_f64(),
+            device_ids: playback.map(|playback| playback.device_ids).unwrap_or_default(),
+            media_controls: false,
+        }
+    }
+
+    pub fn backend_name(&self) -> &'static str {
+        self.backend.name()
+    }
+
+    pub fn list_output_devices(&self) -> Result<Vec<AudioOutputDevice>, VoiceboxError> {
+        self.backend.list_devices()
+    }
+
+    /// How long `device_id`, or the default device for `default`, takes to
+    /// play what its stream pulls, as the stream last reported it. None
+    /// until it has played something since the app started.
+    pub fn device_latency(&self, device_id: &str) -> Result<Option<Duration>, VoiceboxError> {
+        let device = self
+            .backend
+            .list_devices()?
+            .into_iter()
+            .find(|device| device.id == device_id || device.is_default && device_id == DEFAULT_DEVICE_ID)
+            .ok_or_else(|| VoiceboxError::not_found(format!("No output device {}", device_id)))?;
+        Ok(self.stream_latency(&device.id))
+    }
+
+    fn stream_latency(&self, device_id: &str) -> Option<Duration> {
+        let micros = self.latencies.lock().unwrap().get(device_id)?.load(Ordering::Relaxed);
+        (micros != u64::MAX).then(|| Duration::from_micros(micros))
+    }
+
+    /// Where `device_id`'s streams report their latency.
+    fn latency_slot(&self, device_id: &str) -> Arc<AtomicU64> {
+        let mut latencies = self.latencies.lock().unwrap();
+        latencies.entry(device_id.to_string()).or_insert_with(|| Arc::new(AtomicU64::new(u64::MAX))).clone()
+    }
+
+    pub fn device_format(&self, device_id: &str) -> Result<DeviceFormat, VoiceboxError> {
+        let format = self.backend.device_format(device_id)?;
+        if format.sample_rate > HFP_MAX_SAMPLE_RATE {
+            self.full_rates.lock().unwrap().insert(device_id.to_string(), format.sample_rate);
+        }
+        Ok(format)
+    }
+
+    /// The format to play a clip on `device` in. A Bluetooth device that
+    /// looks switched to its hands-free profile is asked again after each of
+    /// PROFILE_RETRY_DELAYS; if it stays there, the format is returned
+    /// flagged as degraded.
+    async fn negotiate_format(&self, device: &AudioOutputDevice) -> Result<(DeviceFormat, bool), VoiceboxError> {
+        let previous = self.full_rates.lock().unwrap().get(&device.id).copied();
+        let mut format = self.device_format(&device.id)?;
+        let mut retries = PROFILE_RETRY_DELAYS.iter();
+        while is_degraded_profile(device.transport, format.sample_rate, previous) {
+            let Some(&delay) = retries.next() else {
+                let previous_sample_rate = previous.unwrap_or_default();
+                warn!(
+                    "{} stayed on its hands-free profile at {} Hz (was {} Hz), playing degraded",
+                    device.name, format.sample_rate, previous_sample_rate
+                );
+                METRICS.playback_degraded_profiles.inc();
+                audio_log::record(AudioEvent::PlaybackDegraded {
+                    device_id: device.id.clone(),
+                    sample_rate: format.sample_rate,
+                    previous_sample_rate,
+                });
+                return Ok((format, true));
+            };
+            debug!(
+                "{} is at {} Hz, likely its hands-free profile; asking again in {:?}",
+                device.name, format.sample_rate, delay
+            );
+            tokio::time::sleep(delay).await;
+            format = self.device_format(&device.id)?;
+        }
+        Ok((format, false))
+    }
+
+    /// Play `source` on a device until the returned stream is stopped.
+    /// `format` is what the source produces, from device_format. Clip
+    /// playback is separate and carries on alongside.
+    pub fn play_live(
+        &self,
+        device_id: &str,
+        format: DeviceFormat,
+        source: Arc<dyn LiveSource>,
+        options: PlaybackOptions,
+    ) -> Result<LiveStream, VoiceboxError> {
+        let shared = PlaybackShared::new(1);
+        let mut renderer = Renderer::new(Vec::new(), format, options, shared.clone());
+        renderer.live = Some(source);
+        self.backend.play(device_id, renderer).map_err(|e| {
+            audio_log::record(AudioEvent::DeviceError {
+                device_id: Some(device_id.to_string()),
+                error: e.message().to_string(),
+            });
+            VoiceboxError::playback(format!("Failed to stream to device {}: {}", device_id, e))
+        })?;
+        debug!("Live stream started on {}", device_id);
+        Ok(LiveStream { shared })
+    }
+
+    /// Set by stop_all_playback, cleared when the next playback starts.
+    pub fn is_stopped(&self) -> bool {
+        self.current
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|playback| playback.shared.stop.load(Ordering::Relaxed))
+    }
+
+    /// Start playback of a WAV, MP3, FLAC, Ogg Vorbis or M4A/AAC clip on the
+    /// given devices, converted to each one's rate. `volumes`, one per device id
+    /// and up to MAX_DEVICE_VOLUME, sets how loud each device plays it;
+    /// without, all play at 1.0. `options` has its gain and how long it
+    /// fades in and out over on each device. `title` is what the system's
+    /// now-playing UI shows. The devices open first and then start the clip together,
+    /// lined up by their latency. A device that fails to open is left out,
+    /// with its error in the device results, unless the options are
+    /// strict, when it fails the playback.
+    pub async fn play_audio_to_devices(
+        &self,
+        audio_data: Vec<u8>,
+        device_ids: Vec<String>,
+        volumes: Option<Vec<f32>>,
+        options: PlaybackOptions,
+        title: Option<String>,
+    ) -> Result<PlaybackHandle, VoiceboxError> {
+        debug!("play_audio_to_devices called with {} bytes, {} device IDs", audio_data.len(), device_ids.len());
+
+        let decoded = self.decode(&audio_data)?;
+        debug!(
+            "Audio decoded: {} samples, {}Hz, {} channels, {}",
+            decoded.samples.len(),
+            decoded.sample_rate,
+            decoded.channels,
+            decoded.codec
+        );
+
+        let source = PlaybackSource {
+            samples: decoded.samples,
+            sample_rate: decoded.sample_rate,
+            channels: decoded.channels,
+            options,
+            stream: None,
+            plays: Plays::Once,
+        };
+        self.start(source, &device_ids, volumes.as_deref(), title, options.strict).await
+    }
+
+    /// Play a clip on the given devices as play_audio_to_devices does, over
+    /// and over without a gap: `repeat_count` times, or until stopped
+    /// without. The devices stay open throughout, each time through
+    /// starting on the frame after the last one's end; it fades in on the
+    /// first and out at the end of the last. Its position is into the time
+    /// through it's on, and iteration() counts them.
+    pub async fn loop_audio_to_devices(
+        &self,
+        audio_data: Vec<u8>,
+        device_ids: Vec<String>,
+        volumes: Option<Vec<f32>>,
+        options: PlaybackOptions,
+        repeat_count: Option<u32>,
+        title: Option<String>,
+    ) -> Result<PlaybackHandle, VoiceboxError> {
+        let plays = match repeat_count {
+            Some(0) => return Err(VoiceboxError::invalid_argument("A clip has to play at least once")),
+            Some(times) => Plays::Times(times),
+            None => Plays::UntilStopped,
+        };
+        let decoded = self.decode(&audio_data)?;
+        if decoded.samples.is_empty() {
+            return Err(VoiceboxError::invalid_argument("An empty clip can't loop"));
+        }
+        let source = PlaybackSource {
+            samples: decoded.samples,
+            sample_rate: decoded.sample_rate,
+            channels: decoded.channels,
+            options,
+            stream: None,
+            plays,
+        };
+        self.start(source, &device_ids, volumes.as_deref(), title, options.strict).await
+    }
+
+    /// Play an audio file on the given devices, as play_audio_to_devices
+    /// plays a clip, decoding it a little at a time as it plays so a long
+    /// one is never all in memory. A file that is missing, unreadable or
+    /// not audio fails before any device opens, and so does a device that
+    /// fails to open. Such a playback can't seek or move to another device.
+    pub async fn play_file_to_devices(
+        &self,
+        path: &std::path::Path,
+        device_ids: Vec<String>,
+        volumes: Option<Vec<f32>>,
+        title: Option<String>,
+    ) -> Result<PlaybackHandle, VoiceboxError> {
+        let opened = path.to_path_buf();
+        let stream = tokio::task::spawn_blocking(move || SourceStream::open(&opened)).await??;
+        debug!(
+            "Streaming {}: {}Hz, {} channels, {:?} frames",
+            path.display(),
+            stream.sample_rate,
+            stream.channels,
+            stream.frames
+        );
+        let source = PlaybackSource {
+            samples: Vec::new(),
+            sample_rate: stream.sample_rate,
+            channels: stream.channels,
+            options: PlaybackOptions::default(),
+            stream: Some(Arc::new(stream)),
+            plays: Plays::Once,
+        };
+        self.start(source, &device_ids, volumes.as_deref(), title, true).await
+    }
+
+    /// Start a playback stream on the given devices, for audio that arrives
+    /// a chunk at a time: it plays each chunk pushed with
+    /// push_playback_chunk as it comes, silence while it waits for the next,
+    /// and ends once end_playback_stream has been called and the rest has
+    /// played out. It replaces whatever is playing, as a clip does. Its id
+    /// is the stream's.
+    pub async fn begin_playback_stream(
+        &self,
+        device_ids: Vec<String>,
+        sample_rate: u32,
+        channels: u16,
+        volumes: Option<Vec<f32>>,
+        title: Option<String>,
+    ) -> Result<PlaybackHandle, VoiceboxError> {
+        let source = PlaybackSource {
+            samples: Vec::new(),
+            sample_rate,
+            channels,
+            options: PlaybackOptions::default(),
+            stream: Some(Arc::new(SourceStream::pushed(sample_rate, channels))),
+            plays: Plays::Once,
+        };
+        let playback = self.start(source, &device_ids, volumes.as_deref(), title, true).await?;
+        let mut pushed = self.pushed.lock().unwrap();
+        pushed.retain(|_, playback| !playback.is_finished());
+        if !playback.is_finished() {
+            pushed.insert(playback.id, playback.clone());
+        }
+        debug!("Playback stream {} begun at {}Hz, {} channels", playback.id, sample_rate, channels);
+        Ok(playback)
+    }
+
+    /// Add a chunk of 16-bit little-endian PCM, base64 encoded and
+    /// interleaved in whole frames, to the end of playback stream
+    /// `stream_id`. A stream that has been ended, stopped or replaced takes
+    /// no more.
+    pub fn push_playback_chunk(&self, stream_id: u64, pcm_base64: &str) -> Result<(), VoiceboxError> {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let playback = self.open_stream(stream_id)?;
+        let Some(stream) = &playback.source.stream else {
+            return Err(VoiceboxError::internal(format!("Playback {} isn't a stream", stream_id)));
+        };
+        let pcm = general_purpose::STANDARD
+            .decode(pcm_base64)
+            .map_err(|e| VoiceboxError::invalid_argument(format!("The chunk is not valid base64: {}", e)))?;
+        let channels = stream.channels as usize;
+        if pcm.len() % (2 * channels) != 0 {
+            return Err(VoiceboxError::invalid_argument(format!(
+                "A chunk of {} bytes isn't whole {}-channel 16-bit frames",
+                pcm.len(),
+                channels
+            )));
+        }
+        let mut window = stream.window.lock().unwrap();
+        window.trim(channels);
+        let buffered = window.samples.len() / channels + pcm.len() / (2 * channels);
+        if buffered as f64 > MAX_STREAM_BUFFERED.as_secs_f64() * stream.sample_rate as f64 {
+            return Err(VoiceboxError::busy(format!(
+                "Playback stream {} has more than {} seconds waiting to play",
+                stream_id,
+                MAX_STREAM_BUFFERED.as_secs()
+            )));
+        }
+        window.samples.extend(
+            pcm.chunks_exact(2)
+                .map(|sample| i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0),
+        );
+        Ok(())
+    }
+
+    /// Take no more chunks on playback stream `stream_id`: it plays what it
+    /// has, then finishes as completed.
+    pub fn end_playback_stream(&self, stream_id: u64) -> Result<(), VoiceboxError> {
+        let playback = self.open_stream(stream_id)?;
+        self.pushed.lock().unwrap().remove(&stream_id);
+        if let Some(stream) = &playback.source.stream {
+            stream.window.lock().unwrap().ended = true;
+        }
+        debug!("Playback stream {} ended", stream_id);
+        Ok(())
+    }
+
+    /// Playback stream `stream_id`, while it takes chunks.
+    fn open_stream(&self, stream_id: u64) -> Result<PlaybackHandle, VoiceboxError> {
+        let mut pushed = self.pushed.lock().unwrap();
+        pushed.retain(|_, playback| !playback.is_finished());
+        pushed
+            .get(&stream_id)
+            .cloned()
+            .ok_or_else(|| VoiceboxError::not_found(format!("Playback stream {} isn't open", stream_id)))
+    }
+
+    /// Queue a clip, as play_audio_to_devices takes it, to play on the
+    /// given devices once those queued before it have, after `gap` of
+    /// silence (DEFAULT_QUEUE_GAP without). The queue is one playback,
+    /// whose devices stay open from clip to clip. With nothing queued, or
+    /// once the queue has played out or been stopped, the clip starts
+    /// right away, replacing whatever is playing, as a new queue. A queue
+    /// plays to the devices it started with; queueing for others fails.
+    pub async fn queue_audio_for_playback(
+        &self,
+        audio_data: Vec<u8>,
+        device_ids: Vec<String>,
+        gap: Option<Duration>,
+    ) -> Result<QueuedClip, VoiceboxError> {
+        let decoded = self.decode(&audio_data)?;
+        if decoded.sample_rate == 0 || decoded.channels == 0 {
+            return Err(VoiceboxError::invalid_argument("Audio needs a sample rate and channels"));
+        }
+        let mut queue = self.queue.lock().await;
+        if let Some(queued) = queue.as_ref().filter(|queued| !queued.playback.is_finished()) {
+            if queued.device_ids != device_ids {
+                return Err(VoiceboxError::invalid_argument(format!(
+                    "The queue plays to {}; clear it to queue for other devices",
+                    queued.device_ids.join(", ")
+                )));
+            }
+            let Some(stream) = &queued.playback.source.stream else {
+                return Err(VoiceboxError::internal("The queue isn't a stream"));
+            };
+            let format = DeviceFormat {
+                sample_rate: stream.sample_rate,
+                channels: stream.channels,
+            };
+            let samples = convert(&decoded.samples, decoded.channels, decoded.sample_rate, format);
+            let channels = stream.channels as usize;
+            let mut window = stream.window.lock().unwrap();
+            // Once a device has played out the last clip, the queue is over
+            if !queued.playback.is_finished() && window.readers.iter().all(Option::is_some) {
+                let gap = (gap.unwrap_or(DEFAULT_QUEUE_GAP).as_secs_f64() * stream.sample_rate as f64) as usize;
+                window.samples.extend(std::iter::repeat_n(0.0, gap * channels));
+                let start = window.first_frame + window.samples.len() / channels;
+                window.samples.extend(samples);
+                let end = window.first_frame + window.samples.len() / channels;
+                window.items.push((start, end));
+                window.ended = true;
+                let index = window.items.len() - 1;
+                debug!("Queued clip {} on playback {}", index, queued.playback.id);
+                return Ok(QueuedClip {
+                    playback: queued.playback.clone(),
+                    index,
+                    started: false,
+                });
+            }
+        }
+
+        let frames = decoded.samples.len() / decoded.channels as usize;
+        let stream = SourceStream::pushed(decoded.sample_rate, decoded.channels);
+        {
+            let mut window = stream.window.lock().unwrap();
+            window.samples.extend(decoded.samples);
+            window.items.push((0, frames));
+            window.ended = true;
+        }
+        let source = PlaybackSource {
+            samples: Vec::new(),
+            sample_rate: decoded.sample_rate,
+            channels: decoded.channels,
+            options: PlaybackOptions::default(),
+            stream: Some(Arc::new(stream)),
+            plays: Plays::Once,
+        };
+        let playback = self.start(source, &device_ids, None, None, true).await?;
+        *queue = Some(PlaybackQueue {
+            playback: playback.clone(),
+            device_ids,
+        });
+        debug!("Started playback queue {}", playback.id);
+        Ok(QueuedClip {
+            playback,
+            index: 0,
+            started: true,
+        })
+    }
+
+    /// Drop the clips queued after the one playing, which plays on to its
+    /// end. Returns how many were dropped.
+    pub async fn clear_playback_queue(&self) -> usize {
+        let queue = self.queue.lock().await;
+        let Some(queued) = queue.as_ref().filter(|queued| !queued.playback.is_finished()) else {
+            return 0;
+        };
+        let (Some(stream), Some(playing)) = (&queued.playback.source.stream, queued.playback.queue_item()) else {
+            return 0;
+        };
+        let channels = stream.channels as usize;
+        let mut window = stream.window.lock().unwrap();
+        let dropped = window.items.len() - playing - 1;
+        let end = window.items[playing].1;
+        window.items.truncate(playing + 1);
+        let keep = end.saturating_sub(window.first_frame) * channels;
+        window.samples.truncate(keep);
+        debug!("Dropped {} queued clips", dropped);
+        dropped
+    }
+
+    /// Play interleaved frames on the given devices, replacing whatever is
+    /// playing. Returns the clip's length.
+    pub async fn play_samples(
+        &self,
+        samples: &[f32],
+        sample_rate: u32,
+        channels: u16,
+        device_ids: &[String],
+        options: PlaybackOptions,
+    ) -> Result<Duration, VoiceboxError> {
+        let source = PlaybackSource {
+            samples: samples.to_vec(),
+            sample_rate,
+            channels,
+            options,
+            stream: None,
+            plays: Plays::Once,
+        };
+        self.start(source, device_ids, None, None, true).await.map(|playback| playback.duration)
+    }
+
+    /// Play a sine of `frequency_hz`, TEST_TONE_HZ without, on `device_id`
+    /// alone for `duration_secs`, to check it's the device meant. The tone is
+    /// made at the device's own rate and plays the way a clip does, so a
+    /// device it plays on plays clips too. Returns once it has played out,
+    /// Cancelled if something stopped or replaced it first; a device that
+    /// fails to open, or goes away while it plays, is an error.
+    pub async fn play_test_tone(
+        &self,
+        device_id: &str,
+        duration_secs: f32,
+        frequency_hz: Option<f32>,
+    ) -> Result<PlaybackEnd, VoiceboxError> {
+        if !(duration_secs > 0.0 && duration_secs <= MAX_TEST_TONE.as_secs_f32()) {
+            return Err(VoiceboxError::invalid_argument(format!(
+                "A test tone lasts more than 0 and at most {} seconds, not {}",
+                MAX_TEST_TONE.as_secs(),
+                duration_secs
+            )));
+        }
+        let device = self
+            .backend
+            .list_devices()?
+            .into_iter()
+            .find(|device| device.id == device_id || device.is_default && device_id == DEFAULT_DEVICE_ID)
+            .ok_or_else(|| VoiceboxError::not_found(format!("No output device {}", device_id)))?;
+        if !device.is_active() {
+            return Err(VoiceboxError::playback(format!("Output device {} isn't active", device_id)));
+        }
+        let format = self.device_format(&device.id)?;
+        let frequency = frequency_hz.unwrap_or(TEST_TONE_HZ);
+        if !(frequency > 0.0 && frequency < format.sample_rate as f32 / 2.0) {
+            return Err(VoiceboxError::invalid_argument(format!(
+                "{} Hz can't play on {}, which runs at {} Hz",
+                frequency, device_id, format.sample_rate
+            )));
+        }
+        let source = PlaybackSource {
+            samples: test_tone(format.sample_rate, duration_secs, frequency),
+            sample_rate: format.sample_rate,
+            channels: 1,
+            options: PlaybackOptions::default(),
+            stream: None,
+            plays: Plays::Once,
+        };
+        debug!("Test tone of {} Hz for {}s on {}", frequency, duration_secs, device.name);
+        let title = Some("Test tone".to_string());
+        let playback = self.start(source, std::slice::from_ref(&device.id), None, title, true).await?;
+        match playback.finished().await {
+            PlaybackEnd::Failed => Err(VoiceboxError::playback(format!(
+                "Output device {} went away while the test tone played",
+                device_id
+            ))),
+            end => Ok(end),
+        }
+    }
+
+    async fn start(
+        &self,
+        source: PlaybackSource,
+        device_ids: &[String],
+        volumes: Option<&[f32]>,
+        title: Option<String>,
+        strict: bool,
+    ) -> Result<PlaybackHandle, VoiceboxError> {
+        let (sample_rate, channels) = (source.sample_rate, source.channels);
+        if sample_rate == 0 || channels == 0 {
+            return Err(VoiceboxError::invalid_argument("Audio needs a sample rate and channels"));
+        }
+        if let Some(volumes) = volumes {
+            if volumes.len() != device_ids.len() {
+                return Err(VoiceboxError::invalid_argument(format!(
+                    "Got {} volumes for {} devices",
+                    volumes.len(),
+                    device_ids.len()
+                )));
+            }
+            volumes.iter().try_for_each(|volume| check_volume(*volume))?;
+        }
+        debug!("Requested device IDs: {:?}", device_ids);
+        let devices: Vec<AudioOutputDevice> = self
+            .backend
+            .list_devices()?
+            .into_iter()
+            .filter(|device| {
+                device.is_active()
+                    && (device_ids.contains(&device.id)
+                        || device.is_default && device_ids.iter().any(|id| id == DEFAULT_DEVICE_ID))
+            })
+            .collect();
+        if devices.is_empty() {
+            error!("ERROR: No matching devices found");
+            return Err(VoiceboxError::not_found("No matching devices found"));
+        }
+
+        let frames = match &source.stream {
+            Some(stream) => stream.frames.unwrap_or(0) as f64,
+            None => source.samples.len() as f64 / channels as f64,
+        };
+        let mut playback = PlaybackHandle {
+            id: self.next_playback_id.fetch_add(1, Ordering::Relaxed),
+            shared: PlaybackShared::new(devices.len()),
+            source: Arc::new(source),
+            duration: Duration::from_secs_f64(frames / sample_rate as f64),
+            title,
+            device_ids: devices.iter().map(|device| device.id.clone()).collect(),
+            device_results: Vec::new(),
+        };
+
+        if let Some(stream) = &playback.source.stream {
+            stream.spawn_decoder(playback.shared.clone())?;
+        }
+        // Stop any existing playback first
+        if let Some(previous) = self.current.lock().unwrap().replace(playback.clone()) {
+            previous.shared.stop.store(true, Ordering::Relaxed);
+        }
+
+        warn!("Playing to {} device(s) through {}", devices.len(), self.backend.name());
+        // Each device holds the clip until they have all opened, then starts
+        // it at once
+        let synced = devices.len() > 1;
+        let mut device_results = Vec::with_capacity(devices.len());
+        for (i, device) in devices.iter().enumerate() {
+            debug!("Playing to device {}/{}: {}", i + 1, devices.len(), device.name);
+            let started = match self.negotiate_format(device).await {
+                Ok((format, degraded_profile)) => {
+                    let mut renderer = playback.renderer(format);
+                    renderer.awaits_start = synced;
+                    let volume = device_ids
+                        .iter()
+                        .position(|id| *id == device.id || device.is_default && id == DEFAULT_DEVICE_ID)
+                        .and_then(|i| volumes.map(|volumes| volumes[i]))
+                        .unwrap_or(1.0);
+                    renderer.volume = Arc::new(AtomicU32::new(volume.to_bits()));
+                    renderer.volume_level = volume;
+                    renderer.level = Some(playback.shared.level_counters(&device.id));
+                    playback.shared.handoffs.lock().unwrap().insert(device.id.clone(), renderer.handoff.clone());
+                    playback.shared.volumes.lock().unwrap().insert(device.id.clone(), renderer.volume.clone());
+                    self.play_on(&device.id, format, renderer)
+                        .await
+                        .map(|result| (format, DeviceResult { degraded_profile, ..result }))
+                        .map_err(|e| {
+                            VoiceboxError::playback(format!("Failed to play to device {}: {}", device.name, e))
+                        })
+                }
+                Err(e) => Err(e),
+            };
+            let (format, result) = match started {
+                Ok(started) => started,
+                Err(e) => {
+                    audio_log::record(AudioEvent::DeviceError {
+                        device_id: Some(device.id.clone()),
+                        error: e.message().to_string(),
+                    });
+                    if strict || devices.len() == 1 {
+                        // Devices that did start fade out, and the playback counts as finished
+                        playback.shared.stop.store(true, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                    warn!("Playing on without {}: {}", device.name, e);
+                    playback.device_ids.retain(|id| *id != device.id);
+                    playback.shared.handoffs.lock().unwrap().remove(&device.id);
+                    playback.shared.volumes.lock().unwrap().remove(&device.id);
+                    device_results.push(DeviceResult {
+                        device_id: device.id.clone(),
+                        reused_stream: false,
+                        retried: false,
+                        superseded: false,
+                        degraded_profile: false,
+                        error: Some(e.message().to_string()),
+                    });
+                    continue;
+                }
+            };
+            if result.superseded {
+                debug!("Replaced by a newer playback before {} opened", device.name);
+            } else {
+                audio_log::record(AudioEvent::PlaybackStarted {
+                    backend: self.backend.name(),
+                    device_id: device.id.clone(),
+                    sample_rate: format.sample_rate,
+                    channels: format.channels,
+                    duration_ms: playback.duration.as_millis() as u64,
+                });
+                METRICS.playbacks_started.inc();
+                warn!(
+                    "Successfully started playback on device: {}{}",
+                    device.name,
+                    if result.reused_stream { " (joined its open stream)" } else { "" }
+                );
+            }
+            device_results.push(result);
+        }
+        if device_results.iter().all(|result| result.error.is_some()) {
+            playback.shared.stop.store(true, Ordering::Relaxed);
+            let errors: Vec<String> = device_results.into_iter().filter_map(|result| result.error).collect();
+            return Err(VoiceboxError::playback(errors.join("; ")));
+        }
+        if synced {
+            // The device slowest to play what it pulls sets how far ahead
+            let latency = playback.device_ids.iter().filter_map(|id| self.stream_latency(id)).max();
+            let _ = playback.shared.starts_at.set(Instant::now() + START_LEAD + latency.unwrap_or_default());
+        }
+
+        playback.device_results = device_results;
+        if let Some(current) = self.current.lock().unwrap().as_mut().filter(|current| current.same_as(&playback)) {
+            current.device_ids = playback.device_ids.clone();
+            current.device_results = playback.device_results.clone();
+        }
+        Ok(playback)
+    }
+
+    /// Move the current playback, `playback_id`, from one of its devices to
+    /// another. The destination picks the clip up where it has got to, with
+    /// the same gain, and the two crossfade over CROSSFADE_MS before the
+    /// source closes. If the destination can't be opened, the playback
+    /// carries on on the source untouched.
+    pub async fn move_playback(
+        &self,
+        playback_id: u64,
+        from_device_id: &str,
+        to_device_id: &str,
+    ) -> Result<DeviceResult, VoiceboxError> {
+        let playback = self
+            .current_playback()
+            .filter(|playback| playback.id == playback_id)
+            .ok_or_else(|| VoiceboxError::not_found(format!("Playback {} isn't playing", playback_id)))?;
+        if playback.is_streamed() {
+            return Err(VoiceboxError::unsupported(
+                "Audio played as it decodes or arrives can't move to another device",
+            ));
+        }
+        let devices = self.backend.list_devices()?;
+        let resolve = |id: &str| {
+            devices
+                .iter()
+                .filter(|device| device.is_active())
+                .find(|device| device.id == id || device.is_default && id == DEFAULT_DEVICE_ID)
+        };
+        let from = resolve(from_device_id)
+            .map(|device| device.id.clone())
+            .filter(|id| playback.device_ids.contains(id))
+            .ok_or_else(|| {
+                VoiceboxError::invalid_argument(format!("Playback {} isn't on {}", playback_id, from_device_id))
+            })?;
+        let device = resolve(to_device_id)
+            .ok_or_else(|| VoiceboxError::not_found(format!("No output device {}", to_device_id)))?;
+        if playback.device_ids.contains(&device.id) {
+            return Err(VoiceboxError::invalid_argument(format!(
+                "Playback {} is already on {}",
+                playback_id, device.name
+            )));
+        }
+        let source_handoff = playback.shared.handoffs.lock().unwrap().get(&from).cloned();
+        let source_volume = playback.shared.volumes.lock().unwrap().get(&from).cloned();
+
+        let (format, degraded_profile) = self.negotiate_format(device).await?;
+        let mut renderer = playback.renderer(format);
+        renderer.takes_over = source_handoff;
+        renderer.level = Some(playback.shared.level_counters(&device.id));
+        // The destination plays as loud as the source did, and follows it
+        if let Some(volume) = source_volume {
+            renderer.volume_level = f32::from_bits(volume.load(Ordering::Relaxed));
+            renderer.volume = volume;
+        }
+        let volume = renderer.volume.clone();
+        let handoff = renderer.handoff.clone();
+        // Dropping the renderer, e.g. when the device fails to open, counts it out again
+        playback.shared.active.fetch_add(1, Ordering::Relaxed);
+        let result = self
+            .play_on(&device.id, format, renderer)
+            .await
+            .map(|result| DeviceResult { degraded_profile, ..result })
+            .map_err(|e| {
+                audio_log::record(AudioEvent::DeviceError {
+                    device_id: Some(device.id.clone()),
+                    error: e.message().to_string(),
+                });
+                VoiceboxError::playback(format!("Failed to move playback to {}: {}", device.name, e))
+            })?;
+        if result.superseded {
+            return Err(VoiceboxError::not_found(format!(
+                "Playback {} ended before {} opened",
+                playback_id, device.name
+            )));
+        }
+
+        {
+            let mut handoffs = playback.shared.handoffs.lock().unwrap();
+            handoffs.remove(&from);
+            handoffs.insert(device.id.clone(), handoff);
+            let mut volumes = playback.shared.volumes.lock().unwrap();
+            volumes.remove(&from);
+            volumes.insert(device.id.clone(), volume);
+        }
+        if let Some(current) = self.current.lock().unwrap().as_mut().filter(|current| current.same_as(&playback)) {
+            if let Some(index) = current.device_ids.iter().position(|id| *id == from) {
+                current.device_ids[index] = device.id.clone();
+                if let Some(moved) = current.device_results.get_mut(index) {
+                    *moved = result.clone();
+                }
+            }
+        }
+        audio_log::record(AudioEvent::PlaybackMoved {
+            from_device_id: from.clone(),
+            to_device_id: device.id.clone(),
+            sample_rate: format.sample_rate,
+            channels: format.channels,
+        });
+        warn!("Moved playback {} from {} to {}", playback_id, from, device.name);
+        Ok(result)
+    }
+
+    /// Put a clip's renderer on the device's stream: the open one if there
+    /// is one, otherwise a new one, retried once if it fails to open. Only
+    /// one open per device runs at a time; overlapping opens of an endpoint
+    /// can fail as busy.
+    async fn play_on(
+        &self,
+        device_id: &str,
+        format: DeviceFormat,
+        renderer: Renderer,
+    ) -> Result<DeviceResult, VoiceboxError> {
+        let slot = self.streams.lock().unwrap().entry(device_id.to_string()).or_default().clone();
+        let mut stream = slot.lock().await;
+        let renderer = match stream.as_ref() {
+            Some(mixer) => match mixer.join(renderer) {
+                Ok(()) => {
+                    METRICS.playback_streams_reused.inc();
+                    return Ok(DeviceResult {
+                        device_id: device_id.to_string(),
+                        reused_stream: true,
+                        retried: false,
+                        superseded: false,
+                        degraded_profile: false,
+                        error: None,
+                    });
+                }
+                // The stream closed after its last clip
+                Err(renderer) => *renderer,
+            },
+            None => renderer,
+        };
+        if renderer.playback.stop.load(Ordering::Relaxed) {
+            return Ok(DeviceResult {
+                device_id: device_id.to_string(),
+                reused_stream: false,
+                retried: false,
+                superseded: true,
+                degraded_profile: false,
+                error: None,
+            });
+        }
+
+        let latency = self.latency_slot(device_id);
+        let mixer = Arc::new(Mixer::new(device_id, renderer, latency));
+        let mut retried = false;
+        loop {
+            let renderer = Renderer::mixed(format, mixer.clone());
+            let backend = self.backend.clone();
+            let id = device_id.to_string();
+            // Opening blocks until the device plays
+            let opened = tokio::task::spawn_blocking(move || backend.play(&id, renderer))
+                .await
+                .unwrap_or_else(|e| Err(VoiceboxError::internal(format!("The device open panicked: {}", e))));
+            match opened {
+                Ok(()) => break,
+                Err(e) if !retried => {
+                    warn!("Failed to open {}, retrying: {}", device_id, e);
+                    METRICS.playback_open_retries.inc();
+                    retried = true;
+                    tokio::time::sleep(OPEN_RETRY_DELAY).await;
+                }
+                // Dropping the mixer finishes the clip on this device
+                Err(e) => return Err(e),
+            }
+        }
+        *stream = Some(mixer);
+        Ok(DeviceResult {
+            device_id: device_id.to_string(),
+            reused_stream: false,
+            retried,
+            superseded: false,
+            degraded_profile: false,
+            error: None,
+        })
+    }
+
+    /// Decode a clip in whichever container it comes in, as far as
+    /// symphonia reads it. A failure names the container the data looks
+    /// like.
+    fn decode(&self, data: &[u8]) -> Result<audio_import::DecodedAudio, VoiceboxError> {
+        audio_import::decode(data, None).map_err(|e| {
+            warn!("Failed to decode {} bytes of audio: {}", data.len(), e);
+            VoiceboxError::invalid_argument(match audio_import::sniff_container(data) {
+                Some(container) => format!("Couldn't play the {} audio: {}", container, e),
+                None => format!("Couldn't play the audio, which isn't WAV, MP3, FLAC, Ogg or M4A: {}", e),
+            })
+        })
+    }
+
+}
+
+#[cfg(feature = "native-backends")]
+impl Default for AudioOutputState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Sums the clips playing on one device into the device's one stream, so a
+/// clip started while another plays joins it rather than opening a second.
+/// The stream closes once it has had no clips for STREAM_LINGER.
+struct Mixer {
+    device_id: String,
+    /// What the device's stream reports, in microseconds; u64::MAX until
+    /// it does.
+    latency: Arc<AtomicU64>,
+    mixed: Mutex<Mixed>,
+}
+
+struct Mixed {
+    renderers: Vec<Renderer>,
+    /// When the last clip finished, while there are none.
+    idle_since: Option<Instant>,
+    closed: bool,
+    scratch: Vec<f32>,
+}
+
+impl Mixer {
+    fn new(device_id: &str, renderer: Renderer, latency: Arc<AtomicU64>) -> Self {
+        Self {
+            device_id: device_id.to_string(),
+            latency,
+            mixed: Mutex::new(Mixed {
+                renderers: vec![renderer],
+                idle_since: None,
+                closed: false,
+                scratch: Vec::new(),
+            }),
+        }
+    }
+
+    /// Add a clip to the stream, or hand it back if the stream has closed.
+    fn join(&self, renderer: Renderer) -> Result<(), Box<Renderer>> {
+        let mut mixed = self.mixed.lock().unwrap();
+        if mixed.closed {
+            return Err(Box::new(renderer));
+        }
+        mixed.renderers.push(renderer);
+        mixed.idle_since = None;
+        Ok(())
+    }
+
+    /// The device went away: close the stream and end its clips as failed.
+    fn abandon(&self) {
+        let renderers = {
+            let mut mixed = self.mixed.lock().unwrap();
+            mixed.closed = true;
+            std::mem::take(&mut mixed.renderers)
+        };
+        for renderer in renderers {
+            renderer.playback.failed.store(true, Ordering::Relaxed);
+            let mut lost = renderer.playback.lost_devices.lock().unwrap();
+            if !lost.contains(&self.device_id) {
+                lost.push(self.device_id.clone());
+            }
+        }
+    }
+
+    /// Fill `out` with the sum of the clips. Once the stream has lingered
+    /// without any, it closes and this returns false.
+    fn pull(&self, out: &mut [f32]) -> bool {
+        out.fill(0.0);
+        let mut mixed = self.mixed.lock().unwrap();
+        let Mixed {
+            renderers,
+            idle_since,
+            closed,
+            scratch,
+        } = &mut *mixed;
+        if renderers.is_empty() {
+            *closed |= idle_since.get_or_insert_with(Instant::now).elapsed() >= STREAM_LINGER;
+        }
+        if *closed {
+            return false;
+        }
+        scratch.resize(out.len(), 0.0);
+        let latency = match self.latency.load(Ordering::Relaxed) {
+            u64::MAX => Duration::ZERO,
+            micros => Duration::from_micros(micros),
+        };
+        for renderer in renderers.iter_mut() {
+            renderer.latency = latency;
+            renderer.fill(scratch);
+            for (sample, value) in out.iter_mut().zip(scratch.iter()) {
+                *sample += value;
+            }
+        }
+        renderers.retain(|renderer| !renderer.is_done());
+        true
+    }
+}
+
+/// Feeds one device stream: the clip, already in the device's format, with
+/// gain and fades applied as frames are pulled.
+pub struct Renderer {
+    samples: Vec<f32>,
+    /// The frame `samples` starts at: 0, but for a file played as it
+    /// decodes, whose frames are dropped once played.
+    samples_from: usize,
+    /// Where `samples` is refilled from, for a file played as it decodes
+    /// or a playback stream.
+    stream: Option<StreamReader>,
+    /// Out of streamed frames after playing some, and playing silence.
+    starved: bool,
+    /// How many times the clip plays, and how many it has been through.
+    plays: Plays,
+    loops_done: u64,
+    /// Pulled from instead of `samples` for a live stream.
+    live: Option<Arc<dyn LiveSource>>,
+    /// Pulled from instead, as is, for a device's stream of clips.
+    mixer: Option<Arc<Mixer>>,
+    channels: usize,
+    sample_rate: u32,
+    /// In frames.
+    position: usize,
+    gain: f32,
+    /// How long a seek, pause or volume change ramps over: the fade in.
+    fade_frames: usize,
+    stop_fade_frames: usize,
+    crossfade_frames: usize,
+    /// Where the fade in starts, and how long it is.
+    fade_in_from: usize,
+    fade_in_frames: usize,
+    /// How long the clip's last time through ramps down to its end over.
+    end_fade_frames: usize,
+    /// Frames left in the fade after a stop or a handoff, and its length.
+    fade_out_left: Option<usize>,
+    fade_out_frames: usize,
+    /// Set when the playback moves off this device: it fades out over the
+    /// crossfade while the playback carries on on the other.
+    handoff: Arc<AtomicBool>,
+    /// The handoff flag of the renderer this one takes over from. On its
+    /// first frame this one jumps to where the playback has got to and sets
+    /// it, so the two fade at once.
+    takes_over: Option<Arc<AtomicBool>>,
+    /// 1.0 while playing, ramping to 0.0 on pause.
+    pause_level: f32,
+    /// Held silent until the playback's starts_at, to start with its other
+    /// devices.
+    awaits_start: bool,
+    /// How long the device takes to play what it pulls.
+    latency: Duration,
+    /// The playback's seek_generation this has jumped to.
+    seek_generation: u64,
+    /// The device's volume, as f32 bits, and the level ramping to it.
+    volume: Arc<AtomicU32>,
+    volume_level: f32,
+    /// Where what this sends the device is metered, for a clip's playback.
+    level: Option<Arc<LevelCounters>>,
+    playback: Arc<PlaybackShared>,
+    done: Arc<AtomicBool>,
+}
+
+impl Renderer {
+    fn new(samples: Vec<f32>, format: DeviceFormat, options: PlaybackOptions, playback: Arc<PlaybackShared>) -> Self {
+        let frames = |ms: u32| (format.sample_rate as u64 * ms as u64 / 1000) as usize;
+        let fade_frames = frames(options.fade_in_ms);
+        let (mut fade_in_frames, mut end_fade_frames) = (fade_frames, frames(options.fade_out_ms));
+        // Too short for both fades: they meet somewhere in the middle
+        let clip_frames = samples.len() / format.channels.max(1) as usize;
+        if clip_frames > 0 && fade_in_frames + end_fade_frames > clip_frames {
+            fade_in_frames = clip_frames * fade_in_frames / (fade_in_frames + end_fade_frames);
+            end_fade_frames = clip_frames - fade_in_frames;
+        }
+        let stop_fade_frames = frames(STOP_FADE_MS);
+        Self {
+            samples,
+            samples_from: 0,
+            stream: None,
+            starved: false,
+            plays: Plays::Once,
+            loops_done: 0,
+            live: None,
+            mixer: None,
+            channels: format.channels.max(1) as usize,
+            sample_rate: format.sample_rate.max(1),
+            position: 0,
+            gain: options.gain,
+            fade_frames,
+            stop_fade_frames,
+            crossfade_frames: frames(CROSSFADE_MS),
+            fade_in_from: 0,
+            fade_in_frames,
+            end_fade_frames,
+            fade_out_left: None,
+            fade_out_frames: stop_fade_frames,
+            handoff: Arc::new(AtomicBool::new(false)),
+            takes_over: None,
+            pause_level: 1.0,
+            awaits_start: false,
+            latency: Duration::ZERO,
+            seek_generation: playback.seek_generation.load(Ordering::Acquire),
+            volume: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            volume_level: 1.0,
+            level: None,
+            playback,
+            done: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// The stream of clips `mixer` sums, on a device of `format`.
+    fn mixed(format: DeviceFormat, mixer: Arc<Mixer>) -> Self {
+        let mut renderer = Self::new(Vec::new(), format, PlaybackOptions::default(), PlaybackShared::new(1));
+        renderer.mixer = Some(mixer);
+        renderer
+    }
+
+    /// Write the next interleaved frames into `out`; silence once done.
+    pub fn fill(&mut self, out: &mut [f32]) {
+        self.render(out);
+        if let Some(level) = &self.level {
+            level.measure(out);
+        }
+    }
+
+    fn render(&mut self, out: &mut [f32]) {
+        if let Some(mixer) = self.mixer.clone() {
+            if !mixer.pull(out) {
+                self.finish();
+            }
+            return;
+        }
+        if let Some(live) = self.live.clone() {
+            self.fill_live(&*live, out);
+            return;
+        }
+        if self.awaits_start {
+            // Stopped before it started: nothing to fade out
+            if self.playback.stop.load(Ordering::Relaxed) {
+                out.fill(0.0);
+                self.finish();
+                return;
+            }
+            let Some(starts_at) = self.playback.starts_at.get() else {
+                out.fill(0.0);
+                return;
+            };
+            // The frames of this period heard before then stay silent
+            let lead = starts_at.saturating_duration_since(Instant::now() + self.latency);
+            let lead = (lead.as_secs_f64() * self.sample_rate as f64) as usize * self.channels;
+            if lead >= out.len() {
+                out.fill(0.0);
+                return;
+            }
+            self.awaits_start = false;
+            let (silent, rest) = out.split_at_mut(lead);
+            silent.fill(0.0);
+            self.render(rest);
+            return;
+        }
+        if let Some(takes_over) = self.takes_over.take() {
+            let position_ms = self.playback.position_ms.load(Ordering::Relaxed);
+            self.position = (position_ms * self.sample_rate as u64 / 1000) as usize;
+            // Where a looped clip has got to counts every time through
+            let clip_frames = self.samples.len() / self.channels;
+            if self.plays != Plays::Once && clip_frames > 0 {
+                self.loops_done = (self.position / clip_frames) as u64;
+                self.position %= clip_frames;
+            }
+            self.fade_in_from = self.position;
+            self.fade_in_frames = self.crossfade_frames;
+            if self.playback.paused.load(Ordering::Relaxed) {
+                self.pause_level = 0.0;
+            }
+            takes_over.store(true, Ordering::Relaxed);
+        }
+        // A seek fades in from its frame, as a start does
+        let seek_generation = self.playback.seek_generation.load(Ordering::Acquire);
+        if seek_generation != self.seek_generation {
+            self.seek_generation = seek_generation;
+            let seek_ms = self.playback.seek_ms.load(Ordering::Relaxed);
+            self.position = (seek_ms * self.sample_rate as u64 / 1000) as usize;
+            self.fade_in_from = self.position;
+            self.fade_in_frames = self.fade_frames;
+        }
+        let ended = match self.stream.take() {
+            Some(reader) => {
+                let ended = self.refill(&reader, out.len() / self.channels);
+                self.stream = Some(reader);
+                ended
+            }
+            None => true,
+        };
+        let total_frames = self.samples_from + self.samples.len() / self.channels;
+        // Counted as they go, since a looped clip's position wraps
+        let mut frames_played = 0;
+        let pause_step = 1.0 / self.fade_frames.max(1) as f32;
+        let volume = f32::from_bits(self.volume.load(Ordering::Relaxed));
+        for frame in out.chunks_mut(self.channels) {
+            if self.fade_out_left.is_none() {
+                if self.playback.stop.load(Ordering::Relaxed) {
+                    self.fade_out_frames = self.stop_fade_frames;
+                    self.fade_out_left = Some(self.stop_fade_frames);
+                } else if self.handoff.load(Ordering::Relaxed) {
+                    self.fade_out_frames = self.crossfade_frames;
+                    self.fade_out_left = Some(self.crossfade_frames);
+                }
+            }
+            // A looped clip carries straight on from its start, without a fade
+            if self.position >= total_frames && total_frames > 0 && self.stream.is_none() && self.loops_again() {
+                self.position = 0;
+                self.loops_done += 1;
+                self.fade_in_frames = 0;
+            }
+            if self.fade_out_left == Some(0) || ended && self.position >= total_frames && self.drained() {
+                self.finish();
+                frame.fill(0.0);
+                continue;
+            }
+            // Waiting for the decoder or the next chunk, unless there's no need to any more
+            if self.position >= total_frames {
+                if self.fade_out_left.is_some() {
+                    self.finish();
+                } else if !self.starved && self.position > 0 {
+                    self.starved = true;
+                    self.playback.underruns.fetch_add(1, Ordering::Relaxed);
+                }
+                frame.fill(0.0);
+                continue;
+            }
+            // Fading back in, as after a seek
+            if self.starved {
+                self.starved = false;
+                self.fade_in_from = self.position;
+                self.fade_in_frames = self.fade_frames;
+            }
+            // A pause ramps down and holds the position; resuming ramps back up
+            if self.fade_out_left.is_none() {
+                self.pause_level = if self.playback.paused.load(Ordering::Relaxed) {
+                    (self.pause_level - pause_step).max(0.0)
+                } else {
+                    (self.pause_level + pause_step).min(1.0)
+                };
+            }
+            if self.pause_level == 0.0 {
+                // Nothing left to fade when stopped while paused
+                if self.fade_out_left.is_some() {
+                    self.finish();
+                }
+                frame.fill(0.0);
+                continue;
+            }
+
+            let mut level = self.gain * self.pause_level;
+            let faded_in = self.position - self.fade_in_from;
+            if faded_in < self.fade_in_frames {
+                level *= faded_in as f32 / self.fade_in_frames as f32;
+            }
+            // Down to the end of a clip's last time through; a stream's end isn't known ahead
+            let to_end = total_frames - 1 - self.position;
+            if to_end < self.end_fade_frames && self.stream.is_none() && !self.loops_again() {
+                level *= to_end as f32 / self.end_fade_frames as f32;
+            }
+            if let Some(left) = self.fade_out_left.as_mut() {
+                level *= *left as f32 / self.fade_out_frames.max(1) as f32;
+                *left -= 1;
+            }
+            // A volume change ramps over the fade, as a pause does
+            self.volume_level = if (volume - self.volume_level).abs() <= pause_step {
+                volume
+            } else {
+                self.volume_level + pause_step.copysign(volume - self.volume_level)
+            };
+            level *= self.volume_level;
+            let start = (self.position - self.samples_from) * self.channels;
+            for (sample, source) in frame.iter_mut().zip(&self.samples[start..start + self.channels]) {
+                *sample = if self.volume_level > 1.0 {
+                    soft_clip(source * level)
+                } else {
+                    source * level
+                };
+            }
+            self.position += 1;
+            frames_played += 1;
+        }
+        METRICS.frames_played.add(frames_played);
+        // Where this got to before a seek that came in meanwhile is stale
+        if self.playback.seek_generation.load(Ordering::Acquire) == self.seek_generation {
+            let played = self.loops_done * total_frames as u64 + self.position as u64;
+            let position_ms = played * 1000 / self.sample_rate as u64;
+            self.playback.position_ms.fetch_max(position_ms, Ordering::Relaxed);
+        }
+    }
+
+    /// Drop the frames played from `samples` and add what the file's
+    /// decoder has for the next `frames`, in the device's format. True once
+    /// the file has nothing more for this device.
+    fn refill(&mut self, reader: &StreamReader, frames: usize) -> bool {
+        let stream = &reader.stream;
+        let played = self.position.saturating_sub(self.samples_from).min(self.samples.len() / self.channels);
+        self.samples.drain(..played * self.channels);
+        self.samples_from += played;
+
+        let source_channels = stream.channels as usize;
+        // Interpolated between the two source frames around each device
+        // frame, as a clip is by convert
+        let ratio = stream.sample_rate as f64 / self.sample_rate as f64;
+        let source_frame = |frame: usize| (frame as u64 * stream.sample_rate as u64 / self.sample_rate as u64) as usize;
+        let mut next = self.samples_from + self.samples.len() / self.channels;
+        let wanted = self.position + frames;
+        let mut gathered = Vec::new();
+        let ended = {
+            let mut window = stream.window.lock().unwrap();
+            let available = window.first_frame + window.samples.len() / source_channels;
+            while next < wanted {
+                let frame = source_frame(next);
+                if frame >= available {
+                    break;
+                }
+                let frac = (next as f64 * ratio - frame as f64) as f32;
+                // The last frame has none after it to lean toward
+                let after = if frac == 0.0 || window.ended && frame + 1 == available {
+                    frame
+                } else if frame + 1 < available {
+                    frame + 1
+                } else {
+                    break;
+                };
+                let (start, after) = (
+                    frame.saturating_sub(window.first_frame) * source_channels,
+                    after.saturating_sub(window.first_frame) * source_channels,
+                );
+                gathered.extend((0..source_channels).map(|ch| {
+                    let (a, b) = (window.samples[start + ch], window.samples[after + ch]);
+                    a + (b - a) * frac
+                }));
+                next += 1;
+            }
+            window.readers[reader.reader] = Some(source_frame(self.position));
+            window.ended && next < wanted
+        };
+        stream.moved.notify_one();
+        self.samples.extend(map_channels(&gathered, stream.channels, self.channels as u16));
+        ended
+    }
+
+    /// Gain and fades over what the live source has, ending once a stop
+    /// has faded out. Pause doesn't apply: a live stream has no position
+    /// to hold.
+    fn fill_live(&mut self, live: &dyn LiveSource, out: &mut [f32]) {
+        if self.fade_out_left == Some(0) {
+            self.finish();
+            out.fill(0.0);
+            return;
+        }
+        live.pull(out);
+        let start_position = self.position;
+        for frame in out.chunks_mut(self.channels) {
+            if self.playback.stop.load(Ordering::Relaxed) && self.fade_out_left.is_none() {
+                self.fade_out_left = Some(self.stop_fade_frames);
+            }
+            let mut level = self.gain;
+            if self.position < self.fade_in_frames {
+                level *= self.position as f32 / self.fade_in_frames as f32;
+            }
+            if let Some(left) = self.fade_out_left.as_mut() {
+                if *left == 0 {
+                    frame.fill(0.0);
+                    continue;
+                }
+                level *= *left as f32 / self.stop_fade_frames.max(1) as f32;
+                *left -= 1;
+            }
+            for sample in frame {
+                *sample *= level;
+            }
+            self.position += 1;
+        }
+        METRICS.frames_played.add((self.position - start_position) as u64);
+        if self.fade_out_left == Some(0) {
+            self.finish();
+        }
+    }
+
+    /// Whether a looped clip has another time through to play.
+    fn loops_again(&self) -> bool {
+        match self.plays {
+            Plays::Once => false,
+            Plays::Times(times) => self.loops_done + 1 < times as u64,
+            Plays::UntilStopped => true,
+        }
+    }
+
+    /// Whether a streamed source that has ended has nothing past where this
+    /// is, given up on under the window's lock so a clip queued meanwhile
+    /// isn't missed. Always for a clip.
+    fn drained(&self) -> bool {
+        let Some(reader) = &self.stream else {
+            return true;
+        };
+        let stream = &reader.stream;
+        let frame = (self.position as u64 * stream.sample_rate as u64 / self.sample_rate as u64) as usize;
+        let mut window = stream.window.lock().unwrap();
+        let available = window.first_frame + window.samples.len() / stream.channels as usize;
+        if !window.ended || frame < available {
+            return false;
+        }
+        window.readers[reader.reader] = None;
+        true
+    }
+
+    fn finish(&mut self) {
+        if self.done.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        if let Some(reader) = &self.stream {
+            reader.stream.move_reader(reader.reader, None);
+        }
+        if self.playback.active.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.playback.finished.notify_waiters();
+        }
+    }
+
+    /// For a backend to call before a fill, with how long the device takes
+    /// to play what it pulls, as its stream reports it.
+    pub fn set_latency(&mut self, latency: Duration) {
+        match &self.mixer {
+            Some(mixer) => mixer.latency.store(latency.as_micros() as u64, Ordering::Relaxed),
+            None => self.latency = latency,
+        }
+    }
+
+    /// True once the clip has played out, or faded out after a stop.
+    pub fn is_done(&self) -> bool {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    #[cfg_attr(not(feature = "native-backends"), allow(dead_code))]
+    fn done_flag(&self) -> Arc<AtomicBool> {
+        self.done.clone()
+    }
+
+    /// For a backend to call once the device has gone away, from wherever
+    /// it hears of it: the clips on its stream end as failed, and the
+    /// stream is done.
+    fn on_device_lost(&self) -> impl Fn() + Send + 'static {
+        let (mixer, done) = (self.mixer.clone(), self.done.clone());
+        move || {
+            if let Some(mixer) = &mixer {
+                mixer.abandon();
+            }
+            done.store(true, Ordering::Relaxed);
+        }
+    }
 }
 
-pub struct AudioOutputState {
+impl Drop for Renderer {
+    /// A stream that goes away early, e.g. its device was unplugged, no
+    /// longer keeps the playback going.
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// A device volume as play_audio_to_devices and set_playback_volume take it.
+fn check_volume(volume: f32) -> Result<(), VoiceboxError> {
+    if (0.0..=MAX_DEVICE_VOLUME).contains(&volume) {
+        Ok(())
+    } else {
+        Err(VoiceboxError::invalid_argument(format!(
+            "Volume {} isn't between 0 and {}",
+            volume, MAX_DEVICE_VOLUME
+        )))
+    }
+}
+
+/// Leaves a sample alone up to SOFT_CLIP_KNEE, then bends it smoothly
+/// towards full scale so it never goes past.
+fn soft_clip(sample: f32) -> f32 {
+    const SOFT_CLIP_KNEE: f32 = 0.8;
+    let magnitude = sample.abs();
+    if magnitude <= SOFT_CLIP_KNEE {
+        return sample;
+    }
+    let headroom = 1.0 - SOFT_CLIP_KNEE;
+    let bent = SOFT_CLIP_KNEE + headroom * ((magnitude - SOFT_CLIP_KNEE) / headroom).tanh();
+    bent.copysign(sample)
+}
+
+/// Decoded frames in a device's format: interpolated to its rate, then fit
+/// to its channels. Each device gets its own conversion, as targets of one
+/// playback can run at different rates.
+fn convert(samples: &[f32], channels: u16, sample_rate: u32, format: DeviceFormat) -> Vec<f32> {
+    let resampled = audio_util::resample(samples, channels, sample_rate, format.sample_rate);
+    map_channels(&resampled, channels, format.channels)
+}
+
+/// A mono sine at TEST_TONE_AMPLITUDE, fading in and out over
+/// TEST_TONE_FADE_MS so it starts and stops without a click.
+fn test_tone(sample_rate: u32, duration_secs: f32, frequency: f32) -> Vec<f32> {
+    let frames = (sample_rate as f32 * duration_secs) as usize;
+    let fade = ((sample_rate * TEST_TONE_FADE_MS / 1000) as usize).min(frames / 2).max(1);
+    (0..frames)
+        .map(|n| {
+            let gain = (n.min(frames - 1 - n) as f32 / fade as f32).min(1.0);
+            let phase = std::f64::consts::TAU * frequency as f64 * n as f64 / sample_rate as f64;
+            TEST_TONE_AMPLITUDE * gain * phase.sin() as f32
+        })
+        .collect()
+}
+
+/// Fit frames to the device's channel count: a mono device gets the average,
+/// and extra device channels repeat the clip's last channel (mono plays on
+/// both sides of a stereo device).
+pub(crate) fn map_channels(samples: &[f32], src_channels: u16, dst_channels: u16) -> Vec<f32> {
+    if src_channels == dst_channels {
+        return samples.to_vec();
+    }
+    let (src, dst) = (src_channels as usize, dst_channels as usize);
+    let mut mapped = Vec::with_capacity(samples.len() / src * dst);
+    for frame in samples.chunks_exact(src) {
+        if dst == 1 {
+            mapped.push(frame.iter().sum::<f32>() / src as f32);
+        } else {
+            mapped.extend((0..dst).map(|ch| frame[ch.min(src - 1)]));
+        }
+    }
+    mapped
+}
+
+/// The system's output devices, through cpal.
+#[cfg(feature = "native-backends")]
+pub struct CpalBackend {
     host: Host,
-    stop_flag: Arc<AtomicBool>,
 }
 
-impl AudioOutputState {
+#[cfg(feature = "native-backends")]
+impl CpalBackend {
     pub fn new() -> Self {
         Self {
             host: cpal::default_host(),
-            stop_flag: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    pub fn stop_all_playback(&self) -> Result<(), String> {
-        eprintln!("stop_all_playback: Setting stop flag");
-        self.stop_flag.store(true, Ordering::Relaxed);
-        eprintln!("stop_all_playback: Stop flag set - active streams will output silence");
-        Ok(())
+    fn find_device(&self, device_id: &str) -> Result<Device, VoiceboxError> {
+        self.host
+            .output_devices()
+            .map_err(|e| VoiceboxError::playback(format!("Failed to enumerate devices: {}", e)))?
+            .find(|device| device.name().is_ok_and(|name| device_id_for(&name) == device_id))
+            .ok_or_else(|| VoiceboxError::not_found(format!("No output device {}", device_id)))
+    }
+}
+
+#[cfg(feature = "native-backends")]
+impl Default for CpalBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A stable ID from the device name (cpal doesn't provide stable IDs).
+#[cfg(feature = "native-backends")]
+fn device_id_for(name: &str) -> String {
+    format!("device_{}", name.replace(' ', "_").to_lowercase())
+}
+
+/// An output device as the system lists it.
+#[cfg(feature = "native-backends")]
+// Only listed on Windows and macOS
+#[cfg_attr(not(any(windows, target_os = "macos")), allow(dead_code))]
+struct Endpoint {
+    id: String,
+    name: String,
+    state: DeviceState,
+}
+
+/// The render endpoints in every state but removed, from the MMDevice API.
+#[cfg(all(feature = "native-backends", windows))]
+mod endpoints {
+    use super::{DeviceState, Endpoint};
+    use tracing::debug;
+    use wasapi::DeviceEnumerator;
+    use windows::Win32::Media::Audio::{
+        eRender, IMMDeviceEnumerator, MMDeviceEnumerator, DEVICE_STATE, DEVICE_STATE_ACTIVE, DEVICE_STATE_DISABLED,
+        DEVICE_STATE_UNPLUGGED,
+    };
+    use windows::Win32::System::Com::{
+        CoCreateInstance, CoInitializeEx, CoTaskMemFree, CoUninitialize, CLSCTX_ALL, COINIT_MULTITHREADED,
+    };
+
+    pub(super) fn list() -> Vec<Endpoint> {
+        unsafe {
+            let hr = CoInitializeEx(None, COINIT_MULTITHREADED);
+            if hr.is_err() {
+                debug!("Failed to initialize COM to list the endpoints: {:?}", hr);
+                return Vec::new();
+            }
+        }
+        let _com_guard = scopeguard::guard((), |_| unsafe {
+            CoUninitialize();
+        });
+        let states = DEVICE_STATE(DEVICE_STATE_ACTIVE.0 | DEVICE_STATE_DISABLED.0 | DEVICE_STATE_UNPLUGGED.0);
+        let ids = unsafe {
+            CoCreateInstance::<_, IMMDeviceEnumerator>(&MMDeviceEnumerator, None, CLSCTX_ALL)
+                .and_then(|enumerator| enumerator.EnumAudioEndpoints(eRender, states))
+                .and_then(|devices| {
+                    (0..devices.GetCount()?)
+                        .map(|index| {
+                            let id = devices.Item(index)?.GetId()?;
+                            let text = id.to_string().unwrap_or_default();
+                            CoTaskMemFree(Some(id.0 as *const _));
+                            Ok(text)
+                        })
+                        .collect::<windows::core::Result<Vec<String>>>()
+                })
+        };
+        let (ids, enumerator) = match (ids, DeviceEnumerator::new()) {
+            (Ok(ids), Ok(enumerator)) => (ids, enumerator),
+            (Err(e), _) => {
+                debug!("Failed to list the endpoints: {}", e);
+                return Vec::new();
+            }
+            (_, Err(e)) => {
+                debug!("Failed to list the endpoints: {}", e);
+                return Vec::new();
+            }
+        };
+        ids.into_iter()
+            .filter_map(|id| {
+                let device = enumerator.get_device(&id).ok()?;
+                let state = match device.get_state().ok()? {
+                    wasapi::DeviceState::Active => DeviceState::Active,
+                    wasapi::DeviceState::Disabled => DeviceState::Disabled,
+                    wasapi::DeviceState::Unplugged => DeviceState::Unplugged,
+                    wasapi::DeviceState::NotPresent => return None,
+                };
+                Some(Endpoint {
+                    name: device.get_friendlyname().unwrap_or_else(|_| id.clone()),
+                    id,
+                    state,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The devices with output streams, from the HAL. macOS only lists devices
+/// that are there, so every one is active.
+#[cfg(all(feature = "native-backends", target_os = "macos"))]
+mod endpoints {
+    use super::{DeviceState, Endpoint};
+    use coreaudio_sys::{
+        kAudioDevicePropertyDeviceUID, kAudioDevicePropertyStreams, kAudioHardwarePropertyDevices,
+        kAudioObjectPropertyName, kAudioObjectPropertyScopeGlobal, kAudioObjectPropertyScopeOutput,
+        kAudioObjectSystemObject, AudioObjectGetPropertyData, AudioObjectGetPropertyDataSize, AudioObjectID,
+        AudioObjectPropertyAddress,
+    };
+    use core_foundation_sys::base::CFRelease;
+    use core_foundation_sys::string::{kCFStringEncodingUTF8, CFStringGetCString, CFStringRef};
+    use std::ffi::{c_char, c_void, CStr};
+
+    /// kAudioObjectPropertyElementMain, renamed across SDKs.
+    const ELEMENT_MAIN: u32 = 0;
+
+    fn address(selector: u32, scope: u32) -> AudioObjectPropertyAddress {
+        AudioObjectPropertyAddress {
+            mSelector: selector,
+            mScope: scope,
+            mElement: ELEMENT_MAIN,
+        }
+    }
+
+    fn data_size(object: AudioObjectID, selector: u32, scope: u32) -> Option<u32> {
+        let mut size = 0;
+        let address = address(selector, scope);
+        let status = unsafe { AudioObjectGetPropertyDataSize(object, &address, 0, std::ptr::null(), &mut size) };
+        (status == 0).then_some(size)
+    }
+
+    /// A CFString property, released once copied out.
+    fn string(object: AudioObjectID, selector: u32) -> Option<String> {
+        let mut value: CFStringRef = std::ptr::null();
+        let mut size = std::mem::size_of::<CFStringRef>() as u32;
+        let status = unsafe {
+            AudioObjectGetPropertyData(
+                object,
+                &address(selector, kAudioObjectPropertyScopeGlobal),
+                0,
+                std::ptr::null(),
+                &mut size,
+                &mut value as *mut CFStringRef as *mut c_void,
+            )
+        };
+        if status != 0 || value.is_null() {
+            return None;
+        }
+        let mut buffer = [0 as c_char; 512];
+        let copied = unsafe {
+            let copied = CFStringGetCString(value, buffer.as_mut_ptr(), buffer.len() as _, kCFStringEncodingUTF8);
+            CFRelease(value as *const c_void);
+            copied
+        };
+        (copied != 0).then(|| unsafe { CStr::from_ptr(buffer.as_ptr()) }.to_string_lossy().into_owned())
+    }
+
+    pub(super) fn list() -> Vec<Endpoint> {
+        let global = kAudioObjectPropertyScopeGlobal;
+        let Some(size) = data_size(kAudioObjectSystemObject, kAudioHardwarePropertyDevices, global) else {
+            return Vec::new();
+        };
+        let mut devices = vec![0 as AudioObjectID; size as usize / std::mem::size_of::<AudioObjectID>()];
+        let mut size = (devices.len() * std::mem::size_of::<AudioObjectID>()) as u32;
+        let status = unsafe {
+            AudioObjectGetPropertyData(
+                kAudioObjectSystemObject,
+                &address(kAudioHardwarePropertyDevices, global),
+                0,
+                std::ptr::null(),
+                &mut size,
+                devices.as_mut_ptr() as *mut c_void,
+            )
+        };
+        if status != 0 {
+            return Vec::new();
+        }
+        devices.truncate(size as usize / std::mem::size_of::<AudioObjectID>());
+        devices
+            .into_iter()
+            // Inputs have no output streams
+            .filter(|&device| {
+                let streams = data_size(device, kAudioDevicePropertyStreams, kAudioObjectPropertyScopeOutput);
+                streams.is_some_and(|size| size > 0)
+            })
+            .filter_map(|device| {
+                Some(Endpoint {
+                    id: string(device, kAudioDevicePropertyDeviceUID)?,
+                    name: string(device, kAudioObjectPropertyName)?,
+                    state: DeviceState::Active,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Nothing beyond what cpal lists.
+#[cfg(all(feature = "native-backends", not(any(windows, target_os = "macos"))))]
+mod endpoints {
+    use super::Endpoint;
+
+    pub(super) fn list() -> Vec<Endpoint> {
+        Vec::new()
+    }
+}
+
+#[cfg(feature = "native-backends")]
+impl OutputBackend for CpalBackend {
+    fn name(&self) -> &'static str {
+        "cpal"
     }
 
-    pub fn list_output_devices(&self) -> Result<Vec<AudioOutputDevice>, String> {
+    fn list_devices(&self) -> Result<Vec<AudioOutputDevice>, VoiceboxError> {
         let devices = self
             .host
             .output_devices()
-            .map_err(|e| format!("Failed to enumerate output devices: {}", e))?;
+            .map_err(|e| VoiceboxError::playback(format!("Failed to enumerate output devices: {}", e)))?;
 
         let default_device = self.host.default_output_device();
+        let mut endpoints = endpoints::list();
 
         let mut result = Vec::new();
         for device in devices {
             let name = device
                 .name()
-                .map_err(|e| format!("Failed to get device name: {}", e))?;
-
-            // Generate a stable ID from the device name (cpal doesn't provide stable IDs)
-            let id = format!("device_{}", name.replace(' ', "_").to_lowercase());
+                .map_err(|e| VoiceboxError::playback(format!("Failed to get device name: {}", e)))?;
 
             let is_default = default_device
                 .as_ref()
                 .map(|d| d.name().unwrap_or_default() == name)
                 .unwrap_or(false);
+            let config = device
+                .default_output_config()
+                .map_err(|e| debug!("list_devices: No default config for {}: {}", name, e))
+                .ok();
+            // cpal and the system APIs only share the name
+            let endpoint = endpoints
+                .iter()
+                .position(|endpoint| endpoint.state == DeviceState::Active && endpoint.name == name)
+                .map(|index| endpoints.remove(index));
 
             result.push(AudioOutputDevice {
-                id,
+                id: device_id_for(&name),
+                transport: DeviceTransport::from_name(&name),
                 name,
                 is_default,
+                state: DeviceState::Active,
+                default_sample_rate: config.as_ref().map_or(0, |config| config.sample_rate().0),
+                channels: config.as_ref().map_or(0, |config| config.channels()),
+                endpoint_id: endpoint.map(|endpoint| endpoint.id),
+            });
+        }
+
+        // cpal only lists the devices that can play
+        for endpoint in endpoints.into_iter().filter(|endpoint| endpoint.state != DeviceState::Active) {
+            result.push(AudioOutputDevice {
+                id: device_id_for(&endpoint.name),
+                transport: DeviceTransport::from_name(&endpoint.name),
+                name: endpoint.name,
+                is_default: false,
+                state: endpoint.state,
+                default_sample_rate: 0,
+                channels: 0,
+                endpoint_id: Some(endpoint.id),
             });
         }
 
         Ok(result)
     }
 
-    pub async fn play_audio_to_devices(
-        &self,
-        audio_data: Vec<u8>,
-        device_ids: Vec<String>,
-    ) -> Result<(), String> {
-        eprintln!("play_audio_to_devices called with {} bytes, {} device IDs", audio_data.len(), device_ids.len());
-        eprintln!("Requested device IDs: {:?}", device_ids);
-        
-        // Decode audio file (assuming WAV format)
-        eprintln!("Decoding audio data...");
-        let (samples, sample_rate, channels) = self.decode_wav(&audio_data)?;
-        eprintln!("Audio decoded: {} samples, {}Hz, {} channels", samples.len(), sample_rate, channels);
-
-        // Find devices by ID
-        eprintln!("Enumerating output devices...");
-        let devices: Vec<Device> = self
-            .host
-            .output_devices()
-            .map_err(|e| format!("Failed to enumerate devices: {}", e))?
-            .filter_map(|device| {
-                let name = device.name().ok()?;
-                let id = format!("device_{}", name.replace(' ', "_").to_lowercase());
-                eprintln!("Found device: {} (id: {})", name, id);
-                if device_ids.contains(&id) {
-                    eprintln!("  -> Matched! Will play to this device");
-                    Some(device)
-                } else {
-                    None
+    fn device_format(&self, device_id: &str) -> Result<DeviceFormat, VoiceboxError> {
+        let config = self
+            .find_device(device_id)?
+            .default_output_config()
+            .map_err(|e| VoiceboxError::playback(format!("Failed to get default config: {}", e)))?;
+        Ok(DeviceFormat {
+            sample_rate: config.sample_rate().0,
+            channels: config.channels(),
+        })
+    }
+
+    fn play(&self, device_id: &str, renderer: Renderer) -> Result<(), VoiceboxError> {
+        let device = self.find_device(device_id)?;
+        let done = renderer.done_flag();
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+        // A cpal stream stops when dropped and can't leave the thread that
+        // built it, so this thread holds it until the clip is done
+        std::thread::Builder::new()
+            .name("audio-output".to_string())
+            .spawn(move || {
+                let stream = match build_stream(&device, renderer) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                if let Err(e) = stream.play() {
+                    error!("play_to_device: Failed to play stream: {}", e);
+                    let _ = ready_tx.send(Err(format!("Failed to play stream: {}", e)));
+                    return;
+                }
+                let _ = ready_tx.send(Ok(()));
+                while !done.load(Ordering::Relaxed) {
+                    std::thread::sleep(Duration::from_millis(50));
                 }
+                // Let the device play out what it already pulled
+                std::thread::sleep(Duration::from_millis(200));
+                debug!("play_to_device: Playback finished, closing the stream");
             })
-            .collect();
+            .map_err(|e| VoiceboxError::playback(format!("Failed to start the playback thread: {}", e)))?;
+        ready_rx
+            .recv()
+            .map_err(|_| VoiceboxError::playback("The playback thread exited before the stream started"))?
+            .map_err(VoiceboxError::playback)
+    }
+}
 
-        if devices.is_empty() {
-            eprintln!("ERROR: No matching devices found");
-            return Err("No matching devices found".to_string());
+#[cfg(feature = "native-backends")]
+fn build_stream(device: &Device, mut renderer: Renderer) -> Result<cpal::Stream, String> {
+    let config = device
+        .default_output_config()
+        .map_err(|e| format!("Failed to get default config: {}", e))?;
+    debug!(
+        "play_to_device: Device config - {}Hz, {} channels, format: {:?}",
+        config.sample_rate().0,
+        config.channels(),
+        config.sample_format()
+    );
+    let stream_config = StreamConfig {
+        channels: config.channels(),
+        sample_rate: config.sample_rate(),
+        buffer_size: cpal::BufferSize::Default,
+    };
+    let device_id = device.name().ok().map(|name| device_id_for(&name));
+    let device_lost = renderer.on_device_lost();
+    let err_fn = move |err: cpal::StreamError| {
+        error!("Playback error: {}", err);
+        audio_log::record(AudioEvent::DeviceError {
+            device_id: device_id.clone(),
+            error: err.to_string(),
+        });
+        match err {
+            cpal::StreamError::DeviceNotAvailable => device_lost(),
+            cpal::StreamError::BackendSpecific { .. } => METRICS.playback_underruns.inc(),
         }
+    };
 
-        eprintln!("Playing to {} device(s)", devices.len());
-        
-        // Stop any existing playback first
-        self.stop_all_playback().ok();
-        
-        // Reset stop flag for new playback
-        self.stop_flag.store(false, Ordering::Relaxed);
-        
-        // Play to each device
-        for (i, device) in devices.iter().enumerate() {
-            let device_name = device.name().unwrap_or_else(|_| "unknown".to_string());
-            eprintln!("Playing to device {}/{}: {}", i + 1, devices.len(), device_name);
-            self.play_to_device(device, samples.clone(), sample_rate, channels, self.stop_flag.clone())
-                .map_err(|e| format!("Failed to play to device {}: {}", device_name, e))?;
-            eprintln!("Successfully started playback on device: {}", device_name);
+    let stream = match config.sample_format() {
+        SampleFormat::F32 => device.build_output_stream(
+            &stream_config,
+            move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+                report_latency(&mut renderer, info);
+                renderer.fill(data)
+            },
+            err_fn,
+            None,
+        ),
+        SampleFormat::I16 => {
+            let mut scratch = Vec::new();
+            device.build_output_stream(
+                &stream_config,
+                move |data: &mut [i16], info: &cpal::OutputCallbackInfo| {
+                    report_latency(&mut renderer, info);
+                    scratch.resize(data.len(), 0.0);
+                    renderer.fill(&mut scratch);
+                    for (sample, value) in data.iter_mut().zip(&scratch) {
+                        *sample = (value * 32767.0) as i16;
+                    }
+                },
+                err_fn,
+                None,
+            )
+        }
+        SampleFormat::U16 => {
+            let mut scratch = Vec::new();
+            device.build_output_stream(
+                &stream_config,
+                move |data: &mut [u16], info: &cpal::OutputCallbackInfo| {
+                    report_latency(&mut renderer, info);
+                    scratch.resize(data.len(), 0.0);
+                    renderer.fill(&mut scratch);
+                    for (sample, value) in data.iter_mut().zip(&scratch) {
+                        *sample = ((value + 1.0) * 32767.5) as u16;
+                    }
+                },
+                err_fn,
+                None,
+            )
         }
+        _ => return Err("Unsupported sample format".to_string()),
+    };
+    stream.map_err(|e| format!("Failed to build stream: {}", e))
+}
 
-        eprintln!("play_audio_to_devices completed successfully");
-        Ok(())
+/// From when cpal asked for the frames to when the first is heard.
+#[cfg(feature = "native-backends")]
+fn report_latency(renderer: &mut Renderer, info: &cpal::OutputCallbackInfo) {
+    let timestamp = info.timestamp();
+    if let Some(latency) = timestamp.playback.duration_since(&timestamp.callback) {
+        renderer.set_latency(latency);
     }
+}
 
-    fn decode_wav(&self, data: &[u8]) -> Result<(Vec<f32>, u32, u16), String> {
-        use symphonia::core::formats::FormatOptions;
-        use symphonia::core::io::MediaSourceStream;
-        use symphonia::core::meta::MetadataOptions;
+/// One period a NullSink pulled.
+#[derive(Debug, Clone)]
+pub struct RecordedPeriod {
+    pub device_id: String,
+    /// Since that device's playback started.
+    pub at: Duration,
+    /// When it was pulled, to line devices up by.
+    pub pulled_at: Instant,
+    pub frames: Vec<f32>,
+}
 
-        eprintln!("decode_wav: Creating MediaSourceStream from {} bytes", data.len());
-        let mss = MediaSourceStream::new(
-            Box::new(std::io::Cursor::new(data.to_vec())),
-            Default::default(),
-        );
+/// An output that plays nowhere: it pulls frames the way a device would, a
+/// period at a time in real time, and records them. For tests.
+pub struct NullSink {
+    format: DeviceFormat,
+    devices: Vec<AudioOutputDevice>,
+    period: Duration,
+    recorded: Arc<Mutex<Vec<RecordedPeriod>>>,
+    open_delay: Duration,
+    /// Opens left to fail.
+    failures: AtomicUsize,
+    /// Devices being opened, to refuse a second open of one as busy the
+    /// way a real endpoint might.
+    opening: Mutex<Vec<String>>,
+    /// Device ids of the streams opened, in order.
+    opened: Mutex<Vec<String>>,
+    /// Formats devices report before falling back to `format`, one a query.
+    queued_formats: Mutex<HashMap<String, VecDeque<DeviceFormat>>>,
+    /// Device ids of the format queries, in order.
+    format_queries: Mutex<Vec<String>>,
+    /// Devices unplugged by lose_device.
+    lost: Arc<Mutex<Vec<String>>>,
+    /// What devices report as their latency, by device id; none otherwise.
+    latencies: HashMap<String, Duration>,
+}
 
-        eprintln!("decode_wav: Probing audio format...");
-        let mut format = symphonia::default::get_probe()
-            .format(
-                &Default::default(),
-                mss,
-                &FormatOptions::default(),
-                &MetadataOptions::default(),
-            )
-            .map_err(|e| {
-                eprintln!("decode_wav: Failed to probe audio: {}", e);
-                format!("Failed to probe audio: {}", e)
-            })?
-            .format;
-        
-        eprintln!("decode_wav: Audio format probed successfully");
+impl NullSink {
+    /// One default device, `device_null`, pulling 10 ms periods.
+    pub fn new(format: DeviceFormat) -> Self {
+        Self {
+            format,
+            devices: vec![AudioOutputDevice {
+                id: "device_null".to_string(),
+                name: "Null".to_string(),
+                is_default: true,
+                transport: DeviceTransport::Unknown,
+                state: DeviceState::Active,
+                default_sample_rate: format.sample_rate,
+                channels: format.channels,
+                endpoint_id: None,
+            }],
+            period: Duration::from_millis(10),
+            recorded: Arc::new(Mutex::new(Vec::new())),
+            open_delay: Duration::ZERO,
+            failures: AtomicUsize::new(0),
+            opening: Mutex::new(Vec::new()),
+            opened: Mutex::new(Vec::new()),
+            queued_formats: Mutex::new(HashMap::new()),
+            format_queries: Mutex::new(Vec::new()),
+            lost: Arc::new(Mutex::new(Vec::new())),
+            latencies: HashMap::new(),
+        }
+    }
 
-        eprintln!("decode_wav: Finding audio track...");
-        let track = format
-            .tracks()
-            .iter()
-            .find(|t| t.codec_params.codec != symphonia::core::codecs::CODEC_TYPE_NULL)
-            .ok_or_else(|| {
-                eprintln!("decode_wav: No audio track found");
-                "No audio track found".to_string()
-            })?;
+    /// Take `delay` to open a device, as real ones do.
+    pub fn with_open_delay(mut self, delay: Duration) -> Self {
+        self.open_delay = delay;
+        self
+    }
 
-        let sample_rate = track
-            .codec_params
-            .sample_rate
-            .ok_or_else(|| {
-                eprintln!("decode_wav: No sample rate found in track");
-                "No sample rate found".to_string()
-            })?;
+    /// Have `device_id`'s streams report `latency`, as a device with a
+    /// deeper buffer does.
+    pub fn with_latency(mut self, device_id: &str, latency: Duration) -> Self {
+        self.latencies.insert(device_id.to_string(), latency);
+        self
+    }
 
-        let channels = track
-            .codec_params
-            .channels
-            .ok_or_else(|| {
-                eprintln!("decode_wav: No channels found in track");
-                "No channels found".to_string()
-            })?
-            .count() as u16;
+    /// Fail the next `count` opens.
+    pub fn fail_opens(&self, count: usize) {
+        self.failures.store(count, Ordering::Relaxed);
+    }
 
-        eprintln!("decode_wav: Track info - sample_rate: {}, channels: {}", sample_rate, channels);
+    /// Unplug `device_id`: its open stream stops pulling and reports the
+    /// device gone, as cpal's error callback does.
+    pub fn lose_device(&self, device_id: &str) {
+        self.lost.lock().unwrap().push(device_id.to_string());
+    }
 
-        eprintln!("decode_wav: Creating decoder...");
-        let mut decoder = symphonia::default::get_codecs()
-            .make(&track.codec_params, &Default::default())
-            .map_err(|e| {
-                eprintln!("decode_wav: Failed to create decoder: {}", e);
-                format!("Failed to create decoder: {}", e)
-            })?;
-        
-        eprintln!("decode_wav: Decoder created successfully");
+    /// Streams opened on `device_id` so far.
+    pub fn opens(&self, device_id: &str) -> usize {
+        self.opened.lock().unwrap().iter().filter(|id| *id == device_id).count()
+    }
 
-        let mut samples = Vec::new();
-        let mut packet_count = 0;
-        eprintln!("decode_wav: Starting packet decoding loop...");
-        loop {
-            let packet = match format.next_packet() {
-                Ok(packet) => packet,
-                Err(e) => {
-                    eprintln!("decode_wav: End of stream or error: {:?}", e);
-                    break;
-                }
-            };
+    /// Replace the devices, named after their ids; the first is the
+    /// default.
+    pub fn with_devices(mut self, ids: &[&str]) -> Self {
+        self.devices = ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| AudioOutputDevice {
+                id: id.to_string(),
+                name: id.to_string(),
+                is_default: i == 0,
+                transport: DeviceTransport::from_name(id),
+                state: DeviceState::Active,
+                default_sample_rate: self.format.sample_rate,
+                channels: self.format.channels,
+                endpoint_id: None,
+            })
+            .collect();
+        self
+    }
 
-            packet_count += 1;
-            let decoded = decoder
-                .decode(&packet)
-                .map_err(|e| {
-                    eprintln!("decode_wav: Decode error on packet {}: {}", packet_count, e);
-                    format!("Decode error: {}", e)
-                })?;
-
-            // Convert to f32 samples by matching on the buffer type
-            use symphonia::core::audio::{AudioBufferRef, Signal};
-            use symphonia::core::conv::FromSample;
-
-            let spec = *decoded.spec();
-            let num_channels = spec.channels.count();
-            let num_frames = decoded.frames();
-
-            eprintln!("decode_wav: Packet {} - {} frames, {} channels", packet_count, num_frames, num_channels);
-
-            // Interleave samples from all channels
-            for frame_idx in 0..num_frames {
-                for ch in 0..num_channels {
-                    let sample_f32 = match &decoded {
-                        AudioBufferRef::U8(buf) => f32::from_sample(buf.chan(ch)[frame_idx]),
-                        AudioBufferRef::U16(buf) => f32::from_sample(buf.chan(ch)[frame_idx]),
-                        AudioBufferRef::U24(buf) => f32::from_sample(buf.chan(ch)[frame_idx]),
-                        AudioBufferRef::U32(buf) => f32::from_sample(buf.chan(ch)[frame_idx]),
-                        AudioBufferRef::S8(buf) => f32::from_sample(buf.chan(ch)[frame_idx]),
-                        AudioBufferRef::S16(buf) => f32::from_sample(buf.chan(ch)[frame_idx]),
-                        AudioBufferRef::S24(buf) => f32::from_sample(buf.chan(ch)[frame_idx]),
-                        AudioBufferRef::S32(buf) => f32::from_sample(buf.chan(ch)[frame_idx]),
-                        AudioBufferRef::F32(buf) => buf.chan(ch)[frame_idx],
-                        AudioBufferRef::F64(buf) => buf.chan(ch)[frame_idx] as f32,
-                    };
-                    samples.push(sample_f32);
-                }
-            }
+    /// Put `device_id` in `state`, as the sound settings or its jack would.
+    pub fn with_device_state(mut self, device_id: &str, state: DeviceState) -> Self {
+        if let Some(device) = self.devices.iter_mut().find(|device| device.id == device_id) {
+            device.state = state;
         }
-
-        eprintln!("decode_wav: Decoded {} packets, total {} samples", packet_count, samples.len());
-        eprintln!("decode_wav: Returning sample_rate={}, channels={}", sample_rate, channels);
-        Ok((samples, sample_rate, channels))
+        self
     }
 
-    fn play_to_device(
-        &self,
-        device: &Device,
-        samples: Vec<f32>,
-        sample_rate: u32,
-        channels: u16,
-        stop_flag: Arc<AtomicBool>,
-    ) -> Result<(), String> {
-        let device_name = device.name().unwrap_or_else(|_| "unknown".to_string());
-        eprintln!("play_to_device: Starting playback to device: {}", device_name);
-        eprintln!("play_to_device: Input - {} samples, {}Hz, {} channels", samples.len(), sample_rate, channels);
-        
-        let config = device
-            .default_output_config()
-            .map_err(|e| format!("Failed to get default config: {}", e))?;
-
-        // Prepare samples for the device's format
-        let device_sample_rate = config.sample_rate().0;
-        let device_channels = config.channels();
-        let device_sample_format = config.sample_format();
-        
-        eprintln!("play_to_device: Device config - {}Hz, {} channels, format: {:?}", 
-                  device_sample_rate, device_channels, device_sample_format);
-
-        // Resample if needed (simple linear interpolation for now)
-        let resampled = if device_sample_rate != sample_rate {
-            eprintln!("play_to_device: Resampling from {}Hz to {}Hz", sample_rate, device_sample_rate);
-            let result = self.resample(&samples, sample_rate, device_sample_rate);
-            eprintln!("play_to_device: Resampled {} samples to {} samples", samples.len(), result.len());
-            result
-        } else {
-            eprintln!("play_to_device: No resampling needed");
-            samples
-        };
-
-        // Interleave/convert channels if needed
-        eprintln!("play_to_device: Interleaving channels from {} to {} channels", channels, device_channels);
-        let interleaved = self.interleave_channels(&resampled, channels, device_channels);
-        eprintln!("play_to_device: Interleaved to {} samples", interleaved.len());
-
-        // Create shared buffer for playback
-        let buffer: Arc<Mutex<Vec<f32>>> = Arc::new(Mutex::new(interleaved));
-        let position = Arc::new(AtomicUsize::new(0));
-        let buffer_clone = buffer.clone();
-        let position_clone = position.clone();
-
-        let err_fn = |err| eprintln!("Playback error: {}", err);
-
-        let stream_config = StreamConfig {
-            channels: device_channels,
-            sample_rate: cpal::SampleRate(device_sample_rate),
-            buffer_size: cpal::BufferSize::Default,
-        };
-
-        let stop_flag_clone = stop_flag.clone();
-        let stream = match config.sample_format() {
-            SampleFormat::F32 => {
-                let buffer = buffer_clone.clone();
-                let pos = position_clone.clone();
-                device
-                    .build_output_stream(
-                        &stream_config,
-                        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                            // Check stop flag - if set, output silence
-                            if stop_flag_clone.load(Ordering::Relaxed) {
-                                for sample in data.iter_mut() {
-                                    *sample = 0.0;
-                                }
-                                return;
-                            }
-                            
-                            let mut idx = pos.load(Ordering::Relaxed);
-                            let buf = buffer.lock().unwrap();
-                            for sample in data.iter_mut() {
-                                if idx < buf.len() {
-                                    *sample = buf[idx];
-                                    idx += 1;
-                                } else {
-                                    *sample = 0.0;
-                                }
-                            }
-                            pos.store(idx, Ordering::Relaxed);
-                        },
-                        err_fn,
-                        None,
-                    )
-                    .map_err(|e| format!("Failed to build stream: {}", e))?
-            }
-            SampleFormat::I16 => {
-                let buffer = buffer_clone.clone();
-                let pos = position_clone.clone();
-                device
-                    .build_output_stream(
-                        &stream_config,
-                        move |data: &mut [i16], _: &cpal::OutputCallbackInfo| {
-                            // Check stop flag - if set, output silence
-                            if stop_flag_clone.load(Ordering::Relaxed) {
-                                for sample in data.iter_mut() {
-                                    *sample = 0;
-                                }
-                                return;
-                            }
-                            
-                            let mut idx = pos.load(Ordering::Relaxed);
-                            let buf = buffer.lock().unwrap();
-                            for sample in data.iter_mut() {
-                                if idx < buf.len() {
-                                    *sample = (buf[idx] * 32767.0) as i16;
-                                    idx += 1;
-                                } else {
-                                    *sample = 0;
-                                }
-                            }
-                            pos.store(idx, Ordering::Relaxed);
-                        },
-                        err_fn,
-                        None,
-                    )
-                    .map_err(|e| format!("Failed to build stream: {}", e))?
-            }
-            SampleFormat::U16 => {
-                let buffer = buffer_clone.clone();
-                let pos = position_clone.clone();
-                device
-                    .build_output_stream(
-                        &stream_config,
-                        move |data: &mut [u16], _: &cpal::OutputCallbackInfo| {
-                            // Check stop flag - if set, output silence
-                            if stop_flag_clone.load(Ordering::Relaxed) {
-                                for sample in data.iter_mut() {
-                                    *sample = 32768;
-                                }
-                                return;
-                            }
-                            
-                            let mut idx = pos.load(Ordering::Relaxed);
-                            let buf = buffer.lock().unwrap();
-                            for sample in data.iter_mut() {
-                                if idx < buf.len() {
-                                    *sample = ((buf[idx] + 1.0) * 32767.5) as u16;
-                                    idx += 1;
-                                } else {
-                                    *sample = 32768;
-                                }
-                            }
-                            pos.store(idx, Ordering::Relaxed);
-                        },
-                        err_fn,
-                        None,
-                    )
-                    .map_err(|e| format!("Failed to build stream: {}", e))?
-            }
-            _ => return Err("Unsupported sample format".to_string()),
-        };
+    /// Have `device_id` report `formats`, one per query, before going back
+    /// to the sink's own, as a device switching profiles does.
+    pub fn queue_formats(&self, device_id: &str, formats: &[DeviceFormat]) {
+        self.queued_formats
+            .lock()
+            .unwrap()
+            .entry(device_id.to_string())
+            .or_default()
+            .extend(formats);
+    }
 
-        eprintln!("play_to_device: Starting stream playback...");
-        stream.play().map_err(|e| {
-            eprintln!("play_to_device: Failed to play stream: {}", e);
-            format!("Failed to play stream: {}", e)
-        })?;
-        
-        eprintln!("play_to_device: Stream started successfully");
+    /// Times `device_id` was asked for its format so far.
+    pub fn format_queries(&self, device_id: &str) -> usize {
+        self.format_queries.lock().unwrap().iter().filter(|id| *id == device_id).count()
+    }
 
-        eprintln!("play_to_device: Function completed successfully");
-        Ok(())
+    pub fn recorded(&self) -> Vec<RecordedPeriod> {
+        self.recorded.lock().unwrap().clone()
     }
 
-    fn resample(&self, samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
-        if from_rate == to_rate {
-            return samples.to_vec();
-        }
+    /// Everything `device_id` was fed, in order, including the silence it
+    /// pulled after the clip ended within the last period.
+    pub fn samples(&self, device_id: &str) -> Vec<f32> {
+        self.recorded
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|period| period.device_id == device_id)
+            .flat_map(|period| period.frames.iter().copied())
+            .collect()
+    }
+}
 
-        let ratio = to_rate as f64 / from_rate as f64;
-        let new_len = (samples.len() as f64 * ratio) as usize;
-        let mut resampled = Vec::with_capacity(new_len);
+impl OutputBackend for NullSink {
+    fn name(&self) -> &'static str {
+        "null"
+    }
 
-        for i in 0..new_len {
-            let src_idx = (i as f64 / ratio) as usize;
-            if src_idx < samples.len() {
-                resampled.push(samples[src_idx]);
-            } else {
-                resampled.push(0.0);
-            }
-        }
+    fn list_devices(&self) -> Result<Vec<AudioOutputDevice>, VoiceboxError> {
+        Ok(self.devices.clone())
+    }
 
-        resampled
+    fn device_format(&self, device_id: &str) -> Result<DeviceFormat, VoiceboxError> {
+        self.format_queries.lock().unwrap().push(device_id.to_string());
+        let queued = self
+            .queued_formats
+            .lock()
+            .unwrap()
+            .get_mut(device_id)
+            .and_then(|formats| formats.pop_front());
+        Ok(queued.unwrap_or(self.format))
     }
 
-    fn interleave_channels(
-        &self,
-        samples: &[f32],
-        src_channels: u16,
-        dst_channels: u16,
-    ) -> Vec<f32> {
-        if src_channels == dst_channels {
-            return samples.to_vec();
-        }
-
-        let mut interleaved = Vec::new();
-        let samples_per_channel = samples.len() / src_channels as usize;
-
-        for i in 0..samples_per_channel {
-            for ch in 0..dst_channels {
-                let src_ch = if ch < src_channels { ch } else { src_channels - 1 };
-                let idx = (i * src_channels as usize) + src_ch as usize;
-                if idx < samples.len() {
-                    interleaved.push(samples[idx]);
-                } else {
-                    interleaved.push(0.0);
-                }
+    fn play(&self, device_id: &str, mut renderer: Renderer) -> Result<(), VoiceboxError> {
+        {
+            let mut opening = self.opening.lock().unwrap();
+            if opening.iter().any(|id| id == device_id) {
+                return Err(VoiceboxError::busy(format!("{} is already being opened", device_id)));
             }
+            opening.push(device_id.to_string());
         }
+        std::thread::sleep(self.open_delay);
+        let failed = self
+            .failures
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| left.checked_sub(1))
+            .is_ok();
+        self.opening.lock().unwrap().retain(|id| id != device_id);
+        if failed {
+            return Err(VoiceboxError::playback(format!("{} failed to open", device_id)));
+        }
+        self.opened.lock().unwrap().push(device_id.to_string());
 
-        interleaved
-    }
-}
-
-impl Default for AudioOutputState {
-    fn default() -> Self {
-        Self::new()
+        let period_frames = (renderer.sample_rate as u128 * self.period.as_millis() / 1000) as usize;
+        let (period, recorded, device_id) = (self.period, self.recorded.clone(), device_id.to_string());
+        let (lost, device_lost) = (self.lost.clone(), renderer.on_device_lost());
+        let latency = self.latencies.get(&device_id).copied();
+        std::thread::Builder::new()
+            .name("null-output".to_string())
+            .spawn(move || {
+                let started = Instant::now();
+                let mut pulled: u32 = 0;
+                while !renderer.is_done() {
+                    if lost.lock().unwrap().contains(&device_id) {
+                        device_lost();
+                        break;
+                    }
+                    let (at, pulled_at) = (started.elapsed(), Instant::now());
+                    let mut frames = vec![0.0; period_frames * renderer.channels()];
+                    if let Some(latency) = latency {
+                        renderer.set_latency(latency);
+                    }
+                    renderer.fill(&mut frames);
+                    recorded.lock().unwrap().push(RecordedPeriod {
+                        device_id: device_id.clone(),
+                        at,
+                        pulled_at,
+                        frames,
+                    });
+                    pulled += 1;
+                    if let Some(wait) = (period * pulled).checked_sub(started.elapsed()) {
+                        std::thread::sleep(wait);
+                    }
+                }
+            })
+            .map(|_| ())
+            .map_err(|e| VoiceboxError::playback(format!("Failed to start the null output thread: {}", e)))
     }
 }
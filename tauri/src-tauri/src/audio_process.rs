@@ -0,0 +1,239 @@
+use crate::audio_capture::{DEFAULT_SILENCE_THRESHOLD, NORMALIZE_PEAK};
+use crate::audio_export::{self, ExportError, ExportFormat};
+use crate::audio_import;
+use crate::audio_util::{self, EncodeSpec, WavSampleFormat};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+pub use crate::audio_util::MAX_SAMPLE_RATE;
+
+/// Cut the silence before the first and after the last sound.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct TrimSilence {
+    /// Peak level under which a frame is silence, by default
+    /// DEFAULT_SILENCE_THRESHOLD.
+    #[serde(default = "default_threshold")]
+    pub threshold: f32,
+    /// Silence kept on each side.
+    #[serde(default)]
+    pub padding_ms: u32,
+}
+
+/// Scale so the loudest sample reaches `peak`, as a capture's normalize does.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct Normalize {
+    #[serde(default = "default_peak")]
+    pub peak: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct Resample {
+    pub sample_rate: u32,
+}
+
+/// The file the result is saved as.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct ConvertFormat {
+    pub format: ExportFormat,
+    /// 16-bit without one. FLAC is only 16-bit.
+    #[serde(default)]
+    pub sample_format: Option<WavSampleFormat>,
+}
+
+fn default_threshold() -> f32 {
+    DEFAULT_SILENCE_THRESHOLD
+}
+
+fn default_peak() -> f32 {
+    NORMALIZE_PEAK
+}
+
+/// One step of process_audio. Steps run in the order given, each on the
+/// previous one's result, so the same list on the same audio always gives
+/// the same file: Normalize then TrimSilence measures silence after the
+/// gain, TrimSilence then Normalize before it.
+///
+/// ConvertFormat rounds the samples to its sample format where it stands,
+/// so later steps see what that format can store, and sets the output
+/// format; the last one wins. Without any, the result is 16-bit WAV.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AudioOp {
+    TrimSilence(TrimSilence),
+    Normalize(Normalize),
+    Resample(Resample),
+    /// Average the channels into one.
+    Downmix,
+    ConvertFormat(ConvertFormat),
+}
+
+impl AudioOp {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AudioOp::TrimSilence(_) => "trim_silence",
+            AudioOp::Normalize(_) => "normalize",
+            AudioOp::Resample(_) => "resample",
+            AudioOp::Downmix => "downmix",
+            AudioOp::ConvertFormat(_) => "convert_format",
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            AudioOp::TrimSilence(trim) if !(0.0..=1.0).contains(&trim.threshold) => {
+                Err("The silence threshold must be between 0 and 1".to_string())
+            }
+            AudioOp::Normalize(normalize) if !(normalize.peak > 0.0 && normalize.peak <= 1.0) => {
+                Err("The normalize peak must be above 0 and at most 1".to_string())
+            }
+            AudioOp::Resample(resample) if resample.sample_rate == 0 || resample.sample_rate > MAX_SAMPLE_RATE => {
+                Err(format!("The sample rate must be between 1 and {} Hz", MAX_SAMPLE_RATE))
+            }
+            AudioOp::ConvertFormat(ConvertFormat {
+                format: ExportFormat::Flac,
+                sample_format: Some(WavSampleFormat::Int24 | WavSampleFormat::Float32),
+            }) => Err("FLAC output is only 16-bit".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Payload of the `audio-process-progress` event, sent as each step starts.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessProgress {
+    /// From 1.
+    pub step: usize,
+    pub steps: usize,
+    /// `decode`, an op's name, or `encode`.
+    pub stage: &'static str,
+}
+
+/// Where process_audio saves the result.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProcessOutput {
+    /// A new file in the imports library, named after its content.
+    Library,
+    /// A path of the user's choosing, replaced if it exists.
+    Path { path: PathBuf },
+}
+
+/// The encoded result, not yet saved.
+#[derive(Debug, Clone)]
+pub struct Processed {
+    pub data: Vec<u8>,
+    pub format: ExportFormat,
+    pub sample_format: WavSampleFormat,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub frames: u64,
+}
+
+/// Result of process_audio.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessedAudio {
+    pub path: PathBuf,
+    pub format: ExportFormat,
+    pub sample_format: WavSampleFormat,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub duration_ms: u64,
+}
+
+/// Check every op before any work, so a bad parameter late in the list
+/// doesn't cost a decode.
+pub fn validate(ops: &[AudioOp]) -> Result<(), ExportError> {
+    for op in ops {
+        op.validate().map_err(|message| ExportError::InvalidSource { message })?;
+    }
+    Ok(())
+}
+
+/// Decode `data`, run `ops` over it and encode the result, all in memory.
+/// Blocking.
+pub fn process(
+    data: &[u8],
+    extension: Option<&str>,
+    ops: &[AudioOp],
+    progress: &mut dyn FnMut(ProcessProgress),
+) -> Result<Processed, ExportError> {
+    validate(ops)?;
+    let steps = ops.len() + 2;
+    let mut report = |step: usize, stage: &'static str| progress(ProcessProgress { step, steps, stage });
+
+    report(1, "decode");
+    let decoded = audio_import::decode(data, extension).map_err(|e| ExportError::InvalidSource {
+        message: format!("Source audio could not be decoded: {}", e),
+    })?;
+    let mut samples = decoded.samples;
+    let mut sample_rate = decoded.sample_rate;
+    let mut channels = decoded.channels;
+    let mut output = (ExportFormat::Wav, WavSampleFormat::Int16);
+
+    for (i, op) in ops.iter().enumerate() {
+        report(i + 2, op.name());
+        match op {
+            AudioOp::TrimSilence(trim) => {
+                let padding = (sample_rate as u64 * trim.padding_ms as u64 / 1000) as usize;
+                let kept = audio_util::trim_silence(&samples, channels, trim.threshold, padding);
+                if kept.is_empty() {
+                    return Err(ExportError::InvalidSource {
+                        message: "The audio is all silence; trimming would leave nothing".to_string(),
+                    });
+                }
+                samples = kept.to_vec();
+            }
+            AudioOp::Normalize(normalize) => audio_util::normalize(&mut samples, normalize.peak),
+            AudioOp::Resample(resample) => {
+                samples = audio_util::resample(&samples, channels, sample_rate, resample.sample_rate);
+                sample_rate = resample.sample_rate;
+            }
+            AudioOp::Downmix => {
+                if channels > 1 {
+                    samples = audio_util::downmix(&samples, channels);
+                    channels = 1;
+                }
+            }
+            AudioOp::ConvertFormat(convert) => {
+                let sample_format = convert.sample_format.unwrap_or(WavSampleFormat::Int16);
+                audio_util::quantize(&mut samples, sample_format);
+                output = (convert.format, sample_format);
+            }
+        }
+    }
+
+    report(steps, "encode");
+    let (format, sample_format) = output;
+    let spec = EncodeSpec::pcm16(sample_rate, channels).with_format(sample_format);
+    let data = audio_export::encode(&samples, spec, format)?;
+    Ok(Processed {
+        data,
+        format,
+        sample_format,
+        sample_rate,
+        channels,
+        frames: samples.len() as u64 / channels.max(1) as u64,
+    })
+}
+
+/// Write the result where `output` says. Either the whole file lands or
+/// nothing does. Blocking.
+pub fn save(processed: Processed, output: &ProcessOutput, data_dir: &Path) -> Result<ProcessedAudio, ExportError> {
+    let path = match output {
+        ProcessOutput::Library => audio_import::store(data_dir, &processed.data, processed.format.extension())?,
+        ProcessOutput::Path { path } => {
+            audio_export::write_export(path, &processed.data)?;
+            path.clone()
+        }
+    };
+    info!("Processed audio saved to {}", path.display());
+    Ok(ProcessedAudio {
+        path,
+        format: processed.format,
+        sample_format: processed.sample_format,
+        sample_rate: processed.sample_rate,
+        channels: processed.channels,
+        duration_ms: processed.frames * 1000 / processed.sample_rate.max(1) as u64,
+    })
+}
@@ -0,0 +1,450 @@
+use crate::audio_capture::{self, CaptureBackend, FrameSink};
+use crate::audio_log::{self, AudioEvent};
+use crate::audio_output::{self, AudioOutputState, DeviceFormat, LiveSource, LiveStream, PlaybackOptions};
+use crate::error::VoiceboxError;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+pub const MIN_GAIN_DB: f32 = -60.0;
+pub const MAX_GAIN_DB: f32 = 24.0;
+
+/// The buffer between capture and output starts at this much audio, grows a
+/// step on each underrun up to MAX_BUFFER_MS and shrinks back while none
+/// happen. With the capture's own chunks on top that stays under ~100 ms.
+const START_BUFFER_MS: u32 = 40;
+pub const MIN_BUFFER_MS: u32 = 20;
+pub const MAX_BUFFER_MS: u32 = 80;
+const BUFFER_STEP_MS: u32 = 10;
+
+/// How far past the target the buffer may fill, e.g. from a burst of capture
+/// chunks, before the oldest audio is dropped as an overrun.
+const HEADROOM_MS: u32 = 30;
+
+/// Played without an underrun for this long, the buffer shrinks a step.
+const SHRINK_AFTER_MS: u32 = 10_000;
+
+/// What a route captures.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CaptureSourceSpec {
+    /// Everything playing on the default output device, as the app's
+    /// captures record it.
+    System,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteState {
+    Running,
+    /// The capture failed or the output stream ended; stop_route cleans up.
+    Failed,
+}
+
+/// Payload of start_audio_route and get_route_status.
+#[derive(Debug, Clone, Serialize)]
+#[non_exhaustive]
+pub struct RouteStatus {
+    pub route_id: u64,
+    pub source: CaptureSourceSpec,
+    pub destination_device_id: String,
+    pub gain_db: f32,
+    pub state: RouteState,
+    pub error: Option<String>,
+    /// Once the capture has delivered frames.
+    pub source_sample_rate: Option<u32>,
+    pub destination_sample_rate: u32,
+    /// What the buffer currently aims to hold.
+    pub buffer_target_ms: u32,
+    pub buffered_ms: u32,
+    pub underruns: u64,
+    pub overruns: u64,
+    /// Gaps the capture backend reported.
+    pub dropouts: u64,
+}
+
+/// A snapshot of a RouteBuffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferStats {
+    pub target_ms: u32,
+    pub buffered_ms: u32,
+    pub underruns: u64,
+    pub overruns: u64,
+    pub source_sample_rate: Option<u32>,
+}
+
+/// The bounded buffer between a route's capture and its output stream.
+/// Captured frames are converted to the destination's format on the way in,
+/// so the output side only copies.
+pub struct RouteBuffer {
+    format: DeviceFormat,
+    state: Mutex<BufferState>,
+}
+
+struct BufferState {
+    /// Interleaved, in the destination's format.
+    samples: VecDeque<f32>,
+    target_frames: usize,
+    /// Refilling to the target, first or after an underrun, with silence
+    /// played meanwhile.
+    priming: bool,
+    /// Frames played since the last underrun or shrink.
+    steady_frames: usize,
+    underruns: u64,
+    overruns: u64,
+    source: Option<(u32, u16)>,
+    resampler: Resampler,
+}
+
+impl RouteBuffer {
+    pub fn new(format: DeviceFormat) -> Self {
+        let format = DeviceFormat {
+            sample_rate: format.sample_rate.max(1),
+            channels: format.channels.max(1),
+        };
+        let buffer = Self {
+            format,
+            state: Mutex::new(BufferState {
+                samples: VecDeque::new(),
+                target_frames: 0,
+                priming: true,
+                steady_frames: 0,
+                underruns: 0,
+                overruns: 0,
+                source: None,
+                resampler: Resampler::default(),
+            }),
+        };
+        buffer.state.lock().unwrap().target_frames = buffer.frames_for(START_BUFFER_MS);
+        buffer
+    }
+
+    fn frames_for(&self, ms: u32) -> usize {
+        (self.format.sample_rate as u64 * ms as u64 / 1000) as usize
+    }
+
+    fn ms_for(&self, frames: usize) -> u32 {
+        (frames as u64 * 1000 / self.format.sample_rate as u64) as u32
+    }
+
+    /// Add captured frames, in whatever format the capture delivers.
+    pub fn push(&self, frames: &[f32], sample_rate: u32, channels: u16) {
+        if frames.is_empty() || sample_rate == 0 || channels == 0 {
+            return;
+        }
+        let dst_channels = self.format.channels as usize;
+        let mapped = audio_output::map_channels(frames, channels, self.format.channels);
+        let mut state = self.state.lock().unwrap();
+        if state.source != Some((sample_rate, channels)) {
+            state.source = Some((sample_rate, channels));
+            state.resampler = Resampler::default();
+        }
+        let mut converted = Vec::with_capacity(mapped.len());
+        state
+            .resampler
+            .process(&mapped, dst_channels, sample_rate, self.format.sample_rate, &mut converted);
+        state.samples.extend(converted);
+
+        let limit = state.target_frames + self.frames_for(HEADROOM_MS);
+        let buffered = state.samples.len() / dst_channels;
+        if buffered > limit {
+            let excess = buffered - state.target_frames;
+            state.samples.drain(..excess * dst_channels);
+            state.overruns += 1;
+            drop(state);
+            audio_log::record(AudioEvent::RouteOverrun {
+                dropped_ms: self.ms_for(excess),
+            });
+        }
+    }
+
+    pub fn stats(&self) -> BufferStats {
+        let state = self.state.lock().unwrap();
+        BufferStats {
+            target_ms: self.ms_for(state.target_frames),
+            buffered_ms: self.ms_for(state.samples.len() / self.format.channels as usize),
+            underruns: state.underruns,
+            overruns: state.overruns,
+            source_sample_rate: state.source.map(|(rate, _)| rate),
+        }
+    }
+}
+
+impl LiveSource for RouteBuffer {
+    fn pull(&self, out: &mut [f32]) {
+        let channels = self.format.channels as usize;
+        let wanted = out.len() / channels;
+        let mut state = self.state.lock().unwrap();
+        let buffered = state.samples.len() / channels;
+        if state.priming {
+            if buffered < state.target_frames {
+                out.fill(0.0);
+                return;
+            }
+            state.priming = false;
+        }
+
+        let taken = wanted.min(buffered) * channels;
+        for (sample, value) in out.iter_mut().zip(state.samples.drain(..taken)) {
+            *sample = value;
+        }
+        out[taken..].fill(0.0);
+        if buffered < wanted {
+            state.underruns += 1;
+            state.priming = true;
+            state.steady_frames = 0;
+            let grown = state.target_frames + self.frames_for(BUFFER_STEP_MS);
+            state.target_frames = grown.min(self.frames_for(MAX_BUFFER_MS));
+            let target_ms = self.ms_for(state.target_frames);
+            drop(state);
+            audio_log::record(AudioEvent::RouteUnderrun { target_ms });
+            return;
+        }
+
+        state.steady_frames += wanted;
+        let min_frames = self.frames_for(MIN_BUFFER_MS);
+        if state.steady_frames >= self.frames_for(SHRINK_AFTER_MS) && state.target_frames > min_frames {
+            let step = self.frames_for(BUFFER_STEP_MS);
+            state.target_frames = state.target_frames.saturating_sub(step).max(min_frames);
+            state.steady_frames = 0;
+            // Drop what the smaller target no longer needs, or the latency stays
+            let excess = (state.samples.len() / channels).saturating_sub(state.target_frames).min(step);
+            state.samples.drain(..excess * channels);
+        }
+    }
+}
+
+/// Linear resampling across chunk boundaries.
+#[derive(Default)]
+struct Resampler {
+    /// The previous chunk's last frame.
+    last: Vec<f32>,
+    /// Where the next output frame falls, in input frames after `last`.
+    phase: f64,
+}
+
+impl Resampler {
+    fn process(&mut self, input: &[f32], channels: usize, from_rate: u32, to_rate: u32, out: &mut Vec<f32>) {
+        if from_rate == to_rate {
+            out.extend_from_slice(input);
+            return;
+        }
+        let frames = input.len() / channels;
+        if frames == 0 {
+            return;
+        }
+        if self.last.is_empty() {
+            // Start on the chunk's first frame
+            self.last = input[..channels].to_vec();
+            self.phase = 1.0;
+        }
+        let step = from_rate as f64 / to_rate as f64;
+        // Frame 0 is `last`, the chunk's frames follow
+        let frame = |i: usize| if i == 0 { &self.last[..] } else { &input[(i - 1) * channels..i * channels] };
+        let mut position = self.phase;
+        while position < frames as f64 {
+            let i = position as usize;
+            let t = (position - i as f64) as f32;
+            let (a, b) = (frame(i), frame(i + 1));
+            out.extend(a.iter().zip(b).map(|(a, b)| a + (b - a) * t));
+            position += step;
+        }
+        self.phase = position - frames as f64;
+        self.last = input[(frames - 1) * channels..frames * channels].to_vec();
+    }
+}
+
+struct Route {
+    source: CaptureSourceSpec,
+    destination_device_id: String,
+    destination_sample_rate: u32,
+    gain_db: f32,
+    buffer: Arc<RouteBuffer>,
+    sink: FrameSink,
+    stop: Arc<AtomicBool>,
+    stream: LiveStream,
+}
+
+impl Route {
+    fn status(&self, route_id: u64) -> RouteStatus {
+        let stats = self.buffer.stats();
+        let error = self.sink.failure().or_else(|| {
+            self.stream
+                .is_finished()
+                .then(|| format!("The output stream on {} ended", self.destination_device_id))
+        });
+        RouteStatus {
+            route_id,
+            source: self.source.clone(),
+            destination_device_id: self.destination_device_id.clone(),
+            gain_db: self.gain_db,
+            state: if error.is_some() { RouteState::Failed } else { RouteState::Running },
+            error,
+            source_sample_rate: stats.source_sample_rate,
+            destination_sample_rate: self.destination_sample_rate,
+            buffer_target_ms: stats.target_ms,
+            buffered_ms: stats.buffered_ms,
+            underruns: stats.underruns,
+            overruns: stats.overruns,
+            dropouts: self.sink.dropouts(),
+        }
+    }
+
+    fn shut_down(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.stream.stop();
+    }
+}
+
+/// Live routes from a capture straight to an output device. Any number can
+/// run, apart from the app's own capture and playback.
+pub struct AudioRoutes {
+    backend: Arc<dyn CaptureBackend>,
+    routes: Mutex<HashMap<u64, Route>>,
+    next_id: AtomicU64,
+}
+
+impl AudioRoutes {
+    /// Captures with audio_capture::default_backend().
+    pub fn new() -> Self {
+        Self::with_backend(audio_capture::default_backend())
+    }
+
+    pub fn with_backend(backend: Arc<dyn CaptureBackend>) -> Self {
+        Self {
+            backend,
+            routes: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Start forwarding `source` to a device of `output`, scaled by `gain_db`.
+    /// The device that source is captured from is refused: the route would
+    /// capture its own output.
+    pub fn start(
+        &self,
+        output: &AudioOutputState,
+        source: CaptureSourceSpec,
+        destination_device_id: &str,
+        gain_db: f32,
+    ) -> Result<RouteStatus, VoiceboxError> {
+        if !(MIN_GAIN_DB..=MAX_GAIN_DB).contains(&gain_db) {
+            return Err(VoiceboxError::invalid_argument(format!(
+                "The gain must be between {} and {} dB",
+                MIN_GAIN_DB, MAX_GAIN_DB
+            )));
+        }
+        let destination = output
+            .list_output_devices()?
+            .into_iter()
+            .find(|device| device.id == destination_device_id)
+            .ok_or_else(|| VoiceboxError::not_found(format!("No output device {}", destination_device_id)))?;
+        match source {
+            CaptureSourceSpec::System if destination.is_default => {
+                return Err(VoiceboxError::invalid_argument(format!(
+                    "System audio is captured from {}, so routing it there would feed back",
+                    destination.name
+                )));
+            }
+            CaptureSourceSpec::System => {}
+        }
+
+        let format = output.device_format(&destination.id)?;
+        let buffer = Arc::new(RouteBuffer::new(format));
+        let options = PlaybackOptions {
+            gain: 10f32.powf(gain_db / 20.0),
+            ..PlaybackOptions::default()
+        };
+        let stream = output.play_live(&destination.id, format, buffer.clone(), options)?;
+        let fed = buffer.clone();
+        let sink = FrameSink::forwarding(Arc::new(move |frames, sample_rate, channels| {
+            fed.push(frames, sample_rate, channels)
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+        if let Err(e) = self.backend.start(sink.clone(), stop.clone()) {
+            stop.store(true, Ordering::Relaxed);
+            stream.stop();
+            return Err(e);
+        }
+
+        let route_id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        info!(
+            "Audio route {} started: {:?} through {} to {} at {} dB",
+            route_id,
+            source,
+            self.backend.name(),
+            destination.id,
+            gain_db
+        );
+        let route = Route {
+            source,
+            destination_device_id: destination.id,
+            destination_sample_rate: format.sample_rate,
+            gain_db,
+            buffer,
+            sink,
+            stop,
+            stream,
+        };
+        let status = route.status(route_id);
+        self.routes.lock().unwrap().insert(route_id, route);
+        audio_log::record(AudioEvent::RouteStarted {
+            route_id,
+            destination_device_id: status.destination_device_id.clone(),
+            gain_db,
+        });
+        Ok(status)
+    }
+
+    /// Stop a route, returning its final status.
+    pub fn stop(&self, route_id: u64) -> Result<RouteStatus, VoiceboxError> {
+        let route = self
+            .routes
+            .lock()
+            .unwrap()
+            .remove(&route_id)
+            .ok_or_else(|| VoiceboxError::not_found(format!("There is no audio route {}", route_id)))?;
+        let status = route.status(route_id);
+        route.shut_down();
+        audio_log::record(AudioEvent::RouteStopped {
+            route_id,
+            underruns: status.underruns,
+            overruns: status.overruns,
+        });
+        info!("Audio route {} stopped", route_id);
+        Ok(status)
+    }
+
+    /// The devices routes are playing to.
+    pub fn destinations(&self) -> Vec<String> {
+        self.routes
+            .lock()
+            .unwrap()
+            .values()
+            .map(|route| route.destination_device_id.clone())
+            .collect()
+    }
+
+    pub fn stop_all(&self) {
+        for (_, route) in self.routes.lock().unwrap().drain() {
+            route.shut_down();
+        }
+    }
+
+    pub fn status(&self, route_id: u64) -> Result<RouteStatus, VoiceboxError> {
+        self.routes
+            .lock()
+            .unwrap()
+            .get(&route_id)
+            .map(|route| route.status(route_id))
+            .ok_or_else(|| VoiceboxError::not_found(format!("There is no audio route {}", route_id)))
+    }
+}
+
+impl Default for AudioRoutes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,151 @@
+use crate::audio_capture::{self, AudioCaptureState};
+use crate::audio_output::{AudioOutputState, PlaybackOptions};
+use crate::error::VoiceboxError;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+pub const TONE_HZ: f32 = 1000.0;
+const TONE_RATE: u32 = 48000;
+const TONE_MS: u32 = 500;
+const TONE_AMPLITUDE: f32 = 0.3;
+
+/// Lets the capture deliver its first frames before the tone starts, so the
+/// moment playback starts is inside the capture.
+const SETTLE: Duration = Duration::from_millis(300);
+
+/// How long after playback starts to keep listening for the tone. A capture
+/// that isn't permitted delivers silence or nothing, and ends the test here.
+pub const LISTEN_TIMEOUT: Duration = Duration::from_secs(3);
+
+const POLL: Duration = Duration::from_millis(50);
+
+/// Goertzel window for finding the tone's onset; the latency's resolution.
+const WINDOW_MS: u32 = 5;
+
+/// Tone level under which a window doesn't count, about -46 dBFS.
+const MIN_LEVEL: f32 = 0.005;
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SelfTestReport {
+    pub device_id: String,
+    pub capture_backend: &'static str,
+    /// Whether the tone came back through loopback capture.
+    pub tone_detected: bool,
+    /// From starting playback to the tone arriving in the capture.
+    pub latency_ms: Option<u32>,
+    /// Why loopback couldn't be checked, e.g. a missing permission or another
+    /// capture running. The tone was still played.
+    pub capture_error: Option<VoiceboxError>,
+}
+
+/// Play a tone on `device_id` while capturing system audio, and look for it
+/// in the capture. Only playback failures are errors; capture problems are
+/// part of the report.
+pub async fn run(
+    output: &AudioOutputState,
+    capture: &AudioCaptureState,
+    device_id: &str,
+) -> Result<SelfTestReport, VoiceboxError> {
+    let mut report = SelfTestReport {
+        device_id: device_id.to_string(),
+        capture_backend: capture.backend_name(),
+        tone_detected: false,
+        latency_ms: None,
+        capture_error: None,
+    };
+
+    let limit_secs = (SETTLE + LISTEN_TIMEOUT).as_secs() as u32 + 2;
+    let capturing = match audio_capture::start_capture(capture, limit_secs).await {
+        Ok(()) => {
+            tokio::time::sleep(SETTLE).await;
+            true
+        }
+        Err(e) => {
+            warn!("Audio self-test: loopback capture unavailable: {}", e);
+            report.capture_error = Some(e);
+            false
+        }
+    };
+    let offset = capture.samples.lock().unwrap().len();
+
+    let tone = tone(TONE_RATE, TONE_MS);
+    let played = output.play_samples(
+        &tone,
+        TONE_RATE,
+        1,
+        &[device_id.to_string()],
+        PlaybackOptions::default(),
+    )
+    .await;
+    if let Err(e) = played {
+        if capturing {
+            let _ = audio_capture::stop_capture(capture).await;
+        }
+        return Err(e);
+    }
+    if !capturing {
+        return Ok(report);
+    }
+
+    let deadline = Instant::now() + LISTEN_TIMEOUT;
+    while report.latency_ms.is_none() && capture.is_capturing() && Instant::now() < deadline {
+        tokio::time::sleep(POLL).await;
+        let sample_rate = *capture.sample_rate.lock().unwrap();
+        let channels = *capture.channels.lock().unwrap();
+        let captured = capture.samples.lock().unwrap()[offset..].to_vec();
+        report.latency_ms = find_tone(&captured, sample_rate, channels, TONE_HZ)
+            .map(|frame| (frame as u64 * 1000 / sample_rate as u64) as u32);
+    }
+    report.tone_detected = report.latency_ms.is_some();
+    if let Err(e) = audio_capture::stop_capture(capture).await {
+        report.capture_error = Some(e);
+    }
+    info!(
+        "Audio self-test on {}: tone_detected={}, latency_ms={:?}",
+        device_id, report.tone_detected, report.latency_ms
+    );
+    Ok(report)
+}
+
+/// A mono sine at TONE_HZ.
+fn tone(sample_rate: u32, duration_ms: u32) -> Vec<f32> {
+    let frames = sample_rate as usize * duration_ms as usize / 1000;
+    (0..frames)
+        .map(|n| TONE_AMPLITUDE * (std::f32::consts::TAU * TONE_HZ * n as f32 / sample_rate as f32).sin())
+        .collect()
+}
+
+/// The first frame of the first window where `frequency` dominates, from
+/// interleaved frames. Other sounds playing at the same time don't count
+/// unless the tone is at least as loud as they are.
+pub fn find_tone(samples: &[f32], sample_rate: u32, channels: u16, frequency: f32) -> Option<usize> {
+    let channels = channels.max(1) as usize;
+    let window = (sample_rate * WINDOW_MS / 1000) as usize;
+    if window == 0 {
+        return None;
+    }
+    let mono: Vec<f32> = samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    mono.chunks_exact(window).position(|chunk| {
+        let level = goertzel_level(chunk, sample_rate, frequency);
+        let rms = (chunk.iter().map(|s| s * s).sum::<f32>() / chunk.len() as f32).sqrt();
+        // A pure sine's RMS is its amplitude / √2
+        level >= MIN_LEVEL && level / std::f32::consts::SQRT_2 >= rms * 0.7
+    })
+    .map(|index| index * window)
+}
+
+/// Amplitude of `frequency` in `samples`.
+fn goertzel_level(samples: &[f32], sample_rate: u32, frequency: f32) -> f32 {
+    let coefficient = 2.0 * (std::f32::consts::TAU * frequency / sample_rate as f32).cos();
+    let (mut previous, mut before) = (0.0f32, 0.0f32);
+    for sample in samples {
+        let current = sample + coefficient * previous - before;
+        before = previous;
+        previous = current;
+    }
+    let power = previous * previous + before * before - coefficient * previous * before;
+    2.0 * power.max(0.0).sqrt() / samples.len() as f32
+}
@@ -0,0 +1,176 @@
+use crate::audio_util::{EncodeSpec, WavEncoder, WavSampleFormat};
+use crate::error::VoiceboxError;
+use crate::recording_metadata::{PauseInterval, RecordingMetadata};
+use serde::Serialize;
+use std::io::{Cursor, Read, Seek};
+
+/// Samples read from the source per step, so a segment is copied through in
+/// pieces rather than decoded whole.
+const CHUNK_SAMPLES: usize = 64 * 1024;
+
+/// Where a segment starts, e.g. a capture marker, and what to call it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SplitPoint {
+    pub at_frames: u64,
+    pub label: Option<String>,
+}
+
+/// One piece of a split recording, in frames of it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Segment {
+    pub start_frames: u64,
+    pub end_frames: u64,
+    /// Of the point it starts at. None for the first segment, unless a point
+    /// is right at the start.
+    pub label: Option<String>,
+}
+
+impl Segment {
+    pub fn frames(&self) -> u64 {
+        self.end_frames - self.start_frames
+    }
+}
+
+/// The segments `points` cut `total_frames` into. Points at or past the end
+/// are ignored, as are repeats of a position. A segment shorter than
+/// `min_frames` is merged into the next one, which then starts where it
+/// did and keeps its label if it had one; a short last one is merged into
+/// the one before.
+pub fn plan(total_frames: u64, points: &[SplitPoint], min_frames: u64) -> Vec<Segment> {
+    let mut points: Vec<&SplitPoint> = points.iter().filter(|point| point.at_frames < total_frames).collect();
+    // Stable, so the first of several points at one position names it
+    points.sort_by_key(|point| point.at_frames);
+    points.dedup_by_key(|point| point.at_frames);
+
+    let mut segments = vec![Segment {
+        start_frames: 0,
+        end_frames: total_frames,
+        label: None,
+    }];
+    for point in points {
+        let last = segments.last_mut().unwrap();
+        if point.at_frames == last.start_frames {
+            last.label = point.label.clone();
+            continue;
+        }
+        last.end_frames = point.at_frames;
+        segments.push(Segment {
+            start_frames: point.at_frames,
+            end_frames: total_frames,
+            label: point.label.clone(),
+        });
+    }
+
+    let mut merged: Vec<Segment> = Vec::with_capacity(segments.len());
+    let mut carried: Option<Segment> = None;
+    for mut segment in segments {
+        if let Some(short) = carried.take() {
+            segment.start_frames = short.start_frames;
+            segment.label = short.label.or(segment.label);
+        }
+        if segment.frames() < min_frames {
+            carried = Some(segment);
+        } else {
+            merged.push(segment);
+        }
+    }
+    if let Some(short) = carried {
+        match merged.last_mut() {
+            Some(last) => last.end_frames = short.end_frames,
+            None => merged.push(short),
+        }
+    }
+    merged
+}
+
+/// Split the WAV read from `wav` at `points`, handing each segment to `save`
+/// in order, as a WAV of its own with its label: the point's, or
+/// `base_label` numbered. Only one segment is in memory at a time, copied
+/// from the source in chunks. Returns how many were saved.
+pub fn split_wav<R: Read + Seek>(
+    wav: R,
+    points: &[SplitPoint],
+    min_frames: u64,
+    base_label: &str,
+    mut save: impl FnMut(&Segment, String, Vec<u8>) -> Result<(), VoiceboxError>,
+) -> Result<usize, VoiceboxError> {
+    let mut reader =
+        hound::WavReader::new(wav).map_err(|e| VoiceboxError::invalid_argument(format!("Not a readable WAV: {}", e)))?;
+    let spec = reader.spec();
+    let format = match (spec.sample_format, spec.bits_per_sample) {
+        (hound::SampleFormat::Int, 16) => WavSampleFormat::Int16,
+        (hound::SampleFormat::Int, 24) => WavSampleFormat::Int24,
+        (hound::SampleFormat::Float, 32) => WavSampleFormat::Float32,
+        (format, bits) => {
+            return Err(VoiceboxError::unsupported(format!(
+                "Splitting {}-bit {:?} WAVs isn't supported",
+                bits, format
+            )))
+        }
+    };
+    let encode_spec = EncodeSpec::pcm16(spec.sample_rate, spec.channels).with_format(format);
+    let channels = spec.channels.max(1) as usize;
+    let segments = plan(reader.duration() as u64, points, min_frames);
+
+    let read_error = |e: hound::Error| VoiceboxError::io(format!("Failed to read the WAV: {}", e));
+    let mut chunk = Vec::with_capacity(CHUNK_SAMPLES);
+    for (index, segment) in segments.iter().enumerate() {
+        reader.seek(segment.start_frames as u32)?;
+        let mut encoder = WavEncoder::new(Cursor::new(Vec::new()), encode_spec).map_err(VoiceboxError::internal)?;
+        let mut left = segment.frames() as usize * channels;
+        while left > 0 {
+            chunk.clear();
+            let take = left.min(CHUNK_SAMPLES);
+            match format {
+                WavSampleFormat::Int16 => {
+                    for sample in reader.samples::<i16>().take(take) {
+                        chunk.push(sample.map_err(read_error)? as f32 / i16::MAX as f32);
+                    }
+                }
+                WavSampleFormat::Int24 => {
+                    for sample in reader.samples::<i32>().take(take) {
+                        chunk.push(sample.map_err(read_error)? as f32 / 8_388_607.0);
+                    }
+                }
+                WavSampleFormat::Float32 => {
+                    for sample in reader.samples::<f32>().take(take) {
+                        chunk.push(sample.map_err(read_error)?);
+                    }
+                }
+            }
+            if chunk.len() < take {
+                return Err(VoiceboxError::io("The WAV ended before its header said it would"));
+            }
+            encoder.write(&chunk).map_err(VoiceboxError::internal)?;
+            left -= take;
+        }
+        let label = match &segment.label {
+            Some(label) => label.clone(),
+            None => format!("{} ({}/{})", base_label, index + 1, segments.len()),
+        };
+        save(segment, label, encoder.finish().map_err(VoiceboxError::internal)?.into_inner())?;
+    }
+    Ok(segments.len())
+}
+
+/// `metadata` of the whole recording, narrowed to `segment` of it: its
+/// duration and the pauses within it. The fingerprint is dropped, as it was
+/// of the whole.
+pub fn segment_metadata(metadata: &RecordingMetadata, segment: &Segment, sample_rate: u32) -> RecordingMetadata {
+    let to_ms = |frames: u64| frames * 1000 / sample_rate.max(1) as u64;
+    let (start_ms, end_ms) = (to_ms(segment.start_frames), to_ms(segment.end_frames));
+    RecordingMetadata {
+        duration_ms: end_ms - start_ms,
+        fingerprint: None,
+        pauses: metadata
+            .pauses
+            .iter()
+            .filter(|pause| (start_ms..end_ms).contains(&pause.at_ms))
+            .map(|pause| PauseInterval {
+                at_ms: pause.at_ms - start_ms,
+                duration_ms: pause.duration_ms,
+            })
+            .collect(),
+        ..metadata.clone()
+    }
+}
@@ -0,0 +1,960 @@
+use std::io::{Cursor, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// The highest rate audio is resampled to.
+pub const MAX_SAMPLE_RATE: u32 = 384_000;
+
+/// How samples are stored in the WAV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WavSampleFormat {
+    #[serde(alias = "pcm16")]
+    Int16,
+    /// Rounded to the nearest step, like Int16.
+    #[serde(alias = "pcm24")]
+    Int24,
+    /// IEEE float, written bit for bit.
+    Float32,
+}
+
+impl WavSampleFormat {
+    pub fn bits(self) -> u16 {
+        match self {
+            WavSampleFormat::Int16 => 16,
+            WavSampleFormat::Int24 => 24,
+            WavSampleFormat::Float32 => 32,
+        }
+    }
+
+    fn bytes(self) -> u16 {
+        self.bits() / 8
+    }
+
+    /// Full scale of the integer formats.
+    fn scale(self) -> Option<f32> {
+        match self {
+            WavSampleFormat::Int16 => Some(i16::MAX as f32),
+            WavSampleFormat::Int24 => Some(8_388_607.0),
+            WavSampleFormat::Float32 => None,
+        }
+    }
+
+    /// WAVE_FORMAT_PCM or WAVE_FORMAT_IEEE_FLOAT, also the first field of the
+    /// extensible subformat GUID.
+    fn format_code(self) -> u16 {
+        match self {
+            WavSampleFormat::Int16 | WavSampleFormat::Int24 => 1,
+            WavSampleFormat::Float32 => 3,
+        }
+    }
+}
+
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// The rest of the KSDATAFORMAT_SUBTYPE_* GUIDs after the format code.
+const SUBFORMAT_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeSpec {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub format: WavSampleFormat,
+    /// Speaker positions for WAVE_FORMAT_EXTENSIBLE (SPEAKER_FRONT_LEFT = 1,
+    /// ...). Without one, the channels take the first positions in order.
+    pub channel_mask: Option<u32>,
+}
+
+impl EncodeSpec {
+    /// 16-bit PCM, what captures and exports use.
+    pub fn pcm16(sample_rate: u32, channels: u16) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            format: WavSampleFormat::Int16,
+            channel_mask: None,
+        }
+    }
+
+    pub fn with_format(mut self, format: WavSampleFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_channel_mask(mut self, mask: u32) -> Self {
+        self.channel_mask = Some(mask);
+        self
+    }
+
+    /// WAVEFORMATEX can't say which speaker a channel is for or tell 24-bit
+    /// samples from padded ones, so those need the extensible header.
+    fn is_extensible(&self) -> bool {
+        self.channels > 2 || self.format == WavSampleFormat::Int24 || self.channel_mask.is_some()
+    }
+
+    fn block_align(&self) -> u16 {
+        self.channels * self.format.bytes()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.sample_rate == 0 || self.channels == 0 {
+            return Err("A WAV needs a sample rate and at least one channel".to_string());
+        }
+        if let Some(mask) = self.channel_mask {
+            if mask.count_ones() > self.channels as u32 {
+                return Err(format!(
+                    "Channel mask {:#x} names {} speakers for {} channels",
+                    mask,
+                    mask.count_ones(),
+                    self.channels
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The speaker positions channels take without an explicit mask, the way
+/// other encoders (and hound) default them.
+fn default_channel_mask(channels: u16) -> u32 {
+    // Only 18 positions are defined
+    ((1u64 << channels.min(18)) - 1) as u32
+}
+
+/// Encode interleaved frames into a complete WAV file.
+pub fn encode_wav(samples: &[f32], spec: EncodeSpec) -> Result<Vec<u8>, String> {
+    let mut encoder = WavEncoder::new(Cursor::new(Vec::new()), spec)?;
+    encoder.write(samples)?;
+    Ok(encoder.finish()?.into_inner())
+}
+
+/// Writes a WAV as frames arrive. Sizes in the header are filled in by
+/// finish, so the writer has to be seekable.
+pub struct WavEncoder<W: Write + Seek> {
+    writer: W,
+    spec: EncodeSpec,
+    /// Where the header starts, for writers that don't start at zero.
+    start: u64,
+    data_bytes: u64,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write + Seek> WavEncoder<W> {
+    pub fn new(mut writer: W, spec: EncodeSpec) -> Result<Self, String> {
+        spec.validate()?;
+        let start = writer.stream_position().map_err(|e| format!("Failed to write WAV header: {}", e))?;
+        let mut encoder = Self {
+            writer,
+            spec,
+            start,
+            data_bytes: 0,
+            buffer: Vec::new(),
+        };
+        let header = encoder.header(0);
+        encoder
+            .writer
+            .write_all(&header)
+            .map_err(|e| format!("Failed to write WAV header: {}", e))?;
+        Ok(encoder)
+    }
+
+    /// Append interleaved samples. Calls needn't line up with frames, as long
+    /// as whole frames have been written by finish.
+    pub fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let bytes = self.spec.format.bytes() as u64;
+        if self.header_len() + self.data_bytes + samples.len() as u64 * bytes > u32::MAX as u64 {
+            return Err("The audio is too long for a WAV file (4 GB)".to_string());
+        }
+        self.buffer.clear();
+        self.buffer.reserve(samples.len() * bytes as usize);
+        for sample in samples {
+            let scale = self.spec.format.scale().unwrap_or(1.0);
+            match self.spec.format {
+                WavSampleFormat::Int16 => {
+                    let value = (sample.clamp(-1.0, 1.0) * scale).round() as i16;
+                    self.buffer.extend_from_slice(&value.to_le_bytes());
+                }
+                WavSampleFormat::Int24 => {
+                    let value = (sample.clamp(-1.0, 1.0) * scale).round() as i32;
+                    self.buffer.extend_from_slice(&value.to_le_bytes()[..3]);
+                }
+                WavSampleFormat::Float32 => self.buffer.extend_from_slice(&sample.to_le_bytes()),
+            }
+        }
+        self.writer
+            .write_all(&self.buffer)
+            .map_err(|e| format!("Failed to write WAV samples: {}", e))?;
+        self.data_bytes += self.buffer.len() as u64;
+        Ok(())
+    }
+
+    /// Fill in the header and return the writer, positioned after the WAV.
+    pub fn finish(mut self) -> Result<W, String> {
+        if !self.data_bytes.is_multiple_of(self.spec.block_align() as u64) {
+            return Err("The audio ends partway through a frame".to_string());
+        }
+        let io_error = |e: std::io::Error| format!("Failed to finalize WAV: {}", e);
+        // Chunks are word-aligned; only odd 24-bit mono data needs the pad
+        if self.data_bytes % 2 == 1 {
+            self.writer.write_all(&[0]).map_err(io_error)?;
+        }
+        let end = self.writer.stream_position().map_err(io_error)?;
+        let header = self.header(self.data_bytes as u32);
+        self.writer.seek(SeekFrom::Start(self.start)).map_err(io_error)?;
+        self.writer.write_all(&header).map_err(io_error)?;
+        self.writer.seek(SeekFrom::Start(end)).map_err(io_error)?;
+        self.writer.flush().map_err(io_error)?;
+        Ok(self.writer)
+    }
+
+    fn header_len(&self) -> u64 {
+        self.header(0).len() as u64
+    }
+
+    /// RIFF, fmt, fact (float only) and the data chunk's header.
+    fn header(&self, data_bytes: u32) -> Vec<u8> {
+        let spec = &self.spec;
+        let mut fmt = Vec::with_capacity(40);
+        let tag = if spec.is_extensible() {
+            WAVE_FORMAT_EXTENSIBLE
+        } else {
+            spec.format.format_code()
+        };
+        fmt.extend_from_slice(&tag.to_le_bytes());
+        fmt.extend_from_slice(&spec.channels.to_le_bytes());
+        fmt.extend_from_slice(&spec.sample_rate.to_le_bytes());
+        fmt.extend_from_slice(&(spec.sample_rate * spec.block_align() as u32).to_le_bytes());
+        fmt.extend_from_slice(&spec.block_align().to_le_bytes());
+        fmt.extend_from_slice(&spec.format.bits().to_le_bytes());
+        if spec.is_extensible() {
+            fmt.extend_from_slice(&22u16.to_le_bytes());
+            fmt.extend_from_slice(&spec.format.bits().to_le_bytes());
+            let mask = spec.channel_mask.unwrap_or_else(|| default_channel_mask(spec.channels));
+            fmt.extend_from_slice(&mask.to_le_bytes());
+            fmt.extend_from_slice(&spec.format.format_code().to_le_bytes());
+            fmt.extend_from_slice(&SUBFORMAT_GUID_TAIL);
+        } else if spec.format == WavSampleFormat::Float32 {
+            // Formats other than PCM carry a (here empty) extension size
+            fmt.extend_from_slice(&0u16.to_le_bytes());
+        }
+
+        let mut header = Vec::with_capacity(80);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&[0; 4]);
+        header.extend_from_slice(b"WAVE");
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+        header.extend_from_slice(&fmt);
+        if spec.format == WavSampleFormat::Float32 {
+            let frames = data_bytes / spec.block_align() as u32;
+            header.extend_from_slice(b"fact");
+            header.extend_from_slice(&4u32.to_le_bytes());
+            header.extend_from_slice(&frames.to_le_bytes());
+        }
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_bytes.to_le_bytes());
+
+        let riff_size = header.len() as u32 - 8 + data_bytes + data_bytes % 2;
+        header[4..8].copy_from_slice(&riff_size.to_le_bytes());
+        header
+    }
+}
+
+/// A RIFF chunk: id, size and `body`, padded to an even length.
+pub fn riff_chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(8 + body.len() + 1);
+    chunk.extend_from_slice(id);
+    chunk.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(body);
+    if body.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+/// Insert whole chunks (see riff_chunk) into a WAV file just before its data
+/// chunk, where readers that stop at the audio still find them.
+pub fn insert_chunks(wav: &[u8], chunks: &[Vec<u8>]) -> Result<Vec<u8>, String> {
+    if wav.len() < 12 || &wav[0..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+        return Err("Not a WAV file".to_string());
+    }
+    let mut offset = 12;
+    let data_offset = loop {
+        if offset + 8 > wav.len() {
+            return Err("The WAV file has no data chunk".to_string());
+        }
+        if &wav[offset..offset + 4] == b"data" {
+            break offset;
+        }
+        let size = u32::from_le_bytes(wav[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        offset += 8 + size + size % 2;
+    };
+
+    let inserted: usize = chunks.iter().map(Vec::len).sum();
+    if wav.len() as u64 + inserted as u64 > u32::MAX as u64 {
+        return Err("The audio is too long for a WAV file (4 GB)".to_string());
+    }
+    let mut out = Vec::with_capacity(wav.len() + inserted);
+    out.extend_from_slice(&wav[..data_offset]);
+    for chunk in chunks {
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&wav[data_offset..]);
+    let riff_size = out.len() as u32 - 8;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(out)
+}
+
+/// How a backend delivers its samples, little-endian and interleaved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawSampleFormat {
+    Float32,
+    Int16,
+    /// Packed in three bytes.
+    Int24,
+    /// Also 24-bit samples in 32-bit containers, which sit in the top bytes.
+    Int32,
+}
+
+impl RawSampleFormat {
+    /// From whether samples are float, their container size and how many of
+    /// its bits are valid, the way WAVEFORMATEXTENSIBLE describes them.
+    /// None for what can't be read.
+    pub fn from_wave(float: bool, container_bits: u16, valid_bits: u16) -> Option<Self> {
+        match (float, container_bits, valid_bits) {
+            (true, 32, 32) => Some(RawSampleFormat::Float32),
+            (false, 16, 16) => Some(RawSampleFormat::Int16),
+            (false, 24, 24) => Some(RawSampleFormat::Int24),
+            (false, 32, 24 | 32) => Some(RawSampleFormat::Int32),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(self) -> usize {
+        match self {
+            RawSampleFormat::Int16 => 2,
+            RawSampleFormat::Int24 => 3,
+            RawSampleFormat::Float32 | RawSampleFormat::Int32 => 4,
+        }
+    }
+}
+
+/// Samples from raw `bytes`, integers scaled to [-1, 1). Floats are taken as
+/// they are. A partial sample at the end is dropped.
+pub fn decode_samples(bytes: &[u8], format: RawSampleFormat) -> Vec<f32> {
+    let samples = bytes.chunks_exact(format.bytes());
+    match format {
+        RawSampleFormat::Float32 => samples.map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect(),
+        RawSampleFormat::Int16 => samples
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32_768.0)
+            .collect(),
+        // Into the top of an i32, so the sign comes along
+        RawSampleFormat::Int24 => samples
+            .map(|b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2_147_483_648.0)
+            .collect(),
+        RawSampleFormat::Int32 => samples
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0)
+            .collect(),
+    }
+}
+
+/// Average interleaved frames down to one channel.
+pub fn downmix(samples: &[f32], channels: u16) -> Vec<f32> {
+    samples
+        .chunks(channels.max(1) as usize)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect()
+}
+
+/// Scale so the loudest sample reaches `peak`. Silence is left alone.
+pub fn normalize(samples: &mut [f32], peak: f32) {
+    let current = self::peak(samples);
+    if current <= f32::EPSILON {
+        return;
+    }
+    let gain = peak / current;
+    for sample in samples {
+        *sample *= gain;
+    }
+}
+
+/// Loudest sample magnitude.
+pub fn peak(samples: &[f32]) -> f32 {
+    samples.iter().fold(0.0f32, |max, s| max.max(s.abs()))
+}
+
+/// Sums of squares are kept in units of 2^-32 of full scale squared.
+const SQUARE_SCALE: f64 = (1u64 << 32) as f64;
+
+/// The level of what went through since it was last taken, kept as
+/// frames pass so a meter reads it without scanning a buffer: what a
+/// capture's backend delivered, or what a playback sent a device.
+#[derive(Debug, Default)]
+pub(crate) struct LevelCounters {
+    /// f32 bits: non-negative floats order the same as their bits.
+    peak: AtomicU32,
+    sum_squares: AtomicU64,
+    samples: AtomicU64,
+}
+
+impl LevelCounters {
+    /// Count `samples` in, clamped to full scale. Returns their own RMS and
+    /// peak.
+    pub(crate) fn measure(&self, samples: &[f32]) -> (f32, f32) {
+        let (peak, sum_squares) = samples.iter().fold((0.0f32, 0.0f64), |(peak, sum), s| {
+            let s = s.clamp(-1.0, 1.0);
+            (peak.max(s.abs()), sum + (s as f64) * (s as f64))
+        });
+        self.record(peak, sum_squares, samples.len());
+        ((sum_squares / samples.len().max(1) as f64).sqrt() as f32, peak)
+    }
+
+    fn record(&self, peak: f32, sum_squares: f64, samples: usize) {
+        self.peak.fetch_max(peak.to_bits(), Ordering::Relaxed);
+        self.sum_squares.fetch_add((sum_squares * SQUARE_SCALE) as u64, Ordering::Relaxed);
+        self.samples.fetch_add(samples as u64, Ordering::Relaxed);
+    }
+
+    /// RMS and peak since the last call, starting afresh.
+    pub(crate) fn take(&self) -> (f32, f32) {
+        let peak = f32::from_bits(self.peak.swap(0, Ordering::Relaxed));
+        let sum_squares = self.sum_squares.swap(0, Ordering::Relaxed) as f64 / SQUARE_SCALE;
+        let samples = self.samples.swap(0, Ordering::Relaxed);
+        if samples == 0 {
+            return (0.0, 0.0);
+        }
+        ((sum_squares / samples as f64).sqrt() as f32, peak)
+    }
+}
+
+/// A sample at this magnitude or over has clipped. Loopback audio is clamped
+/// at full scale, and a little under it allows for the device's rounding.
+pub const CLIP_THRESHOLD: f32 = 0.999;
+
+/// Clipped frames in a row that count as clipping. One full-scale frame can
+/// be a clean peak; a run of them is a flattened waveform.
+pub const CLIP_RUN_FRAMES: u64 = 3;
+
+/// How much of some interleaved audio clipped, counted in frames: a frame
+/// clipped when any of its channels did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct ClipStats {
+    pub total_frames: u64,
+    pub clipped_frames: u64,
+    /// The most clipped frames in a row.
+    pub longest_run: u64,
+    /// Clipped frames in a row at the end, carried into the next add.
+    #[serde(skip)]
+    run: u64,
+}
+
+impl ClipStats {
+    /// Count the frames of `samples`, continuing a run from the last call.
+    pub fn add(&mut self, samples: &[f32], channels: u16) {
+        for frame in samples.chunks(channels.max(1) as usize) {
+            self.total_frames += 1;
+            if frame.iter().any(|s| s.abs() >= CLIP_THRESHOLD) {
+                self.clipped_frames += 1;
+                self.run += 1;
+                self.longest_run = self.longest_run.max(self.run);
+            } else {
+                self.run = 0;
+            }
+        }
+    }
+
+    /// Count `frames` frames known to be under CLIP_THRESHOLD, without
+    /// looking at them.
+    pub fn add_unclipped(&mut self, frames: u64) {
+        if frames > 0 {
+            self.total_frames += frames;
+            self.run = 0;
+        }
+    }
+
+    /// Share of the frames that clipped, 0 to 100.
+    pub fn percentage(&self) -> f64 {
+        if self.total_frames == 0 {
+            return 0.0;
+        }
+        self.clipped_frames as f64 * 100.0 / self.total_frames as f64
+    }
+
+    /// Whether some run reached CLIP_RUN_FRAMES.
+    pub fn detected(&self) -> bool {
+        self.longest_run >= CLIP_RUN_FRAMES
+    }
+}
+
+/// ClipStats of interleaved `samples`.
+pub fn clip_stats(samples: &[f32], channels: u16) -> ClipStats {
+    let mut stats = ClipStats::default();
+    stats.add(samples, channels);
+    stats
+}
+
+/// Integrated loudness in LUFS, after ITU-R BS.1770: K-weighted, over 400 ms
+/// blocks gated at -70 LUFS and then 10 LU under their mean. Every channel
+/// counts the same, which is right up to stereo. None for audio shorter than
+/// a block or with no block above the gate.
+pub fn integrated_loudness(samples: &[f32], channels: u16, sample_rate: u32) -> Option<f32> {
+    let channels = channels.max(1) as usize;
+    let frames = samples.len() / channels;
+    // Blocks of four 100 ms steps, so they overlap by 75%
+    let step = (sample_rate / 10) as usize;
+    if step == 0 || frames < 4 * step {
+        return None;
+    }
+    let mut step_power = vec![0.0f64; frames / step];
+    for ch in 0..channels {
+        let (mut shelf, mut high_pass) = (Biquad::k_shelf(sample_rate), Biquad::k_high_pass(sample_rate));
+        for (i, power) in step_power.iter_mut().enumerate() {
+            for frame in i * step..(i + 1) * step {
+                let weighted = high_pass.run(shelf.run(samples[frame * channels + ch] as f64));
+                *power += weighted * weighted;
+            }
+        }
+    }
+    let blocks: Vec<f64> = step_power.windows(4).map(|steps| steps.iter().sum::<f64>() / (4 * step) as f64).collect();
+    let loudness = |power: f64| -0.691 + 10.0 * power.log10();
+    let gated = |threshold: f64| -> Option<f64> {
+        let kept: Vec<f64> = blocks.iter().copied().filter(|&power| loudness(power) > threshold).collect();
+        (!kept.is_empty()).then(|| kept.iter().sum::<f64>() / kept.len() as f64)
+    };
+    let relative = loudness(gated(-70.0)?) - 10.0;
+    Some(loudness(gated(relative.max(-70.0))?) as f32)
+}
+
+/// A second-order IIR section, direct form I.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self {
+            b: b.map(|b| b / a[0]),
+            a: [a[1] / a[0], a[2] / a[0]],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    /// The K-weighting's first stage, a shelf raising the highs by about
+    /// 4 dB, for any rate (as libebur128 derives it).
+    fn k_shelf(sample_rate: u32) -> Self {
+        let (frequency, q, gain_db) = (1681.974450955533, 0.7071752369554196, 3.999843853973347);
+        let k = (std::f64::consts::PI * frequency / sample_rate as f64).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        Self::new(
+            [vh + vb * k / q + k * k, 2.0 * (k * k - vh), vh - vb * k / q + k * k],
+            [1.0 + k / q + k * k, 2.0 * (k * k - 1.0), 1.0 - k / q + k * k],
+        )
+    }
+
+    /// The second stage, a high-pass at about 38 Hz.
+    fn k_high_pass(sample_rate: u32) -> Self {
+        let (frequency, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (std::f64::consts::PI * frequency / sample_rate as f64).tan();
+        Self::new([1.0, -2.0, 1.0], [1.0 + k / q + k * k, 2.0 * (k * k - 1.0), 1.0 - k / q + k * k])
+    }
+
+    fn run(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// Linear interpolation, per channel.
+pub fn resample(samples: &[f32], channels: u16, from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate {
+        return samples.to_vec();
+    }
+    let channels = channels.max(1) as usize;
+    let in_frames = samples.len() / channels;
+    if in_frames == 0 || from_rate == 0 {
+        return Vec::new();
+    }
+    let out_frames = (in_frames as u64 * to_rate as u64 / from_rate as u64) as usize;
+    let ratio = from_rate as f64 / to_rate as f64;
+
+    let mut out = Vec::with_capacity(out_frames * channels);
+    for i in 0..out_frames {
+        let pos = i as f64 * ratio;
+        let index = pos as usize;
+        let frac = (pos - index as f64) as f32;
+        let next = (index + 1).min(in_frames - 1);
+        for ch in 0..channels {
+            let a = samples[index * channels + ch];
+            let b = samples[next * channels + ch];
+            out.push(a + (b - a) * frac);
+        }
+    }
+    out
+}
+
+/// Zero crossings of the sinc kept on each side of resample_sinc's kernel,
+/// at the lower of the two rates.
+const SINC_ZERO_CROSSINGS: usize = 16;
+
+/// Kernel phases resample_sinc works out ahead; a ratio that needs more
+/// has each one worked out as it is used.
+const MAX_SINC_PHASES: u32 = 1024;
+
+/// Blackman-windowed sinc, per channel, low-passed under the lower rate's
+/// Nyquist so that downsampling doesn't alias. Slower than resample, for
+/// audio that is kept.
+pub fn resample_sinc(samples: &[f32], channels: u16, from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate {
+        return samples.to_vec();
+    }
+    let channels = channels.max(1) as usize;
+    let in_frames = samples.len() / channels;
+    if in_frames == 0 || from_rate == 0 || to_rate == 0 {
+        return Vec::new();
+    }
+    // Output frame i sits at input frame i * down / up
+    let divisor = gcd(from_rate, to_rate);
+    let (up, down) = ((to_rate / divisor) as u64, (from_rate / divisor) as u64);
+    let cutoff = (to_rate as f64 / from_rate as f64).min(1.0);
+    let half = (SINC_ZERO_CROSSINGS as f64 / cutoff).ceil() as i64;
+    // Taps at input frames index - half + 1 ..= index + half, for an output
+    // frame `frac` of the way from index to index + 1
+    let kernel = |frac: f64| -> Vec<f32> {
+        let taps: Vec<f64> = (1 - half..=half)
+            .map(|k| {
+                let distance = k as f64 - frac;
+                let x = std::f64::consts::PI * distance / half as f64;
+                let window = 0.42 + 0.5 * x.cos() + 0.08 * (2.0 * x).cos();
+                sinc(cutoff * distance) * window
+            })
+            .collect();
+        // Unity gain at DC, whatever the phase
+        let sum: f64 = taps.iter().sum();
+        taps.iter().map(|tap| (tap / sum) as f32).collect()
+    };
+    let table: Vec<Vec<f32>> = if up <= MAX_SINC_PHASES as u64 {
+        (0..up).map(|phase| kernel(phase as f64 / up as f64)).collect()
+    } else {
+        Vec::new()
+    };
+
+    let out_frames = (in_frames as u64 * up / down) as usize;
+    let mut out = Vec::with_capacity(out_frames * channels);
+    let mut computed;
+    for i in 0..out_frames as u64 {
+        let index = (i * down / up) as i64;
+        let phase = i * down % up;
+        let taps = match table.get(phase as usize) {
+            Some(taps) => taps,
+            None => {
+                computed = kernel(phase as f64 / up as f64);
+                &computed
+            }
+        };
+        let first = index + 1 - half;
+        for ch in 0..channels {
+            let mut value = 0.0;
+            for (k, tap) in taps.iter().enumerate() {
+                let frame = first + k as i64;
+                if (0..in_frames as i64).contains(&frame) {
+                    value += samples[frame as usize * channels + ch] * tap;
+                }
+            }
+            out.push(value);
+        }
+    }
+    out
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        let x = std::f64::consts::PI * x;
+        x.sin() / x
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// The frames between the first and last one with a sample at or above
+/// `threshold`, widened by `padding_frames` on each side. Empty when it is
+/// all silence.
+pub fn trim_silence(samples: &[f32], channels: u16, threshold: f32, padding_frames: usize) -> &[f32] {
+    let channels = channels.max(1) as usize;
+    match loud_frames(samples, channels as u16, threshold, padding_frames) {
+        Some(frames) => &samples[frames.start * channels..frames.end * channels],
+        None => &[],
+    }
+}
+
+/// The range of frames trim_silence keeps, None when it is all silence.
+pub fn loud_frames(samples: &[f32], channels: u16, threshold: f32, padding_frames: usize) -> Option<Range<usize>> {
+    let channels = channels.max(1) as usize;
+    let loud = |frame: &[f32]| frame.iter().any(|s| s.abs() >= threshold);
+    let first = samples.chunks_exact(channels).position(loud)?;
+    let total = samples.len() / channels;
+    let last = total - 1 - samples.chunks_exact(channels).rev().position(loud).unwrap_or(0);
+    Some(first.saturating_sub(padding_frames)..(last + 1 + padding_frames).min(total))
+}
+
+/// Windows voice_segments measures the level of.
+pub const VAD_WINDOW_MS: u32 = 20;
+
+/// Kept either side of an utterance, so it doesn't start or end abruptly.
+/// Never more than half the silence to the next one.
+pub const VAD_PADDING_MS: u32 = 100;
+
+/// How voice_segments splits audio into utterances.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+pub struct VadOptions {
+    /// An utterance shorter than this is merged into the nearer neighbor.
+    pub min_segment_secs: f64,
+    /// An utterance longer than this is cut at its quietest window in the
+    /// second half of this length. 0 for no limit.
+    pub max_segment_secs: f64,
+    /// RMS level, in dBFS, under which a window is silence.
+    pub silence_threshold_db: f32,
+    /// Silence shorter than this doesn't end an utterance.
+    pub min_gap_secs: f64,
+}
+
+/// The frames of each utterance in `samples`, in order, by their energy
+/// over VAD_WINDOW_MS windows. Empty when it is all silence.
+pub fn voice_segments(samples: &[f32], channels: u16, sample_rate: u32, options: VadOptions) -> Vec<Range<usize>> {
+    let channels = channels.max(1) as usize;
+    let total = samples.len() / channels;
+    let secs_to_frames = |secs: f64| (secs.max(0.0) * sample_rate as f64).round() as usize;
+    let window = (sample_rate as usize * VAD_WINDOW_MS as usize / 1000).max(1);
+    let levels: Vec<f32> = samples
+        .chunks(window * channels)
+        .map(|chunk| {
+            let mean_square = chunk.iter().map(|s| (*s as f64).powi(2)).sum::<f64>() / chunk.len() as f64;
+            (10.0 * mean_square.log10()) as f32
+        })
+        .collect();
+
+    let mut segments: Vec<Range<usize>> = Vec::new();
+    let min_gap = secs_to_frames(options.min_gap_secs);
+    for (index, level) in levels.iter().enumerate() {
+        if *level < options.silence_threshold_db {
+            continue;
+        }
+        let (start, end) = (index * window, ((index + 1) * window).min(total));
+        match segments.last_mut() {
+            Some(last) if start - last.end < min_gap.max(1) => last.end = end,
+            _ => segments.push(start..end),
+        }
+    }
+
+    // Each short one into the neighbor across the shorter silence, until
+    // none is left or it is the only one
+    let min_frames = secs_to_frames(options.min_segment_secs);
+    while segments.len() > 1 {
+        let Some(short) = segments.iter().position(|segment| segment.len() < min_frames) else {
+            break;
+        };
+        let gap_before = short.checked_sub(1).map(|before| segments[short].start - segments[before].end);
+        let gap_after = segments.get(short + 1).map(|after| after.start - segments[short].end);
+        let into = match (gap_before, gap_after) {
+            (Some(before), Some(after)) if after < before => short + 1,
+            (Some(_), _) => short - 1,
+            _ => short + 1,
+        };
+        let merged = segments[short].start.min(segments[into].start)..segments[short].end.max(segments[into].end);
+        segments[into] = merged;
+        segments.remove(short);
+    }
+
+    let max_frames = secs_to_frames(options.max_segment_secs);
+    if max_frames > 0 {
+        let mut split = Vec::with_capacity(segments.len());
+        for mut segment in segments {
+            while segment.len() > max_frames {
+                // In whole windows, at least half the limit in
+                let first = (segment.start + max_frames.div_ceil(2)).div_ceil(window);
+                let cut = (first..(segment.start + max_frames) / window)
+                    .min_by(|a, b| levels[*a].total_cmp(&levels[*b]))
+                    .map_or(segment.start + max_frames, |quietest| quietest * window);
+                split.push(segment.start..cut);
+                segment.start = cut;
+            }
+            split.push(segment);
+        }
+        segments = split;
+    }
+
+    let padding = sample_rate as usize * VAD_PADDING_MS as usize / 1000;
+    let bounds: Vec<(usize, usize)> = segments.iter().map(|segment| (segment.start, segment.end)).collect();
+    for (index, segment) in segments.iter_mut().enumerate() {
+        let floor = index.checked_sub(1).map_or(0, |before| bounds[before].1.midpoint(segment.start));
+        let ceiling = bounds.get(index + 1).map_or(total, |after| segment.end.midpoint(after.0));
+        segment.start = segment.start.saturating_sub(padding).max(floor);
+        segment.end = (segment.end + padding).min(ceiling);
+    }
+    segments
+}
+
+/// Round samples to what `format` can store, as the encoder would.
+pub fn quantize(samples: &mut [f32], format: WavSampleFormat) {
+    let Some(scale) = format.scale() else {
+        return;
+    };
+    for sample in samples {
+        *sample = (sample.clamp(-1.0, 1.0) * scale).round() / scale;
+    }
+}
+
+/// A logarithmic sine sweep from `from_hz` to `to_hz`, with 5 ms fades so it
+/// starts and ends without a click. Its sharp autocorrelation makes it easy
+/// to find in a recording, even a noisy one.
+pub fn chirp(sample_rate: u32, duration_ms: u32, from_hz: f32, to_hz: f32, amplitude: f32) -> Vec<f32> {
+    let frames = (sample_rate as u64 * duration_ms as u64 / 1000) as usize;
+    let fade = (sample_rate as usize / 200).min(frames / 2).max(1);
+    let duration = duration_ms as f64 / 1000.0;
+    let rate = (to_hz as f64 / from_hz as f64).ln();
+    (0..frames)
+        .map(|n| {
+            let t = n as f64 / sample_rate as f64;
+            let phase = std::f64::consts::TAU * from_hz as f64 * duration / rate * ((t / duration * rate).exp() - 1.0);
+            let gain = (n.min(frames - 1 - n) as f32 / fade as f32).min(1.0);
+            amplitude * gain * phase.sin() as f32
+        })
+        .collect()
+}
+
+/// Where a reference signal lines up best within a longer one.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct CorrelationPeak {
+    /// Samples into the signal where the reference starts.
+    pub lag: usize,
+    /// Normalized cross-correlation at `lag`, 0 to 1: 1 for a scaled copy of
+    /// the reference. A copy with its polarity inverted counts too.
+    pub correlation: f32,
+    /// The highest normalized correlation at least a tenth of the reference
+    /// away from `lag`, where echoes and noise show up.
+    pub runner_up: f32,
+}
+
+/// Cross-correlate mono `signal` against `reference`, through an FFT. None if
+/// the signal is shorter than the reference or either is silent.
+pub fn correlate(signal: &[f32], reference: &[f32]) -> Option<CorrelationPeak> {
+    if reference.is_empty() || signal.len() < reference.len() {
+        return None;
+    }
+    let reference_energy: f64 = reference.iter().map(|s| (*s as f64).powi(2)).sum();
+    if reference_energy == 0.0 {
+        return None;
+    }
+    let size = (signal.len() + reference.len()).next_power_of_two();
+    let twiddles = fft_twiddles(size);
+    let spectrum = |samples: &[f32]| {
+        let mut buffer: Vec<(f32, f32)> = samples.iter().map(|s| (*s, 0.0)).collect();
+        buffer.resize(size, (0.0, 0.0));
+        fft(&mut buffer, &twiddles);
+        buffer
+    };
+    let signal_spectrum = spectrum(signal);
+    let reference_spectrum = spectrum(reference);
+    // signal × conj(reference), conjugated so a forward FFT inverts it
+    let mut product: Vec<(f32, f32)> = signal_spectrum
+        .iter()
+        .zip(&reference_spectrum)
+        .map(|(&(a, b), &(c, d))| (a * c + b * d, -(b * c - a * d)))
+        .collect();
+    fft(&mut product, &twiddles);
+
+    // Energy of each window of the signal, from running sums
+    let mut running = Vec::with_capacity(signal.len() + 1);
+    running.push(0.0f64);
+    for sample in signal {
+        running.push(running.last().unwrap() + (*sample as f64).powi(2));
+    }
+    let lags = signal.len() - reference.len() + 1;
+    let normalized: Vec<f32> = (0..lags)
+        .map(|lag| {
+            let window_energy = running[lag + reference.len()] - running[lag];
+            if window_energy <= reference_energy * 1e-9 {
+                return 0.0;
+            }
+            let dot = product[lag].0 as f64 / size as f64;
+            (dot.abs() / (window_energy * reference_energy).sqrt()).min(1.0) as f32
+        })
+        .collect();
+
+    let (lag, &correlation) = normalized.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1))?;
+    if correlation == 0.0 {
+        return None;
+    }
+    let exclude = reference.len() / 10;
+    let runner_up = normalized
+        .iter()
+        .enumerate()
+        .filter(|(other, _)| other.abs_diff(lag) > exclude)
+        .fold(0.0f32, |max, (_, value)| max.max(*value));
+    Some(CorrelationPeak {
+        lag,
+        correlation,
+        runner_up,
+    })
+}
+
+/// The first half of the `size`th roots of unity, e^(-2πik/size), for fft().
+pub(crate) fn fft_twiddles(size: usize) -> Vec<(f32, f32)> {
+    (0..size / 2)
+        .map(|k| {
+            let (sin, cos) = (-std::f64::consts::TAU * k as f64 / size as f64).sin_cos();
+            (cos as f32, sin as f32)
+        })
+        .collect()
+}
+
+/// In-place radix-2 FFT of (re, im) pairs. The length must be a power of two,
+/// with `twiddles` from fft_twiddles() for it.
+pub(crate) fn fft(buffer: &mut [(f32, f32)], twiddles: &[(f32, f32)]) {
+    let n = buffer.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            buffer.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let step = n / len;
+        for chunk in buffer.chunks_exact_mut(len) {
+            for k in 0..len / 2 {
+                let (cos, sin) = twiddles[k * step];
+                let (re, im) = chunk[k + len / 2];
+                let t = (re * cos - im * sin, re * sin + im * cos);
+                let u = chunk[k];
+                chunk[k] = (u.0 + t.0, u.1 + t.1);
+                chunk[k + len / 2] = (u.0 - t.0, u.1 - t.1);
+            }
+        }
+        len <<= 1;
+    }
+}
@@ -0,0 +1,294 @@
+use serde::Serialize;
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+use std::path::PathBuf;
+
+/// Passed by the login entry when the user wants Voicebox to start in the tray.
+pub const HIDDEN_FLAG: &str = "--hidden";
+
+/// Also start in the tray, for login items and scripts set up by hand.
+pub const HIDDEN_FLAG_ALIASES: [&str; 2] = ["--minimized", "--autostart"];
+
+/// Name of the login entry (Run value on Windows, file stem elsewhere).
+#[cfg(windows)]
+const ENTRY_NAME: &str = "Voicebox";
+#[cfg(target_os = "macos")]
+const LAUNCH_AGENT_LABEL: &str = "sh.voicebox.app";
+
+/// Launch-at-login state as registered with the OS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct AutostartStatus {
+    pub enabled: bool,
+    pub start_minimized: bool,
+}
+
+/// Whether this process was started with `--hidden` or one of its aliases.
+pub fn launched_hidden() -> bool {
+    std::env::args().any(|arg| arg == HIDDEN_FLAG || HIDDEN_FLAG_ALIASES.contains(&arg.as_str()))
+}
+
+/// Point an enabled login entry at this binary if it names another path,
+/// because the app was moved or an update installed it elsewhere. True if the
+/// entry was rewritten.
+pub fn refresh() -> Result<bool, String> {
+    let status = get()?;
+    if !status.enabled {
+        return Ok(false);
+    }
+    let target = launch_target()?;
+    if entry_program().as_deref() == Some(target.as_str()) {
+        return Ok(false);
+    }
+    set(true, status.start_minimized)?;
+    Ok(true)
+}
+
+/// Binary the login entry should launch. For an AppImage this is the image
+/// itself, not the path it was mounted at for this run.
+fn launch_target() -> Result<String, String> {
+    #[cfg(target_os = "linux")]
+    if let Ok(appimage) = std::env::var("APPIMAGE") {
+        return Ok(appimage);
+    }
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to locate app binary: {}", e))?;
+    Ok(exe.to_string_lossy().into_owned())
+}
+
+/// The program a command line starts: its first argument, in double quotes
+/// (`\"` standing for a quote inside them) or up to the first space.
+#[cfg(any(windows, target_os = "linux"))]
+fn command_program(command: &str) -> String {
+    let command = command.trim_start();
+    let Some(quoted) = command.strip_prefix('"') else {
+        return command.split_whitespace().next().unwrap_or("").to_string();
+    };
+    let mut program = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.as_str().starts_with('"') => program.extend(chars.next()),
+            '"' => break,
+            c => program.push(c),
+        }
+    }
+    program
+}
+
+#[cfg(target_os = "macos")]
+fn launch_agent_path() -> Result<PathBuf, String> {
+    let home = std::env::var("HOME").map_err(|_| "HOME is not set".to_string())?;
+    Ok(PathBuf::from(home)
+        .join("Library/LaunchAgents")
+        .join(format!("{}.plist", LAUNCH_AGENT_LABEL)))
+}
+
+/// The first of the launch agent's ProgramArguments.
+#[cfg(target_os = "macos")]
+fn entry_program() -> Option<String> {
+    let contents = std::fs::read_to_string(launch_agent_path().ok()?).ok()?;
+    let arguments = contents.split_once("<key>ProgramArguments</key>")?.1;
+    let program = arguments.split_once("<string>")?.1.split_once("</string>")?.0;
+    Some(program.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&"))
+}
+
+#[cfg(target_os = "macos")]
+pub fn get() -> Result<AutostartStatus, String> {
+    let path = launch_agent_path()?;
+    let Ok(contents) = std::fs::read_to_string(&path) else {
+        return Ok(AutostartStatus {
+            enabled: false,
+            start_minimized: false,
+        });
+    };
+    Ok(AutostartStatus {
+        enabled: true,
+        start_minimized: contents.contains(&format!("<string>{}</string>", HIDDEN_FLAG)),
+    })
+}
+
+#[cfg(target_os = "macos")]
+pub fn set(enabled: bool, start_minimized: bool) -> Result<AutostartStatus, String> {
+    let path = launch_agent_path()?;
+    if !enabled {
+        if path.exists() {
+            std::fs::remove_file(&path).map_err(|e| format!("Failed to remove launch agent: {}", e))?;
+        }
+        return get();
+    }
+
+    let escape = |s: &str| s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    let mut arguments = format!("        <string>{}</string>\n", escape(&launch_target()?));
+    if start_minimized {
+        arguments.push_str(&format!("        <string>{}</string>\n", HIDDEN_FLAG));
+    }
+    let plist = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+{}    </array>
+    <key>RunAtLoad</key>
+    <true/>
+</dict>
+</plist>
+"#,
+        LAUNCH_AGENT_LABEL, arguments
+    );
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create LaunchAgents dir: {}", e))?;
+    }
+    std::fs::write(&path, plist).map_err(|e| format!("Failed to write launch agent: {}", e))?;
+    get()
+}
+
+#[cfg(windows)]
+const RUN_KEY: &str = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Run";
+
+#[cfg(windows)]
+fn reg(args: &[&str]) -> std::io::Result<std::process::Output> {
+    use std::os::windows::process::CommandExt;
+    use std::process::Command;
+    const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+    Command::new("reg").args(args).creation_flags(CREATE_NO_WINDOW).output()
+}
+
+/// The program of the Run value, which reg query prints after its type.
+#[cfg(windows)]
+fn entry_program() -> Option<String> {
+    let output = reg(&["query", RUN_KEY, "/v", ENTRY_NAME]).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let command = stdout.lines().find_map(|line| line.split_once("REG_SZ"))?.1;
+    Some(command_program(command))
+}
+
+#[cfg(windows)]
+pub fn get() -> Result<AutostartStatus, String> {
+    let output = reg(&["query", RUN_KEY, "/v", ENTRY_NAME])
+        .map_err(|e| format!("Failed to query the registry: {}", e))?;
+    // reg exits non-zero when the value doesn't exist
+    if !output.status.success() {
+        return Ok(AutostartStatus {
+            enabled: false,
+            start_minimized: false,
+        });
+    }
+    let value = String::from_utf8_lossy(&output.stdout);
+    Ok(AutostartStatus {
+        enabled: true,
+        start_minimized: value.contains(HIDDEN_FLAG),
+    })
+}
+
+#[cfg(windows)]
+pub fn set(enabled: bool, start_minimized: bool) -> Result<AutostartStatus, String> {
+    let output = if enabled {
+        let mut command = format!("\"{}\"", launch_target()?);
+        if start_minimized {
+            command.push(' ');
+            command.push_str(HIDDEN_FLAG);
+        }
+        reg(&["add", RUN_KEY, "/v", ENTRY_NAME, "/t", "REG_SZ", "/d", &command, "/f"])
+    } else {
+        if !get()?.enabled {
+            return get();
+        }
+        reg(&["delete", RUN_KEY, "/v", ENTRY_NAME, "/f"])
+    }
+    .map_err(|e| format!("Failed to update the registry: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to update the login entry: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    get()
+}
+
+#[cfg(target_os = "linux")]
+fn desktop_entry_path() -> Result<PathBuf, String> {
+    let config_dir = std::env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| std::env::var("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map_err(|_| "Neither XDG_CONFIG_HOME nor HOME is set".to_string())?;
+    Ok(config_dir.join("autostart").join("voicebox.desktop"))
+}
+
+/// The program of the desktop entry's Exec line.
+#[cfg(target_os = "linux")]
+fn entry_program() -> Option<String> {
+    let contents = std::fs::read_to_string(desktop_entry_path().ok()?).ok()?;
+    let exec = contents.lines().find_map(|line| line.strip_prefix("Exec="))?;
+    Some(command_program(exec))
+}
+
+#[cfg(target_os = "linux")]
+pub fn get() -> Result<AutostartStatus, String> {
+    let path = desktop_entry_path()?;
+    let Ok(contents) = std::fs::read_to_string(&path) else {
+        return Ok(AutostartStatus {
+            enabled: false,
+            start_minimized: false,
+        });
+    };
+    // Desktop environments disable an entry rather than deleting it
+    let disabled = contents.lines().any(|line| {
+        let line = line.trim();
+        line == "Hidden=true" || line == "X-GNOME-Autostart-enabled=false"
+    });
+    let exec = contents.lines().find(|line| line.starts_with("Exec=")).unwrap_or("");
+    Ok(AutostartStatus {
+        enabled: !disabled,
+        start_minimized: exec.split_whitespace().any(|arg| arg == HIDDEN_FLAG),
+    })
+}
+
+#[cfg(target_os = "linux")]
+pub fn set(enabled: bool, start_minimized: bool) -> Result<AutostartStatus, String> {
+    let path = desktop_entry_path()?;
+    if !enabled {
+        if path.exists() {
+            std::fs::remove_file(&path).map_err(|e| format!("Failed to remove autostart entry: {}", e))?;
+        }
+        return get();
+    }
+
+    let mut exec = format!("\"{}\"", launch_target()?.replace('"', "\\\""));
+    if start_minimized {
+        exec.push(' ');
+        exec.push_str(HIDDEN_FLAG);
+    }
+    let entry = format!(
+        "[Desktop Entry]\nType=Application\nName=Voicebox\nExec={}\nX-GNOME-Autostart-enabled=true\n",
+        exec
+    );
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create autostart dir: {}", e))?;
+    }
+    std::fs::write(&path, entry).map_err(|e| format!("Failed to write autostart entry: {}", e))?;
+    get()
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", windows)))]
+fn entry_program() -> Option<String> {
+    None
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", windows)))]
+pub fn get() -> Result<AutostartStatus, String> {
+    Ok(AutostartStatus {
+        enabled: false,
+        start_minimized: false,
+    })
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", windows)))]
+pub fn set(_enabled: bool, _start_minimized: bool) -> Result<AutostartStatus, String> {
+    Err("Launch at login is not supported on this platform".to_string())
+}
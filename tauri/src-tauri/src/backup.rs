@@ -0,0 +1,389 @@
+use crate::process_manager::SERVER_RECORD_FILE;
+use crate::settings::SETTINGS_FILE;
+use crate::storage;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Component, Path, PathBuf};
+use zip::write::SimpleFileOptions;
+
+/// Written at the root of every archive; import refuses archives without it.
+pub const BACKUP_MANIFEST: &str = "voicebox-backup.json";
+
+/// The server database, which every valid backup must contain.
+const DATABASE_FILE: &str = "voicebox.db";
+
+/// Top-level directories left out of backups: regenerable caches, scratch space,
+/// and (unless requested) the downloaded base models.
+const CACHE_DIRS: [&str; 3] = ["cache", "temp", "tmp"];
+const MODELS_DIR: &str = "models";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub app_version: String,
+    pub created_at: u64,
+    pub include_models: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupProgress {
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupResult {
+    pub path: PathBuf,
+    pub files: usize,
+    pub bytes: u64,
+    pub include_models: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreResult {
+    pub files_restored: usize,
+    pub bytes_restored: u64,
+    pub merged: bool,
+    pub manifest: BackupManifest,
+    /// Contents of the archived settings.json, applied by the caller since the
+    /// live settings are held in memory.
+    #[serde(skip)]
+    pub settings_json: Option<String>,
+}
+
+/// Whether a data dir entry belongs in a backup.
+fn is_backed_up(relative_path: &Path, include_models: bool) -> bool {
+    let Some(Component::Normal(top)) = relative_path.components().next() else {
+        return false;
+    };
+    let top = top.to_string_lossy();
+    if relative_path.components().count() == 1 {
+        // Settings are written from the live values; the server record is runtime state
+        return top != SETTINGS_FILE && top != SERVER_RECORD_FILE;
+    }
+    !CACHE_DIRS.contains(&top.as_ref()) && (include_models || top != MODELS_DIR)
+}
+
+/// Zip names always use forward slashes regardless of platform.
+fn archive_name(relative_path: &Path) -> String {
+    relative_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Write a zip of `data_dir` to `dest`, streaming each file into the archive.
+/// `settings_json` is stored as settings.json so the backup carries the user's
+/// preferences even when the data dir was moved away from the app dir.
+pub fn export<F>(
+    data_dir: &Path,
+    dest: &Path,
+    include_models: bool,
+    settings_json: Option<&str>,
+    on_progress: F,
+) -> Result<BackupResult, String>
+where
+    F: Fn(&BackupProgress),
+{
+    let files: Vec<_> = storage::walk_files(data_dir)?
+        .into_iter()
+        .filter(|f| is_backed_up(&f.relative_path, include_models))
+        .collect();
+    if !files.iter().any(|f| f.relative_path == Path::new(DATABASE_FILE)) {
+        return Err(format!("No {} found in {}", DATABASE_FILE, data_dir.display()));
+    }
+
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create backup folder: {}", e))?;
+    }
+    // Build next to the destination and rename at the end so a failed export
+    // never leaves something that looks like a complete backup
+    let partial_path = dest.with_extension("zip.partial");
+    let result = write_archive(data_dir, &partial_path, &files, include_models, settings_json, on_progress)
+        .and_then(|result| {
+            std::fs::rename(&partial_path, dest)
+                .map_err(|e| format!("Failed to finalize backup: {}", e))?;
+            Ok(result)
+        });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&partial_path);
+    }
+
+    result.map(|(files, bytes)| BackupResult {
+        path: dest.to_path_buf(),
+        files,
+        bytes,
+        include_models,
+    })
+}
+
+fn write_archive<F>(
+    data_dir: &Path,
+    archive_path: &Path,
+    files: &[storage::FileEntry],
+    include_models: bool,
+    settings_json: Option<&str>,
+    on_progress: F,
+) -> Result<(usize, u64), String>
+where
+    F: Fn(&BackupProgress),
+{
+    use std::io::Write;
+
+    let archive = File::create(archive_path)
+        .map_err(|e| format!("Failed to create {}: {}", archive_path.display(), e))?;
+    let mut zip = zip::ZipWriter::new(BufWriter::new(archive));
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    let manifest = BackupManifest {
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: crate::process_manager::unix_timestamp(),
+        include_models,
+    };
+    let manifest_json = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| format!("Failed to serialize backup manifest: {}", e))?;
+    zip.start_file(BACKUP_MANIFEST, options)
+        .map_err(|e| format!("Failed to write backup manifest: {}", e))?;
+    zip.write_all(manifest_json.as_bytes())
+        .map_err(|e| format!("Failed to write backup manifest: {}", e))?;
+    if let Some(settings_json) = settings_json {
+        zip.start_file(SETTINGS_FILE, options)
+            .map_err(|e| format!("Failed to write settings to backup: {}", e))?;
+        zip.write_all(settings_json.as_bytes())
+            .map_err(|e| format!("Failed to write settings to backup: {}", e))?;
+    }
+
+    let mut progress = BackupProgress {
+        files_done: 0,
+        files_total: files.len(),
+        bytes_done: 0,
+        bytes_total: files.iter().map(|f| f.size).sum(),
+    };
+    on_progress(&progress);
+
+    for file in files {
+        let name = archive_name(&file.relative_path);
+        // Model weights can exceed the 4 GiB limit of plain zip entries
+        let file_options = options.large_file(file.size >= u32::MAX as u64);
+        let mut source = File::open(data_dir.join(&file.relative_path))
+            .map(BufReader::new)
+            .map_err(|e| format!("Failed to open {}: {}", name, e))?;
+        zip.start_file(name.as_str(), file_options)
+            .map_err(|e| format!("Failed to add {} to backup: {}", name, e))?;
+        std::io::copy(&mut source, &mut zip)
+            .map_err(|e| format!("Failed to add {} to backup: {}", name, e))?;
+
+        progress.files_done += 1;
+        progress.bytes_done += file.size;
+        on_progress(&progress);
+    }
+
+    let writer = zip.finish().map_err(|e| format!("Failed to finish backup: {}", e))?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())
+        .and_then(|file| file.sync_all())
+        .map_err(|e| format!("Failed to finish backup: {}", e))?;
+
+    Ok((progress.files_done, progress.bytes_done))
+}
+
+/// Check that `archive` is a Voicebox backup and every entry stays inside the
+/// extraction root. Returns the manifest.
+fn validate_archive(archive: &mut zip::ZipArchive<BufReader<File>>) -> Result<BackupManifest, String> {
+    let manifest: BackupManifest = {
+        let entry = archive
+            .by_name(BACKUP_MANIFEST)
+            .map_err(|_| format!("Not a Voicebox backup: {} is missing", BACKUP_MANIFEST))?;
+        serde_json::from_reader(entry)
+            .map_err(|e| format!("Not a Voicebox backup: unreadable {}: {}", BACKUP_MANIFEST, e))?
+    };
+    if archive.by_name(DATABASE_FILE).is_err() {
+        return Err(format!("Backup is incomplete: {} is missing", DATABASE_FILE));
+    }
+
+    for i in 0..archive.len() {
+        let entry = archive
+            .by_index(i)
+            .map_err(|e| format!("Failed to read backup: {}", e))?;
+        if entry.enclosed_name().is_none() {
+            return Err(format!("Backup contains an unsafe path: {}", entry.name()));
+        }
+    }
+
+    Ok(manifest)
+}
+
+/// Read the manifest of a backup archive without extracting anything, e.g. to
+/// check that a file handed to the app is a Voicebox backup.
+pub fn read_manifest(src: &Path) -> Result<BackupManifest, String> {
+    let file = File::open(src).map_err(|e| format!("Failed to open {}: {}", src.display(), e))?;
+    let mut archive = zip::ZipArchive::new(BufReader::new(file))
+        .map_err(|e| format!("Failed to read backup: {}", e))?;
+    validate_archive(&mut archive)
+}
+
+/// Restore a backup into `data_dir`. The archive is validated and extracted to
+/// a staging directory first; the data dir is only touched once that succeeds.
+/// With `merge` the backup's files overwrite matching ones and everything else
+/// is kept, otherwise the data dir's contents are replaced.
+pub fn import<F>(src: &Path, data_dir: &Path, merge: bool, on_progress: F) -> Result<RestoreResult, String>
+where
+    F: Fn(&BackupProgress),
+{
+    let file = File::open(src).map_err(|e| format!("Failed to open {}: {}", src.display(), e))?;
+    let mut archive = zip::ZipArchive::new(BufReader::new(file))
+        .map_err(|e| format!("Failed to read backup: {}", e))?;
+    let manifest = validate_archive(&mut archive)?;
+
+    let bytes_total: u64 = (0..archive.len())
+        .filter_map(|i| archive.by_index(i).ok().map(|e| e.size()))
+        .sum();
+    let available = storage::free_space(data_dir)?;
+    if available < bytes_total {
+        return Err(format!(
+            "Not enough free space to restore: {} bytes required, {} bytes available",
+            bytes_total, available
+        ));
+    }
+
+    // A sibling of the data dir is on the same volume, so moving files out of it is a rename
+    let staging_dir = staging_path(data_dir);
+    let _ = std::fs::remove_dir_all(&staging_dir);
+    std::fs::create_dir_all(&staging_dir)
+        .map_err(|e| format!("Failed to create staging directory: {}", e))?;
+
+    let (files_restored, bytes_restored) =
+        match extract(&mut archive, &staging_dir, bytes_total, &on_progress) {
+            Ok(counts) => counts,
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&staging_dir);
+                return Err(format!("{}. The data directory was not modified.", e));
+            }
+        };
+    let settings_json = std::fs::read_to_string(staging_dir.join(SETTINGS_FILE)).ok();
+    let _ = std::fs::remove_file(staging_dir.join(SETTINGS_FILE));
+    let _ = std::fs::remove_file(staging_dir.join(BACKUP_MANIFEST));
+
+    if !merge {
+        clear_data_dir(data_dir)?;
+    }
+    // Past this point the staged copy may be the only complete one, so keep it on failure
+    move_into(&staging_dir, data_dir).map_err(|e| {
+        format!(
+            "{}. The extracted backup was kept at {}",
+            e,
+            staging_dir.display()
+        )
+    })?;
+    let _ = std::fs::remove_dir_all(&staging_dir);
+
+    Ok(RestoreResult {
+        files_restored,
+        bytes_restored,
+        merged: merge,
+        manifest,
+        settings_json,
+    })
+}
+
+fn staging_path(data_dir: &Path) -> PathBuf {
+    let mut name = data_dir
+        .file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_default();
+    name.push(".restore");
+    data_dir.with_file_name(name)
+}
+
+fn extract<F>(
+    archive: &mut zip::ZipArchive<BufReader<File>>,
+    dest: &Path,
+    bytes_total: u64,
+    on_progress: &F,
+) -> Result<(usize, u64), String>
+where
+    F: Fn(&BackupProgress),
+{
+    let mut progress = BackupProgress {
+        files_done: 0,
+        files_total: archive.len(),
+        bytes_done: 0,
+        bytes_total,
+    };
+    on_progress(&progress);
+
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .map_err(|e| format!("Failed to read backup: {}", e))?;
+        // Already checked by validate_archive
+        let Some(relative_path) = entry.enclosed_name() else {
+            continue;
+        };
+        let out_path = dest.join(&relative_path);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&out_path)
+                .map_err(|e| format!("Failed to create {}: {}", relative_path.display(), e))?;
+        } else {
+            if let Some(parent) = out_path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+            }
+            let mut out_file = File::create(&out_path)
+                .map(BufWriter::new)
+                .map_err(|e| format!("Failed to create {}: {}", relative_path.display(), e))?;
+            std::io::copy(&mut entry, &mut out_file)
+                .map_err(|e| format!("Failed to extract {}: {}", relative_path.display(), e))?;
+        }
+
+        progress.files_done += 1;
+        progress.bytes_done += entry.size();
+        on_progress(&progress);
+    }
+
+    Ok((progress.files_done, progress.bytes_done))
+}
+
+/// Remove everything from the data dir except app-owned files.
+fn clear_data_dir(data_dir: &Path) -> Result<(), String> {
+    let entries = match std::fs::read_dir(data_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("Failed to read data dir: {}", e)),
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        if name == SETTINGS_FILE || name == SERVER_RECORD_FILE {
+            continue;
+        }
+        let path = entry.path();
+        let result = if path.is_dir() {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+        result.map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Move every file under `from` to the same relative path under `to`,
+/// overwriting existing files.
+fn move_into(from: &Path, to: &Path) -> Result<(), String> {
+    for file in storage::walk_files(from)? {
+        let source = from.join(&file.relative_path);
+        let dest = to.join(&file.relative_path);
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        std::fs::rename(&source, &dest)
+            .map_err(|e| format!("Failed to restore {}: {}", file.relative_path.display(), e))?;
+    }
+    Ok(())
+}
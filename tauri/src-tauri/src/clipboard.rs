@@ -0,0 +1,102 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Subdirectory of the system temp dir holding clips put on the clipboard.
+const TEMP_DIR: &str = "voicebox-clipboard";
+
+/// Clips older than this are removed at startup; by then they have been pasted or forgotten.
+const MAX_TEMP_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How the clip was put on the clipboard. Raw audio flavors are barely
+/// supported by other apps, so it always goes as a file reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipboardRepresentation {
+    /// CF_HDROP file list (Windows).
+    FileDrop,
+    /// NSPasteboard file URL (macOS).
+    FileUrl,
+    /// text/uri-list (Linux).
+    UriList,
+}
+
+impl ClipboardRepresentation {
+    fn current() -> Self {
+        if cfg!(windows) {
+            ClipboardRepresentation::FileDrop
+        } else if cfg!(target_os = "macos") {
+            ClipboardRepresentation::FileUrl
+        } else {
+            ClipboardRepresentation::UriList
+        }
+    }
+}
+
+fn temp_dir() -> PathBuf {
+    std::env::temp_dir().join(TEMP_DIR)
+}
+
+/// Write a WAV clip to the temp dir and put it on the clipboard as a file. Blocking.
+pub fn copy_wav(wav: &[u8]) -> Result<ClipboardRepresentation, String> {
+    let dir = temp_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let path = dir.join(format!("voicebox-{}.wav", millis));
+    std::fs::write(&path, wav).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    set_file(&path)?;
+    info!("Copied audio to the clipboard as {}", path.display());
+    Ok(ClipboardRepresentation::current())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_file(path: &Path) -> Result<(), String> {
+    let mut clipboard = arboard::Clipboard::new().map_err(|e| format!("Failed to open the clipboard: {}", e))?;
+    clipboard
+        .set()
+        .file_list(&[path])
+        .map_err(|e| format!("Failed to copy to the clipboard: {}", e))
+}
+
+/// X11 and Wayland clipboards are served by the owning process, so a thread
+/// keeps serving the file until another app takes the clipboard over.
+#[cfg(target_os = "linux")]
+fn set_file(path: &Path) -> Result<(), String> {
+    use arboard::SetExtLinux;
+
+    let mut clipboard = arboard::Clipboard::new().map_err(|e| format!("Failed to open the clipboard: {}", e))?;
+    let path = path.to_path_buf();
+    std::thread::spawn(move || {
+        if let Err(e) = clipboard.set().wait().file_list(&[&path]) {
+            warn!("Failed to copy to the clipboard: {}", e);
+        }
+    });
+    Ok(())
+}
+
+/// Remove clips left over from earlier sessions. Called from setup.
+pub fn cleanup_temp_files() {
+    let Ok(entries) = std::fs::read_dir(temp_dir()) else {
+        return;
+    };
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let expired = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age > MAX_TEMP_AGE);
+        if expired && std::fs::remove_file(entry.path()).is_ok() {
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        info!("Removed {} old clipboard clip(s)", removed);
+    }
+}
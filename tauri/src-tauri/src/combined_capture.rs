@@ -0,0 +1,361 @@
+use crate::audio_capture::{self, CaptureBackend, FrameSink, NORMALIZE_PEAK};
+use crate::audio_log::{self, AudioEvent};
+use crate::audio_util::{self, EncodeSpec};
+use crate::error::VoiceboxError;
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Both legs are resampled to this rate before they are put together.
+pub const COMBINED_SAMPLE_RATE: u32 = 48000;
+
+/// A leg's clock is only measured over at least this much of the capture.
+/// Over less, drift is well under a millisecond while the jitter in when
+/// deliveries arrive is not.
+pub const MIN_DRIFT_SPAN: Duration = Duration::from_secs(10);
+
+/// A measured clock further than this from its nominal rate is put down to
+/// a stall in delivery rather than drift, and the nominal rate is used.
+/// Device clocks stay within a few hundred ppm.
+pub const MAX_DRIFT_PPM: f64 = 2000.0;
+
+/// How the two legs share the WAV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CombinedLayout {
+    /// Stereo: system audio with the microphone added to both channels.
+    #[default]
+    Mixed,
+    /// Four channels: system audio left and right, then the microphone on
+    /// both of the second pair.
+    Separate,
+}
+
+impl CombinedLayout {
+    pub fn channels(&self) -> u16 {
+        match self {
+            CombinedLayout::Mixed => 2,
+            CombinedLayout::Separate => 4,
+        }
+    }
+}
+
+/// One leg's frames as delivered, with when the deliveries arrived.
+#[derive(Debug, Clone, Default)]
+pub struct LegRecording {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// When the first delivery arrived, and its frames.
+    first: Option<(Instant, u64)>,
+    last: Option<Instant>,
+}
+
+impl LegRecording {
+    /// Append a delivery that arrived at `now`.
+    pub fn push(&mut self, frames: &[f32], sample_rate: u32, channels: u16, now: Instant) {
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+        let count = frames.len() as u64 / channels.max(1) as u64;
+        self.first.get_or_insert((now, count));
+        self.last = Some(now);
+        self.samples.extend_from_slice(frames);
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.samples.len() as u64 / self.channels.max(1) as u64
+    }
+
+    /// When the first frame was recorded: the first delivery's arrival, less
+    /// the audio in it. None before anything arrived.
+    pub fn started_at(&self) -> Option<Instant> {
+        let (arrived, count) = self.first?;
+        let length = Duration::from_secs_f64(count as f64 / self.sample_rate.max(1) as f64);
+        Some(arrived.checked_sub(length).unwrap_or(arrived))
+    }
+
+    /// The rate frames came at by the monotonic clock, from the end of the
+    /// first delivery to the last one. The nominal rate when that span is
+    /// under MIN_DRIFT_SPAN or the result is beyond MAX_DRIFT_PPM.
+    pub fn clock_rate(&self) -> f64 {
+        let nominal = self.sample_rate as f64;
+        let (Some((first, first_frames)), Some(last)) = (self.first, self.last) else {
+            return nominal;
+        };
+        let span = last.duration_since(first);
+        if span < MIN_DRIFT_SPAN {
+            return nominal;
+        }
+        let measured = (self.frames() - first_frames) as f64 / span.as_secs_f64();
+        if drift_ppm(measured, nominal).abs() > MAX_DRIFT_PPM {
+            nominal
+        } else {
+            measured
+        }
+    }
+}
+
+fn drift_ppm(rate: f64, nominal: f64) -> f64 {
+    (rate / nominal - 1.0) * 1e6
+}
+
+/// Two legs put together by `combine`.
+#[derive(Debug, Clone)]
+pub struct Combined {
+    /// Interleaved, at COMBINED_SAMPLE_RATE.
+    pub samples: Vec<f32>,
+    pub channels: u16,
+    /// How long after system audio the microphone's first frame was
+    /// recorded; negative when it came first.
+    pub microphone_offset_ms: i64,
+    pub system_drift_ppm: i64,
+    pub microphone_drift_ppm: i64,
+}
+
+/// Put the legs of a combined capture into one stream at
+/// COMBINED_SAMPLE_RATE.
+///
+/// The legs start a few hundred milliseconds apart, so they are lined up on
+/// when each one's first frame was recorded: the later one starts with that
+/// much silence. Over a long capture the two devices' clocks drift apart, so
+/// each leg is resampled from the rate its frames actually came at against
+/// the monotonic clock (see `LegRecording::clock_rate`) rather than its
+/// nominal rate; both then keep time with the same clock and stay aligned.
+/// A mix that would clip is scaled down to NORMALIZE_PEAK.
+pub fn combine(
+    system: &LegRecording,
+    microphone: &LegRecording,
+    layout: CombinedLayout,
+) -> Result<Combined, VoiceboxError> {
+    let system_start = system
+        .started_at()
+        .ok_or_else(|| VoiceboxError::capture("No system audio was captured"))?;
+    let microphone_start = microphone
+        .started_at()
+        .ok_or_else(|| VoiceboxError::capture("The microphone delivered no audio"))?;
+    let start = system_start.min(microphone_start);
+    let lead = |leg_start: Instant| {
+        (leg_start.duration_since(start).as_secs_f64() * COMBINED_SAMPLE_RATE as f64).round() as usize
+    };
+
+    let system_samples = conform(system, to_stereo(&system.samples, system.channels), 2, lead(system_start));
+    let microphone_samples = conform(
+        microphone,
+        audio_util::downmix(&microphone.samples, microphone.channels),
+        1,
+        lead(microphone_start),
+    );
+
+    let channels = layout.channels();
+    let frames = (system_samples.len() / 2).max(microphone_samples.len());
+    let mut samples = Vec::with_capacity(frames * channels as usize);
+    for frame in 0..frames {
+        let left = system_samples.get(frame * 2).copied().unwrap_or(0.0);
+        let right = system_samples.get(frame * 2 + 1).copied().unwrap_or(0.0);
+        let voice = microphone_samples.get(frame).copied().unwrap_or(0.0);
+        match layout {
+            CombinedLayout::Mixed => samples.extend([left + voice, right + voice]),
+            CombinedLayout::Separate => samples.extend([left, right, voice, voice]),
+        }
+    }
+    if samples.iter().any(|sample| sample.abs() > 1.0) {
+        audio_util::normalize(&mut samples, NORMALIZE_PEAK);
+    }
+
+    let offset_ms = |later: Instant, earlier: Instant| later.duration_since(earlier).as_millis() as i64;
+    Ok(Combined {
+        samples,
+        channels,
+        microphone_offset_ms: if microphone_start >= system_start {
+            offset_ms(microphone_start, system_start)
+        } else {
+            -offset_ms(system_start, microphone_start)
+        },
+        system_drift_ppm: drift_ppm(system.clock_rate(), system.sample_rate as f64).round() as i64,
+        microphone_drift_ppm: drift_ppm(microphone.clock_rate(), microphone.sample_rate as f64).round() as i64,
+    })
+}
+
+/// `samples`, `leg`'s audio reshaped to `channels`, resampled from the
+/// leg's measured clock to COMBINED_SAMPLE_RATE behind `lead` frames of
+/// silence.
+fn conform(leg: &LegRecording, samples: Vec<f32>, channels: u16, lead: usize) -> Vec<f32> {
+    // In millihertz, so the drift correction isn't rounded away
+    let from_rate = (leg.clock_rate() * 1000.0).round() as u32;
+    let resampled = audio_util::resample(&samples, channels, from_rate, COMBINED_SAMPLE_RATE * 1000);
+    let mut out = vec![0.0; lead * channels as usize];
+    out.extend(resampled);
+    out
+}
+
+/// Mono doubled, or the first two channels of anything wider.
+fn to_stereo(samples: &[f32], channels: u16) -> Vec<f32> {
+    match channels {
+        0 | 2 => samples.to_vec(),
+        1 => samples.iter().flat_map(|&sample| [sample, sample]).collect(),
+        _ => samples.chunks(channels as usize).flat_map(|frame| [frame[0], frame[1]]).collect(),
+    }
+}
+
+struct Leg {
+    name: &'static str,
+    recording: Arc<Mutex<LegRecording>>,
+    sink: FrameSink,
+    stop: Arc<AtomicBool>,
+}
+
+impl Leg {
+    /// Start `backend` recording into a new leg, which stops itself after
+    /// `max_duration_secs`.
+    fn start(
+        name: &'static str,
+        backend: &dyn CaptureBackend,
+        max_duration_secs: u32,
+    ) -> Result<Self, VoiceboxError> {
+        let recording = Arc::new(Mutex::new(LegRecording::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (fed, limited) = (recording.clone(), stop.clone());
+        let sink = FrameSink::forwarding(Arc::new(move |frames, sample_rate, channels| {
+            let now = Instant::now();
+            let mut recording = fed.lock().unwrap();
+            if recording.frames() >= max_duration_secs as u64 * sample_rate as u64 {
+                limited.store(true, Ordering::Relaxed);
+                return;
+            }
+            recording.push(frames, sample_rate, channels, now);
+        }))
+        .including_own_audio();
+        if let Err(e) = backend.start(sink.clone(), stop.clone()) {
+            stop.store(true, Ordering::Relaxed);
+            return Err(match e {
+                // Kept as it is, so the UI can ask for the permission
+                VoiceboxError::Permission { .. } => e,
+                e => VoiceboxError::capture(format!("Failed to start recording the {}: {}", name, e.message())),
+            });
+        }
+        Ok(Self {
+            name,
+            recording,
+            sink,
+            stop,
+        })
+    }
+}
+
+struct Running {
+    system: Leg,
+    microphone: Leg,
+    layout: CombinedLayout,
+}
+
+/// System audio and the microphone recorded together into one WAV, for
+/// talking over a backing track. One at a time, and not alongside the app's
+/// own capture.
+pub struct CombinedCapture {
+    system: Arc<dyn CaptureBackend>,
+    microphone: Arc<dyn CaptureBackend>,
+    running: Mutex<Option<Running>>,
+}
+
+impl CombinedCapture {
+    /// Records through audio_capture's default backends.
+    pub fn new() -> Self {
+        Self::with_backends(audio_capture::default_backend(), audio_capture::default_microphone_backend())
+    }
+
+    pub fn with_backends(system: Arc<dyn CaptureBackend>, microphone: Arc<dyn CaptureBackend>) -> Self {
+        Self {
+            system,
+            microphone,
+            running: Mutex::new(None),
+        }
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.running.lock().unwrap().is_some()
+    }
+
+    /// Start both legs, each stopping on its own after `max_duration_secs`.
+    /// If either can't start, neither runs.
+    pub fn start(&self, max_duration_secs: u32, layout: CombinedLayout) -> Result<(), VoiceboxError> {
+        let mut running = self.running.lock().unwrap();
+        if running.is_some() {
+            return Err(VoiceboxError::busy("A combined capture is already running"));
+        }
+        let system = Leg::start("system audio", self.system.as_ref(), max_duration_secs)?;
+        let microphone = match Leg::start("microphone", self.microphone.as_ref(), max_duration_secs) {
+            Ok(leg) => leg,
+            Err(e) => {
+                system.stop.store(true, Ordering::Relaxed);
+                return Err(e);
+            }
+        };
+        info!(
+            "Combined capture started through {} and {}, {:?}",
+            self.system.name(),
+            self.microphone.name(),
+            layout
+        );
+        audio_log::record(AudioEvent::CombinedCaptureStarted {
+            system_backend: self.system.name(),
+            microphone_backend: self.microphone.name(),
+            max_duration_secs,
+        });
+        *running = Some(Running {
+            system,
+            microphone,
+            layout,
+        });
+        Ok(())
+    }
+
+    /// Stop both legs and return them put together (see `combine`) as base64
+    /// WAV. A leg that failed along the way fails the whole capture.
+    pub async fn stop(&self) -> Result<String, VoiceboxError> {
+        let running = self
+            .running
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| VoiceboxError::not_found("No combined capture is running"))?;
+        for leg in [&running.system, &running.microphone] {
+            leg.stop.store(true, Ordering::Relaxed);
+        }
+        tokio::time::sleep(self.system.drain_time().max(self.microphone.drain_time())).await;
+        for leg in [&running.system, &running.microphone] {
+            if let Some(error) = leg.sink.failure() {
+                return Err(VoiceboxError::capture(format!("Recording the {} failed: {}", leg.name, error)));
+            }
+        }
+
+        let system = std::mem::take(&mut *running.system.recording.lock().unwrap());
+        let microphone = std::mem::take(&mut *running.microphone.recording.lock().unwrap());
+        let layout = running.layout;
+        let wav = tokio::task::spawn_blocking(move || {
+            let combined = combine(&system, &microphone, layout)?;
+            let frames = combined.samples.len() as u64 / combined.channels as u64;
+            audio_log::record(AudioEvent::CombinedCaptureStopped {
+                duration_ms: frames * 1000 / COMBINED_SAMPLE_RATE as u64,
+                microphone_offset_ms: combined.microphone_offset_ms,
+                system_drift_ppm: combined.system_drift_ppm,
+                microphone_drift_ppm: combined.microphone_drift_ppm,
+            });
+            audio_util::encode_wav(
+                &combined.samples,
+                EncodeSpec::pcm16(COMBINED_SAMPLE_RATE, combined.channels),
+            )
+            .map_err(VoiceboxError::capture)
+        })
+        .await??;
+        Ok(general_purpose::STANDARD.encode(&wav))
+    }
+}
+
+impl Default for CombinedCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,298 @@
+use crate::error::VoiceboxError;
+use crate::metrics::METRICS;
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// One above the server's port.
+pub const DEFAULT_PORT: u16 = 17494;
+
+/// Shortest bearer token set_control_api accepts.
+pub const MIN_TOKEN_CHARS: usize = 16;
+
+/// How long stop() waits for requests in flight before dropping them.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// What a request asks the app to do. Each maps onto an existing command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlAction {
+    /// The capture and playback status.
+    Status,
+    StartCapture { max_duration_secs: Option<u32> },
+    /// Ends the capture and saves it to the recordings library.
+    StopCapture,
+    /// Marks the current position of the running capture.
+    AddMarker,
+    /// Plays a library recording. `devices` are output device ids or names;
+    /// empty for the default device.
+    PlayRecording { id: String, devices: Vec<String> },
+    StopPlayback,
+}
+
+impl ControlAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ControlAction::Status => "status",
+            ControlAction::StartCapture { .. } => "start_capture",
+            ControlAction::StopCapture => "stop_capture",
+            ControlAction::AddMarker => "add_marker",
+            ControlAction::PlayRecording { .. } => "play_recording",
+            ControlAction::StopPlayback => "stop_playback",
+        }
+    }
+}
+
+pub type ControlFuture = Pin<Box<dyn Future<Output = Result<serde_json::Value, VoiceboxError>> + Send>>;
+
+/// Carries out an action and returns the response body. The app's runs the
+/// matching command; tests substitute their own.
+pub type Dispatch = Arc<dyn Fn(ControlAction) -> ControlFuture + Send + Sync>;
+
+/// What get_control_api_status and set_control_api return.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ControlApiStatus {
+    pub running: bool,
+    /// Always a loopback address.
+    pub address: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StartCaptureBody {
+    max_duration_secs: Option<u32>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PlayRecordingBody {
+    devices: Vec<String>,
+}
+
+struct Api {
+    token: String,
+    dispatch: Dispatch,
+}
+
+struct Running {
+    address: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Running {
+    async fn stop(self) {
+        let _ = self.shutdown.send(());
+        let abort = self.task.abort_handle();
+        if tokio::time::timeout(SHUTDOWN_GRACE, self.task).await.is_err() {
+            warn!("Control API requests still running after {:?}, dropping them", SHUTDOWN_GRACE);
+            abort.abort();
+        }
+        info!("Control API on {} stopped", self.address);
+    }
+}
+
+/// The local HTTP control surface for stream decks and similar tools. Off
+/// unless enabled; every request needs the bearer token.
+#[derive(Default)]
+pub struct ControlApiState {
+    running: Mutex<Option<Running>>,
+}
+
+impl ControlApiState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve on 127.0.0.1:`port`, replacing the server already running. The
+    /// address is always loopback, whatever the settings say; port 0 picks a
+    /// free one.
+    pub async fn start(&self, port: u16, token: String, dispatch: Dispatch) -> Result<SocketAddr, VoiceboxError> {
+        if token.chars().count() < MIN_TOKEN_CHARS {
+            return Err(VoiceboxError::invalid_argument(format!(
+                "The control API token must be at least {} characters",
+                MIN_TOKEN_CHARS
+            )));
+        }
+        let mut running = self.running.lock().await;
+        if let Some(previous) = running.take() {
+            previous.stop().await;
+        }
+
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .await
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::AddrInUse => VoiceboxError::busy(format!("Port {} is already in use", port)),
+                _ => VoiceboxError::io(format!("Failed to listen on port {}: {}", port, e)),
+            })?;
+        let address = listener.local_addr()?;
+        let router = router(Arc::new(Api { token, dispatch }));
+        let (shutdown, signal) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            let served = axum::serve(listener, router).with_graceful_shutdown(async {
+                let _ = signal.await;
+            });
+            if let Err(e) = served.await {
+                warn!("Control API stopped with an error: {}", e);
+            }
+        });
+        info!("Control API listening on {}", address);
+        *running = Some(Running {
+            address,
+            shutdown,
+            task,
+        });
+        Ok(address)
+    }
+
+    /// Stop serving, letting requests in flight finish for a moment.
+    pub async fn stop(&self) {
+        if let Some(running) = self.running.lock().await.take() {
+            running.stop().await;
+        }
+    }
+
+    pub async fn status(&self) -> ControlApiStatus {
+        let address = self.running.lock().await.as_ref().map(|running| running.address.to_string());
+        ControlApiStatus {
+            running: address.is_some(),
+            address,
+        }
+    }
+}
+
+type ApiState = State<Arc<Api>>;
+
+fn router(api: Arc<Api>) -> Router {
+    Router::new()
+        .route(
+            "/v1/status",
+            get(|State(api): ApiState, headers: HeaderMap| async move {
+                api.handle(&headers, Ok(ControlAction::Status)).await
+            }),
+        )
+        .route(
+            "/v1/capture/start",
+            post(|State(api): ApiState, headers: HeaderMap, body: Bytes| async move {
+                let action = parse::<StartCaptureBody>(&body).map(|body| ControlAction::StartCapture {
+                    max_duration_secs: body.max_duration_secs,
+                });
+                api.handle(&headers, action).await
+            }),
+        )
+        .route(
+            "/v1/capture/stop",
+            post(|State(api): ApiState, headers: HeaderMap| async move {
+                api.handle(&headers, Ok(ControlAction::StopCapture)).await
+            }),
+        )
+        .route(
+            "/v1/capture/marker",
+            post(|State(api): ApiState, headers: HeaderMap| async move {
+                api.handle(&headers, Ok(ControlAction::AddMarker)).await
+            }),
+        )
+        .route(
+            "/v1/playback/recordings/:id",
+            post(
+                |State(api): ApiState, Path(id): Path<String>, headers: HeaderMap, body: Bytes| async move {
+                    let action = parse::<PlayRecordingBody>(&body).map(|body| ControlAction::PlayRecording {
+                        id,
+                        devices: body.devices,
+                    });
+                    api.handle(&headers, action).await
+                },
+            ),
+        )
+        .route(
+            "/v1/playback/stop",
+            post(|State(api): ApiState, headers: HeaderMap| async move {
+                api.handle(&headers, Ok(ControlAction::StopPlayback)).await
+            }),
+        )
+        .fallback(|State(api): ApiState, headers: HeaderMap| async move {
+            api.handle(&headers, Err(VoiceboxError::not_found("There is no such endpoint"))).await
+        })
+        .with_state(api)
+}
+
+/// A JSON body, or the defaults for an empty one.
+fn parse<T: Default + DeserializeOwned>(body: &[u8]) -> Result<T, VoiceboxError> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(T::default());
+    }
+    serde_json::from_slice(body).map_err(|e| VoiceboxError::invalid_argument(format!("Invalid request body: {}", e)))
+}
+
+impl Api {
+    /// Check the token, run `action` and answer with its result or error,
+    /// counting both.
+    async fn handle(&self, headers: &HeaderMap, action: Result<ControlAction, VoiceboxError>) -> Response {
+        let started = Instant::now();
+        METRICS.control_api_requests.inc();
+        let response = if !self.authorized(headers) {
+            METRICS.control_api_errors.inc();
+            debug!("Control API request refused: missing or wrong token");
+            let error = VoiceboxError::permission("control_api", "Missing or wrong bearer token");
+            (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")], Json(error)).into_response()
+        } else {
+            let name = action.as_ref().map_or("invalid", ControlAction::as_str);
+            match action {
+                Ok(action) => (self.dispatch)(action).await,
+                Err(e) => Err(e),
+            }
+            .map_or_else(
+                |e| {
+                    METRICS.control_api_errors.inc();
+                    warn!("Control API {} failed: {}", name, e);
+                    (status_for(&e), Json(e)).into_response()
+                },
+                |value| {
+                    debug!("Control API {} succeeded", name);
+                    Json(value).into_response()
+                },
+            )
+        };
+        METRICS.control_api_request_ms.record(started.elapsed().as_millis() as u64);
+        response
+    }
+
+    fn authorized(&self, headers: &HeaderMap) -> bool {
+        let Some(token) = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        else {
+            return false;
+        };
+        // Compare every byte, so the time taken doesn't tell how much matched
+        token.len() == self.token.len()
+            && token.bytes().zip(self.token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+}
+
+fn status_for(error: &VoiceboxError) -> StatusCode {
+    match error {
+        VoiceboxError::InvalidArgument { .. } => StatusCode::BAD_REQUEST,
+        VoiceboxError::Permission { .. } => StatusCode::FORBIDDEN,
+        VoiceboxError::NotFound { .. } => StatusCode::NOT_FOUND,
+        VoiceboxError::Busy { .. } => StatusCode::CONFLICT,
+        VoiceboxError::Unsupported { .. } => StatusCode::NOT_IMPLEMENTED,
+        VoiceboxError::InsufficientSpace { .. } | VoiceboxError::QuotaExceeded { .. } => {
+            StatusCode::INSUFFICIENT_STORAGE
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
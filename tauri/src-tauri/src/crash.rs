@@ -0,0 +1,243 @@
+use serde::Serialize;
+
+/// Below this share of available memory a SIGKILL we didn't send is treated as
+/// the OOM killer even when the kernel log can't be read.
+const LOW_MEMORY_PERCENT: u64 = 10;
+
+/// Server output lines sent with `server-crashed`.
+pub const CRASH_TAIL_LINES: usize = 50;
+
+/// Why the server process went away without being asked to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CrashReason {
+    OutOfMemory,
+    Signal { signal: i32 },
+    ExitCode { code: i32 },
+    Unknown,
+}
+
+impl CrashReason {
+    /// What to tell the user, for reasons where there's something to suggest.
+    pub fn advice(&self) -> Option<&'static str> {
+        match self {
+            CrashReason::OutOfMemory => Some(
+                "The server ran out of memory. Close other applications, use a smaller model, \
+                 or generate shorter clips.",
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Payload of the `server-crashed` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerCrash {
+    pub pid: u32,
+    pub reason: CrashReason,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub memory: Option<MemorySnapshot>,
+    pub tail_lines: Vec<String>,
+    pub advice: Option<String>,
+    /// False once OOM crashes repeat, so the server isn't restarted into the same wall.
+    pub restart_allowed: bool,
+    /// When the app starts the server again on its own; None if it won't,
+    /// because auto-restart is off, the crash is an OOM repeat or the server
+    /// keeps crashing.
+    pub auto_restart_in_ms: Option<u64>,
+}
+
+/// System memory at the time the crash was noticed.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct MemorySnapshot {
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+}
+
+impl MemorySnapshot {
+    fn is_low(&self) -> bool {
+        self.total_bytes > 0 && self.available_bytes * 100 / self.total_bytes < LOW_MEMORY_PERCENT
+    }
+}
+
+/// Restarting after an OOM kill usually just reloads the model into the same
+/// squeeze, so only one automatic retry is allowed for those.
+pub const MAX_OOM_RESTARTS: u32 = 1;
+
+/// Whether an automatic restart is reasonable after this crash, given how many
+/// OOM crashes (including this one) happened since the user last stopped the server.
+pub fn restart_allowed(reason: &CrashReason, oom_crashes: u32) -> bool {
+    match reason {
+        CrashReason::OutOfMemory => oom_crashes <= MAX_OOM_RESTARTS,
+        _ => true,
+    }
+}
+
+/// Classify an unexpected exit. Blocking: may shell out to read system logs.
+pub fn classify_exit(pid: u32, code: Option<i32>, signal: Option<i32>) -> (CrashReason, Option<MemorySnapshot>) {
+    let memory = memory_snapshot();
+
+    #[cfg(unix)]
+    if signal == Some(libc::SIGKILL) {
+        // We never SIGKILL a server we didn't mean to stop, so something else did
+        let low_memory = memory.map(|m| m.is_low()).unwrap_or(false);
+        if low_memory || oom_logged(pid) {
+            return (CrashReason::OutOfMemory, memory);
+        }
+    }
+
+    #[cfg(windows)]
+    if let Some(code) = code {
+        // STATUS_NO_MEMORY / STATUS_COMMITMENT_LIMIT
+        const OOM_STATUSES: [u32; 2] = [0xC000_0017, 0xC000_012D];
+        let low_memory = memory.map(|m| m.is_low()).unwrap_or(false);
+        if OOM_STATUSES.contains(&(code as u32)) || (low_memory && oom_logged(pid)) {
+            return (CrashReason::OutOfMemory, memory);
+        }
+    }
+
+    let reason = match (signal, code) {
+        (Some(signal), _) => CrashReason::Signal { signal },
+        (None, Some(code)) => CrashReason::ExitCode { code },
+        (None, None) => CrashReason::Unknown,
+    };
+    (reason, memory)
+}
+
+/// Look for an OOM-killer record naming `pid` in the kernel log. Needs read
+/// access to the log, so a miss proves nothing.
+#[cfg(target_os = "linux")]
+fn oom_logged(pid: u32) -> bool {
+    use std::process::Command;
+
+    let mentions_pid = |output: &[u8]| {
+        let text = String::from_utf8_lossy(output);
+        let needle = format!("process {}", pid);
+        text.lines().any(|line| {
+            let lower = line.to_lowercase();
+            (lower.contains("out of memory") || lower.contains("oom-kill") || lower.contains("oom_reaper"))
+                && line.contains(&needle)
+        })
+    };
+
+    let journal = Command::new("journalctl")
+        .args(["-k", "--since", "-10min", "--no-pager", "-q"])
+        .output();
+    if let Ok(output) = journal {
+        if output.status.success() && mentions_pid(&output.stdout) {
+            return true;
+        }
+    }
+    Command::new("dmesg")
+        .output()
+        .map(|output| output.status.success() && mentions_pid(&output.stdout))
+        .unwrap_or(false)
+}
+
+/// macOS kills memory hogs through jetsam, which logs via memorystatus.
+#[cfg(target_os = "macos")]
+fn oom_logged(pid: u32) -> bool {
+    use std::process::Command;
+
+    Command::new("log")
+        .args([
+            "show",
+            "--last",
+            "10m",
+            "--style",
+            "compact",
+            "--predicate",
+            "sender == \"kernel\" AND eventMessage CONTAINS \"memorystatus\"",
+        ])
+        .output()
+        .map(|output| {
+            let text = String::from_utf8_lossy(&output.stdout);
+            let needle = format!("pid {}", pid);
+            text.lines().any(|line| line.contains(&needle))
+        })
+        .unwrap_or(false)
+}
+
+/// Windows logs commit-limit exhaustion through the Resource-Exhaustion-Detector.
+/// Its events name the top consumers, so look for our pid among them.
+#[cfg(windows)]
+fn oom_logged(pid: u32) -> bool {
+    use std::os::windows::process::CommandExt;
+    use std::process::Command;
+    const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+    let query = "*[System[Provider[@Name='Microsoft-Windows-Resource-Exhaustion-Detector'] \
+                 and TimeCreated[timediff(@SystemTime) <= 600000]]]";
+    Command::new("wevtutil")
+        .args(["qe", "System", &format!("/q:{}", query), "/f:text", "/rd:true", "/c:5"])
+        .creation_flags(CREATE_NO_WINDOW)
+        .output()
+        .map(|output| {
+            let text = String::from_utf8_lossy(&output.stdout);
+            text.contains(&format!("({})", pid)) || text.contains("voicebox-server")
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn oom_logged(_pid: u32) -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn memory_snapshot() -> Option<MemorySnapshot> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let field = |name: &str| -> Option<u64> {
+        meminfo
+            .lines()
+            .find(|line| line.starts_with(name))?
+            .split_whitespace()
+            .nth(1)?
+            .parse::<u64>()
+            .ok()
+            .map(|kb| kb * 1024)
+    };
+    Some(MemorySnapshot {
+        total_bytes: field("MemTotal:")?,
+        available_bytes: field("MemAvailable:")?,
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn memory_snapshot() -> Option<MemorySnapshot> {
+    use std::process::Command;
+
+    let sysctl = |name: &str| -> Option<u64> {
+        let output = Command::new("sysctl").args(["-n", name]).output().ok()?;
+        String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+    };
+    let total_bytes = sysctl("hw.memsize")?;
+    // Percentage of memory the kernel considers available
+    let level = sysctl("kern.memorystatus_level")?;
+    Some(MemorySnapshot {
+        total_bytes,
+        available_bytes: total_bytes / 100 * level,
+    })
+}
+
+#[cfg(windows)]
+fn memory_snapshot() -> Option<MemorySnapshot> {
+    use windows::Win32::System::SystemInformation::{GlobalMemoryStatusEx, MEMORYSTATUSEX};
+
+    let mut status = MEMORYSTATUSEX {
+        dwLength: std::mem::size_of::<MEMORYSTATUSEX>() as u32,
+        ..Default::default()
+    };
+    unsafe { GlobalMemoryStatusEx(&mut status).ok()? };
+    // The commit limit, not physical RAM, is what takes processes down on Windows
+    Some(MemorySnapshot {
+        total_bytes: status.ullTotalPageFile,
+        available_bytes: status.ullAvailPageFile,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn memory_snapshot() -> Option<MemorySnapshot> {
+    None
+}
@@ -0,0 +1,183 @@
+use serde::Serialize;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Crash logs kept; older ones are deleted when a new one is written.
+const MAX_CRASH_LOGS: usize = 10;
+
+/// Holds the file name of a crash log the frontend hasn't picked up yet.
+const PENDING_MARKER: &str = "crash-pending";
+
+static LOGS_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Given the panic message.
+type PanicCallback = Box<dyn Fn(&str)>;
+
+thread_local! {
+    /// Run by the panic hook on the panicking thread, before it unwinds.
+    static ON_PANIC: RefCell<Option<PanicCallback>> = RefCell::new(None);
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CrashReport {
+    pub file: String,
+    pub contents: String,
+    /// Unix seconds the log was written.
+    pub crashed_at: u64,
+    /// True the first time this report is returned after the crash.
+    pub pending: bool,
+}
+
+/// Chain a hook onto the default one that writes each panic to
+/// `<logs dir>/crash-<timestamp>.log`. Called first thing in main(), before
+/// the logs dir is known; panics until then go to the temp dir.
+pub fn install() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let message = panic_message(info);
+        ON_PANIC.with(|callback| {
+            if let Some(callback) = callback.borrow().as_ref() {
+                callback(&message);
+            }
+        });
+        if let Err(e) = write_report(&message) {
+            eprintln!("Failed to write crash log: {}", e);
+        }
+        default_hook(info);
+    }));
+}
+
+/// Where crash logs go from now on. Called from setup.
+pub fn set_logs_dir(dir: PathBuf) {
+    let _ = LOGS_DIR.set(dir);
+}
+
+fn logs_dir() -> PathBuf {
+    LOGS_DIR
+        .get()
+        .cloned()
+        .unwrap_or_else(|| std::env::temp_dir().join("voicebox-logs"))
+}
+
+/// Run `callback` with the panic message if the current thread panics. For
+/// worker threads whose owner would otherwise wait on them forever.
+pub fn on_this_thread_panic(callback: impl Fn(&str) + 'static) {
+    ON_PANIC.with(|slot| *slot.borrow_mut() = Some(Box::new(callback)));
+}
+
+fn panic_message(info: &std::panic::PanicHookInfo) -> String {
+    let payload = info
+        .payload()
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| info.payload().downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Box<dyn Any>".to_string());
+    match info.location() {
+        Some(location) => format!("{} at {}:{}", payload, location.file(), location.line()),
+        None => payload,
+    }
+}
+
+fn write_report(message: &str) -> std::io::Result<()> {
+    let dir = logs_dir();
+    std::fs::create_dir_all(&dir)?;
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let thread = std::thread::current();
+    let report = format!(
+        "Voicebox {} crashed\n\
+         Time: {}\n\
+         OS: {} {} ({})\n\
+         Thread: {}\n\
+         Panic: {}\n\
+         \n\
+         Backtrace:\n{}\n",
+        env!("CARGO_PKG_VERSION"),
+        now.as_secs(),
+        std::env::consts::OS,
+        os_version(),
+        std::env::consts::ARCH,
+        thread.name().unwrap_or("<unnamed>"),
+        message,
+        std::backtrace::Backtrace::force_capture(),
+    );
+
+    let name = format!("crash-{}.log", now.as_millis());
+    std::fs::write(dir.join(&name), report)?;
+    std::fs::write(dir.join(PENDING_MARKER), &name)?;
+    eprintln!("Crash log written to {}", dir.join(&name).display());
+    prune(&dir);
+    Ok(())
+}
+
+/// Best effort; a console-less way to ask Windows isn't worth it here.
+fn os_version() -> String {
+    let version = if cfg!(target_os = "macos") {
+        std::process::Command::new("sw_vers")
+            .arg("-productVersion")
+            .output()
+            .ok()
+            .map(|o| String::from_utf8_lossy(&o.stdout).into_owned())
+    } else {
+        std::fs::read_to_string("/proc/sys/kernel/osrelease").ok()
+    };
+    version
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "unknown version".to_string())
+}
+
+/// Crash logs in `dir`, newest first. Names sort by their millisecond timestamp.
+fn crash_logs(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut logs: Vec<(u128, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let millis = name.strip_prefix("crash-")?.strip_suffix(".log")?.parse().ok()?;
+            Some((millis, entry.path()))
+        })
+        .collect();
+    logs.sort_by_key(|(millis, _)| std::cmp::Reverse(*millis));
+    logs.into_iter().map(|(_, path)| path).collect()
+}
+
+fn prune(dir: &Path) {
+    for old in crash_logs(dir).into_iter().skip(MAX_CRASH_LOGS) {
+        let _ = std::fs::remove_file(old);
+    }
+}
+
+/// The newest crash log, if any, and clear the pending marker so the frontend
+/// only offers to send it once.
+pub fn last_report() -> Result<Option<CrashReport>, String> {
+    let dir = logs_dir();
+    let Some(path) = crash_logs(&dir).into_iter().next() else {
+        return Ok(None);
+    };
+    let file = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let contents =
+        std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let crashed_at = file
+        .strip_prefix("crash-")
+        .and_then(|s| s.strip_suffix(".log"))
+        .and_then(|s| s.parse::<u128>().ok())
+        .map_or(0, |millis| (millis / 1000) as u64);
+
+    let marker = dir.join(PENDING_MARKER);
+    let pending = std::fs::read_to_string(&marker).is_ok_and(|name| name.trim() == file);
+    if marker.exists() {
+        let _ = std::fs::remove_file(&marker);
+    }
+    Ok(Some(CrashReport {
+        file,
+        contents,
+        crashed_at,
+        pending,
+    }))
+}
@@ -0,0 +1,195 @@
+use crate::settings::SETTINGS_FILE;
+use crate::storage;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationProgress {
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationResult {
+    pub old_path: PathBuf,
+    pub new_path: PathBuf,
+    pub files_copied: usize,
+    pub bytes_copied: u64,
+    pub old_removed: bool,
+    /// Set when the copy succeeded but deleting the old tree did not.
+    pub cleanup_error: Option<String>,
+}
+
+/// Files in the data dir that belong to the app rather than the server's data
+/// and therefore stay where they are.
+fn is_app_owned(relative_path: &Path) -> bool {
+    relative_path == Path::new(SETTINGS_FILE)
+}
+
+/// Check that `new_path` can receive the contents of `old_path`.
+pub fn validate_destination(old_path: &Path, new_path: &Path) -> Result<(), String> {
+    if !new_path.is_absolute() {
+        return Err(format!("Destination must be an absolute path: {}", new_path.display()));
+    }
+
+    let old_canonical = old_path
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve current data dir: {}", e))?;
+    // The destination may not exist yet; resolve through its closest existing ancestor
+    let ancestor = storage::existing_ancestor(new_path)
+        .ok_or_else(|| format!("Destination is not reachable: {}", new_path.display()))?;
+    let ancestor_canonical = ancestor
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve destination: {}", e))?;
+    let new_canonical = match new_path.strip_prefix(&ancestor) {
+        Ok(rest) => ancestor_canonical.join(rest),
+        Err(_) => ancestor_canonical,
+    };
+
+    if new_canonical == old_canonical {
+        return Err("Destination is the current data directory".to_string());
+    }
+    if new_canonical.starts_with(&old_canonical) {
+        return Err("Destination cannot be inside the current data directory".to_string());
+    }
+    if old_canonical.starts_with(&new_canonical) {
+        return Err("Destination cannot contain the current data directory".to_string());
+    }
+
+    if new_path.exists() {
+        if !new_path.is_dir() {
+            return Err(format!("Destination is not a directory: {}", new_path.display()));
+        }
+        let is_empty = std::fs::read_dir(new_path)
+            .map_err(|e| format!("Failed to read destination: {}", e))?
+            .next()
+            .is_none();
+        if !is_empty {
+            return Err(format!("Destination is not empty: {}", new_path.display()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy the data dir to `new_path`, verify the copy, and optionally remove the
+/// old tree. The settings file is left in place. On any failure before
+/// verification completes, the partial copy is removed and the old directory
+/// is left untouched.
+pub fn migrate<F>(old_path: &Path, new_path: &Path, keep_old: bool, on_progress: F) -> Result<MigrationResult, String>
+where
+    F: Fn(&MigrationProgress),
+{
+    validate_destination(old_path, new_path)?;
+
+    let files: Vec<_> = storage::walk_files(old_path)?
+        .into_iter()
+        .filter(|f| !is_app_owned(&f.relative_path))
+        .collect();
+    let bytes_total: u64 = files.iter().map(|f| f.size).sum();
+
+    let available = storage::free_space(new_path)?;
+    if available < bytes_total {
+        return Err(format!(
+            "Not enough free space at destination: {} bytes required, {} bytes available",
+            bytes_total, available
+        ));
+    }
+
+    let created_root = !new_path.exists();
+    std::fs::create_dir_all(new_path).map_err(|e| format!("Failed to create destination: {}", e))?;
+
+    let cleanup = || {
+        if created_root {
+            let _ = std::fs::remove_dir_all(new_path);
+        } else if let Ok(entries) = std::fs::read_dir(new_path) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let _ = if path.is_dir() {
+                    std::fs::remove_dir_all(&path)
+                } else {
+                    std::fs::remove_file(&path)
+                };
+            }
+        }
+    };
+
+    let mut progress = MigrationProgress {
+        files_done: 0,
+        files_total: files.len(),
+        bytes_done: 0,
+        bytes_total,
+    };
+    on_progress(&progress);
+
+    for file in &files {
+        let source = old_path.join(&file.relative_path);
+        let dest = new_path.join(&file.relative_path);
+        let copy_result = dest
+            .parent()
+            .map(std::fs::create_dir_all)
+            .unwrap_or(Ok(()))
+            .and_then(|_| std::fs::copy(&source, &dest));
+        if let Err(e) = copy_result {
+            cleanup();
+            return Err(format!(
+                "Copied {} of {} files before failing on {}: {}. The original data directory was not modified.",
+                progress.files_done,
+                progress.files_total,
+                file.relative_path.display(),
+                e
+            ));
+        }
+        progress.files_done += 1;
+        progress.bytes_done += file.size;
+        on_progress(&progress);
+    }
+
+    // Verify every file arrived with the expected size before touching the original
+    for file in &files {
+        let dest = new_path.join(&file.relative_path);
+        let copied_size = std::fs::metadata(&dest).map(|m| m.len()).ok();
+        if copied_size != Some(file.size) {
+            cleanup();
+            return Err(format!(
+                "Verification failed for {}: expected {} bytes, found {:?}. The original data directory was not modified.",
+                file.relative_path.display(),
+                file.size,
+                copied_size
+            ));
+        }
+    }
+
+    Ok(MigrationResult {
+        old_path: old_path.to_path_buf(),
+        new_path: new_path.to_path_buf(),
+        files_copied: progress.files_done,
+        bytes_copied: progress.bytes_done,
+        old_removed: !keep_old,
+        cleanup_error: None,
+    })
+}
+
+/// Delete the migrated contents of the old data dir, keeping app-owned files.
+pub fn remove_old_tree(old_path: &Path) -> Result<(), String> {
+    let entries = std::fs::read_dir(old_path)
+        .map_err(|e| format!("Failed to read old data dir: {}", e))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let relative = path.strip_prefix(old_path).unwrap_or(&path);
+        if is_app_owned(relative) {
+            continue;
+        }
+        let result = if path.is_dir() {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+        result.map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+    }
+    // Only succeeds once nothing is left, i.e. when the old dir was not the app's own
+    let _ = std::fs::remove_dir(old_path);
+    Ok(())
+}
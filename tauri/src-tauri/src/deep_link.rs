@@ -0,0 +1,159 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+use tracing::{info, warn};
+
+pub const SCHEME: &str = "voicebox";
+
+/// Same limit the server puts on GenerationRequest.text.
+pub const MAX_TEXT_CHARS: usize = 5000;
+/// Limit for every other parameter.
+const MAX_PARAM_CHARS: usize = 500;
+/// Links longer than this are rejected before parsing.
+const MAX_LINK_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeepLinkAction {
+    /// `voicebox://generate?voice=..&text=..`: open the generator pre-filled.
+    Generate,
+    /// `voicebox://open?view=..`: bring the app forward, optionally on a view.
+    Open,
+}
+
+impl DeepLinkAction {
+    fn parse(action: &str) -> Option<Self> {
+        match action {
+            "generate" => Some(DeepLinkAction::Generate),
+            "open" => Some(DeepLinkAction::Open),
+            _ => None,
+        }
+    }
+
+    /// Query parameters the action understands; anything else is dropped.
+    fn params(&self) -> &'static [&'static str] {
+        match self {
+            DeepLinkAction::Generate => &["voice", "text", "language", "instruct"],
+            DeepLinkAction::Open => &["view"],
+        }
+    }
+}
+
+/// Payload of the `deep-link` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeepLink {
+    pub action: DeepLinkAction,
+    /// Percent-decoded query parameters.
+    pub params: BTreeMap<String, String>,
+}
+
+/// Payload of the `deep-link-unknown` event, for links we couldn't act on.
+#[derive(Debug, Clone, Serialize)]
+pub struct UnhandledDeepLink {
+    pub url: String,
+    pub reason: String,
+}
+
+/// Validate a `voicebox://` URL and extract its action and parameters.
+pub fn parse(url: &str) -> Result<DeepLink, String> {
+    if url.len() > MAX_LINK_BYTES {
+        return Err(format!("Link is longer than {} bytes", MAX_LINK_BYTES));
+    }
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid link: {}", e))?;
+    if parsed.scheme() != SCHEME {
+        return Err(format!("Unsupported scheme '{}'", parsed.scheme()));
+    }
+
+    // voicebox://generate parses with "generate" as the host
+    let action_name = parsed
+        .host_str()
+        .map(|h| h.to_lowercase())
+        .unwrap_or_else(|| parsed.path().trim_matches('/').to_lowercase());
+    let action = DeepLinkAction::parse(&action_name)
+        .ok_or_else(|| format!("Unknown action '{}'", action_name))?;
+
+    let mut params = BTreeMap::new();
+    for (key, value) in parsed.query_pairs() {
+        if !action.params().contains(&key.as_ref()) {
+            info!("Ignoring unknown deep link parameter '{}'", key);
+            continue;
+        }
+        let limit = if key == "text" { MAX_TEXT_CHARS } else { MAX_PARAM_CHARS };
+        if value.chars().count() > limit {
+            return Err(format!("Parameter '{}' is longer than {} characters", key, limit));
+        }
+        params.insert(key.into_owned(), value.into_owned());
+    }
+
+    Ok(DeepLink { action, params })
+}
+
+enum PendingLink {
+    Link(DeepLink),
+    Unhandled(UnhandledDeepLink),
+}
+
+/// Links that arrived before the frontend called deep_link_ready.
+pub struct DeepLinkState {
+    ready: Mutex<bool>,
+    pending: Mutex<Vec<PendingLink>>,
+}
+
+impl DeepLinkState {
+    pub fn new() -> Self {
+        Self {
+            ready: Mutex::new(false),
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+fn emit(app: &AppHandle, link: PendingLink) {
+    let result = match link {
+        PendingLink::Link(link) => crate::event_bus::emit(app, "deep-link", link),
+        PendingLink::Unhandled(link) => crate::event_bus::emit(app, "deep-link-unknown", link),
+    };
+    if let Err(e) = result {
+        warn!("Failed to emit deep link event: {}", e);
+    }
+}
+
+/// Parse a link and deliver it to the frontend, or queue it until the
+/// frontend is listening.
+pub fn dispatch(app: &AppHandle, url: &str) {
+    info!("Deep link received: {}", url);
+    let link = match parse(url) {
+        Ok(link) => PendingLink::Link(link),
+        Err(reason) => {
+            warn!("Unhandled deep link {}: {}", url, reason);
+            PendingLink::Unhandled(UnhandledDeepLink {
+                url: url.to_string(),
+                reason,
+            })
+        }
+    };
+
+    let state = app.state::<DeepLinkState>();
+    // Check and queue under the same lock so mark_ready can't slip in between
+    let ready = state.ready.lock().unwrap();
+    if *ready {
+        drop(ready);
+        emit(app, link);
+    } else {
+        state.pending.lock().unwrap().push(link);
+    }
+}
+
+/// Called once the frontend listens for deep link events; flushes the queue.
+pub fn mark_ready(app: &AppHandle) {
+    let state = app.state::<DeepLinkState>();
+    let pending = {
+        let mut ready = state.ready.lock().unwrap();
+        *ready = true;
+        std::mem::take(&mut *state.pending.lock().unwrap())
+    };
+    for link in pending {
+        emit(app, link);
+    }
+}
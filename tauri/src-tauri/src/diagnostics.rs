@@ -0,0 +1,326 @@
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Stands in for anything redacted.
+pub const REDACTED: &str = "[redacted]";
+
+/// Only the end of a log file goes in; the start is rarely what support needs.
+const MAX_LOG_FILE_BYTES: u64 = 2 * 1024 * 1024;
+
+/// Object keys whose string values are secrets, matched case-insensitively
+/// as substrings (`auth_token`, `apiKey`, ...).
+const SECRET_KEYS: [&str; 5] = ["token", "secret", "password", "apikey", "api_key"];
+
+/// Text markers followed by a secret: auth headers and query parameters.
+const SECRET_MARKERS: [&str; 5] = ["Bearer ", "bearer ", "token=", "api_key=", "password="];
+
+/// Account names shorter than this are left alone; they would match inside
+/// too many ordinary words.
+const MIN_USER_NAME_LEN: usize = 3;
+
+/// Scrubs secrets, the user's home directory and their account name (which
+/// device names like "alice's AirPods" can contain) from everything in a
+/// bundle.
+pub struct Redactor {
+    home: Option<String>,
+    /// The home directory's last component.
+    user: Option<String>,
+    secrets: Vec<String>,
+}
+
+impl Redactor {
+    /// `secrets` are known values (e.g. the external server token) removed
+    /// wherever they appear; empty ones are ignored.
+    pub fn new(home: Option<&Path>, secrets: Vec<String>) -> Self {
+        let home = home
+            .map(|home| home.to_string_lossy().trim_end_matches(['/', '\\']).to_string())
+            .filter(|home| !home.is_empty());
+        let user = home
+            .as_deref()
+            .and_then(|home| home.rsplit(['/', '\\']).next())
+            .filter(|user| user.len() >= MIN_USER_NAME_LEN)
+            .map(str::to_string);
+        let secrets = secrets.into_iter().filter(|secret| !secret.is_empty()).collect();
+        Self { home, user, secrets }
+    }
+
+    pub fn redact_str(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for secret in &self.secrets {
+            text = text.replace(secret.as_str(), REDACTED);
+        }
+        for marker in SECRET_MARKERS {
+            text = redact_after(&text, marker);
+        }
+        if let Some(home) = &self.home {
+            text = text.replace(home.as_str(), "~");
+        }
+        if let Some(user) = &self.user {
+            text = redact_word(&text, user);
+        }
+        text
+    }
+
+    /// Redact every string in `value`, and replace string values under keys
+    /// that name a secret outright.
+    pub fn redact_json(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(text) => *text = self.redact_str(text),
+            serde_json::Value::Array(items) => items.iter_mut().for_each(|item| self.redact_json(item)),
+            serde_json::Value::Object(map) => {
+                for (key, item) in map.iter_mut() {
+                    if is_secret_key(key) && item.is_string() {
+                        *item = serde_json::Value::String(REDACTED.to_string());
+                    } else {
+                        self.redact_json(item);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_lowercase();
+    SECRET_KEYS.iter().any(|secret| key.contains(secret))
+}
+
+/// Replace each whole-word `word` in `text`, ignoring ASCII case.
+fn redact_word(text: &str, word: &str) -> String {
+    let lower = text.to_ascii_lowercase();
+    let word = word.to_ascii_lowercase();
+    let is_word_char = |c: Option<char>| c.is_some_and(char::is_alphanumeric);
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (start, _) in lower.match_indices(&word) {
+        let end = start + word.len();
+        if is_word_char(text[..start].chars().next_back()) || is_word_char(text[end..].chars().next()) {
+            continue;
+        }
+        out.push_str(&text[copied..start]);
+        out.push_str(REDACTED);
+        copied = end;
+    }
+    out.push_str(&text[copied..]);
+    out
+}
+
+/// Replace the run of token characters after each `marker`.
+fn redact_after(text: &str, marker: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(index) = rest.find(marker) {
+        let (before, after) = rest.split_at(index + marker.len());
+        out.push_str(before);
+        let end = after
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "-._~+/=".contains(c)))
+            .unwrap_or(after.len());
+        if end > 0 {
+            out.push_str(REDACTED);
+        }
+        rest = &after[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// One entry of the manifest: where a section's data is in the archive, or
+/// why it is missing.
+#[derive(Debug, Clone, Serialize)]
+pub struct SectionEntry {
+    pub name: String,
+    pub files: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// Contents of manifest.json.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticsManifest {
+    /// Unix seconds.
+    pub created_at: u64,
+    pub app_version: String,
+    pub os: String,
+    pub os_version: Option<String>,
+    pub arch: String,
+    pub sections: Vec<SectionEntry>,
+}
+
+/// A diagnostics archive being put together. A section that failed to
+/// gather is recorded in the manifest instead of failing the export.
+pub struct DiagnosticsBundle {
+    redactor: Redactor,
+    files: Vec<(String, Vec<u8>)>,
+    sections: Vec<SectionEntry>,
+}
+
+impl DiagnosticsBundle {
+    pub fn new(redactor: Redactor) -> Self {
+        Self {
+            redactor,
+            files: Vec::new(),
+            sections: Vec::new(),
+        }
+    }
+
+    /// Add `<name>.json`, redacted.
+    pub fn add_json<T: Serialize>(&mut self, name: &str, value: Result<T, String>) {
+        let result = value
+            .and_then(|value| serde_json::to_value(value).map_err(|e| format!("Failed to serialize: {}", e)))
+            .and_then(|mut value| {
+                self.redactor.redact_json(&mut value);
+                serde_json::to_vec_pretty(&value).map_err(|e| format!("Failed to serialize: {}", e))
+            });
+        self.push(name, result.map(|bytes| vec![(format!("{}.json", name), bytes)]));
+    }
+
+    /// Add the end of every file in `dir` under `<name>/`, redacted.
+    pub fn add_dir(&mut self, name: &str, dir: Result<PathBuf, String>) {
+        let result = dir.and_then(|dir| {
+            let mut entries: Vec<PathBuf> = std::fs::read_dir(&dir)
+                .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file())
+                .collect();
+            entries.sort();
+            entries
+                .iter()
+                .map(|path| {
+                    let text = read_tail(path, MAX_LOG_FILE_BYTES)?;
+                    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                    Ok((format!("{}/{}", name, file_name), self.redactor.redact_str(&text).into_bytes()))
+                })
+                .collect::<Result<Vec<_>, String>>()
+        });
+        self.push(name, result);
+    }
+
+    /// Note a section that could not be gathered at all.
+    pub fn add_missing(&mut self, name: &str, error: String) {
+        self.push(name, Err(error));
+    }
+
+    fn push(&mut self, name: &str, result: Result<Vec<(String, Vec<u8>)>, String>) {
+        let entry = match result {
+            Ok(files) => {
+                let names = files.iter().map(|(file, _)| file.clone()).collect();
+                self.files.extend(files);
+                SectionEntry {
+                    name: name.to_string(),
+                    files: names,
+                    error: None,
+                }
+            }
+            Err(error) => SectionEntry {
+                name: name.to_string(),
+                files: Vec::new(),
+                error: Some(self.redactor.redact_str(&error)),
+            },
+        };
+        self.sections.push(entry);
+    }
+
+    /// Write the archive with its manifest to `dest`. Blocking.
+    pub fn write(self, dest: &Path, app_version: &str) -> Result<DiagnosticsManifest, String> {
+        let manifest = DiagnosticsManifest {
+            created_at: crate::process_manager::unix_timestamp(),
+            app_version: app_version.to_string(),
+            os: std::env::consts::OS.to_string(),
+            os_version: os_version().map(|version| self.redactor.redact_str(&version)),
+            arch: std::env::consts::ARCH.to_string(),
+            sections: self.sections,
+        };
+        let manifest_json =
+            serde_json::to_vec_pretty(&manifest).map_err(|e| format!("Failed to serialize manifest: {}", e))?;
+
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        let archive = File::create(dest).map_err(|e| format!("Failed to create {}: {}", dest.display(), e))?;
+        let mut zip = zip::ZipWriter::new(BufWriter::new(archive));
+        let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        for (name, bytes) in std::iter::once((MANIFEST_FILE.to_string(), manifest_json)).chain(self.files) {
+            zip.start_file(name.as_str(), options)
+                .map_err(|e| format!("Failed to add {}: {}", name, e))?;
+            zip.write_all(&bytes)
+                .map_err(|e| format!("Failed to write {}: {}", name, e))?;
+        }
+        zip.finish()
+            .map_err(|e| format!("Failed to finish {}: {}", dest.display(), e))?
+            .flush()
+            .map_err(|e| format!("Failed to write {}: {}", dest.display(), e))?;
+        Ok(manifest)
+    }
+}
+
+/// The last `max_bytes` of a file, as text.
+fn read_tail(path: &Path, max_bytes: u64) -> Result<String, String> {
+    let mut file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    if len > max_bytes {
+        file.seek(SeekFrom::Start(len - max_bytes))
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    }
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Input devices as cpal sees them; output devices come from AudioOutputState.
+#[cfg(feature = "native-backends")]
+pub fn input_devices() -> Result<Vec<serde_json::Value>, String> {
+    use cpal::traits::{DeviceTrait, HostTrait};
+
+    let host = cpal::default_host();
+    let default_name = host.default_input_device().and_then(|d| d.name().ok());
+    let devices = host
+        .input_devices()
+        .map_err(|e| format!("Failed to enumerate input devices: {}", e))?;
+    Ok(devices
+        .filter_map(|device| device.name().ok())
+        .map(|name| {
+            let is_default = default_name.as_deref() == Some(name.as_str());
+            serde_json::json!({ "name": name, "is_default": is_default })
+        })
+        .collect())
+}
+
+/// Without the native backends there is no cpal to ask.
+#[cfg(not(feature = "native-backends"))]
+pub fn input_devices() -> Result<Vec<serde_json::Value>, String> {
+    Ok(Vec::new())
+}
+
+/// Best effort, e.g. "14.4.1" on macOS or the PRETTY_NAME of os-release.
+/// Blocking: it may run a command.
+pub fn os_version() -> Option<String> {
+    #[cfg(target_os = "macos")]
+    let version = std::process::Command::new("sw_vers")
+        .arg("-productVersion")
+        .output()
+        .ok()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+    #[cfg(windows)]
+    let version = std::process::Command::new("cmd")
+        .args(["/C", "ver"])
+        .output()
+        .ok()
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+    #[cfg(target_os = "linux")]
+    let version = std::fs::read_to_string("/etc/os-release").ok().and_then(|contents| {
+        contents
+            .lines()
+            .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+            .map(|name| name.trim_matches('"').to_string())
+    });
+    #[cfg(not(any(target_os = "macos", windows, target_os = "linux")))]
+    let version = None;
+    version.filter(|version| !version.is_empty())
+}
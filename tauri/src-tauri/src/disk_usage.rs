@@ -0,0 +1,221 @@
+use crate::storage;
+use serde::Serialize;
+use std::path::{Component, Path};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a computed report is reused before walking the data dir again.
+pub const USAGE_CACHE_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageCategory {
+    Models,
+    Voices,
+    Outputs,
+    Logs,
+    Cache,
+    Other,
+}
+
+impl UsageCategory {
+    pub const ALL: [UsageCategory; 6] = [
+        UsageCategory::Models,
+        UsageCategory::Voices,
+        UsageCategory::Outputs,
+        UsageCategory::Logs,
+        UsageCategory::Cache,
+        UsageCategory::Other,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UsageCategory::Models => "models",
+            UsageCategory::Voices => "voices",
+            UsageCategory::Outputs => "outputs",
+            UsageCategory::Logs => "logs",
+            UsageCategory::Cache => "cache",
+            UsageCategory::Other => "other",
+        }
+    }
+
+    pub fn parse(category: &str) -> Result<Self, String> {
+        let normalized = category.trim().to_lowercase();
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|c| c.as_str() == normalized)
+            .ok_or_else(|| format!("Unknown usage category '{}'", category))
+    }
+
+    /// Categories the server regenerates or the user can lose without losing voices.
+    pub fn is_clearable(&self) -> bool {
+        matches!(self, UsageCategory::Cache | UsageCategory::Outputs)
+    }
+
+    /// Top-level data dir entries that make up the category.
+    fn top_level_dirs(&self) -> &'static [&'static str] {
+        match self {
+            UsageCategory::Models => &["models"],
+            UsageCategory::Voices => &["profiles"],
+            UsageCategory::Outputs => &["generations"],
+            UsageCategory::Logs => &["logs"],
+            UsageCategory::Cache => &["cache", "temp", "tmp"],
+            UsageCategory::Other => &[],
+        }
+    }
+}
+
+/// Category of a file in the data dir, by its top-level directory.
+pub fn category_for(relative_path: &Path) -> UsageCategory {
+    let mut components = relative_path.components();
+    let Some(Component::Normal(top)) = components.next() else {
+        return UsageCategory::Other;
+    };
+    if components.next().is_none() {
+        let is_log = Path::new(top).extension().is_some_and(|ext| ext == "log");
+        return if is_log { UsageCategory::Logs } else { UsageCategory::Other };
+    }
+    let top = top.to_string_lossy();
+    UsageCategory::ALL
+        .iter()
+        .copied()
+        .find(|c| c.top_level_dirs().contains(&top.as_ref()))
+        .unwrap_or(UsageCategory::Other)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CategoryUsage {
+    pub category: UsageCategory,
+    pub bytes: u64,
+    pub files: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DataDirUsage {
+    pub data_dir: String,
+    pub categories: Vec<CategoryUsage>,
+    pub total_bytes: u64,
+    /// Free space on the volume holding the data dir.
+    pub free_bytes: u64,
+    /// Unix timestamp (seconds) of the walk that produced this report.
+    pub computed_at: u64,
+}
+
+/// Walk the data dir and total file sizes per category.
+pub fn compute(data_dir: &Path, cancel: &AtomicBool) -> Result<DataDirUsage, String> {
+    let mut categories: Vec<CategoryUsage> = UsageCategory::ALL
+        .iter()
+        .map(|&category| CategoryUsage {
+            category,
+            bytes: 0,
+            files: 0,
+        })
+        .collect();
+
+    let files = if data_dir.exists() {
+        storage::walk_files_cancellable(data_dir, cancel)?
+    } else {
+        Vec::new()
+    };
+    for file in &files {
+        let category = category_for(&file.relative_path);
+        if let Some(usage) = categories.iter_mut().find(|u| u.category == category) {
+            usage.bytes += file.size;
+            usage.files += 1;
+        }
+    }
+
+    Ok(DataDirUsage {
+        data_dir: data_dir.display().to_string(),
+        total_bytes: categories.iter().map(|u| u.bytes).sum(),
+        categories,
+        free_bytes: storage::free_space(data_dir)?,
+        computed_at: crate::process_manager::unix_timestamp(),
+    })
+}
+
+/// Delete the files of the given clearable categories. Returns the bytes freed.
+pub fn clear(data_dir: &Path, categories: &[UsageCategory]) -> Result<u64, String> {
+    if let Some(category) = categories.iter().find(|c| !c.is_clearable()) {
+        return Err(format!(
+            "Category '{}' cannot be cleared (only cache and outputs can)",
+            category.as_str()
+        ));
+    }
+
+    let mut freed = 0;
+    for category in categories {
+        for dir in category.top_level_dirs() {
+            let path = data_dir.join(dir);
+            if !path.exists() {
+                continue;
+            }
+            // Empty the directory but keep it, the server expects it to exist
+            for file in storage::walk_files(&path)? {
+                let file_path = path.join(&file.relative_path);
+                std::fs::remove_file(&file_path)
+                    .map_err(|e| format!("Failed to remove {}: {}", file_path.display(), e))?;
+                freed += file.size;
+            }
+            let entries = std::fs::read_dir(&path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            for entry in entries.flatten() {
+                if entry.path().is_dir() {
+                    let _ = std::fs::remove_dir_all(entry.path());
+                }
+            }
+        }
+    }
+    Ok(freed)
+}
+
+/// Cached usage report plus the cancel flag of the walk in progress, if any.
+pub struct DiskUsageState {
+    pub cached: Mutex<Option<(Instant, DataDirUsage)>>,
+    pub cancel: Mutex<Option<Arc<AtomicBool>>>,
+}
+
+impl DiskUsageState {
+    pub fn new() -> Self {
+        Self {
+            cached: Mutex::new(None),
+            cancel: Mutex::new(None),
+        }
+    }
+
+    /// The cached report if it is recent and describes `data_dir`.
+    pub fn fresh(&self, data_dir: &Path) -> Option<DataDirUsage> {
+        let cached = self.cached.lock().unwrap();
+        match &*cached {
+            Some((at, usage))
+                if at.elapsed() < USAGE_CACHE_TTL && usage.data_dir == data_dir.display().to_string() =>
+            {
+                Some(usage.clone())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn invalidate(&self) {
+        *self.cached.lock().unwrap() = None;
+    }
+
+    /// Signal the walk in progress, if any, to stop.
+    pub fn cancel(&self) -> bool {
+        match self.cancel.lock().unwrap().take() {
+            Some(flag) => {
+                flag.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Default for DiskUsageState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
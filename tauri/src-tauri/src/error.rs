@@ -0,0 +1,261 @@
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+use std::fmt;
+
+/// Why a command failed. Serialized as `{code, message, details?}`: the UI
+/// branches on `code`, which must not change once shipped, and shows
+/// `message`. `details` is only present for codes that carry extra fields.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VoiceboxError {
+    /// System audio capture couldn't start or produced no audio.
+    Capture { message: String },
+    /// Playback or output device failures.
+    Playback { message: String },
+    /// The server is unreachable, incompatible, or failed a request.
+    Server { message: String },
+    Io { message: String },
+    /// An OS permission isn't granted. `permission` is a PermissionKind,
+    /// e.g. `screen_recording`.
+    Permission { permission: String, message: String },
+    InvalidArgument { message: String },
+    NotFound { message: String },
+    /// Something else has to stop or finish first, e.g. the running server.
+    Busy { message: String },
+    Unsupported { message: String },
+    /// Not enough free space on the volume holding `path`.
+    InsufficientSpace {
+        path: String,
+        required_bytes: u64,
+        available_bytes: u64,
+        message: String,
+    },
+    /// Over one of the recordings library's limits even with every unpinned
+    /// recording pruned. `limit` is the quota setting, e.g. `max_items`.
+    QuotaExceeded {
+        limit: String,
+        max: u64,
+        required: u64,
+        message: String,
+    },
+    /// A test signal played on an output never showed up in the capture
+    /// listening for it, within `listened_ms`.
+    NotDetected { listened_ms: u64, message: String },
+    Internal { message: String },
+}
+
+impl VoiceboxError {
+    pub fn capture(message: impl fmt::Display) -> Self {
+        VoiceboxError::Capture { message: message.to_string() }
+    }
+
+    pub fn playback(message: impl fmt::Display) -> Self {
+        VoiceboxError::Playback { message: message.to_string() }
+    }
+
+    pub fn server(message: impl fmt::Display) -> Self {
+        VoiceboxError::Server { message: message.to_string() }
+    }
+
+    pub fn io(message: impl fmt::Display) -> Self {
+        VoiceboxError::Io { message: message.to_string() }
+    }
+
+    pub fn permission(permission: impl fmt::Display, message: impl fmt::Display) -> Self {
+        VoiceboxError::Permission {
+            permission: permission.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn invalid_argument(message: impl fmt::Display) -> Self {
+        VoiceboxError::InvalidArgument { message: message.to_string() }
+    }
+
+    pub fn not_found(message: impl fmt::Display) -> Self {
+        VoiceboxError::NotFound { message: message.to_string() }
+    }
+
+    pub fn busy(message: impl fmt::Display) -> Self {
+        VoiceboxError::Busy { message: message.to_string() }
+    }
+
+    pub fn unsupported(message: impl fmt::Display) -> Self {
+        VoiceboxError::Unsupported { message: message.to_string() }
+    }
+
+    pub fn insufficient_space(path: impl fmt::Display, required_bytes: u64, available_bytes: u64) -> Self {
+        const MB: u64 = 1024 * 1024;
+        VoiceboxError::InsufficientSpace {
+            message: format!(
+                "Not enough free space for {}: {} MB needed, {} MB available",
+                path,
+                required_bytes.div_ceil(MB),
+                available_bytes / MB
+            ),
+            path: path.to_string(),
+            required_bytes,
+            available_bytes,
+        }
+    }
+
+    pub fn quota_exceeded(limit: impl fmt::Display, max: u64, required: u64) -> Self {
+        VoiceboxError::QuotaExceeded {
+            message: format!(
+                "The recordings library can't fit this recording within its {} of {} ({} needed), \
+                 even with every unpinned recording pruned",
+                limit, max, required
+            ),
+            limit: limit.to_string(),
+            max,
+            required,
+        }
+    }
+
+    pub fn not_detected(listened_ms: u64) -> Self {
+        VoiceboxError::NotDetected {
+            message: format!(
+                "The test signal wasn't heard within {} ms. Check that the output is looped back into the capture",
+                listened_ms
+            ),
+            listened_ms,
+        }
+    }
+
+    pub fn internal(message: impl fmt::Display) -> Self {
+        VoiceboxError::Internal { message: message.to_string() }
+    }
+
+    /// The stable identifier the frontend matches on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            VoiceboxError::Capture { .. } => "capture",
+            VoiceboxError::Playback { .. } => "playback",
+            VoiceboxError::Server { .. } => "server",
+            VoiceboxError::Io { .. } => "io",
+            VoiceboxError::Permission { .. } => "permission",
+            VoiceboxError::InvalidArgument { .. } => "invalid_argument",
+            VoiceboxError::NotFound { .. } => "not_found",
+            VoiceboxError::Busy { .. } => "busy",
+            VoiceboxError::Unsupported { .. } => "unsupported",
+            VoiceboxError::InsufficientSpace { .. } => "insufficient_space",
+            VoiceboxError::QuotaExceeded { .. } => "quota_exceeded",
+            VoiceboxError::NotDetected { .. } => "not_detected",
+            VoiceboxError::Internal { .. } => "internal",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            VoiceboxError::Capture { message }
+            | VoiceboxError::Playback { message }
+            | VoiceboxError::Server { message }
+            | VoiceboxError::Io { message }
+            | VoiceboxError::Permission { message, .. }
+            | VoiceboxError::InvalidArgument { message }
+            | VoiceboxError::NotFound { message }
+            | VoiceboxError::Busy { message }
+            | VoiceboxError::Unsupported { message }
+            | VoiceboxError::InsufficientSpace { message, .. }
+            | VoiceboxError::QuotaExceeded { message, .. }
+            | VoiceboxError::NotDetected { message, .. }
+            | VoiceboxError::Internal { message } => message,
+        }
+    }
+
+    pub fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            VoiceboxError::Permission { permission, .. } => Some(serde_json::json!({ "permission": permission })),
+            VoiceboxError::InsufficientSpace {
+                path,
+                required_bytes,
+                available_bytes,
+                ..
+            } => Some(serde_json::json!({
+                "path": path,
+                "required_bytes": required_bytes,
+                "available_bytes": available_bytes,
+            })),
+            VoiceboxError::QuotaExceeded { limit, max, required, .. } => Some(serde_json::json!({
+                "limit": limit,
+                "max": max,
+                "required": required,
+            })),
+            VoiceboxError::NotDetected { listened_ms, .. } => Some(serde_json::json!({ "listened_ms": listened_ms })),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for VoiceboxError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let details = self.details();
+        let mut map = serializer.serialize_map(Some(if details.is_some() { 3 } else { 2 }))?;
+        map.serialize_entry("code", self.code())?;
+        map.serialize_entry("message", self.message())?;
+        if let Some(details) = &details {
+            map.serialize_entry("details", details)?;
+        }
+        map.end()
+    }
+}
+
+impl fmt::Display for VoiceboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for VoiceboxError {}
+
+/// Helpers that still return String have no code to offer.
+impl From<String> for VoiceboxError {
+    fn from(message: String) -> Self {
+        VoiceboxError::Internal { message }
+    }
+}
+
+impl From<&str> for VoiceboxError {
+    fn from(message: &str) -> Self {
+        VoiceboxError::internal(message)
+    }
+}
+
+impl From<std::io::Error> for VoiceboxError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => VoiceboxError::not_found(e),
+            _ => VoiceboxError::io(e),
+        }
+    }
+}
+
+impl From<serde_json::Error> for VoiceboxError {
+    fn from(e: serde_json::Error) -> Self {
+        VoiceboxError::internal(format!("JSON error: {}", e))
+    }
+}
+
+impl From<reqwest::Error> for VoiceboxError {
+    fn from(e: reqwest::Error) -> Self {
+        VoiceboxError::server(e)
+    }
+}
+
+impl From<tauri::Error> for VoiceboxError {
+    fn from(e: tauri::Error) -> Self {
+        VoiceboxError::internal(e)
+    }
+}
+
+/// A spawn_blocking task that panicked or was cancelled.
+impl From<tokio::task::JoinError> for VoiceboxError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        VoiceboxError::internal(format!("Background task failed: {}", e))
+    }
+}
+
+impl From<VoiceboxError> for String {
+    fn from(e: VoiceboxError) -> Self {
+        e.message().to_string()
+    }
+}
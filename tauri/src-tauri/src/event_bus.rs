@@ -0,0 +1,271 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Listener, Manager};
+use tracing::warn;
+
+/// What batched events arrive as in the webview:
+/// `{ events: [{ topic, payload }, ...], dropped: { topic: count } }`.
+pub const BATCH_EVENT: &str = "batched-events";
+
+pub const DEFAULT_WINDOW: Duration = Duration::from_millis(50);
+
+/// Events kept per topic within one window; older ones are dropped first.
+pub const DEFAULT_QUEUE_CAP: usize = 32;
+
+/// Sent as soon as they happen, never batched: the UI acts on them right
+/// away, and a window closing may not wait for the next flush.
+const IMMEDIATE_TOPICS: &[&str] = &[
+    "capture-hotkey-stopped",
+    "capture-stopped",
+    "capture-failed",
+    "playback-finished",
+    "server-crashed",
+    "window-closing",
+];
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Where events end up: the webview in the app, a recorder in tests.
+pub trait EventSink: Send + Sync {
+    fn send(&self, topic: &str, payload: &Value) -> Result<(), String>;
+}
+
+impl EventSink for AppHandle {
+    fn send(&self, topic: &str, payload: &Value) -> Result<(), String> {
+        self.emit(topic, payload).map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BusConfig {
+    pub window: Duration,
+    pub queue_cap: usize,
+    immediate: HashSet<String>,
+    caps: HashMap<String, usize>,
+}
+
+impl BusConfig {
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn immediate(mut self, topic: &str) -> Self {
+        self.immediate.insert(topic.to_string());
+        self
+    }
+
+    /// Keep at most `cap` events of `topic` per window, e.g. 1 for a level
+    /// meter that only needs the latest reading.
+    pub fn cap(mut self, topic: &str, cap: usize) -> Self {
+        self.caps.insert(topic.to_string(), cap.max(1));
+        self
+    }
+
+    fn cap_for(&self, topic: &str) -> usize {
+        self.caps.get(topic).copied().unwrap_or(self.queue_cap).max(1)
+    }
+}
+
+impl Default for BusConfig {
+    /// The app's: a 50 ms window, with IMMEDIATE_TOPICS sent right away.
+    fn default() -> Self {
+        Self {
+            window: DEFAULT_WINDOW,
+            queue_cap: DEFAULT_QUEUE_CAP,
+            immediate: IMMEDIATE_TOPICS.iter().map(|topic| topic.to_string()).collect(),
+            caps: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchedEvent {
+    pub topic: String,
+    pub payload: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Batch {
+    /// In the order they were emitted, across topics.
+    pub events: Vec<BatchedEvent>,
+    /// Events left out of this batch because their topic's queue was full.
+    pub dropped: BTreeMap<String, u64>,
+}
+
+#[derive(Default)]
+struct Pending {
+    window_start: Option<Instant>,
+    next_seq: u64,
+    queues: HashMap<String, VecDeque<(u64, Value)>>,
+    dropped: BTreeMap<String, u64>,
+}
+
+/// Every event from the Rust side goes through here (see `emit`). Events
+/// emitted within one window are sent together as a single BATCH_EVENT, so a
+/// burst costs the webview one message instead of dozens.
+pub struct EventBus {
+    config: BusConfig,
+    sink: Arc<dyn EventSink>,
+    clock: Arc<dyn Clock>,
+    pending: Mutex<Pending>,
+    /// Since launch, per topic.
+    total_dropped: Mutex<BTreeMap<String, u64>>,
+}
+
+impl EventBus {
+    pub fn new(sink: Arc<dyn EventSink>, clock: Arc<dyn Clock>, config: BusConfig) -> Self {
+        Self {
+            config,
+            sink,
+            clock,
+            pending: Mutex::new(Pending::default()),
+            total_dropped: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.config.window
+    }
+
+    pub fn emit<S: Serialize>(&self, topic: &str, payload: S) -> Result<(), String> {
+        let payload = serde_json::to_value(payload).map_err(|e| format!("Failed to serialize {}: {}", topic, e))?;
+        if self.config.immediate.contains(topic) {
+            // Whatever was queued before it still arrives first
+            self.flush()?;
+            return self.sink.send(topic, &payload);
+        }
+
+        let now = self.clock.now();
+        let due = {
+            let mut pending = self.pending.lock().unwrap();
+            let window_start = *pending.window_start.get_or_insert(now);
+            let seq = pending.next_seq;
+            pending.next_seq += 1;
+            let queue = pending.queues.entry(topic.to_string()).or_default();
+            queue.push_back((seq, payload));
+            let mut dropped = 0;
+            while queue.len() > self.config.cap_for(topic) {
+                queue.pop_front();
+                dropped += 1;
+            }
+            if dropped > 0 {
+                *pending.dropped.entry(topic.to_string()).or_default() += dropped;
+                *self.total_dropped.lock().unwrap().entry(topic.to_string()).or_default() += dropped;
+            }
+            now.saturating_duration_since(window_start) >= self.config.window
+        };
+        if due {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Send what's queued once its window has passed. Called on a timer, so
+    /// a lone event doesn't wait for the next one.
+    pub fn flush_due(&self) -> Result<(), String> {
+        let window_start = self.pending.lock().unwrap().window_start;
+        match window_start {
+            Some(start) if self.clock.now().saturating_duration_since(start) >= self.config.window => self.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Send everything queued now, as one batch.
+    pub fn flush(&self) -> Result<(), String> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        let mut events: Vec<(u64, BatchedEvent)> = pending
+            .queues
+            .into_iter()
+            .flat_map(|(topic, queue)| {
+                queue.into_iter().map(move |(seq, payload)| {
+                    (
+                        seq,
+                        BatchedEvent {
+                            topic: topic.clone(),
+                            payload,
+                        },
+                    )
+                })
+            })
+            .collect();
+        if events.is_empty() {
+            return Ok(());
+        }
+        events.sort_by_key(|(seq, _)| *seq);
+        let batch = Batch {
+            events: events.into_iter().map(|(_, event)| event).collect(),
+            dropped: pending.dropped,
+        };
+        let payload = serde_json::to_value(&batch).map_err(|e| format!("Failed to serialize a batch: {}", e))?;
+        self.sink.send(BATCH_EVENT, &payload)
+    }
+
+    /// Events dropped from full queues since launch, per topic.
+    pub fn dropped(&self) -> BTreeMap<String, u64> {
+        self.total_dropped.lock().unwrap().clone()
+    }
+}
+
+/// Emit an event to the webview through the app's bus, or directly if the
+/// bus isn't set up yet (early in startup).
+pub fn emit<S: Serialize + Clone>(app: &AppHandle, topic: &str, payload: S) -> Result<(), String> {
+    match app.try_state::<EventBus>() {
+        Some(bus) => bus.emit(topic, payload),
+        None => app.emit(topic, payload).map_err(|e| e.to_string()),
+    }
+}
+
+/// Call `handler` with the payload of each `topic` event, whether it was sent
+/// on its own or inside a batch. The Rust side's counterpart of the
+/// webview's listenEvent.
+pub fn listen<F>(app: &AppHandle, topic: &str, handler: F)
+where
+    F: Fn(&str) + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    let direct = handler.clone();
+    app.listen(topic, move |event| direct(event.payload()));
+    let topic = topic.to_string();
+    app.listen(BATCH_EVENT, move |event| {
+        let Ok(batch) = serde_json::from_str::<Batch>(event.payload()) else {
+            return;
+        };
+        for event in batch.events.iter().filter(|event| event.topic == topic) {
+            handler(&event.payload.to_string());
+        }
+    });
+}
+
+/// Set up the app's bus and flush it every window.
+pub fn init(app: &AppHandle) {
+    // Server output comes in bursts, like a traceback, that should arrive whole
+    let config = BusConfig::default().cap("server-log", crate::server_output::LOG_EVENT_QUEUE);
+    let bus = EventBus::new(Arc::new(app.clone()), Arc::new(SystemClock), config);
+    let window = bus.window();
+    app.manage(bus);
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(window);
+        loop {
+            interval.tick().await;
+            if let Err(e) = app.state::<EventBus>().flush_due() {
+                warn!("Failed to send batched events: {}", e);
+            }
+        }
+    });
+}
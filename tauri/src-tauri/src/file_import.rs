@@ -0,0 +1,85 @@
+use crate::audio_import::{ImportError, ImportedAudio};
+use serde::Serialize;
+use std::path::PathBuf;
+use tauri::{AppHandle, Manager};
+use tracing::{info, warn};
+
+/// Payload of the `audio-import-failed` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportFailure {
+    pub path: PathBuf,
+    pub error: ImportError,
+}
+
+/// Payload of the `audio-import-finished` event, sent once per batch after
+/// every file in it has been tried.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportBatch {
+    pub imported: Vec<ImportedAudio>,
+    pub failed: Vec<ImportFailure>,
+}
+
+/// Serializes batches, so files dropped or opened while another batch is
+/// still importing wait their turn instead of decoding in parallel.
+pub struct FileImportState {
+    queue: tokio::sync::Mutex<()>,
+}
+
+impl FileImportState {
+    pub fn new() -> Self {
+        Self {
+            queue: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+
+/// Files the OS asked us to open: double-clicks, Open With, or arguments of a
+/// launch. Brings the window forward, then imports them like a drop.
+pub fn open(app: &AppHandle, paths: Vec<PathBuf>) {
+    if paths.is_empty() {
+        return;
+    }
+    info!("Opening {} file(s): {:?}", paths.len(), paths);
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+    spawn(app, paths);
+}
+
+/// Import `paths` one after another in the background through the same
+/// pipeline as import_audio_file, emitting `audio-imported` or
+/// `audio-import-failed` per file and `audio-import-finished` at the end.
+pub fn spawn(app: &AppHandle, paths: Vec<PathBuf>) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let state = app.state::<FileImportState>();
+        let _turn = state.queue.lock().await;
+
+        let mut batch = ImportBatch::default();
+        for path in paths {
+            match crate::import_audio(&app, path.clone(), None).await {
+                Ok(imported) => {
+                    info!("Imported {:?} as {:?}", path, imported.path);
+                    if let Err(e) = crate::event_bus::emit(&app, "audio-imported", &imported) {
+                        warn!("Failed to emit audio-imported event: {}", e);
+                    }
+                    batch.imported.push(imported);
+                }
+                Err(error) => {
+                    warn!("Failed to import {:?}: {}", path, error);
+                    let failure = ImportFailure { path, error };
+                    if let Err(e) = crate::event_bus::emit(&app, "audio-import-failed", &failure) {
+                        warn!("Failed to emit audio-import-failed event: {}", e);
+                    }
+                    batch.failed.push(failure);
+                }
+            }
+        }
+
+        if let Err(e) = crate::event_bus::emit(&app, "audio-import-finished", &batch) {
+            warn!("Failed to emit audio-import-finished event: {}", e);
+        }
+    });
+}
@@ -0,0 +1,178 @@
+use crate::audio_util;
+use base64::Engine;
+use serde::Serialize;
+
+/// Audio is mixed to mono and averaged down to this rate first.
+const RATE: u32 = 8000;
+
+/// Samples per analysis frame at RATE (256 ms), and between frame starts
+/// (64 ms), close enough that copies started apart still line up.
+const FRAME: usize = 2048;
+const HOP: usize = 512;
+
+/// Log-spaced energy bands between these; each frame's hash has a bit per
+/// pair of neighbouring bands.
+const BANDS: usize = 33;
+const LOW_HZ: f32 = 300.0;
+const HIGH_HZ: f32 = 3000.0;
+
+/// Furthest two fingerprints are slid against each other, in frames (about
+/// 5 s), for captures started a little apart.
+const MAX_SHIFT: usize = 80;
+
+/// find_similar_recordings' threshold when none is given. Unrelated audio
+/// scores around 0.5, since half the bits agree by chance.
+pub const DEFAULT_THRESHOLD: f32 = 0.75;
+
+/// A library recording close to the one asked about.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SimilarRecording {
+    pub id: String,
+    /// 0 to 1, where 1 is the same audio.
+    pub similarity: f32,
+}
+
+/// A spectral hash of interleaved `samples`: one u32 per frame, each bit
+/// telling whether the energy difference between two neighbouring bands rose
+/// or fell since the previous frame. That survives gain, noise and lossy
+/// encoding while telling different audio apart. Empty for audio shorter
+/// than two frames. Blocking: a few seconds per hour of audio.
+pub fn compute(samples: &[f32], sample_rate: u32, channels: u16) -> Vec<u32> {
+    let mono = downsample(samples, sample_rate, channels);
+    let window: Vec<f32> = (0..FRAME)
+        .map(|n| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * n as f32 / FRAME as f32).cos())
+        .collect();
+    let edges: Vec<usize> = (0..=BANDS)
+        .map(|band| {
+            let hz = LOW_HZ * (HIGH_HZ / LOW_HZ).powf(band as f32 / BANDS as f32);
+            (hz * FRAME as f32 / RATE as f32).round() as usize
+        })
+        .collect();
+    let twiddles = audio_util::fft_twiddles(FRAME);
+
+    let mut hashes = Vec::new();
+    let mut previous: Option<[f32; BANDS]> = None;
+    let mut buffer = vec![(0.0f32, 0.0f32); FRAME];
+    let mut start = 0;
+    while start + FRAME <= mono.len() {
+        for (slot, (&sample, &weight)) in buffer.iter_mut().zip(mono[start..start + FRAME].iter().zip(&window)) {
+            *slot = (sample * weight, 0.0);
+        }
+        audio_util::fft(&mut buffer, &twiddles);
+        let mut energies = [0.0f32; BANDS];
+        for (band, energy) in energies.iter_mut().enumerate() {
+            *energy = buffer[edges[band]..edges[band + 1].max(edges[band] + 1)]
+                .iter()
+                .map(|&(re, im)| re * re + im * im)
+                .sum();
+        }
+        if let Some(previous) = previous {
+            let mut hash = 0u32;
+            for bit in 0..BANDS - 1 {
+                let now = energies[bit] - energies[bit + 1];
+                let before = previous[bit] - previous[bit + 1];
+                if now - before > 0.0 {
+                    hash |= 1 << bit;
+                }
+            }
+            hashes.push(hash);
+        }
+        previous = Some(energies);
+        start += HOP;
+    }
+    hashes
+}
+
+/// How alike two fingerprints are, 0 to 1: the share of bits that agree
+/// where they overlap, at the best shift within a few seconds. The overlap
+/// must cover at least half of the shorter one. 0 if either is empty.
+pub fn similarity(a: &[u32], b: &[u32]) -> f32 {
+    let shorter = a.len().min(b.len());
+    if shorter == 0 {
+        return 0.0;
+    }
+    let min_overlap = shorter.div_ceil(2);
+    let mut best = 0.0f32;
+    for shift in -(MAX_SHIFT as isize)..=MAX_SHIFT as isize {
+        let (a, b) = if shift >= 0 {
+            (a.get(shift as usize..), Some(b))
+        } else {
+            (Some(a), b.get(shift.unsigned_abs()..))
+        };
+        let (Some(a), Some(b)) = (a, b) else {
+            continue;
+        };
+        let overlap = a.len().min(b.len());
+        if overlap < min_overlap {
+            continue;
+        }
+        let differing: u32 = a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum();
+        let bits = overlap as f32 * (BANDS - 1) as f32;
+        best = best.max(1.0 - differing as f32 / bits);
+    }
+    best
+}
+
+/// `hashes` as stored in RecordingMetadata: little-endian, base64.
+pub fn encode(hashes: &[u32]) -> String {
+    let bytes: Vec<u8> = hashes.iter().flat_map(|hash| hash.to_le_bytes()).collect();
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+pub fn decode(encoded: &str) -> Result<Vec<u32>, String> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| format!("The fingerprint is not valid base64: {}", e))?;
+    if bytes.len() % 4 != 0 {
+        return Err(format!("The fingerprint is {} bytes, not a multiple of 4", bytes.len()));
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+/// The `candidates` (id, fingerprint) at least `threshold` alike to
+/// `fingerprint`, most alike first.
+pub fn rank<'a>(
+    fingerprint: &[u32],
+    candidates: impl IntoIterator<Item = (&'a str, &'a [u32])>,
+    threshold: f32,
+) -> Vec<SimilarRecording> {
+    let mut matches: Vec<SimilarRecording> = candidates
+        .into_iter()
+        .map(|(id, other)| SimilarRecording {
+            id: id.to_string(),
+            similarity: similarity(fingerprint, other),
+        })
+        .filter(|candidate| candidate.similarity >= threshold)
+        .collect();
+    matches.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    matches
+}
+
+/// Mix to mono and box-average down to RATE. Audio already at or below it
+/// is only mixed.
+fn downsample(samples: &[f32], sample_rate: u32, channels: u16) -> Vec<f32> {
+    let channels = channels.max(1) as usize;
+    let mono = samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32);
+    if sample_rate <= RATE {
+        return mono.collect();
+    }
+    let step = sample_rate as f64 / RATE as f64;
+    let frames = samples.len() / channels;
+    let mut out = Vec::with_capacity(frames * RATE as usize / sample_rate as usize + 1);
+    let (mut sum, mut count, mut next) = (0.0f32, 0u32, step);
+    for (i, sample) in mono.enumerate() {
+        if i as f64 >= next {
+            out.push(sum / count.max(1) as f32);
+            (sum, count) = (0.0, 0);
+            next += step;
+        }
+        sum += sample;
+        count += 1;
+    }
+    out
+}
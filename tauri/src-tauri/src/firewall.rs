@@ -0,0 +1,191 @@
+use serde::Serialize;
+#[cfg(windows)]
+use tracing::info;
+
+/// Name of the inbound rule created for the sidecar in remote mode.
+pub const FIREWALL_RULE_NAME: &str = "Voicebox Server";
+
+/// Outcome of making sure remote clients can reach the server.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum FirewallRuleStatus {
+    Created,
+    Existing,
+    Failed { reason: String },
+    /// Platforms without a firewall prompt for listening apps.
+    NotApplicable,
+}
+
+/// Make sure an inbound allow rule exists for the sidecar on `port`, creating
+/// it if needed. Idempotent: an existing matching rule is left alone. Blocks
+/// while the user answers the elevation prompt, so call it off the async
+/// runtime.
+#[cfg(windows)]
+pub fn ensure_firewall_rule(port: u16) -> FirewallRuleStatus {
+    use windows::Win32::System::Com::{CoInitializeEx, CoUninitialize, COINIT_MULTITHREADED};
+
+    let program = match sidecar_path() {
+        Ok(path) => path,
+        Err(reason) => return FirewallRuleStatus::Failed { reason },
+    };
+
+    unsafe {
+        let hr = CoInitializeEx(None, COINIT_MULTITHREADED);
+        if hr.is_err() {
+            return FirewallRuleStatus::Failed {
+                reason: format!("Failed to initialize COM: {:?}", hr),
+            };
+        }
+    }
+    let _com_guard = scopeguard::guard((), |_| unsafe {
+        CoUninitialize();
+    });
+
+    match windows_impl::has_matching_rule(&program, port) {
+        Ok(true) => return FirewallRuleStatus::Existing,
+        Ok(false) => {}
+        Err(e) => {
+            return FirewallRuleStatus::Failed {
+                reason: format!("Failed to query Windows Firewall: {}", e),
+            }
+        }
+    }
+
+    info!("Creating firewall rule '{}' for {:?}", FIREWALL_RULE_NAME, program);
+    match windows_impl::add_rule(&program, port) {
+        Ok(()) => return FirewallRuleStatus::Created,
+        Err(e) if e.code() == windows::Win32::Foundation::E_ACCESSDENIED => {
+            info!("Not elevated, asking for permission to add the firewall rule");
+        }
+        Err(e) => {
+            return FirewallRuleStatus::Failed {
+                reason: format!("Failed to add firewall rule: {}", e),
+            }
+        }
+    }
+
+    if let Err(reason) = windows_impl::add_rule_elevated(&program, port) {
+        return FirewallRuleStatus::Failed { reason };
+    }
+    // The elevated helper's exit code says little (e.g. a declined prompt), so check the result
+    match windows_impl::has_matching_rule(&program, port) {
+        Ok(true) => FirewallRuleStatus::Created,
+        Ok(false) => FirewallRuleStatus::Failed {
+            reason: "The firewall rule was not created (administrator permission declined?)"
+                .to_string(),
+        },
+        Err(e) => FirewallRuleStatus::Failed {
+            reason: format!("Failed to query Windows Firewall: {}", e),
+        },
+    }
+}
+
+#[cfg(not(windows))]
+pub fn ensure_firewall_rule(_port: u16) -> FirewallRuleStatus {
+    FirewallRuleStatus::NotApplicable
+}
+
+/// Location of the bundled sidecar, which Tauri places next to the app binary.
+#[cfg(windows)]
+fn sidecar_path() -> Result<std::path::PathBuf, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to locate app binary: {}", e))?;
+    let dir = exe
+        .parent()
+        .ok_or_else(|| "Failed to locate app directory".to_string())?;
+    Ok(dir.join("voicebox-server.exe"))
+}
+
+#[cfg(windows)]
+mod windows_impl {
+    use super::FIREWALL_RULE_NAME;
+    use std::path::Path;
+    use windows::core::BSTR;
+    use windows::Win32::Foundation::VARIANT_TRUE;
+    use windows::Win32::NetworkManagement::WindowsFirewall::{
+        INetFwPolicy2, INetFwRule, NetFwPolicy2, NetFwRule, NET_FW_ACTION_ALLOW,
+        NET_FW_IP_PROTOCOL_TCP, NET_FW_PROFILE2_DOMAIN, NET_FW_PROFILE2_PRIVATE,
+        NET_FW_RULE_DIR_IN,
+    };
+    use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER};
+
+    /// Public networks are left alone on purpose: exposing the server on
+    /// coffee-shop Wi-Fi should stay an explicit user decision.
+    const PROFILES: i32 = NET_FW_PROFILE2_PRIVATE.0 | NET_FW_PROFILE2_DOMAIN.0;
+
+    fn policy() -> windows::core::Result<INetFwPolicy2> {
+        unsafe { CoCreateInstance(&NetFwPolicy2, None, CLSCTX_INPROC_SERVER) }
+    }
+
+    /// Whether our named rule exists and still allows this binary on this port.
+    /// A rule left over from an older install location doesn't count.
+    pub fn has_matching_rule(program: &Path, port: u16) -> windows::core::Result<bool> {
+        unsafe {
+            let rules = policy()?.Rules()?;
+            let Ok(rule) = rules.Item(&BSTR::from(FIREWALL_RULE_NAME)) else {
+                return Ok(false);
+            };
+            let application = rule.ApplicationName()?.to_string();
+            Ok(rule.Enabled()? == VARIANT_TRUE
+                && rule.Direction()? == NET_FW_RULE_DIR_IN
+                && rule.Action()? == NET_FW_ACTION_ALLOW
+                && rule.LocalPorts()?.to_string() == port.to_string()
+                && application.eq_ignore_ascii_case(&program.to_string_lossy()))
+        }
+    }
+
+    /// Add the rule in-process. Fails with E_ACCESSDENIED unless the app runs elevated.
+    pub fn add_rule(program: &Path, port: u16) -> windows::core::Result<()> {
+        unsafe {
+            let rules = policy()?.Rules()?;
+            // Replace a stale rule of the same name rather than adding a duplicate
+            let _ = rules.Remove(&BSTR::from(FIREWALL_RULE_NAME));
+
+            let rule: INetFwRule = CoCreateInstance(&NetFwRule, None, CLSCTX_INPROC_SERVER)?;
+            rule.SetName(&BSTR::from(FIREWALL_RULE_NAME))?;
+            rule.SetDescription(&BSTR::from("Lets other devices reach the Voicebox server in remote mode"))?;
+            rule.SetApplicationName(&BSTR::from(program.to_string_lossy().as_ref()))?;
+            rule.SetProtocol(NET_FW_IP_PROTOCOL_TCP.0)?;
+            rule.SetLocalPorts(&BSTR::from(port.to_string().as_str()))?;
+            rule.SetDirection(NET_FW_RULE_DIR_IN)?;
+            rule.SetAction(NET_FW_ACTION_ALLOW)?;
+            rule.SetProfiles(PROFILES)?;
+            rule.SetEnabled(VARIANT_TRUE)?;
+            rules.Add(&rule)
+        }
+    }
+
+    /// Add the rule through an elevated netsh, which shows the UAC prompt.
+    pub fn add_rule_elevated(program: &Path, port: u16) -> Result<(), String> {
+        use std::os::windows::process::CommandExt;
+        use std::process::Command;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+        let netsh = format!(
+            "/c netsh advfirewall firewall delete rule name=\"{name}\" & \
+             netsh advfirewall firewall add rule name=\"{name}\" dir=in action=allow \
+             program=\"{program}\" protocol=TCP localport={port} profile=private,domain enable=yes",
+            name = FIREWALL_RULE_NAME,
+            program = program.display(),
+            port = port,
+        );
+        // Single quotes delimit the PowerShell string; double any inside the path
+        let script = format!(
+            "Start-Process -FilePath cmd.exe -ArgumentList '{}' -Verb RunAs -Wait -WindowStyle Hidden",
+            netsh.replace('\'', "''")
+        );
+
+        let output = Command::new("powershell")
+            .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+            .creation_flags(CREATE_NO_WINDOW)
+            .output()
+            .map_err(|e| format!("Failed to request elevation: {}", e))?;
+        if !output.status.success() {
+            // Start-Process throws when the UAC prompt is cancelled
+            return Err(format!(
+                "Administrator permission was not granted: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use crate::audio_import;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tracing::warn;
+
+/// In the app data dir, next to the settings.
+pub const LEDGER_FILE: &str = "watch_ledger.json";
+
+/// How long a file's size has to hold still before it counts as written.
+/// Sync clients and recorders write in bursts with pauses between them.
+pub const SETTLE_TIME: Duration = Duration::from_secs(3);
+
+/// Size and modification time, to tell whether a file changed since it was
+/// last handled without reading it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub len: u64,
+    /// Unix milliseconds.
+    pub modified_ms: u64,
+}
+
+impl FileStamp {
+    /// None if `path` isn't a readable file.
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok().filter(|m| m.is_file())?;
+        let modified_ms = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_millis() as u64);
+        Some(Self {
+            len: metadata.len(),
+            modified_ms,
+        })
+    }
+}
+
+/// What watched folders have already handled, kept across restarts: the
+/// content hashes of imported files, so a copy or a re-synced file isn't
+/// imported twice, and the stamp of every file handled, so unchanged files
+/// aren't read again on each scan.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ledger {
+    hashes: BTreeSet<String>,
+    files: BTreeMap<PathBuf, FileStamp>,
+}
+
+impl Ledger {
+    /// An empty ledger if the file is missing. A corrupt one is started over,
+    /// which at worst imports files again.
+    pub fn load(path: &Path) -> Self {
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            return Self::default();
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!("{} is corrupt, starting a new one: {}", path.display(), e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string(self).map_err(|e| format!("Failed to serialize the import ledger: {}", e))?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, json).map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+        std::fs::rename(&tmp, path).map_err(|e| format!("Failed to save {}: {}", path.display(), e))
+    }
+
+    /// Whether `path` was handled and hasn't changed since.
+    pub fn is_handled(&self, path: &Path, stamp: FileStamp) -> bool {
+        self.files.get(path) == Some(&stamp)
+    }
+
+    pub fn note_handled(&mut self, path: &Path, stamp: FileStamp) {
+        self.files.insert(path.to_path_buf(), stamp);
+    }
+
+    pub fn has_hash(&self, hash: &str) -> bool {
+        self.hashes.contains(hash)
+    }
+
+    pub fn note_hash(&mut self, hash: String) {
+        self.hashes.insert(hash);
+    }
+
+    /// Drop the stamps of files in `dir`, e.g. when it is no longer watched.
+    /// Hashes stay, so its files aren't imported again if it's re-added.
+    pub fn forget_folder(&mut self, dir: &Path) {
+        self.files.retain(|path, _| path.parent() != Some(dir));
+    }
+}
+
+/// SHA-256 of the file's contents, hex. Blocking.
+pub fn content_hash(path: &Path) -> Result<String, String> {
+    let mut file = std::fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Whether a watched folder should import `path`: an audio file that isn't
+/// hidden (sync clients write into hidden temporary files first).
+pub fn is_candidate(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_none_or(|name| name.starts_with('.') || name.starts_with("~$"));
+    !hidden && audio_import::is_audio_file(path)
+}
+
+/// The candidate files directly in `dir`, sorted. Blocking.
+pub fn scan(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+        .map(|entry| entry.path())
+        .filter(|path| is_candidate(path))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Holds new files back until they stop growing.
+pub struct Settler {
+    settle: Duration,
+    /// Size last seen, and since when.
+    pending: HashMap<PathBuf, (u64, Instant)>,
+}
+
+impl Settler {
+    pub fn new(settle: Duration) -> Self {
+        Self {
+            settle,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Note `path`'s current size; a change restarts its wait.
+    pub fn observe(&mut self, path: PathBuf, len: u64, now: Instant) {
+        match self.pending.get_mut(&path) {
+            Some((last_len, _)) if *last_len == len => {}
+            Some(entry) => *entry = (len, now),
+            None => {
+                self.pending.insert(path, (len, now));
+            }
+        }
+    }
+
+    /// Stop waiting for `path`, e.g. because it was deleted.
+    pub fn forget(&mut self, path: &Path) {
+        self.pending.remove(path);
+    }
+
+    /// Files still waiting, to be observed again.
+    pub fn pending(&self) -> Vec<PathBuf> {
+        self.pending.keys().cloned().collect()
+    }
+
+    /// Files whose size has held still for the settle time, sorted. Empty
+    /// files keep waiting: they've been created but not written yet.
+    pub fn take_ready(&mut self, now: Instant) -> Vec<PathBuf> {
+        let mut ready: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, (len, since))| *len > 0 && now.saturating_duration_since(*since) >= self.settle)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in &ready {
+            self.pending.remove(path);
+        }
+        ready.sort();
+        ready
+    }
+}
@@ -0,0 +1,491 @@
+use crate::process_manager::{self, ServerInfo, ServerRecord};
+use crate::server_auth;
+use crate::server_error::{BinaryProblemKind, ServerStartError, StartupPhase, TAIL_LINES};
+use crate::settings::{Settings, SettingsState};
+use crate::storage;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+pub const HEADLESS_FLAG: &str = "--headless";
+
+/// Tauri keeps the app data dir under the bundle identifier.
+const APP_IDENTIFIER: &str = "sh.voicebox.app";
+
+const STOP_GRACE: Duration = Duration::from_secs(3);
+
+/// Sidecar output goes here rather than to a pipe, so a `--keep-running`
+/// server can keep logging after we exit.
+const SERVER_LOG_FILE: &str = "server-headless.log";
+
+pub const EXIT_OK: i32 = 0;
+/// Anything not covered below, e.g. no app data dir.
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+/// The server binary is missing or can't be executed.
+pub const EXIT_BINARY_PROBLEM: i32 = 3;
+pub const EXIT_SPAWN_FAILED: i32 = 4;
+pub const EXIT_STARTUP_TIMEOUT: i32 = 5;
+pub const EXIT_CRASHED_DURING_STARTUP: i32 = 6;
+/// Something other than a voicebox server holds the port.
+pub const EXIT_PORT_CONFLICT: i32 = 7;
+/// Without `--start-server`, no server was running.
+pub const EXIT_NOT_RUNNING: i32 = 8;
+/// The server started, then died before we were interrupted.
+pub const EXIT_SERVER_EXITED: i32 = 9;
+/// The data dir can't be written or is full.
+pub const EXIT_STORAGE_PROBLEM: i32 = 10;
+
+pub const USAGE: &str = "\
+Usage: voicebox --headless [options]
+
+Runs the voicebox server without opening a window. Prints the server URL to
+stdout once it is ready and stays running until interrupted.
+
+Options:
+  --start-server     Start the server if one isn't already running. Without
+                     this, only report a running server.
+  --keep-running     Leave the server running when interrupted.
+  --remote           Bind to all interfaces so other devices can connect.
+  --offline          Don't let the server reach the model hub.
+  --port <port>      Port to use (default 17493).
+  --data-dir <path>  Absolute path of the data directory (default: the
+                     app's own, or the portable one).
+  -h, --help         Show this help.
+
+Exit codes:
+  0  interrupted normally, or a running server was found
+  1  other failure
+  2  invalid arguments
+  3  server binary missing or not executable
+  4  server could not be spawned
+  5  server did not become ready in time
+  6  server exited during startup
+  7  port in use by another program
+  8  no server running (without --start-server)
+  9  server exited after starting
+  10 data directory not writable or full";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeadlessOptions {
+    pub start_server: bool,
+    pub keep_running: bool,
+    pub remote: bool,
+    /// None probes the network, as the window does.
+    pub offline: Option<bool>,
+    pub port: u16,
+    pub data_dir: Option<PathBuf>,
+}
+
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        Self {
+            start_server: false,
+            keep_running: false,
+            remote: false,
+            offline: None,
+            port: process_manager::SERVER_PORT,
+            data_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CliMode {
+    /// Build the Tauri app; its own argument handling applies.
+    Gui,
+    Help,
+    Headless(HeadlessOptions),
+}
+
+/// Decide how to run from the arguments after the program name. Only
+/// `--headless` invocations are validated; anything else goes to the window
+/// untouched.
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<CliMode, String> {
+    let args: Vec<String> = args.into_iter().collect();
+    if !args.iter().any(|arg| arg == HEADLESS_FLAG) {
+        return Ok(CliMode::Gui);
+    }
+
+    let mut options = HeadlessOptions::default();
+    let mut rest = args.into_iter();
+    while let Some(arg) = rest.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg, None),
+        };
+        match flag.as_str() {
+            "--port" => {
+                let value = take_value(&flag, inline, &mut rest)?;
+                options.port = value
+                    .parse()
+                    .ok()
+                    .filter(|port| *port != 0)
+                    .ok_or_else(|| format!("Invalid port: {}", value))?;
+            }
+            "--data-dir" => options.data_dir = Some(PathBuf::from(take_value(&flag, inline, &mut rest)?)),
+            _ if inline.is_some() => return Err(format!("{} does not take a value", flag)),
+            HEADLESS_FLAG => {}
+            "--start-server" => options.start_server = true,
+            "--keep-running" => options.keep_running = true,
+            "--remote" => options.remote = true,
+            "--offline" => options.offline = Some(true),
+            "-h" | "--help" => return Ok(CliMode::Help),
+            _ => return Err(format!("Unknown option: {}", flag)),
+        }
+    }
+    Ok(CliMode::Headless(options))
+}
+
+fn take_value(flag: &str, inline: Option<String>, rest: &mut impl Iterator<Item = String>) -> Result<String, String> {
+    inline
+        .or_else(|| rest.next())
+        .filter(|value| !value.is_empty())
+        .ok_or_else(|| format!("{} needs a value", flag))
+}
+
+/// Process exit code for a failed start, so scripts can tell failures apart.
+pub fn exit_code(error: &ServerStartError) -> i32 {
+    match error {
+        ServerStartError::BinaryProblem { .. } => EXIT_BINARY_PROBLEM,
+        ServerStartError::SpawnFailed { .. } => EXIT_SPAWN_FAILED,
+        ServerStartError::Timeout { .. } => EXIT_STARTUP_TIMEOUT,
+        ServerStartError::CrashedDuringStartup { .. } => EXIT_CRASHED_DURING_STARTUP,
+        ServerStartError::PortConflict { .. } => EXIT_PORT_CONFLICT,
+        ServerStartError::Storage { .. } => EXIT_STORAGE_PROBLEM,
+        ServerStartError::InvalidOptions { .. }
+        | ServerStartError::RestartRequired
+        | ServerStartError::Internal { .. } => EXIT_FAILURE,
+    }
+}
+
+/// The app data dir without an app: the `--data-dir` or portable override if
+/// there is one, otherwise the directory Tauri's `app_data_dir()` resolves to.
+pub fn app_data_dir() -> Option<PathBuf> {
+    if let Some(dir) = crate::paths::data_dir_override() {
+        return Some(dir.to_path_buf());
+    }
+    let base = if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+    };
+    base.map(|dir| dir.join(APP_IDENTIFIER))
+}
+
+/// Release builds on Windows are GUI-subsystem and get no console; borrow the
+/// terminal we were started from so the URL and logs show up there.
+pub fn attach_console() {
+    #[cfg(windows)]
+    unsafe {
+        use windows::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+        let _ = AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+/// Run the server without a window and return the process exit code.
+pub fn run(options: HeadlessOptions) -> i32 {
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            error!("Failed to start the async runtime: {}", e);
+            return EXIT_FAILURE;
+        }
+    };
+    runtime.block_on(run_async(options))
+}
+
+async fn run_async(options: HeadlessOptions) -> i32 {
+    let Some(app_data_dir) = app_data_dir() else {
+        error!("Could not determine the app data directory");
+        return EXIT_FAILURE;
+    };
+    let settings = SettingsState::load(Some(&app_data_dir)).get();
+    let data_dir = options
+        .data_dir
+        .clone()
+        .or_else(|| settings.data_dir.clone())
+        .unwrap_or_else(|| app_data_dir.clone());
+
+    let (mut child, info) = if process_manager::check_health(options.port).await {
+        let record = process_manager::clean_stale_server_record(&data_dir);
+        info!("Found a running server on port {}", options.port);
+        let auth_token = record
+            .as_ref()
+            .filter(|record| record.requires_auth)
+            .and_then(|_| server_auth::read_token(&data_dir));
+        (None, server_info(&options, record.as_ref(), true, None, auth_token).await)
+    } else if !options.start_server {
+        error!("No server is running on port {}", options.port);
+        return EXIT_NOT_RUNNING;
+    } else {
+        match start(&options, &settings, &app_data_dir, &data_dir).await {
+            Ok((child, info)) => (Some(child), info),
+            Err(e) => {
+                e.log();
+                return exit_code(&e);
+            }
+        }
+    };
+
+    if let Err(e) = process_manager::write_endpoint_file(&app_data_dir, &info) {
+        warn!("{}", e);
+    }
+    // The one line scripts read; everything else goes to stderr
+    println!("{}", info.url);
+    for url in &info.remote_urls {
+        info!("Reachable at {}", url);
+    }
+    if info.auth_token_present {
+        info!("Other machines need the bearer token in {}", server_auth::token_path(&data_dir).display());
+    }
+
+    let code = tokio::select! {
+        _ = shutdown_signal() => EXIT_OK,
+        status = wait_for_exit(child.as_mut()) => {
+            error!("Server exited unexpectedly ({})", status);
+            process_manager::remove_endpoint_file(&app_data_dir);
+            process_manager::remove_server_record(&data_dir);
+            return EXIT_SERVER_EXITED;
+        }
+    };
+
+    // A server we only found running belongs to whoever started it
+    if options.keep_running {
+        info!("Leaving the server running");
+    } else if let Some(child) = &child {
+        stop(options.port, child.id()).await;
+        process_manager::remove_endpoint_file(&app_data_dir);
+        process_manager::remove_server_record(&data_dir);
+    }
+    code
+}
+
+/// Spawn the sidecar and wait until it answers its health endpoint.
+#[tracing::instrument(name = "server_start", skip_all, fields(port = options.port, remote = options.remote))]
+async fn start(
+    options: &HeadlessOptions,
+    settings: &Settings,
+    app_data_dir: &Path,
+    data_dir: &Path,
+) -> Result<(std::process::Child, ServerInfo), ServerStartError> {
+    process_manager::clean_stale_server_record(data_dir);
+    if std::net::TcpStream::connect(("127.0.0.1", options.port)).is_ok() {
+        return Err(ServerStartError::PortConflict {
+            occupant: format!("a program that isn't a voicebox server, on port {}", options.port),
+        });
+    }
+    if let Some(problem) = storage::probe(data_dir, storage::MIN_FREE_BYTES).problem {
+        return Err(ServerStartError::Storage { problem });
+    }
+
+    let offline = match options.offline {
+        Some(offline) => offline,
+        None => !process_manager::detect_network_available().await,
+    };
+
+    let logs_dir = app_data_dir.join("logs");
+    std::fs::create_dir_all(&logs_dir).map_err(|e| format!("Failed to create {}: {}", logs_dir.display(), e))?;
+    let log_path = logs_dir.join(SERVER_LOG_FILE);
+    let log_file =
+        std::fs::File::create(&log_path).map_err(|e| format!("Failed to create {}: {}", log_path.display(), e))?;
+    let stderr_file = log_file
+        .try_clone()
+        .map_err(|e| format!("Failed to open {}: {}", log_path.display(), e))?;
+
+    let mut command = std::process::Command::new(sidecar_path()?);
+    command
+        .arg("--data-dir")
+        .arg(data_dir)
+        .args(["--port", &options.port.to_string()])
+        .args(["--log-level", settings.server_log_level.as_str()])
+        .stdin(std::process::Stdio::null())
+        .stdout(log_file)
+        .stderr(stderr_file);
+    // Never on the LAN without a token
+    let auth_token = if options.remote {
+        let token = server_auth::read_or_create_token(data_dir)?;
+        command.args(["--host", "0.0.0.0"]).env(server_auth::AUTH_TOKEN_ENV, &token);
+        Some(token)
+    } else {
+        None
+    };
+    if offline {
+        command.arg("--offline").env("HF_HUB_OFFLINE", "1");
+    }
+    if !settings.server_access_logs {
+        command.arg("--no-access-log");
+    }
+    // Its own process group, so Ctrl+C reaches only us and --keep-running can
+    // leave it alone
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    info!("Starting voicebox-server (offline: {}), output in {}", offline, log_path.display());
+    let mut child = command.spawn().map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => ServerStartError::BinaryProblem {
+            kind: BinaryProblemKind::NotFound,
+        },
+        std::io::ErrorKind::PermissionDenied => ServerStartError::BinaryProblem {
+            kind: BinaryProblemKind::NotExecutable,
+        },
+        _ => ServerStartError::SpawnFailed {
+            os_error: e.to_string(),
+        },
+    })?;
+    let pid = child.id();
+    let started_at = process_manager::unix_timestamp();
+
+    let startup_timeout = Duration::from_secs(settings.server_startup_timeout_secs.into());
+    let started = Instant::now();
+    loop {
+        if let Ok(Some(status)) = child.try_wait() {
+            return Err(ServerStartError::CrashedDuringStartup {
+                exit_code: status.code(),
+                tail_lines: output_tail(&log_path).1,
+            });
+        }
+        if process_manager::check_health(options.port).await {
+            break;
+        }
+        if started.elapsed() > startup_timeout {
+            process_manager::force_kill(pid);
+            let _ = child.wait();
+            let (last_phase, tail_lines) = output_tail(&log_path);
+            return Err(ServerStartError::Timeout { last_phase, tail_lines });
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    info!("Server is ready (PID {})", pid);
+
+    let record = ServerRecord {
+        pid,
+        port: options.port,
+        started_at,
+        idle_shutdown_after_minutes: None,
+        launch_options: None,
+        requires_auth: auth_token.is_some(),
+    };
+    if let Err(e) = process_manager::write_server_record(data_dir, &record) {
+        warn!("{}", e);
+    }
+    let info = ServerInfo {
+        startup_ms: Some(started.elapsed().as_millis() as u64),
+        ..server_info(options, Some(&record), false, Some(offline), auth_token).await
+    };
+    Ok((child, info))
+}
+
+/// Where Tauri installs the sidecar: next to our own executable.
+fn sidecar_path() -> Result<PathBuf, ServerStartError> {
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to locate the executable: {}", e))?;
+    let name = if cfg!(windows) {
+        "voicebox-server.exe"
+    } else {
+        "voicebox-server"
+    };
+    Ok(exe.parent().unwrap_or(Path::new(".")).join(name))
+}
+
+/// The last lines of the server's output, and the startup phase they show.
+fn output_tail(log_path: &Path) -> (StartupPhase, Vec<String>) {
+    let contents = std::fs::read_to_string(log_path).unwrap_or_default();
+    let phase = contents
+        .lines()
+        .filter_map(StartupPhase::from_log_line)
+        .next_back()
+        .unwrap_or(StartupPhase::Launching);
+    let lines: Vec<String> = contents.lines().map(str::to_string).collect();
+    let tail = lines[lines.len().saturating_sub(TAIL_LINES)..].to_vec();
+    (phase, tail)
+}
+
+async fn server_info(
+    options: &HeadlessOptions,
+    record: Option<&ServerRecord>,
+    adopted_existing: bool,
+    offline: Option<bool>,
+    auth_token: Option<String>,
+) -> ServerInfo {
+    ServerInfo {
+        url: format!("http://127.0.0.1:{}", options.port),
+        remote: options.remote,
+        remote_urls: if options.remote {
+            process_manager::remote_urls(options.port)
+        } else {
+            Vec::new()
+        },
+        port: options.port,
+        pid: record.map(|record| record.pid),
+        adopted_existing,
+        started_at: record.map_or_else(process_manager::unix_timestamp, |record| record.started_at),
+        startup_ms: None,
+        auth_token_present: auth_token.is_some(),
+        auth_token,
+        version: process_manager::fetch_server_version(options.port).await,
+        offline,
+        firewall_rule: None,
+        managed: true,
+        launch_options: None,
+    }
+}
+
+/// SIGINT, or SIGTERM on Unix.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Resolves when a server we spawned exits; never for an adopted one.
+async fn wait_for_exit(child: Option<&mut std::process::Child>) -> String {
+    let Some(child) = child else {
+        return std::future::pending().await;
+    };
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return status.to_string(),
+            Ok(None) => tokio::time::sleep(Duration::from_millis(500)).await,
+            Err(e) => return e.to_string(),
+        }
+    }
+}
+
+/// Ask the server to shut down, then kill it if it doesn't within STOP_GRACE.
+async fn stop(port: u16, pid: u32) {
+    info!("Stopping the server (PID {})", pid);
+    let shutdown = reqwest::Client::new()
+        .post(format!("http://127.0.0.1:{}/shutdown", port))
+        .timeout(Duration::from_secs(2))
+        .send()
+        .await;
+    if shutdown.is_ok() {
+        let deadline = Instant::now() + STOP_GRACE;
+        while Instant::now() < deadline {
+            if !process_manager::is_process_running(pid) {
+                info!("Server stopped");
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+    warn!("Server did not stop in time, killing it");
+    process_manager::force_kill(pid);
+}
@@ -0,0 +1,256 @@
+use crate::audio_capture::{self, AudioCaptureState};
+use crate::audio_output::AudioOutputState;
+use crate::error::VoiceboxError;
+use crate::settings::{HotkeyAction, SettingsState};
+use serde::Serialize;
+use std::str::FromStr;
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutEvent, ShortcutState};
+use tracing::{info, warn};
+
+/// Why a hotkey could not be registered.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HotkeyError {
+    UnknownAction { message: String },
+    InvalidAccelerator { accelerator: String, message: String },
+    /// Already bound to another Voicebox action, or (`action` None) claimed by
+    /// another application.
+    Conflict {
+        accelerator: String,
+        action: Option<HotkeyAction>,
+        message: String,
+    },
+    Internal { message: String },
+}
+
+impl std::fmt::Display for HotkeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HotkeyError::UnknownAction { message }
+            | HotkeyError::InvalidAccelerator { message, .. }
+            | HotkeyError::Conflict { message, .. }
+            | HotkeyError::Internal { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for HotkeyError {
+    fn from(message: String) -> Self {
+        HotkeyError::Internal { message }
+    }
+}
+
+/// Payload of `capture-hotkey-started`, for a capture the UI didn't start.
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureHotkeyStarted {
+    pub max_duration_secs: u32,
+}
+
+/// Payload of `capture-hotkey-stopped`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureHotkeyStopped {
+    /// Base64 WAV, as stop_system_audio_capture returns it.
+    pub audio: String,
+}
+
+/// The capture the hotkey started, or is stopping, by session. Its audio goes
+/// out as `capture-hotkey-stopped` however it ends, since no window may be
+/// waiting to collect it.
+pub struct HotkeyCaptureState {
+    session: Mutex<Option<u64>>,
+}
+
+impl HotkeyCaptureState {
+    pub fn new() -> Self {
+        Self {
+            session: Mutex::new(None),
+        }
+    }
+
+    fn claim(&self, session: u64) {
+        *self.session.lock().unwrap() = Some(session);
+    }
+
+    /// Whether `session` is the hotkey's capture.
+    pub fn owns(&self, session: u64) -> bool {
+        *self.session.lock().unwrap() == Some(session)
+    }
+
+    /// Whether `session` was the hotkey's capture, which it no longer is.
+    fn release(&self, session: u64) -> bool {
+        let mut claimed = self.session.lock().unwrap();
+        let owned = *claimed == Some(session);
+        if owned {
+            *claimed = None;
+        }
+        owned
+    }
+}
+
+/// Called by end_capture for every capture it stops: emit
+/// `capture-hotkey-stopped` when `session` was the hotkey's and was saved.
+pub fn capture_ended(app: &AppHandle, session: u64, result: &Result<String, VoiceboxError>) {
+    if !app.state::<HotkeyCaptureState>().release(session) {
+        return;
+    }
+    if let Ok(audio) = result {
+        let stopped = CaptureHotkeyStopped { audio: audio.clone() };
+        if let Err(e) = crate::event_bus::emit(app, "capture-hotkey-stopped", stopped) {
+            warn!("Failed to emit capture-hotkey-stopped event: {}", e);
+        }
+    }
+}
+
+/// Payload of the `hotkey-triggered` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct HotkeyTriggered {
+    pub action: HotkeyAction,
+    /// Base64 WAV when the hotkey ended a capture.
+    pub audio: Option<String>,
+    /// Seconds into the current capture, for AddCaptureMarker.
+    pub marker_secs: Option<f64>,
+    pub error: Option<String>,
+}
+
+fn parse_accelerator(accelerator: &str) -> Result<Shortcut, HotkeyError> {
+    Shortcut::from_str(accelerator.trim()).map_err(|e| HotkeyError::InvalidAccelerator {
+        accelerator: accelerator.to_string(),
+        message: format!("Invalid shortcut '{}': {}", accelerator, e),
+    })
+}
+
+/// Bind `accelerator` to `action`, replacing the action's previous binding,
+/// and persist it.
+pub fn register(app: &AppHandle, action: HotkeyAction, accelerator: &str) -> Result<(), HotkeyError> {
+    let shortcut = parse_accelerator(accelerator)?;
+    let settings = app.state::<SettingsState>();
+    let bindings = settings.get().hotkeys;
+
+    for (other, other_accelerator) in &bindings {
+        if *other == action {
+            continue;
+        }
+        if parse_accelerator(other_accelerator).is_ok_and(|s| s.id() == shortcut.id()) {
+            return Err(HotkeyError::Conflict {
+                accelerator: accelerator.to_string(),
+                action: Some(*other),
+                message: format!("'{}' is already used for {}", accelerator, other.as_str()),
+            });
+        }
+    }
+
+    let global_shortcut = app.global_shortcut();
+    if let Some(previous) = bindings.get(&action).and_then(|a| parse_accelerator(a).ok()) {
+        if previous.id() != shortcut.id() {
+            let _ = global_shortcut.unregister(previous);
+        } else if global_shortcut.is_registered(shortcut) {
+            return Ok(());
+        }
+    }
+
+    bind(app, action, shortcut).map_err(|e| HotkeyError::Conflict {
+        accelerator: accelerator.to_string(),
+        action: None,
+        message: format!("'{}' could not be registered, it may be in use by another application: {}", accelerator, e),
+    })?;
+    settings.update(|s| {
+        s.hotkeys.insert(action, accelerator.trim().to_string());
+    })?;
+    info!("Registered hotkey {} for {}", accelerator, action.as_str());
+    Ok(())
+}
+
+/// Remove the binding for `action`, if any.
+pub fn unregister(app: &AppHandle, action: HotkeyAction) -> Result<(), HotkeyError> {
+    let settings = app.state::<SettingsState>();
+    let Some(accelerator) = settings.get().hotkeys.get(&action).cloned() else {
+        return Ok(());
+    };
+    if let Ok(shortcut) = parse_accelerator(&accelerator) {
+        if let Err(e) = app.global_shortcut().unregister(shortcut) {
+            warn!("Failed to unregister hotkey {}: {}", accelerator, e);
+        }
+    }
+    settings.update(|s| {
+        s.hotkeys.remove(&action);
+    })?;
+    info!("Unregistered hotkey {} for {}", accelerator, action.as_str());
+    Ok(())
+}
+
+/// Register the hotkeys saved in settings. Called from setup; a binding that
+/// fails (e.g. now taken by another app) is logged and kept in settings.
+pub fn restore(app: &AppHandle) {
+    let bindings = app.state::<SettingsState>().get().hotkeys;
+    for (action, accelerator) in bindings {
+        let result = parse_accelerator(&accelerator)
+            .and_then(|shortcut| bind(app, action, shortcut).map_err(|e| HotkeyError::from(e.to_string())));
+        match result {
+            Ok(()) => info!("Restored hotkey {} for {}", accelerator, action.as_str()),
+            Err(e) => warn!("Failed to restore hotkey {} for {}: {}", accelerator, action.as_str(), e),
+        }
+    }
+}
+
+fn bind(app: &AppHandle, action: HotkeyAction, shortcut: Shortcut) -> Result<(), tauri_plugin_global_shortcut::Error> {
+    app.global_shortcut()
+        .on_shortcut(shortcut, move |app, _shortcut, event: ShortcutEvent| {
+            if event.state == ShortcutState::Pressed {
+                let app = app.clone();
+                tauri::async_runtime::spawn(async move { run_action(&app, action).await });
+            }
+        })
+}
+
+async fn run_action(app: &AppHandle, action: HotkeyAction) {
+    let mut triggered = HotkeyTriggered {
+        action,
+        audio: None,
+        marker_secs: None,
+        error: None,
+    };
+
+    match action {
+        HotkeyAction::ToggleCapture => {
+            let capture = app.state::<AudioCaptureState>();
+            if capture.is_capturing() {
+                // Stopped by the hotkey, so end_capture sends its audio out too
+                app.state::<HotkeyCaptureState>().claim(capture.current_session());
+                match crate::end_capture(app, audio_capture::SaveAs::default()).await {
+                    Ok(audio) => triggered.audio = Some(audio),
+                    Err(e) => triggered.error = Some(e.to_string()),
+                }
+            } else if !audio_capture::is_supported() {
+                triggered.error = Some("System audio capture is not supported on this platform".to_string());
+            } else {
+                let max_duration_secs = app.state::<SettingsState>().get().hotkey_capture_max_secs;
+                match crate::begin_capture(app, max_duration_secs).await {
+                    Ok(()) => {
+                        app.state::<HotkeyCaptureState>().claim(capture.current_session());
+                        let started = CaptureHotkeyStarted { max_duration_secs };
+                        let _ = crate::event_bus::emit(app, "capture-hotkey-started", started);
+                    }
+                    Err(e) => triggered.error = Some(e.to_string()),
+                }
+            }
+        }
+        HotkeyAction::AddCaptureMarker => match crate::mark_capture(app, None) {
+            Some(secs) => triggered.marker_secs = Some(secs),
+            None => triggered.error = Some("No capture is running".to_string()),
+        },
+        HotkeyAction::StopPlayback => {
+            if let Err(e) = app.state::<AudioOutputState>().stop_all_playback() {
+                triggered.error = Some(e.to_string());
+            }
+        }
+    }
+
+    if let Some(error) = &triggered.error {
+        warn!("Hotkey {} failed: {}", action.as_str(), error);
+    }
+    if let Err(e) = crate::event_bus::emit(app, "hotkey-triggered", triggered) {
+        warn!("Failed to emit hotkey-triggered event: {}", e);
+    }
+}
@@ -0,0 +1,126 @@
+use crate::backup;
+use crate::file_import;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager};
+use tracing::{info, warn};
+
+/// URL scheme forwarded to the running instance unchanged.
+pub const DEEP_LINK_SCHEME: &str = "voicebox://";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportKind {
+    /// A Voicebox backup archive, ready for import_data_backup.
+    Backup,
+    /// An audio file that can be used as a voice sample.
+    Audio,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportFile {
+    pub path: PathBuf,
+    pub kind: ImportKind,
+}
+
+/// Payload of the `second-instance` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct SecondInstance {
+    pub args: Vec<String>,
+    pub cwd: String,
+    /// Already dispatched through the deep-link plugin; listed for completeness.
+    pub deep_links: Vec<String>,
+    /// File arguments resolved against `cwd` and recognized as importable.
+    pub files: Vec<ImportFile>,
+}
+
+/// Handle a launch that the single-instance plugin redirected to us: bring the
+/// window forward and pass the arguments on to the frontend.
+///
+/// The plugin's lock is owned by the OS (a named mutex on Windows, a D-Bus name
+/// on Linux, a socket that is reclaimed when nothing answers on macOS), so a
+/// crashed instance never leaves users locked out. It is keyed on the data dir
+/// (see paths::instance_suffix), so only launches on our own data dir get here.
+pub fn handle_second_instance(app: &AppHandle, args: Vec<String>, cwd: String) {
+    info!("Second instance launched with args {:?} (cwd: {})", args, cwd);
+
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+    crate::tray::refresh(app);
+
+    // The first argument is the executable
+    let cwd_path = PathBuf::from(&cwd);
+    let mut deep_links = Vec::new();
+    let mut files = Vec::new();
+    for arg in args.iter().skip(1) {
+        if arg.starts_with(DEEP_LINK_SCHEME) {
+            deep_links.push(arg.clone());
+        } else if !arg.starts_with('-') {
+            if let Some(file) = classify_file(&cwd_path, arg) {
+                files.push(file);
+            }
+        }
+    }
+
+    // Audio files are imported here; backups are left to the frontend, which
+    // asks before replacing anything
+    let audio: Vec<PathBuf> = files
+        .iter()
+        .filter(|file| file.kind == ImportKind::Audio)
+        .map(|file| file.path.clone())
+        .collect();
+
+    let payload = SecondInstance {
+        args,
+        cwd,
+        deep_links,
+        files,
+    };
+    if let Err(e) = crate::event_bus::emit(app, "second-instance", payload) {
+        warn!("Failed to emit second-instance event: {}", e);
+    }
+    file_import::open(app, audio);
+}
+
+/// Audio files among the arguments of our own launch (Windows and Linux pass
+/// double-clicked files this way; macOS sends an open-file event instead).
+pub fn launch_audio_files(args: &[String]) -> Vec<PathBuf> {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    args.iter()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-') && !arg.starts_with(DEEP_LINK_SCHEME))
+        .filter_map(|arg| classify_file(&cwd, arg))
+        .filter(|file| file.kind == ImportKind::Audio)
+        .map(|file| file.path)
+        .collect()
+}
+
+/// Resolve a file argument and decide whether it is something we can import.
+fn classify_file(cwd: &Path, arg: &str) -> Option<ImportFile> {
+    let path = cwd.join(arg);
+    let path = path.canonicalize().ok().filter(|p| p.is_file())?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+
+    let kind = if extension == "zip" {
+        match backup::read_manifest(&path) {
+            Ok(_) => ImportKind::Backup,
+            Err(e) => {
+                warn!("Ignoring {:?}: {}", path, e);
+                return None;
+            }
+        }
+    } else if crate::audio_import::AUDIO_EXTENSIONS.contains(&extension.as_str()) {
+        ImportKind::Audio
+    } else {
+        info!("Ignoring unsupported file argument {:?}", path);
+        return None;
+    };
+    Some(ImportFile { path, kind })
+}
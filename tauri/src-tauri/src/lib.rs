@@ -1 +1,129 @@
+//! The audio side of Voicebox as a library: system audio capture, playback to
+//! output devices and WAV encoding, without Tauri. The app's commands wrap
+//! these; tools of your own can drive them directly.
+//!
+//! The stable surface is [`audio_capture`], [`audio_output`],
+//! [`audio_route`] and [`audio_util`], plus the [`error`] and
+//! [`recording_metadata`] types they return. It is versioned by
+//! [`API_VERSION`], not the app's version. Other modules are internal, public
+//! only with the `test-support` feature the integration tests build with.
+//!
+//! The platform backends (ScreenCaptureKit, WASAPI, cpal) come with the
+//! default `native-backends` feature. Without it, captures run through the
+//! synthetic backend and playback through a backend you supply.
+//!
+//! Capture a second of a generated tone as WAV:
+//!
+//! ```
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//! use voicebox::audio_capture::{self, AudioCaptureState, SyntheticBackend, SyntheticPattern};
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let tone = SyntheticPattern::Sine { frequency: 440.0, amplitude: 0.5 };
+//! let state = AudioCaptureState::with_backend(Arc::new(SyntheticBackend::new(tone)));
+//! audio_capture::start_capture(&state, 60).await?;
+//! tokio::time::sleep(Duration::from_secs(1)).await;
+//! let wav_base64 = audio_capture::stop_capture(&state).await?;
+//! assert!(!wav_base64.is_empty());
+//! # Ok::<(), voicebox::error::VoiceboxError>(())
+//! # }).unwrap();
+//! ```
+//!
+//! Play frames through an output backend, here one that only records them:
+//!
+//! ```
+//! use std::sync::Arc;
+//! use voicebox::audio_output::{AudioOutputState, DeviceFormat, NullSink, PlaybackOptions};
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let sink = Arc::new(NullSink::new(DeviceFormat { sample_rate: 48000, channels: 2 }));
+//! let output = AudioOutputState::with_backend(sink.clone());
+//! let clip = vec![0.25; 4800];
+//! let devices = ["device_null".to_string()];
+//! let length = output.play_samples(&clip, 48000, 1, &devices, PlaybackOptions::default()).await?;
+//! assert_eq!(length.as_millis(), 100);
+//! # Ok::<(), voicebox::error::VoiceboxError>(())
+//! # }).unwrap();
+//! ```
+//!
+//! Encode samples as 24-bit WAV:
+//!
+//! ```
+//! use voicebox::audio_util::{encode_wav, EncodeSpec, WavSampleFormat};
+//!
+//! let spec = EncodeSpec::pcm16(48000, 1).with_format(WavSampleFormat::Int24);
+//! let wav = encode_wav(&[0.0, 0.5, -0.5], spec)?;
+//! assert_eq!(&wav[..4], b"RIFF");
+//! # Ok::<(), String>(())
+//! ```
+
+/// The version of the library surface, following semver: a breaking change
+/// to the stable modules bumps the major version. Their option and status
+/// structs are `#[non_exhaustive]`, so a field added to one is a minor
+/// change.
+pub const API_VERSION: &str = "1.1.0";
+
 pub mod audio_capture;
+pub mod audio_output;
+pub mod audio_route;
+pub mod audio_util;
+pub mod error;
+pub mod recording_metadata;
+
+/// Declares the modules the stable ones are built on and the app's own: private
+/// to the crate, or public with the `test-support` feature, which only the
+/// integration tests turn on.
+macro_rules! internal_modules {
+    ($($module:ident),* $(,)?) => {
+        $(
+            #[cfg(feature = "test-support")]
+            #[doc(hidden)]
+            pub mod $module;
+            // Much of each is only used by the app, which has its own copy
+            #[cfg(not(feature = "test-support"))]
+            #[allow(dead_code)]
+            mod $module;
+        )*
+    };
+}
+
+internal_modules! {
+    audio_conflict,
+    audio_environment,
+    audio_export,
+    audio_import,
+    audio_latency,
+    audio_log,
+    audio_process,
+    audio_selftest,
+    audio_split,
+    backup,
+    combined_capture,
+    control_api,
+    crash_log,
+    data_migration,
+    diagnostics,
+    event_bus,
+    fingerprint,
+    firewall,
+    folder_watch,
+    headless,
+    logging,
+    metrics,
+    paths,
+    pipeline_benchmark,
+    power,
+    process_manager,
+    process_utils,
+    recordings,
+    schedule,
+    server_auth,
+    server_error,
+    server_options,
+    server_output,
+    settings,
+    setup_checks,
+    storage,
+    system_audio_info,
+}
@@ -0,0 +1,171 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use tracing::Level;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+/// Target for the sidecar's own output, so `voicebox::server=warn` quiets it
+/// without touching the app's lines.
+pub const SERVER_TARGET: &str = "voicebox::server";
+
+/// Daily files named `app.log.<date>` in the logs dir.
+const LOG_FILE_PREFIX: &str = "app.log";
+const MAX_LOG_FILES: usize = 7;
+
+/// Upper bound for get_app_logs, whatever the UI asks for.
+const MAX_TAIL_LINES: usize = 5000;
+
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+static LOGS_DIR: OnceLock<PathBuf> = OnceLock::new();
+static NEXT_SPAN_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    pub timestamp: String,
+    pub level: String,
+    /// Spans, target and message, as written to the file.
+    pub message: String,
+}
+
+fn default_filter() -> &'static str {
+    if cfg!(debug_assertions) {
+        "info,voicebox=debug"
+    } else {
+        "info"
+    }
+}
+
+/// Install the global subscriber: a file layer under `logs_dir` always, plus
+/// stdout in debug builds, or stderr when `headless` (stdout is its output).
+/// RUST_LOG, if set, replaces the default filter. Called once, from setup or
+/// before a headless run; without a logs dir only the console is written.
+pub fn init(logs_dir: Option<&Path>, headless: bool) {
+    let directives = std::env::var("RUST_LOG").unwrap_or_else(|_| default_filter().to_string());
+    let filter = EnvFilter::try_new(&directives).unwrap_or_else(|e| {
+        eprintln!("Ignoring invalid RUST_LOG {:?}: {}", directives, e);
+        EnvFilter::new(default_filter())
+    });
+    let (filter, handle) = reload::Layer::new(filter);
+
+    let appender = logs_dir.and_then(|dir| {
+        RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix(LOG_FILE_PREFIX)
+            .max_log_files(MAX_LOG_FILES)
+            .build(dir)
+            .inspect_err(|e| eprintln!("Failed to open the log file in {}: {}", dir.display(), e))
+            .ok()
+    });
+    let file_layer = appender.map(|writer| fmt::layer().with_ansi(false).with_writer(writer));
+    let stdout_layer = (cfg!(debug_assertions) && !headless).then(fmt::layer);
+    let stderr_layer = headless.then(|| fmt::layer().with_writer(std::io::stderr));
+
+    if let Err(e) = tracing_subscriber::registry()
+        .with(filter)
+        .with(stdout_layer)
+        .with(stderr_layer)
+        .with(file_layer)
+        .try_init()
+    {
+        eprintln!("Failed to install the logger: {}", e);
+        return;
+    }
+    let _ = FILTER.set(handle);
+    if let Some(dir) = logs_dir {
+        let _ = LOGS_DIR.set(dir.to_path_buf());
+    }
+}
+
+/// Replace the filter directives, e.g. `info,voicebox::audio_output=debug`.
+pub fn set_filter(directives: &str) -> Result<(), String> {
+    let filter = EnvFilter::try_new(directives).map_err(|e| format!("Invalid log filter {:?}: {}", directives, e))?;
+    FILTER
+        .get()
+        .ok_or("Logging is not initialized")?
+        .reload(filter)
+        .map_err(|e| format!("Failed to apply log filter: {}", e))?;
+    tracing::info!("Log filter set to {}", directives);
+    Ok(())
+}
+
+/// Distinguishes spans that have no natural id of their own, like server starts.
+pub fn next_span_id() -> u64 {
+    NEXT_SPAN_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The last `tail_lines` entries at `level_at_least` or more severe, oldest
+/// first. Blocking.
+pub fn tail(tail_lines: usize, level_at_least: Option<&str>) -> Result<Vec<LogEntry>, String> {
+    let min_level = level_at_least
+        .map(|level| Level::from_str(level).map_err(|_| format!("Unknown log level: {}", level)))
+        .transpose()?
+        .unwrap_or(Level::TRACE);
+    let tail_lines = tail_lines.min(MAX_TAIL_LINES);
+    let Some(dir) = LOGS_DIR.get() else {
+        return Ok(Vec::new());
+    };
+
+    let mut entries = Vec::new();
+    for file in log_files(dir) {
+        let contents =
+            std::fs::read_to_string(&file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        // Level is the more verbose the greater
+        let mut older: Vec<LogEntry> = parse(&contents)
+            .into_iter()
+            .filter(|entry| Level::from_str(&entry.level).is_ok_and(|level| level <= min_level))
+            .collect();
+        older.append(&mut entries);
+        entries = older;
+        if entries.len() >= tail_lines {
+            break;
+        }
+    }
+    Ok(entries.split_off(entries.len().saturating_sub(tail_lines)))
+}
+
+/// Log files in `dir`, newest first. The date suffix sorts chronologically.
+fn log_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(read) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = read
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(LOG_FILE_PREFIX))
+        })
+        .collect();
+    files.sort();
+    files.reverse();
+    files
+}
+
+/// Split fmt output (`<timestamp>  INFO <spans>: <target>: <message>`) into
+/// entries. Lines that don't start with a timestamp continue the previous one.
+fn parse(contents: &str) -> Vec<LogEntry> {
+    let mut entries: Vec<LogEntry> = Vec::new();
+    for line in contents.lines() {
+        let mut parts = line.splitn(2, ' ');
+        let timestamp = parts.next().unwrap_or_default();
+        let rest = parts.next().unwrap_or_default().trim_start();
+        let mut rest_parts = rest.splitn(2, ' ');
+        let level = rest_parts.next().unwrap_or_default();
+        if Level::from_str(level).is_ok() && timestamp.contains('T') {
+            entries.push(LogEntry {
+                timestamp: timestamp.to_string(),
+                level: level.to_string(),
+                message: rest_parts.next().unwrap_or_default().to_string(),
+            });
+        } else if let Some(last) = entries.last_mut() {
+            last.message.push('\n');
+            last.message.push_str(line);
+        }
+    }
+    entries
+}
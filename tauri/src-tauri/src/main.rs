@@ -1,220 +1,638 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+#[cfg(target_os = "macos")]
+mod app_menu;
 mod audio_capture;
+mod audio_conflict;
+mod audio_environment;
+mod audio_export;
+mod audio_import;
+mod audio_latency;
+mod audio_log;
 mod audio_output;
+mod audio_process;
+mod audio_route;
+mod audio_selftest;
+mod audio_split;
+mod audio_util;
+mod autostart;
+mod backup;
+mod clipboard;
+mod combined_capture;
+mod control_api;
+mod crash;
+mod crash_log;
+mod data_migration;
+mod deep_link;
+mod diagnostics;
+mod disk_usage;
+mod error;
+mod event_bus;
+mod file_import;
+mod fingerprint;
+mod firewall;
+mod folder_watch;
+mod headless;
+mod hotkeys;
+mod instance;
+mod logging;
+mod media_controls;
+mod metrics;
+mod mini_recorder;
+mod mru;
+mod notifications;
+mod paths;
+mod permissions;
+mod pipeline_benchmark;
+mod power;
+mod power_events;
+mod process_manager;
+mod process_utils;
+mod progress_indicator;
+mod recording_metadata;
+mod recordings;
+mod reveal;
+mod schedule;
+mod scheduled_capture;
+mod server_auth;
+mod server_error;
+mod server_options;
+mod server_output;
+mod settings;
+mod setup_checks;
+mod status_indicator;
+mod storage;
+mod system_audio_info;
+mod tray;
+mod updates;
+mod watched_folders;
+mod window_state;
 
-use std::sync::Mutex;
-use tauri::{command, State, Manager, WindowEvent, Emitter, Listener, RunEvent};
+use process_manager::{
+    ServerInfo, ServerRecord, ServerState, ServerStatus, StopOutcome, VersionCompatibility, SERVER_PORT,
+};
+use error::VoiceboxError;
+use server_error::{
+    BinaryProblemKind, ServerStartError, StartupPhase, StartupProgress, STARTUP_PROGRESS_INTERVAL, TAIL_LINES,
+};
+use server_options::ServerLaunchOptions;
+use server_output::LogStream;
+use settings::{
+    CloseBehavior, HotkeyAction, ProcessPriority, ServerLogLevel, SettingApplied, SettingsState, UpdateChannel,
+};
+use tauri::{command, DragDropEvent, State, Manager, WindowEvent, Emitter, RunEvent};
 use tauri_plugin_shell::ShellExt;
-use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn, Instrument};
 
 const LEGACY_PORT: u16 = 8000;
-const SERVER_PORT: u16 = 17493;
 
-struct ServerState {
-    child: Mutex<Option<tauri_plugin_shell::process::CommandChild>>,
-    server_pid: Mutex<Option<u32>>,
-    keep_running_on_close: Mutex<bool>,
+/// Data directory handed to the server: the migrated location if the user moved
+/// it, otherwise the app data dir.
+fn resolve_data_dir(app: &tauri::AppHandle) -> Result<std::path::PathBuf, String> {
+    if let Some(settings) = app.try_state::<SettingsState>() {
+        if let Some(dir) = settings.get().data_dir {
+            return Ok(dir);
+        }
+    }
+    paths::app_data_dir(app)
+}
+
+/// Probe the data dir and remember the result. Settings stop being saved
+/// while their directory can't be written, and `storage-problem` is emitted
+/// when a problem appears or changes.
+fn refresh_storage(app: &tauri::AppHandle) -> storage::StorageStatus {
+    let status = match resolve_data_dir(app) {
+        Ok(dir) => storage::probe(&dir, storage::MIN_FREE_BYTES),
+        Err(e) => storage::StorageStatus::unresolved(e),
+    };
+    // Settings stay in the app data dir when the data dir is moved
+    let settings_writable = match paths::app_data_dir(app) {
+        Ok(dir) if dir == status.path => status.writable,
+        Ok(dir) => storage::probe(&dir, 0).writable,
+        Err(_) => false,
+    };
+    if let Err(e) = app.state::<SettingsState>().set_persistent(settings_writable) {
+        warn!("Failed to save settings: {}", e);
+    }
+
+    let previous = app.state::<storage::StorageState>().replace(status.clone());
+    let previous_problem = previous.and_then(|status| status.problem);
+    match &status.problem {
+        Some(problem) if previous_problem.as_ref() != Some(problem) => {
+            warn!("Storage problem at {}: {}", problem.path.display(), problem.detail);
+            if let Err(e) = event_bus::emit(app, "storage-problem", problem) {
+                warn!("Failed to emit storage-problem event: {}", e);
+            }
+        }
+        None if previous_problem.is_some() => info!("The data directory is usable again"),
+        _ => {}
+    }
+    status
+}
+
+/// Build the ServerInfo for a server we spawned or adopted, query its version,
+/// and remember it for get_server_status.
+async fn publish_server_info(
+    state: &ServerState,
+    pid: Option<u32>,
+    adopted_existing: bool,
+    started_at: Option<u64>,
+    remote: bool,
+    offline: Option<bool>,
+    firewall_rule: Option<firewall::FirewallRuleStatus>,
+) -> ServerInfo {
+    let port = state.port();
+    let version = process_manager::fetch_server_version(port).await;
+    let auth_token = state.auth_token.lock().unwrap().clone();
+    let info = ServerInfo {
+        url: format!("http://127.0.0.1:{}", port),
+        remote,
+        remote_urls: if remote {
+            process_manager::remote_urls(port)
+        } else {
+            Vec::new()
+        },
+        port,
+        pid,
+        adopted_existing,
+        started_at: started_at.unwrap_or_else(process_manager::unix_timestamp),
+        startup_ms: None,
+        auth_token_present: auth_token.is_some(),
+        auth_token,
+        version,
+        offline,
+        firewall_rule,
+        managed: true,
+        launch_options: state.launch_options.lock().unwrap().clone(),
+    };
+    *state.info.lock().unwrap() = Some(info.clone());
+    info
 }
 
 #[command]
 async fn start_server(
     app: tauri::AppHandle,
     state: State<'_, ServerState>,
+    settings: State<'_, SettingsState>,
     remote: Option<bool>,
-) -> Result<String, String> {
-    // Check if server is already running (managed by this app instance)
-    if state.child.lock().unwrap().is_some() {
-        return Ok(format!("http://127.0.0.1:{}", SERVER_PORT));
+    offline: Option<bool>,
+    options: Option<ServerLaunchOptions>,
+    startup_timeout_secs: Option<u32>,
+) -> Result<ServerInfo, ServerStartError> {
+    let is_remote = remote.unwrap_or(false);
+    let options = options.filter(|options| !options.is_empty());
+    if let Some(options) = &options {
+        options.validate().map_err(|message| ServerStartError::InvalidOptions { message })?;
     }
+    let startup_timeout_secs = startup_timeout_secs.unwrap_or_else(|| settings.get().server_startup_timeout_secs);
+    settings::validate_startup_timeout(startup_timeout_secs)
+        .map_err(|message| ServerStartError::InvalidOptions { message })?;
+    let startup_timeout = std::time::Duration::from_secs(startup_timeout_secs.into());
 
-    // Check if a voicebox server is already running on our port (from previous session with keep_running=true)
-    #[cfg(unix)]
-    {
-        use std::process::Command;
-        if let Ok(output) = Command::new("lsof")
-            .args(["-i", &format!(":{}", SERVER_PORT), "-sTCP:LISTEN"])
-            .output()
-        {
-            let output_str = String::from_utf8_lossy(&output.stdout);
-            for line in output_str.lines().skip(1) {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    let command = parts[0];
-                    let pid_str = parts[1];
-                    if command.contains("voicebox") {
-                        if let Ok(pid) = pid_str.parse::<u32>() {
-                            println!("Found existing voicebox-server on port {} (PID: {}), reusing it", SERVER_PORT, pid);
-                            // Store the PID so we can kill it on exit if needed
-                            *state.server_pid.lock().unwrap() = Some(pid);
-                            return Ok(format!("http://127.0.0.1:{}", SERVER_PORT));
-                        }
-                    }
-                }
-            }
+    // While connected to an external server there is nothing to start; the
+    // bundled sidecar is only used again after use_bundled_server
+    if !*state.managed.lock().unwrap() {
+        let info = state.info.lock().unwrap().clone();
+        return info.ok_or_else(|| "No external server is configured".to_string().into());
+    }
+
+    // A second caller (e.g. a double-mounted effect) waits for the first and
+    // gets its server back instead of spawning another one onto the same port
+    let requested = options.clone().unwrap_or_default();
+    let info = process_manager::start_serialized(
+        &state,
+        |info| process_manager::check_health(info.port),
+        || {
+            launch_server(
+                app.clone(),
+                state.clone(),
+                settings.clone(),
+                is_remote,
+                offline,
+                requested.clone(),
+                startup_timeout,
+            )
+        },
+    )
+    .await
+    .inspect(|info| {
+        update_endpoint_file(&app, Some(info));
+        let _ = event_bus::emit(&app, "server-started", info);
+        let _ = event_bus::emit(&app, "server-ready", info);
+        if std::mem::take(&mut *state.recovering.lock().unwrap()) {
+            metrics::METRICS.server_restarts.inc();
+            let _ = event_bus::emit(&app, "server-ready-after-restart", info);
         }
+    })
+    .inspect_err(|e| {
+        e.log();
+        update_endpoint_file(&app, None);
+    })?;
+
+    // Options only apply at spawn, so a server that was already running
+    // can't take different ones
+    if options.is_some() && info.launch_options != options {
+        let e = ServerStartError::RestartRequired;
+        e.log();
+        return Err(e);
     }
-    
-    #[cfg(windows)]
-    {
-        use std::process::Command;
-        if let Ok(output) = Command::new("netstat")
-            .args(["-ano"])
-            .output()
-        {
-            let output_str = String::from_utf8_lossy(&output.stdout);
-            for line in output_str.lines() {
-                if line.contains(&format!(":{}", SERVER_PORT)) && line.contains("LISTENING") {
-                    if let Some(pid_str) = line.split_whitespace().last() {
-                        if let Ok(pid) = pid_str.parse::<u32>() {
-                            if let Ok(tasklist_output) = Command::new("tasklist")
-                                .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
-                                .output()
-                            {
-                                let tasklist_str = String::from_utf8_lossy(&tasklist_output.stdout);
-                                if tasklist_str.to_lowercase().contains("voicebox") {
-                                    println!("Found existing voicebox-server on port {} (PID: {}), reusing it", SERVER_PORT, pid);
-                                    // Store the PID so we can kill it on exit if needed
-                                    *state.server_pid.lock().unwrap() = Some(pid);
-                                    return Ok(format!("http://127.0.0.1:{}", SERVER_PORT));
-                                }
-                            }
-                        }
-                    }
-                }
+    Ok(info)
+}
+
+/// start_server for callers outside the frontend, such as the tray menu.
+async fn start_server_from_tray(app: &tauri::AppHandle, remote: bool) {
+    let result = start_server(
+        app.clone(),
+        app.state::<ServerState>(),
+        app.state::<SettingsState>(),
+        Some(remote),
+        None,
+        None,
+        None,
+    )
+    .await;
+    if let Err(e) = result {
+        error!("Tray: failed to start server: {}", e);
+    }
+}
+
+/// restart_server for the tray menu, keeping the server's network exposure.
+async fn restart_server_from_tray(app: &tauri::AppHandle) {
+    let result =
+        restart_server(app.clone(), app.state::<ServerState>(), app.state::<SettingsState>(), None, None).await;
+    if let Err(e) = result {
+        error!("Tray: failed to restart server: {}", e);
+    }
+}
+
+async fn stop_server_from_tray(app: &tauri::AppHandle) {
+    if let Err(e) = stop_server(app.clone(), app.state::<ServerState>()).await {
+        warn!("Tray: failed to stop server: {}", e);
+    }
+}
+
+/// Keep server-endpoint.json in step with the server: written while one is
+/// running, removed once it is gone.
+fn update_endpoint_file(app: &tauri::AppHandle, info: Option<&ServerInfo>) {
+    let Ok(app_data_dir) = paths::app_data_dir(app) else {
+        return;
+    };
+    match info {
+        Some(info) => {
+            if let Err(e) = process_manager::write_endpoint_file(&app_data_dir, info) {
+                warn!("{}", e);
             }
         }
+        None => process_manager::remove_endpoint_file(&app_data_dir),
     }
+}
 
-    // Kill any orphaned voicebox-server from previous session on legacy port 8000
-    // This handles upgrades from older versions that used a fixed port
-    #[cfg(unix)]
-    {
-        use std::process::Command;
-        // Find processes listening on legacy port 8000 with their command names
-        if let Ok(output) = Command::new("lsof")
-            .args(["-i", &format!(":{}", LEGACY_PORT), "-sTCP:LISTEN"])
-            .output()
-        {
-            let output_str = String::from_utf8_lossy(&output.stdout);
-            for line in output_str.lines().skip(1) { // Skip header line
-                // lsof output format: COMMAND PID USER FD TYPE DEVICE SIZE/OFF NODE NAME
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    let command = parts[0];
-                    let pid_str = parts[1];
-                    
-                    // Only kill if it's a voicebox-server process
-                    if command.contains("voicebox") {
-                        if let Ok(pid) = pid_str.parse::<i32>() {
-                            println!("Found orphaned voicebox-server on legacy port {} (PID: {}, CMD: {}), killing it...", LEGACY_PORT, pid, command);
-                            // Kill the process group
-                            let _ = Command::new("kill")
-                                .args(["-9", "--", &format!("-{}", pid)])
-                                .output();
-                            let _ = Command::new("kill")
-                                .args(["-9", &pid.to_string()])
-                                .output();
-                        }
-                    } else {
-                        println!("Legacy port {} is in use by non-voicebox process: {} (PID: {}), not killing", LEGACY_PORT, command, pid_str);
-                    }
+/// The bearer token other machines must send to the server, while a
+/// remote-mode server we started or adopted, or an external one, is in use.
+#[command]
+fn get_server_auth_token(state: State<'_, ServerState>) -> Option<String> {
+    state.auth_token.lock().unwrap().clone()
+}
+
+#[command]
+fn get_endpoint_file_path(app: tauri::AppHandle) -> Result<String, VoiceboxError> {
+    let app_data_dir = paths::app_data_dir(&app)?;
+    Ok(process_manager::endpoint_file_path(&app_data_dir).display().to_string())
+}
+
+/// Point the app at a server running elsewhere, e.g. on a GPU machine on the
+/// LAN, instead of the bundled sidecar. A managed server we were running is
+/// stopped first; the external one is never stopped or killed. Unreachable
+/// hosts and servers that aren't Voicebox fail with their own messages.
+#[command]
+async fn connect_to_server(
+    app: tauri::AppHandle,
+    state: State<'_, ServerState>,
+    url: String,
+    auth_token: Option<String>,
+) -> Result<ServerInfo, VoiceboxError> {
+    let (url, port) = process_manager::parse_external_url(&url).map_err(VoiceboxError::invalid_argument)?;
+    let auth_token = auth_token.filter(|t| !t.trim().is_empty());
+
+    let version = process_manager::identify_server(&url, auth_token.as_deref())
+        .await
+        .map_err(|failure| VoiceboxError::server(failure.message(&url)))?;
+    match version.as_deref().map(process_manager::check_server_compatibility) {
+        Some(VersionCompatibility::Compatible) => {}
+        Some(VersionCompatibility::TooOld) => {
+            return Err(VoiceboxError::server(format!(
+                "Server at {} is version {}, older than the minimum {}",
+                url,
+                version.as_deref().unwrap_or("unknown"),
+                process_manager::MIN_SERVER_VERSION
+            )));
+        }
+        Some(VersionCompatibility::TooNew) => {
+            return Err(VoiceboxError::server(format!(
+                "Server at {} is version {}, newer than this app supports",
+                url,
+                version.as_deref().unwrap_or("unknown")
+            )));
+        }
+        Some(VersionCompatibility::Unknown) | None => {
+            return Err(VoiceboxError::server(format!(
+                "Could not determine the version of the server at {}",
+                url
+            )));
+        }
+    }
+
+    let _guard = state.start_lock.lock().await;
+    if *state.managed.lock().unwrap() && state.server_pid.lock().unwrap().is_some() {
+        info!("connect_to_server: Stopping the bundled server");
+        stop_server(app.clone(), state.clone()).await?;
+    }
+
+    let info = ServerInfo {
+        url: url.clone(),
+        remote: false,
+        remote_urls: Vec::new(),
+        port,
+        pid: None,
+        adopted_existing: false,
+        started_at: process_manager::unix_timestamp(),
+        startup_ms: None,
+        auth_token_present: auth_token.is_some(),
+        auth_token: None,
+        version,
+        offline: None,
+        firewall_rule: None,
+        managed: false,
+        launch_options: None,
+    };
+    *state.managed.lock().unwrap() = false;
+    *state.auth_token.lock().unwrap() = auth_token;
+    *state.info.lock().unwrap() = Some(info.clone());
+    update_endpoint_file(&app, Some(&info));
+    info!("Using external server at {}", url);
+
+    spawn_external_health_monitor(app, url);
+    Ok(info)
+}
+
+/// Older name of connect_to_server.
+#[command]
+async fn use_external_server(
+    app: tauri::AppHandle,
+    state: State<'_, ServerState>,
+    url: String,
+    auth_token: Option<String>,
+) -> Result<ServerInfo, VoiceboxError> {
+    connect_to_server(app, state, url, auth_token).await
+}
+
+/// Forget the external server so the next start_server spawns the sidecar again.
+#[command]
+async fn use_bundled_server(app: tauri::AppHandle, state: State<'_, ServerState>) -> Result<(), VoiceboxError> {
+    let _guard = state.start_lock.lock().await;
+    if *state.managed.lock().unwrap() {
+        return Ok(());
+    }
+    *state.managed.lock().unwrap() = true;
+    *state.auth_token.lock().unwrap() = None;
+    let _ = state.info.lock().unwrap().take();
+    update_endpoint_file(&app, None);
+    info!("Switched back to the bundled server");
+    Ok(())
+}
+
+/// Poll an external server and emit `server-health-changed` when it stops or
+/// starts answering. Ends once the app switches to another server.
+fn spawn_external_health_monitor(app: tauri::AppHandle, url: String) {
+    tokio::spawn(async move {
+        let mut last_healthy = true;
+        loop {
+            tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+
+            let state = app.state::<ServerState>();
+            let current = state.info.lock().unwrap().as_ref().map(|info| (info.url.clone(), info.managed));
+            if *state.managed.lock().unwrap() || current != Some((url.clone(), false)) {
+                break;
+            }
+            if state.asleep_since.lock().unwrap().is_some() {
+                continue;
+            }
+
+            let auth_token = state.auth_token.lock().unwrap().clone();
+            let healthy = process_manager::check_health_at(&url, auth_token.as_deref()).await;
+            if healthy != last_healthy {
+                last_healthy = healthy;
+                info!("External server at {} is {}", url, if healthy { "reachable again" } else { "unreachable" });
+                let payload = serde_json::json!({
+                    "healthy": healthy,
+                    "url": url,
+                    "managed": false,
+                });
+                if let Err(e) = event_bus::emit(&app, "server-health-changed", payload) {
+                    warn!("Failed to emit server-health-changed event: {}", e);
                 }
             }
         }
+    });
+}
+
+/// Adopt a running server or spawn the sidecar. Called with the start lock held.
+#[tracing::instrument(name = "server_start", skip_all, fields(id = logging::next_span_id(), remote = is_remote))]
+async fn launch_server(
+    app: tauri::AppHandle,
+    state: State<'_, ServerState>,
+    settings: State<'_, SettingsState>,
+    is_remote: bool,
+    offline: Option<bool>,
+    options: ServerLaunchOptions,
+    startup_timeout: std::time::Duration,
+) -> Result<ServerInfo, ServerStartError> {
+    // Only a server spawned below, or one whose record says, has known options
+    // or a token
+    *state.launch_options.lock().unwrap() = None;
+    *state.auth_token.lock().unwrap() = None;
+
+    // Get data directory
+    let data_dir = resolve_data_dir(&app)?;
+
+    // A kept-running server may have shut itself down after its idle timeout,
+    // leaving server.json behind. Drop the record if its process is gone,
+    // otherwise look for the server on the port it recorded.
+    let port = process_manager::clean_stale_server_record(&data_dir).map_or(SERVER_PORT, |record| record.port);
+
+    // Check if a voicebox server is already running on that port (from previous session with keep_running=true)
+    let mut existing_server_pid: Option<u32> = None;
+    // Something else listening on the port, which would make the sidecar fail to bind
+    let mut port_occupant: Option<String> = None;
+    for listener in process_utils::listeners_on(port) {
+        if listener.is_voicebox_server() {
+            info!("Found existing voicebox-server on port {} (PID: {})", port, listener.pid);
+            existing_server_pid = Some(listener.pid);
+            break;
+        }
+        port_occupant = Some(listener.to_string());
     }
-    
-    #[cfg(windows)]
-    {
-        use std::process::Command;
-        // On Windows, find PIDs on legacy port 8000, then check their names
-        if let Ok(output) = Command::new("netstat")
-            .args(["-ano"])
-            .output()
-        {
-            let output_str = String::from_utf8_lossy(&output.stdout);
-            for line in output_str.lines() {
-                if line.contains(&format!(":{}", LEGACY_PORT)) && line.contains("LISTENING") {
-                    if let Some(pid_str) = line.split_whitespace().last() {
-                        if let Ok(pid) = pid_str.parse::<u32>() {
-                            // Get process name for this PID
-                            if let Ok(tasklist_output) = Command::new("tasklist")
-                                .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
-                                .output()
-                            {
-                                let tasklist_str = String::from_utf8_lossy(&tasklist_output.stdout);
-                                if tasklist_str.to_lowercase().contains("voicebox") {
-                                    println!("Found orphaned voicebox-server on legacy port {} (PID: {}), killing it...", LEGACY_PORT, pid);
-                                    let _ = Command::new("taskkill")
-                                        .args(["/PID", &pid.to_string(), "/T", "/F"])
-                                        .output();
-                                } else {
-                                    println!("Legacy port {} is in use by non-voicebox process (PID: {}), not killing", LEGACY_PORT, pid);
-                                }
-                            }
-                        }
-                    }
-                }
+
+    // Reuse the existing server unless it is too old for this app (e.g. kept running
+    // across an auto-update), in which case replace it with the bundled one, or
+    // isn't exposed the way this start asks for
+    let mut replaced_server_version: Option<String> = None;
+    if let Some(pid) = existing_server_pid {
+        let version = process_manager::fetch_server_version(port).await;
+        let compatibility = version
+            .as_deref()
+            .map(process_manager::check_server_compatibility)
+            .unwrap_or(VersionCompatibility::Unknown);
+        let record = process_manager::read_server_record(&data_dir).filter(|record| record.pid == pid);
+        // Only a remote-mode server needs the token, so its record says how it
+        // was started; one without a record is taken to be local
+        let remote = record.as_ref().is_some_and(|record| record.requires_auth);
+
+        if compatibility == VersionCompatibility::TooOld {
+            info!(
+                "Existing voicebox-server (PID: {}) is version {}, older than the minimum {}; replacing it",
+                pid,
+                version.as_deref().unwrap_or("unknown"),
+                process_manager::MIN_SERVER_VERSION
+            );
+            process_manager::force_kill(pid);
+            process_manager::remove_server_record(&data_dir);
+            replaced_server_version = version;
+        } else if remote != is_remote {
+            let mode = |remote: bool| if remote { "remote" } else { "local" };
+            info!(
+                "Existing voicebox-server (PID: {}) runs in {} mode, not {}; replacing it",
+                pid,
+                mode(remote),
+                mode(is_remote)
+            );
+            process_manager::force_kill(pid);
+            process_manager::remove_server_record(&data_dir);
+        } else {
+            if compatibility == VersionCompatibility::TooNew {
+                warn!(
+                    "Warning: existing voicebox-server version {} is newer than this app supports",
+                    version.as_deref().unwrap_or("unknown")
+                );
             }
+            info!("Reusing existing voicebox-server (PID: {})", pid);
+            // Store the PID so we can kill it on exit if needed
+            *state.server_pid.lock().unwrap() = Some(pid);
+            *state.port.lock().unwrap() = port;
+            let started_at = record.as_ref().map(|record| record.started_at);
+            if remote {
+                *state.auth_token.lock().unwrap() = server_auth::read_token(&data_dir);
+            }
+            *state.launch_options.lock().unwrap() = record.and_then(|record| record.launch_options);
+            let firewall_rule = if is_remote {
+                Some(ensure_firewall_rule_on(port).await)
+            } else {
+                None
+            };
+            return Ok(publish_server_info(&state, Some(pid), true, started_at, is_remote, None, firewall_rule).await);
+        }
+    }
+
+    // Kill any orphaned voicebox-server from previous session on legacy port 8000
+    // This handles upgrades from older versions that used a fixed port
+    for listener in process_utils::listeners_on(LEGACY_PORT) {
+        if listener.is_voicebox_server() {
+            info!("Found orphaned voicebox-server on legacy port {} ({}), killing it...", LEGACY_PORT, listener);
+            process_utils::kill(listener.pid);
+        } else {
+            info!("Legacy port {} is in use by non-voicebox process: {}, not killing", LEGACY_PORT, listener);
         }
     }
-    
-    // Brief wait for port to be released
-    std::thread::sleep(std::time::Duration::from_millis(200));
 
-    // Get app data directory
-    let data_dir = app
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data dir: {}", e))?;
+    // Brief wait for the port of a server killed above to be released
+    tokio::time::sleep(process_manager::KILL_PORT_RELEASE_WAIT).await;
+
+    // Anything else on the port would make the sidecar fail to bind, so move
+    // to a free one. In dev builds the occupant is usually the manually
+    // started Python server, which the sidecar fallback below adopts
+    #[cfg(not(debug_assertions))]
+    let port = {
+        let host = if is_remote {
+            std::net::Ipv4Addr::UNSPECIFIED
+        } else {
+            std::net::Ipv4Addr::LOCALHOST
+        };
+        if port_occupant.is_some() || !process_manager::is_port_bindable(host.into(), port) {
+            let occupant = port_occupant.unwrap_or_else(|| format!("another program on port {}", port));
+            let Some(free_port) = process_manager::pick_port(host.into(), port) else {
+                return Err(ServerStartError::PortConflict { occupant });
+            };
+            info!("Port {} is taken by {}, using port {} instead", port, occupant, free_port);
+            free_port
+        } else {
+            port
+        }
+    };
+    #[cfg(debug_assertions)]
+    let _ = port_occupant;
+    *state.port.lock().unwrap() = port;
+
+    // On Windows the firewall prompt for 0.0.0.0 is easy to miss, after which
+    // remote clients silently time out, so set up the rule before binding
+    let firewall_rule = if is_remote {
+        Some(ensure_firewall_rule_on(port).await)
+    } else {
+        None
+    };
+
+    // Creates the data dir, and refuses to start on one that can't be written or is full
+    if let Some(problem) = refresh_storage(&app).problem {
+        return Err(ServerStartError::Storage { problem });
+    }
 
-    // Ensure data directory exists
-    std::fs::create_dir_all(&data_dir)
-        .map_err(|e| format!("Failed to create data dir: {}", e))?;
+    info!("Starting voicebox-server sidecar");
+    info!("Data directory: {:?}", data_dir);
+    info!("Remote mode: {}", is_remote);
 
-    println!("=================================================================");
-    println!("Starting voicebox-server sidecar");
-    println!("Data directory: {:?}", data_dir);
-    println!("Remote mode: {}", remote.unwrap_or(false));
+    // Without a network the server would stall on Hugging Face update checks
+    // before falling back to the local cache, so tell it up front
+    let offline = match offline {
+        Some(offline) => offline,
+        None => !process_manager::detect_network_available().await,
+    };
+    info!("Offline mode: {}", offline);
 
     let sidecar_result = app.shell().sidecar("voicebox-server");
 
     let mut sidecar = match sidecar_result {
         Ok(s) => s,
         Err(e) => {
-            eprintln!("Failed to get sidecar: {}", e);
+            error!("Failed to get sidecar: {}", e);
 
             // In dev mode, check if the server is already running (started manually)
             #[cfg(debug_assertions)]
             {
-                eprintln!("Dev mode: Checking if server is already running on port {}...", SERVER_PORT);
+                warn!("Dev mode: Checking if server is already running on port {}...", port);
 
                 // Try to connect to the server port
                 use std::net::TcpStream;
                 if TcpStream::connect_timeout(
-                    &format!("127.0.0.1:{}", SERVER_PORT).parse().unwrap(),
+                    &format!("127.0.0.1:{}", port).parse().unwrap(),
                     std::time::Duration::from_secs(1),
                 ).is_ok() {
-                    println!("Found server already running on port {}", SERVER_PORT);
-                    return Ok(format!("http://127.0.0.1:{}", SERVER_PORT));
+                    info!("Found server already running on port {}", port);
+                    return Ok(publish_server_info(&state, None, true, None, is_remote, None, firewall_rule.clone()).await);
                 }
 
-                eprintln!("");
-                eprintln!("=================================================================");
-                eprintln!("DEV MODE: No server found on port {}", SERVER_PORT);
-                eprintln!("");
-                eprintln!("Start the Python server in a separate terminal:");
-                eprintln!("  bun run dev:server");
-                eprintln!("=================================================================");
-                eprintln!("");
+                return Err(ServerStartError::BinaryProblem {
+                    kind: BinaryProblemKind::DevServerNotRunning,
+                });
             }
 
-            return Err(format!("Failed to start server. In dev mode, run 'bun run dev:server' in a separate terminal."));
+            #[cfg(not(debug_assertions))]
+            return Err(ServerStartError::BinaryProblem {
+                kind: BinaryProblemKind::NotFound,
+            });
         }
     };
 
-    println!("Sidecar command created successfully");
+    debug!("Sidecar command created successfully");
 
     // Pass data directory and port to Python server
     sidecar = sidecar.args([
@@ -223,400 +641,3876 @@ async fn start_server(
             .to_str()
             .ok_or_else(|| "Invalid data dir path".to_string())?,
         "--port",
-        &SERVER_PORT.to_string(),
+        &port.to_string(),
     ]);
 
-    if remote.unwrap_or(false) {
+    // Never on the LAN without a token
+    let auth_token = if is_remote {
         sidecar = sidecar.args(["--host", "0.0.0.0"]);
+        Some(server_auth::read_or_create_token(&data_dir)?)
+    } else {
+        None
+    };
+
+    // Caller's variables first, so the app's own offline switch wins
+    let envs = options.envs();
+    if !envs.is_empty() {
+        let names: Vec<&str> = envs.iter().map(|(name, _)| name.as_str()).collect();
+        info!("Extra environment: {}", names.join(", "));
+        sidecar = sidecar.envs(envs);
+    }
+
+    if offline {
+        sidecar = sidecar.arg("--offline").env("HF_HUB_OFFLINE", "1");
+    }
+    if let Some(token) = &auth_token {
+        sidecar = sidecar.env(server_auth::AUTH_TOKEN_ENV, token);
+    }
+
+    let server_settings = settings.get();
+    let log_level = options.parsed_log_level()?.unwrap_or(server_settings.server_log_level);
+    info!("Log level: {}", log_level.as_str());
+    sidecar = sidecar.args(["--log-level", log_level.as_str()]);
+    if !server_settings.server_access_logs {
+        sidecar = sidecar.arg("--no-access-log");
     }
+    if !options.extra_args.is_empty() {
+        info!("Extra arguments: {}", options.extra_args.join(" "));
+        sidecar = sidecar.args(&options.extra_args);
+    }
+
+    // The server outlives the window with keep_running, so it has to enforce the idle policy itself
+    let idle_timeout = state.spawn_idle_timeout();
+    if let Some(minutes) = idle_timeout {
+        info!("Idle timeout: {} minutes", minutes);
+        sidecar = sidecar.args(["--idle-timeout-minutes", &minutes.to_string()]);
+    }
+
+    // Raw chunks rather than whole lines: a multi-megabyte traceback line
+    // is cut by server_output instead of being buffered first
+    sidecar = sidecar.set_raw_out(true);
 
-    println!("Spawning server process...");
+    info!("Spawning server process...");
     let spawn_result = sidecar.spawn();
 
     let (mut rx, child) = match spawn_result {
         Ok(result) => result,
         Err(e) => {
-            eprintln!("Failed to spawn server process: {}", e);
+            error!("Failed to spawn server process: {}", e);
 
             // In dev mode, check if a manually-started server is available
             #[cfg(debug_assertions)]
             {
                 use std::net::TcpStream;
                 if TcpStream::connect_timeout(
-                    &format!("127.0.0.1:{}", SERVER_PORT).parse().unwrap(),
+                    &format!("127.0.0.1:{}", port).parse().unwrap(),
                     std::time::Duration::from_secs(1),
                 ).is_ok() {
-                    println!("Found manually-started server on port {}", SERVER_PORT);
-                    return Ok(format!("http://127.0.0.1:{}", SERVER_PORT));
+                    info!("Found manually-started server on port {}", port);
+                    return Ok(publish_server_info(&state, None, true, None, is_remote, None, firewall_rule.clone()).await);
                 }
 
-                eprintln!("");
-                eprintln!("=================================================================");
-                eprintln!("DEV MODE: Server binary failed to start");
-                eprintln!("");
-                eprintln!("Start the Python server in a separate terminal:");
-                eprintln!("  bun run dev:server");
-                eprintln!("=================================================================");
-                eprintln!("");
-                return Err("Dev mode: Start server manually with 'bun run dev:server'".to_string());
+                return Err(ServerStartError::BinaryProblem {
+                    kind: BinaryProblemKind::DevServerNotRunning,
+                });
             }
 
             #[cfg(not(debug_assertions))]
-            {
-                eprintln!("This could be due to:");
-                eprintln!("  - Missing or corrupted binary");
-                eprintln!("  - Missing execute permissions");
-                eprintln!("  - Code signing issues on macOS");
-                eprintln!("  - Missing dependencies");
-                return Err(format!("Failed to spawn: {}", e));
-            }
+            return Err(match &e {
+                tauri_plugin_shell::Error::Io(io) if io.kind() == std::io::ErrorKind::NotFound => {
+                    ServerStartError::BinaryProblem {
+                        kind: BinaryProblemKind::NotFound,
+                    }
+                }
+                tauri_plugin_shell::Error::Io(io) if io.kind() == std::io::ErrorKind::PermissionDenied => {
+                    ServerStartError::BinaryProblem {
+                        kind: BinaryProblemKind::NotExecutable,
+                    }
+                }
+                _ => ServerStartError::SpawnFailed {
+                    os_error: e.to_string(),
+                },
+            });
         }
     };
 
-    println!("Server process spawned, waiting for ready signal...");
-    println!("=================================================================");
+    info!("Server process spawned, waiting for ready signal...");
 
     // Store child process and PID
     let process_pid = child.pid();
     *state.server_pid.lock().unwrap() = Some(process_pid);
     *state.child.lock().unwrap() = Some(child);
 
-    // Wait for server to be ready by listening for startup log
-    // PyInstaller bundles can be slow on first import, especially torch/transformers
-    let timeout = tokio::time::Duration::from_secs(120);
+    let started_at = process_manager::unix_timestamp();
+    let record = ServerRecord {
+        pid: process_pid,
+        port,
+        started_at,
+        idle_shutdown_after_minutes: idle_timeout,
+        launch_options: Some(options.clone()),
+        requires_auth: auth_token.is_some(),
+    };
+    if let Err(e) = process_manager::write_server_record(&data_dir, &record) {
+        warn!("{}", e);
+    }
+
+    if let Some(priority) = server_settings.process_priority {
+        // Not being allowed to change priority is no reason to fail the start
+        match process_manager::set_process_priority(process_pid, priority) {
+            Ok(()) => info!("Server priority set to {}", priority.as_str()),
+            Err(e) => warn!("Warning: {}", e),
+        }
+    }
+
+    // Wait for server to be ready by polling its health endpoint; its startup
+    // log only hints when to look. PyInstaller bundles can be slow on first
+    // import, especially torch/transformers, hence startup_timeout and the
+    // progress events meanwhile
     let start_time = tokio::time::Instant::now();
-    let mut error_output = Vec::new();
+    let mut next_probe = start_time;
+    let log = state.log.clone();
+    let first_line = log.next_seq();
+    let mut stdout_lines = server_output::LineSplitter::default();
+    let mut stderr_lines = server_output::LineSplitter::default();
+    let mut phase = StartupPhase::Launching;
+    let mut exit_code: Option<i32> = None;
+    let progress = |phase: StartupPhase, line: Option<String>| {
+        let progress = StartupProgress {
+            phase,
+            line,
+            elapsed_secs: start_time.elapsed().as_secs(),
+        };
+        let _ = event_bus::emit(&app, "server-startup-progress", &progress);
+    };
+    progress(phase, None);
+    let mut last_progress = start_time;
 
     loop {
-        if start_time.elapsed() > timeout {
-            eprintln!("Server startup timeout after 120 seconds");
-            if !error_output.is_empty() {
-                eprintln!("Collected error output:");
-                for line in &error_output {
-                    eprintln!("  {}", line);
-                }
-            }
+        if start_time.elapsed() > startup_timeout {
+            error!("Server startup timeout after {} seconds", startup_timeout.as_secs());
 
             // In dev mode, check if a manual server came up during the wait
             #[cfg(debug_assertions)]
             {
                 use std::net::TcpStream;
                 if TcpStream::connect_timeout(
-                    &format!("127.0.0.1:{}", SERVER_PORT).parse().unwrap(),
+                    &format!("127.0.0.1:{}", port).parse().unwrap(),
                     std::time::Duration::from_secs(1),
                 ).is_ok() {
                     // Kill the placeholder process
                     let _ = state.child.lock().unwrap().take();
-                    println!("Found manually-started server on port {}", SERVER_PORT);
-                    return Ok(format!("http://127.0.0.1:{}", SERVER_PORT));
+                    info!("Found manually-started server on port {}", port);
+                    return Ok(publish_server_info(&state, None, true, None, is_remote, None, firewall_rule.clone()).await);
                 }
             }
 
-            return Err("Server startup timeout - check Console.app for detailed logs".to_string());
+            return Err(ServerStartError::Timeout {
+                last_phase: phase,
+                tail_lines: log.tail_since(first_line, TAIL_LINES),
+            });
         }
 
         match tokio::time::timeout(tokio::time::Duration::from_millis(100), rx.recv()).await {
             Ok(Some(event)) => {
-                match event {
-                    tauri_plugin_shell::process::CommandEvent::Stdout(line) => {
-                        let line_str = String::from_utf8_lossy(&line);
-                        println!("Server output: {}", line_str);
-
-                        if line_str.contains("Uvicorn running") || line_str.contains("Application startup complete") {
-                            println!("Server is ready!");
-                            break;
-                        }
+                let (splitter, stream, chunk) = match event {
+                    tauri_plugin_shell::process::CommandEvent::Stdout(chunk) => {
+                        (&mut stdout_lines, LogStream::Stdout, chunk)
                     }
-                    tauri_plugin_shell::process::CommandEvent::Stderr(line) => {
-                        let line_str = String::from_utf8_lossy(&line).to_string();
-                        eprintln!("Server: {}", line_str);
-
-                        // Collect error lines for debugging
-                        if line_str.contains("ERROR") || line_str.contains("Error") || line_str.contains("Failed") {
-                            error_output.push(line_str.clone());
-                        }
-
-                        // Uvicorn logs to stderr, so check there too
-                        if line_str.contains("Uvicorn running") || line_str.contains("Application startup complete") {
-                            println!("Server is ready!");
-                            break;
-                        }
+                    // Uvicorn logs to stderr, so check there too
+                    tauri_plugin_shell::process::CommandEvent::Stderr(chunk) => {
+                        (&mut stderr_lines, LogStream::Stderr, chunk)
+                    }
+                    tauri_plugin_shell::process::CommandEvent::Terminated(payload) => {
+                        // The channel closes right after; Ok(None) below reports it
+                        exit_code = payload.code;
+                        continue;
                     }
-                    _ => {}
+                    _ => continue,
+                };
+                let mut announced = false;
+                for line in server_output::take(splitter, &chunk, stream, &log) {
+                    phase = StartupPhase::from_log_line(&line.text).unwrap_or(phase);
+                    announced |= line.text.contains("Uvicorn running")
+                        || line.text.contains("Application startup complete");
+                    if !line.text.trim().is_empty() {
+                        progress(phase, Some(line.to_string()));
+                        last_progress = tokio::time::Instant::now();
+                    }
+                }
+                if announced {
+                    info!("Server says it's up, checking its health endpoint");
+                    next_probe = tokio::time::Instant::now();
                 }
             }
             Ok(None) => {
+                server_output::finish(&mut stdout_lines, LogStream::Stdout, &log);
+                server_output::finish(&mut stderr_lines, LogStream::Stderr, &log);
+
                 // In dev mode, this is expected when using the placeholder binary
                 #[cfg(debug_assertions)]
                 {
                     use std::net::TcpStream;
-                    eprintln!("Server process ended (dev mode placeholder detected)");
+                    warn!("Server process ended (dev mode placeholder detected)");
 
                     // Check if a manually-started server is available
                     if TcpStream::connect_timeout(
-                        &format!("127.0.0.1:{}", SERVER_PORT).parse().unwrap(),
+                        &format!("127.0.0.1:{}", port).parse().unwrap(),
                         std::time::Duration::from_secs(1),
                     ).is_ok() {
                         // Clean up state
                         let _ = state.child.lock().unwrap().take();
                         let _ = state.server_pid.lock().unwrap().take();
-                        println!("Found manually-started server on port {}", SERVER_PORT);
-                        return Ok(format!("http://127.0.0.1:{}", SERVER_PORT));
+                        info!("Found manually-started server on port {}", port);
+                        return Ok(publish_server_info(&state, None, true, None, is_remote, None, firewall_rule.clone()).await);
                     }
 
-                    eprintln!("");
-                    eprintln!("=================================================================");
-                    eprintln!("DEV MODE: No bundled server binary available");
-                    eprintln!("");
-                    eprintln!("Start the Python server in a separate terminal:");
-                    eprintln!("  bun run dev:server");
-                    eprintln!("=================================================================");
-                    eprintln!("");
-                    return Err("Dev mode: Start server manually with 'bun run dev:server'".to_string());
+                    let _ = exit_code;
+                    return Err(ServerStartError::BinaryProblem {
+                        kind: BinaryProblemKind::DevServerNotRunning,
+                    });
                 }
 
                 #[cfg(not(debug_assertions))]
-                {
-                    eprintln!("Server process ended unexpectedly during startup!");
-                    eprintln!("The server binary may have crashed or exited with an error.");
-                    eprintln!("Check Console.app logs for more details (search for 'voicebox')");
-                    return Err("Server process ended unexpectedly".to_string());
-                }
+                return Err(ServerStartError::CrashedDuringStartup {
+                    exit_code,
+                    tail_lines: log.tail_since(first_line, TAIL_LINES),
+                });
             }
             Err(_) => {
-                // Timeout on this recv, continue loop
-                continue;
+                // Timeout on this recv, probe if due
+                if last_progress.elapsed() >= STARTUP_PROGRESS_INTERVAL {
+                    progress(phase, None);
+                    last_progress = tokio::time::Instant::now();
+                }
+            }
+        }
+
+        if tokio::time::Instant::now() >= next_probe {
+            if process_manager::is_ready(port).await {
+                info!("Server is ready!");
+                break;
             }
+            next_probe = tokio::time::Instant::now() + process_manager::READY_POLL_INTERVAL;
         }
     }
 
+    let startup_ms = start_time.elapsed().as_millis() as u64;
+    info!("Server took {} ms to start", startup_ms);
+    metrics::METRICS.server_starts.inc();
+    metrics::METRICS.server_startup_ms.record(startup_ms);
+
     // Spawn task to continue reading output
+    let exit_app = app.clone();
     tokio::spawn(async move {
         while let Some(event) = rx.recv().await {
             match event {
-                tauri_plugin_shell::process::CommandEvent::Stdout(line) => {
-                    println!("Server: {}", String::from_utf8_lossy(&line));
+                tauri_plugin_shell::process::CommandEvent::Stdout(chunk) => {
+                    server_output::take(&mut stdout_lines, &chunk, LogStream::Stdout, &log);
                 }
-                tauri_plugin_shell::process::CommandEvent::Stderr(line) => {
-                    eprintln!("Server error: {}", String::from_utf8_lossy(&line));
+                tauri_plugin_shell::process::CommandEvent::Stderr(chunk) => {
+                    server_output::take(&mut stderr_lines, &chunk, LogStream::Stderr, &log);
+                }
+                tauri_plugin_shell::process::CommandEvent::Terminated(payload) => {
+                    server_output::finish(&mut stdout_lines, LogStream::Stdout, &log);
+                    server_output::finish(&mut stderr_lines, LogStream::Stderr, &log);
+                    let tail_lines = log.tail_since(first_line, crash::CRASH_TAIL_LINES);
+                    handle_server_exit(&exit_app, process_pid, payload.code, payload.signal, tail_lines).await;
                 }
                 _ => {}
             }
         }
     });
 
-    Ok(format!("http://127.0.0.1:{}", SERVER_PORT))
+    *state.launch_options.lock().unwrap() = Some(options);
+    *state.auth_token.lock().unwrap() = auth_token;
+    let info = publish_server_info(&state, Some(process_pid), false, Some(started_at), is_remote, Some(offline), firewall_rule).await;
+    let info = ServerInfo {
+        startup_ms: Some(startup_ms),
+        ..info
+    };
+    *state.info.lock().unwrap() = Some(info.clone());
+    spawn_network_monitor(app.clone(), process_pid, offline);
+
+    if let Some(version) = &info.version {
+        if process_manager::check_server_compatibility(version) != VersionCompatibility::Compatible {
+            warn!(
+                "Warning: bundled voicebox-server version {} is outside the supported range for app {}",
+                version,
+                env!("CARGO_PKG_VERSION")
+            );
+        }
+    }
+
+    if let Some(old_version) = replaced_server_version {
+        let payload = serde_json::json!({
+            "from": old_version,
+            "to": info.version,
+        });
+        if let Err(e) = event_bus::emit(&app, "server-upgraded", payload) {
+            warn!("Failed to emit server-upgraded event: {}", e);
+        }
+    }
+
+    Ok(info)
 }
 
-/// Check if a Windows process is still running
-#[cfg(windows)]
-fn is_process_running(pid: u32) -> bool {
-    use std::process::Command;
-    if let Ok(output) = Command::new("tasklist")
-        .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
-        .output()
+/// Called when the sidecar we spawned exits after becoming ready. Exits we
+/// asked for are ignored; anything else is classified and reported as
+/// `server-crashed`, and the server restarted if auto-restart allows.
+async fn handle_server_exit(
+    app: &tauri::AppHandle,
+    pid: u32,
+    code: Option<i32>,
+    signal: Option<i32>,
+    tail_lines: Vec<String>,
+) {
+    let state = app.state::<ServerState>();
     {
-        // If process exists, tasklist returns it in output
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        return !output_str.trim().is_empty() && output_str.contains(&pid.to_string());
-    }
-    false
-}
-
-/// Kill entire Windows process tree by enumerating children
-#[cfg(windows)]
-fn kill_windows_process_tree(parent_pid: u32) -> Result<(), String> {
-    use std::process::Command;
-
-    // Find all child processes using WMIC
-    let output = Command::new("wmic")
-        .args([
-            "process",
-            "where",
-            &format!("ParentProcessId={}", parent_pid),
-            "get",
-            "ProcessId"
-        ])
-        .output();
+        // stop_server and the exit path take the PID before killing the
+        // process, so if it is still ours nobody asked it to stop
+        let mut current = state.server_pid.lock().unwrap();
+        if *current != Some(pid) {
+            info!("Server (PID {}) exited", pid);
+            return;
+        }
+        *current = None;
+    }
+    let _ = state.child.lock().unwrap().take();
+    let crashed = state.info.lock().unwrap().take();
+    *state.recovering.lock().unwrap() = true;
+    metrics::METRICS.server_crashes.inc();
+    if let Ok(data_dir) = resolve_data_dir(app) {
+        process_manager::remove_server_record(&data_dir);
+    }
+    update_endpoint_file(app, None);
 
-    if let Ok(output) = output {
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        for line in output_str.lines().skip(1) { // Skip header
-            if let Ok(child_pid) = line.trim().parse::<u32>() {
-                println!("Found child process: {}", child_pid);
-                // Recursively kill child's children
-                let _ = kill_windows_process_tree(child_pid);
-                // Kill the child
-                let _ = Command::new("taskkill")
-                    .args(["/PID", &child_pid.to_string(), "/F"])
-                    .output();
-            }
+    let (reason, memory) = tokio::task::spawn_blocking(move || crash::classify_exit(pid, code, signal))
+        .await
+        .unwrap_or((crash::CrashReason::Unknown, None));
+
+    let oom_crashes = {
+        let mut count = state.oom_crashes.lock().unwrap();
+        if reason == crash::CrashReason::OutOfMemory {
+            *count += 1;
         }
+        *count
+    };
+
+    let restart_allowed = crash::restart_allowed(&reason, oom_crashes);
+    let restart_in = if restart_allowed && *state.auto_restart.lock().unwrap() {
+        state.restart_budget.lock().unwrap().take(std::time::Instant::now())
+    } else {
+        None
+    };
+
+    let report = crash::ServerCrash {
+        pid,
+        restart_allowed,
+        auto_restart_in_ms: restart_in.map(|delay| delay.as_millis() as u64),
+        advice: reason.advice().map(|a| a.to_string()),
+        reason,
+        exit_code: code,
+        signal,
+        memory,
+        tail_lines,
+    };
+    error!("Server (PID {}) crashed: {:?}", pid, report.reason);
+    if let Some(advice) = &report.advice {
+        warn!("{}", advice);
     }
 
-    // Kill the parent process
-    let _ = Command::new("taskkill")
-        .args(["/PID", &parent_pid.to_string(), "/F"])
-        .output();
+    if let Err(e) = event_bus::emit(app, "server-crashed", report) {
+        error!("Failed to emit server-crashed event: {}", e);
+    }
 
-    Ok(())
+    match restart_in {
+        Some(delay) => {
+            let (remote, offline) = crashed.map_or((false, None), |info| (info.remote, info.offline));
+            tauri::async_runtime::spawn(restart_after_crash(app.clone(), delay, remote, offline));
+        }
+        None if restart_allowed && *state.auto_restart.lock().unwrap() => {
+            warn!(
+                "Server keeps crashing, not restarting it again ({} restarts at most per {:?})",
+                process_manager::MAX_AUTO_RESTARTS,
+                process_manager::AUTO_RESTART_WINDOW
+            );
+        }
+        None => {}
+    }
 }
 
-#[command]
-async fn stop_server(state: State<'_, ServerState>) -> Result<(), String> {
-    let pid = state.server_pid.lock().unwrap().take();
-    let _child = state.child.lock().unwrap().take();
-    
-    if let Some(pid) = pid {
-        println!("stop_server: Killing server process group with PID: {}", pid);
-        
-        #[cfg(unix)]
-        {
-            use std::process::Command;
-            // Kill process group with SIGTERM first
-            let _ = Command::new("kill")
-                .args(["-TERM", "--", &format!("-{}", pid)])
-                .output();
-            
-            // Brief wait then force kill
-            std::thread::sleep(std::time::Duration::from_millis(100));
-            
-            let _ = Command::new("kill")
-                .args(["-9", "--", &format!("-{}", pid)])
-                .output();
-            let _ = Command::new("kill")
-                .args(["-9", &pid.to_string()])
-                .output();
-        }
-        
-        #[cfg(windows)]
-        {
-            // Layer 1: Try graceful HTTP shutdown first
-            println!("Attempting graceful shutdown via HTTP...");
-            let client = reqwest::blocking::Client::builder()
-                .timeout(std::time::Duration::from_secs(2))
-                .build()
-                .unwrap();
-
-            let shutdown_result = client
-                .post(&format!("http://127.0.0.1:{}/shutdown", SERVER_PORT))
-                .send();
-
-            if shutdown_result.is_ok() {
-                println!("HTTP shutdown sent, waiting for graceful exit...");
-                // Wait up to 3 seconds for graceful shutdown
-                for i in 0..30 {
-                    std::thread::sleep(std::time::Duration::from_millis(100));
-                    if !is_process_running(pid) {
-                        println!("Process exited gracefully after {}ms", i * 100);
-                        return Ok(());
-                    }
-                }
-                println!("Graceful shutdown timed out, forcing kill...");
-            } else {
-                println!("HTTP shutdown failed, forcing kill...");
-            }
+/// Start the server again `delay` after a crash, with the exposure and
+/// launch options it had, and emit `server-ready` once it answers. Skipped if
+/// it was stopped or started by hand meanwhile: both clear `recovering`.
+/// Boxed because the server it starts comes back through handle_server_exit.
+fn restart_after_crash(
+    app: tauri::AppHandle,
+    delay: std::time::Duration,
+    remote: bool,
+    offline: Option<bool>,
+) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>> {
+    Box::pin(async move {
+        info!("Restarting the server in {:?}", delay);
+        tokio::time::sleep(delay).await;
+        let state = app.state::<ServerState>();
+        if !*state.recovering.lock().unwrap() || !*state.auto_restart.lock().unwrap() {
+            info!("Server was stopped or started meanwhile, not restarting it");
+            return;
+        }
+        let options = state.launch_options.lock().unwrap().clone();
+        let settings = app.state::<SettingsState>();
+        // start_server emits `server-ready` on success and has logged a failure
+        if let Err(e) = start_server(app.clone(), state, settings, Some(remote), offline, options, None).await {
+            let _ = event_bus::emit(&app, "server-restart-failed", &e);
+        }
+    })
+}
 
-            // Layer 2: Kill process tree with enumeration
-            println!("Killing process tree for wrapper PID {}...", pid);
-            kill_windows_process_tree(pid)?;
+/// Watch connectivity while the server we spawned runs and emit
+/// `network-status-changed` when it flips, so the UI can offer a restart in
+/// the other mode.
+fn spawn_network_monitor(app: tauri::AppHandle, pid: u32, server_offline: bool) {
+    tokio::spawn(async move {
+        let mut last_available = !server_offline;
+        loop {
+            tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
 
-            // Layer 3: Verify and kill by name if still running
-            std::thread::sleep(std::time::Duration::from_millis(200));
-            if is_process_running(pid) {
-                println!("Process tree kill failed, killing by name...");
-                use std::process::Command;
-                let _ = Command::new("taskkill")
-                    .args(["/IM", "voicebox-server.exe", "/T", "/F"])
-                    .output();
+            // Stop once this server is gone or was replaced
+            let state = app.state::<ServerState>();
+            if *state.server_pid.lock().unwrap() != Some(pid) {
+                break;
+            }
+            if state.asleep_since.lock().unwrap().is_some() {
+                continue;
             }
 
-            // Layer 4: Final verification
-            std::thread::sleep(std::time::Duration::from_millis(200));
-            if is_process_running(pid) {
-                eprintln!("WARNING: Failed to kill server after all attempts");
-            } else {
-                println!("Server killed successfully");
+            let available = process_manager::detect_network_available().await;
+            if available != last_available {
+                last_available = available;
+                info!("Network {}", if available { "available" } else { "unavailable" });
+                let payload = serde_json::json!({
+                    "available": available,
+                    "server_offline": server_offline,
+                });
+                if let Err(e) = event_bus::emit(&app, "network-status-changed", payload) {
+                    warn!("Failed to emit network-status-changed event: {}", e);
+                }
             }
         }
+    });
+}
 
-        #[cfg(unix)]
-        {
-            println!("stop_server: Process group kill completed");
-        }
+#[command]
+async fn ensure_firewall_rule(state: State<'_, ServerState>) -> Result<firewall::FirewallRuleStatus, VoiceboxError> {
+    Ok(ensure_firewall_rule_on(state.port()).await)
+}
+
+async fn ensure_firewall_rule_on(port: u16) -> firewall::FirewallRuleStatus {
+    let status = tokio::task::spawn_blocking(move || firewall::ensure_firewall_rule(port))
+        .await
+        .unwrap_or_else(|e| firewall::FirewallRuleStatus::Failed {
+            reason: format!("Firewall task failed: {}", e),
+        });
+    if let firewall::FirewallRuleStatus::Failed { reason } = &status {
+        warn!("Firewall rule not ensured: {}", reason);
     }
-    
-    Ok(())
+    status
 }
 
+/// Legacy variant of start_server that only returns the URL.
 #[command]
-fn set_keep_server_running(state: State<'_, ServerState>, keep_running: bool) {
-    *state.keep_running_on_close.lock().unwrap() = keep_running;
+async fn start_server_url(
+    app: tauri::AppHandle,
+    state: State<'_, ServerState>,
+    settings: State<'_, SettingsState>,
+    remote: Option<bool>,
+) -> Result<String, ServerStartError> {
+    start_server(app, state, settings, remote, None, None, None).await.map(|info| info.url)
 }
 
-#[command]
-async fn start_system_audio_capture(
-    state: State<'_, audio_capture::AudioCaptureState>,
-    max_duration_secs: u32,
-) -> Result<(), String> {
-    audio_capture::start_capture(&state, max_duration_secs).await
+/// The server_stop_grace_secs setting.
+fn stop_grace(app: &tauri::AppHandle) -> std::time::Duration {
+    std::time::Duration::from_secs(app.state::<SettingsState>().get().server_stop_grace_secs.into())
 }
 
-#[command]
-async fn stop_system_audio_capture(
-    state: State<'_, audio_capture::AudioCaptureState>,
-) -> Result<String, String> {
-    audio_capture::stop_capture(&state).await
+/// Drop what was known about a server that has been stopped: its child,
+/// info, token, record and endpoint file. A deliberate stop also starts the
+/// crash budgets over.
+fn forget_server(app: &tauri::AppHandle, state: &ServerState) {
+    let _ = state.child.lock().unwrap().take();
+    let _ = state.info.lock().unwrap().take();
+    let _ = state.auth_token.lock().unwrap().take();
+    *state.oom_crashes.lock().unwrap() = 0;
+    state.restart_budget.lock().unwrap().reset();
+    *state.recovering.lock().unwrap() = false;
+    if let Ok(data_dir) = resolve_data_dir(app) {
+        process_manager::remove_server_record(&data_dir);
+    }
+    update_endpoint_file(app, None);
 }
 
+/// Stop the bundled server and make sure it's gone: fails, naming its PID,
+/// if it's still running even after being killed.
 #[command]
-fn is_system_audio_supported() -> bool {
-    audio_capture::is_supported()
+async fn stop_server(app: tauri::AppHandle, state: State<'_, ServerState>) -> Result<StopOutcome, VoiceboxError> {
+    // An external server belongs to someone else
+    if !*state.managed.lock().unwrap() {
+        info!("stop_server: Using an external server, nothing to stop");
+        return Ok(StopOutcome::nothing_to_stop());
+    }
+
+    // Taken first, so its exit isn't mistaken for a crash
+    let pid = state.server_pid.lock().unwrap().take();
+    let outcome = match pid {
+        Some(pid) => {
+            let child = state.child.lock().unwrap().take();
+            info!("stop_server: Stopping server with PID: {}", pid);
+            let (port, grace) = (state.port(), stop_grace(&app));
+            let stopped = tokio::task::spawn_blocking(move || {
+                process_manager::stop_server_process(pid, port, grace, process_manager::KILL_EXIT_TIMEOUT)
+            })
+            .await;
+            match stopped {
+                Ok(outcome) if outcome.terminated => outcome,
+                // Still ours to stop, and its record is left for a later
+                // launch to find it by
+                stopped => {
+                    *state.server_pid.lock().unwrap() = Some(pid);
+                    *state.child.lock().unwrap() = child;
+                    let outcome = stopped?;
+                    return Err(VoiceboxError::server(format!(
+                        "The server (PID {}) is still running {} ms after it was told to stop and then killed",
+                        pid, outcome.waited_ms
+                    )));
+                }
+            }
+        }
+        None => StopOutcome::nothing_to_stop(),
+    };
+    forget_server(&app, &state);
+    let _ = event_bus::emit(&app, "server-stopped", outcome);
+    Ok(outcome)
 }
 
+/// Stop the bundled server, wait for it to let go of its port, and start it
+/// again, e.g. after changing a setting it only reads at startup. Works for
+/// a server this instance spawned and one adopted from an earlier session.
+/// `remote` defaults to the exposure it had and `options` to the launch
+/// options it was started with. Emits `server-restarting`, then
+/// `server-ready` with its info; returns its URL.
 #[command]
-fn list_audio_output_devices(
-    state: State<'_, audio_output::AudioOutputState>,
-) -> Result<Vec<audio_output::AudioOutputDevice>, String> {
-    state.list_output_devices()
+async fn restart_server(
+    app: tauri::AppHandle,
+    state: State<'_, ServerState>,
+    settings: State<'_, SettingsState>,
+    remote: Option<bool>,
+    options: Option<ServerLaunchOptions>,
+) -> Result<String, ServerStartError> {
+    if !*state.managed.lock().unwrap() {
+        return Err("Connected to an external server; there is no bundled server to restart"
+            .to_string()
+            .into());
+    }
+    let was_remote = state.info.lock().unwrap().as_ref().is_some_and(|info| info.remote);
+    let remote = remote.unwrap_or(was_remote);
+    let options = options.or_else(|| state.launch_options.lock().unwrap().clone());
+    if let Some(options) = &options {
+        options.validate().map_err(|message| ServerStartError::InvalidOptions { message })?;
+    }
+    let _ = event_bus::emit(&app, "server-restarting", serde_json::json!({ "remote": remote }));
+
+    // Adopted without this instance ever starting it, it's only known by its record
+    let pid = {
+        let mut pid = state.server_pid.lock().unwrap();
+        if pid.is_none() {
+            *pid = resolve_data_dir(&app)
+                .ok()
+                .and_then(|data_dir| process_manager::clean_stale_server_record(&data_dir))
+                .map(|record| record.pid);
+        }
+        *pid
+    };
+    stop_server(app.clone(), state.clone()).await.map_err(|e| ServerStartError::from(e.to_string()))?;
+    let port = state.port();
+    if !process_manager::wait_for_port_free(port, pid, process_manager::PORT_RELEASE_TIMEOUT).await {
+        let occupant = match pid {
+            Some(pid) => format!("the previous voicebox-server (PID {})", pid),
+            None => "the previous voicebox-server".to_string(),
+        };
+        return Err(ServerStartError::PortConflict { occupant });
+    }
+
+    let info = start_server(app.clone(), state, settings, Some(remote), None, options, None).await?;
+    Ok(info.url)
 }
 
-#[command]
-async fn play_audio_to_devices(
-    state: State<'_, audio_output::AudioOutputState>,
-    audio_data: Vec<u8>,
-    device_ids: Vec<String>,
-) -> Result<(), String> {
-    state.play_audio_to_devices(audio_data, device_ids).await
+/// Emit a progress event at most every 100ms; the first and final updates are
+/// always sent.
+fn emit_progress<T: serde::Serialize + Clone>(
+    app: &tauri::AppHandle,
+    event: &str,
+    payload: &T,
+    done: bool,
+    last_emit: &std::cell::Cell<Option<std::time::Instant>>,
+) {
+    let due = last_emit
+        .get()
+        .map_or(true, |at| at.elapsed() >= std::time::Duration::from_millis(100));
+    if due || done {
+        last_emit.set(Some(std::time::Instant::now()));
+        let _ = event_bus::emit(app, event, payload);
+    }
 }
 
 #[command]
-fn stop_audio_playback(
-    state: State<'_, audio_output::AudioOutputState>,
-) -> Result<(), String> {
-    state.stop_all_playback()
-}
+async fn migrate_data_dir(
+    app: tauri::AppHandle,
+    state: State<'_, ServerState>,
+    settings: State<'_, SettingsState>,
+    new_path: String,
+    keep_old: Option<bool>,
+    stop_running_server: Option<bool>,
+) -> Result<data_migration::MigrationResult, VoiceboxError> {
+    let old_path = resolve_data_dir(&app)?;
+    let new_path = std::path::PathBuf::from(new_path);
+    data_migration::validate_destination(&old_path, &new_path).map_err(VoiceboxError::invalid_argument)?;
+
+    let running = state.server_pid.lock().unwrap().is_some();
+    if running {
+        if !stop_running_server.unwrap_or(false) {
+            return Err(VoiceboxError::busy("The server must be stopped before migrating the data directory"));
+        }
+        info!("migrate_data_dir: Stopping server before migration");
+        stop_server(app.clone(), state.clone()).await?;
+    }
+
+    let keep_old = keep_old.unwrap_or(false);
+    info!("Migrating data directory from {:?} to {:?} (keep_old: {})", old_path, new_path, keep_old);
+
+    // Clears the dock/taskbar bar even if the operation fails partway
+    let _progress = progress_indicator::clear_on_drop(&app, "data-migration");
+    let progress_app = app.clone();
+    let (source, dest) = (old_path.clone(), new_path.clone());
+    let mut result = tokio::task::spawn_blocking(move || {
+        let last_emit = std::cell::Cell::new(None);
+        data_migration::migrate(&source, &dest, keep_old, |progress| {
+            let done = progress.files_done == progress.files_total;
+            emit_progress(&progress_app, "data-migration-progress", progress, done, &last_emit);
+        })
+    })
+    .await
+    .map_err(|e| format!("Migration task failed: {}", e))??;
+
+    // The copy is verified; from here on the new location is authoritative
+    settings.update(|s| s.data_dir = Some(new_path.clone()))?;
+    info!("Data directory is now {:?}", new_path);
+
+    if !keep_old {
+        if let Err(e) = data_migration::remove_old_tree(&old_path) {
+            warn!("Failed to remove old data directory: {}", e);
+            result.old_removed = false;
+            result.cleanup_error = Some(e);
+        }
+    }
+
+    Ok(result)
+}
+
+#[command]
+async fn export_data_backup(
+    app: tauri::AppHandle,
+    settings: State<'_, SettingsState>,
+    dest_path: Option<String>,
+    include_models: Option<bool>,
+) -> Result<backup::BackupResult, VoiceboxError> {
+    let data_dir = resolve_data_dir(&app)?;
+    let dest = match dest_path {
+        Some(path) => std::path::PathBuf::from(path),
+        None => app
+            .path()
+            .download_dir()
+            .map_err(|e| format!("Failed to get downloads dir: {}", e))?
+            .join(format!("voicebox-backup-{}.zip", process_manager::unix_timestamp())),
+    };
+    let include_models = include_models.unwrap_or(false);
+
+    // The backup carries preferences but not where this machine keeps its data
+    let mut current_settings = settings.get();
+    current_settings.data_dir = None;
+    let settings_json = serde_json::to_string_pretty(&current_settings)
+        .map_err(|e| format!("Failed to serialize settings: {}", e))?;
+
+    info!("Exporting backup of {:?} to {:?} (include_models: {})", data_dir, dest, include_models);
+
+    // Clears the dock/taskbar bar even if the operation fails partway
+    let _progress = progress_indicator::clear_on_drop(&app, "data-backup");
+    let progress_app = app.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let last_emit = std::cell::Cell::new(None);
+        backup::export(&data_dir, &dest, include_models, Some(&settings_json), |progress| {
+            let done = progress.files_done == progress.files_total;
+            emit_progress(&progress_app, "data-backup-progress", progress, done, &last_emit);
+        })
+    })
+    .await
+    .map_err(|e| format!("Backup task failed: {}", e))??;
+
+    info!("Backup written: {} files, {} bytes", result.files, result.bytes);
+    Ok(result)
+}
+
+#[command]
+async fn import_data_backup(
+    app: tauri::AppHandle,
+    state: State<'_, ServerState>,
+    settings: State<'_, SettingsState>,
+    src_path: String,
+    merge: bool,
+) -> Result<backup::RestoreResult, VoiceboxError> {
+    if state.server_pid.lock().unwrap().is_some() {
+        return Err(VoiceboxError::busy("The server must be stopped before restoring a backup"));
+    }
+
+    let data_dir = resolve_data_dir(&app)?;
+    let src = std::path::PathBuf::from(src_path);
+    info!("Restoring backup {:?} into {:?} (merge: {})", src, data_dir, merge);
+
+    // Clears the dock/taskbar bar even if the operation fails partway
+    let _progress = progress_indicator::clear_on_drop(&app, "data-restore");
+    let progress_app = app.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let last_emit = std::cell::Cell::new(None);
+        backup::import(&src, &data_dir, merge, |progress| {
+            let done = progress.files_done == progress.files_total;
+            emit_progress(&progress_app, "data-restore-progress", progress, done, &last_emit);
+        })
+    })
+    .await
+    .map_err(|e| format!("Restore task failed: {}", e))??;
+
+    if let Some(json) = &result.settings_json {
+        match serde_json::from_str::<settings::Settings>(json) {
+            Ok(restored) => {
+                // Keep pointing at the data dir we just restored into
+                settings.update(|s| {
+                    let data_dir = s.data_dir.take();
+                    *s = restored;
+                    s.data_dir = data_dir;
+                })?;
+            }
+            Err(e) => warn!("Ignoring unreadable settings in backup: {}", e),
+        }
+    }
+
+    info!("Backup restored: {} files, {} bytes", result.files_restored, result.bytes_restored);
+    Ok(result)
+}
+
+/// Whether the data dir can be written and has room, probed afresh. While
+/// `problem` is set the app runs degraded; see storage::StorageStatus.
+#[command]
+async fn get_storage_status(app: tauri::AppHandle) -> Result<storage::StorageStatus, VoiceboxError> {
+    Ok(tokio::task::spawn_blocking(move || refresh_storage(&app)).await?)
+}
+
+/// Check before a model download that the data dir has `required_bytes`
+/// free, failing with insufficient_space otherwise. Returns the bytes free.
+#[command]
+async fn check_model_download_space(app: tauri::AppHandle, required_bytes: u64) -> Result<u64, VoiceboxError> {
+    let data_dir = resolve_data_dir(&app)?;
+    tokio::task::spawn_blocking(move || storage::ensure_free_space(&data_dir, required_bytes)).await?
+}
+
+#[command]
+async fn get_data_dir_usage(
+    app: tauri::AppHandle,
+    usage: State<'_, disk_usage::DiskUsageState>,
+    force_refresh: Option<bool>,
+) -> Result<disk_usage::DataDirUsage, VoiceboxError> {
+    let data_dir = resolve_data_dir(&app)?;
+    if !force_refresh.unwrap_or(false) {
+        if let Some(cached) = usage.fresh(&data_dir) {
+            return Ok(cached);
+        }
+    }
+
+    // A newer request supersedes any walk still running
+    usage.cancel();
+    let cancel = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    *usage.cancel.lock().unwrap() = Some(cancel.clone());
+
+    let walk_cancel = cancel.clone();
+    let result = tokio::task::spawn_blocking(move || disk_usage::compute(&data_dir, &walk_cancel))
+        .await
+        .map_err(|e| format!("Disk usage task failed: {}", e))?;
+
+    {
+        let mut current = usage.cancel.lock().unwrap();
+        if current.as_ref().is_some_and(|c| std::sync::Arc::ptr_eq(c, &cancel)) {
+            *current = None;
+        }
+    }
+
+    let report = result?;
+    *usage.cached.lock().unwrap() = Some((std::time::Instant::now(), report.clone()));
+    Ok(report)
+}
+
+#[command]
+fn cancel_data_dir_usage(usage: State<'_, disk_usage::DiskUsageState>) -> bool {
+    usage.cancel()
+}
+
+#[command]
+async fn clear_server_cache(
+    app: tauri::AppHandle,
+    usage: State<'_, disk_usage::DiskUsageState>,
+    categories: Vec<String>,
+) -> Result<u64, VoiceboxError> {
+    let categories = categories
+        .iter()
+        .map(|c| disk_usage::UsageCategory::parse(c))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(VoiceboxError::invalid_argument)?;
+    let data_dir = resolve_data_dir(&app)?;
+
+    let freed = tokio::task::spawn_blocking(move || disk_usage::clear(&data_dir, &categories))
+        .await
+        .map_err(|e| format!("Clear cache task failed: {}", e))??;
+    usage.invalidate();
+
+    info!("Cleared server cache: {} bytes freed", freed);
+    Ok(freed)
+}
+
+/// Whether a server that crashes is started again on its own, for this
+/// session. On by default.
+#[command]
+fn set_auto_restart(state: State<'_, ServerState>, enabled: bool) {
+    *state.auto_restart.lock().unwrap() = enabled;
+    info!("Server auto-restart {}", if enabled { "enabled" } else { "disabled" });
+}
+
+#[command]
+fn set_keep_server_running(
+    app: tauri::AppHandle,
+    state: State<'_, ServerState>,
+    settings: State<'_, SettingsState>,
+    keep_running: bool,
+    idle_minutes: Option<u32>,
+) -> Result<(), VoiceboxError> {
+    let idle_minutes = idle_minutes.filter(|m| *m > 0);
+    settings.update(|s| {
+        s.keep_server_running_on_close = keep_running;
+        s.idle_timeout_minutes = idle_minutes;
+    })?;
+    apply_keep_running(&state, &settings);
+    for key in ["keep_server_running_on_close", "idle_timeout_minutes"] {
+        let _ = event_bus::emit(&app, "setting-changed", serde_json::json!({ "key": key }));
+    }
+    Ok(())
+}
+
+/// ServerState keeps a copy of the close policy for the exit path; sync it
+/// from the store.
+fn apply_keep_running(state: &ServerState, settings: &SettingsState) {
+    let values = settings.get();
+    *state.keep_running_on_close.lock().unwrap() = values.keep_server_running_on_close;
+    *state.idle_timeout_minutes.lock().unwrap() = values.idle_timeout_minutes;
+}
+
+#[command]
+fn get_setting(settings: State<'_, SettingsState>, key: String) -> Result<serde_json::Value, VoiceboxError> {
+    settings.get_value(&key).map_err(VoiceboxError::not_found)
+}
+
+#[command]
+fn get_all_settings(
+    settings: State<'_, SettingsState>,
+) -> Result<serde_json::Map<String, serde_json::Value>, VoiceboxError> {
+    Ok(settings.get_all_values()?)
+}
+
+#[command]
+fn set_setting(
+    app: tauri::AppHandle,
+    state: State<'_, ServerState>,
+    settings: State<'_, SettingsState>,
+    key: String,
+    value: serde_json::Value,
+) -> Result<(), VoiceboxError> {
+    settings.set_value(&key, value).map_err(VoiceboxError::invalid_argument)?;
+    apply_keep_running(&state, &settings);
+    let _ = event_bus::emit(&app, "setting-changed", serde_json::json!({ "key": key }));
+    Ok(())
+}
+
+#[command]
+async fn get_server_status(
+    state: State<'_, ServerState>,
+    settings: State<'_, SettingsState>,
+) -> Result<ServerStatus, VoiceboxError> {
+    let pid = *state.server_pid.lock().unwrap();
+    let managed = *state.managed.lock().unwrap();
+    let info = state.info.lock().unwrap().clone();
+    let auth_token = state.auth_token.lock().unwrap().clone();
+    let server_settings = settings.get();
+    // An adopted server isn't our child, so ask the OS whether the PID lives
+    let running = match pid {
+        Some(pid) => process_manager::is_process_running(pid),
+        None => !managed && info.is_some(),
+    };
+    let healthy = match (&info, running) {
+        (Some(info), true) => Some(process_manager::is_ready_at(&info.url, auth_token.as_deref()).await),
+        _ => None,
+    };
+    let now = process_manager::unix_timestamp();
+    Ok(ServerStatus {
+        running,
+        managed_by_app: managed && running,
+        pid,
+        port: info.as_ref().map_or_else(|| state.port(), |info| info.port),
+        url: info.as_ref().map(|info| info.url.clone()),
+        uptime_secs: info.as_ref().filter(|_| running).map(|info| info.uptime_secs(now)),
+        healthy,
+        keep_running_on_close: *state.keep_running_on_close.lock().unwrap(),
+        idle_timeout_minutes: *state.idle_timeout_minutes.lock().unwrap(),
+        log_level: server_settings.server_log_level,
+        access_logs: server_settings.server_access_logs,
+        process_priority: server_settings.process_priority,
+        managed,
+        info,
+        paths_mode: paths::mode(),
+    })
+}
+
+#[derive(serde::Serialize)]
+struct ServerVersionInfo {
+    app_version: String,
+    server_version: Option<String>,
+    compatibility: VersionCompatibility,
+}
+
+#[command]
+async fn get_server_version(state: State<'_, ServerState>) -> Result<ServerVersionInfo, VoiceboxError> {
+    let running = state.server_pid.lock().unwrap().is_some();
+    let external_url = if *state.managed.lock().unwrap() {
+        None
+    } else {
+        state.info.lock().unwrap().as_ref().map(|info| info.url.clone())
+    };
+    let server_version = if let Some(url) = external_url {
+        let auth_token = state.auth_token.lock().unwrap().clone();
+        process_manager::fetch_server_version_at(&url, auth_token.as_deref()).await
+    } else if running {
+        process_manager::fetch_server_version(state.port()).await
+    } else {
+        None
+    };
+    let compatibility = server_version
+        .as_deref()
+        .map(process_manager::check_server_compatibility)
+        .unwrap_or(VersionCompatibility::Unknown);
+
+    Ok(ServerVersionInfo {
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        server_version,
+        compatibility,
+    })
+}
+
+/// Level and access-log flags are only read by the sidecar at startup, so a
+/// change made while it runs is reported as RequiresRestart.
+fn applied_on_next_start(state: &ServerState) -> SettingApplied {
+    if state.server_pid.lock().unwrap().is_some() {
+        SettingApplied::RequiresRestart
+    } else {
+        SettingApplied::Applied
+    }
+}
+
+#[command]
+fn set_server_log_level(
+    state: State<'_, ServerState>,
+    settings: State<'_, SettingsState>,
+    level: String,
+) -> Result<SettingApplied, VoiceboxError> {
+    let level = ServerLogLevel::parse(&level).map_err(VoiceboxError::invalid_argument)?;
+    let changed = settings.get().server_log_level != level;
+    settings.update(|s| s.server_log_level = level)?;
+    Ok(if changed { applied_on_next_start(&state) } else { SettingApplied::Applied })
+}
+
+#[command]
+fn set_server_access_logs(
+    state: State<'_, ServerState>,
+    settings: State<'_, SettingsState>,
+    enabled: bool,
+) -> Result<SettingApplied, VoiceboxError> {
+    let changed = settings.get().server_access_logs != enabled;
+    settings.update(|s| s.server_access_logs = enabled)?;
+    Ok(if changed { applied_on_next_start(&state) } else { SettingApplied::Applied })
+}
+
+/// Takes effect immediately on a running server we own, no restart needed.
+#[command]
+fn set_server_priority(
+    state: State<'_, ServerState>,
+    settings: State<'_, SettingsState>,
+    priority: String,
+) -> Result<SettingApplied, VoiceboxError> {
+    let priority = ProcessPriority::parse(&priority).map_err(VoiceboxError::invalid_argument)?;
+    settings.update(|s| s.process_priority = Some(priority))?;
+
+    let pid = *state.server_pid.lock().unwrap();
+    if let Some(pid) = pid {
+        process_manager::set_process_priority(pid, priority)?;
+        info!("Server priority set to {}", priority.as_str());
+    }
+    Ok(SettingApplied::Applied)
+}
+
+/// Start a system audio capture and report it: `capture-started`, then
+/// `capture-auto-stopped` and `capture-stopped` if it runs into
+/// `max_duration_secs`, or `capture-failed` if it can't start.
+async fn begin_capture(app: &tauri::AppHandle, max_duration_secs: u32) -> Result<(), VoiceboxError> {
+    begin_capture_with(app, audio_capture::CaptureOptions::new(max_duration_secs)).await
+}
+
+#[tracing::instrument(name = "capture", skip_all, fields(session = tracing::field::Empty))]
+async fn begin_capture_with(app: &tauri::AppHandle, options: audio_capture::CaptureOptions) -> Result<(), VoiceboxError> {
+    if app.state::<combined_capture::CombinedCapture>().is_capturing() {
+        return Err(VoiceboxError::busy("A combined capture is running"));
+    }
+    let max_duration_secs = options.max_duration_secs;
+    let streamed = options.stream;
+    let state = app.state::<audio_capture::AudioCaptureState>();
+    // Dropped right away if the start fails
+    let wake_lock = app.state::<power::PowerState>().acquire("System audio capture");
+    if let Err(e) = audio_capture::start_capture_with(&state, options).await {
+        // The capture already running is unaffected
+        if matches!(e, VoiceboxError::Busy { .. }) {
+            return Err(e);
+        }
+        // Name the missing permission when that's why it failed; a refused
+        // option, like unavailable pre-roll, isn't about permissions
+        let e = if matches!(e, VoiceboxError::Unsupported { .. }) {
+            e
+        } else {
+            let explain_app = app.clone();
+            let explained = tokio::task::spawn_blocking(move || {
+                permissions::explain_failure(&explain_app, permissions::PermissionKind::ScreenRecording, e)
+            });
+            explained.await?
+        };
+        emit_capture_failed(app, &e);
+        return Err(e);
+    }
+    let session = state.next_session();
+    tracing::Span::current().record("session", session);
+    info!("System audio capture started (limit {}s)", max_duration_secs);
+    let _ = event_bus::emit(app, "capture-started", serde_json::json!({ "session": session }));
+    spawn_capture_level_meter(app, session, wake_lock);
+    if streamed {
+        spawn_capture_streamer(app, session);
+    }
+    Ok(())
+}
+
+/// Emit `capture-level` for level meters (the main window's and the mini
+/// recorder's) until the capture ends, and `capture-paused` and
+/// `capture-resumed` as auto-pause leaves out silence, and
+/// `capture-device-changed` when the capture moves to another output, and
+/// `capture-clipping` the first time the source clips. The
+/// wake lock is held until then too, however the capture ends: stopped, hit
+/// its limit or failed. A capture that ran into its limit or stopped for silence is
+/// reported as `capture-auto-stopped`, and one that filled its buffer as
+/// `capture-buffer-full`; one that ended because its application quit is
+/// finalized here and reported as `capture-auto-stopped`. One the hotkey
+/// started is finalized here however it ended on its own.
+fn spawn_capture_level_meter(app: &tauri::AppHandle, session: u64, wake_lock: power::WakeLock) {
+    const LEVEL_INTERVAL_MS: u32 = 100;
+    let app = app.clone();
+    let meter = async move {
+        let _wake_lock = wake_lock;
+        let started = std::time::Instant::now();
+        let mut dropouts = 0;
+        let mut device_switches = 0;
+        let mut clipping = false;
+        let mut paused = false;
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(LEVEL_INTERVAL_MS as u64));
+        loop {
+            interval.tick().await;
+            let state = app.state::<audio_capture::AudioCaptureState>();
+            if state.current_session() != session || !state.is_capturing() {
+                break;
+            }
+            let (rms, peak) = state.take_level();
+            let _ = event_bus::emit(
+                &app,
+                "capture-level",
+                serde_json::json!({
+                    "session": session,
+                    "rms": rms,
+                    "peak": peak,
+                    "elapsed_ms": started.elapsed().as_millis() as u64,
+                }),
+            );
+            // The device overran before the backend read it: something is
+            // starving the audio path, and the UI can suggest closing apps
+            let status = state.status();
+            if status.dropouts > dropouts {
+                warn!("Capture dropouts: {} so far ({:?})", status.dropouts, status.health);
+                let _ = event_bus::emit(
+                    &app,
+                    "capture-health-warning",
+                    serde_json::json!({ "session": session, "dropouts": status.dropouts, "health": status.health }),
+                );
+            }
+            dropouts = status.dropouts;
+            // The output device went away and the capture moved on to the
+            // one that took over, with silence for the gap
+            if status.health.device_switches > device_switches {
+                let _ = event_bus::emit(
+                    &app,
+                    "capture-device-changed",
+                    serde_json::json!({ "session": session, "device_switches": status.health.device_switches }),
+                );
+            }
+            device_switches = status.health.device_switches;
+            // Once, while the user can still turn the volume down and
+            // record again
+            let clips = state.clip_stats();
+            if !clipping && clips.detected() {
+                clipping = true;
+                warn!("Capture is clipping: {} frames so far", clips.clipped_frames);
+                let _ = event_bus::emit(
+                    &app,
+                    "capture-clipping",
+                    serde_json::json!({
+                        "session": session,
+                        "clipped_frames": clips.clipped_frames,
+                        "clip_percentage": clips.percentage(),
+                        "longest_clip_frames": clips.longest_run,
+                    }),
+                );
+            }
+            if status.paused != paused {
+                paused = status.paused;
+                let topic = if paused { "capture-paused" } else { "capture-resumed" };
+                info!("System audio capture {} for source silence", if paused { "paused" } else { "resumed" });
+                let _ = event_bus::emit(
+                    &app,
+                    topic,
+                    serde_json::json!({ "session": session, "reason": "source-silence" }),
+                );
+            }
+        }
+
+        // Nobody stopped or restarted it, so it ended on its own
+        let (current, auto_stop, options, status) = {
+            let state = app.state::<audio_capture::AudioCaptureState>();
+            let current = state.current_session() == session;
+            let auto_stop = state.auto_stop().filter(|_| current);
+            (current, auto_stop, state.options(), state.status())
+        };
+        match auto_stop {
+            // Pauses by hand don't count, so this isn't max_duration_secs
+            // after the start
+            Some(audio_capture::AutoStopReason::Limit) => {
+                let max_duration_secs = options.max_duration_secs;
+                info!("System audio capture reached its {}s limit", max_duration_secs);
+                let payload = serde_json::json!({ "reason": "limit", "max_duration_secs": max_duration_secs });
+                let _ = event_bus::emit(&app, "capture-auto-stopped", payload);
+                emit_capture_stopped(&app, session, "timeout", status.elapsed_secs);
+            }
+            // Nothing more to record, so the frontend can stop and fetch it
+            Some(audio_capture::AutoStopReason::Silence) => {
+                let silence_stop_secs = options.silence_stop_secs;
+                info!("System audio capture stopped after {:?}s of silence", silence_stop_secs);
+                let payload = serde_json::json!({
+                    "reason": "silence",
+                    "session": session,
+                    "silence_stop_secs": silence_stop_secs,
+                });
+                let _ = event_bus::emit(&app, "capture-auto-stopped", payload);
+            }
+            // Stopped so memory doesn't run out; what it holds is kept for stop
+            Some(audio_capture::AutoStopReason::BufferFull) => {
+                let max_buffer_secs = options.max_buffer_secs;
+                warn!("System audio capture filled its {:?}s buffer", max_buffer_secs);
+                let payload = serde_json::json!({ "session": session, "max_buffer_secs": max_buffer_secs });
+                let _ = event_bus::emit(&app, "capture-buffer-full", payload);
+            }
+            // Nothing more comes once the application quits, so keep what was
+            // recorded instead of leaving it to a stop nobody may send.
+            // end_capture reports a failure itself, as capture-failed
+            Some(audio_capture::AutoStopReason::ApplicationExited) => {
+                if end_capture(&app, audio_capture::SaveAs::default()).await.is_ok() {
+                    info!("Finalized system audio capture after its application quit");
+                    let payload = serde_json::json!({ "reason": "application_exited", "session": session });
+                    let _ = event_bus::emit(&app, "capture-auto-stopped", payload);
+                }
+            }
+            // The backend failed, and nothing more is coming until it is
+            // stopped and started again
+            None if current && status.last_error.is_some() => {
+                emit_capture_stopped(&app, session, "error", status.elapsed_secs);
+            }
+            _ => {}
+        }
+        // Collected here when the hotkey started it, unless it already was;
+        // end_capture sends the audio out as capture-hotkey-stopped
+        if current && app.state::<hotkeys::HotkeyCaptureState>().owns(session) {
+            let _ = end_capture(&app, audio_capture::SaveAs::default()).await;
+        }
+    };
+    tokio::spawn(meter.instrument(tracing::Span::current()));
+}
+
+/// Emit a streamed capture's audio as `audio-capture-chunk` every
+/// STREAM_CHUNK_MS while it runs. end_capture sends what is left.
+fn spawn_capture_streamer(app: &tauri::AppHandle, session: u64) {
+    let app = app.clone();
+    let streamer = async move {
+        let period = tokio::time::Duration::from_millis(audio_capture::STREAM_CHUNK_MS as u64);
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let state = app.state::<audio_capture::AudioCaptureState>();
+            if state.current_session() != session || !state.is_capturing() {
+                break;
+            }
+            state.send_chunk(|chunk| emit_capture_chunk(&app, session, chunk));
+        }
+    };
+    tokio::spawn(streamer.instrument(tracing::Span::current()));
+}
+
+fn emit_capture_chunk(app: &tauri::AppHandle, session: u64, chunk: audio_capture::CaptureChunk) {
+    let payload = serde_json::json!({ "session": session, "chunk": chunk });
+    if let Err(e) = event_bus::emit(app, "audio-capture-chunk", payload) {
+        warn!("Failed to emit audio-capture-chunk event: {}", e);
+    }
+}
+
+/// Scrubs what diagnostics bundles and audio environment snapshots must not
+/// carry: the server and control API tokens, the home directory and the
+/// account name.
+fn diagnostics_redactor(app: &tauri::AppHandle) -> diagnostics::Redactor {
+    let auth_token = app.state::<ServerState>().auth_token.lock().unwrap().clone();
+    let control_token = app.state::<SettingsState>().get().control_api.token;
+    diagnostics::Redactor::new(
+        app.path().home_dir().ok().as_deref(),
+        auth_token.into_iter().chain(control_token).collect(),
+    )
+}
+
+/// The audio environment as of now, redacted, from cached device lists.
+fn audio_environment(app: &tauri::AppHandle) -> audio_environment::AudioEnvironmentSnapshot {
+    let mut snapshot = app.state::<audio_environment::AudioEnvironment>().snapshot(
+        &app.state::<audio_capture::AudioCaptureState>(),
+        &app.state::<audio_output::AudioOutputState>(),
+    );
+    snapshot.redact(&diagnostics_redactor(app));
+    snapshot
+}
+
+/// Emit `capture-failed` with the audio environment it failed in.
+fn emit_capture_failed(app: &tauri::AppHandle, e: &VoiceboxError) {
+    let payload = serde_json::json!({ "error": e.message(), "code": e.code(), "environment": audio_environment(app) });
+    let _ = event_bus::emit(app, "capture-failed", payload);
+}
+
+/// Stop the capture and return the recording as base64 WAV, or the path of
+/// the file a capture to one was written to, saved as `save` asks.
+#[tracing::instrument(name = "capture", skip_all, fields(session = tracing::field::Empty))]
+async fn end_capture(app: &tauri::AppHandle, save: audio_capture::SaveAs) -> Result<String, VoiceboxError> {
+    let state = app.state::<audio_capture::AudioCaptureState>();
+    let session = state.current_session();
+    tracing::Span::current().record("session", session);
+    let auto_stop = state.auto_stop();
+    state.next_session();
+    let result = audio_capture::stop_capture_as(&state, save).await;
+    // Before the next await, while they are still this capture's
+    let options = state.options();
+    let markers = options.split_on_markers.then(|| state.markers());
+    // The frames that came in after the streamer's last tick, as the last chunk
+    if options.stream {
+        state.send_chunk(|chunk| emit_capture_chunk(app, session, chunk));
+    }
+    match &result {
+        // Not kept: the audio was never in memory, and the file is the caller's
+        Ok(path) if options.spill_path.is_some() => info!("System audio capture written to {}", path),
+        Ok(audio) => {
+            info!("System audio capture stopped");
+            metrics::METRICS.ipc_payload_bytes.record(audio.len() as u64);
+            state.keep_recording(session, audio);
+            let label = format!("System audio capture {}", session);
+            mru::note(app, settings::RecentKind::Recording, &session.to_string(), &label);
+            fingerprint_capture(app, session).await;
+            if let Some(markers) = markers.filter(|markers| !markers.is_empty()) {
+                split_capture(app, session, &label, markers).await;
+            }
+        }
+        Err(e) => {
+            error!("System audio capture failed: {}", e);
+            emit_capture_failed(app, e);
+        }
+    }
+    let reason = match (&result, auto_stop) {
+        (Err(_), _) => "error",
+        (Ok(_), Some(audio_capture::AutoStopReason::Limit)) => "timeout",
+        (Ok(_), _) => "manual",
+    };
+    emit_capture_stopped(app, session, reason, state.status().elapsed_secs);
+    hotkeys::capture_ended(app, session, &result);
+    result
+}
+
+/// Emit `capture-stopped` for `session`, with why it stopped ("timeout",
+/// "manual" or "error") and how long it recorded for. A capture that ran
+/// into its limit reports it then, and again once it is collected. The
+/// session id is what export_audio takes to save the capture.
+fn emit_capture_stopped(app: &tauri::AppHandle, session: u64, reason: &str, elapsed_secs: f64) {
+    let payload = serde_json::json!({ "session": session, "reason": reason, "elapsed_secs": elapsed_secs });
+    let _ = event_bus::emit(app, "capture-stopped", payload);
+}
+
+/// Fingerprint the kept capture `session` on a blocking thread, for
+/// find_similar_recordings, unless it is over fingerprint_max_minutes.
+async fn fingerprint_capture(app: &tauri::AppHandle, session: u64) {
+    let state = app.state::<audio_capture::AudioCaptureState>();
+    let max_minutes = app.state::<SettingsState>().get().fingerprint_max_minutes;
+    let duration_ms = state.recording_metadata(session).map_or(0, |metadata| metadata.duration_ms);
+    if duration_ms > max_minutes as u64 * 60_000 {
+        info!("Not fingerprinting capture {}: longer than {} minutes", session, max_minutes);
+        return;
+    }
+    // Copied before the first await, while the samples are still this capture's
+    let (samples, sample_rate, channels) = state.captured_audio();
+    match tokio::task::spawn_blocking(move || fingerprint::compute(&samples, sample_rate, channels)).await {
+        Ok(hashes) if hashes.is_empty() => debug!("Capture {} is too short to fingerprint", session),
+        Ok(hashes) => {
+            state.set_fingerprint(session, fingerprint::encode(&hashes));
+        }
+        Err(e) => warn!("Failed to fingerprint capture {}: {}", session, e),
+    }
+}
+
+/// Save the kept capture `session` to the library cut at `markers`, then
+/// emit `capture-split` with the entries, or `capture-split-failed`.
+async fn split_capture(app: &tauri::AppHandle, session: u64, label: &str, markers: Vec<audio_capture::CaptureMarker>) {
+    let state = app.state::<audio_capture::AudioCaptureState>();
+    let (Some(audio), Some(metadata)) = (state.recording(session), state.recording_metadata(session)) else {
+        return;
+    };
+    let points: Vec<audio_split::SplitPoint> = markers
+        .into_iter()
+        .map(|marker| audio_split::SplitPoint {
+            at_frames: marker.at_frames,
+            label: marker.label,
+        })
+        .collect();
+    let (task_app, label) = (app.clone(), label.to_string());
+    let split = tokio::task::spawn_blocking(move || {
+        use base64::Engine;
+        let wav = base64::engine::general_purpose::STANDARD
+            .decode(audio)
+            .map_err(|e| VoiceboxError::internal(format!("The capture is not valid base64: {}", e)))?;
+        let mut entries = Vec::new();
+        if task_app.state::<SettingsState>().get().split_keep_original {
+            entries.push(save_to_library(&task_app, &wav, Some(label.clone()), metadata.clone())?.0);
+        }
+        let sample_rate = metadata.sample_rate;
+        entries.extend(save_segments(&task_app, std::io::Cursor::new(wav), &points, sample_rate, &label, &metadata)?);
+        Ok::<_, VoiceboxError>(entries)
+    });
+    match split.await.map_err(VoiceboxError::from).and_then(|split| split) {
+        Ok(recordings) => {
+            info!("Split capture {} into {} library recording(s)", session, recordings.len());
+            let payload = serde_json::json!({ "session": session, "recordings": recordings });
+            if let Err(e) = event_bus::emit(app, "capture-split", payload) {
+                warn!("Failed to emit capture-split event: {}", e);
+            }
+        }
+        Err(e) => {
+            error!("Failed to split capture {}: {}", session, e);
+            let payload = serde_json::json!({ "session": session, "error": e.message(), "code": e.code() });
+            let _ = event_bus::emit(app, "capture-split-failed", payload);
+        }
+    }
+}
+
+/// Save the WAV read from `wav` to the library as segments cut at `points`,
+/// each with `metadata` narrowed to it. Segments under split_min_segment_ms
+/// are merged forward. Blocking.
+fn save_segments<R: std::io::Read + std::io::Seek>(
+    app: &tauri::AppHandle,
+    wav: R,
+    points: &[audio_split::SplitPoint],
+    sample_rate: u32,
+    base_label: &str,
+    metadata: &recording_metadata::RecordingMetadata,
+) -> Result<Vec<recordings::RecordingEntry>, VoiceboxError> {
+    let min_ms = app.state::<SettingsState>().get().split_min_segment_ms;
+    let min_frames = min_ms as u64 * sample_rate as u64 / 1000;
+    let mut entries = Vec::new();
+    audio_split::split_wav(wav, points, min_frames, base_label, |segment, label, segment_wav| {
+        let segment_metadata = audio_split::segment_metadata(metadata, segment, sample_rate);
+        entries.push(save_to_library(app, &segment_wav, Some(label), segment_metadata)?.0);
+        Ok(())
+    })?;
+    Ok(entries)
+}
+
+/// Whether a capture of `scope`, or of `target` when it was pointed at a
+/// device, would hear playback to `playback_ids`. A conflict is emitted as
+/// `audio-conflict` and returned, or refused as busy when `block` is set.
+fn check_audio_conflict(
+    app: &tauri::AppHandle,
+    scope: audio_capture::CaptureScope,
+    target: Option<&audio_capture::CaptureTarget>,
+    playback_ids: &[String],
+    block: bool,
+) -> Result<Option<audio_conflict::AudioConflict>, VoiceboxError> {
+    if scope == audio_capture::CaptureScope::Nothing || playback_ids.is_empty() {
+        return Ok(None);
+    }
+    let devices = app.state::<audio_output::AudioOutputState>().list_output_devices()?;
+    let conflict = match target {
+        Some(target) => audio_conflict::check_target(&devices, &target.name, playback_ids),
+        None => audio_conflict::check(scope, &devices, playback_ids),
+    };
+    let Some(conflict) = conflict else {
+        return Ok(None);
+    };
+    warn!("{}", conflict.message);
+    if let Err(e) = event_bus::emit(app, "audio-conflict", &conflict) {
+        warn!("Failed to emit audio-conflict event: {}", e);
+    }
+    if block {
+        return Err(VoiceboxError::busy(conflict.message));
+    }
+    Ok(Some(conflict))
+}
+
+/// The capture target `device_id` names, if the backend lists it.
+async fn capture_target(
+    app: &tauri::AppHandle,
+    device_id: Option<String>,
+) -> Result<Option<audio_capture::CaptureTarget>, VoiceboxError> {
+    let Some(device_id) = device_id else {
+        return Ok(None);
+    };
+    let app = app.clone();
+    let targets = tokio::task::spawn_blocking(move || app.state::<audio_capture::AudioCaptureState>().targets());
+    Ok(targets.await??.into_iter().find(|target| target.id == device_id))
+}
+
+/// Start a capture, of the default output, of `device_id` (from
+/// list_audio_capture_targets) or of the one application `application_pid`
+/// (from list_capturable_applications), the fields of `request` all but
+/// max_duration_secs optional. Playback or routes it would record are
+/// reported in `conflict_warning`, or refuse the start with
+/// `block_on_conflict`. With `auto_pause_on_source_silence`, silent
+/// stretches are left out; with `split_on_markers`, stopping also saves it
+/// to the library cut at its markers. With `stream`, the audio comes as
+/// `audio-capture-chunk` events while it runs, and stopping returns only a
+/// manifest of them. `max_buffer_secs` caps the audio held in memory: the
+/// capture ends there with `capture-buffer-full`, or with `rolling` keeps
+/// only its latest max_buffer_secs. With `pre_roll_ms`, a running rolling
+/// capture is carried on as this one, from that long before the start;
+/// without one it starts without the pre-roll, or with `require_pre_roll`
+/// fails. With `capture_to_file`, the audio goes
+/// to a WAV in the app data dir as it arrives, and stopping returns its path.
+/// With `silence_stop_secs`, the capture stops on its own once that long
+/// passes under `silence_threshold_db` (-60 dBFS RMS by default) after the
+/// source was first heard, and `capture-auto-stopped` says so. With
+/// `trim_silence`, the silence before its first sound and after its last
+/// is left out of the saved audio.
+#[command]
+async fn start_system_audio_capture(
+    app: tauri::AppHandle,
+    request: audio_capture::CaptureRequest,
+) -> Result<audio_conflict::ConflictCheck, VoiceboxError> {
+    let mut options = request.options()?;
+    if request.capture_to_file {
+        options.spill_path = Some(capture_file_path(&app)?);
+    }
+    begin_checked_capture(&app, options, request.block_on_conflict).await
+}
+
+/// A new file under the app data dir for a capture written as it arrives.
+fn capture_file_path(app: &tauri::AppHandle) -> Result<std::path::PathBuf, VoiceboxError> {
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    let dir = paths::app_data_dir(app).map_err(VoiceboxError::io)?.join("captures");
+    Ok(dir.join(format!("capture-{}.wav", millis)))
+}
+
+/// Check for playback or routes the capture would record, refusing it as
+/// busy if `block` is set, then start it.
+async fn begin_checked_capture(
+    app: &tauri::AppHandle,
+    options: audio_capture::CaptureOptions,
+    block: bool,
+) -> Result<audio_conflict::ConflictCheck, VoiceboxError> {
+    let scope = app.state::<audio_capture::AudioCaptureState>().scope();
+    let mut playing = app.state::<audio_route::AudioRoutes>().destinations();
+    if let Some(playback) = app.state::<audio_output::AudioOutputState>().current_playback() {
+        playing.extend_from_slice(playback.device_ids());
+    }
+    // A device that isn't listed is left for the start to refuse
+    let target = capture_target(app, options.device_id.clone()).await?;
+    // Another application's audio never includes this app's playback
+    let conflict_warning = match options.application_pid {
+        Some(_) => None,
+        None => check_audio_conflict(app, scope, target.as_ref(), &playing, block)?,
+    };
+    begin_capture_with(app, options).await?;
+    Ok(audio_conflict::ConflictCheck { conflict_warning })
+}
+
+/// The devices start_system_audio_capture can record instead of the default
+/// output. Empty where the backend always records the same thing.
+#[command]
+async fn list_audio_capture_targets(app: tauri::AppHandle) -> Result<Vec<audio_capture::CaptureTarget>, VoiceboxError> {
+    tokio::task::spawn_blocking(move || app.state::<audio_capture::AudioCaptureState>().targets()).await?
+}
+
+/// The running applications start_system_audio_capture can record alone.
+/// Empty where the backend can't capture a single application.
+#[command]
+async fn list_capturable_applications(
+    app: tauri::AppHandle,
+) -> Result<Vec<audio_capture::CapturableApplication>, VoiceboxError> {
+    tokio::task::spawn_blocking(move || app.state::<audio_capture::AudioCaptureState>().applications()).await?
+}
+
+/// Whether a capture is recording, stopping or idle, how far it has got,
+/// and how the current or last one's audio path is holding up. A reloaded
+/// frontend reattaches with this and can still stop the capture.
+#[command]
+fn get_capture_status(state: State<'_, audio_capture::AudioCaptureState>) -> audio_capture::CaptureStatus {
+    state.status()
+}
+
+/// What stop_system_audio_capture returns: the recording as base64 WAV, or
+/// with an encoding asked for as base64 in that encoding with its MIME type;
+/// for a streamed capture only what was streamed, or for a capture to a file
+/// its path. Only a capture to a file isn't kept for export_audio.
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+enum StoppedCapture {
+    Audio(String),
+    Encoded { data: String, mime_type: &'static str },
+    Streamed(audio_capture::CaptureManifest),
+    File { path: String },
+}
+
+/// Stop the capture. A target_sample_rate resamples the recording to that
+/// rate; a capture to a file can't be. output_format is "pcm16" (the
+/// default), "pcm24" or "float32", which keeps the samples as delivered.
+/// normalize scales to a peak in dBFS, such as -1, or to a loudness such as
+/// "lufs:-16", raising the level by at most 30 dB.
+/// encoding is "wav" or "flac", which keeps the rate and channels and is
+/// always 16-bit; the recording kept for export_audio stays a WAV.
+#[command]
+async fn stop_system_audio_capture(
+    app: tauri::AppHandle,
+    target_sample_rate: Option<u32>,
+    output_format: Option<audio_util::WavSampleFormat>,
+    normalize: Option<audio_capture::NormalizeTarget>,
+    encoding: Option<audio_export::ExportFormat>,
+) -> Result<StoppedCapture, VoiceboxError> {
+    let options = app.state::<audio_capture::AudioCaptureState>().options();
+    // Before stopping, so a request that can't be met leaves the capture running
+    if let Some(encoding) = encoding {
+        if options.stream || options.spill_path.is_some() {
+            return Err(VoiceboxError::invalid_argument(
+                "Only a capture returned as audio can be encoded; this one is streamed or written to a file",
+            ));
+        }
+        let format = output_format.unwrap_or(options.output_format);
+        if encoding == audio_export::ExportFormat::Flac && format != audio_util::WavSampleFormat::Int16 {
+            return Err(VoiceboxError::invalid_argument(format!(
+                "FLAC captures are 16-bit, not {}-bit",
+                format.bits()
+            )));
+        }
+    }
+    let save = audio_capture::SaveAs {
+        target_sample_rate,
+        output_format,
+        normalize,
+    };
+    let audio = end_capture(&app, save).await?;
+    Ok(if options.stream {
+        StoppedCapture::Streamed(app.state::<audio_capture::AudioCaptureState>().stream_manifest())
+    } else if options.spill_path.is_some() {
+        StoppedCapture::File { path: audio }
+    } else if let Some(encoding) = encoding {
+        let data = tokio::task::spawn_blocking(move || audio_export::transcode_base64(&audio, encoding))
+            .await?
+            .map_err(|e| VoiceboxError::capture(format!("Failed to encode the capture: {}", e)))?;
+        StoppedCapture::Encoded {
+            data,
+            mime_type: encoding.mime_type(),
+        }
+    } else {
+        StoppedCapture::Audio(audio)
+    })
+}
+
+/// What stop_system_audio_capture_v2 returns: the recording as base64 WAV
+/// with its rate, length, level and normalization gain.
+#[derive(serde::Serialize)]
+struct CapturedAudio {
+    data_base64: String,
+    #[serde(flatten)]
+    summary: audio_capture::CaptureSummary,
+}
+
+/// Like stop_system_audio_capture, returning the recording along with what
+/// the frontend would otherwise read from its header, and the gain normalize
+/// applied. Not for a streamed capture or one written to a file, which have
+/// no audio to return.
+#[command]
+async fn stop_system_audio_capture_v2(
+    app: tauri::AppHandle,
+    target_sample_rate: Option<u32>,
+    output_format: Option<audio_util::WavSampleFormat>,
+    normalize: Option<audio_capture::NormalizeTarget>,
+) -> Result<CapturedAudio, VoiceboxError> {
+    let state = app.state::<audio_capture::AudioCaptureState>();
+    let options = state.options();
+    // Before stopping, so the capture keeps running
+    if options.stream || options.spill_path.is_some() {
+        return Err(VoiceboxError::invalid_argument(
+            "A streamed capture or one written to a file has no audio to return; use stop_system_audio_capture",
+        ));
+    }
+    let save = audio_capture::SaveAs {
+        target_sample_rate,
+        output_format,
+        normalize,
+    };
+    let data_base64 = end_capture(&app, save).await?;
+    let summary = state
+        .summary()
+        .ok_or_else(|| VoiceboxError::busy("Another capture started before this one's audio was returned"))?;
+    Ok(CapturedAudio { data_base64, summary })
+}
+
+/// Stop the capture and hold its WAV in Rust instead of returning it, for
+/// the frontend to fetch with get_capture_chunk and free with
+/// release_capture. Once the capture has stopped, calling it again returns
+/// the same capture. Not for a streamed capture or one written to a file.
+#[command]
+async fn finalize_capture(
+    app: tauri::AppHandle,
+    target_sample_rate: Option<u32>,
+    output_format: Option<audio_util::WavSampleFormat>,
+    normalize: Option<audio_capture::NormalizeTarget>,
+) -> Result<audio_capture::FinalizedCapture, VoiceboxError> {
+    let state = app.state::<audio_capture::AudioCaptureState>();
+    if !state.is_capturing() {
+        if let Some(finalized) = state.finalized() {
+            return Ok(finalized);
+        }
+    }
+    let options = state.options();
+    if options.stream || options.spill_path.is_some() {
+        return Err(VoiceboxError::invalid_argument(
+            "A streamed capture or one written to a file has no audio to finalize",
+        ));
+    }
+    let session = state.current_session();
+    let save = audio_capture::SaveAs {
+        target_sample_rate,
+        output_format,
+        normalize,
+    };
+    let audio = end_capture(&app, save).await?;
+    let handle = app.clone();
+    tokio::task::spawn_blocking(move || {
+        handle
+            .state::<audio_capture::AudioCaptureState>()
+            .finalize(session, &audio)
+    })
+    .await?
+}
+
+/// Chunk `index` of the capture finalize_capture holds, as base64.
+#[command]
+fn get_capture_chunk(state: State<'_, audio_capture::AudioCaptureState>, index: u32) -> Result<String, VoiceboxError> {
+    state.finalized_chunk(index)
+}
+
+/// Free the capture finalize_capture holds. False when it held none.
+#[command]
+fn release_capture(state: State<'_, audio_capture::AudioCaptureState>) -> bool {
+    state.release_finalized()
+}
+
+/// Write the stopped capture's WAV to `path` from Rust, for a path the
+/// frontend picked with the dialog plugin. Saved as stop did unless
+/// target_sample_rate, output_format or normalize ask otherwise; an
+/// existing file is refused without options.overwrite.
+#[command]
+async fn save_capture_to_file(
+    app: tauri::AppHandle,
+    path: String,
+    options: Option<audio_capture::SaveToFile>,
+    target_sample_rate: Option<u32>,
+    output_format: Option<audio_util::WavSampleFormat>,
+    normalize: Option<audio_capture::NormalizeTarget>,
+) -> Result<audio_capture::SavedCapture, VoiceboxError> {
+    let state = app.state::<audio_capture::AudioCaptureState>();
+    let save = audio_capture::SaveAs {
+        target_sample_rate,
+        output_format,
+        normalize,
+    };
+    let path = std::path::PathBuf::from(path);
+    let saved = audio_capture::save_capture_to_file(&state, path, save, options.unwrap_or_default()).await?;
+    info!("System audio capture saved to {} ({} bytes)", saved.path, saved.bytes);
+    Ok(saved)
+}
+
+/// Split the stopped capture into its utterances, each a base64 WAV saved
+/// as stop was, for picking the cleanest ones to clone from. Silence
+/// quieter than silence_threshold_db and at least min_gap_secs long
+/// separates them; shorter ones than min_segment_secs are merged into a
+/// neighbor and longer ones than max_segment_secs cut. Empty when nothing
+/// was heard.
+#[command]
+async fn segment_capture(
+    app: tauri::AppHandle,
+    min_segment_secs: f64,
+    max_segment_secs: f64,
+    silence_threshold_db: f32,
+    min_gap_secs: f64,
+) -> Result<Vec<audio_capture::CaptureSegment>, VoiceboxError> {
+    let state = app.state::<audio_capture::AudioCaptureState>();
+    let vad = audio_util::VadOptions {
+        min_segment_secs,
+        max_segment_secs,
+        silence_threshold_db,
+        min_gap_secs,
+    };
+    let segments = audio_capture::segment_capture(&state, vad).await?;
+    info!("System audio capture split into {} segments", segments.len());
+    Ok(segments)
+}
+
+/// Stop the capture without building its WAV, for a cancel in the UI, and
+/// emit `capture-cancelled`. Nothing is kept for export_audio. Does nothing
+/// when no capture is running.
+#[command]
+async fn cancel_system_audio_capture(app: tauri::AppHandle) -> Result<(), VoiceboxError> {
+    let state = app.state::<audio_capture::AudioCaptureState>();
+    let session = state.current_session();
+    if audio_capture::cancel_capture(&state).await {
+        info!("System audio capture cancelled");
+        let _ = event_bus::emit(&app, "capture-cancelled", serde_json::json!({ "session": session }));
+    }
+    Ok(())
+}
+
+/// Record system audio and the microphone together, lined up, into one WAV:
+/// mixed to stereo, or as two stereo pairs with the `separate` layout. Fails
+/// if either can't start.
+#[command]
+async fn start_combined_capture(
+    app: tauri::AppHandle,
+    max_duration_secs: u32,
+    layout: Option<combined_capture::CombinedLayout>,
+) -> Result<(), VoiceboxError> {
+    if app.state::<audio_capture::AudioCaptureState>().is_capturing() {
+        return Err(VoiceboxError::busy("A system audio capture is already running"));
+    }
+    // Both backends block until their audio is flowing
+    tokio::task::spawn_blocking(move || {
+        app.state::<combined_capture::CombinedCapture>()
+            .start(max_duration_secs, layout.unwrap_or_default())
+    })
+    .await?
+}
+
+/// Stop the combined capture and return it as base64 WAV.
+#[command]
+async fn stop_combined_capture(app: tauri::AppHandle) -> Result<String, VoiceboxError> {
+    app.state::<combined_capture::CombinedCapture>().stop().await
+}
+
+#[command]
+fn schedule_capture(
+    app: tauri::AppHandle,
+    spec: settings::ScheduleSpec,
+) -> Result<scheduled_capture::ScheduleInfo, VoiceboxError> {
+    scheduled_capture::schedule(&app, spec)
+}
+
+#[command]
+fn list_scheduled_captures(app: tauri::AppHandle) -> Vec<scheduled_capture::ScheduleInfo> {
+    scheduled_capture::list(&app)
+}
+
+#[command]
+fn cancel_scheduled_capture(app: tauri::AppHandle, id: u64) -> Result<(), VoiceboxError> {
+    scheduled_capture::cancel(&app, id)
+}
+
+#[command]
+fn get_notification_prefs(settings: State<'_, SettingsState>) -> settings::NotificationPrefs {
+    settings.get().notifications
+}
+
+#[command]
+fn set_notification_prefs(
+    settings: State<'_, SettingsState>,
+    prefs: settings::NotificationPrefs,
+) -> Result<(), VoiceboxError> {
+    Ok(settings.update(|s| s.notifications = prefs)?)
+}
+
+#[command]
+fn get_close_behavior(settings: State<'_, SettingsState>) -> String {
+    settings.get().close_behavior.as_str().to_string()
+}
+
+#[command]
+fn set_close_behavior(settings: State<'_, SettingsState>, behavior: String) -> Result<(), VoiceboxError> {
+    let behavior = CloseBehavior::parse(&behavior).map_err(VoiceboxError::invalid_argument)?;
+    Ok(settings.update(|s| s.close_behavior = behavior)?)
+}
+
+/// Remember something the user just used. `path_or_id` is an absolute file
+/// path or an id, e.g. a capture session.
+#[command]
+fn add_recent_item(
+    app: tauri::AppHandle,
+    kind: settings::RecentKind,
+    path_or_id: String,
+    label: String,
+) -> Result<(), VoiceboxError> {
+    if path_or_id.is_empty() {
+        return Err(VoiceboxError::invalid_argument("A recent item needs a path or id"));
+    }
+    Ok(mru::add(&app, kind, &path_or_id, &label)?)
+}
+
+/// The newest `limit` items of `kind`, minus files deleted since.
+#[command]
+async fn get_recent_items(
+    app: tauri::AppHandle,
+    kind: settings::RecentKind,
+    limit: usize,
+) -> Result<Vec<settings::RecentItem>, VoiceboxError> {
+    Ok(tokio::task::spawn_blocking(move || mru::get(&app, kind, limit)).await??)
+}
+
+/// Forget the items of `kind`, or every recent item without one.
+#[command]
+fn clear_recent_items(app: tauri::AppHandle, kind: Option<settings::RecentKind>) -> Result<(), VoiceboxError> {
+    Ok(mru::clear(&app, kind)?)
+}
+
+/// Show progress for a frontend-driven operation on the dock/taskbar; None clears it.
+#[command]
+fn set_app_progress(
+    app: tauri::AppHandle,
+    fraction: Option<f32>,
+    state: Option<progress_indicator::ProgressState>,
+) {
+    progress_indicator::set(&app, progress_indicator::APP_SOURCE, fraction, state.unwrap_or_default());
+}
+
+/// Decode, optionally convert, and store an audio file under `<data_dir>/imports/`.
+async fn import_audio(
+    app: &tauri::AppHandle,
+    path: std::path::PathBuf,
+    convert_to: Option<audio_import::ImportTarget>,
+) -> Result<audio_import::ImportedAudio, audio_import::ImportError> {
+    let data_dir = resolve_data_dir(app)?;
+    let max_bytes = app.state::<SettingsState>().get().max_import_mb as u64 * 1024 * 1024;
+    let source = path.clone();
+    let imported = tokio::task::spawn_blocking(move || {
+        audio_import::import_file(&path, &data_dir, convert_to.as_ref(), max_bytes)
+    })
+    .await
+    .map_err(|e| format!("Import task failed: {}", e))??;
+    mru::note(app, settings::RecentKind::Imported, &source.to_string_lossy(), &mru::file_label(&source));
+    Ok(imported)
+}
+
+#[command]
+async fn import_audio_file(
+    app: tauri::AppHandle,
+    path: String,
+    convert_to: Option<audio_import::ImportTarget>,
+) -> Result<audio_import::ImportedAudio, audio_import::ImportError> {
+    import_audio(&app, std::path::PathBuf::from(path), convert_to).await
+}
+
+#[command]
+async fn add_watched_folder(
+    app: tauri::AppHandle,
+    path: String,
+    convert: bool,
+) -> Result<watched_folders::WatchedFolderStatus, VoiceboxError> {
+    let path = std::path::PathBuf::from(path);
+    tokio::task::spawn_blocking(move || watched_folders::add(&app, path, convert)).await?
+}
+
+#[command]
+fn remove_watched_folder(app: tauri::AppHandle, path: String) -> Result<(), VoiceboxError> {
+    watched_folders::remove(&app, std::path::Path::new(&path))
+}
+
+#[command]
+fn list_watched_folders(app: tauri::AppHandle) -> Vec<watched_folders::WatchedFolderStatus> {
+    watched_folders::list(&app)
+}
+
+#[command]
+fn set_max_import_size(settings: State<'_, SettingsState>, megabytes: u32) -> Result<(), VoiceboxError> {
+    if megabytes == 0 {
+        return Err(VoiceboxError::invalid_argument("Import size limit must be at least 1 MB"));
+    }
+    Ok(settings.update(|s| s.max_import_mb = megabytes)?)
+}
+
+/// Load the audio behind an export source.
+async fn export_source_bytes(
+    app: &tauri::AppHandle,
+    source: audio_export::ExportSource,
+) -> Result<Vec<u8>, audio_export::ExportError> {
+    use audio_export::{ExportError, ExportSource};
+    use base64::Engine;
+
+    let decode_base64 = |audio: &str| {
+        base64::engine::general_purpose::STANDARD
+            .decode(audio)
+            .map_err(|e| ExportError::InvalidSource {
+                message: format!("Audio is not valid base64: {}", e),
+            })
+    };
+    match source {
+        ExportSource::CaptureSession(session) => {
+            let audio = app
+                .state::<audio_capture::AudioCaptureState>()
+                .recording(session)
+                .ok_or_else(|| ExportError::SourceNotFound {
+                    message: format!("Capture {} is no longer available", session),
+                })?;
+            decode_base64(&audio)
+        }
+        ExportSource::Recording(id) => {
+            let state = app.state::<ServerState>();
+            let url = state.info.lock().unwrap().as_ref().map(|info| info.url.clone());
+            let auth_token = state.auth_token.lock().unwrap().clone();
+            let url = url.ok_or_else(|| "The server is not running".to_string())?;
+            process_manager::fetch_generation_audio(&url, auth_token.as_deref(), &id)
+                .await?
+                .ok_or_else(|| ExportError::SourceNotFound {
+                    message: format!("Recording {} was not found", id),
+                })
+        }
+        ExportSource::Bytes(audio) => decode_base64(&audio),
+        ExportSource::File(path) => tokio::fs::read(&path).await.map_err(|e| ExportError::SourceNotFound {
+            message: format!("Cannot read {}: {}", path.display(), e),
+        }),
+    }
+}
+
+/// Save audio through the native save dialog, transcoding to `format` if
+/// needed. With `embed_metadata`, a capture's metadata goes into the WAV.
+#[command]
+async fn export_audio(
+    app: tauri::AppHandle,
+    settings: State<'_, SettingsState>,
+    source: audio_export::ExportSource,
+    suggested_name: String,
+    format: audio_export::ExportFormat,
+    embed_metadata: Option<recording_metadata::MetadataChunk>,
+) -> Result<audio_export::ExportOutcome, audio_export::ExportError> {
+    use audio_export::ExportError;
+    use tauri_plugin_dialog::DialogExt;
+
+    // Only captures carry metadata, and only WAV has a place for it
+    let embed = match (embed_metadata, &source) {
+        (None, _) => None,
+        (Some(_), _) if format != audio_export::ExportFormat::Wav => {
+            return Err(ExportError::InvalidSource {
+                message: "Metadata can only be embedded in WAV exports".to_string(),
+            });
+        }
+        (Some(chunk), audio_export::ExportSource::CaptureSession(session)) => Some((chunk, *session)),
+        (Some(_), _) => {
+            return Err(ExportError::InvalidSource {
+                message: "Only system audio captures have metadata to embed".to_string(),
+            });
+        }
+    };
+
+    // Load first so a missing source fails before the user picks a path
+    let data = export_source_bytes(&app, source).await?;
+    let embed = match embed {
+        Some((chunk, session)) => {
+            let metadata = app
+                .state::<audio_capture::AudioCaptureState>()
+                .recording_metadata(session)
+                .ok_or_else(|| ExportError::SourceNotFound {
+                    message: format!("Capture {} is no longer available", session),
+                })?;
+            Some((chunk, metadata))
+        }
+        None => None,
+    };
+
+    let mut dialog = app
+        .dialog()
+        .file()
+        .set_title("Export Audio")
+        .set_file_name(audio_export::file_name_for(&suggested_name, format))
+        .add_filter(format.filter_name(), &[format.extension()]);
+    if let Some(dir) = settings.get().last_export_dir.filter(|dir| dir.is_dir()) {
+        dialog = dialog.set_directory(dir);
+    }
+    if let Some(window) = app.get_webview_window("main") {
+        dialog = dialog.set_parent(&window);
+    }
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    dialog.save_file(move |path| {
+        let _ = tx.send(path);
+    });
+    let Some(path) = rx.await.ok().flatten() else {
+        return Ok(audio_export::ExportOutcome::Cancelled);
+    };
+    let path = path
+        .into_path()
+        .map_err(|e| format!("Unsupported save location: {}", e))?;
+
+    let dest = path.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut encoded = audio_export::transcode(data, format)?;
+        if let Some((chunk, metadata)) = embed {
+            encoded = recording_metadata::embed(&encoded, &metadata, chunk)?;
+        }
+        audio_export::write_export(&dest, &encoded)
+    })
+    .await
+    .map_err(|e| format!("Export task failed: {}", e))??;
+
+    if let Some(dir) = path.parent() {
+        let dir = dir.to_path_buf();
+        if let Err(e) = settings.update(|s| s.last_export_dir = Some(dir)) {
+            warn!("Failed to remember export directory: {}", e);
+        }
+    }
+    info!("Exported audio to {}", path.display());
+    mru::note(&app, settings::RecentKind::Exported, &path.to_string_lossy(), &mru::file_label(&path));
+    Ok(audio_export::ExportOutcome::Saved { path })
+}
+
+/// Run `operations` over a recording or file, in order, and save the result
+/// as a new library entry or at a chosen path. Emits `audio-process-progress`
+/// as each step starts; nothing is written unless every step succeeds.
+#[command]
+async fn process_audio(
+    app: tauri::AppHandle,
+    source: audio_export::ExportSource,
+    operations: Vec<audio_process::AudioOp>,
+    output: audio_process::ProcessOutput,
+) -> Result<audio_process::ProcessedAudio, audio_export::ExportError> {
+    // Bad parameters fail before the source is loaded
+    audio_process::validate(&operations)?;
+    let extension = match &source {
+        audio_export::ExportSource::File(path) => path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase()),
+        _ => None,
+    };
+    let data = export_source_bytes(&app, source).await?;
+    let data_dir = resolve_data_dir(&app)?;
+
+    let handle = app.clone();
+    let processed = tokio::task::spawn_blocking(move || {
+        let mut progress = |progress: audio_process::ProcessProgress| {
+            if let Err(e) = event_bus::emit(&handle, "audio-process-progress", progress) {
+                warn!("Failed to emit processing progress: {}", e);
+            }
+        };
+        let processed = audio_process::process(&data, extension.as_deref(), &operations, &mut progress)?;
+        audio_process::save(processed, &output, &data_dir)
+    })
+    .await
+    .map_err(|e| format!("Processing task failed: {}", e))??;
+
+    mru::note(
+        &app,
+        settings::RecentKind::Exported,
+        &processed.path.to_string_lossy(),
+        &mru::file_label(&processed.path),
+    );
+    Ok(processed)
+}
+
+/// Where a kept capture came from, by the session id from `capture-stopped`.
+#[command]
+fn get_recording_metadata(
+    capture: State<'_, audio_capture::AudioCaptureState>,
+    id: u64,
+) -> Result<recording_metadata::RecordingMetadata, VoiceboxError> {
+    capture
+        .recording_metadata(id)
+        .ok_or_else(|| VoiceboxError::not_found(format!("Capture {} is no longer available", id)))
+}
+
+/// Save a WAV into the recordings library under the quota setting, emitting
+/// `recordings-pruned` for anything pruned to make room. Returns the entry
+/// and its file. Blocking.
+fn save_to_library(
+    app: &tauri::AppHandle,
+    wav: &[u8],
+    label: Option<String>,
+    metadata: recording_metadata::RecordingMetadata,
+) -> Result<(recordings::RecordingEntry, std::path::PathBuf), VoiceboxError> {
+    let data_dir = resolve_data_dir(app)?;
+    let quota = app.state::<SettingsState>().get().recordings_quota;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let library = app.state::<recordings::RecordingsState>();
+    let library = library.open(&data_dir);
+    let (entry, pruned) = library.add(wav, label, metadata, &quota, now)?;
+    let path = library.path(&entry);
+    drop(library);
+    info!("Saved recording {} to the library", entry.id);
+    if !pruned.removed_ids.is_empty() {
+        if let Err(e) = event_bus::emit(app, "recordings-pruned", &pruned) {
+            warn!("Failed to emit recordings-pruned event: {}", e);
+        }
+    }
+    Ok((entry, path))
+}
+
+/// Save a kept capture, by its session id, into the recordings library.
+#[command]
+async fn save_capture_to_library(
+    app: tauri::AppHandle,
+    session: u64,
+    label: Option<String>,
+) -> Result<recordings::RecordingEntry, VoiceboxError> {
+    let capture = app.state::<audio_capture::AudioCaptureState>();
+    let missing = || VoiceboxError::not_found(format!("Capture {} is no longer available", session));
+    let audio = capture.recording(session).ok_or_else(missing)?;
+    let metadata = capture.recording_metadata(session).ok_or_else(missing)?;
+    tokio::task::spawn_blocking(move || {
+        use base64::Engine;
+        let wav = base64::engine::general_purpose::STANDARD
+            .decode(audio)
+            .map_err(|e| VoiceboxError::internal(format!("The capture is not valid base64: {}", e)))?;
+        save_to_library(&app, &wav, label, metadata).map(|(entry, _)| entry)
+    })
+    .await?
+}
+
+/// Split library recording `id` at `at_ms`, saving each segment as a
+/// recording of its own. The original stays unless split_keep_original is
+/// off. Returns the segments' entries, in order.
+#[command]
+async fn split_recording(
+    app: tauri::AppHandle,
+    id: String,
+    at_ms: Vec<u64>,
+) -> Result<Vec<recordings::RecordingEntry>, VoiceboxError> {
+    if at_ms.is_empty() {
+        return Err(VoiceboxError::invalid_argument("at_ms must name at least one split point"));
+    }
+    let data_dir = resolve_data_dir(&app)?;
+    tokio::task::spawn_blocking(move || {
+        let (entry, path) = {
+            let library = app.state::<recordings::RecordingsState>();
+            let library = library.open(&data_dir);
+            let entry = library
+                .list()?
+                .into_iter()
+                .find(|entry| entry.id == id)
+                .ok_or_else(|| VoiceboxError::not_found(format!("There is no recording {}", id)))?;
+            let path = library.path(&entry);
+            (entry, path)
+        };
+        let sample_rate = hound::WavReader::open(&path)
+            .map_err(|e| VoiceboxError::invalid_argument(format!("Recording {} is not a readable WAV: {}", id, e)))?
+            .spec()
+            .sample_rate;
+        let points: Vec<audio_split::SplitPoint> = at_ms
+            .iter()
+            .map(|ms| audio_split::SplitPoint {
+                at_frames: ms * sample_rate as u64 / 1000,
+                label: None,
+            })
+            .collect();
+        let file = std::io::BufReader::new(std::fs::File::open(&path)?);
+        let base_label = entry.label.clone().unwrap_or_else(|| entry.id.clone());
+        let entries = save_segments(&app, file, &points, sample_rate, &base_label, &entry.metadata)?;
+        if !app.state::<SettingsState>().get().split_keep_original {
+            app.state::<recordings::RecordingsState>().open(&data_dir).remove(&entry.id)?;
+        }
+        info!("Split recording {} into {} segment(s)", entry.id, entries.len());
+        Ok(entries)
+    })
+    .await?
+}
+
+/// The library's recordings, oldest first.
+#[command]
+fn list_recordings(
+    app: tauri::AppHandle,
+    library: State<'_, recordings::RecordingsState>,
+) -> Result<Vec<recordings::RecordingEntry>, VoiceboxError> {
+    library.open(&resolve_data_dir(&app)?).list()
+}
+
+/// Pinned recordings are never pruned.
+#[command]
+fn pin_recording(
+    app: tauri::AppHandle,
+    library: State<'_, recordings::RecordingsState>,
+    id: String,
+    pinned: bool,
+) -> Result<recordings::RecordingEntry, VoiceboxError> {
+    library.open(&resolve_data_dir(&app)?).pin(&id, pinned)
+}
+
+/// Library recordings that sound like `id`, most alike first. `threshold`
+/// is 0 to 1 and defaults to fingerprint::DEFAULT_THRESHOLD.
+#[command]
+async fn find_similar_recordings(
+    app: tauri::AppHandle,
+    id: String,
+    threshold: Option<f32>,
+) -> Result<Vec<fingerprint::SimilarRecording>, VoiceboxError> {
+    let threshold = threshold.unwrap_or(fingerprint::DEFAULT_THRESHOLD);
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(VoiceboxError::invalid_argument("threshold must be between 0 and 1"));
+    }
+    let data_dir = resolve_data_dir(&app)?;
+    tokio::task::spawn_blocking(move || {
+        app.state::<recordings::RecordingsState>()
+            .open(&data_dir)
+            .find_similar(&id, threshold)
+    })
+    .await?
+}
+
+#[command]
+fn get_recordings_usage(
+    app: tauri::AppHandle,
+    library: State<'_, recordings::RecordingsState>,
+    settings: State<'_, SettingsState>,
+) -> Result<recordings::RecordingsUsage, VoiceboxError> {
+    library.open(&resolve_data_dir(&app)?).usage(&settings.get().recordings_quota)
+}
+
+/// Put audio on the clipboard as a WAV file reference, for pasting into chat apps and DAWs.
+#[command]
+async fn copy_audio_to_clipboard(
+    app: tauri::AppHandle,
+    source: audio_export::ExportSource,
+) -> Result<clipboard::ClipboardRepresentation, audio_export::ExportError> {
+    let data = export_source_bytes(&app, source).await?;
+    tokio::task::spawn_blocking(move || {
+        let wav = audio_export::transcode(data, audio_export::ExportFormat::Wav)?;
+        clipboard::copy_wav(&wav).map_err(audio_export::ExportError::from)
+    })
+    .await
+    .map_err(|e| format!("Clipboard task failed: {}", e))?
+}
+
+#[command]
+async fn open_mini_recorder(app: tauri::AppHandle) -> Result<(), VoiceboxError> {
+    Ok(mini_recorder::open(&app)?)
+}
+
+#[command]
+async fn close_mini_recorder(app: tauri::AppHandle) -> Result<(), VoiceboxError> {
+    mini_recorder::close(&app);
+    Ok(())
+}
+
+/// Check the given release channel (or the saved one) for a newer version.
+/// Passing a channel also makes it the saved one.
+#[command]
+async fn check_for_updates(
+    app: tauri::AppHandle,
+    settings: State<'_, SettingsState>,
+    channel: Option<String>,
+) -> Result<updates::UpdateInfo, updates::UpdateError> {
+    let channel = match channel {
+        Some(channel) => {
+            let channel = UpdateChannel::parse(&channel)?;
+            if settings.get().update_channel != channel {
+                settings.update(|s| s.update_channel = channel)?;
+                let _ = event_bus::emit(&app, "setting-changed", serde_json::json!({ "key": "update_channel" }));
+            }
+            channel
+        }
+        None => settings.get().update_channel,
+    };
+    info!("Checking the {} channel for updates", channel.as_str());
+    let info = updates::check(&app, channel).await?;
+    match &info.version {
+        Some(version) => info!("Update available: {}", version),
+        None => info!("No update available"),
+    }
+    Ok(info)
+}
+
+/// Download the update found by the last check, stop the server unless it is
+/// set to outlive the app, then install and restart.
+#[command]
+async fn install_update(
+    app: tauri::AppHandle,
+    state: State<'_, ServerState>,
+    updates: State<'_, updates::UpdateState>,
+) -> Result<(), updates::UpdateError> {
+    let update = updates.pending().ok_or(updates::UpdateError::NoPendingUpdate)?;
+    info!("Downloading update {}", update.version);
+    let bytes = updates::download(&app, &update).await?;
+
+    // The installer replaces the sidecar binary, which fails while it runs on Windows
+    if state.close_action() == process_manager::CloseAction::StopServer {
+        let stop = stop_server(app.clone(), state.clone());
+        match tokio::time::timeout(stop_grace(&app) + process_manager::STOP_KILL_MARGIN, stop).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => warn!("Failed to stop server before update: {}", e),
+            Err(_) => warn!("Server did not stop in time, installing anyway"),
+        }
+    }
+
+    tokio::task::spawn_blocking(move || update.install(bytes))
+        .await
+        .map_err(|e| format!("Install task failed: {}", e))?
+        .map_err(|e| updates::UpdateError::InstallFailed { message: e.to_string() })?;
+
+    info!("Update installed, restarting");
+    app.restart()
+}
+
+/// get_capture_capabilities' `system_audio` alone, for callers from before
+/// it.
+#[command]
+async fn is_system_audio_supported(app: tauri::AppHandle) -> Result<bool, VoiceboxError> {
+    Ok(get_capture_capabilities(app).await?.system_audio)
+}
+
+/// Whether system audio can be captured here and, if not, why: the OS
+/// version, a permission or a missing output device. Also whether a
+/// capture can be pointed at a device or limited to one application.
+#[command]
+async fn get_capture_capabilities(app: tauri::AppHandle) -> Result<audio_capture::CaptureCapabilities, VoiceboxError> {
+    Ok(tokio::task::spawn_blocking(move || app.state::<audio_capture::AudioCaptureState>().capabilities()).await?)
+}
+
+#[command]
+fn list_audio_output_devices(
+    state: State<'_, audio_output::AudioOutputState>,
+) -> Result<Vec<audio_output::AudioOutputDevice>, VoiceboxError> {
+    state.list_output_devices()
+}
+
+/// How long `device_id` takes to play what it is given, in milliseconds, as
+/// its stream last reported it; null until it has played something.
+#[command]
+fn get_device_latency(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+) -> Result<Option<f64>, VoiceboxError> {
+    Ok(state.device_latency(&device_id)?.map(|latency| latency.as_secs_f64() * 1000.0))
+}
+
+/// Play a sine on `device_id` alone for `duration_secs`, 440 Hz unless
+/// `frequency_hz`, to check it's the device meant, e.g. a virtual cable
+/// before a call. Returns once it has played out, as `completed`, or
+/// `cancelled` if stopped first.
+#[command]
+async fn play_test_tone(
+    app: tauri::AppHandle,
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+    duration_secs: f32,
+    frequency_hz: Option<f32>,
+) -> Result<audio_output::PlaybackEnd, VoiceboxError> {
+    let _wake_lock = app.state::<power::PowerState>().acquire("Test tone");
+    state
+        .play_test_tone(&device_id, duration_secs, frequency_hz)
+        .await
+        .inspect_err(|e| emit_playback_device_error(&app, e))
+}
+
+/// What play_audio_to_devices returns.
+#[derive(serde::Serialize)]
+struct PlaybackStarted {
+    /// For move_playback.
+    playback_id: u64,
+    /// How the clip got onto each device, including whether it joined a
+    /// stream already open there.
+    devices: Vec<audio_output::DeviceResult>,
+    /// With `wait`, whether the clip played out or was stopped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ended: Option<audio_output::PlaybackEnd>,
+    #[serde(flatten)]
+    conflict: audio_conflict::ConflictCheck,
+}
+
+/// Payload of the `playback-degraded` event.
+#[derive(Clone, serde::Serialize)]
+struct PlaybackDegraded {
+    device_id: String,
+    message: String,
+}
+
+/// Tell the UI a device took the clip on its hands-free profile.
+fn emit_playback_degraded(app: &tauri::AppHandle, device_id: &str) {
+    let degraded = PlaybackDegraded {
+        device_id: device_id.to_string(),
+        message: "This Bluetooth device switched to its hands-free profile, so playback sounds muffled. \
+                  Close apps using its microphone to get full quality back."
+            .to_string(),
+    };
+    if let Err(e) = event_bus::emit(app, "playback-degraded", degraded) {
+        warn!("Failed to emit playback-degraded event: {}", e);
+    }
+}
+
+/// Emit `playback-device-error` with the audio environment, for a playback
+/// a device failed.
+fn emit_playback_device_error(app: &tauri::AppHandle, e: &VoiceboxError) {
+    if !matches!(e, VoiceboxError::Playback { .. }) {
+        return;
+    }
+    let payload = serde_json::json!({ "error": e.message(), "code": e.code(), "environment": audio_environment(app) });
+    if let Err(e) = event_bus::emit(app, "playback-device-error", payload) {
+        warn!("Failed to emit playback-device-error event: {}", e);
+    }
+}
+
+/// How often a playback reports where it has got to, as `playback-progress`.
+const PLAYBACK_PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Play a clip on `device_ids`, which may include `default`, as `request`
+/// asks, all of whose fields are optional. A capture running that would
+/// record it is reported in `conflict_warning`, or refuses the playback
+/// with `block_on_conflict`. Clips started while a device is opening wait
+/// for it, then share its stream. `volumes`, 0 to 2 and one per device id,
+/// sets how loud each device plays it. It reports `playback-progress` as it
+/// plays, `playback-level` with the peak and RMS each device was sent, and
+/// `playback-finished` with how it ended. With `wait`, it returns once the
+/// clip has played out or stop_audio_playback stopped it, `ended` telling
+/// which.
+/// `loop_playback` plays it over and over without a gap until stopped, or
+/// `repeat_count` times; its progress is then into the time through it's
+/// on, with an `iteration` count. A clip looped until stopped can't be
+/// waited for. The devices start the clip together once all have opened;
+/// one that fails to open is left out, its error in `devices`, unless
+/// `strict`, when the playback fails. It ramps up over its first
+/// `fade_in_ms` and down over its last `fade_out_ms`, 5 ms each unless
+/// given, so a clip that doesn't start or end at a zero crossing doesn't pop.
+#[command]
+async fn play_audio_to_devices(
+    app: tauri::AppHandle,
+    state: State<'_, audio_output::AudioOutputState>,
+    audio_data: Vec<u8>,
+    device_ids: Vec<String>,
+    request: audio_output::PlaybackRequest,
+) -> Result<PlaybackStarted, VoiceboxError> {
+    metrics::METRICS.ipc_payload_bytes.record(audio_data.len() as u64);
+    let looped = request.looped();
+    if looped && request.repeat_count.is_none() && request.wait {
+        return Err(VoiceboxError::invalid_argument(
+            "A clip looped until stopped never finishes, so it can't be waited for",
+        ));
+    }
+    let conflict_warning = check_playback_conflict(&app, &device_ids, request.block_on_conflict).await?;
+    let wake_lock = app.state::<power::PowerState>().acquire("Audio playback");
+    let options = request.options();
+    let (volumes, title) = (request.volumes, request.title);
+    let playback = if looped {
+        state
+            .loop_audio_to_devices(audio_data, device_ids, volumes, options, request.repeat_count, title)
+            .await
+    } else {
+        state.play_audio_to_devices(audio_data, device_ids, volumes, options, title).await
+    }
+    .inspect_err(|e| emit_playback_device_error(&app, e))?;
+    for error in playback.device_results().iter().filter_map(|result| result.error.as_deref()) {
+        emit_playback_device_error(&app, &VoiceboxError::playback(error));
+    }
+    follow_playback(app, playback, wake_lock, conflict_warning, request.wait).await
+}
+
+/// Play an audio file in the data dir on `device_ids`, as
+/// play_audio_to_devices plays a clip, read and decoded from disk as it
+/// plays rather than sent over IPC. It plays once with the default fades,
+/// and fails if any device does. A path outside the data dir, or one that
+/// is missing or unreadable, fails before any device opens.
+#[command]
+async fn play_audio_file_to_devices(
+    app: tauri::AppHandle,
+    state: State<'_, audio_output::AudioOutputState>,
+    path: String,
+    device_ids: Vec<String>,
+    request: audio_output::PlaybackRequest,
+) -> Result<PlaybackStarted, VoiceboxError> {
+    request.check_plays_once("A file")?;
+    let data_dir = resolve_data_dir(&app)?;
+    // A migrated data dir lives outside the app data dir, so both are allowed
+    let roots: Vec<std::path::PathBuf> = paths::app_data_dir(&app)
+        .into_iter()
+        .chain(std::iter::once(data_dir.clone()))
+        .collect();
+    let target = reveal::RevealTarget::Custom(std::path::PathBuf::from(&path));
+    let path = reveal::resolve(&target, &data_dir, &roots).map_err(|e| match e {
+        reveal::RevealError::NotFound { .. } => VoiceboxError::not_found(e),
+        reveal::RevealError::NotAllowed { .. } => VoiceboxError::invalid_argument(e),
+        _ => VoiceboxError::internal(e),
+    })?;
+    let conflict_warning = check_playback_conflict(&app, &device_ids, request.block_on_conflict).await?;
+    let wake_lock = app.state::<power::PowerState>().acquire("Audio playback");
+    let playback = state
+        .play_file_to_devices(&path, device_ids, request.volumes, request.title)
+        .await
+        .inspect_err(|e| emit_playback_device_error(&app, e))?;
+    follow_playback(app, playback, wake_lock, conflict_warning, request.wait).await
+}
+
+/// Start playing audio that arrives a chunk at a time, such as speech as
+/// the server synthesizes it, on `device_ids`, with `request` as
+/// play_audio_file_to_devices takes it. Chunks of 16-bit PCM at
+/// `sample_rate` and `channels` go in with push_playback_chunk, under the
+/// returned playback_id, until end_playback_stream. Running out before the
+/// next chunk plays silence and emits `playback-underrun`.
+#[command]
+async fn begin_playback_stream(
+    app: tauri::AppHandle,
+    state: State<'_, audio_output::AudioOutputState>,
+    device_ids: Vec<String>,
+    sample_rate: u32,
+    channels: u16,
+    request: audio_output::PlaybackRequest,
+) -> Result<PlaybackStarted, VoiceboxError> {
+    request.check_plays_once("A playback stream")?;
+    let conflict_warning = check_playback_conflict(&app, &device_ids, request.block_on_conflict).await?;
+    let wake_lock = app.state::<power::PowerState>().acquire("Audio playback");
+    let playback = state
+        .begin_playback_stream(device_ids, sample_rate, channels, request.volumes, request.title)
+        .await
+        .inspect_err(|e| emit_playback_device_error(&app, e))?;
+    follow_playback(app, playback, wake_lock, conflict_warning, request.wait).await
+}
+
+/// Add base64 16-bit little-endian PCM to the end of playback stream
+/// `stream_id`. Fails once the stream has been ended or stopped.
+#[command]
+fn push_playback_chunk(
+    state: State<'_, audio_output::AudioOutputState>,
+    stream_id: u64,
+    pcm_base64: String,
+) -> Result<(), VoiceboxError> {
+    metrics::METRICS.ipc_payload_bytes.record(pcm_base64.len() as u64);
+    state.push_playback_chunk(stream_id, &pcm_base64)
+}
+
+/// No more chunks for playback stream `stream_id`: it plays what it has
+/// and then finishes.
+#[command]
+fn end_playback_stream(
+    state: State<'_, audio_output::AudioOutputState>,
+    stream_id: u64,
+) -> Result<(), VoiceboxError> {
+    state.end_playback_stream(stream_id)
+}
+
+/// What queue_audio_for_playback returns.
+#[derive(serde::Serialize)]
+struct QueuedAudio {
+    /// The clip's place in the queue, as `playback-item-started` gives it.
+    index: usize,
+    #[serde(flatten)]
+    playback: PlaybackStarted,
+}
+
+/// Queue a clip to play on `device_ids` after those already queued, with
+/// `gap_ms` of silence before it (300 without), the devices staying open in
+/// between. With nothing queued it starts right away. `playback-item-started`
+/// tells when each clip begins; stop_audio_playback drops the rest.
+#[command]
+async fn queue_audio_for_playback(
+    app: tauri::AppHandle,
+    state: State<'_, audio_output::AudioOutputState>,
+    audio_data: Vec<u8>,
+    device_ids: Vec<String>,
+    gap_ms: Option<u64>,
+    block_on_conflict: Option<bool>,
+) -> Result<QueuedAudio, VoiceboxError> {
+    metrics::METRICS.ipc_payload_bytes.record(audio_data.len() as u64);
+    let conflict_warning = check_playback_conflict(&app, &device_ids, block_on_conflict.unwrap_or(false)).await?;
+    let queued = state
+        .queue_audio_for_playback(audio_data, device_ids, gap_ms.map(std::time::Duration::from_millis))
+        .await
+        .inspect_err(|e| emit_playback_device_error(&app, e))?;
+    let playback = if queued.started {
+        let wake_lock = app.state::<power::PowerState>().acquire("Audio playback");
+        follow_playback(app, queued.playback, wake_lock, conflict_warning, false).await?
+    } else {
+        PlaybackStarted {
+            playback_id: queued.playback.id(),
+            devices: queued.playback.device_results().to_vec(),
+            ended: None,
+            conflict: audio_conflict::ConflictCheck { conflict_warning },
+        }
+    };
+    Ok(QueuedAudio {
+        index: queued.index,
+        playback,
+    })
+}
+
+/// Drop the queued clips after the one playing, returning how many.
+#[command]
+async fn clear_playback_queue(state: State<'_, audio_output::AudioOutputState>) -> Result<usize, VoiceboxError> {
+    Ok(state.clear_playback_queue().await)
+}
+
+/// A capture running that would record playback to `device_ids`, as a
+/// warning, or refusing the playback with `block_on_conflict`.
+async fn check_playback_conflict(
+    app: &tauri::AppHandle,
+    device_ids: &[String],
+    block_on_conflict: bool,
+) -> Result<Option<audio_conflict::AudioConflict>, VoiceboxError> {
+    let capture = app.state::<audio_capture::AudioCaptureState>();
+    if !capture.is_capturing() {
+        return Ok(None);
+    }
+    let target = capture_target(app, capture.options().device_id).await?;
+    check_audio_conflict(app, capture.scope(), target.as_ref(), device_ids, block_on_conflict)
+}
+
+/// Report a playback that started as it goes, with `playback-progress`,
+/// `playback-level` for each of its devices and `playback-finished`,
+/// holding `wake_lock` until it ends. With `wait`, returns once it has.
+async fn follow_playback(
+    app: tauri::AppHandle,
+    playback: audio_output::PlaybackHandle,
+    wake_lock: power::WakeLock,
+    conflict_warning: Option<audio_conflict::AudioConflict>,
+    wait: bool,
+) -> Result<PlaybackStarted, VoiceboxError> {
+    let devices = playback.device_results().to_vec();
+    media_controls::sync(&app);
+    for device in devices.iter().filter(|device| device.degraded_profile) {
+        emit_playback_degraded(&app, &device.device_id);
+    }
+    let playback_id = playback.id();
+    let waiting = wait.then(|| playback.clone());
+
+    // Held until the clip has played out or playback is stopped
+    tokio::spawn(async move {
+        let _wake_lock = wake_lock;
+        let finished = playback.finished();
+        tokio::pin!(finished);
+        let mut underruns = 0;
+        let mut item = None;
+        let end = loop {
+            // Each clip of a queue as it begins, the first right away
+            if !playback.is_finished() && playback.queue_item() > item {
+                item = playback.queue_item();
+                let started = serde_json::json!({
+                    "playback_id": playback.id(),
+                    "index": item,
+                });
+                if let Err(e) = event_bus::emit(&app, "playback-item-started", started) {
+                    warn!("Failed to emit playback-item-started event: {}", e);
+                }
+            }
+            tokio::select! {
+                end = &mut finished => break end,
+                _ = tokio::time::sleep(PLAYBACK_PROGRESS_INTERVAL) => {}
+            }
+            // Stopped or replaced, and only fading out
+            if playback.is_finished() {
+                continue;
+            }
+            // Moves since it started are on the current playback's copy
+            let device_ids = app
+                .state::<audio_output::AudioOutputState>()
+                .current_playback()
+                .filter(|current| current.same_as(&playback))
+                .map_or_else(|| playback.device_ids().to_vec(), |current| current.device_ids().to_vec());
+            let progress = serde_json::json!({
+                "playback_id": playback.id(),
+                "position_secs": playback.position().as_secs_f64(),
+                "duration_secs": playback.duration().as_secs_f64(),
+                "iteration": playback.iteration(),
+                "device_ids": device_ids,
+                "paused": playback.is_paused(),
+            });
+            if let Err(e) = event_bus::emit(&app, "playback-progress", progress) {
+                warn!("Failed to emit playback-progress event: {}", e);
+            }
+            // What each device was sent since the last, for a meter showing the signal gets there
+            for device_id in &device_ids {
+                let Some((rms, peak)) = playback.take_level(device_id) else {
+                    continue;
+                };
+                let level = serde_json::json!({
+                    "playback_id": playback.id(),
+                    "device_id": device_id,
+                    "peak": peak,
+                    "rms": rms,
+                    "position_secs": playback.position().as_secs_f64(),
+                });
+                if let Err(e) = event_bus::emit(&app, "playback-level", level) {
+                    warn!("Failed to emit playback-level event: {}", e);
+                }
+            }
+            if playback.underruns() > underruns {
+                underruns = playback.underruns();
+                let underrun = serde_json::json!({
+                    "playback_id": playback.id(),
+                    "underruns": underruns,
+                    "position_secs": playback.position().as_secs_f64(),
+                });
+                if let Err(e) = event_bus::emit(&app, "playback-underrun", underrun) {
+                    warn!("Failed to emit playback-underrun event: {}", e);
+                }
+            }
+            media_controls::sync(&app);
+        };
+        // Releases the media session once nothing else is playing
+        media_controls::sync(&app);
+        let reason = match end {
+            audio_output::PlaybackEnd::Completed => "completed",
+            audio_output::PlaybackEnd::Cancelled => "stopped",
+            audio_output::PlaybackEnd::Failed => "error",
+        };
+        let payload = serde_json::json!({
+            "playback_id": playback.id(),
+            "reason": reason,
+            "position_secs": playback.position().as_secs_f64(),
+            "lost_device_ids": playback.lost_devices(),
+        });
+        if let Err(e) = event_bus::emit(&app, "playback-finished", payload) {
+            warn!("Failed to emit playback-finished event: {}", e);
+        }
+    });
+    let ended = match waiting {
+        Some(playback) => Some(playback.finished().await),
+        None => None,
+    };
+    Ok(PlaybackStarted {
+        playback_id,
+        devices,
+        ended,
+        conflict: audio_conflict::ConflictCheck { conflict_warning },
+    })
+}
+
+/// Move playback `playback_id` from one of its devices to another without a
+/// gap. If `to_device_id` can't be opened, it keeps playing where it was.
+#[command]
+async fn move_playback(
+    app: tauri::AppHandle,
+    state: State<'_, audio_output::AudioOutputState>,
+    playback_id: u64,
+    from_device_id: String,
+    to_device_id: String,
+) -> Result<audio_output::DeviceResult, VoiceboxError> {
+    let result = state
+        .move_playback(playback_id, &from_device_id, &to_device_id)
+        .await
+        .inspect_err(|e| emit_playback_device_error(&app, e))?;
+    if result.degraded_profile {
+        emit_playback_degraded(&app, &result.device_id);
+    }
+    Ok(result)
+}
+
+/// Play a test tone on `device_id` (the default output without one) and
+/// listen for it through system audio capture.
+#[command]
+async fn run_audio_selftest(
+    app: tauri::AppHandle,
+    device_id: Option<String>,
+) -> Result<audio_selftest::SelfTestReport, VoiceboxError> {
+    let output = app.state::<audio_output::AudioOutputState>();
+    let device_id = match device_id {
+        Some(id) => id,
+        None => output
+            .list_output_devices()?
+            .into_iter()
+            .find(|device| device.is_default)
+            .map(|device| device.id)
+            .ok_or_else(|| VoiceboxError::not_found("There is no default output device"))?,
+    };
+    let capture = app.state::<audio_capture::AudioCaptureState>();
+    let mut report = audio_selftest::run(&output, &capture, &device_id).await?;
+
+    // Name the missing permission when that's why loopback failed
+    if let Some(e) = report.capture_error.take_if(|e| !matches!(e, VoiceboxError::Busy { .. })) {
+        let explain_app = app.clone();
+        let explained = tokio::task::spawn_blocking(move || {
+            permissions::explain_failure(&explain_app, permissions::PermissionKind::ScreenRecording, e)
+        });
+        report.capture_error = Some(explained.await?);
+    }
+    Ok(report)
+}
+
+/// Play a chirp on `output_device_id` while capturing `capture_source`, and
+/// time its arrival in the capture.
+#[command]
+async fn measure_route_latency(
+    app: tauri::AppHandle,
+    output_device_id: String,
+    capture_source: audio_route::CaptureSourceSpec,
+) -> Result<audio_latency::LatencyMeasurement, VoiceboxError> {
+    let output = app.state::<audio_output::AudioOutputState>();
+    let capture = app.state::<audio_capture::AudioCaptureState>();
+    let measured = match capture_source {
+        audio_route::CaptureSourceSpec::System => audio_latency::measure(&output, &capture, &output_device_id).await,
+    };
+    match measured {
+        // Name the missing permission when that's why the capture failed
+        Err(e) if !matches!(e, VoiceboxError::Busy { .. } | VoiceboxError::NotDetected { .. }) => {
+            let explained = tokio::task::spawn_blocking(move || {
+                permissions::explain_failure(&app, permissions::PermissionKind::ScreenRecording, e)
+            });
+            Err(explained.await?)
+        }
+        measured => measured,
+    }
+}
+
+/// The system's default output, its volume and mute state. Blocking.
+fn read_system_output(app: &tauri::AppHandle) -> system_audio_info::SystemOutputInfo {
+    system_audio_info::read(&read_output_devices(app))
+}
+
+/// The output devices, empty if they can't be listed. Blocking. The
+/// listing is kept for audio environment snapshots.
+fn read_output_devices(app: &tauri::AppHandle) -> Vec<audio_output::AudioOutputDevice> {
+    let output = app.state::<audio_output::AudioOutputState>();
+    match output.list_output_devices() {
+        Ok(devices) => {
+            app.state::<audio_environment::AudioEnvironment>()
+                .update_devices(&devices, |id| output.device_format(id).ok());
+            devices
+        }
+        Err(e) => {
+            warn!("Failed to list output devices: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// The system's default output, its volume and mute state. Volume and mute
+/// are None where they can't be read.
+#[command]
+async fn get_system_output_info(app: tauri::AppHandle) -> Result<system_audio_info::SystemOutputInfo, VoiceboxError> {
+    Ok(tokio::task::spawn_blocking(move || read_system_output(&app)).await?)
+}
+
+/// Devices, formats, OS and backends, and the capture and playback under
+/// way, as attached to capture and playback failures. Device names are
+/// redacted the way diagnostics bundles are.
+#[command]
+fn get_audio_environment(app: tauri::AppHandle) -> audio_environment::AudioEnvironmentSnapshot {
+    audio_environment(&app)
+}
+
+/// Emit `system-output-changed` whenever the default output, its volume or
+/// its mute state changes, and `audio-devices-changed` with the new list
+/// whenever output devices come or go or the default moves, once the change
+/// has settled. A device that has gone stops being played to.
+fn spawn_system_output_watcher(app: tauri::AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut debouncer = system_audio_info::Debouncer::default();
+        let mut devices_debouncer = system_audio_info::Debouncer::default();
+        let mut devices = None;
+        loop {
+            let read_app = app.clone();
+            let read = tokio::task::spawn_blocking(move || {
+                let devices = read_output_devices(&read_app);
+                let info = system_audio_info::read(&devices);
+                (devices, info)
+            });
+            match read.await {
+                Ok((listed, info)) => {
+                    if let Some(changed) = debouncer.observe(info, std::time::Instant::now()) {
+                        debug!("System output changed: {:?}", changed);
+                        if let Err(e) = event_bus::emit(&app, "system-output-changed", changed) {
+                            warn!("Failed to emit system-output-changed event: {}", e);
+                        }
+                    }
+                    let previous = devices.get_or_insert_with(|| listed.clone());
+                    if let Some(listed) = devices_debouncer.observe(listed, std::time::Instant::now()) {
+                        on_output_devices_changed(&app, previous, &listed);
+                        *previous = listed;
+                    }
+                }
+                Err(e) => warn!("Failed to read the system output: {}", e),
+            }
+            tokio::time::sleep(system_audio_info::POLL_INTERVAL).await;
+        }
+    });
+}
+
+/// Stop playing to the devices that went away and tell the UI the list
+/// changed.
+fn on_output_devices_changed(
+    app: &tauri::AppHandle,
+    before: &[audio_output::AudioOutputDevice],
+    after: &[audio_output::AudioOutputDevice],
+) {
+    let change = audio_output::DeviceListChange::between(before, after);
+    if change.is_empty() {
+        return;
+    }
+    info!("Output devices changed: {:?}", change);
+    let output = app.state::<audio_output::AudioOutputState>();
+    for device_id in &change.removed {
+        output.drop_removed_device(device_id);
+    }
+    let payload = serde_json::json!({
+        "devices": after,
+        "added": change.added,
+        "removed": change.removed,
+        "default_changed": change.default_changed,
+    });
+    if let Err(e) = event_bus::emit(app, "audio-devices-changed", payload) {
+        warn!("Failed to emit audio-devices-changed event: {}", e);
+    }
+}
+
+#[command]
+fn start_audio_route(
+    output: State<'_, audio_output::AudioOutputState>,
+    routes: State<'_, audio_route::AudioRoutes>,
+    source: audio_route::CaptureSourceSpec,
+    destination_device_id: String,
+    gain_db: f32,
+) -> Result<audio_route::RouteStatus, VoiceboxError> {
+    routes.start(&output, source, &destination_device_id, gain_db)
+}
+
+#[command]
+fn stop_audio_route(
+    routes: State<'_, audio_route::AudioRoutes>,
+    route_id: u64,
+) -> Result<audio_route::RouteStatus, VoiceboxError> {
+    routes.stop(route_id)
+}
+
+#[command]
+fn get_route_status(
+    routes: State<'_, audio_route::AudioRoutes>,
+    route_id: u64,
+) -> Result<audio_route::RouteStatus, VoiceboxError> {
+    routes.status(route_id)
+}
+
+#[command]
+fn stop_audio_playback(
+    state: State<'_, audio_output::AudioOutputState>,
+) -> Result<(), VoiceboxError> {
+    state.stop_all_playback()
+}
+
+#[command]
+fn pause_audio_playback(
+    app: tauri::AppHandle,
+    state: State<'_, audio_output::AudioOutputState>,
+) -> Result<(), VoiceboxError> {
+    state.pause_playback()?;
+    media_controls::sync(&app);
+    Ok(())
+}
+
+#[command]
+fn resume_audio_playback(
+    app: tauri::AppHandle,
+    state: State<'_, audio_output::AudioOutputState>,
+) -> Result<(), VoiceboxError> {
+    state.resume_playback()?;
+    media_controls::sync(&app);
+    Ok(())
+}
+
+/// Change how loud the playback is on one of its devices, 0 to 2, while it
+/// plays.
+#[command]
+fn set_playback_volume(
+    state: State<'_, audio_output::AudioOutputState>,
+    device_id: String,
+    volume: f32,
+) -> Result<(), VoiceboxError> {
+    state.set_playback_volume(&device_id, volume)
+}
+
+/// Jump the playback to `position_secs` on all its devices; to its end or
+/// past stops it.
+#[command]
+fn seek_audio_playback(
+    app: tauri::AppHandle,
+    state: State<'_, audio_output::AudioOutputState>,
+    position_secs: f64,
+) -> Result<(), VoiceboxError> {
+    state.seek_playback(position_secs)?;
+    media_controls::sync(&app);
+    Ok(())
+}
+
+/// What is playing to devices, if anything, such as a clip still playing
+/// after the webview reloaded.
+#[command]
+fn get_playback_status(
+    state: State<'_, audio_output::AudioOutputState>,
+    media: State<'_, media_controls::MediaControlsState>,
+) -> audio_output::PlaybackStatus {
+    let mut status = state.status();
+    status.media_controls = media.is_available();
+    status
+}
+
+/// Longest capture the control API starts without a max_duration_secs.
+const CONTROL_CAPTURE_MAX_SECS: u32 = 300;
+
+/// Mark the running capture where it has got to, for split_on_markers.
+/// Seconds into it; None when not capturing.
+fn mark_capture(app: &tauri::AppHandle, label: Option<String>) -> Option<f64> {
+    let capture = app.state::<audio_capture::AudioCaptureState>();
+    let marker = capture.add_marker(label)?;
+    let sample_rate = *capture.sample_rate.lock().unwrap() as f64;
+    Some(marker.at_frames as f64 / sample_rate.max(1.0))
+}
+
+/// Mark the running capture, emitting `capture-marker`. Returns the seconds
+/// into it.
+#[command]
+fn add_capture_marker(app: tauri::AppHandle, label: Option<String>) -> Result<f64, VoiceboxError> {
+    let marker_secs =
+        mark_capture(&app, label.clone()).ok_or_else(|| VoiceboxError::capture("No capture is running"))?;
+    let marker = serde_json::json!({ "marker_secs": marker_secs, "label": label });
+    if let Err(e) = event_bus::emit(&app, "capture-marker", marker) {
+        warn!("Failed to emit capture-marker event: {}", e);
+    }
+    Ok(marker_secs)
+}
+
+/// Pause the running capture by hand, with the device left running so
+/// resuming is instant, and emit `capture-paused`. The pause doesn't count
+/// towards max_duration_secs.
+#[command]
+fn pause_system_audio_capture(app: tauri::AppHandle) -> Result<(), VoiceboxError> {
+    let state = app.state::<audio_capture::AudioCaptureState>();
+    state.pause_capture()?;
+    info!("System audio capture paused by hand");
+    let payload = serde_json::json!({ "session": state.current_session(), "reason": "user" });
+    let _ = event_bus::emit(&app, "capture-paused", payload);
+    Ok(())
+}
+
+/// Resume a capture paused with pause_system_audio_capture, and emit
+/// `capture-resumed`.
+#[command]
+fn resume_system_audio_capture(app: tauri::AppHandle) -> Result<(), VoiceboxError> {
+    let state = app.state::<audio_capture::AudioCaptureState>();
+    state.resume_capture()?;
+    info!("System audio capture resumed by hand");
+    let payload = serde_json::json!({ "session": state.current_session(), "reason": "user" });
+    let _ = event_bus::emit(&app, "capture-resumed", payload);
+    Ok(())
+}
+
+/// Output device ids for `names`, each a device id, a device name or
+/// `default`. None at all means the default device.
+fn resolve_output_devices(app: &tauri::AppHandle, names: &[String]) -> Result<Vec<String>, VoiceboxError> {
+    if names.is_empty() {
+        return Ok(vec![audio_output::DEFAULT_DEVICE_ID.to_string()]);
+    }
+    let devices = app.state::<audio_output::AudioOutputState>().list_output_devices()?;
+    names
+        .iter()
+        .map(|name| {
+            if name == audio_output::DEFAULT_DEVICE_ID {
+                return Ok(name.clone());
+            }
+            devices
+                .iter()
+                .find(|device| device.id == *name || device.name.eq_ignore_ascii_case(name))
+                .map(|device| device.id.clone())
+                .ok_or_else(|| VoiceboxError::not_found(format!("There is no output device '{}'", name)))
+        })
+        .collect()
+}
+
+/// Carry out a control API request through the command it stands for, so it
+/// behaves as if the UI had asked.
+async fn run_control_action(
+    app: tauri::AppHandle,
+    action: control_api::ControlAction,
+) -> Result<serde_json::Value, VoiceboxError> {
+    use control_api::ControlAction;
+    let value = match action {
+        ControlAction::Status => serde_json::json!({
+            "capture": get_capture_status(app.state()),
+            "playback": get_playback_status(app.state(), app.state()),
+        }),
+        ControlAction::StartCapture { max_duration_secs } => {
+            let max_duration_secs = max_duration_secs.unwrap_or(CONTROL_CAPTURE_MAX_SECS);
+            let options = audio_capture::CaptureOptions::new(max_duration_secs);
+            let started = begin_checked_capture(&app, options, false).await?;
+            serde_json::to_value(started)?
+        }
+        // The capture goes to the library: a stream deck has nowhere to put it
+        ControlAction::StopCapture => {
+            let session = app.state::<audio_capture::AudioCaptureState>().current_session();
+            end_capture(&app, audio_capture::SaveAs::default()).await?;
+            serde_json::to_value(save_capture_to_library(app, session, None).await?)?
+        }
+        ControlAction::AddMarker => serde_json::json!({ "marker_secs": add_capture_marker(app, None)? }),
+        ControlAction::PlayRecording { id, devices } => {
+            let device_ids = resolve_output_devices(&app, &devices)?;
+            let (audio, title) = {
+                let library = app.state::<recordings::RecordingsState>();
+                let library = library.open(&resolve_data_dir(&app)?);
+                let entry = library
+                    .list()?
+                    .into_iter()
+                    .find(|entry| entry.id == id)
+                    .ok_or_else(|| VoiceboxError::not_found(format!("There is no recording {}", id)))?;
+                (std::fs::read(library.path(&entry))?, entry.label)
+            };
+            let request = audio_output::PlaybackRequest {
+                title,
+                ..audio_output::PlaybackRequest::default()
+            };
+            let started = play_audio_to_devices(app.clone(), app.state(), audio, device_ids, request).await?;
+            serde_json::to_value(started)?
+        }
+        ControlAction::StopPlayback => {
+            stop_audio_playback(app.state())?;
+            serde_json::json!({})
+        }
+    };
+    Ok(value)
+}
+
+fn control_dispatch(app: &tauri::AppHandle) -> control_api::Dispatch {
+    let app = app.clone();
+    std::sync::Arc::new(move |action| Box::pin(run_control_action(app.clone(), action)))
+}
+
+/// Start the control API at launch if it was left enabled.
+fn restore_control_api(app: &tauri::AppHandle) {
+    let config = app.state::<SettingsState>().get().control_api;
+    let (true, Some(token)) = (config.enabled, config.token) else {
+        return;
+    };
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let control = app.state::<control_api::ControlApiState>();
+        if let Err(e) = control.start(config.port, token, control_dispatch(&app)).await {
+            warn!("Failed to start the control API: {}", e);
+        }
+    });
+}
+
+/// Turn the local control API for stream decks on or off. It only ever
+/// listens on 127.0.0.1, and every request needs `token` as a bearer token.
+/// `port` and `token` default to the saved ones.
+#[command]
+async fn set_control_api(
+    app: tauri::AppHandle,
+    enabled: bool,
+    port: Option<u16>,
+    token: Option<String>,
+) -> Result<control_api::ControlApiStatus, VoiceboxError> {
+    let settings = app.state::<SettingsState>();
+    let mut config = settings.get().control_api;
+    config.enabled = enabled;
+    if let Some(port) = port {
+        if port == 0 {
+            return Err(VoiceboxError::invalid_argument("The control API port must be at least 1"));
+        }
+        config.port = port;
+    }
+    if token.is_some() {
+        config.token = token;
+    }
+
+    let control = app.state::<control_api::ControlApiState>();
+    if enabled {
+        let token = config.token.clone().ok_or_else(|| {
+            VoiceboxError::invalid_argument("The control API needs a token to be enabled")
+        })?;
+        control.start(config.port, token, control_dispatch(&app)).await?;
+    } else {
+        control.stop().await;
+    }
+    settings.update(|s| s.control_api = config)?;
+    Ok(control.status().await)
+}
+
+#[command]
+async fn get_control_api_status(app: tauri::AppHandle) -> control_api::ControlApiStatus {
+    app.state::<control_api::ControlApiState>().status().await
+}
+
+/// Keep the system awake during a long server job (e.g. a batch generation)
+/// until end_busy is called with the returned id, or at most `max_minutes`.
+#[command]
+fn begin_busy(
+    app: tauri::AppHandle,
+    power: State<'_, power::PowerState>,
+    reason: String,
+    max_minutes: Option<u32>,
+) -> u64 {
+    let id = power.begin_busy(&reason);
+    // A frontend that reloads mid-job never calls end_busy
+    let max = std::time::Duration::from_secs(max_minutes.unwrap_or(power::BUSY_MAX_MINUTES).max(1) as u64 * 60);
+    tokio::spawn(async move {
+        tokio::time::sleep(max).await;
+        if app.state::<power::PowerState>().end_busy(id) {
+            info!("Busy hold {} ({}) expired", id, reason);
+        }
+    });
+    id
+}
+
+#[command]
+fn end_busy(power: State<'_, power::PowerState>, id: u64) -> bool {
+    power.end_busy(id)
+}
+
+#[command]
+fn get_power_assertions(power: State<'_, power::PowerState>) -> power::PowerAssertions {
+    power.assertions()
+}
+
+/// Open one of our folders (or a file inside them) in the system file manager
+/// and return its absolute path.
+#[command]
+async fn reveal_path(app: tauri::AppHandle, target: reveal::RevealTarget) -> Result<String, reveal::RevealError> {
+    let data_dir = resolve_data_dir(&app)?;
+    let tauri_paths = app.path();
+    // A migrated data dir lives outside the app dirs, so both are allowed
+    let roots: Vec<std::path::PathBuf> = [
+        paths::app_data_dir(&app),
+        tauri_paths.app_log_dir().map_err(|e| e.to_string()),
+        tauri_paths.app_cache_dir().map_err(|e| e.to_string()),
+    ]
+    .into_iter()
+    .filter_map(Result::ok)
+        .chain(std::iter::once(data_dir.clone()))
+        .collect();
+
+    let resolved = tokio::task::spawn_blocking(move || {
+        let resolved = reveal::resolve(&target, &data_dir, &roots)?;
+        reveal::open_in_file_manager(&resolved)?;
+        Ok::<_, reveal::RevealError>(resolved)
+    })
+    .await
+    .map_err(|e| format!("Reveal task failed: {}", e))??;
+    info!("Revealed {}", resolved.display());
+    Ok(resolved.to_string_lossy().into_owned())
+}
+
+/// Change what gets logged at runtime, e.g. `info,voicebox::audio_output=debug`.
+/// Lasts until the app restarts.
+#[command]
+fn set_log_filter(filter: String) -> Result<(), VoiceboxError> {
+    logging::set_filter(&filter).map_err(VoiceboxError::invalid_argument)
+}
+
+/// Capture, playback and server counters since launch or the last
+/// reset_metrics.
+#[command]
+fn get_metrics() -> metrics::MetricsSnapshot {
+    metrics::METRICS.snapshot()
+}
+
+#[command]
+fn get_audio_activity_log() -> audio_log::AudioActivityLog {
+    audio_log::AUDIO_LOG.snapshot()
+}
+
+#[command]
+fn reset_metrics() {
+    metrics::METRICS.reset();
+}
+
+/// Time the stages of turning a `duration_secs` capture into what
+/// stop_system_audio_capture returns, on generated audio, to see where a slow
+/// stop goes. With `emit_payload`, also times sending the result to the
+/// webview as a `pipeline-benchmark-payload` event nothing listens to.
+#[command]
+async fn run_pipeline_benchmark(
+    app: tauri::AppHandle,
+    duration_secs: u32,
+    emit_payload: Option<bool>,
+) -> Result<pipeline_benchmark::BenchmarkReport, VoiceboxError> {
+    tokio::task::spawn_blocking(move || {
+        let emit = |payload: &str| app.emit("pipeline-benchmark-payload", payload).map_err(|e| e.to_string());
+        let emit: Option<pipeline_benchmark::Emit> = match emit_payload {
+            Some(true) => Some(&emit),
+            _ => None,
+        };
+        pipeline_benchmark::run(duration_secs, emit)
+    })
+    .await?
+}
+
+/// The newest app log lines for the diagnostics view, optionally only those at
+/// `level_at_least` ("error", "warn", "info", ...) or more severe.
+#[command]
+async fn get_app_logs(
+    tail_lines: usize,
+    level_at_least: Option<String>,
+) -> Result<Vec<logging::LogEntry>, VoiceboxError> {
+    Ok(tokio::task::spawn_blocking(move || logging::tail(tail_lines, level_at_least.as_deref())).await??)
+}
+
+/// The newest `last_n` lines of server output, or all that are kept (up to
+/// SERVER_LOG_LINES), oldest first. New lines also arrive as `server-log`.
+#[command]
+fn get_server_logs(state: State<'_, ServerState>, last_n: Option<usize>) -> Vec<server_output::ServerLogLine> {
+    state.log.last(last_n)
+}
+
+/// Write the kept server output to `path` as text, one timestamped line each.
+/// Returns how many lines were written.
+#[command]
+async fn export_server_logs(state: State<'_, ServerState>, path: String) -> Result<usize, VoiceboxError> {
+    let log = state.log.clone();
+    tokio::task::spawn_blocking(move || log.export(std::path::Path::new(&path)))
+        .await?
+        .map_err(VoiceboxError::io)
+}
+
+/// Send each new line of server output as a `server-log` event. Lines come
+/// through a bounded queue, so the tasks reading the server's output never
+/// wait on the webview.
+fn spawn_server_log_forwarder(app: &tauri::AppHandle) {
+    let mut lines = app.state::<ServerState>().log.subscribe(server_output::LOG_EVENT_QUEUE);
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        while let Some(line) = lines.recv().await {
+            if let Err(e) = event_bus::emit(&app, "server-log", &line) {
+                debug!("Failed to emit server-log event: {}", e);
+            }
+        }
+    });
+}
+
+/// Where this instance keeps its data, and whether that is the default,
+/// a `--data-dir` argument or a portable install.
+#[command]
+fn get_app_paths(app: tauri::AppHandle) -> Result<paths::AppPaths, VoiceboxError> {
+    Ok(paths::app_paths(&app)?)
+}
+
+/// The newest crash log, so the frontend can offer to send it after a restart.
+/// `pending` is only true the first time a given crash is returned.
+#[command]
+async fn get_last_crash_report() -> Result<Option<crash_log::CrashReport>, VoiceboxError> {
+    Ok(tokio::task::spawn_blocking(crash_log::last_report).await??)
+}
+
+/// Every permission onboarding cares about, e.g. `{ "screen_recording": "granted" }`.
+#[command]
+async fn get_permissions_status(
+    app: tauri::AppHandle,
+) -> Result<std::collections::BTreeMap<permissions::PermissionKind, permissions::PermissionStatus>, VoiceboxError> {
+    Ok(tokio::task::spawn_blocking(move || permissions::all(&app)).await?)
+}
+
+#[command]
+async fn request_permission(
+    app: tauri::AppHandle,
+    kind: permissions::PermissionKind,
+) -> Result<permissions::PermissionStatus, VoiceboxError> {
+    Ok(tokio::task::spawn_blocking(move || permissions::request(&app, kind)).await??)
+}
+
+/// Whether system audio capture has the Screen Recording permission it
+/// needs on macOS: `granted`, `denied` or `not_determined`. Always
+/// `granted` elsewhere.
+#[command]
+async fn check_capture_permission(app: tauri::AppHandle) -> Result<permissions::PermissionStatus, VoiceboxError> {
+    let status = tokio::task::spawn_blocking(move || {
+        permissions::recheck(&app, permissions::PermissionKind::ScreenRecording)
+    });
+    Ok(permissions::capture_status(status.await?))
+}
+
+/// Ask for the Screen Recording permission, with the system prompt if macOS
+/// still shows one and its settings pane if not, and return the outcome.
+#[command]
+async fn request_capture_permission(app: tauri::AppHandle) -> Result<permissions::PermissionStatus, VoiceboxError> {
+    let status = tokio::task::spawn_blocking(move || {
+        permissions::request(&app, permissions::PermissionKind::ScreenRecording)
+    });
+    Ok(permissions::capture_status(status.await??))
+}
+
+/// Where Tauri looks for the bundled sidecar: next to the app's executable.
+fn sidecar_path() -> Result<std::path::PathBuf, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to locate the app: {}", e))?;
+    Ok(exe.with_file_name(format!("voicebox-server{}", std::env::consts::EXE_SUFFIX)))
+}
+
+/// The setup wizard's smoke start: the running server answers its health
+/// check, or else the bundled sidecar launches far enough to log startup,
+/// and is killed there. `--help` keeps it from serving if it gets further.
+async fn smoke_start_server(app: &tauri::AppHandle) -> setup_checks::Finding {
+    use setup_checks::Finding;
+    use tauri_plugin_shell::process::CommandEvent;
+
+    let state = app.state::<ServerState>();
+    let info = state.info.lock().unwrap().clone();
+    if let Some(info) = info {
+        let token = state.auth_token.lock().unwrap().clone();
+        return if process_manager::check_health_at(&info.url, token.as_deref()).await {
+            Finding::pass(format!("The server at {} answers", info.url))
+        } else {
+            Finding::fail(
+                format!("The server at {} doesn't answer its health check", info.url),
+                "Restart the server from the tray menu",
+            )
+        };
+    }
+
+    let spawned = app
+        .shell()
+        .sidecar("voicebox-server")
+        .and_then(|sidecar| sidecar.arg("--help").spawn());
+    let (mut rx, child) = match spawned {
+        Ok(spawned) => spawned,
+        Err(e) => {
+            let error = ServerStartError::SpawnFailed { os_error: e.to_string() };
+            return Finding::fail(error.to_string(), error.hints().join(". "));
+        }
+    };
+    // Killed however this ends, the check timing out included
+    let _child = scopeguard::guard(child, |child| {
+        let _ = child.kill();
+    });
+    while let Some(event) = rx.recv().await {
+        match event {
+            CommandEvent::Stdout(line) | CommandEvent::Stderr(line) => {
+                if StartupPhase::from_log_line(&String::from_utf8_lossy(&line)).is_some() {
+                    return Finding::pass("The bundled server launches");
+                }
+            }
+            CommandEvent::Terminated(payload) => {
+                let error = ServerStartError::CrashedDuringStartup {
+                    exit_code: payload.code,
+                    tail_lines: Vec::new(),
+                };
+                return Finding::fail(error.to_string(), error.hints().join(". "));
+            }
+            _ => {}
+        }
+    }
+    Finding::fail(
+        "The bundled server closed its output before starting up",
+        "Check the app logs for details (search for 'voicebox')",
+    )
+}
+
+/// Run one setup check, off the async runtime where it blocks.
+async fn probe_setup(app: tauri::AppHandle, check: setup_checks::SetupCheck) -> setup_checks::Finding {
+    use setup_checks::{Finding, SetupCheck};
+
+    let finding = match check {
+        SetupCheck::ServerBinary => {
+            tokio::task::spawn_blocking(|| match sidecar_path() {
+                #[cfg(debug_assertions)]
+                Ok(path) if !path.exists() => {
+                    let error = ServerStartError::BinaryProblem {
+                        kind: BinaryProblemKind::DevServerNotRunning,
+                    };
+                    Finding::warn(
+                        format!("Dev build without a bundled server at {}", path.display()),
+                        error.hints().join(". "),
+                    )
+                }
+                Ok(path) => setup_checks::server_binary(&path, std::env::consts::ARCH, std::env::consts::OS),
+                Err(e) => Finding::fail(e, "Reinstall Voicebox"),
+            })
+            .await
+        }
+        SetupCheck::DiskSpace => {
+            tokio::task::spawn_blocking(move || {
+                setup_checks::disk_space(&refresh_storage(&app), setup_checks::MODELS_BYTES)
+            })
+            .await
+        }
+        SetupCheck::Permissions => tokio::task::spawn_blocking(move || permissions::setup_finding(&app)).await,
+        SetupCheck::OutputDevice => {
+            tokio::task::spawn_blocking(move || {
+                setup_checks::output_device(&app.state::<audio_output::AudioOutputState>())
+            })
+            .await
+        }
+        SetupCheck::ServerSmokeStart => return smoke_start_server(&app).await,
+    };
+    finding.unwrap_or_else(|e| Finding::fail(format!("The check crashed: {}", e), "Run the check again"))
+}
+
+/// Check what onboarding needs before the first generation: the server
+/// binary, free space for the models, permissions and an output device, plus
+/// a smoke start of the server with `include_server_start`. The checks run at
+/// once and each gives up after CHECK_TIMEOUT, so this returns in about that
+/// long even when things are broken.
+#[command]
+async fn run_setup_checks(
+    app: tauri::AppHandle,
+    include_server_start: Option<bool>,
+) -> Vec<setup_checks::CheckResult> {
+    let mut checks = setup_checks::SetupCheck::DEFAULT.to_vec();
+    if include_server_start.unwrap_or(false) {
+        checks.push(setup_checks::SetupCheck::ServerSmokeStart);
+    }
+    setup_checks::run_all(&checks, setup_checks::CHECK_TIMEOUT, |check| probe_setup(app.clone(), check)).await
+}
+
+/// Run one setup check again, for the wizard's retry buttons.
+#[command]
+async fn run_single_check(app: tauri::AppHandle, name: setup_checks::SetupCheck) -> setup_checks::CheckResult {
+    setup_checks::run(name, setup_checks::CHECK_TIMEOUT, probe_setup(app, name)).await
+}
+
+/// Everything a support request needs in one zip: app and server logs, crash
+/// reports, server status and health, devices and the audio environment,
+/// permissions, paths and settings, with secrets, the home directory and the
+/// account name scrubbed. Without `dest`, asks where to save it and returns
+/// None if the dialog is cancelled. A section that can't be gathered, e.g.
+/// server health while the server is down, is listed as missing in
+/// manifest.json instead of failing the export.
+#[command]
+async fn export_diagnostics_bundle(
+    app: tauri::AppHandle,
+    state: State<'_, ServerState>,
+    settings: State<'_, SettingsState>,
+    audio_output: State<'_, audio_output::AudioOutputState>,
+    usage: State<'_, disk_usage::DiskUsageState>,
+    dest: Option<String>,
+) -> Result<Option<String>, VoiceboxError> {
+    use tauri_plugin_dialog::DialogExt;
+
+    let dest = match dest {
+        Some(dest) => std::path::PathBuf::from(dest),
+        None => {
+            let mut dialog = app
+                .dialog()
+                .file()
+                .set_title("Save Diagnostics")
+                .set_file_name(format!("voicebox-diagnostics-{}.zip", process_manager::unix_timestamp()))
+                .add_filter("Zip archive", &["zip"]);
+            if let Some(window) = app.get_webview_window("main") {
+                dialog = dialog.set_parent(&window);
+            }
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            dialog.save_file(move |path| {
+                let _ = tx.send(path);
+            });
+            let Some(path) = rx.await.ok().flatten() else {
+                return Ok(None);
+            };
+            path.into_path()
+                .map_err(|e| VoiceboxError::invalid_argument(format!("Unsupported save location: {}", e)))?
+        }
+    };
+
+    let auth_token = state.auth_token.lock().unwrap().clone();
+    let mut bundle = diagnostics::DiagnosticsBundle::new(diagnostics_redactor(&app));
+
+    // Logs dir holds the app logs (with the server's output), the headless
+    // server log and crash logs
+    bundle.add_dir("logs", paths::logs_dir(&app));
+    bundle.add_json("last_crash", get_last_crash_report().await.map_err(String::from));
+    bundle.add_json(
+        "server_status",
+        get_server_status(state.clone(), settings.clone()).await.map_err(String::from),
+    );
+    bundle.add_json("server_version", get_server_version(state.clone()).await.map_err(String::from));
+    let server_url = state.info.lock().unwrap().as_ref().map(|info| info.url.clone());
+    match server_url {
+        Some(url) => {
+            let health = process_manager::fetch_health_at(&url, auth_token.as_deref()).await;
+            bundle.add_json("server_health", health);
+        }
+        None => bundle.add_missing("server_health", "The server is not running".to_string()),
+    }
+    bundle.add_json(
+        "data_dir_usage",
+        get_data_dir_usage(app.clone(), usage, None).await.map_err(String::from),
+    );
+    bundle.add_json("output_devices", list_audio_output_devices(audio_output).map_err(String::from));
+    let input_devices = tokio::task::spawn_blocking(diagnostics::input_devices)
+        .await
+        .map_err(|e| format!("Device query failed: {}", e))
+        .and_then(|devices| devices);
+    bundle.add_json("input_devices", input_devices);
+    bundle.add_json("permissions", get_permissions_status(app.clone()).await.map_err(String::from));
+    let capture_capabilities = get_capture_capabilities(app.clone()).await.map_err(String::from);
+    bundle.add_json(
+        "capabilities",
+        Ok(serde_json::json!({
+            "system_audio_capture": capture_capabilities.as_ref().is_ok_and(|capabilities| capabilities.system_audio),
+            "capture": capture_capabilities.ok(),
+            "capture_backend": app.state::<audio_capture::AudioCaptureState>().backend_name(),
+            "autostart": get_autostart().ok(),
+        })),
+    );
+    bundle.add_json("metrics", Ok(get_metrics()));
+    bundle.add_json("audio_activity", Ok(get_audio_activity_log()));
+    let environment = app.state::<audio_environment::AudioEnvironment>().snapshot(
+        &app.state::<audio_capture::AudioCaptureState>(),
+        &app.state::<audio_output::AudioOutputState>(),
+    );
+    bundle.add_json("audio_environment", Ok(environment));
+    if let Some(report) = pipeline_benchmark::last() {
+        bundle.add_json("pipeline_benchmark", Ok(report));
+    }
+    bundle.add_json("paths", get_app_paths(app.clone()).map_err(String::from));
+    bundle.add_json("settings", Ok(settings.get()));
+
+    let app_version = app.package_info().version.to_string();
+    let dest_path = dest.clone();
+    let manifest = tokio::task::spawn_blocking(move || bundle.write(&dest_path, &app_version))
+        .await
+        .map_err(|e| format!("Diagnostics task failed: {}", e))??;
+
+    let missing: Vec<&str> = manifest
+        .sections
+        .iter()
+        .filter(|section| section.error.is_some())
+        .map(|section| section.name.as_str())
+        .collect();
+    info!("Diagnostics bundle written to {} (missing: {:?})", dest.display(), missing);
+    Ok(Some(dest.to_string_lossy().into_owned()))
+}
+
+/// Forget the saved window geometry and recenter the window.
+#[command]
+fn reset_window_state(app: tauri::AppHandle, settings: State<'_, SettingsState>) -> Result<(), VoiceboxError> {
+    settings.update(|s| {
+        s.window_state = None;
+        s.devtools_open = false;
+    })?;
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.unmaximize();
+        let _ = window.set_size(tauri::LogicalSize::new(1200.0, 800.0));
+        let _ = window.center();
+    }
+    Ok(())
+}
+
+/// Read back from the OS, so an entry the user removed by hand shows as disabled.
+#[command]
+fn get_autostart() -> Result<autostart::AutostartStatus, VoiceboxError> {
+    Ok(autostart::get()?)
+}
+
+#[command]
+fn set_autostart(enabled: bool, start_minimized: bool) -> Result<autostart::AutostartStatus, VoiceboxError> {
+    let status = autostart::set(enabled, start_minimized)?;
+    info!("Launch at login: {:?}", status);
+    // Read back from the OS, which may have kept or refused the entry
+    if status.enabled != enabled {
+        return Err(VoiceboxError::internal(if enabled {
+            "The login entry was written but the system doesn't list it"
+        } else {
+            "The login entry is still registered after removing it"
+        }));
+    }
+    Ok(status)
+}
+
+/// The frontend is listening for deep link events; deliver any that were queued.
+#[command]
+fn deep_link_ready(app: tauri::AppHandle) {
+    deep_link::mark_ready(&app);
+}
+
+#[command]
+fn register_hotkey(
+    app: tauri::AppHandle,
+    action: String,
+    accelerator: String,
+) -> Result<(), hotkeys::HotkeyError> {
+    let action = HotkeyAction::parse(&action).map_err(|message| hotkeys::HotkeyError::UnknownAction { message })?;
+    hotkeys::register(&app, action, &accelerator)
+}
+
+#[command]
+fn unregister_hotkey(app: tauri::AppHandle, action: String) -> Result<(), hotkeys::HotkeyError> {
+    let action = HotkeyAction::parse(&action).map_err(|message| hotkeys::HotkeyError::UnknownAction { message })?;
+    hotkeys::unregister(&app, action)
+}
+
+/// register_hotkey for toggle_capture: the hotkey starts a system audio
+/// capture, or stops the one running, without the window having focus.
+#[command]
+fn register_capture_hotkey(app: tauri::AppHandle, accelerator: String) -> Result<(), hotkeys::HotkeyError> {
+    hotkeys::register(&app, HotkeyAction::ToggleCapture, &accelerator)
+}
+
+#[command]
+fn unregister_capture_hotkey(app: tauri::AppHandle) -> Result<(), hotkeys::HotkeyError> {
+    hotkeys::unregister(&app, HotkeyAction::ToggleCapture)
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    let mut context = tauri::generate_context!();
+    // The single-instance lock is keyed on the identifier. With a data dir
+    // override, key it on that dir too so separate profiles can run at once.
+    if let Some(suffix) = paths::instance_suffix() {
+        let config = context.config_mut();
+        config.identifier = format!("{}.{}", config.identifier, suffix);
+    }
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
     tauri::Builder::default()
+        // Must be registered first so a second launch exits before doing anything else
+        .plugin(tauri_plugin_single_instance::init(|app, args, cwd| {
+            instance::handle_second_instance(app, args, cwd);
+        }))
+        .plugin(tauri_plugin_deep_link::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_shell::init())
-        .manage(ServerState {
-            child: Mutex::new(None),
-            server_pid: Mutex::new(None),
-            keep_running_on_close: Mutex::new(false),
-        })
+        .manage(ServerState::new())
         .manage(audio_capture::AudioCaptureState::new())
         .manage(audio_output::AudioOutputState::new())
+        .manage(audio_environment::AudioEnvironment::default())
+        .manage(audio_route::AudioRoutes::new())
+        .manage(combined_capture::CombinedCapture::new())
+        .manage(disk_usage::DiskUsageState::new())
+        .manage(deep_link::DeepLinkState::new())
+        .manage(file_import::FileImportState::new())
+        .manage(window_state::WindowStateTracker::new())
+        .manage(notifications::NotificationState::new())
+        .manage(mini_recorder::MiniRecorderState::new())
+        .manage(progress_indicator::ProgressIndicatorState::new())
+        .manage(updates::UpdateState::new())
+        .manage(power::PowerState::new())
+        .manage(permissions::PermissionsState::new())
+        .manage(status_indicator::StatusIndicatorState::new())
+        .manage(storage::StorageState::new())
+        .manage(recordings::RecordingsState::new())
+        .manage(control_api::ControlApiState::new())
+        .manage(hotkeys::HotkeyCaptureState::new())
         .setup(|app| {
+            let data_dir = paths::app_data_dir(app.handle()).ok();
+            let logs_dir = paths::logs_dir(app.handle()).ok();
+            logging::init(logs_dir.as_deref(), false);
+            if let Some(dir) = logs_dir {
+                crash_log::set_logs_dir(dir);
+            }
+            app.manage(SettingsState::load(data_dir.as_deref()));
+            // Keeps running without a writable data dir, degraded
+            refresh_storage(app.handle());
+            apply_keep_running(&app.state::<ServerState>(), &app.state::<SettingsState>());
+            std::thread::spawn(clipboard::cleanup_temp_files);
+            progress_indicator::install(app.handle());
+            event_bus::init(app.handle());
+            spawn_server_log_forwarder(app.handle());
+            media_controls::init(app.handle());
+            watched_folders::init(app.handle(), data_dir.as_deref());
+
+            // The window is created hidden; a login launch with --hidden leaves
+            // it that way and only the tray shows
+            if let Some(window) = app.get_webview_window("main") {
+                window_state::restore(&window);
+                if autostart::launched_hidden() {
+                    info!("Started with {}, keeping the window hidden", autostart::HIDDEN_FLAG);
+                } else {
+                    let _ = window.show();
+                }
+            }
+            // A hidden launch is for the server, which shouldn't wait for the
+            // webview to ask for it
+            if autostart::launched_hidden() {
+                let app = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    start_server_from_tray(&app, false).await;
+                });
+            }
+            // Dev builds run from the target dir and mustn't take over the entry
+            #[cfg(not(debug_assertions))]
+            std::thread::spawn(|| match autostart::refresh() {
+                Ok(true) => info!("Pointed the login entry at the moved or updated app"),
+                Ok(false) => {}
+                Err(e) => warn!("Failed to refresh the login entry: {}", e),
+            });
+
             #[cfg(desktop)]
             {
                 app.handle().plugin(tauri_plugin_updater::Builder::new().build())?;
                 app.handle().plugin(tauri_plugin_process::init())?;
+                app.handle().plugin(tauri_plugin_global_shortcut::Builder::new().build())?;
+                app.handle().plugin(tauri_plugin_notification::init())?;
+                notifications::install(app.handle());
+                tray::create(app.handle())?;
+                status_indicator::install(app.handle());
+                power_events::install(app.handle());
+                #[cfg(target_os = "macos")]
+                app_menu::create(app.handle())?;
+                hotkeys::restore(app.handle());
+
+                use tauri_plugin_deep_link::DeepLinkExt;
+                // Installers register the scheme; this covers dev builds and unpacked AppImages
+                #[cfg(any(windows, target_os = "linux"))]
+                if let Err(e) = app.deep_link().register_all() {
+                    warn!("Failed to register the {}:// scheme: {}", deep_link::SCHEME, e);
+                }
+                let handle = app.handle().clone();
+                app.deep_link().on_open_url(move |event| {
+                    for url in event.urls() {
+                        deep_link::dispatch(&handle, url.as_str());
+                    }
+                });
+                // A link that launched the app
+                if let Ok(Some(urls)) = app.deep_link().get_current() {
+                    for url in urls {
+                        deep_link::dispatch(app.handle(), url.as_str());
+                    }
+                }
             }
 
+            scheduled_capture::init(app.handle());
+            spawn_system_output_watcher(app.handle().clone());
+            restore_control_api(app.handle());
+
+            // Audio files the app was launched to open; later ones arrive
+            // through handle_second_instance
+            let args: Vec<String> = std::env::args().collect();
+            file_import::open(app.handle(), instance::launch_audio_files(&args));
+
             // Hide title bar icon on Windows
             #[cfg(windows)]
             {
@@ -639,190 +4533,290 @@ pub fn run() {
         })
         .invoke_handler(tauri::generate_handler![
             start_server,
+            start_server_url,
+            ensure_firewall_rule,
+            get_endpoint_file_path,
+            get_server_auth_token,
+            connect_to_server,
+            use_external_server,
+            use_bundled_server,
             stop_server,
+            restart_server,
+            migrate_data_dir,
+            export_data_backup,
+            import_data_backup,
+            get_storage_status,
+            check_model_download_space,
+            get_data_dir_usage,
+            cancel_data_dir_usage,
+            clear_server_cache,
             set_keep_server_running,
+            set_auto_restart,
+            get_server_status,
+            get_server_version,
+            set_server_log_level,
+            set_server_access_logs,
+            set_server_priority,
             start_system_audio_capture,
+            list_audio_capture_targets,
+            list_capturable_applications,
             stop_system_audio_capture,
+            stop_system_audio_capture_v2,
+            finalize_capture,
+            get_capture_chunk,
+            release_capture,
+            save_capture_to_file,
+            segment_capture,
+            cancel_system_audio_capture,
+            pause_system_audio_capture,
+            resume_system_audio_capture,
+            start_combined_capture,
+            stop_combined_capture,
+            get_capture_status,
+            add_capture_marker,
+            schedule_capture,
+            list_scheduled_captures,
+            cancel_scheduled_capture,
             is_system_audio_supported,
+            get_capture_capabilities,
             list_audio_output_devices,
+            get_device_latency,
+            play_test_tone,
             play_audio_to_devices,
-            stop_audio_playback
+            play_audio_file_to_devices,
+            begin_playback_stream,
+            push_playback_chunk,
+            end_playback_stream,
+            queue_audio_for_playback,
+            clear_playback_queue,
+            move_playback,
+            run_audio_selftest,
+            measure_route_latency,
+            get_system_output_info,
+            get_audio_environment,
+            start_audio_route,
+            stop_audio_route,
+            get_route_status,
+            stop_audio_playback,
+            pause_audio_playback,
+            resume_audio_playback,
+            seek_audio_playback,
+            set_playback_volume,
+            get_playback_status,
+            set_control_api,
+            get_control_api_status,
+            register_hotkey,
+            unregister_hotkey,
+            register_capture_hotkey,
+            unregister_capture_hotkey,
+            deep_link_ready,
+            get_autostart,
+            set_autostart,
+            reset_window_state,
+            get_notification_prefs,
+            set_notification_prefs,
+            get_close_behavior,
+            set_close_behavior,
+            import_audio_file,
+            set_max_import_size,
+            add_watched_folder,
+            remove_watched_folder,
+            list_watched_folders,
+            export_audio,
+            process_audio,
+            get_recording_metadata,
+            save_capture_to_library,
+            list_recordings,
+            pin_recording,
+            find_similar_recordings,
+            split_recording,
+            get_recordings_usage,
+            copy_audio_to_clipboard,
+            get_setting,
+            set_setting,
+            get_all_settings,
+            open_mini_recorder,
+            close_mini_recorder,
+            set_app_progress,
+            check_for_updates,
+            install_update,
+            begin_busy,
+            end_busy,
+            get_power_assertions,
+            reveal_path,
+            get_permissions_status,
+            run_setup_checks,
+            run_single_check,
+            get_last_crash_report,
+            export_diagnostics_bundle,
+            add_recent_item,
+            get_recent_items,
+            clear_recent_items,
+            get_app_paths,
+            set_log_filter,
+            get_metrics,
+            reset_metrics,
+            get_audio_activity_log,
+            run_pipeline_benchmark,
+            get_app_logs,
+            get_server_logs,
+            export_server_logs,
+            request_permission,
+            check_capture_permission,
+            request_capture_permission
         ])
         .on_window_event(|window, event| {
-            if let WindowEvent::CloseRequested { api, .. } = event {
+            window_state::on_window_event(window, event);
+            mini_recorder::on_window_event(window, event);
+            if let WindowEvent::Focused(true) = event {
+                notifications::on_window_focused(window.app_handle());
+            }
+
+            // Import dropped audio here so drag-and-drop works without any frontend wiring
+            if let WindowEvent::DragDrop(DragDropEvent::Drop { paths, .. }) = event {
+                let audio: Vec<_> = paths.iter().filter(|p| audio_import::is_audio_file(p)).cloned().collect();
+                if !audio.is_empty() {
+                    file_import::spawn(window.app_handle(), audio);
+                }
+            }
+
+            // Other windows (the mini recorder) just close
+            if let (WindowEvent::CloseRequested { api, .. }, "main") = (event, window.label()) {
                 // Prevent automatic close
                 api.prevent_close();
 
-                // Emit event to frontend to check setting and stop server if needed
                 let app_handle = window.app_handle();
 
-                if let Err(e) = app_handle.emit("window-close-requested", ()) {
-                    eprintln!("Failed to emit window-close-requested event: {}", e);
-                    // If event emission fails, allow close anyway
-                    window.close().ok();
+                // Hiding needs a way back, so without a tray icon the window closes as usual.
+                // The tray's Quit and a second launch both still work on the hidden window.
+                let close_behavior = app_handle.state::<SettingsState>().get().close_behavior;
+                if close_behavior == CloseBehavior::MinimizeToTray && tray::exists(app_handle) {
+                    info!("Close requested, hiding the window to the tray");
+                    window.hide().ok();
+                    tray::refresh(app_handle);
                     return;
                 }
 
-                // Set up listener for frontend response
-                let window_for_close = window.clone();
-                let (tx, mut rx) = mpsc::unbounded_channel::<()>();
-
-                // Listen for response from frontend using window's listen method
-                let listener_id = window.listen("window-close-allowed", move |_| {
-                    // Frontend has checked setting and stopped server if needed
-                    // Signal that we can close
-                    let _ = tx.send(());
-                });
+                // Repeated close requests while the server is still stopping are ignored
+                let Some(action) = app_handle.state::<ServerState>().begin_close() else {
+                    return;
+                };
+                info!("Close requested, server action: {:?}", action);
+                // Quitting takes the mini recorder along; only close-to-tray leaves it up
+                mini_recorder::close(app_handle);
+                if let Err(e) = event_bus::emit(app_handle, "window-closing", serde_json::json!({ "action": action })) {
+                    warn!("Failed to emit window-closing event: {}", e);
+                }
 
-                // Wait for frontend response or timeout
-                tokio::spawn(async move {
-                    tokio::select! {
-                        _ = rx.recv() => {
-                            // Frontend responded, close window
-                            window_for_close.close().ok();
-                        }
-                        _ = tokio::time::sleep(tokio::time::Duration::from_secs(5)) => {
-                            // Timeout - close anyway
-                            eprintln!("Window close timeout, closing anyway");
-                            window_for_close.close().ok();
-                        }
+                // destroy() rather than close(), which would come back through CloseRequested
+                let window = window.clone();
+                match action {
+                    process_manager::CloseAction::KeepServer => {
+                        window.destroy().ok();
                     }
-                    // Clean up listener
-                    window_for_close.unlisten(listener_id);
-                });
+                    process_manager::CloseAction::StopServer => {
+                        tauri::async_runtime::spawn(async move {
+                            let app = window.app_handle().clone();
+                            let stop = stop_server(app.clone(), app.state::<ServerState>());
+                            let wait = stop_grace(&app) + process_manager::STOP_KILL_MARGIN;
+                            match tokio::time::timeout(wait, stop).await {
+                                Ok(Ok(_)) => {}
+                                Ok(Err(e)) => warn!("Failed to stop server on close: {}", e),
+                                Err(_) => warn!("Server did not stop in time, closing anyway"),
+                            }
+                            window.destroy().ok();
+                        });
+                    }
+                }
             }
         })
-        .build(tauri::generate_context!())
+        .build(context)
         .expect("error while building tauri application")
         .run(|app, event| {
             match &event {
                 RunEvent::Exit => {
-                    println!("=================================================================");
-                    println!("RunEvent::Exit received - checking server cleanup");
+                    // Never leave the machine unable to sleep
+                    app.state::<power::PowerState>().release_all();
+                    app.state::<audio_route::AudioRoutes>().stop_all();
+                    tauri::async_runtime::block_on(app.state::<control_api::ControlApiState>().stop());
+
+                    info!("RunEvent::Exit received - checking server cleanup");
                     let state = app.state::<ServerState>();
                     let keep_running = *state.keep_running_on_close.lock().unwrap();
-                    println!("keep_running_on_close = {}", keep_running);
-                    
-                    if !keep_running {
+                    info!("keep_running_on_close = {}", keep_running);
+                    let data_dir = resolve_data_dir(app).ok();
+                    let managed = *state.managed.lock().unwrap();
+
+                    if !managed {
+                        info!("Using an external server, leaving it running");
+                        update_endpoint_file(app, None);
+                    } else if !keep_running {
+                        if let Some(data_dir) = &data_dir {
+                            process_manager::remove_server_record(data_dir);
+                        }
+                        update_endpoint_file(app, None);
+
                         // Get the stored PID for process group killing
                         let pid = state.server_pid.lock().unwrap().take();
                         // Also take the child to clean up
                         let _child = state.child.lock().unwrap().take();
                         
                         if let Some(pid) = pid {
-                            println!("Killing server process group with PID: {}", pid);
-                            
-                            // Kill the entire process group on Unix systems
-                            // Using negative PID sends signal to all processes in the group
-                            #[cfg(unix)]
-                            {
-                                use std::process::Command;
-                                // First try SIGTERM to the process group
-                                let pgid_kill = Command::new("kill")
-                                    .args(["-TERM", "--", &format!("-{}", pid)])
-                                    .output();
-                                
-                                match pgid_kill {
-                                    Ok(output) => {
-                                        if output.status.success() {
-                                            println!("SIGTERM sent to process group -{}", pid);
-                                        } else {
-                                            // Process group kill failed, try direct kill
-                                            println!("Process group kill failed, trying direct kill");
-                                            let _ = Command::new("kill")
-                                                .args(["-TERM", &pid.to_string()])
-                                                .output();
-                                        }
-                                    }
-                                    Err(e) => {
-                                        eprintln!("Failed to execute kill command: {}", e);
-                                    }
-                                }
-                                
-                                // Give it a moment, then force kill if needed
-                                std::thread::sleep(std::time::Duration::from_millis(100));
-                                
-                                // Force kill with SIGKILL
-                                let _ = Command::new("kill")
-                                    .args(["-9", "--", &format!("-{}", pid)])
-                                    .output();
-                                let _ = Command::new("kill")
-                                    .args(["-9", &pid.to_string()])
-                                    .output();
-                                
-                                println!("Server process group kill completed");
-                            }
-                            
-                            #[cfg(windows)]
-                            {
-                                // Layer 1: Try graceful HTTP shutdown first
-                                println!("Attempting graceful shutdown via HTTP...");
-                                let client = reqwest::blocking::Client::builder()
-                                    .timeout(std::time::Duration::from_secs(2))
-                                    .build()
-                                    .unwrap();
-
-                                let shutdown_result = client
-                                    .post(&format!("http://127.0.0.1:{}/shutdown", SERVER_PORT))
-                                    .send();
-
-                                if shutdown_result.is_ok() {
-                                    println!("HTTP shutdown sent, waiting for graceful exit...");
-                                    // Wait up to 3 seconds for graceful shutdown
-                                    for i in 0..30 {
-                                        std::thread::sleep(std::time::Duration::from_millis(100));
-                                        if !is_process_running(pid) {
-                                            println!("Process exited gracefully after {}ms", i * 100);
-                                            println!("Server process tree kill completed");
-                                            return;
-                                        }
-                                    }
-                                    println!("Graceful shutdown timed out, forcing kill...");
-                                } else {
-                                    println!("HTTP shutdown failed, forcing kill...");
-                                }
-
-                                // Layer 2: Kill process tree with enumeration
-                                println!("Killing process tree for wrapper PID {}...", pid);
-                                let _ = kill_windows_process_tree(pid);
-
-                                // Layer 3: Verify and kill by name if still running
-                                std::thread::sleep(std::time::Duration::from_millis(200));
-                                if is_process_running(pid) {
-                                    println!("Process tree kill failed, killing by name...");
-                                    use std::process::Command;
-                                    let _ = Command::new("taskkill")
-                                        .args(["/IM", "voicebox-server.exe", "/T", "/F"])
-                                        .output();
-                                }
-
-                                // Layer 4: Final verification
-                                std::thread::sleep(std::time::Duration::from_millis(200));
-                                if is_process_running(pid) {
-                                    eprintln!("WARNING: Failed to kill server after all attempts");
-                                } else {
-                                    println!("Server killed successfully");
-                                }
-                                println!("Server process tree kill completed");
-                            }
+                            // The kill comes out of the grace period, so quitting never hangs on the server
+                            info!("Stopping server with PID: {}", pid);
+                            process_manager::stop_server_process_within(pid, state.port(), stop_grace(app));
                         } else {
-                            println!("No server PID found (already stopped or never started)");
+                            info!("No server PID found (already stopped or never started)");
                         }
                     } else {
-                        println!("Keeping server running per user setting");
+                        info!("Keeping server running per user setting");
+                        // server.json stays behind; if the server idles out, the next launch cleans it up
                     }
-                    println!("=================================================================");
                 }
                 RunEvent::ExitRequested { api, .. } => {
-                    println!("RunEvent::ExitRequested received");
+                    info!("RunEvent::ExitRequested received");
                     // Don't prevent exit, just log it
                     let _ = api;
                 }
+                // Finder hands double-clicked files to the running app, so
+                // they come here on first launch and afterwards alike
+                #[cfg(target_os = "macos")]
+                RunEvent::Opened { urls } => {
+                    let audio: Vec<_> = urls
+                        .iter()
+                        .filter_map(|url| url.to_file_path().ok())
+                        .filter(|path| audio_import::is_audio_file(path))
+                        .collect();
+                    file_import::open(app, audio);
+                }
                 _ => {}
             }
         });
 }
 
 fn main() {
-    run();
+    crash_log::install();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mode = headless::parse_args(args.clone()).and_then(|mode| paths::init(&args).map(|()| mode));
+    match mode {
+        Ok(headless::CliMode::Gui) => run(),
+        Ok(headless::CliMode::Help) => {
+            headless::attach_console();
+            println!("{}", headless::USAGE);
+        }
+        Ok(headless::CliMode::Headless(options)) => {
+            headless::attach_console();
+            let logs_dir = headless::app_data_dir().map(|dir| dir.join("logs"));
+            logging::init(logs_dir.as_deref(), true);
+            if let Some(dir) = logs_dir {
+                crash_log::set_logs_dir(dir);
+            }
+            std::process::exit(headless::run(options));
+        }
+        Err(e) => {
+            headless::attach_console();
+            eprintln!("{}\n\n{}", e, headless::USAGE);
+            std::process::exit(headless::EXIT_USAGE);
+        }
+    }
 }
@@ -0,0 +1,257 @@
+use crate::audio_output::AudioOutputState;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use tracing::{debug, warn};
+
+/// Shown in the now-playing UI for clips played without a title.
+const DEFAULT_TITLE: &str = "Voicebox";
+
+/// What the OS media session shows.
+#[derive(Debug, Clone)]
+struct NowPlaying {
+    title: String,
+    duration: Duration,
+    position: Duration,
+    paused: bool,
+}
+
+enum Request {
+    Update(NowPlaying),
+    Release,
+}
+
+/// The OS media session: media keys and the Now Playing widget on macOS,
+/// the media overlay (SMTC) on Windows, MPRIS on Linux. Voicebox only holds
+/// it while a playback is active, so afterwards the keys go back to the
+/// user's music player.
+pub struct MediaControlsState {
+    /// To the thread that owns the session.
+    requests: Mutex<Option<mpsc::Sender<Request>>>,
+    available: AtomicBool,
+}
+
+impl MediaControlsState {
+    fn new() -> Self {
+        Self {
+            requests: Mutex::new(None),
+            available: AtomicBool::new(platform::SUPPORTED),
+        }
+    }
+
+    /// False on platforms without a media session API, and once claiming
+    /// one has failed (e.g. no D-Bus session on Linux).
+    pub fn is_available(&self) -> bool {
+        self.available.load(Ordering::Relaxed)
+    }
+}
+
+/// Start the thread that claims and releases the session. Called from setup.
+pub fn init(app: &AppHandle) {
+    app.manage(MediaControlsState::new());
+    if !platform::SUPPORTED {
+        return;
+    }
+    let (sender, receiver) = mpsc::channel();
+    let handle = app.clone();
+    let spawned = std::thread::Builder::new()
+        .name("media-controls".to_string())
+        .spawn(move || run(&handle, receiver));
+    let state = app.state::<MediaControlsState>();
+    match spawned {
+        Ok(_) => *state.requests.lock().unwrap() = Some(sender),
+        Err(e) => {
+            warn!("Failed to start the media controls thread: {}", e);
+            state.available.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Publish the current playback to the session, claiming it if needed, or
+/// release the session once nothing plays. Called as playback progresses.
+pub fn sync(app: &AppHandle) {
+    let Some(state) = app.try_state::<MediaControlsState>() else {
+        return;
+    };
+    if !state.is_available() {
+        return;
+    }
+    let request = match app.state::<AudioOutputState>().current_playback() {
+        Some(playback) => Request::Update(NowPlaying {
+            title: playback.title().unwrap_or(DEFAULT_TITLE).to_string(),
+            duration: playback.duration(),
+            position: playback.position(),
+            paused: playback.is_paused(),
+        }),
+        None => Request::Release,
+    };
+    if let Some(requests) = state.requests.lock().unwrap().as_ref() {
+        // Fails only once the thread has given up on the session
+        let _ = requests.send(request);
+    }
+}
+
+/// Owns the session, which isn't Send on every platform.
+fn run(app: &AppHandle, requests: mpsc::Receiver<Request>) {
+    let mut session: Option<platform::Session> = None;
+    for request in requests {
+        match request {
+            Request::Update(now_playing) => {
+                if session.is_none() {
+                    match platform::Session::claim(app, on_event) {
+                        Ok(claimed) => {
+                            debug!("Claimed the media session");
+                            session = Some(claimed);
+                        }
+                        Err(e) => {
+                            warn!("Media controls are unavailable: {}", e);
+                            app.state::<MediaControlsState>().available.store(false, Ordering::Relaxed);
+                            return;
+                        }
+                    }
+                }
+                if let Some(session) = session.as_mut() {
+                    if let Err(e) = session.publish(&now_playing) {
+                        warn!("Failed to update the media session: {}", e);
+                    }
+                }
+            }
+            Request::Release => {
+                if let Some(session) = session.take() {
+                    session.release();
+                    debug!("Released the media session");
+                }
+            }
+        }
+    }
+}
+
+/// Route a media key or now-playing button to playback.
+fn on_event(app: &AppHandle, action: MediaAction) {
+    let output = app.state::<AudioOutputState>();
+    let result = match action {
+        MediaAction::Play => output.resume_playback(),
+        MediaAction::Pause => output.pause_playback(),
+        MediaAction::Toggle => output.toggle_pause(),
+        MediaAction::Stop => output.stop_all_playback(),
+    };
+    match result {
+        Ok(()) => sync(app),
+        // A key pressed just as the playback ended
+        Err(e) => debug!("Ignored media {:?}: {}", action, e),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MediaAction {
+    Play,
+    Pause,
+    Toggle,
+    Stop,
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod platform {
+    use super::{MediaAction, NowPlaying, DEFAULT_TITLE};
+    use souvlaki::{MediaControlEvent, MediaControls, MediaMetadata, MediaPlayback, MediaPosition, PlatformConfig};
+    use std::time::Duration;
+    use tauri::AppHandle;
+
+    pub const SUPPORTED: bool = true;
+
+    pub struct Session {
+        controls: MediaControls,
+        /// Title and duration last set, so progress updates don't resend them.
+        metadata: Option<(String, Duration)>,
+    }
+
+    impl Session {
+        pub fn claim(app: &AppHandle, on_event: fn(&AppHandle, MediaAction)) -> Result<Self, String> {
+            // SMTC is tied to a window on Windows
+            #[cfg(target_os = "windows")]
+            let hwnd = {
+                use tauri::Manager;
+                let window = app.get_webview_window("main").ok_or("There is no main window")?;
+                Some(window.hwnd().map_err(|e| e.to_string())?.0)
+            };
+            #[cfg(not(target_os = "windows"))]
+            let hwnd = None;
+
+            let config = PlatformConfig {
+                dbus_name: "voicebox",
+                display_name: DEFAULT_TITLE,
+                hwnd,
+            };
+            let mut controls = MediaControls::new(config).map_err(|e| format!("{:?}", e))?;
+            let handle = app.clone();
+            controls
+                .attach(move |event| {
+                    let action = match event {
+                        MediaControlEvent::Play => MediaAction::Play,
+                        MediaControlEvent::Pause => MediaAction::Pause,
+                        MediaControlEvent::Toggle => MediaAction::Toggle,
+                        MediaControlEvent::Stop => MediaAction::Stop,
+                        _ => return,
+                    };
+                    on_event(&handle, action);
+                })
+                .map_err(|e| format!("{:?}", e))?;
+            Ok(Self {
+                controls,
+                metadata: None,
+            })
+        }
+
+        pub fn publish(&mut self, now_playing: &NowPlaying) -> Result<(), String> {
+            let metadata = (now_playing.title.clone(), now_playing.duration);
+            if self.metadata.as_ref() != Some(&metadata) {
+                self.controls
+                    .set_metadata(MediaMetadata {
+                        title: Some(&now_playing.title),
+                        artist: Some(DEFAULT_TITLE),
+                        duration: Some(now_playing.duration),
+                        ..Default::default()
+                    })
+                    .map_err(|e| format!("{:?}", e))?;
+                self.metadata = Some(metadata);
+            }
+            let progress = Some(MediaPosition(now_playing.position));
+            let playback = if now_playing.paused {
+                MediaPlayback::Paused { progress }
+            } else {
+                MediaPlayback::Playing { progress }
+            };
+            self.controls.set_playback(playback).map_err(|e| format!("{:?}", e))
+        }
+
+        pub fn release(mut self) {
+            let _ = self.controls.set_playback(MediaPlayback::Stopped);
+            if let Err(e) = self.controls.detach() {
+                tracing::warn!("Failed to release the media session: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "ios"))]
+mod platform {
+    use super::{MediaAction, NowPlaying};
+    use tauri::AppHandle;
+
+    pub const SUPPORTED: bool = false;
+
+    pub struct Session;
+
+    impl Session {
+        pub fn claim(_app: &AppHandle, _on_event: fn(&AppHandle, MediaAction)) -> Result<Self, String> {
+            Err("Media controls aren't supported on this platform".to_string())
+        }
+
+        pub fn publish(&mut self, _now_playing: &NowPlaying) -> Result<(), String> {
+            Ok(())
+        }
+
+        pub fn release(self) {}
+    }
+}
@@ -0,0 +1,337 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters for this run of the app, for get_metrics and the diagnostics
+/// bundle. A process-wide static so capture and playback callbacks can bump
+/// them directly; each update is a relaxed atomic add, cheap enough to leave
+/// on everywhere.
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn reset(&self) {
+        self.0.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Power-of-two buckets: bucket `i` holds values below 2^i that didn't fit
+/// the bucket before, so 0, 1, 2-3, 4-7, and so on up to u64::MAX.
+const BUCKETS: usize = 65;
+
+pub struct Histogram {
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl Histogram {
+    pub const fn new() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+            buckets: [const { AtomicU64::new(0) }; BUCKETS],
+        }
+    }
+
+    pub fn record(&self, value: u64) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+        self.buckets[(u64::BITS - value.leading_zeros()) as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum.load(Ordering::Relaxed);
+        let buckets = self
+            .buckets
+            .iter()
+            .enumerate()
+            .filter_map(|(i, bucket)| {
+                let count = bucket.load(Ordering::Relaxed);
+                let le = if i == 64 { u64::MAX } else { (1u64 << i) - 1 };
+                (count > 0).then_some(Bucket { le, count })
+            })
+            .collect();
+        HistogramSnapshot {
+            count,
+            sum,
+            mean: (count > 0).then(|| sum as f64 / count as f64),
+            max: self.max.load(Ordering::Relaxed),
+            buckets,
+        }
+    }
+
+    fn reset(&self) {
+        self.count.store(0, Ordering::Relaxed);
+        self.sum.store(0, Ordering::Relaxed);
+        self.max.store(0, Ordering::Relaxed);
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Bucket {
+    /// Values up to and including this one, above the previous bucket's.
+    pub le: u64,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistogramSnapshot {
+    pub count: u64,
+    pub sum: u64,
+    pub mean: Option<f64>,
+    pub max: u64,
+    /// Only the buckets that have values.
+    pub buckets: Vec<Bucket>,
+}
+
+pub struct Metrics {
+    pub captures_started: Counter,
+    /// Captures that couldn't start or ended with an error.
+    pub captures_failed: Counter,
+    pub frames_captured: Counter,
+    /// Gaps in captured audio the backend noticed, such as a WASAPI data
+    /// discontinuity.
+    pub capture_dropouts: Counter,
+    /// Frames lost in those gaps, where the backend can tell how many.
+    pub frames_dropped: Counter,
+    /// Deliveries that waited on the capture buffer's lock.
+    pub capture_lock_contentions: Counter,
+    /// Frames queued on the device at each WASAPI read.
+    pub capture_lag_frames: Histogram,
+    pub capture_late_wakeups: Counter,
+    pub capture_callback_gaps: Counter,
+    /// Captures carried over to another device after losing theirs.
+    pub capture_device_switches: Counter,
+    /// Captures paused by auto-pause for source silence.
+    pub capture_auto_pauses: Counter,
+    /// One per device a clip started on.
+    pub playbacks_started: Counter,
+    /// Of those, clips that joined the device's open stream.
+    pub playback_streams_reused: Counter,
+    /// Device opens that failed once and were tried again.
+    pub playback_open_retries: Counter,
+    /// Bluetooth devices played to on their hands-free profile after the
+    /// retries.
+    pub playback_degraded_profiles: Counter,
+    pub frames_played: Counter,
+    /// Stream errors from output devices, which is how cpal reports
+    /// underruns (ALSA xruns, for example).
+    pub playback_underruns: Counter,
+    pub server_starts: Counter,
+    /// Starts that followed a crash of the server we own.
+    pub server_restarts: Counter,
+    pub server_crashes: Counter,
+    /// From spawning the sidecar to it reporting ready.
+    pub server_startup_ms: Histogram,
+    /// Raw bytes the sidecar wrote to stdout and stderr.
+    pub server_output_bytes: Counter,
+    /// Of those, bytes cut from lines over server_output::MAX_LINE_BYTES.
+    pub server_output_truncated_bytes: Counter,
+    /// Progress bar redraws folded into single log lines.
+    pub server_progress_updates: Counter,
+    /// Server output lines that skipped the `server-log` event because the
+    /// webview fell behind. They are still kept in the server log.
+    pub server_log_events_dropped: Counter,
+    /// Audio passed between the UI and the app: clips sent for playback and
+    /// captures returned.
+    pub ipc_payload_bytes: Histogram,
+    /// Requests to the local control API, refused ones included.
+    pub control_api_requests: Counter,
+    /// Of those, requests answered with an error, a wrong token included.
+    pub control_api_errors: Counter,
+    pub control_api_request_ms: Histogram,
+    reset_at: AtomicU64,
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Self {
+            captures_started: Counter::new(),
+            captures_failed: Counter::new(),
+            frames_captured: Counter::new(),
+            capture_dropouts: Counter::new(),
+            frames_dropped: Counter::new(),
+            capture_lock_contentions: Counter::new(),
+            capture_lag_frames: Histogram::new(),
+            capture_late_wakeups: Counter::new(),
+            capture_callback_gaps: Counter::new(),
+            capture_device_switches: Counter::new(),
+            capture_auto_pauses: Counter::new(),
+            playbacks_started: Counter::new(),
+            playback_streams_reused: Counter::new(),
+            playback_open_retries: Counter::new(),
+            playback_degraded_profiles: Counter::new(),
+            frames_played: Counter::new(),
+            playback_underruns: Counter::new(),
+            server_starts: Counter::new(),
+            server_restarts: Counter::new(),
+            server_crashes: Counter::new(),
+            server_startup_ms: Histogram::new(),
+            server_output_bytes: Counter::new(),
+            server_output_truncated_bytes: Counter::new(),
+            server_progress_updates: Counter::new(),
+            server_log_events_dropped: Counter::new(),
+            ipc_payload_bytes: Histogram::new(),
+            control_api_requests: Counter::new(),
+            control_api_errors: Counter::new(),
+            control_api_request_ms: Histogram::new(),
+            reset_at: AtomicU64::new(0),
+        }
+    }
+
+    /// Read every counter. Updates racing with it may land on either side,
+    /// so related counters can be off by the updates in flight.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let reset_at = self.reset_at.load(Ordering::Relaxed);
+        MetricsSnapshot {
+            reset_at: (reset_at > 0).then_some(reset_at),
+            captures_started: self.captures_started.get(),
+            captures_failed: self.captures_failed.get(),
+            frames_captured: self.frames_captured.get(),
+            capture_dropouts: self.capture_dropouts.get(),
+            frames_dropped: self.frames_dropped.get(),
+            capture_lock_contentions: self.capture_lock_contentions.get(),
+            capture_lag_frames: self.capture_lag_frames.snapshot(),
+            capture_late_wakeups: self.capture_late_wakeups.get(),
+            capture_callback_gaps: self.capture_callback_gaps.get(),
+            capture_device_switches: self.capture_device_switches.get(),
+            capture_auto_pauses: self.capture_auto_pauses.get(),
+            playbacks_started: self.playbacks_started.get(),
+            playback_streams_reused: self.playback_streams_reused.get(),
+            playback_open_retries: self.playback_open_retries.get(),
+            playback_degraded_profiles: self.playback_degraded_profiles.get(),
+            frames_played: self.frames_played.get(),
+            playback_underruns: self.playback_underruns.get(),
+            server_starts: self.server_starts.get(),
+            server_restarts: self.server_restarts.get(),
+            server_crashes: self.server_crashes.get(),
+            server_startup_ms: self.server_startup_ms.snapshot(),
+            server_output_bytes: self.server_output_bytes.get(),
+            server_output_truncated_bytes: self.server_output_truncated_bytes.get(),
+            server_progress_updates: self.server_progress_updates.get(),
+            server_log_events_dropped: self.server_log_events_dropped.get(),
+            ipc_payload_bytes: self.ipc_payload_bytes.snapshot(),
+            control_api_requests: self.control_api_requests.get(),
+            control_api_errors: self.control_api_errors.get(),
+            control_api_request_ms: self.control_api_request_ms.snapshot(),
+        }
+    }
+
+    pub fn reset(&self) {
+        for counter in [
+            &self.captures_started,
+            &self.captures_failed,
+            &self.frames_captured,
+            &self.capture_dropouts,
+            &self.frames_dropped,
+            &self.capture_lock_contentions,
+            &self.capture_late_wakeups,
+            &self.capture_callback_gaps,
+            &self.capture_device_switches,
+            &self.capture_auto_pauses,
+            &self.playbacks_started,
+            &self.playback_streams_reused,
+            &self.playback_open_retries,
+            &self.playback_degraded_profiles,
+            &self.frames_played,
+            &self.playback_underruns,
+            &self.server_starts,
+            &self.server_restarts,
+            &self.server_crashes,
+            &self.server_output_bytes,
+            &self.server_output_truncated_bytes,
+            &self.server_progress_updates,
+            &self.server_log_events_dropped,
+            &self.control_api_requests,
+            &self.control_api_errors,
+        ] {
+            counter.reset();
+        }
+        self.capture_lag_frames.reset();
+        self.server_startup_ms.reset();
+        self.ipc_payload_bytes.reset();
+        self.control_api_request_ms.reset();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.reset_at.store(now, Ordering::Relaxed);
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetricsSnapshot {
+    /// Unix seconds of the last reset_metrics; None when counting since launch.
+    pub reset_at: Option<u64>,
+    pub captures_started: u64,
+    pub captures_failed: u64,
+    pub frames_captured: u64,
+    pub capture_dropouts: u64,
+    pub frames_dropped: u64,
+    pub capture_lock_contentions: u64,
+    pub capture_lag_frames: HistogramSnapshot,
+    pub capture_late_wakeups: u64,
+    pub capture_callback_gaps: u64,
+    pub capture_device_switches: u64,
+    pub capture_auto_pauses: u64,
+    pub playbacks_started: u64,
+    pub playback_streams_reused: u64,
+    pub playback_open_retries: u64,
+    pub playback_degraded_profiles: u64,
+    pub frames_played: u64,
+    pub playback_underruns: u64,
+    pub server_starts: u64,
+    pub server_restarts: u64,
+    pub server_crashes: u64,
+    pub server_startup_ms: HistogramSnapshot,
+    pub server_output_bytes: u64,
+    pub server_output_truncated_bytes: u64,
+    pub server_progress_updates: u64,
+    pub server_log_events_dropped: u64,
+    pub ipc_payload_bytes: HistogramSnapshot,
+    pub control_api_requests: u64,
+    pub control_api_errors: u64,
+    pub control_api_request_ms: HistogramSnapshot,
+}
@@ -0,0 +1,157 @@
+use crate::settings::{SettingsState, WindowPosition};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, PhysicalPosition, WebviewUrl, WebviewWindow, WebviewWindowBuilder, Window, WindowEvent};
+use tracing::warn;
+
+pub const LABEL: &str = "mini-recorder";
+
+/// Frontend route rendering the recorder controls without the app chrome.
+const ROUTE: &str = "/mini-recorder";
+
+const WIDTH: f64 = 300.0;
+const HEIGHT: f64 = 76.0;
+
+/// Gap between the cursor and the window when opened without a saved position.
+const CURSOR_OFFSET_PX: i32 = 16;
+
+const SAVE_DEBOUNCE_MS: u64 = 500;
+
+pub struct MiniRecorderState {
+    /// Held while the window exists, so a second open focuses it instead.
+    window: Mutex<Option<WebviewWindow>>,
+    /// Bumped on every move; a pending save only runs if no newer one came in.
+    generation: AtomicU64,
+}
+
+impl MiniRecorderState {
+    pub fn new() -> Self {
+        Self {
+            window: Mutex::new(None),
+            generation: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Open the always-on-top recorder, or bring the open one forward.
+pub fn open(app: &AppHandle) -> Result<(), String> {
+    let state = app.state::<MiniRecorderState>();
+    let mut slot = state.window.lock().unwrap();
+    if let Some(window) = slot.as_ref() {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+        return Ok(());
+    }
+
+    let window = WebviewWindowBuilder::new(app, LABEL, WebviewUrl::App(ROUTE.into()))
+        .title("Voicebox Recorder")
+        .inner_size(WIDTH, HEIGHT)
+        .resizable(false)
+        .maximizable(false)
+        .minimizable(false)
+        .decorations(false)
+        .always_on_top(true)
+        .visible_on_all_workspaces(true)
+        .skip_taskbar(true)
+        // Shown once it is in place, so it doesn't flash at the default position
+        .visible(false)
+        .build()
+        .map_err(|e| format!("Failed to open the mini recorder: {}", e))?;
+
+    let position = saved_position(app, &window).or_else(|| near_cursor(app, &window));
+    if let Some(position) = position {
+        let _ = window.set_position(position);
+    } else {
+        let _ = window.center();
+    }
+    let _ = window.show();
+    let _ = window.set_focus();
+
+    *slot = Some(window);
+    Ok(())
+}
+
+pub fn close(app: &AppHandle) {
+    let window = app.state::<MiniRecorderState>().window.lock().unwrap().take();
+    if let Some(window) = window {
+        save_position(app, window.outer_position());
+        let _ = window.destroy();
+    }
+}
+
+/// The saved position, if it still lands on a connected monitor.
+fn saved_position(app: &AppHandle, window: &WebviewWindow) -> Option<PhysicalPosition<i32>> {
+    let saved = app.state::<SettingsState>().get().mini_recorder_position?;
+    let size = window.outer_size().ok()?;
+    let on_screen = window.available_monitors().ok()?.iter().any(|m| {
+        let origin = m.position();
+        saved.x >= origin.x
+            && saved.y >= origin.y
+            && saved.x + size.width as i32 <= origin.x + m.size().width as i32
+            && saved.y + size.height as i32 <= origin.y + m.size().height as i32
+    });
+    on_screen.then(|| PhysicalPosition::new(saved.x, saved.y))
+}
+
+/// Just above the cursor, which is usually on the tray icon that opened it,
+/// kept inside the monitor's work area.
+fn near_cursor(app: &AppHandle, window: &WebviewWindow) -> Option<PhysicalPosition<i32>> {
+    let cursor = app.cursor_position().ok()?;
+    let monitor = app.monitor_from_point(cursor.x, cursor.y).ok()??;
+    let size = window.outer_size().ok()?;
+    let area = monitor.work_area();
+
+    let min_x = area.position.x;
+    let max_x = (area.position.x + area.size.width as i32 - size.width as i32).max(min_x);
+    let min_y = area.position.y;
+    let max_y = (area.position.y + area.size.height as i32 - size.height as i32).max(min_y);
+
+    let x = cursor.x as i32 - size.width as i32 / 2;
+    // Below the cursor when there is no room above (a tray at the top of the screen)
+    let above = cursor.y as i32 - size.height as i32 - CURSOR_OFFSET_PX;
+    let y = if above >= min_y { above } else { cursor.y as i32 + CURSOR_OFFSET_PX };
+    Some(PhysicalPosition::new(x.clamp(min_x, max_x), y.clamp(min_y, max_y)))
+}
+
+/// Hook for the builder's window event handler.
+pub fn on_window_event(window: &Window, event: &WindowEvent) {
+    if window.label() != LABEL {
+        return;
+    }
+    match event {
+        WindowEvent::Moved(_) => {
+            let state = window.state::<MiniRecorderState>();
+            let generation = state.generation.fetch_add(1, Ordering::SeqCst) + 1;
+            let window = window.clone();
+            tauri::async_runtime::spawn(async move {
+                tokio::time::sleep(tokio::time::Duration::from_millis(SAVE_DEBOUNCE_MS)).await;
+                if window.state::<MiniRecorderState>().generation.load(Ordering::SeqCst) == generation {
+                    save_position(window.app_handle(), window.outer_position());
+                }
+            });
+        }
+        WindowEvent::CloseRequested { .. } => save_position(window.app_handle(), window.outer_position()),
+        WindowEvent::Destroyed => {
+            let _ = window.state::<MiniRecorderState>().window.lock().unwrap().take();
+        }
+        _ => {}
+    }
+}
+
+fn save_position(app: &AppHandle, position: tauri::Result<PhysicalPosition<i32>>) {
+    let Ok(position) = position else {
+        return;
+    };
+    let position = WindowPosition {
+        x: position.x,
+        y: position.y,
+    };
+    let settings = app.state::<SettingsState>();
+    if settings.get().mini_recorder_position.as_ref() == Some(&position) {
+        return;
+    }
+    if let Err(e) = settings.update(|s| s.mini_recorder_position = Some(position)) {
+        warn!("Failed to save mini recorder position: {}", e);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::audio_capture::AudioCaptureState;
+use crate::settings::{RecentItem, RecentKind, SettingsState};
+use std::path::Path;
+use tauri::{AppHandle, Manager};
+use tracing::warn;
+
+/// Entries kept per kind; older ones fall off the end.
+pub const MAX_PER_KIND: usize = 20;
+
+/// Record `target` as the most recently used of its kind. A target that is an
+/// absolute path is treated as a file and checked for existence on read.
+pub fn add(app: &AppHandle, kind: RecentKind, target: &str, label: &str) -> Result<(), String> {
+    if target.is_empty() {
+        return Err("A recent item needs a path or id".to_string());
+    }
+    let item = RecentItem {
+        kind,
+        target: target.to_string(),
+        label: label.to_string(),
+        is_file: Path::new(target).is_absolute(),
+        used_at: crate::process_manager::unix_timestamp(),
+    };
+    let is_file = item.is_file;
+    app.state::<SettingsState>().update(|s| {
+        s.recent_items.retain(|existing| !(existing.kind == kind && existing.target == item.target));
+        s.recent_items.insert(0, item);
+        let mut kept = 0;
+        s.recent_items.retain(|existing| {
+            if existing.kind != kind {
+                return true;
+            }
+            kept += 1;
+            kept <= MAX_PER_KIND
+        });
+    })?;
+
+    if is_file {
+        refresh_menu(app);
+        #[cfg(target_os = "macos")]
+        crate::app_menu::note_recent_document(Path::new(target));
+    }
+    Ok(())
+}
+
+/// Up to `limit` entries of `kind`, newest first. Files that were deleted and
+/// captures that are no longer kept are pruned first.
+pub fn get(app: &AppHandle, kind: RecentKind, limit: usize) -> Result<Vec<RecentItem>, String> {
+    let settings = app.state::<SettingsState>();
+    let items = settings.get().recent_items;
+    let (live, dead): (Vec<RecentItem>, Vec<RecentItem>) = items.into_iter().partition(|item| is_live(app, item));
+    if !dead.is_empty() {
+        settings.update(|s| s.recent_items.retain(|item| !dead.contains(item)))?;
+        refresh_menu(app);
+    }
+    Ok(live.into_iter().filter(|item| item.kind == kind).take(limit).collect())
+}
+
+/// Forget the entries of `kind`, or all of them.
+pub fn clear(app: &AppHandle, kind: Option<RecentKind>) -> Result<(), String> {
+    app.state::<SettingsState>()
+        .update(|s| s.recent_items.retain(|item| kind.is_some_and(|kind| item.kind != kind)))?;
+    refresh_menu(app);
+    #[cfg(target_os = "macos")]
+    if kind.is_none() {
+        crate::app_menu::clear_recent_documents();
+    }
+    Ok(())
+}
+
+fn is_live(app: &AppHandle, item: &RecentItem) -> bool {
+    if item.is_file {
+        return Path::new(&item.target).is_file();
+    }
+    match item.kind {
+        // Captures are kept in memory, so they don't survive a restart
+        RecentKind::Recording => item
+            .target
+            .parse::<u64>()
+            .is_ok_and(|session| app.state::<AudioCaptureState>().has_recording(session)),
+        _ => true,
+    }
+}
+
+/// Rebuild the Open Recent menu from the file entries. macOS only; other
+/// platforms have no menu bar.
+fn refresh_menu(app: &AppHandle) {
+    #[cfg(target_os = "macos")]
+    {
+        let files: Vec<RecentItem> = app
+            .state::<SettingsState>()
+            .get()
+            .recent_items
+            .into_iter()
+            .filter(|item| item.is_file)
+            .collect();
+        if let Err(e) = crate::app_menu::set_recent_files(app, &files) {
+            warn!("Failed to update the Open Recent menu: {}", e);
+        }
+    }
+    #[cfg(not(target_os = "macos"))]
+    let _ = app;
+}
+
+/// Record a finished operation without failing it when the list can't be saved.
+pub fn note(app: &AppHandle, kind: RecentKind, target: &str, label: &str) {
+    if let Err(e) = add(app, kind, target, label) {
+        warn!("Failed to update recent items: {}", e);
+    }
+}
+
+/// The file name of `path`, for labels.
+pub fn file_label(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
@@ -0,0 +1,233 @@
+use crate::settings::{NotificationPrefs, SettingsState};
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager};
+use tauri_plugin_notification::NotificationExt;
+use tracing::warn;
+
+/// The notification plugin has no click callback on desktop, but clicking a
+/// notification brings the app forward. A focus this soon after showing one
+/// is treated as its activation.
+const ACTIVATION_WINDOW: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationCategory {
+    CaptureAutoStopped,
+    CaptureFailed,
+    ServerCrashed,
+    ServerReadyAfterRestart,
+    ModelDownloadComplete,
+    ImportFailed,
+    ScheduledCapture,
+}
+
+impl NotificationCategory {
+    fn enabled(&self, prefs: &NotificationPrefs) -> bool {
+        match self {
+            NotificationCategory::CaptureAutoStopped => prefs.capture_auto_stopped,
+            NotificationCategory::CaptureFailed => prefs.capture_failed,
+            NotificationCategory::ServerCrashed => prefs.server_crashed,
+            NotificationCategory::ServerReadyAfterRestart => prefs.server_ready_after_restart,
+            NotificationCategory::ModelDownloadComplete => prefs.model_download_complete,
+            NotificationCategory::ImportFailed => prefs.import_failed,
+            NotificationCategory::ScheduledCapture => prefs.scheduled_capture,
+        }
+    }
+
+    /// Import failures are shown even over a focused window: opening a file
+    /// raises the window, and nothing else in it reports the failure. Nor
+    /// does anything report scheduled captures, which run unattended and
+    /// report missed runs just as the window opens.
+    fn shown_when_focused(&self) -> bool {
+        matches!(self, NotificationCategory::ImportFailed | NotificationCategory::ScheduledCapture)
+    }
+}
+
+/// The most recent notification, for attributing the next window focus to it.
+pub struct NotificationState {
+    last: Mutex<Option<(NotificationCategory, Instant)>>,
+}
+
+impl NotificationState {
+    pub fn new() -> Self {
+        Self {
+            last: Mutex::new(None),
+        }
+    }
+}
+
+/// Show a notification unless its category is disabled or the user is
+/// already looking at the window (see shown_when_focused). Callers pass
+/// fixed text: no paths, tokens, or raw server output.
+pub fn notify(app: &AppHandle, category: NotificationCategory, title: &str, body: &str) {
+    let prefs = app.state::<SettingsState>().get().notifications;
+    if !category.enabled(&prefs) {
+        return;
+    }
+    let window_focused = app
+        .get_webview_window("main")
+        .map(|w| w.is_visible().unwrap_or(false) && w.is_focused().unwrap_or(false))
+        .unwrap_or(false);
+    if window_focused && !category.shown_when_focused() {
+        return;
+    }
+
+    if let Err(e) = app.notification().builder().title(title).body(body).show() {
+        warn!("Failed to show notification: {}", e);
+        return;
+    }
+    *app.state::<NotificationState>().last.lock().unwrap() = Some((category, Instant::now()));
+}
+
+/// Called when the main window gains focus. Emits `notification-activated`
+/// if that focus most likely came from clicking our last notification.
+pub fn on_window_focused(app: &AppHandle) {
+    let last = app.state::<NotificationState>().last.lock().unwrap().take();
+    if let Some((category, shown_at)) = last {
+        if shown_at.elapsed() <= ACTIVATION_WINDOW {
+            let payload = serde_json::json!({ "category": category });
+            if let Err(e) = crate::event_bus::emit(app, "notification-activated", payload) {
+                warn!("Failed to emit notification-activated event: {}", e);
+            }
+        }
+    }
+}
+
+/// Turn app events into notifications. Called from setup.
+pub fn install(app: &AppHandle) {
+    let handle = app.clone();
+    crate::event_bus::listen(app, "capture-auto-stopped", move |payload| {
+        let sleep = serde_json::from_str::<serde_json::Value>(payload)
+            .ok()
+            .is_some_and(|v| v["reason"] == "sleep");
+        let body = if sleep {
+            "System audio capture was stopped because the computer went to sleep."
+        } else {
+            "System audio capture reached its maximum length."
+        };
+        notify(&handle, NotificationCategory::CaptureAutoStopped, "Capture stopped", body);
+    });
+
+    let handle = app.clone();
+    crate::event_bus::listen(app, "capture-failed", move |_| {
+        notify(
+            &handle,
+            NotificationCategory::CaptureFailed,
+            "Capture failed",
+            "System audio capture could not be completed.",
+        );
+    });
+
+    let handle = app.clone();
+    crate::event_bus::listen(app, "server-crashed", move |payload| {
+        // Only the reason is used; the payload also carries log lines
+        let out_of_memory = serde_json::from_str::<serde_json::Value>(payload)
+            .ok()
+            .and_then(|v| v["reason"]["type"].as_str().map(|t| t == "out_of_memory"))
+            .unwrap_or(false);
+        let body = if out_of_memory {
+            "The Voicebox server ran out of memory and stopped."
+        } else {
+            "The Voicebox server stopped unexpectedly."
+        };
+        notify(&handle, NotificationCategory::ServerCrashed, "Server crashed", body);
+    });
+
+    let handle = app.clone();
+    crate::event_bus::listen(app, "server-ready-after-restart", move |_| {
+        notify(
+            &handle,
+            NotificationCategory::ServerReadyAfterRestart,
+            "Server is back",
+            "The Voicebox server restarted and is ready.",
+        );
+    });
+
+    // Emitted by the frontend, which tracks downloads through the server API
+    let handle = app.clone();
+    crate::event_bus::listen(app, "model-download-complete", move |payload| {
+        let model = serde_json::from_str::<serde_json::Value>(payload)
+            .ok()
+            .and_then(|v| v["model_name"].as_str().map(|s| s.to_string()))
+            .filter(|name| is_display_safe(name));
+        let body = match model {
+            Some(name) => format!("{} is ready to use.", name),
+            None => "The model is ready to use.".to_string(),
+        };
+        notify(&handle, NotificationCategory::ModelDownloadComplete, "Download complete", &body);
+    });
+
+    let handle = app.clone();
+    crate::event_bus::listen(app, "audio-import-finished", move |payload| {
+        // Counts only; file names stay out of the notification
+        let counts = serde_json::from_str::<serde_json::Value>(payload).ok().map(|v| {
+            let len = |key: &str| v[key].as_array().map_or(0, |a| a.len());
+            (len("failed"), len("imported") + len("failed"))
+        });
+        let Some((failed, total)) = counts.filter(|(failed, _)| *failed > 0) else {
+            return;
+        };
+        let body = if total == 1 {
+            "The file could not be imported as audio.".to_string()
+        } else {
+            format!("{} of {} files could not be imported as audio.", failed, total)
+        };
+        notify(&handle, NotificationCategory::ImportFailed, "Import failed", &body);
+    });
+
+    let handle = app.clone();
+    crate::event_bus::listen(app, "scheduled-capture-started", move |payload| {
+        let body = match schedule_label(payload) {
+            Some(label) => format!("Recording \"{}\".", label),
+            None => "A scheduled capture is recording.".to_string(),
+        };
+        notify(&handle, NotificationCategory::ScheduledCapture, "Scheduled capture started", &body);
+    });
+
+    let handle = app.clone();
+    crate::event_bus::listen(app, "scheduled-capture-finished", move |payload| {
+        let failed = serde_json::from_str::<serde_json::Value>(payload)
+            .ok()
+            .is_some_and(|v| !v["error"].is_null());
+        let label = schedule_label(payload).unwrap_or_else(|| "The scheduled capture".to_string());
+        let (title, body) = if failed {
+            ("Scheduled capture failed", format!("{} could not be recorded.", label))
+        } else {
+            ("Scheduled capture finished", format!("{} was recorded and saved.", label))
+        };
+        notify(&handle, NotificationCategory::ScheduledCapture, title, &body);
+    });
+
+    let handle = app.clone();
+    crate::event_bus::listen(app, "scheduled-capture-missed", move |payload| {
+        let missed = serde_json::from_str::<serde_json::Value>(payload)
+            .ok()
+            .and_then(|v| v["missed_at"].as_array().map(|a| a.len()))
+            .unwrap_or(0);
+        let label = schedule_label(payload).unwrap_or_else(|| "A scheduled capture".to_string());
+        let body = if missed == 1 {
+            format!("{} did not run at its scheduled time.", label)
+        } else {
+            format!("{} did not run at {} of its scheduled times.", label, missed)
+        };
+        notify(&handle, NotificationCategory::ScheduledCapture, "Scheduled capture missed", &body);
+    });
+}
+
+/// The label of a scheduled-capture event, if it is safe to show.
+fn schedule_label(payload: &str) -> Option<String> {
+    serde_json::from_str::<serde_json::Value>(payload)
+        .ok()
+        .and_then(|v| v["label"].as_str().map(str::to_string))
+        .filter(|label| !label.is_empty() && is_display_safe(label))
+}
+
+/// A model name is shown only if it can't be a path or carry a secret.
+fn is_display_safe(name: &str) -> bool {
+    name.len() <= 80
+        && !name.contains(['\\', '?', '=', '@'])
+        && !name.starts_with('/')
+        && name.chars().all(|c| !c.is_control())
+}
@@ -0,0 +1,144 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tauri::{AppHandle, Manager};
+
+pub const DATA_DIR_FLAG: &str = "--data-dir";
+
+/// Next to the executable, switches to portable mode. Empty means a `data`
+/// folder beside it; otherwise the first line names the folder, relative to
+/// the executable's.
+pub const PORTABLE_MARKER: &str = "portable.txt";
+const PORTABLE_DEFAULT_DIR: &str = "data";
+
+static OVERRIDE: OnceLock<(PathsMode, PathBuf)> = OnceLock::new();
+
+/// Where the app data dir came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PathsMode {
+    /// The per-user directory Tauri picks.
+    Default,
+    /// `--data-dir <path>` on the command line.
+    Argument,
+    /// A portable.txt marker next to the executable.
+    Portable,
+}
+
+/// What get_app_paths reports for status and diagnostics.
+#[derive(Debug, Clone, Serialize)]
+pub struct AppPaths {
+    pub mode: PathsMode,
+    pub app_data_dir: PathBuf,
+    pub logs_dir: PathBuf,
+}
+
+/// Resolve the override from the launch arguments (after the program name)
+/// and the portable marker, once, before the app is built. An override that
+/// isn't absolute or writable is an error rather than a silent fallback, so a
+/// portable install never writes to the host machine.
+pub fn init(args: &[String]) -> Result<(), String> {
+    let resolved = match data_dir_argument(args)? {
+        Some(dir) => Some((PathsMode::Argument, dir)),
+        None => portable_dir()?.map(|dir| (PathsMode::Portable, dir)),
+    };
+    if let Some((mode, dir)) = resolved {
+        validate(&dir)?;
+        let _ = OVERRIDE.set((mode, dir));
+    }
+    Ok(())
+}
+
+fn data_dir_argument(args: &[String]) -> Result<Option<PathBuf>, String> {
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let value = if arg == DATA_DIR_FLAG {
+            iter.next().cloned()
+        } else if let Some(value) = arg.strip_prefix(DATA_DIR_FLAG).and_then(|rest| rest.strip_prefix('=')) {
+            Some(value.to_string())
+        } else {
+            continue;
+        };
+        let dir = value
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+            .ok_or_else(|| format!("{} needs a path", DATA_DIR_FLAG))?;
+        if !dir.is_absolute() {
+            return Err(format!("{} must be an absolute path: {}", DATA_DIR_FLAG, dir.display()));
+        }
+        return Ok(Some(dir));
+    }
+    Ok(None)
+}
+
+fn portable_dir() -> Result<Option<PathBuf>, String> {
+    let Some(exe_dir) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+    else {
+        return Ok(None);
+    };
+    let marker = exe_dir.join(PORTABLE_MARKER);
+    if !marker.is_file() {
+        return Ok(None);
+    }
+    let contents =
+        std::fs::read_to_string(&marker).map_err(|e| format!("Failed to read {}: {}", marker.display(), e))?;
+    let name = contents.lines().next().map(str::trim).filter(|line| !line.is_empty());
+    Ok(Some(exe_dir.join(name.unwrap_or(PORTABLE_DEFAULT_DIR))))
+}
+
+/// Create `dir` if needed and prove we can write to it.
+fn validate(dir: &Path) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("Cannot create data directory {}: {}", dir.display(), e))?;
+    let probe = dir.join(".voicebox-write-test");
+    std::fs::write(&probe, b"")
+        .and_then(|_| std::fs::remove_file(&probe))
+        .map_err(|e| format!("Data directory {} is not writable: {}", dir.display(), e))
+}
+
+pub fn mode() -> PathsMode {
+    OVERRIDE.get().map_or(PathsMode::Default, |(mode, _)| *mode)
+}
+
+/// The overridden app data dir, if any. For code that runs without an app.
+pub fn data_dir_override() -> Option<&'static Path> {
+    OVERRIDE.get().map(|(_, dir)| dir.as_path())
+}
+
+/// The app data dir: the override if there is one, otherwise Tauri's. Use
+/// this instead of `app.path().app_data_dir()`.
+pub fn app_data_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    match data_dir_override() {
+        Some(dir) => Ok(dir.to_path_buf()),
+        None => app
+            .path()
+            .app_data_dir()
+            .map_err(|e| format!("Failed to get app data dir: {}", e)),
+    }
+}
+
+pub fn logs_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app_data_dir(app)?.join("logs"))
+}
+
+pub fn app_paths(app: &AppHandle) -> Result<AppPaths, String> {
+    Ok(AppPaths {
+        mode: mode(),
+        app_data_dir: app_data_dir(app)?,
+        logs_dir: logs_dir(app)?,
+    })
+}
+
+/// Suffix for the single-instance lock, so instances on different data dirs
+/// can run side by side while two on the same dir still collapse into one.
+pub fn instance_suffix() -> Option<String> {
+    use std::hash::{Hash, Hasher};
+
+    let dir = data_dir_override()?;
+    // canonicalize so /a/../b and /b share a lock
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    dir.hash(&mut hasher);
+    Some(format!("{:016x}", hasher.finish()))
+}
@@ -0,0 +1,375 @@
+use crate::error::VoiceboxError;
+use crate::setup_checks::{CheckStatus, Finding};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager};
+use tracing::{info, warn};
+
+/// Some checks shell out or cross into Objective-C, and onboarding polls.
+const CACHE_TTL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionKind {
+    /// macOS Screen Recording, which ScreenCaptureKit needs for system audio.
+    ScreenRecording,
+    Microphone,
+    Notifications,
+}
+
+impl PermissionKind {
+    pub const ALL: [PermissionKind; 3] = [
+        PermissionKind::ScreenRecording,
+        PermissionKind::Microphone,
+        PermissionKind::Notifications,
+    ];
+
+    /// The serialized name, e.g. `screen_recording`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PermissionKind::ScreenRecording => "screen_recording",
+            PermissionKind::Microphone => "microphone",
+            PermissionKind::Notifications => "notifications",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PermissionKind::ScreenRecording => "Screen Recording",
+            PermissionKind::Microphone => "Microphone",
+            PermissionKind::Notifications => "Notifications",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionStatus {
+    Granted,
+    Denied,
+    NotDetermined,
+    /// This platform doesn't gate the feature behind a permission.
+    NotApplicable,
+}
+
+pub struct PermissionsState {
+    cache: Mutex<Option<(Instant, BTreeMap<PermissionKind, PermissionStatus>)>>,
+}
+
+impl PermissionsState {
+    pub fn new() -> Self {
+        Self { cache: Mutex::new(None) }
+    }
+}
+
+/// Status of every permission, reusing results younger than CACHE_TTL. Blocking.
+pub fn all(app: &AppHandle) -> BTreeMap<PermissionKind, PermissionStatus> {
+    let state = app.state::<PermissionsState>();
+    if let Some((at, statuses)) = state.cache.lock().unwrap().as_ref() {
+        if at.elapsed() < CACHE_TTL {
+            return statuses.clone();
+        }
+    }
+    let statuses: BTreeMap<_, _> = PermissionKind::ALL.iter().map(|&kind| (kind, check(app, kind))).collect();
+    *state.cache.lock().unwrap() = Some((Instant::now(), statuses.clone()));
+    statuses
+}
+
+pub fn status(app: &AppHandle, kind: PermissionKind) -> PermissionStatus {
+    all(app)[&kind]
+}
+
+/// status(), checked again rather than taken from the cache. Blocking.
+pub fn recheck(app: &AppHandle, kind: PermissionKind) -> PermissionStatus {
+    *app.state::<PermissionsState>().cache.lock().unwrap() = None;
+    status(app, kind)
+}
+
+fn check(app: &AppHandle, kind: PermissionKind) -> PermissionStatus {
+    match kind {
+        PermissionKind::ScreenRecording => platform::screen_recording(),
+        PermissionKind::Microphone => platform::microphone(),
+        PermissionKind::Notifications => notifications(app),
+    }
+}
+
+/// The desktop notification plugin reports Granted everywhere; the OS can
+/// still silence us, which only its settings pane shows.
+fn notifications(app: &AppHandle) -> PermissionStatus {
+    use tauri::plugin::PermissionState;
+    use tauri_plugin_notification::NotificationExt;
+
+    match app.notification().permission_state() {
+        Ok(PermissionState::Granted) => PermissionStatus::Granted,
+        Ok(PermissionState::Denied) => PermissionStatus::Denied,
+        Ok(_) => PermissionStatus::NotDetermined,
+        Err(e) => {
+            warn!("Failed to read notification permission: {}", e);
+            PermissionStatus::NotDetermined
+        }
+    }
+}
+
+/// The setup wizard's permissions check. A denied Screen Recording or
+/// Microphone permission fails it, since capture can't work; anything else
+/// not granted yet only warns. Blocking.
+pub fn setup_finding(app: &AppHandle) -> Finding {
+    let mut worst: Option<(CheckStatus, PermissionKind)> = None;
+    let mut missing = Vec::new();
+    for (kind, status) in all(app) {
+        let level = match (kind, status) {
+            (_, PermissionStatus::Granted | PermissionStatus::NotApplicable) => continue,
+            (PermissionKind::ScreenRecording | PermissionKind::Microphone, PermissionStatus::Denied) => {
+                CheckStatus::Fail
+            }
+            _ => CheckStatus::Warn,
+        };
+        let state = if status == PermissionStatus::Denied { "denied" } else { "not granted yet" };
+        missing.push(format!("{} is {}", kind.label(), state));
+        if worst.is_none_or(|(worst, _)| level > worst) {
+            worst = Some((level, kind));
+        }
+    }
+    match worst {
+        None => Finding::pass("Every permission Voicebox uses is granted"),
+        Some((level, kind)) => Finding {
+            status: level,
+            detail: missing.join("; "),
+            fix_hint: Some(format!("Allow Voicebox under {}", platform::settings_location(kind))),
+        },
+    }
+}
+
+/// Show the native prompt if the OS still allows one, otherwise open the
+/// settings pane where the user can grant it. Returns the status afterwards.
+/// Blocking.
+pub fn request(app: &AppHandle, kind: PermissionKind) -> Result<PermissionStatus, String> {
+    *app.state::<PermissionsState>().cache.lock().unwrap() = None;
+    let current = check(app, kind);
+    if matches!(current, PermissionStatus::Granted | PermissionStatus::NotApplicable) {
+        return Ok(current);
+    }
+
+    let prompted = current == PermissionStatus::NotDetermined && platform::prompt(kind);
+    if !prompted {
+        let pane = platform::settings_pane(kind).ok_or_else(|| format!("No settings pane for {}", kind.label()))?;
+        info!("Opening settings for the {} permission", kind.label());
+        open_url(pane)?;
+    }
+    Ok(check(app, kind))
+}
+
+/// Screen Recording as check_capture_permission reports it: Granted where
+/// the platform doesn't gate system audio capture, so the frontend can ask
+/// on every platform.
+pub fn capture_status(status: PermissionStatus) -> PermissionStatus {
+    match status {
+        PermissionStatus::NotApplicable => PermissionStatus::Granted,
+        status => status,
+    }
+}
+
+/// Turn a capture start failure into a Permission error naming the missing
+/// permission, if that is the likely cause. One the backend already refused
+/// as a Permission error is kept as it is.
+pub fn explain_failure(app: &AppHandle, kind: PermissionKind, error: VoiceboxError) -> VoiceboxError {
+    match recheck(app, kind) {
+        PermissionStatus::Denied | PermissionStatus::NotDetermined => {
+            crate::audio_log::record(crate::audio_log::AudioEvent::PermissionFailed {
+                permission: kind.as_str(),
+                error: error.message().to_string(),
+            });
+            if matches!(error, VoiceboxError::Permission { .. }) {
+                return error;
+            }
+            VoiceboxError::permission(
+                kind.as_str(),
+                format!(
+                    "{} permission is not granted. Allow Voicebox under {} and try again. ({})",
+                    kind.label(),
+                    platform::settings_location(kind),
+                    error
+                ),
+            )
+        }
+        _ => error,
+    }
+}
+
+fn open_url(url: &str) -> Result<(), String> {
+    let program = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
+    let mut child = std::process::Command::new(program)
+        .arg(url)
+        .spawn()
+        .map_err(|e| format!("Failed to open {}: {}", url, e))?;
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::{PermissionKind, PermissionStatus};
+    use objc::runtime::{Object, BOOL, YES};
+    use objc::{class, msg_send, sel, sel_impl};
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        fn CGPreflightScreenCaptureAccess() -> bool;
+        fn CGRequestScreenCaptureAccess() -> bool;
+    }
+
+    #[link(name = "AVFoundation", kind = "framework")]
+    extern "C" {
+        static AVMediaTypeAudio: *mut Object;
+    }
+
+    /// AVAuthorizationStatus values.
+    const AV_NOT_DETERMINED: isize = 0;
+    const AV_AUTHORIZED: isize = 3;
+
+    /// macOS doesn't say whether it has asked before, so "not granted" reads as
+    /// NotDetermined and request() falls back to the settings pane.
+    pub fn screen_recording() -> PermissionStatus {
+        if unsafe { CGPreflightScreenCaptureAccess() } {
+            PermissionStatus::Granted
+        } else {
+            PermissionStatus::NotDetermined
+        }
+    }
+
+    pub fn microphone() -> PermissionStatus {
+        let status: isize = unsafe { msg_send![class!(AVCaptureDevice), authorizationStatusForMediaType: AVMediaTypeAudio] };
+        match status {
+            AV_AUTHORIZED => PermissionStatus::Granted,
+            AV_NOT_DETERMINED => PermissionStatus::NotDetermined,
+            // Denied, or Restricted by a profile
+            _ => PermissionStatus::Denied,
+        }
+    }
+
+    /// Returns true if a prompt was shown and granted.
+    pub fn prompt(kind: PermissionKind) -> bool {
+        match kind {
+            // Only prompts the first time; afterwards it just returns false
+            PermissionKind::ScreenRecording => unsafe { CGRequestScreenCaptureAccess() },
+            PermissionKind::Microphone => {
+                let (tx, rx) = std::sync::mpsc::channel();
+                let handler = block::ConcreteBlock::new(move |granted: BOOL| {
+                    let _ = tx.send(granted == YES);
+                })
+                .copy();
+                unsafe {
+                    let _: () = msg_send![class!(AVCaptureDevice), requestAccessForMediaType: AVMediaTypeAudio completionHandler: &*handler];
+                }
+                rx.recv().unwrap_or(false)
+            }
+            PermissionKind::Notifications => false,
+        }
+    }
+
+    pub fn settings_pane(kind: PermissionKind) -> Option<&'static str> {
+        Some(match kind {
+            PermissionKind::ScreenRecording => {
+                "x-apple.systempreferences:com.apple.preference.security?Privacy_ScreenCapture"
+            }
+            PermissionKind::Microphone => "x-apple.systempreferences:com.apple.preference.security?Privacy_Microphone",
+            PermissionKind::Notifications => "x-apple.systempreferences:com.apple.preference.notifications",
+        })
+    }
+
+    pub fn settings_location(kind: PermissionKind) -> &'static str {
+        match kind {
+            PermissionKind::ScreenRecording => "System Settings > Privacy & Security > Screen Recording",
+            PermissionKind::Microphone => "System Settings > Privacy & Security > Microphone",
+            PermissionKind::Notifications => "System Settings > Notifications",
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::{PermissionKind, PermissionStatus};
+    use std::os::windows::process::CommandExt;
+
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+    /// Per-user and desktop-app switches under Settings > Privacy > Microphone.
+    const MICROPHONE_CONSENT_KEYS: [&str; 2] = [
+        r"HKCU\Software\Microsoft\Windows\CurrentVersion\CapabilityAccessManager\ConsentStore\microphone",
+        r"HKCU\Software\Microsoft\Windows\CurrentVersion\CapabilityAccessManager\ConsentStore\microphone\NonPackaged",
+    ];
+
+    pub fn screen_recording() -> PermissionStatus {
+        PermissionStatus::NotApplicable
+    }
+
+    /// Either switch set to Deny blocks desktop apps; a missing value means allowed.
+    pub fn microphone() -> PermissionStatus {
+        let denied = MICROPHONE_CONSENT_KEYS.iter().any(|key| {
+            std::process::Command::new("reg")
+                .args(["query", key, "/v", "Value"])
+                .creation_flags(CREATE_NO_WINDOW)
+                .output()
+                .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains("Deny"))
+        });
+        if denied {
+            PermissionStatus::Denied
+        } else {
+            PermissionStatus::Granted
+        }
+    }
+
+    /// Windows has no runtime prompt for desktop apps.
+    pub fn prompt(_kind: PermissionKind) -> bool {
+        false
+    }
+
+    pub fn settings_pane(kind: PermissionKind) -> Option<&'static str> {
+        match kind {
+            PermissionKind::ScreenRecording => None,
+            PermissionKind::Microphone => Some("ms-settings:privacy-microphone"),
+            PermissionKind::Notifications => Some("ms-settings:notifications"),
+        }
+    }
+
+    pub fn settings_location(kind: PermissionKind) -> &'static str {
+        match kind {
+            PermissionKind::Microphone => "Settings > Privacy & security > Microphone",
+            _ => "Settings > System > Notifications",
+        }
+    }
+}
+
+/// Linux desktops don't gate capture or the microphone per app.
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+mod platform {
+    use super::{PermissionKind, PermissionStatus};
+
+    pub fn screen_recording() -> PermissionStatus {
+        PermissionStatus::NotApplicable
+    }
+
+    pub fn microphone() -> PermissionStatus {
+        PermissionStatus::NotApplicable
+    }
+
+    pub fn prompt(_kind: PermissionKind) -> bool {
+        false
+    }
+
+    pub fn settings_pane(_kind: PermissionKind) -> Option<&'static str> {
+        None
+    }
+
+    pub fn settings_location(_kind: PermissionKind) -> &'static str {
+        "your desktop's privacy settings"
+    }
+}
@@ -0,0 +1,117 @@
+use crate::audio_util::{EncodeSpec, WavEncoder};
+use crate::error::VoiceboxError;
+use base64::{engine::general_purpose, Engine as _};
+use std::io::Cursor;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Longest capture the benchmark will simulate. Ten minutes of 48 kHz stereo
+/// already takes about 500 MB across the stages.
+pub const MAX_DURATION_SECS: u32 = 600;
+
+const SAMPLE_RATE: u32 = 48000;
+const CHANNELS: u16 = 2;
+
+/// Sends the finished payload somewhere, for timing.
+pub type Emit<'a> = &'a dyn Fn(&str) -> Result<(), String>;
+
+/// The last run, for the diagnostics bundle.
+static LAST: Mutex<Option<BenchmarkReport>> = Mutex::new(None);
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct StageTiming {
+    pub stage: &'static str,
+    /// Size of what the stage takes in.
+    pub bytes: u64,
+    pub ms: f64,
+    pub mb_per_sec: f64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BenchmarkReport {
+    pub duration_secs: u32,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub wav_bytes: u64,
+    pub payload_bytes: u64,
+    pub stages: Vec<StageTiming>,
+    pub total_ms: f64,
+    pub ran_at: u64,
+}
+
+/// Time what stopping a capture of `duration_secs` does with its frames, on
+/// generated audio: copy out of the capture buffer, f32 to i16 conversion,
+/// WAV assembly, base64 and serializing the command result. With `emit`, the
+/// payload is also handed to it, e.g. to time sending it as an event.
+/// Blocking, and slow for long durations.
+pub fn run(duration_secs: u32, emit: Option<Emit>) -> Result<BenchmarkReport, VoiceboxError> {
+    if duration_secs == 0 || duration_secs > MAX_DURATION_SECS {
+        return Err(VoiceboxError::invalid_argument(format!(
+            "The benchmark runs for 1 to {} seconds",
+            MAX_DURATION_SECS
+        )));
+    }
+    let captured = generate(duration_secs);
+    let mut stages = Vec::new();
+    let started = Instant::now();
+
+    let samples = timed(&mut stages, "copy", captured.len() as u64 * 4, || captured.clone());
+    let spec = EncodeSpec::pcm16(SAMPLE_RATE, CHANNELS);
+    let mut encoder = WavEncoder::new(Cursor::new(Vec::with_capacity(samples.len() * 2 + 64)), spec)?;
+    timed(&mut stages, "convert", samples.len() as u64 * 4, || encoder.write(&samples))?;
+    let data_bytes = samples.len() as u64 * 2;
+    let wav = timed(&mut stages, "assemble", data_bytes, || encoder.finish())?.into_inner();
+    let payload = timed(&mut stages, "base64", wav.len() as u64, || general_purpose::STANDARD.encode(&wav));
+    // Tauri serializes command results to JSON before they cross to the webview
+    timed(&mut stages, "serialize", payload.len() as u64, || serde_json::to_string(&payload))?;
+    if let Some(emit) = emit {
+        timed(&mut stages, "emit", payload.len() as u64, || emit(&payload))?;
+    }
+
+    let report = BenchmarkReport {
+        duration_secs,
+        sample_rate: SAMPLE_RATE,
+        channels: CHANNELS,
+        wav_bytes: wav.len() as u64,
+        payload_bytes: payload.len() as u64,
+        stages,
+        total_ms: started.elapsed().as_secs_f64() * 1000.0,
+        ran_at: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    };
+    *LAST.lock().unwrap() = Some(report.clone());
+    Ok(report)
+}
+
+/// The most recent report this run of the app, if a benchmark was run.
+pub fn last() -> Option<BenchmarkReport> {
+    LAST.lock().unwrap().clone()
+}
+
+fn timed<T>(stages: &mut Vec<StageTiming>, stage: &'static str, bytes: u64, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    let secs = started.elapsed().as_secs_f64();
+    stages.push(StageTiming {
+        stage,
+        bytes,
+        ms: secs * 1000.0,
+        mb_per_sec: if secs > 0.0 { bytes as f64 / 1_000_000.0 / secs } else { 0.0 },
+    });
+    result
+}
+
+/// A tone with some variation, so nothing downstream gets to compress a
+/// constant signal.
+fn generate(duration_secs: u32) -> Vec<f32> {
+    let frames = SAMPLE_RATE as usize * duration_secs as usize;
+    let mut samples = Vec::with_capacity(frames * CHANNELS as usize);
+    for n in 0..frames {
+        let t = n as f32 / SAMPLE_RATE as f32;
+        let value = 0.4 * (std::f32::consts::TAU * 220.0 * t).sin() + 0.1 * (std::f32::consts::TAU * 3137.0 * t).sin();
+        samples.extend(std::iter::repeat_n(value, CHANNELS as usize));
+    }
+    samples
+}
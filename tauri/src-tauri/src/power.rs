@@ -0,0 +1,340 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+/// How long a begin_busy hold lasts if end_busy is never called.
+pub const BUSY_MAX_MINUTES: u32 = 120;
+
+/// Keeps the system from sleeping while held; the display may still turn off.
+pub trait SleepInhibitor: Send + Sync {
+    fn acquire(&self, reason: &str) -> Result<(), String>;
+    fn release(&self);
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PowerHolder {
+    pub id: u64,
+    pub reason: String,
+    /// Unix seconds.
+    pub since: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PowerAssertions {
+    /// Whether the OS-level assertion is currently held.
+    pub active: bool,
+    pub holders: Vec<PowerHolder>,
+}
+
+struct Holders {
+    by_id: BTreeMap<u64, PowerHolder>,
+    active: bool,
+}
+
+struct Inner {
+    holders: Mutex<Holders>,
+    next_id: AtomicU64,
+    inhibitor: Box<dyn SleepInhibitor>,
+}
+
+impl Inner {
+    fn release(&self, id: u64) {
+        let mut holders = self.holders.lock().unwrap();
+        if holders.by_id.remove(&id).is_some() && holders.by_id.is_empty() && holders.active {
+            self.inhibitor.release();
+            holders.active = false;
+            info!("Released the sleep assertion");
+        }
+    }
+}
+
+/// Reference-counted sleep prevention: the OS assertion is taken when the
+/// first WakeLock is acquired and dropped with the last one.
+pub struct PowerState {
+    inner: Arc<Inner>,
+    /// Locks taken with begin_busy, until end_busy.
+    busy: Mutex<BTreeMap<u64, WakeLock>>,
+}
+
+/// Keeps the system awake until dropped.
+pub struct WakeLock {
+    inner: Arc<Inner>,
+    id: u64,
+}
+
+impl WakeLock {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl Drop for WakeLock {
+    fn drop(&mut self) {
+        self.inner.release(self.id);
+    }
+}
+
+impl PowerState {
+    pub fn new() -> Self {
+        Self::with_inhibitor(SystemInhibitor::new())
+    }
+
+    pub fn with_inhibitor(inhibitor: impl SleepInhibitor + 'static) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                holders: Mutex::new(Holders {
+                    by_id: BTreeMap::new(),
+                    active: false,
+                }),
+                next_id: AtomicU64::new(1),
+                inhibitor: Box::new(inhibitor),
+            }),
+            busy: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Keep the system awake for `reason` until the returned lock is dropped.
+    /// If the OS refuses, the lock is still counted and the failure only logged:
+    /// the operation itself should go ahead.
+    pub fn acquire(&self, reason: &str) -> WakeLock {
+        let id = self.inner.next_id.fetch_add(1, Ordering::SeqCst);
+        let mut holders = self.inner.holders.lock().unwrap();
+        if holders.by_id.is_empty() {
+            match self.inner.inhibitor.acquire(reason) {
+                Ok(()) => {
+                    holders.active = true;
+                    info!("Preventing system sleep: {}", reason);
+                }
+                Err(e) => warn!("Failed to prevent system sleep: {}", e),
+            }
+        }
+        holders.by_id.insert(
+            id,
+            PowerHolder {
+                id,
+                reason: reason.to_string(),
+                since: unix_timestamp(),
+            },
+        );
+        WakeLock {
+            inner: self.inner.clone(),
+            id,
+        }
+    }
+
+    /// Hold a lock until end_busy is called with the returned id.
+    pub fn begin_busy(&self, reason: &str) -> u64 {
+        let lock = self.acquire(reason);
+        let id = lock.id();
+        self.busy.lock().unwrap().insert(id, lock);
+        id
+    }
+
+    /// Returns false if `id` was already ended.
+    pub fn end_busy(&self, id: u64) -> bool {
+        // Dropped after the busy map is unlocked
+        let lock = self.busy.lock().unwrap().remove(&id);
+        lock.is_some()
+    }
+
+    pub fn assertions(&self) -> PowerAssertions {
+        let holders = self.inner.holders.lock().unwrap();
+        PowerAssertions {
+            active: holders.active,
+            holders: holders.by_id.values().cloned().collect(),
+        }
+    }
+
+    /// Drop every holder and the OS assertion. Locks still alive elsewhere
+    /// become no-ops. Called on app exit.
+    pub fn release_all(&self) {
+        let busy = std::mem::take(&mut *self.busy.lock().unwrap());
+        drop(busy);
+        let mut holders = self.inner.holders.lock().unwrap();
+        holders.by_id.clear();
+        if holders.active {
+            self.inner.inhibitor.release();
+            holders.active = false;
+            info!("Released the sleep assertion");
+        }
+    }
+}
+
+impl Default for PowerState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// IOPMAssertion of type PreventUserIdleSystemSleep. The OS releases it if the
+/// process dies.
+#[cfg(target_os = "macos")]
+#[derive(Default)]
+pub struct SystemInhibitor {
+    assertion: Mutex<Option<u32>>,
+}
+
+#[cfg(target_os = "macos")]
+mod iokit {
+    use core_foundation_sys::string::CFStringRef;
+
+    pub const ASSERTION_LEVEL_ON: u32 = 255;
+    pub const RETURN_SUCCESS: i32 = 0;
+
+    #[link(name = "IOKit", kind = "framework")]
+    extern "C" {
+        pub fn IOPMAssertionCreateWithName(
+            assertion_type: CFStringRef,
+            level: u32,
+            name: CFStringRef,
+            id: *mut u32,
+        ) -> i32;
+        pub fn IOPMAssertionRelease(id: u32) -> i32;
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl SystemInhibitor {
+    pub fn new() -> Self {
+        Self {
+            assertion: Mutex::new(None),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl SleepInhibitor for SystemInhibitor {
+    fn acquire(&self, reason: &str) -> Result<(), String> {
+        use core_foundation_sys::base::{kCFAllocatorDefault, CFRelease};
+        use core_foundation_sys::string::{kCFStringEncodingUTF8, CFStringCreateWithCString};
+        use std::ffi::CString;
+
+        let assertion_type = CString::new("PreventUserIdleSystemSleep").unwrap();
+        let name = CString::new(format!("Voicebox: {}", reason).replace('\0', ""))
+            .map_err(|e| format!("Invalid assertion name: {}", e))?;
+        let mut id = 0u32;
+        let result = unsafe {
+            let cf_type = CFStringCreateWithCString(kCFAllocatorDefault, assertion_type.as_ptr(), kCFStringEncodingUTF8);
+            let cf_name = CFStringCreateWithCString(kCFAllocatorDefault, name.as_ptr(), kCFStringEncodingUTF8);
+            let result = iokit::IOPMAssertionCreateWithName(cf_type, iokit::ASSERTION_LEVEL_ON, cf_name, &mut id);
+            CFRelease(cf_type as _);
+            CFRelease(cf_name as _);
+            result
+        };
+        if result != iokit::RETURN_SUCCESS {
+            return Err(format!("IOPMAssertionCreateWithName failed: {:#x}", result));
+        }
+        *self.assertion.lock().unwrap() = Some(id);
+        Ok(())
+    }
+
+    fn release(&self) {
+        if let Some(id) = self.assertion.lock().unwrap().take() {
+            unsafe {
+                iokit::IOPMAssertionRelease(id);
+            }
+        }
+    }
+}
+
+/// SetThreadExecutionState only lasts as long as the calling thread, so a
+/// dedicated thread holds it until told to stop.
+#[cfg(target_os = "windows")]
+#[derive(Default)]
+pub struct SystemInhibitor {
+    stop: Mutex<Option<std::sync::mpsc::Sender<()>>>,
+}
+
+#[cfg(target_os = "windows")]
+impl SystemInhibitor {
+    pub fn new() -> Self {
+        Self { stop: Mutex::new(None) }
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl SleepInhibitor for SystemInhibitor {
+    fn acquire(&self, _reason: &str) -> Result<(), String> {
+        use windows::Win32::System::Power::{SetThreadExecutionState, ES_CONTINUOUS, ES_SYSTEM_REQUIRED};
+
+        let (stop_tx, stop_rx) = std::sync::mpsc::channel::<()>();
+        let (started_tx, started_rx) = std::sync::mpsc::channel::<bool>();
+        std::thread::spawn(move || {
+            let previous = unsafe { SetThreadExecutionState(ES_CONTINUOUS | ES_SYSTEM_REQUIRED) };
+            let _ = started_tx.send(previous.0 != 0);
+            // Until released, or the sender is dropped
+            let _ = stop_rx.recv();
+            unsafe {
+                SetThreadExecutionState(ES_CONTINUOUS);
+            }
+        });
+        match started_rx.recv() {
+            Ok(true) => {
+                *self.stop.lock().unwrap() = Some(stop_tx);
+                Ok(())
+            }
+            _ => Err("SetThreadExecutionState failed".to_string()),
+        }
+    }
+
+    fn release(&self) {
+        if let Some(stop) = self.stop.lock().unwrap().take() {
+            let _ = stop.send(());
+        }
+    }
+}
+
+/// A `systemd-inhibit --what=sleep` child. Its command exits shortly after this
+/// process does, so a crash can't leave the machine unable to sleep.
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+#[derive(Default)]
+pub struct SystemInhibitor {
+    child: Mutex<Option<std::process::Child>>,
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+impl SystemInhibitor {
+    pub fn new() -> Self {
+        Self { child: Mutex::new(None) }
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+impl SleepInhibitor for SystemInhibitor {
+    fn acquire(&self, reason: &str) -> Result<(), String> {
+        use std::os::unix::process::CommandExt;
+
+        let watch = format!("while kill -0 {} 2>/dev/null; do sleep 5; done", std::process::id());
+        let child = std::process::Command::new("systemd-inhibit")
+            .args(["--what=sleep", "--who=Voicebox", "--mode=block"])
+            .arg(format!("--why={}", reason))
+            .args(["sh", "-c", &watch])
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            // Own process group, so release can stop the watch loop too
+            .process_group(0)
+            .spawn()
+            .map_err(|e| format!("Failed to run systemd-inhibit: {}", e))?;
+        *self.child.lock().unwrap() = Some(child);
+        Ok(())
+    }
+
+    fn release(&self) {
+        if let Some(mut child) = self.child.lock().unwrap().take() {
+            unsafe {
+                libc::kill(-(child.id() as i32), libc::SIGTERM);
+            }
+            let _ = child.wait();
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use crate::audio_capture::AudioCaptureState;
+use crate::process_manager::{self, ServerState};
+use tauri::{AppHandle, Manager};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerEvent {
+    Sleep,
+    Wake,
+}
+
+/// Subscribe to the OS's sleep and wake notifications. They are handled here
+/// rather than in the webview, which may be hidden or suspended. Called from
+/// setup; without a subscription the app just doesn't react to sleep.
+pub fn install(app: &AppHandle) {
+    if let Err(e) = platform::subscribe(app) {
+        warn!("Failed to subscribe to sleep/wake notifications: {}", e);
+    }
+}
+
+fn dispatch(app: &AppHandle, event: PowerEvent) {
+    info!("System power event: {:?}", event);
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        match event {
+            PowerEvent::Sleep => on_sleep(&app).await,
+            PowerEvent::Wake => on_wake(&app).await,
+        }
+    });
+}
+
+/// Pause the health monitors and finalize a running capture, which the
+/// capture backends can't survive. The OS only waits a moment before
+/// suspending, so this is best effort.
+async fn on_sleep(app: &AppHandle) {
+    {
+        let state = app.state::<ServerState>();
+        let mut asleep_since = state.asleep_since.lock().unwrap();
+        if asleep_since.is_some() {
+            return;
+        }
+        *asleep_since = Some(process_manager::unix_timestamp());
+    }
+
+    let capture = app.state::<AudioCaptureState>();
+    if !capture.is_capturing() {
+        return;
+    }
+    let session = capture.current_session();
+    // end_capture reports a failure itself, as capture-failed
+    if crate::end_capture(app).await.is_ok() {
+        info!("Finalized system audio capture before sleep");
+        let payload = serde_json::json!({ "reason": "sleep", "session": session });
+        if let Err(e) = crate::event_bus::emit(app, "capture-auto-stopped", payload) {
+            warn!("Failed to emit capture-auto-stopped event: {}", e);
+        }
+    }
+}
+
+/// Resume the monitors, re-check the server and devices, and let the UI
+/// reconcile through `system-resumed`.
+async fn on_wake(app: &AppHandle) {
+    let Some(asleep_since) = app.state::<ServerState>().asleep_since.lock().unwrap().take() else {
+        // Windows reports a resume twice
+        return;
+    };
+    let slept_secs = process_manager::unix_timestamp().saturating_sub(asleep_since);
+
+    let server_healthy = check_server(app).await;
+    if server_healthy == Some(false) {
+        warn!("Server did not answer its health check after wake");
+    }
+
+    let output_devices = app
+        .state::<crate::audio_output::AudioOutputState>()
+        .list_output_devices()
+        .inspect_err(|e| warn!("Failed to list output devices after wake: {}", e))
+        .unwrap_or_default();
+    let input_devices = tokio::task::spawn_blocking(crate::diagnostics::input_devices)
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|devices| devices)
+        .inspect_err(|e| warn!("Failed to list input devices after wake: {}", e))
+        .unwrap_or_default();
+
+    let payload = serde_json::json!({
+        "slept_secs": slept_secs,
+        "server_healthy": server_healthy,
+        "output_devices": output_devices,
+        "input_devices": input_devices,
+    });
+    if let Err(e) = crate::event_bus::emit(app, "system-resumed", payload) {
+        warn!("Failed to emit system-resumed event: {}", e);
+    }
+}
+
+/// Whether the current server answers its health check; None without one.
+async fn check_server(app: &AppHandle) -> Option<bool> {
+    let state = app.state::<ServerState>();
+    let info = state.info.lock().unwrap().clone()?;
+    if info.managed {
+        return Some(process_manager::check_health(info.port).await);
+    }
+    let auth_token = state.auth_token.lock().unwrap().clone();
+    Some(process_manager::check_health_at(&info.url, auth_token.as_deref()).await)
+}
+
+/// PowerRegisterSuspendResumeNotification delivers the same PBT_* codes as
+/// WM_POWERBROADCAST, without needing a window of our own.
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::PowerEvent;
+    use std::ffi::c_void;
+    use tauri::AppHandle;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::System::Power::{PowerRegisterSuspendResumeNotification, DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS};
+    use windows::Win32::UI::WindowsAndMessaging::{
+        DEVICE_NOTIFY_CALLBACK, PBT_APMRESUMEAUTOMATIC, PBT_APMRESUMESUSPEND, PBT_APMSUSPEND,
+    };
+
+    unsafe extern "system" fn on_power_event(context: *const c_void, kind: u32, _setting: *const c_void) -> u32 {
+        let app = unsafe { &*(context as *const AppHandle) };
+        match kind {
+            PBT_APMSUSPEND => super::dispatch(app, PowerEvent::Sleep),
+            PBT_APMRESUMEAUTOMATIC | PBT_APMRESUMESUSPEND => super::dispatch(app, PowerEvent::Wake),
+            _ => {}
+        }
+        0
+    }
+
+    pub fn subscribe(app: &AppHandle) -> Result<(), String> {
+        // The registration lasts as long as the process, and so do these
+        let context = Box::into_raw(Box::new(app.clone()));
+        let parameters = Box::leak(Box::new(DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS {
+            Callback: Some(on_power_event),
+            Context: context.cast(),
+        }));
+        let mut registration = std::ptr::null_mut();
+        unsafe {
+            PowerRegisterSuspendResumeNotification(
+                DEVICE_NOTIFY_CALLBACK,
+                HANDLE(parameters as *mut DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS as *mut c_void),
+                &mut registration,
+            )
+        }
+        .ok()
+        .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::PowerEvent;
+    use objc::runtime::Object;
+    use objc::{class, msg_send, sel, sel_impl};
+    use tauri::AppHandle;
+
+    #[link(name = "AppKit", kind = "framework")]
+    extern "C" {
+        static NSWorkspaceWillSleepNotification: *mut Object;
+        static NSWorkspaceDidWakeNotification: *mut Object;
+    }
+
+    pub fn subscribe(app: &AppHandle) -> Result<(), String> {
+        unsafe {
+            let workspace: *mut Object = msg_send![class!(NSWorkspace), sharedWorkspace];
+            let center: *mut Object = msg_send![workspace, notificationCenter];
+            for (name, event) in [
+                (NSWorkspaceWillSleepNotification, PowerEvent::Sleep),
+                (NSWorkspaceDidWakeNotification, PowerEvent::Wake),
+            ] {
+                let app = app.clone();
+                let handler = block::ConcreteBlock::new(move |_notification: *mut Object| {
+                    super::dispatch(&app, event);
+                })
+                .copy();
+                // The center copies the block and keeps the observer for the
+                // life of the app
+                let _: *mut Object = msg_send![center,
+                    addObserverForName: name
+                    object: std::ptr::null_mut::<Object>()
+                    queue: std::ptr::null_mut::<Object>()
+                    usingBlock: &*handler];
+            }
+        }
+        Ok(())
+    }
+}
+
+/// logind's PrepareForSleep signal, read through `gdbus monitor` (glib ships
+/// with the webview's dependencies), the same way power.rs drives
+/// systemd-inhibit rather than linking a D-Bus client.
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+mod platform {
+    use super::PowerEvent;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+    use tauri::AppHandle;
+    use tracing::info;
+
+    pub fn subscribe(app: &AppHandle) -> Result<(), String> {
+        let mut command = Command::new("gdbus");
+        command
+            .args(["monitor", "--system", "--dest", "org.freedesktop.login1"])
+            .args(["--object-path", "/org/freedesktop/login1"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+        // Don't outlive the app
+        #[cfg(target_os = "linux")]
+        unsafe {
+            command.pre_exec(|| {
+                libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM);
+                Ok(())
+            });
+        }
+        let mut child = command.spawn().map_err(|e| format!("Failed to run gdbus: {}", e))?;
+        let stdout = child.stdout.take().ok_or("gdbus has no output")?;
+
+        let app = app.clone();
+        std::thread::Builder::new()
+            .name("power-events".to_string())
+            .spawn(move || {
+                // e.g. "/org/freedesktop/login1: org.freedesktop.login1.Manager.PrepareForSleep (true,)"
+                for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                    if !line.contains(".PrepareForSleep ") {
+                        continue;
+                    }
+                    let event = if line.contains("(true,)") {
+                        PowerEvent::Sleep
+                    } else {
+                        PowerEvent::Wake
+                    };
+                    super::dispatch(&app, event);
+                }
+                let _ = child.wait();
+                info!("Stopped watching for sleep/wake notifications");
+            })
+            .map(|_| ())
+            .map_err(|e| format!("Failed to start the power event thread: {}", e))
+    }
+}
@@ -0,0 +1,1037 @@
+use crate::firewall::FirewallRuleStatus;
+use crate::paths::PathsMode;
+use crate::server_options::ServerLaunchOptions;
+use crate::server_output::ServerLog;
+use crate::settings::{ProcessPriority, ServerLogLevel};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+/// Port the bundled server listens on unless another program has it.
+pub const SERVER_PORT: u16 = 17493;
+
+/// Ports tried in order when SERVER_PORT is taken by a program that isn't a
+/// voicebox server, before settling for one the OS assigns.
+pub const FALLBACK_PORTS: std::ops::RangeInclusive<u16> = SERVER_PORT + 1..=SERVER_PORT + 100;
+
+pub const SERVER_RECORD_FILE: &str = "server.json";
+
+/// Written to the app data dir while a server is running so external scripts
+/// can find it without knowing the port.
+pub const ENDPOINT_FILE: &str = "server-endpoint.json";
+
+/// How much longer than the server_stop_grace_secs setting closing the window
+/// or installing an update waits for stop_server, for the kill that follows
+/// the grace period, before going ahead anyway.
+pub const STOP_KILL_MARGIN: Duration = Duration::from_secs(4);
+
+/// How long a killed server gets to disappear before stopping it is reported
+/// as failed.
+pub const KILL_EXIT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long a killed server's port takes to be released, waited before
+/// binding it again.
+pub const KILL_PORT_RELEASE_WAIT: Duration = Duration::from_millis(200);
+
+/// How much of the grace period quitting keeps back for killing a server
+/// that didn't exit on its own, and waiting for it to go.
+pub const EXIT_KILL_WAIT: Duration = Duration::from_millis(500);
+
+/// How long one `/shutdown` request may take, out of the stop grace period.
+const SHUTDOWN_REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often a stopping server is checked for having exited.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a starting server gets to answer one readiness probe.
+pub const READY_PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How often start_server probes a starting server's health endpoint.
+pub const READY_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Automatic restarts allowed within AUTO_RESTART_WINDOW. A crash past that
+/// is taken as a crash loop and left for the user to deal with.
+pub const MAX_AUTO_RESTARTS: usize = 3;
+pub const AUTO_RESTART_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Wait before the first automatic restart in the window, doubled for each
+/// later one.
+pub const AUTO_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How long restart_server waits for the old server to let go of its port.
+pub const PORT_RELEASE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What closing the main window does to the server. Sent as the payload of
+/// the informational `window-closing` event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseAction {
+    StopServer,
+    KeepServer,
+}
+
+/// Oldest server API this app can talk to. Bump when the app starts relying on
+/// a server change; servers from a newer minor release than the app are also
+/// treated as incompatible.
+pub const MIN_SERVER_VERSION: &str = "0.1.13";
+
+pub struct ServerState {
+    pub child: Mutex<Option<tauri_plugin_shell::process::CommandChild>>,
+    pub server_pid: Mutex<Option<u32>>,
+    pub keep_running_on_close: Mutex<bool>,
+    pub idle_timeout_minutes: Mutex<Option<u32>>,
+    pub info: Mutex<Option<ServerInfo>>,
+    /// Held for the whole start sequence so concurrent start_server calls
+    /// can't both decide to spawn.
+    pub start_lock: tokio::sync::Mutex<()>,
+    /// Out-of-memory crashes since the user last stopped the server; caps
+    /// automatic restarts.
+    pub oom_crashes: Mutex<u32>,
+    /// Set when the server crashed and cleared by the next successful start,
+    /// which is then reported as `server-ready-after-restart`, or by
+    /// stop_server. A pending automatic restart is dropped once it's cleared.
+    pub recovering: Mutex<bool>,
+    /// Whether a crashed server is started again without waiting for the
+    /// user; see set_auto_restart.
+    pub auto_restart: Mutex<bool>,
+    /// Automatic restarts since the user last stopped the server.
+    pub restart_budget: Mutex<RestartBudget>,
+    /// False while the app is a client of a server it didn't spawn (see
+    /// connect_to_server); such a server is never killed.
+    pub managed: Mutex<bool>,
+    /// Bearer token sent to the server: an external one's, or the one a
+    /// remote-mode server we started requires from other machines.
+    pub auth_token: Mutex<Option<String>>,
+    /// Set by the first close request so repeated ones while the server is
+    /// still stopping are ignored.
+    pub closing: Mutex<bool>,
+    /// Unix seconds the system went to sleep, until it wakes; the health and
+    /// network monitors skip their checks meanwhile.
+    pub asleep_since: Mutex<Option<u64>>,
+    /// Port of the current server, or of the one being started. SERVER_PORT
+    /// until a start finds it taken.
+    pub port: Mutex<u16>,
+    /// Options the current server was spawned with; None for a server this
+    /// instance didn't spawn and has no record of.
+    pub launch_options: Mutex<Option<ServerLaunchOptions>>,
+    /// The servers' recent output, kept across restarts.
+    pub log: Arc<ServerLog>,
+}
+
+impl ServerState {
+    pub fn new() -> Self {
+        Self {
+            child: Mutex::new(None),
+            server_pid: Mutex::new(None),
+            keep_running_on_close: Mutex::new(false),
+            idle_timeout_minutes: Mutex::new(None),
+            info: Mutex::new(None),
+            start_lock: tokio::sync::Mutex::new(()),
+            oom_crashes: Mutex::new(0),
+            recovering: Mutex::new(false),
+            auto_restart: Mutex::new(true),
+            restart_budget: Mutex::new(RestartBudget::default()),
+            managed: Mutex::new(true),
+            auth_token: Mutex::new(None),
+            closing: Mutex::new(false),
+            asleep_since: Mutex::new(None),
+            port: Mutex::new(SERVER_PORT),
+            launch_options: Mutex::new(None),
+            log: Arc::new(ServerLog::default()),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        *self.port.lock().unwrap()
+    }
+
+    /// Decide what a window close does to the server: a server we spawned is
+    /// stopped unless keep_running_on_close is set. Returns None while an
+    /// earlier close is still in progress.
+    pub fn begin_close(&self) -> Option<CloseAction> {
+        let mut closing = self.closing.lock().unwrap();
+        if *closing {
+            return None;
+        }
+        *closing = true;
+        Some(self.close_action())
+    }
+
+    /// What quitting the app should do to the server, without marking a
+    /// close as in progress.
+    pub fn close_action(&self) -> CloseAction {
+        let stop = *self.managed.lock().unwrap()
+            && !*self.keep_running_on_close.lock().unwrap()
+            && self.server_pid.lock().unwrap().is_some();
+        if stop {
+            CloseAction::StopServer
+        } else {
+            CloseAction::KeepServer
+        }
+    }
+
+    /// Forget the current server, killing the child if we spawned it.
+    fn discard_server(&self) {
+        *self.info.lock().unwrap() = None;
+        let pid = self.server_pid.lock().unwrap().take();
+        let child = self.child.lock().unwrap().take();
+        if let Some(child) = child {
+            let _ = child.kill();
+            if let Some(pid) = pid {
+                force_kill(pid);
+            }
+        }
+    }
+
+    /// Idle timeout to hand to the sidecar at spawn time. Only applies when the
+    /// server is going to outlive the window, otherwise the app stops it on close.
+    pub fn spawn_idle_timeout(&self) -> Option<u32> {
+        if *self.keep_running_on_close.lock().unwrap() {
+            *self.idle_timeout_minutes.lock().unwrap()
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for ServerState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Run a server start under `state.start_lock`. A caller that finds a server
+/// already published (typically because it queued behind another start) gets
+/// that server's info back, provided `is_healthy` confirms it still answers;
+/// otherwise the stale server is discarded and `launch` runs. `launch` is
+/// expected to publish the info it returns into `state.info`.
+pub async fn start_serialized<H, HFut, L, LFut, E>(
+    state: &ServerState,
+    is_healthy: H,
+    launch: L,
+) -> Result<ServerInfo, E>
+where
+    H: FnOnce(ServerInfo) -> HFut,
+    HFut: Future<Output = bool>,
+    L: FnOnce() -> LFut,
+    LFut: Future<Output = Result<ServerInfo, E>>,
+{
+    let _guard = state.start_lock.lock().await;
+
+    let current = state.info.lock().unwrap().clone();
+    if let Some(info) = current {
+        if is_healthy(info.clone()).await {
+            return Ok(info);
+        }
+        warn!("Server on port {} is not responding, restarting it", info.port);
+        state.discard_server();
+        tokio::time::sleep(KILL_PORT_RELEASE_WAIT).await;
+    }
+
+    launch().await
+}
+
+/// Recent automatic restarts, to back off between them and stop a crash loop.
+#[derive(Debug, Default)]
+pub struct RestartBudget {
+    recent: VecDeque<Instant>,
+}
+
+impl RestartBudget {
+    /// Use up a restart at `now`: how long to wait before it, or None when
+    /// MAX_AUTO_RESTARTS already happened within AUTO_RESTART_WINDOW.
+    pub fn take(&mut self, now: Instant) -> Option<Duration> {
+        while self.recent.front().is_some_and(|at| now.duration_since(*at) >= AUTO_RESTART_WINDOW) {
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= MAX_AUTO_RESTARTS {
+            return None;
+        }
+        let delay = AUTO_RESTART_BACKOFF * 2u32.pow(self.recent.len() as u32);
+        self.recent.push_back(now);
+        Some(delay)
+    }
+
+    pub fn reset(&mut self) {
+        self.recent.clear();
+    }
+}
+
+/// Details of the server returned by start_server and embedded in ServerStatus.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ServerInfo {
+    pub url: String,
+    /// Whether the server was started bound to 0.0.0.0.
+    pub remote: bool,
+    /// LAN URLs other devices can use when the server binds 0.0.0.0.
+    pub remote_urls: Vec<String>,
+    pub port: u16,
+    /// None for a dev server started by hand, which we don't own.
+    pub pid: Option<u32>,
+    /// True when an already-running server was reused instead of spawned.
+    pub adopted_existing: bool,
+    /// Unix timestamp (seconds) of the spawn, or of the original spawn for an
+    /// adopted server when server.json records it.
+    pub started_at: u64,
+    /// Milliseconds from the spawn until the server answered; None for a
+    /// server that was already running or isn't ours.
+    pub startup_ms: Option<u64>,
+    pub auth_token_present: bool,
+    /// The bearer token other machines must send to a remote-mode server we
+    /// started or adopted. None otherwise, and for an external server, whose
+    /// token the caller already has.
+    pub auth_token: Option<String>,
+    /// Version reported by the server's root endpoint, if it answered.
+    pub version: Option<String>,
+    /// Whether the server was started in offline mode; None for a server we
+    /// adopted rather than spawned.
+    pub offline: Option<bool>,
+    /// Result of ensuring the firewall lets remote clients in; None when not
+    /// in remote mode.
+    pub firewall_rule: Option<FirewallRuleStatus>,
+    /// False for an external server the app only connects to.
+    pub managed: bool,
+    /// Extra options the server was started with, when known.
+    pub launch_options: Option<ServerLaunchOptions>,
+}
+
+impl ServerInfo {
+    /// Seconds from started_at to `now`, both unix timestamps.
+    pub fn uptime_secs(&self, now: u64) -> u64 {
+        now.saturating_sub(self.started_at)
+    }
+}
+
+/// Snapshot of the server as seen by this app instance.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerStatus {
+    /// Whether the server process exists, or for an external server whether
+    /// one is connected.
+    pub running: bool,
+    /// True for a running server this instance spawned or adopted, which it
+    /// stops and restarts; false for an external one.
+    pub managed_by_app: bool,
+    pub pid: Option<u32>,
+    pub port: u16,
+    pub url: Option<String>,
+    /// Seconds since the server's spawn, or its original spawn when adopted.
+    pub uptime_secs: Option<u64>,
+    /// Whether the server answered a quick health probe; None when it isn't
+    /// running.
+    pub healthy: Option<bool>,
+    pub keep_running_on_close: bool,
+    /// Minutes of inactivity after which a kept-running server shuts itself down.
+    ///
+    /// The Tauri process exits with the window, so it can't enforce this itself.
+    /// Instead the value is passed to the sidecar as `--idle-timeout-minutes` when
+    /// it is spawned with keep_running_on_close enabled, and recorded in
+    /// server.json. A server that timed out leaves that record behind; the
+    /// adoption logic in start_server notices the dead PID and removes it.
+    /// Changing the value only takes effect on the next spawn.
+    pub idle_timeout_minutes: Option<u32>,
+    /// Level passed as `--log-level`; changes apply on the next start.
+    pub log_level: ServerLogLevel,
+    /// When false the sidecar is started with `--no-access-log`.
+    pub access_logs: bool,
+    /// Applied to the server process after spawn and whenever it changes.
+    pub process_priority: Option<ProcessPriority>,
+    /// False while connected to an external server.
+    pub managed: bool,
+    /// Set while a server started or adopted by this instance is running.
+    pub info: Option<ServerInfo>,
+    /// Whether the app data dir is the default one, an argument or portable.
+    pub paths_mode: PathsMode,
+}
+
+/// Record of the running sidecar, left in the data dir so the next launch can
+/// tell whether a kept-running server is still alive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerRecord {
+    pub pid: u32,
+    pub port: u16,
+    pub started_at: u64,
+    pub idle_shutdown_after_minutes: Option<u32>,
+    #[serde(default)]
+    pub launch_options: Option<ServerLaunchOptions>,
+    /// Started with the token in the data dir, for a remote-mode server.
+    #[serde(default)]
+    pub requires_auth: bool,
+}
+
+pub fn record_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(SERVER_RECORD_FILE)
+}
+
+pub fn read_server_record(data_dir: &Path) -> Option<ServerRecord> {
+    let contents = std::fs::read_to_string(record_path(data_dir)).ok()?;
+    match serde_json::from_str(&contents) {
+        Ok(record) => Some(record),
+        Err(e) => {
+            warn!("Ignoring unreadable {}: {}", SERVER_RECORD_FILE, e);
+            None
+        }
+    }
+}
+
+pub fn write_server_record(data_dir: &Path, record: &ServerRecord) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(record)
+        .map_err(|e| format!("Failed to serialize server record: {}", e))?;
+    std::fs::write(record_path(data_dir), json)
+        .map_err(|e| format!("Failed to write {}: {}", SERVER_RECORD_FILE, e))
+}
+
+pub fn remove_server_record(data_dir: &Path) {
+    let path = record_path(data_dir);
+    if path.exists() {
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!("Failed to remove {}: {}", SERVER_RECORD_FILE, e);
+        }
+    }
+}
+
+/// Remove server.json if the process it describes is gone, e.g. because the
+/// server hit its idle timeout after the app was closed. Returns the record if
+/// it still points at a live process.
+pub fn clean_stale_server_record(data_dir: &Path) -> Option<ServerRecord> {
+    let record = read_server_record(data_dir)?;
+    if is_process_running(record.pid) {
+        Some(record)
+    } else {
+        info!(
+            "Removing stale {} (PID {} is no longer running)",
+            SERVER_RECORD_FILE, record.pid
+        );
+        remove_server_record(data_dir);
+        None
+    }
+}
+
+/// Contents of server-endpoint.json.
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointFile {
+    pub url: String,
+    pub port: u16,
+    pub pid: Option<u32>,
+    pub started_at: u64,
+    pub requires_auth: bool,
+}
+
+impl From<&ServerInfo> for EndpointFile {
+    fn from(info: &ServerInfo) -> Self {
+        Self {
+            url: info.url.clone(),
+            port: info.port,
+            pid: info.pid,
+            started_at: info.started_at,
+            requires_auth: info.auth_token_present,
+        }
+    }
+}
+
+pub fn endpoint_file_path(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join(ENDPOINT_FILE)
+}
+
+/// Write server-endpoint.json, readable only by the current user.
+pub fn write_endpoint_file(app_data_dir: &Path, info: &ServerInfo) -> Result<(), String> {
+    use std::io::Write;
+
+    let json = serde_json::to_string_pretty(&EndpointFile::from(info))
+        .map_err(|e| format!("Failed to serialize endpoint file: {}", e))?;
+    std::fs::create_dir_all(app_data_dir)
+        .map_err(|e| format!("Failed to create {}: {}", app_data_dir.display(), e))?;
+
+    // Write then rename so readers never see a partial file
+    let path = endpoint_file_path(app_data_dir);
+    let tmp_path = path.with_extension("json.tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(&tmp_path)
+        .and_then(|mut file| file.write_all(json.as_bytes()))
+        .and_then(|_| std::fs::rename(&tmp_path, &path))
+        .map_err(|e| format!("Failed to write {}: {}", ENDPOINT_FILE, e))
+}
+
+pub fn remove_endpoint_file(app_data_dir: &Path) {
+    let path = endpoint_file_path(app_data_dir);
+    if path.exists() {
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!("Failed to remove {}: {}", ENDPOINT_FILE, e);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionCompatibility {
+    Compatible,
+    TooOld,
+    TooNew,
+    Unknown,
+}
+
+/// Parse a `major.minor.patch` version, ignoring any pre-release/build suffix.
+pub fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let core = version
+        .trim()
+        .trim_start_matches('v')
+        .split(['-', '+'])
+        .next()?;
+    let mut parts = core.split('.').map(|p| p.parse::<u64>());
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+    let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+    Some((major, minor, patch))
+}
+
+/// Compare a server version against the range compiled into this binary:
+/// at least MIN_SERVER_VERSION and no newer than the app's own major.minor.
+pub fn check_server_compatibility(server_version: &str) -> VersionCompatibility {
+    let (Some(server), Some(min), Some(app)) = (
+        parse_version(server_version),
+        parse_version(MIN_SERVER_VERSION),
+        parse_version(env!("CARGO_PKG_VERSION")),
+    ) else {
+        return VersionCompatibility::Unknown;
+    };
+
+    if server < min {
+        VersionCompatibility::TooOld
+    } else if (server.0, server.1) > (app.0, app.1) {
+        VersionCompatibility::TooNew
+    } else {
+        VersionCompatibility::Compatible
+    }
+}
+
+/// Kill a server process and its children without waiting. Its port may
+/// take a moment to be released, so callers that bind it next wait
+/// KILL_PORT_RELEASE_WAIT first.
+pub fn force_kill(pid: u32) {
+    crate::process_utils::kill_tree(pid);
+}
+
+/// Ask a server to exit on its own, so it can finish writing its database:
+/// POST `/shutdown`, or on Unix SIGTERM its process group if that fails. Then
+/// wait for `pid` to exit, `grace` in all. False if it's still running and
+/// needs killing. Blocks.
+pub fn stop_gracefully(pid: u32, port: u16, grace: Duration) -> bool {
+    let deadline = Instant::now() + grace;
+    let requested = reqwest::blocking::Client::builder()
+        .timeout(SHUTDOWN_REQUEST_TIMEOUT.min(grace))
+        .build()
+        .and_then(|client| client.post(format!("http://127.0.0.1:{}/shutdown", port)).send())
+        .is_ok_and(|response| response.status().is_success());
+    if !requested {
+        #[cfg(unix)]
+        {
+            info!("Shutdown request to the server failed, sending SIGTERM instead");
+            crate::process_utils::terminate(pid);
+        }
+        // Windows has no signal to fall back on
+        #[cfg(windows)]
+        return !is_process_running(pid);
+    }
+    wait_for_exit(pid, deadline.saturating_duration_since(Instant::now()))
+}
+
+/// What stopping a server came to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct StopOutcome {
+    /// False if the server is still running even after being killed.
+    pub terminated: bool,
+    pub waited_ms: u64,
+    /// Whether it had to be killed after the grace period.
+    pub forced: bool,
+}
+
+impl StopOutcome {
+    /// For a stop with no process of ours to stop.
+    pub fn nothing_to_stop() -> Self {
+        Self {
+            terminated: true,
+            waited_ms: 0,
+            forced: false,
+        }
+    }
+}
+
+/// Poll until `pid` has exited, for up to `timeout`. False if it's still
+/// running. Blocks.
+pub fn wait_for_exit(pid: u32, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if !is_process_running(pid) {
+            return true;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return false;
+        }
+        std::thread::sleep(EXIT_POLL_INTERVAL.min(left));
+    }
+}
+
+/// Give a server this instance owns `grace` to exit on its own, then kill it
+/// with its children and wait up to `kill_wait` for it to go. A PID that no
+/// longer names a voicebox server has been reused and is left alone. Blocks.
+pub fn stop_server_process(pid: u32, port: u16, grace: Duration, kill_wait: Duration) -> StopOutcome {
+    let started = Instant::now();
+    let outcome = |terminated, forced| StopOutcome {
+        terminated,
+        waited_ms: started.elapsed().as_millis() as u64,
+        forced,
+    };
+    if !is_server_process(pid) {
+        info!("Server (PID {}) has already exited", pid);
+        return outcome(true, false);
+    }
+    if stop_gracefully(pid, port, grace) {
+        info!("Server (PID {}) exited gracefully", pid);
+        return outcome(true, false);
+    }
+    // Checked again right before the kill, in case it exited just now
+    if !is_server_process(pid) {
+        info!("Server (PID {}) exited at the end of the grace period", pid);
+        return outcome(true, false);
+    }
+    warn!("Server (PID {}) didn't exit within {:?}, killing it", pid, grace);
+    crate::process_utils::kill_tree(pid);
+
+    let terminated = wait_for_exit(pid, kill_wait);
+    if terminated {
+        info!("Server (PID {}) killed", pid);
+    } else {
+        error!("Server (PID {}) is still running after being killed", pid);
+    }
+    outcome(terminated, true)
+}
+
+/// stop_server_process for quitting, over within `budget` however the server
+/// behaves: the kill and the wait for it come out of the grace period.
+pub fn stop_server_process_within(pid: u32, port: u16, budget: Duration) -> StopOutcome {
+    let kill_wait = EXIT_KILL_WAIT.min(budget / 2);
+    stop_server_process(pid, port, budget - kill_wait, kill_wait)
+}
+
+/// Wait until `pid`, if given, has exited and nothing accepts connections on
+/// `port`, so a new server can bind it. False if that takes over `timeout`.
+pub async fn wait_for_port_free(port: u16, pid: Option<u32>, timeout: Duration) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let exited = !pid.is_some_and(is_process_running);
+        let connect = tokio::net::TcpStream::connect(("127.0.0.1", port));
+        let listening = matches!(tokio::time::timeout(Duration::from_millis(200), connect).await, Ok(Ok(_)));
+        if exited && !listening {
+            return true;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            warn!(
+                "Port {} still taken after {:?} (server {})",
+                port,
+                timeout,
+                if exited { "exited" } else { "still running" }
+            );
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Whether a listener could bind `port` on `host` right now.
+pub fn is_port_bindable(host: std::net::IpAddr, port: u16) -> bool {
+    std::net::TcpListener::bind((host, port)).is_ok()
+}
+
+/// `preferred` if it can be bound on `host`, otherwise the first free port in
+/// FALLBACK_PORTS, otherwise one the OS assigns. None only if binding fails
+/// outright.
+pub fn pick_port(host: std::net::IpAddr, preferred: u16) -> Option<u16> {
+    std::iter::once(preferred)
+        .chain(FALLBACK_PORTS.filter(|&port| port != preferred))
+        .find(|&port| is_port_bindable(host, port))
+        .or_else(|| {
+            let listener = std::net::TcpListener::bind((host, 0)).ok()?;
+            listener.local_addr().ok().map(|addr| addr.port())
+        })
+}
+
+/// Change the scheduling priority of a running server process.
+#[cfg(unix)]
+pub fn set_process_priority(pid: u32, priority: ProcessPriority) -> Result<(), String> {
+    let nice = match priority {
+        ProcessPriority::Normal => 0,
+        ProcessPriority::BelowNormal => 10,
+        ProcessPriority::Background => 19,
+    };
+
+    // Raising the nice value is always allowed; lowering it back needs privileges
+    let result = unsafe { libc::setpriority(libc::PRIO_PROCESS as _, pid as libc::id_t, nice) };
+    if result != 0 {
+        return Err(format!(
+            "Failed to set priority of PID {}: {}",
+            pid,
+            std::io::Error::last_os_error()
+        ));
+    }
+    // The sidecar's own children (e.g. the PyInstaller bootloader's child) share
+    // its process group; best effort since the server may not lead a group
+    unsafe {
+        libc::setpriority(libc::PRIO_PGRP as _, pid as libc::id_t, nice);
+    }
+
+    // Background on macOS also moves the process to the efficiency cores and
+    // throttles its I/O, which nice alone doesn't do
+    #[cfg(target_os = "macos")]
+    {
+        use std::process::Command;
+        let flag = if priority == ProcessPriority::Background { "-b" } else { "-B" };
+        match Command::new("taskpolicy").args([flag, "-p", &pid.to_string()]).output() {
+            Ok(output) if !output.status.success() => {
+                warn!(
+                    "taskpolicy {} failed for PID {}: {}",
+                    flag,
+                    pid,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            Err(e) => warn!("Failed to run taskpolicy: {}", e),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Change the scheduling priority of a running server process.
+#[cfg(windows)]
+pub fn set_process_priority(pid: u32, priority: ProcessPriority) -> Result<(), String> {
+    use windows::Win32::Foundation::CloseHandle;
+    use windows::Win32::System::Threading::{
+        OpenProcess, ProcessPowerThrottling, SetPriorityClass, SetProcessInformation,
+        BELOW_NORMAL_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS,
+        PROCESS_POWER_THROTTLING_CURRENT_VERSION, PROCESS_POWER_THROTTLING_EXECUTION_SPEED,
+        PROCESS_POWER_THROTTLING_STATE, PROCESS_SET_INFORMATION,
+    };
+
+    let priority_class = match priority {
+        ProcessPriority::Normal => NORMAL_PRIORITY_CLASS,
+        ProcessPriority::BelowNormal => BELOW_NORMAL_PRIORITY_CLASS,
+        ProcessPriority::Background => IDLE_PRIORITY_CLASS,
+    };
+
+    unsafe {
+        let handle = OpenProcess(PROCESS_SET_INFORMATION, false, pid)
+            .map_err(|e| format!("Failed to open PID {}: {}", pid, e))?;
+
+        let result = SetPriorityClass(handle, priority_class)
+            .map_err(|e| format!("Failed to set priority of PID {}: {}", pid, e));
+
+        // EcoQoS (Windows 11): background work runs on efficiency cores at low clocks.
+        // Older Windows rejects the call, which is fine
+        let throttling = PROCESS_POWER_THROTTLING_STATE {
+            Version: PROCESS_POWER_THROTTLING_CURRENT_VERSION,
+            ControlMask: PROCESS_POWER_THROTTLING_EXECUTION_SPEED,
+            StateMask: if priority == ProcessPriority::Background {
+                PROCESS_POWER_THROTTLING_EXECUTION_SPEED
+            } else {
+                0
+            },
+        };
+        if let Err(e) = SetProcessInformation(
+            handle,
+            ProcessPowerThrottling,
+            &throttling as *const _ as *const std::ffi::c_void,
+            std::mem::size_of::<PROCESS_POWER_THROTTLING_STATE>() as u32,
+        ) {
+            info!("EcoQoS not applied to PID {}: {}", pid, e);
+        }
+
+        let _ = CloseHandle(handle);
+        result
+    }
+}
+
+/// Check if a process is still running
+pub fn is_process_running(pid: u32) -> bool {
+    crate::process_utils::is_running(pid)
+}
+
+/// Whether `pid` is still a running voicebox server, rather than gone or
+/// reused by another program since it was recorded. Assumed to be one when
+/// its name can't be read.
+pub fn is_server_process(pid: u32) -> bool {
+    is_process_running(pid)
+        && crate::process_utils::process_name(pid).is_none_or(|name| crate::process_utils::is_voicebox_server(&name))
+}
+
+pub fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Host probed to decide whether the server can reach the model hub.
+const NETWORK_PROBE_HOST: &str = "huggingface.co:443";
+
+/// Quick DNS + TCP probe of the model hub. Bounded so it never holds up a
+/// server start by more than ~1.5s.
+pub async fn detect_network_available() -> bool {
+    let probe = tokio::net::TcpStream::connect(NETWORK_PROBE_HOST);
+    matches!(
+        tokio::time::timeout(std::time::Duration::from_millis(1500), probe).await,
+        Ok(Ok(_))
+    )
+}
+
+fn local_url(port: u16) -> String {
+    format!("http://127.0.0.1:{}", port)
+}
+
+fn server_get(
+    client: &reqwest::Client,
+    base_url: &str,
+    path: &str,
+    auth_token: Option<&str>,
+) -> reqwest::RequestBuilder {
+    let request = client.get(format!("{}{}", base_url.trim_end_matches('/'), path));
+    match auth_token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+/// Whether the local server answers its health endpoint.
+pub async fn check_health(port: u16) -> bool {
+    check_health_at(&local_url(port), None).await
+}
+
+/// Whether the local server answers its health endpoint with a 200 within
+/// READY_PROBE_TIMEOUT: its routes are registered, not just its port bound.
+pub async fn is_ready(port: u16) -> bool {
+    is_ready_at(&local_url(port), None).await
+}
+
+/// Like is_ready, for the server at `base_url`.
+pub async fn is_ready_at(base_url: &str, auth_token: Option<&str>) -> bool {
+    let Ok(client) = reqwest::Client::builder().timeout(READY_PROBE_TIMEOUT).build() else {
+        return false;
+    };
+    server_get(&client, base_url, "/health", auth_token)
+        .send()
+        .await
+        .is_ok_and(|response| response.status() == reqwest::StatusCode::OK)
+}
+
+/// Whether the server at `base_url` answers its health endpoint.
+pub async fn check_health_at(base_url: &str, auth_token: Option<&str>) -> bool {
+    let Ok(client) = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(2))
+        .build()
+    else {
+        return false;
+    };
+    server_get(&client, base_url, "/health", auth_token)
+        .send()
+        .await
+        .map(|response| response.status().is_success())
+        .unwrap_or(false)
+}
+
+/// Ask the local server for its version via the root endpoint.
+pub async fn fetch_server_version(port: u16) -> Option<String> {
+    fetch_server_version_at(&local_url(port), None).await
+}
+
+/// Ask the server at `base_url` for its version via the root endpoint.
+pub async fn fetch_server_version_at(base_url: &str, auth_token: Option<&str>) -> Option<String> {
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(2))
+        .build()
+        .ok()?;
+    let response = server_get(&client, base_url, "/", auth_token)
+        .send()
+        .await
+        .ok()?;
+    let body: serde_json::Value = response.json().await.ok()?;
+    body.get("version")
+        .and_then(|v| v.as_str())
+        .map(|v| v.to_string())
+}
+
+/// What a voicebox server's root endpoint says in `message`.
+pub const SERVER_GREETING: &str = "voicebox API";
+
+/// Why the server at a URL can't be used; see identify_server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeFailure {
+    /// Nothing answered HTTP there: the host is down or unknown, the port is
+    /// closed or a firewall is in the way.
+    Unreachable(String),
+    /// A voicebox server that wants a different auth token, or one at all.
+    Unauthorized,
+    /// Something answered, but not a voicebox server.
+    NotVoicebox(String),
+}
+
+impl ProbeFailure {
+    pub fn message(&self, url: &str) -> String {
+        match self {
+            ProbeFailure::Unreachable(reason) => format!(
+                "Can't reach {}: {}. Check the address and port, and that the server is running",
+                url, reason
+            ),
+            ProbeFailure::Unauthorized => format!("The server at {} refused the auth token", url),
+            ProbeFailure::NotVoicebox(reason) => {
+                format!("{} answered, but it isn't a Voicebox server ({})", url, reason)
+            }
+        }
+    }
+}
+
+/// Check that a voicebox server answers at `base_url`: its health endpoint
+/// responds and its root endpoint greets as one. Returns the version it
+/// reports, if any.
+pub async fn identify_server(base_url: &str, auth_token: Option<&str>) -> Result<Option<String>, ProbeFailure> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .map_err(|e| ProbeFailure::Unreachable(e.to_string()))?;
+    let health = server_get(&client, base_url, "/health", auth_token)
+        .send()
+        .await
+        .map_err(|e| ProbeFailure::Unreachable(probe_error(&e)))?;
+    let status = health.status();
+    if matches!(status, reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN) {
+        return Err(ProbeFailure::Unauthorized);
+    }
+    if !status.is_success() {
+        return Err(ProbeFailure::NotVoicebox(format!("/health answered {}", status)));
+    }
+
+    let root = server_get(&client, base_url, "/", auth_token)
+        .send()
+        .await
+        .map_err(|e| ProbeFailure::Unreachable(probe_error(&e)))?;
+    let body: serde_json::Value = root
+        .json()
+        .await
+        .map_err(|_| ProbeFailure::NotVoicebox("its root page isn't JSON".to_string()))?;
+    if body.get("message").and_then(|m| m.as_str()) != Some(SERVER_GREETING) {
+        return Err(ProbeFailure::NotVoicebox("its root page doesn't identify it".to_string()));
+    }
+    Ok(body.get("version").and_then(|v| v.as_str()).map(|v| v.to_string()))
+}
+
+fn probe_error(e: &reqwest::Error) -> String {
+    if e.is_timeout() {
+        "no answer within 5 seconds".to_string()
+    } else if e.is_connect() {
+        "connection failed".to_string()
+    } else {
+        e.to_string()
+    }
+}
+
+/// The server's health report (model, GPU and VRAM use) as returned.
+pub async fn fetch_health_at(base_url: &str, auth_token: Option<&str>) -> Result<serde_json::Value, String> {
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    let response = server_get(&client, base_url, "/health", auth_token)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to reach the server: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("Server returned {} for /health", response.status()));
+    }
+    response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid health response: {}", e))
+}
+
+/// Download a generation's audio file from the server.
+pub async fn fetch_generation_audio(
+    base_url: &str,
+    auth_token: Option<&str>,
+    generation_id: &str,
+) -> Result<Option<Vec<u8>>, String> {
+    if generation_id.is_empty() || !generation_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(format!("Invalid generation id '{}'", generation_id));
+    }
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(60))
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    let response = server_get(&client, base_url, &format!("/audio/{}", generation_id), auth_token)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to reach the server: {}", e))?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(format!("Server returned {} for generation audio", response.status()));
+    }
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|e| format!("Failed to download generation audio: {}", e))?;
+    Ok(Some(bytes.to_vec()))
+}
+
+/// Check a user-supplied server URL and normalize it to `scheme://host:port`
+/// without a trailing slash. Returns the URL and its port.
+pub fn parse_external_url(url: &str) -> Result<(String, u16), String> {
+    let parsed = reqwest::Url::parse(url.trim()).map_err(|e| format!("Invalid server URL: {}", e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!(
+            "Unsupported URL scheme '{}', expected http or https",
+            parsed.scheme()
+        ));
+    }
+    let Some(host) = parsed.host_str() else {
+        return Err("Server URL has no host".to_string());
+    };
+    // Paths like /health are appended to it, so it has to be the server's root
+    if parsed.path() != "/" || parsed.query().is_some() || parsed.fragment().is_some() {
+        return Err(format!("Server URL {} has a path or query; give just the scheme, host and port", url.trim()));
+    }
+    let port = parsed
+        .port_or_known_default()
+        .ok_or_else(|| "Server URL has no port".to_string())?;
+    Ok((format!("{}://{}:{}", parsed.scheme(), host, port), port))
+}
+
+/// URLs under which a server bound to 0.0.0.0 is reachable from the LAN.
+pub fn remote_urls(port: u16) -> Vec<String> {
+    // Connecting a UDP socket sends nothing but makes the OS pick the outbound
+    // interface, which is the address other machines on the LAN can reach.
+    let local_ip = std::net::UdpSocket::bind("0.0.0.0:0")
+        .and_then(|socket| {
+            socket.connect("8.8.8.8:80")?;
+            socket.local_addr()
+        })
+        .map(|addr| addr.ip())
+        .ok();
+
+    match local_ip {
+        Some(ip) if !ip.is_loopback() && !ip.is_unspecified() => {
+            vec![format!("http://{}:{}", ip, port)]
+        }
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,319 @@
+use std::fmt;
+
+/// A process listening on a TCP port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listener {
+    pub pid: u32,
+    /// Executable name, e.g. `voicebox-server` or `python3.exe`; empty when
+    /// it couldn't be read.
+    pub name: String,
+}
+
+impl Listener {
+    pub fn is_voicebox_server(&self) -> bool {
+        is_voicebox_server(&self.name)
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = if self.name.is_empty() { "unknown process" } else { &self.name };
+        write!(f, "{} (PID {})", name, self.pid)
+    }
+}
+
+/// Whether a process with this executable name is a voicebox server: the
+/// sidecar under any platform's naming (`voicebox-server`,
+/// `voicebox-server.exe`), or an older build's. A dev server run from Python
+/// shows up as `python` and isn't one.
+pub fn is_voicebox_server(name: &str) -> bool {
+    name.to_lowercase().contains("voicebox")
+}
+
+/// The processes listening on `port` on any local address. Sockets of other
+/// users' processes may be missing without the rights to inspect them.
+pub fn listeners_on(port: u16) -> Vec<Listener> {
+    let mut pids = listening_pids(port);
+    pids.sort_unstable();
+    pids.dedup();
+    pids.into_iter()
+        .map(|pid| Listener {
+            pid,
+            name: process_name(pid).unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// /proc needs no extra crate: find the inodes of listening sockets on the
+/// port, then the processes holding them.
+#[cfg(target_os = "linux")]
+fn listening_pids(port: u16) -> Vec<u32> {
+    // st 0A is TCP_LISTEN
+    const LISTEN: &str = "0A";
+    let mut inodes = Vec::new();
+    for table in ["/proc/net/tcp", "/proc/net/tcp6"] {
+        let Ok(contents) = std::fs::read_to_string(table) else {
+            continue;
+        };
+        for line in contents.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 10 || fields[3] != LISTEN {
+                continue;
+            }
+            let local_port = fields[1].rsplit(':').next().and_then(|hex| u16::from_str_radix(hex, 16).ok());
+            if local_port == Some(port) {
+                inodes.push(format!("socket:[{}]", fields[9]));
+            }
+        }
+    }
+    if inodes.is_empty() {
+        return Vec::new();
+    }
+
+    let Ok(processes) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    processes
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|pid| {
+            let Ok(fds) = std::fs::read_dir(format!("/proc/{}/fd", pid)) else {
+                return false;
+            };
+            fds.flatten().any(|fd| {
+                std::fs::read_link(fd.path())
+                    .is_ok_and(|target| inodes.iter().any(|inode| target.as_os_str() == inode.as_str()))
+            })
+        })
+        .collect()
+}
+
+#[cfg(any(target_os = "macos", windows))]
+fn listening_pids(port: u16) -> Vec<u32> {
+    use netstat2::{AddressFamilyFlags, ProtocolFlags, ProtocolSocketInfo, TcpState};
+
+    let sockets = match netstat2::get_sockets_info(
+        AddressFamilyFlags::IPV4 | AddressFamilyFlags::IPV6,
+        ProtocolFlags::TCP,
+    ) {
+        Ok(sockets) => sockets,
+        Err(e) => {
+            tracing::warn!("Failed to list sockets: {}", e);
+            return Vec::new();
+        }
+    };
+    sockets
+        .into_iter()
+        .filter(|socket| {
+            matches!(
+                &socket.protocol_socket_info,
+                ProtocolSocketInfo::Tcp(tcp) if tcp.local_port == port && tcp.state == TcpState::Listen
+            )
+        })
+        .flat_map(|socket| socket.associated_pids)
+        .collect()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn listening_pids(_port: u16) -> Vec<u32> {
+    Vec::new()
+}
+
+/// The executable name of `pid`, without its directory.
+#[cfg(target_os = "linux")]
+pub fn process_name(pid: u32) -> Option<String> {
+    let name = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    Some(name.trim_end().to_string())
+}
+
+/// The executable name of `pid`, without its directory.
+#[cfg(target_os = "macos")]
+pub fn process_name(pid: u32) -> Option<String> {
+    let mut buffer = [0u8; 256];
+    let len = unsafe { libc::proc_name(pid as libc::c_int, buffer.as_mut_ptr().cast(), buffer.len() as u32) };
+    if len <= 0 {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&buffer[..len as usize]).into_owned())
+}
+
+/// The executable name of `pid`, without its directory.
+#[cfg(windows)]
+pub fn process_name(pid: u32) -> Option<String> {
+    use std::path::Path;
+    use windows::core::PWSTR;
+    use windows::Win32::Foundation::CloseHandle;
+    use windows::Win32::System::Threading::{
+        OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+        let _close = scopeguard::guard(process, |process| {
+            let _ = CloseHandle(process);
+        });
+        let mut buffer = [0u16; 1024];
+        let mut len = buffer.len() as u32;
+        QueryFullProcessImageNameW(process, PROCESS_NAME_WIN32, PWSTR(buffer.as_mut_ptr()), &mut len).ok()?;
+        let path = String::from_utf16_lossy(&buffer[..len as usize]);
+        Path::new(&path).file_name().map(|name| name.to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn process_name(_pid: u32) -> Option<String> {
+    None
+}
+
+/// Whether `pid` exists. A process that exited but wasn't reaped yet still does.
+#[cfg(unix)]
+pub fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks; EPERM means it exists but belongs to someone else
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Whether `pid` exists and hasn't exited.
+#[cfg(windows)]
+pub fn is_running(pid: u32) -> bool {
+    use windows::Win32::Foundation::{CloseHandle, STILL_ACTIVE};
+    use windows::Win32::System::Threading::{GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION};
+
+    unsafe {
+        let Ok(handle) = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) else {
+            return false;
+        };
+        let mut code = 0u32;
+        let result = GetExitCodeProcess(handle, &mut code);
+        let _ = CloseHandle(handle);
+        result.is_ok() && code == STILL_ACTIVE.0 as u32
+    }
+}
+
+/// Ask `pid` and the process group it leads to exit, with SIGTERM.
+#[cfg(unix)]
+pub fn terminate(pid: u32) {
+    if let Ok(pid) = libc::pid_t::try_from(pid) {
+        if pid > 0 {
+            unsafe { libc::kill(-pid, libc::SIGTERM) };
+        }
+    }
+}
+
+/// Kill `pid` at once, and on Unix the process group it leads.
+#[cfg(unix)]
+pub fn kill(pid: u32) {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return;
+    };
+    if pid <= 0 {
+        return;
+    }
+    unsafe {
+        libc::kill(-pid, libc::SIGKILL);
+        libc::kill(pid, libc::SIGKILL);
+    }
+}
+
+/// Kill `pid` at once. Its children are left running; kill_tree takes
+/// them too.
+#[cfg(windows)]
+pub fn kill(pid: u32) {
+    use windows::Win32::Foundation::CloseHandle;
+    use windows::Win32::System::Threading::{OpenProcess, TerminateProcess, PROCESS_TERMINATE};
+
+    unsafe {
+        if let Ok(handle) = OpenProcess(PROCESS_TERMINATE, false, pid) {
+            let _ = TerminateProcess(handle, 1);
+            let _ = CloseHandle(handle);
+        }
+    }
+}
+
+/// Kill `pid` and every process descended from it, which on Windows, with no
+/// process groups, is the only way to take down the server's children.
+pub fn kill_tree(pid: u32) {
+    // Listed before the kill, which leaves the children without a parent
+    let descendants = descendants(pid, &process_table());
+    kill(pid);
+    for child in descendants {
+        kill(child);
+    }
+}
+
+/// The processes descended from `pid` in `processes`, each a PID and its
+/// parent's, children before grandchildren.
+pub fn descendants(pid: u32, processes: &[(u32, u32)]) -> Vec<u32> {
+    let mut found: Vec<u32> = Vec::new();
+    let mut next = 0;
+    let mut parent = pid;
+    loop {
+        for &(child, _) in processes.iter().filter(|(_, of)| *of == parent) {
+            // A PID that is its own parent, or loops back, isn't walked twice
+            if child != pid && !found.contains(&child) {
+                found.push(child);
+            }
+        }
+        let Some(&child) = found.get(next) else {
+            return found;
+        };
+        parent = child;
+        next += 1;
+    }
+}
+
+/// The PIDs of the processes running, each with its parent's.
+#[cfg(target_os = "linux")]
+pub fn process_table() -> Vec<(u32, u32)> {
+    let Ok(processes) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    processes
+        .flatten()
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+            let stat = std::fs::read_to_string(entry.path().join("stat")).ok()?;
+            // The name in parentheses may hold spaces; the parent is the second field after it
+            let parent = stat.rsplit_once(')')?.1.split_whitespace().nth(1)?.parse().ok()?;
+            Some((pid, parent))
+        })
+        .collect()
+}
+
+/// The PIDs of the processes running, each with its parent's.
+#[cfg(windows)]
+pub fn process_table() -> Vec<(u32, u32)> {
+    use windows::Win32::Foundation::CloseHandle;
+    use windows::Win32::System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS,
+    };
+
+    let mut table = Vec::new();
+    unsafe {
+        let Ok(snapshot) = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) else {
+            return table;
+        };
+        let _close = scopeguard::guard(snapshot, |snapshot| {
+            let _ = CloseHandle(snapshot);
+        });
+        let mut entry = PROCESSENTRY32W {
+            dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+            ..Default::default()
+        };
+        let mut listed = Process32FirstW(snapshot, &mut entry);
+        while listed.is_ok() {
+            table.push((entry.th32ProcessID, entry.th32ParentProcessID));
+            listed = Process32NextW(snapshot, &mut entry);
+        }
+    }
+    table
+}
+
+/// Elsewhere a server's children are in its process group, which kill takes.
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn process_table() -> Vec<(u32, u32)> {
+    Vec::new()
+}
@@ -0,0 +1,157 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::window::{ProgressBarState, ProgressBarStatus};
+use tauri::{AppHandle, Manager};
+use tracing::warn;
+
+/// Operations whose progress events drive the indicator, with the source name
+/// they are tracked under.
+const PROGRESS_EVENTS: [(&str, &str); 3] = [
+    ("data-migration-progress", "data-migration"),
+    ("data-backup-progress", "data-backup"),
+    ("data-restore-progress", "data-restore"),
+];
+
+/// Source name for progress set from the frontend with set_app_progress.
+pub const APP_SOURCE: &str = "app";
+
+/// How long a failed model download stays red before its bar is cleared.
+const ERROR_DISPLAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProgressState {
+    #[default]
+    Normal,
+    Paused,
+    Error,
+}
+
+/// Progress per running operation; the dock/taskbar shows them combined.
+pub struct ProgressIndicatorState {
+    sources: Mutex<BTreeMap<String, (f32, ProgressState)>>,
+}
+
+impl ProgressIndicatorState {
+    pub fn new() -> Self {
+        Self {
+            sources: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+/// Report `source`'s progress (0.0-1.0), or clear it with None. The OS shows
+/// the average of all active sources, in the most severe state among them.
+/// Without dock/taskbar progress support (most Linux desktops) this does nothing.
+pub fn set(app: &AppHandle, source: &str, fraction: Option<f32>, state: ProgressState) {
+    let indicator = app.state::<ProgressIndicatorState>();
+    let bar = {
+        let mut sources = indicator.sources.lock().unwrap();
+        match fraction {
+            Some(fraction) => {
+                sources.insert(source.to_string(), (fraction.clamp(0.0, 1.0), state));
+            }
+            None => {
+                sources.remove(source);
+            }
+        }
+        combined(&sources)
+    };
+
+    if let Some(window) = app.get_webview_window("main") {
+        if let Err(e) = window.set_progress_bar(bar) {
+            // Not fatal: the in-app progress UI still works
+            warn!("Failed to set taskbar progress: {}", e);
+        }
+    }
+}
+
+fn combined(sources: &BTreeMap<String, (f32, ProgressState)>) -> ProgressBarState {
+    if sources.is_empty() {
+        return ProgressBarState {
+            status: Some(ProgressBarStatus::None),
+            progress: None,
+        };
+    }
+    let average = sources.values().map(|(f, _)| f).sum::<f32>() / sources.len() as f32;
+    let status = if sources.values().any(|(_, s)| *s == ProgressState::Error) {
+        ProgressBarStatus::Error
+    } else if sources.values().any(|(_, s)| *s == ProgressState::Paused) {
+        ProgressBarStatus::Paused
+    } else {
+        ProgressBarStatus::Normal
+    };
+    ProgressBarState {
+        status: Some(status),
+        progress: Some((average * 100.0).round() as u64),
+    }
+}
+
+/// Clears `source` when dropped, so an operation that fails or panics midway
+/// never leaves a stuck progress bar behind.
+pub fn clear_on_drop(app: &AppHandle, source: &'static str) -> impl Drop {
+    let app = app.clone();
+    scopeguard::guard((), move |_| set(&app, source, None, ProgressState::Normal))
+}
+
+/// Follow the data-dir progress events, and the `model-download-progress`
+/// events the frontend emits while it tracks downloads through the server.
+/// Called from setup.
+pub fn install(app: &AppHandle) {
+    for (event, source) in PROGRESS_EVENTS {
+        let handle = app.clone();
+        crate::event_bus::listen(app, event, move |payload| {
+            let Ok(payload) = serde_json::from_str::<serde_json::Value>(payload) else {
+                return;
+            };
+            let done = payload["bytes_done"].as_u64().unwrap_or(0);
+            let total = payload["bytes_total"].as_u64().unwrap_or(0);
+            let fraction = if total == 0 { 0.0 } else { done as f32 / total as f32 };
+            let finished = payload["files_done"] == payload["files_total"];
+            let fraction = (!finished).then_some(fraction);
+            set(&handle, source, fraction, ProgressState::Normal);
+        });
+    }
+
+    let handle = app.clone();
+    crate::event_bus::listen(app, "model-download-progress", move |payload| {
+        let Ok(payload) = serde_json::from_str::<serde_json::Value>(payload) else {
+            return;
+        };
+        let Some(model) = payload["model_name"].as_str() else {
+            return;
+        };
+        let source = format!("model-download:{}", model);
+        let fraction = payload["fraction"].as_f64().map(|f| f as f32);
+        if !payload["error"].as_bool().unwrap_or(false) {
+            set(&handle, &source, fraction, ProgressState::Normal);
+            return;
+        }
+        // The frontend stops tracking a failed download, so nothing else would clear it
+        set(&handle, &source, Some(fraction.unwrap_or(1.0)), ProgressState::Error);
+        let handle = handle.clone();
+        tauri::async_runtime::spawn(async move {
+            tokio::time::sleep(ERROR_DISPLAY).await;
+            // Unless a retry has started reporting progress since
+            let failed = {
+                let sources = handle.state::<ProgressIndicatorState>().sources.lock().unwrap();
+                matches!(sources.get(&source), Some((_, ProgressState::Error)))
+            };
+            if failed {
+                set(&handle, &source, None, ProgressState::Normal);
+            }
+        });
+    });
+
+    let handle = app.clone();
+    crate::event_bus::listen(app, "model-download-complete", move |payload| {
+        let Ok(payload) = serde_json::from_str::<serde_json::Value>(payload) else {
+            return;
+        };
+        if let Some(model) = payload["model_name"].as_str() {
+            set(&handle, &format!("model-download:{}", model), None, ProgressState::Normal);
+        }
+    });
+}
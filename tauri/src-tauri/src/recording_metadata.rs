@@ -0,0 +1,251 @@
+use crate::audio_util;
+use serde::{Deserialize, Serialize};
+
+/// What bext's Originator field and the INFO software tag name.
+const ORIGINATOR: &str = "Voicebox";
+
+/// Processing applied between the capture and the saved WAV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Processing {
+    /// Channels averaged into one.
+    Mono,
+    /// Scaled to peak at NORMALIZE_PEAK.
+    Normalize,
+    /// Silence at either end left out.
+    TrimSilence,
+    /// Saved at another rate than the one recorded at.
+    Resample,
+}
+
+impl Processing {
+    fn label(self) -> &'static str {
+        match self {
+            Processing::Mono => "mono",
+            Processing::Normalize => "normalize",
+            Processing::TrimSilence => "trim_silence",
+            Processing::Resample => "resample",
+        }
+    }
+}
+
+/// Where a saved capture came from, for users building datasets from them.
+/// Every field has a default, so entries saved before a field existed still
+/// load.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordingMetadata {
+    /// The capture backend, e.g. `screencapturekit` or `wasapi`.
+    pub backend: Option<String>,
+    /// Of the saved WAV, after processing.
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub duration_ms: u64,
+    /// Gaps the backend reported in the delivered audio.
+    pub dropouts: u64,
+    pub processing: Vec<Processing>,
+    /// Unix seconds.
+    pub started_at: Option<u64>,
+    /// Set when the capture asked for pre-roll.
+    pub pre_roll: Option<PreRoll>,
+    pub health: CaptureHealth,
+    pub app_version: Option<String>,
+    /// Spectral hash of the audio for finding near duplicates in the library,
+    /// base64. None for captures over the fingerprint_max_minutes setting.
+    pub fingerprint: Option<String>,
+    /// Silence auto-pause and pauses by hand left out, in order. Putting
+    /// each back restores the capture's original timeline.
+    pub pauses: Vec<PauseInterval>,
+    /// The format the backend delivered, before any processing. None for
+    /// entries saved before it was recorded.
+    pub source_format: Option<SourceFormat>,
+}
+
+/// How the capture's audio path held up, to tell a busy machine from a
+/// broken device. 0 where the backend doesn't measure a counter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureHealth {
+    /// Deliveries that found the capture buffer held by a reader, such as
+    /// the level meter, and had to wait for it.
+    pub lock_contentions: u64,
+    /// Most frames the device had queued when the backend read it (WASAPI).
+    pub max_lag_frames: u64,
+    /// Waits for the device that overran a period while audio was waiting
+    /// (WASAPI).
+    pub late_wakeups: u64,
+    /// Sample buffers that came well after the previous one ended
+    /// (ScreenCaptureKit).
+    pub callback_gaps: u64,
+    /// Times the capture lost its output device, to unplugging or a new
+    /// default, and carried on on the one that took over (WASAPI).
+    pub device_switches: u64,
+}
+
+/// The audio as the backend delivered it, which may not be what it was
+/// asked for: ScreenCaptureKit can deliver 44.1 kHz or mono despite asking
+/// for 48 kHz stereo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SourceFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// A buffer per channel, interleaved by the backend.
+    pub planar: bool,
+}
+
+/// Audio from before the capture started, asked for and got. `included_ms`
+/// is 0 when there was no rolling buffer to take it from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreRoll {
+    pub requested_ms: u32,
+    pub included_ms: u64,
+}
+
+/// Audio a capture left out while auto-paused for source silence or paused
+/// by hand.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PauseInterval {
+    /// Where in the saved audio the gap falls.
+    pub at_ms: u64,
+    /// How much was left out.
+    pub duration_ms: u64,
+}
+
+/// How export_audio embeds metadata in a WAV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataChunk {
+    /// A LIST/INFO chunk, which most players and tag editors show.
+    Info,
+    /// A Broadcast Wave (EBU Tech 3285) bext chunk, for DAWs and archives.
+    Bext,
+}
+
+impl RecordingMetadata {
+    /// One line describing the capture, for INFO's comment and bext's
+    /// description.
+    pub fn summary(&self) -> String {
+        let mut parts = vec![format!("{} Hz", self.sample_rate), format!("{} ch", self.channels)];
+        if let Some(backend) = &self.backend {
+            parts.insert(0, format!("backend={}", backend));
+        }
+        parts.push(format!("dropouts={}", self.dropouts));
+        if !self.processing.is_empty() {
+            let processing: Vec<&str> = self.processing.iter().map(|p| p.label()).collect();
+            parts.push(format!("processing={}", processing.join("+")));
+        }
+        if !self.pauses.is_empty() {
+            let paused_ms: u64 = self.pauses.iter().map(|pause| pause.duration_ms).sum();
+            parts.push(format!("paused_ms={}", paused_ms));
+        }
+        format!("Voicebox capture: {}", parts.join(", "))
+    }
+
+    fn software(&self) -> String {
+        match &self.app_version {
+            Some(version) => format!("{} {}", ORIGINATOR, version),
+            None => ORIGINATOR.to_string(),
+        }
+    }
+}
+
+/// Embed `metadata` into `wav` as the given chunk.
+pub fn embed(wav: &[u8], metadata: &RecordingMetadata, chunk: MetadataChunk) -> Result<Vec<u8>, String> {
+    let chunk = match chunk {
+        MetadataChunk::Info => info_chunk(metadata),
+        MetadataChunk::Bext => bext_chunk(metadata),
+    };
+    audio_util::insert_chunks(wav, &[chunk])
+}
+
+/// LIST/INFO with the software (ISFT), creation date (ICRD) and a comment
+/// (ICMT) carrying the summary.
+pub fn info_chunk(metadata: &RecordingMetadata) -> Vec<u8> {
+    let mut entries = vec![(b"ISFT", metadata.software())];
+    if let Some(started_at) = metadata.started_at {
+        entries.push((b"ICRD", civil_date(started_at)));
+    }
+    entries.push((b"ICMT", metadata.summary()));
+
+    let mut body = b"INFO".to_vec();
+    for (id, value) in entries {
+        // INFO strings are NUL-terminated
+        let mut text = value.into_bytes();
+        text.push(0);
+        body.extend(audio_util::riff_chunk(id, &text));
+    }
+    audio_util::riff_chunk(b"LIST", &body)
+}
+
+/// A version 2 bext chunk. Loudness isn't measured, so those fields hold
+/// 0x7FFF, the spec's value for unknown.
+pub fn bext_chunk(metadata: &RecordingMetadata) -> Vec<u8> {
+    let mut body = Vec::with_capacity(640);
+    put_ascii(&mut body, &metadata.summary(), 256);
+    put_ascii(&mut body, ORIGINATOR, 32);
+    put_ascii(&mut body, "", 32);
+    let (date, time, time_reference) = match metadata.started_at {
+        Some(started_at) => (
+            civil_date(started_at),
+            clock_time(started_at),
+            // Samples since midnight
+            (started_at % 86400) * metadata.sample_rate as u64,
+        ),
+        None => (String::new(), String::new(), 0),
+    };
+    put_ascii(&mut body, &date, 10);
+    put_ascii(&mut body, &time, 8);
+    body.extend_from_slice(&(time_reference as u32).to_le_bytes());
+    body.extend_from_slice(&((time_reference >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&2u16.to_le_bytes());
+    // UMID
+    body.extend_from_slice(&[0; 64]);
+    // Loudness value and range, max true peak, max momentary and short-term
+    for _ in 0..5 {
+        body.extend_from_slice(&0x7FFFi16.to_le_bytes());
+    }
+    body.extend_from_slice(&[0; 180]);
+    body.extend_from_slice(coding_history(metadata).as_bytes());
+    audio_util::riff_chunk(b"bext", &body)
+}
+
+/// One coding history line (EBU R 98) for the saved PCM.
+fn coding_history(metadata: &RecordingMetadata) -> String {
+    let mode = match metadata.channels {
+        1 => "mono",
+        2 => "stereo",
+        _ => "multichannel",
+    };
+    format!("A=PCM,F={},W=16,M={},T={}\r\n", metadata.sample_rate, mode, metadata.software())
+}
+
+/// `value` as ASCII, cut or NUL-padded to `len` bytes.
+fn put_ascii(out: &mut Vec<u8>, value: &str, len: usize) {
+    let bytes: Vec<u8> = value.bytes().map(|b| if b.is_ascii() { b } else { b'?' }).take(len).collect();
+    out.extend_from_slice(&bytes);
+    out.resize(out.len() + len - bytes.len(), 0);
+}
+
+/// `yyyy-mm-dd` in UTC.
+fn civil_date(unix_secs: u64) -> String {
+    // Howard Hinnant's civil_from_days
+    let z = (unix_secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// `hh:mm:ss` in UTC.
+fn clock_time(unix_secs: u64) -> String {
+    let secs = unix_secs % 86400;
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
@@ -0,0 +1,331 @@
+use crate::error::VoiceboxError;
+use crate::fingerprint::{self, SimilarRecording};
+use crate::recording_metadata::RecordingMetadata;
+use crate::settings::RecordingsQuota;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use tracing::{info, warn};
+
+/// The library's directory, under the data dir.
+pub const RECORDINGS_DIR: &str = "recordings";
+
+const INDEX_FILE: &str = "index.json";
+
+/// Added to a recording's file between its entry leaving the index and the
+/// file being deleted. Leftovers from a crash are deleted on the next save.
+const PRUNING_SUFFIX: &str = ".pruning";
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// One saved recording, as the index lists it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordingEntry {
+    pub id: String,
+    /// File name in the library directory.
+    pub file: String,
+    pub bytes: u64,
+    /// Unix seconds.
+    pub created_at: u64,
+    /// Never pruned.
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub metadata: RecordingMetadata,
+}
+
+/// Payload of the `recordings-pruned` event.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Pruned {
+    pub removed_ids: Vec<String>,
+    pub freed_bytes: u64,
+}
+
+/// What get_recordings_usage reports.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecordingsUsage {
+    pub total_bytes: u64,
+    pub items: u64,
+    pub pinned_items: u64,
+    /// Unix seconds.
+    pub oldest_created_at: Option<u64>,
+    pub quota: RecordingsQuota,
+}
+
+/// Which entries to prune so one more recording of `bytes` fits `quota`:
+/// unpinned ones past max_age_days, then the oldest unpinned until the total
+/// and the count fit. Fails with QuotaExceeded if pruning all of them isn't
+/// enough.
+pub fn plan(
+    entries: &[RecordingEntry],
+    quota: &RecordingsQuota,
+    bytes: u64,
+    now: u64,
+) -> Result<Vec<usize>, VoiceboxError> {
+    let mut unpinned: Vec<usize> = (0..entries.len()).filter(|&i| !entries[i].pinned).collect();
+    unpinned.sort_by_key(|&i| entries[i].created_at);
+
+    let expired = |entry: &RecordingEntry| {
+        quota
+            .max_age_days
+            .is_some_and(|days| now.saturating_sub(entry.created_at) > days as u64 * SECS_PER_DAY)
+    };
+    let mut total = entries.iter().map(|entry| entry.bytes).fold(bytes, u64::saturating_add);
+    let mut items = entries.len() as u64 + 1;
+    let over = |total: u64, items: u64| {
+        quota.max_total_bytes.is_some_and(|max| total > max) || quota.max_items.is_some_and(|max| items > max as u64)
+    };
+
+    let mut prune = Vec::new();
+    // Oldest first, so the expired ones all come before the rest
+    for i in unpinned {
+        if !expired(&entries[i]) && !over(total, items) {
+            break;
+        }
+        total -= entries[i].bytes;
+        items -= 1;
+        prune.push(i);
+    }
+    if let Some(max) = quota.max_total_bytes.filter(|&max| total > max) {
+        return Err(VoiceboxError::quota_exceeded("max_total_bytes", max, total));
+    }
+    if let Some(max) = quota.max_items.filter(|&max| items > max as u64) {
+        return Err(VoiceboxError::quota_exceeded("max_items", max as u64, items));
+    }
+    Ok(prune)
+}
+
+/// Serializes changes to the library, which the commands and scheduled
+/// captures make from different threads.
+#[derive(Default)]
+pub struct RecordingsState {
+    lock: Mutex<()>,
+}
+
+impl RecordingsState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The library under `data_dir`, held until the result is dropped.
+    pub fn open(&self, data_dir: &Path) -> RecordingsLibrary<'_> {
+        RecordingsLibrary {
+            dir: data_dir.join(RECORDINGS_DIR),
+            _guard: self.lock.lock().unwrap(),
+        }
+    }
+}
+
+/// Saved recordings: WAV files and an index of them in one directory. The
+/// index is only ever replaced whole, so a file is listed once it is fully
+/// written and unlisted before it is deleted.
+pub struct RecordingsLibrary<'a> {
+    dir: PathBuf,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl RecordingsLibrary<'_> {
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn path(&self, entry: &RecordingEntry) -> PathBuf {
+        self.dir.join(&entry.file)
+    }
+
+    /// Oldest first. Empty before the first save.
+    pub fn list(&self) -> Result<Vec<RecordingEntry>, VoiceboxError> {
+        match std::fs::read_to_string(self.dir.join(INDEX_FILE)) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| VoiceboxError::internal(format!("The recordings index is corrupt: {}", e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(VoiceboxError::io(format!("Failed to read the recordings index: {}", e))),
+        }
+    }
+
+    pub fn usage(&self, quota: &RecordingsQuota) -> Result<RecordingsUsage, VoiceboxError> {
+        let entries = self.list()?;
+        Ok(RecordingsUsage {
+            total_bytes: entries.iter().map(|entry| entry.bytes).sum(),
+            items: entries.len() as u64,
+            pinned_items: entries.iter().filter(|entry| entry.pinned).count() as u64,
+            oldest_created_at: entries.iter().map(|entry| entry.created_at).min(),
+            quota: *quota,
+        })
+    }
+
+    pub fn pin(&self, id: &str, pinned: bool) -> Result<RecordingEntry, VoiceboxError> {
+        let mut entries = self.list()?;
+        let entry = entries
+            .iter_mut()
+            .find(|entry| entry.id == id)
+            .ok_or_else(|| VoiceboxError::not_found(format!("There is no recording {}", id)))?;
+        entry.pinned = pinned;
+        let entry = entry.clone();
+        self.write_index(&entries)?;
+        Ok(entry)
+    }
+
+    /// Unlist a recording, then delete its file.
+    pub fn remove(&self, id: &str) -> Result<RecordingEntry, VoiceboxError> {
+        let mut entries = self.list()?;
+        let index = entries
+            .iter()
+            .position(|entry| entry.id == id)
+            .ok_or_else(|| VoiceboxError::not_found(format!("There is no recording {}", id)))?;
+        let entry = entries.remove(index);
+        self.write_index(&entries)?;
+        if let Err(e) = std::fs::remove_file(self.path(&entry)) {
+            warn!("Failed to delete recording {}: {}", entry.id, e);
+        }
+        Ok(entry)
+    }
+
+    /// The other recordings at least `threshold` alike to `id`, most alike
+    /// first. Only fingerprinted recordings are compared.
+    pub fn find_similar(&self, id: &str, threshold: f32) -> Result<Vec<SimilarRecording>, VoiceboxError> {
+        let entries = self.list()?;
+        let entry = entries
+            .iter()
+            .find(|entry| entry.id == id)
+            .ok_or_else(|| VoiceboxError::not_found(format!("There is no recording {}", id)))?;
+        let target = entry
+            .metadata
+            .fingerprint
+            .as_deref()
+            .ok_or_else(|| VoiceboxError::not_found(format!("Recording {} has no fingerprint", id)))?;
+        let target = fingerprint::decode(target).map_err(VoiceboxError::internal)?;
+        let others: Vec<(&str, Vec<u32>)> = entries
+            .iter()
+            .filter(|entry| entry.id != id)
+            .filter_map(|entry| {
+                let encoded = entry.metadata.fingerprint.as_deref()?;
+                fingerprint::decode(encoded)
+                    .inspect_err(|e| warn!("Skipping recording {}: {}", entry.id, e))
+                    .ok()
+                    .map(|hashes| (entry.id.as_str(), hashes))
+            })
+            .collect();
+        Ok(fingerprint::rank(
+            &target,
+            others.iter().map(|(id, hashes)| (*id, hashes.as_slice())),
+            threshold,
+        ))
+    }
+
+    /// Save a WAV as a new recording, pruning what `quota` requires first.
+    /// On an error nothing has changed. `now` is in Unix seconds.
+    pub fn add(
+        &self,
+        wav: &[u8],
+        label: Option<String>,
+        metadata: RecordingMetadata,
+        quota: &RecordingsQuota,
+        now: u64,
+    ) -> Result<(RecordingEntry, Pruned), VoiceboxError> {
+        std::fs::create_dir_all(&self.dir)?;
+        self.sweep();
+        let mut entries = self.list()?;
+        let prune = plan(&entries, quota, wav.len() as u64, now)?;
+
+        let hash: String = Sha256::digest(wav).iter().take(4).map(|b| format!("{:02x}", b)).collect();
+        let mut id = format!("{}-{}", now, hash);
+        let mut copy = 1;
+        while entries.iter().any(|entry| entry.id == id) || self.dir.join(format!("{}.wav", id)).exists() {
+            copy += 1;
+            id = format!("{}-{}-{}", now, hash, copy);
+        }
+        let entry = RecordingEntry {
+            file: format!("{}.wav", id),
+            id,
+            bytes: wav.len() as u64,
+            created_at: now,
+            pinned: false,
+            label,
+            metadata,
+        };
+        let path = self.path(&entry);
+        write_atomically(&path, wav)?;
+
+        // Set the pruned files aside, so a failure can put them back
+        let mut set_aside: Vec<(PathBuf, PathBuf)> = Vec::new();
+        let mut result = Ok(());
+        for &i in &prune {
+            let from = self.path(&entries[i]);
+            let to = self.dir.join(format!("{}{}", entries[i].file, PRUNING_SUFFIX));
+            match std::fs::rename(&from, &to) {
+                Ok(()) => set_aside.push((from, to)),
+                // Already gone: only its entry is left to drop
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    result = Err(VoiceboxError::io(format!("Failed to prune {}: {}", from.display(), e)));
+                    break;
+                }
+            }
+        }
+        let mut pruned = Pruned::default();
+        let mut kept = Vec::with_capacity(entries.len() + 1);
+        for (i, old) in entries.drain(..).enumerate() {
+            if prune.contains(&i) {
+                pruned.freed_bytes += old.bytes;
+                pruned.removed_ids.push(old.id);
+            } else {
+                kept.push(old);
+            }
+        }
+        kept.push(entry.clone());
+        if let Err(e) = result.and_then(|()| self.write_index(&kept)) {
+            for (from, to) in set_aside {
+                if let Err(e) = std::fs::rename(&to, &from) {
+                    warn!("Failed to restore {}: {}", from.display(), e);
+                }
+            }
+            let _ = std::fs::remove_file(&path);
+            return Err(e);
+        }
+
+        for (_, to) in set_aside {
+            if let Err(e) = std::fs::remove_file(&to) {
+                warn!("Failed to delete pruned recording {}: {}", to.display(), e);
+            }
+        }
+        if !pruned.removed_ids.is_empty() {
+            info!(
+                "Pruned {} recording(s), {} bytes, to fit the library quota",
+                pruned.removed_ids.len(),
+                pruned.freed_bytes
+            );
+        }
+        Ok((entry, pruned))
+    }
+
+    fn write_index(&self, entries: &[RecordingEntry]) -> Result<(), VoiceboxError> {
+        write_atomically(&self.dir.join(INDEX_FILE), &serde_json::to_vec_pretty(entries)?)
+    }
+
+    /// Delete files a crash left between pruning and deletion.
+    fn sweep(&self) {
+        let Ok(dir) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        for entry in dir.flatten() {
+            if entry.file_name().to_string_lossy().ends_with(PRUNING_SUFFIX) {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+/// Write through a temp file next to `path`, so it is either whole or absent.
+fn write_atomically(path: &Path, bytes: &[u8]) -> Result<(), VoiceboxError> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, bytes)
+        .and_then(|()| std::fs::rename(&tmp, path))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&tmp);
+            VoiceboxError::io(format!("Failed to write {}: {}", path.display(), e))
+        })
+}
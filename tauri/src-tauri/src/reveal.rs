@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// What reveal_path opens, e.g. `{ "kind": "logs_dir" }` or
+/// `{ "kind": "custom", "path": "/.../generations/abc.wav" }`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", content = "path", rename_all = "snake_case")]
+pub enum RevealTarget {
+    DataDir,
+    LogsDir,
+    /// Imported and dropped audio files.
+    RecordingsDir,
+    ModelsDir,
+    /// Must lie inside one of our managed roots.
+    Custom(PathBuf),
+}
+
+impl RevealTarget {
+    /// Subdirectory of the data dir for our own kinds.
+    fn subdir(&self) -> Option<&'static str> {
+        match self {
+            RevealTarget::DataDir => Some(""),
+            RevealTarget::LogsDir => Some("logs"),
+            RevealTarget::RecordingsDir => Some(crate::audio_import::IMPORTS_DIR),
+            RevealTarget::ModelsDir => Some("models"),
+            RevealTarget::Custom(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RevealError {
+    NotFound { path: String },
+    /// Outside the data dir and the app's own directories.
+    NotAllowed { path: String },
+    OpenFailed { message: String },
+    Internal { message: String },
+}
+
+impl std::fmt::Display for RevealError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RevealError::NotFound { path } => write!(f, "{} does not exist", path),
+            RevealError::NotAllowed { path } => write!(f, "{} is outside the Voicebox folders", path),
+            RevealError::OpenFailed { message } => write!(f, "Failed to open the file manager: {}", message),
+            RevealError::Internal { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for RevealError {
+    fn from(message: String) -> Self {
+        RevealError::Internal { message }
+    }
+}
+
+/// Absolute path for `target`. Our own directories are created if missing;
+/// custom paths must exist and resolve (symlinks included) inside `roots`.
+pub fn resolve(target: &RevealTarget, data_dir: &Path, roots: &[PathBuf]) -> Result<PathBuf, RevealError> {
+    if let Some(subdir) = target.subdir() {
+        let dir = data_dir.join(subdir);
+        std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        return Ok(canonicalize(&dir).unwrap_or(dir));
+    }
+
+    let RevealTarget::Custom(path) = target else {
+        unreachable!("every other kind has a subdir");
+    };
+    let display = path.display().to_string();
+    let resolved = canonicalize(path).map_err(|_| RevealError::NotFound { path: display.clone() })?;
+    let allowed = roots
+        .iter()
+        .filter_map(|root| canonicalize(root).ok())
+        .any(|root| resolved.starts_with(&root));
+    if !allowed {
+        return Err(RevealError::NotAllowed { path: display });
+    }
+    Ok(resolved)
+}
+
+/// canonicalize without the `\\?\` prefix Windows adds, which Explorer rejects.
+fn canonicalize(path: &Path) -> std::io::Result<PathBuf> {
+    let canonical = path.canonicalize()?;
+    #[cfg(windows)]
+    {
+        let text = canonical.to_string_lossy();
+        if let Some(stripped) = text.strip_prefix(r"\\?\") {
+            if !stripped.starts_with("UNC\\") {
+                return Ok(PathBuf::from(stripped));
+            }
+        }
+    }
+    Ok(canonical)
+}
+
+/// Show `path` in Finder/Explorer/the default file manager. A file is shown
+/// selected in its folder where the platform supports that.
+pub fn open_in_file_manager(path: &Path) -> Result<(), RevealError> {
+    let mut child = file_manager_command(path)
+        .spawn()
+        .map_err(|e| RevealError::OpenFailed { message: e.to_string() })?;
+    // Reaped off-thread; explorer's exit code is meaningless anyway
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn file_manager_command(path: &Path) -> std::process::Command {
+    let mut command = std::process::Command::new("open");
+    if path.is_file() {
+        command.arg("-R");
+    }
+    command.arg(path);
+    command
+}
+
+#[cfg(target_os = "windows")]
+fn file_manager_command(path: &Path) -> std::process::Command {
+    use std::os::windows::process::CommandExt;
+
+    let mut command = std::process::Command::new("explorer");
+    if path.is_file() {
+        // explorer parses its own command line; /select must be one argument with the path
+        command.raw_arg(format!("/select,\"{}\"", path.display()));
+    } else {
+        command.arg(path);
+    }
+    command
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn file_manager_command(path: &Path) -> std::process::Command {
+    // xdg-open can't select a file, so open its folder
+    let dir = if path.is_file() { path.parent().unwrap_or(path) } else { path };
+    let mut command = std::process::Command::new("xdg-open");
+    command.arg(dir);
+    command
+}
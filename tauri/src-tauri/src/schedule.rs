@@ -0,0 +1,263 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Timelike};
+
+/// Fire times reported as missed at once; a frequent schedule left off for
+/// days would otherwise list thousands.
+pub const MAX_MISSED: usize = 100;
+
+/// How far ahead next_after looks before concluding a schedule never fires
+/// (e.g. `0 0 30 2 *`). Long enough for Feb 29 across a skipped leap year.
+const SEARCH_DAYS: i64 = 8 * 366;
+
+const WEEKDAYS: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+const RRULE_WEEKDAYS: [&str; 7] = ["SU", "MO", "TU", "WE", "TH", "FR", "SA"];
+const MONTHS: [&str; 12] = ["JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC"];
+
+/// When a schedule fires, in local wall-clock minutes. Parsed from a
+/// five-field cron expression (`0 18 * * TUE`) or an RRULE limited to what
+/// maps onto one (`FREQ=WEEKLY;BYDAY=TU;BYHOUR=18;BYMINUTE=0`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recurrence {
+    /// Bit n set when minute n matches; likewise below.
+    minutes: u64,
+    hours: u32,
+    /// Bits 1-31.
+    days: u32,
+    /// Bits 1-12.
+    months: u16,
+    /// Bits 0-6, Sunday first.
+    weekdays: u8,
+    /// Cron's rule: with both the day of month and the weekday restricted,
+    /// a day matching either fires.
+    either_day: bool,
+}
+
+/// What a schedule owes between two checks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Due<Tz: TimeZone> {
+    /// The latest fire time recent enough to start now.
+    pub fire: Option<DateTime<Tz>>,
+    /// Older fire times nothing ran for, oldest first, at most MAX_MISSED.
+    pub missed: Vec<DateTime<Tz>>,
+}
+
+impl Recurrence {
+    /// A cron expression, or an RRULE (recognized by its `=`).
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let expression = expression.trim();
+        if expression.contains('=') {
+            Self::parse_rrule(expression)
+        } else {
+            Self::parse_cron(expression)
+        }
+    }
+
+    fn parse_cron(expression: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "'{}' is not a cron expression (expected 5 fields: minute hour day month weekday)",
+                expression
+            ));
+        };
+        let weekdays = parse_field(weekday, 0, 7, &WEEKDAYS)?;
+        // 7 is Sunday too
+        let weekdays = (weekdays | weekdays >> 7) & 0x7F;
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59, &[])?,
+            hours: parse_field(hour, 0, 23, &[])? as u32,
+            days: parse_field(day, 1, 31, &[])? as u32,
+            months: parse_field(month, 1, 12, &MONTHS)? as u16,
+            weekdays: weekdays as u8,
+            either_day: !day.starts_with('*') && !weekday.starts_with('*'),
+        })
+    }
+
+    /// The RRULE subset without a DTSTART: FREQ=DAILY, WEEKLY or MONTHLY with
+    /// BYHOUR (required), BYMINUTE, BYDAY, BYMONTHDAY and BYMONTH.
+    fn parse_rrule(expression: &str) -> Result<Self, String> {
+        let rule = expression.strip_prefix("RRULE:").unwrap_or(expression);
+        let mut freq = None;
+        let mut recurrence = Self {
+            minutes: 1,
+            hours: 0,
+            days: all_bits(1, 31) as u32,
+            months: all_bits(1, 12) as u16,
+            weekdays: all_bits(0, 6) as u8,
+            either_day: false,
+        };
+        let (mut by_day, mut by_month_day) = (false, false);
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("'{}' is not a KEY=VALUE part of an RRULE", part))?;
+            match key.to_uppercase().as_str() {
+                "FREQ" => freq = Some(value.to_uppercase()),
+                "BYMINUTE" => recurrence.minutes = parse_list(value, 0, 59)?,
+                "BYHOUR" => recurrence.hours = parse_list(value, 0, 23)? as u32,
+                "BYMONTHDAY" => {
+                    recurrence.days = parse_list(value, 1, 31)? as u32;
+                    by_month_day = true;
+                }
+                "BYMONTH" => recurrence.months = parse_list(value, 1, 12)? as u16,
+                "BYDAY" => {
+                    recurrence.weekdays = parse_weekdays(value)?;
+                    by_day = true;
+                }
+                "INTERVAL" if value == "1" => {}
+                "WKST" => {}
+                other => return Err(format!("The RRULE part {} is not supported", other)),
+            }
+        }
+        match freq.as_deref() {
+            Some("DAILY") => {}
+            Some("WEEKLY") if by_day => {}
+            Some("MONTHLY") if by_month_day => {}
+            Some("WEEKLY") => return Err("A weekly RRULE needs BYDAY".to_string()),
+            Some("MONTHLY") => return Err("A monthly RRULE needs BYMONTHDAY".to_string()),
+            Some(other) => return Err(format!("FREQ={} is not supported", other)),
+            None => return Err("The RRULE has no FREQ".to_string()),
+        }
+        if recurrence.hours == 0 {
+            return Err("The RRULE needs BYHOUR".to_string());
+        }
+        Ok(recurrence)
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        if self.months & (1 << date.month()) == 0 {
+            return false;
+        }
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+
+    /// The first fire time strictly after `after`, in its time zone. Wall
+    /// times a DST change skips don't fire; ones it repeats fire once.
+    pub fn next_after<Tz: TimeZone>(&self, after: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let tz = after.timezone();
+        let local = after.naive_local();
+        let start = local.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        for offset in 0..SEARCH_DAYS {
+            let date = start.date() + Duration::days(offset);
+            if !self.day_matches(date) {
+                continue;
+            }
+            for hour in (0..24).filter(|h| self.hours & (1 << h) != 0) {
+                for minute in (0..60).filter(|m| self.minutes & (1 << m) != 0) {
+                    let time = date.and_time(NaiveTime::from_hms_opt(hour, minute, 0)?);
+                    if time < start {
+                        continue;
+                    }
+                    match tz.from_local_datetime(&time).earliest() {
+                        Some(fire) if fire > *after => return Some(fire),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Fire times in (`since`, `now`]: the latest within `grace` of `now`
+    /// is still worth starting, the rest were missed.
+    pub fn due<Tz: TimeZone>(&self, since: &DateTime<Tz>, now: &DateTime<Tz>, grace: Duration) -> Due<Tz> {
+        let cutoff = now.clone() - grace;
+        let mut missed = Vec::new();
+        let mut fire = None;
+        let mut cursor = since.clone();
+        while let Some(next) = self.next_after(&cursor) {
+            if next > *now {
+                break;
+            }
+            if next > cutoff {
+                fire = Some(next.clone());
+            } else if missed.len() < MAX_MISSED {
+                missed.push(next.clone());
+            } else {
+                // Skip ahead to the grace window
+                cursor = cutoff.clone();
+                continue;
+            }
+            cursor = next;
+        }
+        Due { fire, missed }
+    }
+}
+
+fn all_bits(low: u32, high: u32) -> u64 {
+    (low..=high).fold(0, |bits, n| bits | 1 << n)
+}
+
+/// One cron field: `*`, values, `a-b` ranges and `/step`s, comma-separated.
+/// `names` are accepted for the values from `low` on.
+fn parse_field(field: &str, low: u32, high: u32, names: &[&str]) -> Result<u64, String> {
+    let value = |text: &str| -> Result<u32, String> {
+        if let Some(index) = names.iter().position(|name| name.eq_ignore_ascii_case(text)) {
+            return Ok(low + index as u32);
+        }
+        text.parse::<u32>()
+            .ok()
+            .filter(|n| (low..=high).contains(n))
+            .ok_or_else(|| format!("'{}' is not a value from {} to {}", text, low, high))
+    };
+    let mut bits = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => {
+                let step = step
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| format!("'{}' has an invalid step", item))?;
+                (range, step)
+            }
+            None => (item, 1),
+        };
+        let (first, last) = match range {
+            "*" => (low, high),
+            _ => match range.split_once('-') {
+                Some((first, last)) => (value(first)?, value(last)?),
+                // `5/15` runs from 5 to the end
+                None if step > 1 => (value(range)?, high),
+                None => {
+                    let n = value(range)?;
+                    (n, n)
+                }
+            },
+        };
+        if first > last {
+            return Err(format!("'{}' is an empty range", item));
+        }
+        bits |= (first..=last).step_by(step as usize).fold(0, |bits, n| bits | 1 << n);
+    }
+    Ok(bits)
+}
+
+/// A comma-separated list of numbers, as RRULE's BY* parts take.
+fn parse_list(value: &str, low: u32, high: u32) -> Result<u64, String> {
+    value.split(',').try_fold(0, |bits, item| {
+        let n = item
+            .parse::<u32>()
+            .ok()
+            .filter(|n| (low..=high).contains(n))
+            .ok_or_else(|| format!("'{}' is not a value from {} to {}", item, low, high))?;
+        Ok(bits | 1 << n)
+    })
+}
+
+/// BYDAY's two-letter weekdays. Ordinals such as `1MO` need a monthly
+/// position this model doesn't have.
+fn parse_weekdays(value: &str) -> Result<u8, String> {
+    value.split(',').try_fold(0, |bits, item| {
+        let index = RRULE_WEEKDAYS
+            .iter()
+            .position(|day| day.eq_ignore_ascii_case(item))
+            .ok_or_else(|| format!("'{}' is not a supported BYDAY value", item))?;
+        Ok(bits | 1 << index)
+    })
+}
@@ -0,0 +1,288 @@
+use crate::audio_capture::AudioCaptureState;
+use crate::error::VoiceboxError;
+use crate::schedule::Recurrence;
+use crate::settings::{CaptureOutput, ScheduleSpec, ScheduledCapture, SettingsState};
+use crate::storage;
+use base64::Engine;
+use chrono::{DateTime, Local, TimeZone};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use tracing::{info, warn};
+
+/// How often schedules are checked.
+const TICK: Duration = Duration::from_secs(1);
+
+/// A fire time up to this old still starts, e.g. right after the computer
+/// wakes. Older ones are reported as missed.
+const GRACE_SECS: i64 = 120;
+
+pub const MAX_DURATION_SECS: u32 = 12 * 3600;
+
+/// The capture's own limit is set this far past the scheduled length, so the
+/// scheduler ends it rather than the auto-stop.
+const LIMIT_MARGIN_SECS: u32 = 30;
+
+/// What a capture can take on disk per second: 16-bit stereo at 48 kHz.
+const CAPTURE_BYTES_PER_SEC: u64 = 48_000 * 2 * 2;
+
+/// Payload of list_scheduled_captures and schedule_capture.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduleInfo {
+    #[serde(flatten)]
+    pub schedule: ScheduledCapture,
+    /// Unix seconds, or None if it never fires again.
+    pub next_fire_at: Option<u64>,
+}
+
+/// Payload of the `scheduled-capture-started` and `-finished` events.
+#[derive(Debug, Clone, Serialize)]
+struct RunEvent {
+    id: u64,
+    label: String,
+    /// Once the capture has started.
+    session: Option<u64>,
+    /// The saved WAV, once finished.
+    path: Option<PathBuf>,
+    error: Option<String>,
+}
+
+fn info_for(schedule: ScheduledCapture) -> ScheduleInfo {
+    let next_fire_at = Recurrence::parse(&schedule.spec.cron_or_rrule)
+        .ok()
+        .and_then(|recurrence| recurrence.next_after(&Local::now()))
+        .map(|fire| fire.timestamp() as u64);
+    ScheduleInfo { schedule, next_fire_at }
+}
+
+fn unix_now() -> u64 {
+    Local::now().timestamp() as u64
+}
+
+/// Add a schedule. It first fires at the next matching time from now.
+pub fn schedule(app: &AppHandle, spec: ScheduleSpec) -> Result<ScheduleInfo, VoiceboxError> {
+    let recurrence = Recurrence::parse(&spec.cron_or_rrule).map_err(VoiceboxError::invalid_argument)?;
+    if recurrence.next_after(&Local::now()).is_none() {
+        return Err(VoiceboxError::invalid_argument(format!(
+            "'{}' never fires",
+            spec.cron_or_rrule
+        )));
+    }
+    if spec.duration_secs == 0 || spec.duration_secs > MAX_DURATION_SECS {
+        return Err(VoiceboxError::invalid_argument(format!(
+            "A scheduled capture must last between 1 and {} seconds",
+            MAX_DURATION_SECS
+        )));
+    }
+    if spec.label.trim().is_empty() {
+        return Err(VoiceboxError::invalid_argument("A scheduled capture needs a label"));
+    }
+    if let CaptureOutput::File { dir } = &spec.output {
+        if !dir.is_absolute() {
+            return Err(VoiceboxError::invalid_argument(format!(
+                "{} is not an absolute path",
+                dir.display()
+            )));
+        }
+    }
+
+    let mut schedule = ScheduledCapture {
+        id: 0,
+        spec,
+        checked_until: unix_now(),
+    };
+    app.state::<SettingsState>().update(|s| {
+        schedule.id = s.scheduled_captures.iter().map(|c| c.id).max().unwrap_or(0) + 1;
+        s.scheduled_captures.push(schedule.clone());
+    })?;
+    info!("Scheduled capture {} ({})", schedule.id, schedule.spec.cron_or_rrule);
+    Ok(info_for(schedule))
+}
+
+pub fn list(app: &AppHandle) -> Vec<ScheduleInfo> {
+    let schedules = app.state::<SettingsState>().get().scheduled_captures;
+    schedules.into_iter().map(info_for).collect()
+}
+
+/// Remove a schedule. A run already recording finishes and is saved.
+pub fn cancel(app: &AppHandle, id: u64) -> Result<(), VoiceboxError> {
+    let settings = app.state::<SettingsState>();
+    if !settings.get().scheduled_captures.iter().any(|c| c.id == id) {
+        return Err(VoiceboxError::not_found(format!("There is no scheduled capture {}", id)));
+    }
+    settings.update(|s| s.scheduled_captures.retain(|c| c.id != id))?;
+    info!("Cancelled scheduled capture {}", id);
+    Ok(())
+}
+
+/// Start the scheduler. Called at the end of setup, so the notification
+/// listeners are in place for the missed runs it reports first.
+pub fn init(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        // Per schedule, where the last check left off
+        let mut checked: HashMap<u64, DateTime<Local>> = HashMap::new();
+        let mut tick = tokio::time::interval(TICK);
+        tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tick.tick().await;
+            check(&app, &mut checked);
+        }
+    });
+}
+
+/// Start the schedules that are due, and report the ones that were missed:
+/// while the app was closed, the computer asleep, or a capture unstartable.
+fn check(app: &AppHandle, checked: &mut HashMap<u64, DateTime<Local>>) {
+    let settings = app.state::<SettingsState>();
+    let schedules = settings.get().scheduled_captures;
+    checked.retain(|id, _| schedules.iter().any(|c| c.id == *id));
+    let now = Local::now();
+
+    for schedule in schedules {
+        let since = checked.entry(schedule.id).or_insert_with(|| {
+            Local
+                .timestamp_opt(schedule.checked_until as i64, 0)
+                .single()
+                .unwrap_or(now)
+        });
+        let recurrence = match Recurrence::parse(&schedule.spec.cron_or_rrule) {
+            Ok(recurrence) => recurrence,
+            // Validated when added; a hand-edited settings file can still break it
+            Err(e) => {
+                warn!("Scheduled capture {} can't run: {}", schedule.id, e);
+                *since = now;
+                continue;
+            }
+        };
+        let due = recurrence.due(since, &now, chrono::Duration::seconds(GRACE_SECS));
+        *since = now;
+        if due.fire.is_none() && due.missed.is_empty() {
+            continue;
+        }
+
+        let id = schedule.id;
+        if let Err(e) = settings.update(|s| {
+            if let Some(stored) = s.scheduled_captures.iter_mut().find(|c| c.id == id) {
+                stored.checked_until = now.timestamp() as u64;
+            }
+        }) {
+            warn!("Failed to save scheduled capture {}: {}", id, e);
+        }
+        if !due.missed.is_empty() {
+            warn!("Scheduled capture {} missed {} run(s)", id, due.missed.len());
+            let missed_at: Vec<u64> = due.missed.iter().map(|fire| fire.timestamp() as u64).collect();
+            let payload = serde_json::json!({ "id": id, "label": &schedule.spec.label, "missed_at": missed_at });
+            if let Err(e) = crate::event_bus::emit(app, "scheduled-capture-missed", payload) {
+                warn!("Failed to emit scheduled-capture-missed event: {}", e);
+            }
+        }
+        if due.fire.is_some() {
+            tauri::async_runtime::spawn(run(app.clone(), id, schedule.spec));
+        }
+    }
+}
+
+/// Record one run and save it, emitting `scheduled-capture-started` and
+/// `scheduled-capture-finished`.
+async fn run(app: AppHandle, id: u64, spec: ScheduleSpec) {
+    let mut event = RunEvent {
+        id,
+        label: spec.label.clone(),
+        session: None,
+        path: None,
+        error: None,
+    };
+    match record(&app, &spec, &mut event).await {
+        Ok(path) => {
+            info!("Scheduled capture {} saved to {:?}", id, path);
+            event.path = Some(path);
+        }
+        Err(e) => {
+            warn!("Scheduled capture {} failed: {}", id, e);
+            event.error = Some(e.message().to_string());
+        }
+    }
+    if let Err(e) = crate::event_bus::emit(&app, "scheduled-capture-finished", &event) {
+        warn!("Failed to emit scheduled-capture-finished event: {}", e);
+    }
+}
+
+async fn record(app: &AppHandle, spec: &ScheduleSpec, event: &mut RunEvent) -> Result<PathBuf, VoiceboxError> {
+    let started_at = Local::now();
+    // Better not to start than to lose the recording at the end
+    let dir = match &spec.output {
+        CaptureOutput::File { dir } => dir.clone(),
+        CaptureOutput::Library => crate::resolve_data_dir(app)?,
+    };
+    let required = spec.duration_secs as u64 * CAPTURE_BYTES_PER_SEC;
+    tokio::task::spawn_blocking(move || storage::ensure_free_space(&dir, required)).await??;
+    crate::begin_capture(app, spec.duration_secs + LIMIT_MARGIN_SECS).await?;
+    let state = app.state::<AudioCaptureState>();
+    let session = state.current_session();
+    event.session = Some(session);
+    info!("Scheduled capture {} started as session {}", event.id, session);
+    if let Err(e) = crate::event_bus::emit(app, "scheduled-capture-started", &*event) {
+        warn!("Failed to emit scheduled-capture-started event: {}", e);
+    }
+
+    tokio::time::sleep(Duration::from_secs(spec.duration_secs as u64)).await;
+    // Stopped early from the UI, the capture is kept all the same
+    let audio = if state.current_session() == session {
+        crate::end_capture(app).await?
+    } else {
+        state
+            .recording(session)
+            .ok_or_else(|| VoiceboxError::capture("The capture ended without a recording"))?
+    };
+
+    let name = file_stem(&spec.label, &started_at);
+    match &spec.output {
+        CaptureOutput::File { dir } => {
+            let dir = dir.clone();
+            tokio::task::spawn_blocking(move || save(&dir, &name, &audio)).await?
+        }
+        CaptureOutput::Library => {
+            let metadata = state.recording_metadata(session).unwrap_or_else(|| state.metadata());
+            let app = app.clone();
+            tokio::task::spawn_blocking(move || {
+                let wav = decode(&audio)?;
+                crate::save_to_library(&app, &wav, Some(name), metadata).map(|(_, path)| path)
+            })
+            .await?
+        }
+    }
+}
+
+/// `<label> <yyyy-mm-dd HH-MM>`, with characters file systems reject replaced.
+fn file_stem(label: &str, started_at: &DateTime<Local>) -> String {
+    let label: String = label
+        .trim()
+        .chars()
+        .map(|c| if c.is_control() || r#"<>:"/\|?*"#.contains(c) { '_' } else { c })
+        .collect();
+    format!("{} {}", label, started_at.format("%Y-%m-%d %H-%M"))
+}
+
+fn decode(audio: &str) -> Result<Vec<u8>, VoiceboxError> {
+    base64::engine::general_purpose::STANDARD
+        .decode(audio)
+        .map_err(|e| VoiceboxError::internal(format!("The capture is not valid base64: {}", e)))
+}
+
+/// Write the base64 WAV into `dir` without overwriting an earlier run.
+fn save(dir: &Path, stem: &str, audio: &str) -> Result<PathBuf, VoiceboxError> {
+    let bytes = decode(audio)?;
+    std::fs::create_dir_all(dir)?;
+    storage::ensure_free_space(dir, bytes.len() as u64)?;
+    let mut path = dir.join(format!("{}.wav", stem));
+    let mut copy = 1;
+    while path.exists() {
+        copy += 1;
+        path = dir.join(format!("{} ({}).wav", stem, copy));
+    }
+    std::fs::write(&path, bytes)?;
+    Ok(path)
+}
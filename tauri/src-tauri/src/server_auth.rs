@@ -0,0 +1,69 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Holds the token a remote-mode server requires, kept across starts so LAN
+/// clients set up with it keep working.
+pub const TOKEN_FILE: &str = "server-token";
+
+/// Where the sidecar reads its token. Not an argument, since other users can
+/// read those from the process list.
+pub const AUTH_TOKEN_ENV: &str = "VOICEBOX_AUTH_TOKEN";
+
+/// Random bytes in a token; it's written out as hex.
+pub const TOKEN_BYTES: usize = 32;
+
+pub fn token_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(TOKEN_FILE)
+}
+
+/// A new random token, as hex.
+pub fn generate_token() -> Result<String, String> {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    getrandom::getrandom(&mut bytes).map_err(|e| format!("Failed to generate a server token: {}", e))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// The stored token, if there is a usable one.
+pub fn read_token(data_dir: &Path) -> Option<String> {
+    let token = std::fs::read_to_string(token_path(data_dir)).ok()?;
+    let token = token.trim();
+    if token.len() < TOKEN_BYTES * 2 || !token.chars().all(|c| c.is_ascii_hexdigit()) {
+        warn!("Ignoring malformed {}", TOKEN_FILE);
+        return None;
+    }
+    Some(token.to_string())
+}
+
+/// The stored token, or a new one written to the data dir, readable only by
+/// the current user.
+pub fn read_or_create_token(data_dir: &Path) -> Result<String, String> {
+    if let Some(token) = read_token(data_dir) {
+        return Ok(token);
+    }
+    let token = generate_token()?;
+    let path = token_path(data_dir);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let write = |mut file: std::fs::File| {
+        // mode only applies to a new file; a damaged one being replaced may
+        // have been readable by others
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        }
+        file.write_all(token.as_bytes())
+    };
+    options
+        .open(&path)
+        .and_then(write)
+        .map_err(|e| format!("Failed to write {}: {}", TOKEN_FILE, e))?;
+    info!("Created a new server token in {}", path.display());
+    Ok(token)
+}
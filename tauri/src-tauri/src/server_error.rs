@@ -0,0 +1,282 @@
+use crate::storage::{StorageProblem, StorageProblemKind};
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{error, warn};
+
+/// Lines of server output kept for error reports.
+pub const TAIL_LINES: usize = 30;
+
+/// How often a starting server that prints nothing still gets a
+/// `server-startup-progress` event, so the elapsed time keeps moving.
+pub const STARTUP_PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How far the sidecar got before startup stopped, judged from its log output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartupPhase {
+    /// No output yet; the PyInstaller bootloader is still unpacking.
+    Launching,
+    ImportingModules,
+    InitializingDatabase,
+    /// Upgrading the database of an older release.
+    ApplyingMigrations,
+    StartingHttpServer,
+}
+
+impl StartupPhase {
+    /// Phase announced by a line of server.py / uvicorn startup logging.
+    pub fn from_log_line(line: &str) -> Option<Self> {
+        if line.contains("Starting uvicorn") || line.contains("Waiting for application startup") {
+            Some(StartupPhase::StartingHttpServer)
+        } else if line.contains("Migrating ") {
+            Some(StartupPhase::ApplyingMigrations)
+        } else if line.contains("Initializing database") {
+            Some(StartupPhase::InitializingDatabase)
+        } else if line.contains("Importing") || line.contains("voicebox-server starting up") {
+            Some(StartupPhase::ImportingModules)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StartupPhase::Launching => "launching",
+            StartupPhase::ImportingModules => "importing_modules",
+            StartupPhase::InitializingDatabase => "initializing_database",
+            StartupPhase::ApplyingMigrations => "applying_migrations",
+            StartupPhase::StartingHttpServer => "starting_http_server",
+        }
+    }
+}
+
+/// Payload of `server-startup-progress`, sent while start_server waits for the
+/// server: for each line it prints, and every STARTUP_PROGRESS_INTERVAL while
+/// it's quiet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StartupProgress {
+    pub phase: StartupPhase,
+    /// None for the updates sent while the server is quiet.
+    pub line: Option<String>,
+    pub elapsed_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BinaryProblemKind {
+    /// The sidecar binary isn't where Tauri expects it.
+    NotFound,
+    /// The binary exists but the OS refused to run it.
+    NotExecutable,
+    /// Debug build without a bundled server and none started by hand.
+    DevServerNotRunning,
+}
+
+/// Why start_server failed. Serialized as `{kind, ..fields, message, hints}` so
+/// the UI can branch on `kind` and show `tail_lines` inline.
+#[derive(Debug, Clone)]
+pub enum ServerStartError {
+    SpawnFailed {
+        os_error: String,
+    },
+    Timeout {
+        last_phase: StartupPhase,
+        tail_lines: Vec<String>,
+    },
+    CrashedDuringStartup {
+        exit_code: Option<i32>,
+        tail_lines: Vec<String>,
+    },
+    PortConflict {
+        occupant: String,
+    },
+    BinaryProblem {
+        kind: BinaryProblemKind,
+    },
+    /// The data dir can't be written or is full, so the server isn't started.
+    Storage {
+        problem: StorageProblem,
+    },
+    /// Launch options outside the allow-lists; nothing was started.
+    InvalidOptions {
+        message: String,
+    },
+    /// The server is already running with other launch options.
+    RestartRequired,
+    /// Failures around the spawn itself, e.g. an unwritable data dir.
+    Internal {
+        message: String,
+    },
+}
+
+impl ServerStartError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ServerStartError::SpawnFailed { .. } => "spawn_failed",
+            ServerStartError::Timeout { .. } => "timeout",
+            ServerStartError::CrashedDuringStartup { .. } => "crashed_during_startup",
+            ServerStartError::PortConflict { .. } => "port_conflict",
+            ServerStartError::BinaryProblem { .. } => "binary_problem",
+            ServerStartError::Storage { .. } => "storage_problem",
+            ServerStartError::InvalidOptions { .. } => "invalid_options",
+            ServerStartError::RestartRequired => "restart_required",
+            ServerStartError::Internal { .. } => "internal",
+        }
+    }
+
+    /// Troubleshooting suggestions shown under the error message.
+    pub fn hints(&self) -> Vec<&'static str> {
+        match self {
+            ServerStartError::SpawnFailed { .. } => vec![
+                "The server binary may be missing or corrupted",
+                "Missing execute permissions",
+                "Code signing issues on macOS",
+                "Missing dependencies",
+            ],
+            ServerStartError::Timeout { last_phase, .. } => match last_phase {
+                StartupPhase::Launching | StartupPhase::ImportingModules => vec![
+                    "The first start after an install or update can be slow while the bundled libraries load",
+                    "On a slow machine, raise the server startup timeout in Settings",
+                    "Check Console.app (macOS) or the app logs for details (search for 'voicebox')",
+                ],
+                _ => vec!["Check Console.app (macOS) or the app logs for details (search for 'voicebox')"],
+            },
+            ServerStartError::CrashedDuringStartup { .. } => vec![
+                "The server binary may have crashed or exited with an error",
+                "Check Console.app (macOS) or the app logs for details (search for 'voicebox')",
+            ],
+            ServerStartError::PortConflict { .. } => {
+                vec!["Quit the program using the port, or restart your computer"]
+            }
+            ServerStartError::BinaryProblem { kind } => match kind {
+                BinaryProblemKind::NotFound => vec!["Reinstall Voicebox to restore the server binary"],
+                BinaryProblemKind::NotExecutable => vec![
+                    "Missing execute permissions",
+                    "Code signing issues on macOS",
+                    "Antivirus software may be blocking the server binary",
+                ],
+                BinaryProblemKind::DevServerNotRunning => {
+                    vec!["Start the Python server in a separate terminal: bun run dev:server"]
+                }
+            },
+            ServerStartError::Storage { problem } => match problem.kind {
+                StorageProblemKind::InsufficientSpace => vec![
+                    "Free up space on the drive holding the data directory",
+                    "Or move the data directory to another drive in Settings",
+                ],
+                StorageProblemKind::ReadOnly | StorageProblemKind::PermissionDenied => vec![
+                    "Move the data directory to a folder you can write to in Settings",
+                    "Managed computers may restrict the user profile; ask your administrator",
+                ],
+                StorageProblemKind::CannotCreate | StorageProblemKind::WriteFailed => {
+                    vec!["Move the data directory to a folder you can write to in Settings"]
+                }
+            },
+            ServerStartError::RestartRequired => vec!["Restart the server to apply the new launch options"],
+            ServerStartError::InvalidOptions { .. } | ServerStartError::Internal { .. } => Vec::new(),
+        }
+    }
+
+    /// Log the error, its hints and the server's last output.
+    pub fn log(&self) {
+        error!("Server failed to start: {}", self);
+        for hint in self.hints() {
+            warn!("  - {}", hint);
+        }
+        if let ServerStartError::Timeout { tail_lines, .. }
+        | ServerStartError::CrashedDuringStartup { tail_lines, .. } = self
+        {
+            if !tail_lines.is_empty() {
+                warn!("Last server output:");
+                for line in tail_lines {
+                    warn!("  {}", line);
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ServerStartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerStartError::SpawnFailed { os_error } => {
+                write!(f, "Failed to spawn the server: {}", os_error)
+            }
+            ServerStartError::Timeout { last_phase, .. } => write!(
+                f,
+                "Server did not become ready in time (last phase: {})",
+                last_phase.as_str()
+            ),
+            ServerStartError::CrashedDuringStartup { exit_code, .. } => match exit_code {
+                Some(code) => write!(f, "Server exited during startup with code {}", code),
+                None => write!(f, "Server exited unexpectedly during startup"),
+            },
+            ServerStartError::PortConflict { occupant } => {
+                write!(f, "The server port is already in use by {}", occupant)
+            }
+            ServerStartError::BinaryProblem { kind } => match kind {
+                BinaryProblemKind::NotFound => write!(f, "The server binary was not found"),
+                BinaryProblemKind::NotExecutable => write!(f, "The server binary could not be executed"),
+                BinaryProblemKind::DevServerNotRunning => {
+                    write!(f, "Dev mode: no server is running and no bundled server is available")
+                }
+            },
+            ServerStartError::Storage { problem } => write!(
+                f,
+                "The data directory {} can't be used: {}",
+                problem.path.display(),
+                problem.detail
+            ),
+            ServerStartError::InvalidOptions { message } => write!(f, "Invalid server launch options: {}", message),
+            ServerStartError::RestartRequired => {
+                write!(f, "The server is already running with other launch options")
+            }
+            ServerStartError::Internal { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ServerStartError {}
+
+impl From<String> for ServerStartError {
+    fn from(message: String) -> Self {
+        ServerStartError::Internal { message }
+    }
+}
+
+impl Serialize for ServerStartError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("kind", self.kind())?;
+        match self {
+            ServerStartError::SpawnFailed { os_error } => {
+                map.serialize_entry("os_error", os_error)?;
+            }
+            ServerStartError::Timeout { last_phase, tail_lines } => {
+                map.serialize_entry("last_phase", last_phase)?;
+                map.serialize_entry("tail_lines", tail_lines)?;
+            }
+            ServerStartError::CrashedDuringStartup { exit_code, tail_lines } => {
+                map.serialize_entry("exit_code", exit_code)?;
+                map.serialize_entry("tail_lines", tail_lines)?;
+            }
+            ServerStartError::PortConflict { occupant } => {
+                map.serialize_entry("occupant", occupant)?;
+            }
+            // `kind` is taken by the tag
+            ServerStartError::BinaryProblem { kind } => {
+                map.serialize_entry("problem", kind)?;
+            }
+            ServerStartError::Storage { problem } => {
+                map.serialize_entry("storage", problem)?;
+            }
+            ServerStartError::InvalidOptions { .. }
+            | ServerStartError::RestartRequired
+            | ServerStartError::Internal { .. } => {}
+        }
+        map.serialize_entry("message", &self.to_string())?;
+        map.serialize_entry("hints", &self.hints())?;
+        map.end()
+    }
+}
@@ -0,0 +1,121 @@
+use crate::settings::ServerLogLevel;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Server flags a caller may add with `extra_args`, and whether each takes a
+/// value (passed as `--flag=value`). The app's own flags, such as `--port`
+/// and `--data-dir`, aren't among them.
+pub const ALLOWED_ARGS: [(&str, bool); 3] = [
+    ("--no-access-log", false),
+    ("--offline", false),
+    ("--idle-timeout-minutes", true),
+];
+
+/// Prefixes of the environment variables a caller may set: GPU selection,
+/// the model caches and the ML libraries' tuning knobs. Anything that changes
+/// how the process itself is loaded, like PATH or LD_PRELOAD, is refused.
+pub const ALLOWED_ENV_PREFIXES: [&str; 11] = [
+    "CUDA_",
+    "HF_",
+    "HIP_",
+    "HSA_",
+    "MKL_",
+    "OMP_",
+    "PYTORCH_",
+    "ROCR_",
+    "TORCH_",
+    "TRANSFORMERS_",
+    "VOICEBOX_",
+];
+
+/// Set from `models_dir`; the server points Hugging Face's cache at it.
+pub const MODELS_DIR_ENV: &str = "VOICEBOX_MODELS_DIR";
+
+/// Extra launch options for the sidecar, passed to start_server and echoed
+/// back in the ServerInfo of the server they were applied to.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerLaunchOptions {
+    pub env: HashMap<String, String>,
+    pub extra_args: Vec<String>,
+    /// Absolute path for model downloads.
+    pub models_dir: Option<String>,
+    /// Overrides the server_log_level setting for this start.
+    pub log_level: Option<String>,
+}
+
+impl ServerLaunchOptions {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Refuse anything outside the allow-lists, so the webview can only tune
+    /// the server rather than change what runs.
+    pub fn validate(&self) -> Result<(), String> {
+        for arg in &self.extra_args {
+            validate_arg(arg)?;
+        }
+        for (name, value) in &self.env {
+            validate_env(name, value)?;
+        }
+        if let Some(dir) = &self.models_dir {
+            if dir.contains('\0') || !Path::new(dir).is_absolute() {
+                return Err(format!("models_dir must be an absolute path, got '{}'", dir));
+            }
+        }
+        self.parsed_log_level()?;
+        Ok(())
+    }
+
+    pub fn parsed_log_level(&self) -> Result<Option<ServerLogLevel>, String> {
+        self.log_level.as_deref().map(ServerLogLevel::parse).transpose()
+    }
+
+    /// Environment for the sidecar: `env` plus the models dir.
+    pub fn envs(&self) -> Vec<(String, String)> {
+        let mut envs: Vec<(String, String)> = self.env.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        if let Some(dir) = &self.models_dir {
+            envs.push((MODELS_DIR_ENV.to_string(), dir.clone()));
+        }
+        envs
+    }
+}
+
+fn validate_arg(arg: &str) -> Result<(), String> {
+    let (flag, value) = match arg.split_once('=') {
+        Some((flag, value)) => (flag, Some(value)),
+        None => (arg, None),
+    };
+    let Some(&(_, takes_value)) = ALLOWED_ARGS.iter().find(|(allowed, _)| *allowed == flag) else {
+        let known: Vec<&str> = ALLOWED_ARGS.iter().map(|(flag, _)| *flag).collect();
+        return Err(format!("Unsupported server argument '{}' (allowed: {})", arg, known.join(", ")));
+    };
+    match (takes_value, value) {
+        (false, Some(_)) => Err(format!("{} doesn't take a value", flag)),
+        (true, None) => Err(format!("{} needs a value, as {}=<value>", flag, flag)),
+        (true, Some(value)) if !value.parse::<u32>().is_ok_and(|n| n > 0) => {
+            Err(format!("{} needs a positive number, got '{}'", flag, value))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_env(name: &str, value: &str) -> Result<(), String> {
+    let well_formed = !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+    if !well_formed || !ALLOWED_ENV_PREFIXES.iter().any(|prefix| name.starts_with(prefix)) {
+        return Err(format!(
+            "Unsupported environment variable '{}' (allowed prefixes: {})",
+            name,
+            ALLOWED_ENV_PREFIXES.join(", ")
+        ));
+    }
+    if name == crate::server_auth::AUTH_TOKEN_ENV {
+        return Err(format!("{} is set by the app in remote mode", name));
+    }
+    if value.contains('\0') {
+        return Err(format!("The value of {} contains a NUL byte", name));
+    }
+    Ok(())
+}
@@ -0,0 +1,270 @@
+use crate::logging;
+use crate::metrics::METRICS;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+use tracing::info;
+
+/// Longest line kept from the sidecar. Past it the line is cut, and the rest
+/// is counted and dropped up to the next newline.
+pub const MAX_LINE_BYTES: usize = 8 * 1024;
+
+/// Lines of server output kept in memory for get_server_logs.
+pub const SERVER_LOG_LINES: usize = 5000;
+
+/// Lines waiting to go out as `server-log` events. Past it new lines skip the
+/// event, so a slow webview never holds up reading the server's output.
+pub const LOG_EVENT_QUEUE: usize = 1000;
+
+/// One line of sidecar output, ready for the log and the error tail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputLine {
+    pub text: String,
+    /// Carriage-return redraws folded into this line, as tqdm progress bars
+    /// do; `text` is the last one drawn. 0 for an ordinary line.
+    pub updates: u64,
+    /// Bytes cut from this line and its redraws.
+    pub truncated_bytes: u64,
+}
+
+impl std::fmt::Display for OutputLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text.trim_end())?;
+        if self.truncated_bytes > 0 {
+            write!(f, " … [{} bytes truncated]", self.truncated_bytes)?;
+        }
+        if self.updates > 0 {
+            write!(f, " [progress line updated {} times]", self.updates)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits one raw output stream into lines, holding at most MAX_LINE_BYTES
+/// however long a line runs. A `\r` not followed by `\n` starts a redraw of
+/// the same line, so a progress bar comes out as one line when it finishes.
+#[derive(Debug, Default)]
+pub struct LineSplitter {
+    segment: Vec<u8>,
+    /// The last redraw completed, shown if the line ends on an empty one.
+    last: Vec<u8>,
+    updates: u64,
+    truncated_bytes: u64,
+    /// A `\r` was the last byte seen; the next one says whether it was CRLF.
+    pending_cr: bool,
+}
+
+impl LineSplitter {
+    /// The lines `chunk` completes. Chunks may split lines and CRLFs anywhere.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<OutputLine> {
+        let mut lines = Vec::new();
+        for &byte in chunk {
+            if std::mem::take(&mut self.pending_cr) && byte != b'\n' {
+                self.redraw();
+            }
+            match byte {
+                b'\n' => lines.push(self.end_line()),
+                b'\r' => self.pending_cr = true,
+                _ if self.segment.len() < MAX_LINE_BYTES => self.segment.push(byte),
+                _ => self.truncated_bytes += 1,
+            }
+        }
+        lines
+    }
+
+    /// The unfinished line, once the stream has closed.
+    pub fn finish(&mut self) -> Option<OutputLine> {
+        if std::mem::take(&mut self.pending_cr) {
+            self.redraw();
+        }
+        let empty = self.segment.is_empty() && self.last.is_empty() && self.truncated_bytes == 0;
+        (!empty).then(|| self.end_line())
+    }
+
+    fn redraw(&mut self) {
+        self.updates += 1;
+        if !self.segment.is_empty() {
+            self.last = std::mem::take(&mut self.segment);
+        }
+    }
+
+    fn end_line(&mut self) -> OutputLine {
+        self.pending_cr = false;
+        let mut bytes = std::mem::take(&mut self.segment);
+        let last = std::mem::take(&mut self.last);
+        if bytes.is_empty() {
+            bytes = last;
+        }
+        let mut truncated_bytes = std::mem::take(&mut self.truncated_bytes);
+        // A cut can land inside a character; drop its first bytes too
+        if truncated_bytes > 0 {
+            if let Err(e) = std::str::from_utf8(&bytes) {
+                if e.error_len().is_none() {
+                    truncated_bytes += (bytes.len() - e.valid_up_to()) as u64;
+                    bytes.truncate(e.valid_up_to());
+                }
+            }
+        }
+        OutputLine {
+            text: String::from_utf8_lossy(&bytes).into_owned(),
+            updates: std::mem::take(&mut self.updates),
+            truncated_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout",
+            LogStream::Stderr => "stderr",
+        }
+    }
+}
+
+/// One line of server output as kept in the ServerLog and sent as the
+/// `server-log` event.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ServerLogLine {
+    /// Counts up for the life of the app, across server restarts.
+    pub seq: u64,
+    /// RFC 3339, UTC, in milliseconds.
+    pub timestamp: String,
+    pub stream: LogStream,
+    pub text: String,
+}
+
+impl std::fmt::Display for ServerLogLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} [{}] {}", self.timestamp, self.stream.as_str(), self.text)
+    }
+}
+
+#[derive(Default)]
+struct LogLines {
+    lines: VecDeque<ServerLogLine>,
+    next_seq: u64,
+}
+
+/// The most recent lines of server output, oldest dropped first.
+pub struct ServerLog {
+    capacity: usize,
+    lines: Mutex<LogLines>,
+    events: Mutex<Option<mpsc::Sender<ServerLogLine>>>,
+}
+
+impl Default for ServerLog {
+    fn default() -> Self {
+        Self::new(SERVER_LOG_LINES)
+    }
+}
+
+impl ServerLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            lines: Mutex::new(LogLines::default()),
+            events: Mutex::new(None),
+        }
+    }
+
+    pub fn push(&self, stream: LogStream, text: &str) {
+        let line = {
+            let mut log = self.lines.lock().unwrap();
+            let line = ServerLogLine {
+                seq: log.next_seq,
+                timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                stream,
+                text: text.trim_end().to_string(),
+            };
+            log.next_seq += 1;
+            if log.lines.len() == self.capacity {
+                log.lines.pop_front();
+            }
+            log.lines.push_back(line.clone());
+            line
+        };
+        if let Some(events) = &*self.events.lock().unwrap() {
+            if events.try_send(line).is_err() {
+                METRICS.server_log_events_dropped.inc();
+            }
+        }
+    }
+
+    /// Seq the next line will get; with tail_since, a start's own lines.
+    pub fn next_seq(&self) -> u64 {
+        self.lines.lock().unwrap().next_seq
+    }
+
+    /// The newest `last_n` lines, or all kept, oldest first.
+    pub fn last(&self, last_n: Option<usize>) -> Vec<ServerLogLine> {
+        let log = self.lines.lock().unwrap();
+        let skip = last_n.map_or(0, |n| log.lines.len().saturating_sub(n));
+        log.lines.iter().skip(skip).cloned().collect()
+    }
+
+    /// The text of the newest `n` lines from `seq` on, for error reports.
+    pub fn tail_since(&self, seq: u64, n: usize) -> Vec<String> {
+        let log = self.lines.lock().unwrap();
+        let since: Vec<&ServerLogLine> = log.lines.iter().filter(|line| line.seq >= seq).collect();
+        since[since.len().saturating_sub(n)..].iter().map(|line| line.text.clone()).collect()
+    }
+
+    /// Lines pushed from now on, for the `server-log` event. Replaces an
+    /// earlier subscriber. At most `queue` wait; later ones skip the event.
+    pub fn subscribe(&self, queue: usize) -> mpsc::Receiver<ServerLogLine> {
+        let (tx, rx) = mpsc::channel(queue.max(1));
+        *self.events.lock().unwrap() = Some(tx);
+        rx
+    }
+
+    /// Write the kept lines to `path`, one per line. Blocking.
+    pub fn export(&self, path: &Path) -> Result<usize, String> {
+        let lines = self.last(None);
+        let write = || -> std::io::Result<()> {
+            let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+            for line in &lines {
+                writeln!(file, "{}", line)?;
+            }
+            file.flush()
+        };
+        write().map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        Ok(lines.len())
+    }
+}
+
+/// Split a raw chunk of sidecar output, log the lines it completes and keep
+/// them in `log`. Returns the lines for startup to look through.
+pub fn take(splitter: &mut LineSplitter, chunk: &[u8], stream: LogStream, log: &ServerLog) -> Vec<OutputLine> {
+    METRICS.server_output_bytes.add(chunk.len() as u64);
+    let lines = splitter.push(chunk);
+    for line in &lines {
+        note(line, stream, log);
+    }
+    lines
+}
+
+/// Log a stream's last, unterminated line, once it has closed.
+pub fn finish(splitter: &mut LineSplitter, stream: LogStream, log: &ServerLog) {
+    if let Some(line) = splitter.finish() {
+        note(&line, stream, log);
+    }
+}
+
+fn note(line: &OutputLine, stream: LogStream, log: &ServerLog) {
+    METRICS.server_output_truncated_bytes.add(line.truncated_bytes);
+    METRICS.server_progress_updates.add(line.updates);
+    let line = line.to_string();
+    info!(target: logging::SERVER_TARGET, "{}", line);
+    log.push(stream, &line);
+}
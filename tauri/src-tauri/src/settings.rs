@@ -0,0 +1,664 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+pub const SETTINGS_FILE: &str = "settings.json";
+
+/// Bump when a field changes meaning, and migrate older files in `migrate`.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Longest server_stop_grace_secs, since quitting waits out the grace period.
+pub const MAX_STOP_GRACE_SECS: u32 = 60;
+
+/// Longest server_startup_timeout_secs; a start that slow has hung.
+pub const MAX_STARTUP_TIMEOUT_SECS: u32 = 1800;
+
+/// Keys with their own commands because changing them does more than store a
+/// value (registering a shortcut, moving the data dir, ...).
+const READ_ONLY_KEYS: [&str; 9] = [
+    "schema_version",
+    "data_dir",
+    "hotkeys",
+    "window_state",
+    "mini_recorder_position",
+    "recent_items",
+    "watched_folders",
+    "scheduled_captures",
+    "control_api",
+];
+
+/// Log levels understood by the sidecar's `--log-level` flag (uvicorn's set).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerLogLevel {
+    Critical,
+    Error,
+    Warning,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl ServerLogLevel {
+    pub const ALL: [ServerLogLevel; 6] = [
+        ServerLogLevel::Critical,
+        ServerLogLevel::Error,
+        ServerLogLevel::Warning,
+        ServerLogLevel::Info,
+        ServerLogLevel::Debug,
+        ServerLogLevel::Trace,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServerLogLevel::Critical => "critical",
+            ServerLogLevel::Error => "error",
+            ServerLogLevel::Warning => "warning",
+            ServerLogLevel::Info => "info",
+            ServerLogLevel::Debug => "debug",
+            ServerLogLevel::Trace => "trace",
+        }
+    }
+
+    pub fn parse(level: &str) -> Result<Self, String> {
+        let normalized = level.trim().to_lowercase();
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|l| l.as_str() == normalized)
+            .ok_or_else(|| {
+                let known: Vec<&str> = Self::ALL.iter().map(|l| l.as_str()).collect();
+                format!(
+                    "Unknown log level '{}' (expected one of: {})",
+                    level,
+                    known.join(", ")
+                )
+            })
+    }
+}
+
+/// Scheduling priority for the sidecar, so long generations don't make the
+/// rest of the machine sluggish.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessPriority {
+    Normal,
+    BelowNormal,
+    Background,
+}
+
+impl ProcessPriority {
+    pub const ALL: [ProcessPriority; 3] = [
+        ProcessPriority::Normal,
+        ProcessPriority::BelowNormal,
+        ProcessPriority::Background,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProcessPriority::Normal => "normal",
+            ProcessPriority::BelowNormal => "below_normal",
+            ProcessPriority::Background => "background",
+        }
+    }
+
+    pub fn parse(priority: &str) -> Result<Self, String> {
+        let normalized = priority.trim().to_lowercase();
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|p| p.as_str() == normalized)
+            .ok_or_else(|| {
+                let known: Vec<&str> = Self::ALL.iter().map(|p| p.as_str()).collect();
+                format!(
+                    "Unknown process priority '{}' (expected one of: {})",
+                    priority,
+                    known.join(", ")
+                )
+            })
+    }
+}
+
+/// Things a global hotkey can do while Voicebox is in the background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HotkeyAction {
+    ToggleCapture,
+    AddCaptureMarker,
+    StopPlayback,
+}
+
+impl HotkeyAction {
+    pub const ALL: [HotkeyAction; 3] = [
+        HotkeyAction::ToggleCapture,
+        HotkeyAction::AddCaptureMarker,
+        HotkeyAction::StopPlayback,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HotkeyAction::ToggleCapture => "toggle_capture",
+            HotkeyAction::AddCaptureMarker => "add_capture_marker",
+            HotkeyAction::StopPlayback => "stop_playback",
+        }
+    }
+
+    pub fn parse(action: &str) -> Result<Self, String> {
+        let normalized = action.trim().to_lowercase();
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|a| a.as_str() == normalized)
+            .ok_or_else(|| {
+                let known: Vec<&str> = Self::ALL.iter().map(|a| a.as_str()).collect();
+                format!(
+                    "Unknown hotkey action '{}' (expected one of: {})",
+                    action,
+                    known.join(", ")
+                )
+            })
+    }
+}
+
+/// What closing the main window does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseBehavior {
+    /// Run the usual close flow, which quits the app.
+    #[default]
+    Quit,
+    /// Hide the window and keep running in the tray.
+    MinimizeToTray,
+}
+
+impl CloseBehavior {
+    pub const ALL: [CloseBehavior; 2] = [CloseBehavior::Quit, CloseBehavior::MinimizeToTray];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CloseBehavior::Quit => "quit",
+            CloseBehavior::MinimizeToTray => "minimize_to_tray",
+        }
+    }
+
+    pub fn parse(behavior: &str) -> Result<Self, String> {
+        let normalized = behavior.trim().to_lowercase();
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|b| b.as_str() == normalized)
+            .ok_or_else(|| {
+                let known: Vec<&str> = Self::ALL.iter().map(|b| b.as_str()).collect();
+                format!(
+                    "Unknown close behavior '{}' (expected one of: {})",
+                    behavior,
+                    known.join(", ")
+                )
+            })
+    }
+}
+
+/// Release channel check_for_updates looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateChannel {
+    #[default]
+    Stable,
+    /// Pre-releases, published ahead of stable.
+    Beta,
+}
+
+impl UpdateChannel {
+    pub const ALL: [UpdateChannel; 2] = [UpdateChannel::Stable, UpdateChannel::Beta];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpdateChannel::Stable => "stable",
+            UpdateChannel::Beta => "beta",
+        }
+    }
+
+    pub fn parse(channel: &str) -> Result<Self, String> {
+        let normalized = channel.trim().to_lowercase();
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|c| c.as_str() == normalized)
+            .ok_or_else(|| {
+                let known: Vec<&str> = Self::ALL.iter().map(|c| c.as_str()).collect();
+                format!(
+                    "Unknown update channel '{}' (expected one of: {})",
+                    channel,
+                    known.join(", ")
+                )
+            })
+    }
+}
+
+/// Which outcomes raise a native notification while the window isn't focused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationPrefs {
+    pub capture_auto_stopped: bool,
+    pub capture_failed: bool,
+    pub server_crashed: bool,
+    pub server_ready_after_restart: bool,
+    pub model_download_complete: bool,
+    pub import_failed: bool,
+    pub scheduled_capture: bool,
+}
+
+impl Default for NotificationPrefs {
+    fn default() -> Self {
+        Self {
+            capture_auto_stopped: true,
+            capture_failed: true,
+            server_crashed: true,
+            server_ready_after_restart: true,
+            model_download_complete: true,
+            import_failed: true,
+            scheduled_capture: true,
+        }
+    }
+}
+
+/// Main window geometry in physical pixels, saved as the user moves it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowState {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub maximized: bool,
+    /// Name of the monitor the window was on, when the OS reports one.
+    pub monitor: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecentKind {
+    Imported,
+    Exported,
+    Played,
+    /// A system audio capture, by session id.
+    Recording,
+}
+
+/// An entry of the recently-used lists kept by the mru module.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecentItem {
+    pub kind: RecentKind,
+    /// An absolute file path, or an id such as a capture session.
+    pub target: String,
+    pub label: String,
+    /// Whether `target` is a file, pruned once it no longer exists.
+    pub is_file: bool,
+    /// Unix seconds it was last used.
+    pub used_at: u64,
+}
+
+/// A folder whose new audio files are imported as they appear.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchedFolder {
+    pub path: PathBuf,
+    /// Convert to WAV on import rather than copying the file as is.
+    pub convert: bool,
+}
+
+/// Where a scheduled capture's recording is saved, besides being kept like
+/// any other capture.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CaptureOutput {
+    /// A WAV named after the label and start time, in `dir`.
+    File { dir: PathBuf },
+    /// The recordings library, under its quota.
+    Library,
+}
+
+/// Limits on the recordings library; None for no limit. A save that would
+/// go over one prunes the oldest unpinned recordings first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordingsQuota {
+    pub max_total_bytes: Option<u64>,
+    pub max_items: Option<u32>,
+    pub max_age_days: Option<u32>,
+}
+
+/// The local control API for stream decks. Always bound to loopback.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControlApiSettings {
+    pub enabled: bool,
+    pub port: u16,
+    /// Bearer token every request must carry.
+    pub token: Option<String>,
+}
+
+impl Default for ControlApiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: crate::control_api::DEFAULT_PORT,
+            token: None,
+        }
+    }
+}
+
+/// What schedule_capture takes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduleSpec {
+    /// A five-field cron expression or an RRULE, in local time.
+    pub cron_or_rrule: String,
+    pub duration_secs: u32,
+    pub output: CaptureOutput,
+    pub label: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledCapture {
+    pub id: u64,
+    #[serde(flatten)]
+    pub spec: ScheduleSpec,
+    /// Unix seconds. Fire times up to here have been handled: started, or
+    /// reported as missed.
+    pub checked_until: u64,
+}
+
+/// Top-left corner of a fixed-size window, in physical pixels.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowPosition {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub schema_version: u32,
+    pub server_log_level: ServerLogLevel,
+    pub server_access_logs: bool,
+    /// Overrides the app data dir as the server's data directory after a migration.
+    pub data_dir: Option<PathBuf>,
+    /// None leaves the sidecar at the priority it inherits from the app.
+    pub process_priority: Option<ProcessPriority>,
+    /// How long a stopping server gets to exit on its own before it's killed.
+    pub server_stop_grace_secs: u32,
+    /// How long a start waits for the server to answer before giving up.
+    /// PyInstaller bundles can take minutes on slow machines after an update.
+    pub server_startup_timeout_secs: u32,
+    /// Accelerator strings (e.g. "CmdOrCtrl+Shift+R") registered at startup.
+    pub hotkeys: BTreeMap<HotkeyAction, String>,
+    /// Longest capture the toggle_capture hotkey starts; the UI's own
+    /// recorder picks its limit per view.
+    pub hotkey_capture_max_secs: u32,
+    pub window_state: Option<WindowState>,
+    pub mini_recorder_position: Option<WindowPosition>,
+    /// Debug builds only: reopen the devtools if they were open at last exit.
+    pub devtools_open: bool,
+    pub notifications: NotificationPrefs,
+    pub close_behavior: CloseBehavior,
+    /// Largest file import_audio_file accepts, in megabytes.
+    pub max_import_mb: u32,
+    /// Directory the export save dialog opens in.
+    pub last_export_dir: Option<PathBuf>,
+    pub keep_server_running_on_close: bool,
+    /// With keep_server_running_on_close, stop the server after this long idle.
+    pub idle_timeout_minutes: Option<u32>,
+    pub update_channel: UpdateChannel,
+    /// Newest first, capped per kind. Changed through the mru commands.
+    pub recent_items: Vec<RecentItem>,
+    /// Kept while a folder is missing, so it is picked up again when it returns.
+    pub watched_folders: Vec<WatchedFolder>,
+    /// Changed through the schedule commands.
+    pub scheduled_captures: Vec<ScheduledCapture>,
+    pub recordings_quota: RecordingsQuota,
+    /// Captures longer than this aren't fingerprinted for
+    /// find_similar_recordings; 0 fingerprints none.
+    pub fingerprint_max_minutes: u32,
+    /// Segments shorter than this are merged into the next when a recording
+    /// is split at its markers; 0 keeps every cut.
+    pub split_min_segment_ms: u32,
+    /// Keep the full-length recording in the library alongside its segments.
+    pub split_keep_original: bool,
+    /// Changed through set_control_api.
+    pub control_api: ControlApiSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            server_log_level: ServerLogLevel::default(),
+            server_access_logs: true,
+            data_dir: None,
+            process_priority: None,
+            server_stop_grace_secs: 5,
+            server_startup_timeout_secs: 120,
+            hotkeys: BTreeMap::new(),
+            window_state: None,
+            mini_recorder_position: None,
+            devtools_open: false,
+            notifications: NotificationPrefs::default(),
+            close_behavior: CloseBehavior::default(),
+            max_import_mb: 200,
+            hotkey_capture_max_secs: 300,
+            last_export_dir: None,
+            keep_server_running_on_close: false,
+            idle_timeout_minutes: None,
+            update_channel: UpdateChannel::default(),
+            recent_items: Vec::new(),
+            watched_folders: Vec::new(),
+            scheduled_captures: Vec::new(),
+            recordings_quota: RecordingsQuota::default(),
+            fingerprint_max_minutes: 60,
+            split_min_segment_ms: 1000,
+            split_keep_original: true,
+            control_api: ControlApiSettings::default(),
+        }
+    }
+}
+
+/// Whether a changed setting is already in effect or needs a server restart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SettingApplied {
+    Applied,
+    RequiresRestart,
+}
+
+pub struct SettingsState {
+    path: Option<PathBuf>,
+    values: Mutex<Settings>,
+    /// Cleared while the data dir can't be written: changes then apply but
+    /// last only until quit.
+    persistent: AtomicBool,
+}
+
+impl SettingsState {
+    /// Load settings from the data dir, falling back to defaults if the file is
+    /// missing. A corrupt file is moved aside and replaced with defaults.
+    pub fn load(data_dir: Option<&Path>) -> Self {
+        let path = data_dir.map(|dir| dir.join(SETTINGS_FILE));
+        let contents = path.as_ref().and_then(|p| std::fs::read_to_string(p).ok());
+        let mut corrupt = false;
+        let values = match contents.map(|c| serde_json::from_str::<Settings>(&c)) {
+            Some(Ok(settings)) => migrate(settings),
+            Some(Err(e)) => {
+                warn!("{} is corrupt, regenerating it: {}", SETTINGS_FILE, e);
+                corrupt = true;
+                Settings::default()
+            }
+            None => Settings::default(),
+        };
+
+        let state = Self {
+            path,
+            values: Mutex::new(values),
+            persistent: AtomicBool::new(true),
+        };
+        if corrupt {
+            state.back_up_corrupt();
+            if let Err(e) = state.save(&state.get()) {
+                warn!("{}", e);
+            }
+        }
+        state
+    }
+
+    fn back_up_corrupt(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let backup = path.with_extension(format!("json.corrupt-{}", secs));
+        match std::fs::rename(path, &backup) {
+            Ok(()) => info!("Backed up corrupt settings to {}", backup.display()),
+            Err(e) => warn!("Failed to back up corrupt settings: {}", e),
+        }
+    }
+
+    pub fn get(&self) -> Settings {
+        self.values.lock().unwrap().clone()
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.persistent.load(Ordering::SeqCst)
+    }
+
+    /// Stop or resume writing the settings file. On resuming, changes made
+    /// in the meantime are saved.
+    pub fn set_persistent(&self, persistent: bool) -> Result<(), String> {
+        let values = self.values.lock().unwrap();
+        if self.persistent.swap(persistent, Ordering::SeqCst) || !persistent {
+            return Ok(());
+        }
+        self.save(&values)
+    }
+
+    pub fn update<F: FnOnce(&mut Settings)>(&self, f: F) -> Result<(), String> {
+        let mut values = self.values.lock().unwrap();
+        f(&mut values);
+        self.save(&values)
+    }
+
+    /// Every setting as JSON, keyed by field name.
+    pub fn get_all_values(&self) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+        match serde_json::to_value(self.get()) {
+            Ok(serde_json::Value::Object(map)) => Ok(map),
+            Ok(_) => Err("Settings did not serialize to an object".to_string()),
+            Err(e) => Err(format!("Failed to serialize settings: {}", e)),
+        }
+    }
+
+    pub fn get_value(&self, key: &str) -> Result<serde_json::Value, String> {
+        self.get_all_values()?
+            .remove(key)
+            .ok_or_else(|| format!("Unknown setting '{}'", key))
+    }
+
+    /// Set one setting from JSON. The value must have the field's type; keys
+    /// with side effects have to go through their own commands.
+    pub fn set_value(&self, key: &str, value: serde_json::Value) -> Result<(), String> {
+        if READ_ONLY_KEYS.contains(&key) {
+            return Err(format!("Setting '{}' can't be changed with set_setting", key));
+        }
+        let mut values = self.values.lock().unwrap();
+        let mut map = match serde_json::to_value(&*values) {
+            Ok(serde_json::Value::Object(map)) => map,
+            _ => return Err("Failed to serialize settings".to_string()),
+        };
+        if !map.contains_key(key) {
+            return Err(format!("Unknown setting '{}'", key));
+        }
+        map.insert(key.to_string(), value);
+        let updated: Settings = serde_json::from_value(serde_json::Value::Object(map))
+            .map_err(|e| format!("Invalid value for '{}': {}", key, e))?;
+        validate(&updated)?;
+        self.save(&updated)?;
+        *values = updated;
+        Ok(())
+    }
+
+    fn save(&self, values: &Settings) -> Result<(), String> {
+        let Some(path) = self.path.as_ref().filter(|_| self.is_persistent()) else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create settings dir: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(values)
+            .map_err(|e| format!("Failed to serialize settings: {}", e))?;
+        // Write to a temp file and rename so a crash never leaves a half-written file
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, json).map_err(|e| format!("Failed to write settings: {}", e))?;
+        std::fs::rename(&tmp_path, path).map_err(|e| format!("Failed to write settings: {}", e))
+    }
+}
+
+/// Bring settings written by an older app version up to SCHEMA_VERSION.
+fn migrate(mut settings: Settings) -> Settings {
+    if settings.schema_version > SCHEMA_VERSION {
+        warn!(
+            "{} is from a newer version (schema {}), unknown fields are ignored",
+            SETTINGS_FILE, settings.schema_version
+        );
+    }
+    // Nothing has changed meaning yet; files from before versioning read as
+    // the current version through the field default
+    settings.schema_version = SCHEMA_VERSION;
+    settings
+}
+
+/// Also checked for the startup_timeout_secs a caller passes to start_server.
+pub fn validate_startup_timeout(secs: u32) -> Result<(), String> {
+    if !(1..=MAX_STARTUP_TIMEOUT_SECS).contains(&secs) {
+        return Err(format!(
+            "server_startup_timeout_secs must be between 1 and {}",
+            MAX_STARTUP_TIMEOUT_SECS
+        ));
+    }
+    Ok(())
+}
+
+/// Range checks that the types alone don't express.
+fn validate(settings: &Settings) -> Result<(), String> {
+    if settings.max_import_mb == 0 {
+        return Err("max_import_mb must be at least 1".to_string());
+    }
+    if settings.hotkey_capture_max_secs == 0 {
+        return Err("hotkey_capture_max_secs must be at least 1".to_string());
+    }
+    if !(1..=MAX_STOP_GRACE_SECS).contains(&settings.server_stop_grace_secs) {
+        return Err(format!("server_stop_grace_secs must be between 1 and {}", MAX_STOP_GRACE_SECS));
+    }
+    validate_startup_timeout(settings.server_startup_timeout_secs)?;
+    if settings.idle_timeout_minutes == Some(0) {
+        return Err("idle_timeout_minutes must be at least 1, or null to disable".to_string());
+    }
+    let quota = &settings.recordings_quota;
+    if quota.max_total_bytes == Some(0) || quota.max_items == Some(0) || quota.max_age_days == Some(0) {
+        return Err("recordings_quota limits must be at least 1, or null for no limit".to_string());
+    }
+    let control_api = &settings.control_api;
+    if control_api.port == 0 {
+        return Err("control_api.port must be at least 1".to_string());
+    }
+    let token_chars = control_api.token.as_ref().map_or(0, |token| token.chars().count());
+    if control_api.enabled && token_chars < crate::control_api::MIN_TOKEN_CHARS {
+        return Err(format!(
+            "control_api.token must be at least {} characters while the control API is enabled",
+            crate::control_api::MIN_TOKEN_CHARS
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,321 @@
+use crate::audio_output::AudioOutputState;
+use crate::server_error::{BinaryProblemKind, ServerStartError};
+use crate::storage::{StorageProblemKind, StorageStatus};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How long each check gets. They run at once, so a whole run finishes in
+/// about this long however many of them hang.
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// Room for the larger TTS model and Whisper, which the first generation
+/// downloads.
+pub const MODELS_BYTES: u64 = 5 * 1024 * 1024 * 1024;
+
+/// Bytes of a binary read to find its architecture; enough for the ELF,
+/// Mach-O (fat or thin) and PE headers.
+const HEADER_BYTES: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SetupCheck {
+    /// The sidecar is present, executable and built for this machine.
+    ServerBinary,
+    /// The data dir is writable with room for the models.
+    DiskSpace,
+    Permissions,
+    /// There is an output device, and the default one answers.
+    OutputDevice,
+    /// The sidecar starts, or the running server answers. Opt-in, being the
+    /// slowest.
+    ServerSmokeStart,
+}
+
+impl SetupCheck {
+    /// What run_setup_checks runs unless the smoke start is asked for too.
+    pub const DEFAULT: [SetupCheck; 4] = [
+        SetupCheck::ServerBinary,
+        SetupCheck::DiskSpace,
+        SetupCheck::Permissions,
+        SetupCheck::OutputDevice,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    /// Works, but not as well as it could, or couldn't be confirmed.
+    Warn,
+    Fail,
+}
+
+/// What a check found, before run() times it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub status: CheckStatus,
+    pub detail: String,
+    pub fix_hint: Option<String>,
+}
+
+impl Finding {
+    pub fn pass(detail: impl Into<String>) -> Self {
+        Self {
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            fix_hint: None,
+        }
+    }
+
+    pub fn warn(detail: impl Into<String>, fix_hint: impl Into<String>) -> Self {
+        Self {
+            status: CheckStatus::Warn,
+            detail: detail.into(),
+            fix_hint: Some(fix_hint.into()),
+        }
+    }
+
+    pub fn fail(detail: impl Into<String>, fix_hint: impl Into<String>) -> Self {
+        Self {
+            status: CheckStatus::Fail,
+            detail: detail.into(),
+            fix_hint: Some(fix_hint.into()),
+        }
+    }
+}
+
+/// One item of run_setup_checks, and what run_single_check returns.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckResult {
+    pub check: SetupCheck,
+    pub status: CheckStatus,
+    pub detail: String,
+    pub fix_hint: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// Run `probe` as `check`, giving up after `timeout`. A check that times out
+/// warns rather than fails: it couldn't tell whether anything is wrong.
+pub async fn run(check: SetupCheck, timeout: Duration, probe: impl Future<Output = Finding>) -> CheckResult {
+    let started = Instant::now();
+    let finding = tokio::time::timeout(timeout, probe).await.unwrap_or_else(|_| {
+        Finding::warn(
+            format!("The check didn't finish within {} s", timeout.as_secs_f32()),
+            "Run the check again; if it keeps timing out, restart your computer",
+        )
+    });
+    CheckResult {
+        check,
+        status: finding.status,
+        detail: finding.detail,
+        fix_hint: finding.fix_hint,
+        duration_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
+/// Run `checks` at once, each through run(), returning their results in the
+/// order given.
+pub async fn run_all<F, Fut>(checks: &[SetupCheck], timeout: Duration, probe: F) -> Vec<CheckResult>
+where
+    F: Fn(SetupCheck) -> Fut,
+    Fut: Future<Output = Finding> + Send + 'static,
+{
+    let tasks: Vec<_> = checks
+        .iter()
+        .map(|&check| tokio::spawn(run(check, timeout, probe(check))))
+        .collect();
+    let mut results = Vec::with_capacity(checks.len());
+    for (task, &check) in tasks.into_iter().zip(checks) {
+        results.push(task.await.unwrap_or_else(|e| CheckResult {
+            check,
+            status: CheckStatus::Fail,
+            detail: format!("The check crashed: {}", e),
+            fix_hint: None,
+            duration_ms: 0,
+        }));
+    }
+    results
+}
+
+/// The architectures a binary is built for, by the names of
+/// `std::env::consts::ARCH`, from its first bytes. Empty if it isn't an ELF,
+/// Mach-O or PE binary, or is for some other architecture. A universal
+/// Mach-O binary lists each of its slices.
+pub fn binary_arches(header: &[u8]) -> Vec<&'static str> {
+    let u16_at = |at: usize, big: bool| {
+        header
+            .get(at..at + 2)
+            .map(|b| if big { u16::from_be_bytes([b[0], b[1]]) } else { u16::from_le_bytes([b[0], b[1]]) })
+    };
+    let u32_at = |at: usize, big: bool| {
+        header.get(at..at + 4).map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if big {
+                u32::from_be_bytes(b)
+            } else {
+                u32::from_le_bytes(b)
+            }
+        })
+    };
+    let mach = |cputype: u32| match cputype {
+        0x0100_0007 => Some("x86_64"),
+        0x0100_000c => Some("aarch64"),
+        7 => Some("x86"),
+        12 => Some("arm"),
+        _ => None,
+    };
+
+    if header.starts_with(b"\x7fELF") {
+        let big = header.get(5) == Some(&2);
+        let arch = match u16_at(18, big) {
+            Some(0x3e) => Some("x86_64"),
+            Some(0xb7) => Some("aarch64"),
+            Some(0x03) => Some("x86"),
+            Some(0x28) => Some("arm"),
+            _ => None,
+        };
+        return arch.into_iter().collect();
+    }
+    if header.starts_with(b"MZ") {
+        let Some(pe) = u32_at(0x3c, false).map(|at| at as usize) else {
+            return Vec::new();
+        };
+        if header.get(pe..pe + 4) != Some(b"PE\0\0") {
+            return Vec::new();
+        }
+        let arch = match u16_at(pe + 4, false) {
+            Some(0x8664) => Some("x86_64"),
+            Some(0xaa64) => Some("aarch64"),
+            Some(0x014c) => Some("x86"),
+            _ => None,
+        };
+        return arch.into_iter().collect();
+    }
+    match u32_at(0, true) {
+        // Universal: a big-endian table of slices
+        Some(0xcafe_babe) => {
+            let slices = u32_at(4, true).unwrap_or(0) as usize;
+            (0..slices.min(16))
+                .filter_map(|i| u32_at(8 + i * 20, true))
+                .filter_map(mach)
+                .collect()
+        }
+        Some(0xcffa_edfe) | Some(0xcefa_edfe) => u32_at(4, false).and_then(mach).into_iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Whether the sidecar at `path` can run on an `arch` machine running `os`
+/// (`std::env::consts` names). Blocking.
+pub fn server_binary(path: &Path, arch: &str, os: &str) -> Finding {
+    let problem = |kind: BinaryProblemKind, detail: String| {
+        let error = ServerStartError::BinaryProblem { kind };
+        Finding::fail(format!("{}: {}", error, detail), error.hints().join(". "))
+    };
+    let mut header = Vec::new();
+    let read = std::fs::File::open(path).and_then(|file| {
+        use std::io::Read;
+        file.take(HEADER_BYTES).read_to_end(&mut header)
+    });
+    if let Err(e) = read {
+        let kind = match e.kind() {
+            std::io::ErrorKind::NotFound => BinaryProblemKind::NotFound,
+            _ => BinaryProblemKind::NotExecutable,
+        };
+        return problem(kind, format!("{} ({})", path.display(), e));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let executable = std::fs::metadata(path).is_ok_and(|meta| meta.permissions().mode() & 0o111 != 0);
+        if !executable {
+            return problem(
+                BinaryProblemKind::NotExecutable,
+                format!("{} is not marked executable", path.display()),
+            );
+        }
+    }
+
+    let arches = binary_arches(&header);
+    if arches.contains(&arch) {
+        return Finding::pass(format!("{} ({})", path.display(), arches.join(", ")));
+    }
+    let built_for = if arches.is_empty() {
+        "an unknown architecture".to_string()
+    } else {
+        arches.join(", ")
+    };
+    // Rosetta and Windows on Arm run x86_64 binaries, only slower
+    if arch == "aarch64" && arches.contains(&"x86_64") && matches!(os, "macos" | "windows") {
+        return Finding::warn(
+            format!("The server is built for {} and runs emulated on this {} machine", built_for, arch),
+            "Download the Voicebox build for your processor for faster generation",
+        );
+    }
+    Finding::fail(
+        format!("The server is built for {}, but this machine is {}", built_for, arch),
+        "Download the Voicebox build for your processor",
+    )
+}
+
+/// Whether the data dir, as probed into `status`, can hold the models.
+pub fn disk_space(status: &StorageStatus, models_bytes: u64) -> Finding {
+    const MB: u64 = 1024 * 1024;
+    if let Some(problem) = &status.problem {
+        let hints = ServerStartError::Storage {
+            problem: problem.clone(),
+        }
+        .hints();
+        let detail = format!("{}: {}", problem.path.display(), problem.detail);
+        return match problem.kind {
+            StorageProblemKind::InsufficientSpace => Finding::fail(detail, hints.join(". ")),
+            _ => Finding::fail(format!("The data directory can't be written. {}", detail), hints.join(". ")),
+        };
+    }
+    match status.available_bytes {
+        Some(available) if available < models_bytes => Finding::warn(
+            format!(
+                "{} MB free; the models need about {} MB",
+                available / MB,
+                models_bytes / MB
+            ),
+            "Free up space on the drive holding the data directory, or move it in Settings",
+        ),
+        Some(available) => Finding::pass(format!("{} MB free in {}", available / MB, status.path.display())),
+        None => Finding::warn(
+            format!("Couldn't tell how much space is free in {}", status.path.display()),
+            "Make sure the drive has a few GB free for the models",
+        ),
+    }
+}
+
+/// Whether there is an output device, and the default one reports a format
+/// it can play. Blocking.
+pub fn output_device(output: &AudioOutputState) -> Finding {
+    let no_device_hint = "Connect speakers or headphones, or enable a device in the system's sound settings";
+    let devices = match output.list_output_devices() {
+        Ok(devices) => devices,
+        Err(e) => return Finding::fail(format!("Couldn't list output devices: {}", e), no_device_hint),
+    };
+    let Some(device) = devices.iter().find(|device| device.is_default) else {
+        return match devices.first() {
+            None => Finding::fail("No output devices found", no_device_hint),
+            Some(_) => Finding::warn(
+                format!("{} output device(s), none of them the default", devices.len()),
+                "Choose a default output device in the system's sound settings",
+            ),
+        };
+    };
+    match output.device_format(&device.id) {
+        Ok(format) => Finding::pass(format!(
+            "{} ({} Hz, {} channels)",
+            device.name, format.sample_rate, format.channels
+        )),
+        Err(e) => Finding::fail(
+            format!("The default output, {}, doesn't respond: {}", device.name, e),
+            "Reconnect the device or choose another default in the system's sound settings",
+        ),
+    }
+}
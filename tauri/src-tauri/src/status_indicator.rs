@@ -0,0 +1,149 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::image::Image;
+use tauri::{AppHandle, Manager};
+use tracing::{debug, warn};
+
+/// How often the tray tooltip's elapsed time is refreshed while recording.
+const TOOLTIP_INTERVAL: Duration = Duration::from_secs(5);
+
+const TITLE_SUFFIX: &str = " \u{25CF} REC";
+
+/// The red dot in the tray icon's bottom-right corner, as a fraction of the
+/// icon's size.
+const DOT_RADIUS: f32 = 0.22;
+const DOT_COLOR: [u8; 3] = [0xE5, 0x22, 0x22];
+
+/// Capture events that end a recording, whichever way it ends.
+const STOP_EVENTS: [&str; 3] = ["capture-stopped", "capture-failed", "capture-auto-stopped"];
+
+struct Recording {
+    started: Instant,
+    /// The window title before the suffix was added.
+    title: Option<String>,
+    ticker: tauri::async_runtime::JoinHandle<()>,
+}
+
+/// The capture currently shown as recording, if any.
+pub struct StatusIndicatorState {
+    recording: Mutex<Option<Recording>>,
+}
+
+impl StatusIndicatorState {
+    pub fn new() -> Self {
+        Self {
+            recording: Mutex::new(None),
+        }
+    }
+}
+
+/// Follow the capture events emitted from Rust, so the indicator is right
+/// even while the webview is hung or the window hidden. Called from setup,
+/// after the tray is created.
+pub fn install(app: &AppHandle) {
+    let handle = app.clone();
+    crate::event_bus::listen(app, "capture-started", move |_| start(&handle));
+    for event in STOP_EVENTS {
+        let handle = app.clone();
+        crate::event_bus::listen(app, event, move |_| stop(&handle));
+    }
+}
+
+/// Time since the capture started as `m:ss`, while one is shown as recording.
+pub fn elapsed_label(app: &AppHandle) -> Option<String> {
+    let state = app.try_state::<StatusIndicatorState>()?;
+    let recording = state.recording.lock().unwrap();
+    let secs = recording.as_ref()?.started.elapsed().as_secs();
+    Some(format!("{}:{:02}", secs / 60, secs % 60))
+}
+
+fn start(app: &AppHandle) {
+    let window = app.get_webview_window("main");
+    let ticker_app = app.clone();
+    let ticker = tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(TOOLTIP_INTERVAL);
+        loop {
+            interval.tick().await;
+            crate::tray::refresh(&ticker_app);
+        }
+    });
+
+    let title = {
+        let state = app.state::<StatusIndicatorState>();
+        let mut recording = state.recording.lock().unwrap();
+        // A restart without a stop in between keeps the original title
+        let title = match recording.take() {
+            Some(previous) => {
+                previous.ticker.abort();
+                previous.title
+            }
+            None => window.as_ref().and_then(|window| window.title().ok()),
+        };
+        *recording = Some(Recording {
+            started: Instant::now(),
+            title: title.clone(),
+            ticker,
+        });
+        title
+    };
+    debug!("Showing the recording indicator");
+
+    if let Some(tray) = app.tray_by_id(crate::tray::TRAY_ID) {
+        if let Err(e) = tray.set_icon(recording_icon(app)) {
+            warn!("Failed to set the recording tray icon: {}", e);
+        }
+    }
+    if let Some(window) = &window {
+        let base = title.unwrap_or_default();
+        let _ = window.set_title(format!("{}{}", base, TITLE_SUFFIX).trim_start());
+        #[cfg(target_os = "macos")]
+        if let Err(e) = window.set_badge_label(Some("REC".to_string())) {
+            warn!("Failed to set the dock badge: {}", e);
+        }
+    }
+    crate::tray::refresh(app);
+}
+
+fn stop(app: &AppHandle) {
+    let Some(recording) = app.state::<StatusIndicatorState>().recording.lock().unwrap().take() else {
+        return;
+    };
+    recording.ticker.abort();
+    debug!("Clearing the recording indicator");
+
+    if let Some(tray) = app.tray_by_id(crate::tray::TRAY_ID) {
+        let _ = tray.set_icon(app.default_window_icon().cloned());
+    }
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.set_title(&recording.title.unwrap_or_default());
+        #[cfg(target_os = "macos")]
+        let _ = window.set_badge_label(None);
+    }
+    crate::tray::refresh(app);
+}
+
+/// The app icon with a red dot in its bottom-right corner.
+fn recording_icon(app: &AppHandle) -> Option<Image<'static>> {
+    let icon = app.default_window_icon()?;
+    let (width, height) = (icon.width(), icon.height());
+    let mut rgba = icon.rgba().to_vec();
+
+    let radius = width.min(height) as f32 * DOT_RADIUS;
+    let (cx, cy) = (width as f32 - radius, height as f32 - radius);
+    for y in 0..height {
+        for x in 0..width {
+            let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+            // One pixel of falloff keeps the edge from looking jagged
+            let coverage = (radius - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0.0, 1.0);
+            if coverage == 0.0 {
+                continue;
+            }
+            let pixel = &mut rgba[((y * width + x) * 4) as usize..][..4];
+            for (channel, dot) in pixel.iter_mut().zip(DOT_COLOR) {
+                *channel = (*channel as f32 * (1.0 - coverage) + dot as f32 * coverage) as u8;
+            }
+            pixel[3] = pixel[3].max((coverage * 255.0) as u8);
+        }
+    }
+    Some(Image::new_owned(rgba, width, height))
+}
@@ -0,0 +1,257 @@
+use crate::error::VoiceboxError;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+pub const WALK_CANCELLED: &str = "Cancelled";
+
+/// Free space under which the data dir counts as full: the server's database
+/// and generations need room to grow.
+pub const MIN_FREE_BYTES: u64 = 200 * 1024 * 1024;
+
+/// Written and removed again to test that a directory takes writes.
+const PROBE_FILE: &str = ".voicebox-write-probe";
+
+/// A regular file found while walking a directory, relative to the walk root.
+#[derive(Debug, Clone)]
+pub struct FileEntry {
+    pub relative_path: PathBuf,
+    pub size: u64,
+}
+
+/// Recursively list every regular file under `root`. Symlinks are not followed.
+pub fn walk_files(root: &Path) -> Result<Vec<FileEntry>, String> {
+    walk_files_cancellable(root, &AtomicBool::new(false))
+}
+
+/// Like `walk_files`, but gives up with an error once `cancel` is set.
+pub fn walk_files_cancellable(root: &Path, cancel: &AtomicBool) -> Result<Vec<FileEntry>, String> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(WALK_CANCELLED.to_string());
+        }
+        let entries = std::fs::read_dir(&dir)
+            .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+            let path = entry.path();
+            let file_type = entry
+                .file_type()
+                .map_err(|e| format!("Failed to stat {}: {}", path.display(), e))?;
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file() {
+                let size = entry
+                    .metadata()
+                    .map_err(|e| format!("Failed to stat {}: {}", path.display(), e))?
+                    .len();
+                let relative_path = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+                files.push(FileEntry {
+                    relative_path,
+                    size,
+                });
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Total size in bytes and number of files under `root`.
+pub fn dir_size(root: &Path) -> Result<(u64, usize), String> {
+    let files = walk_files(root)?;
+    Ok((files.iter().map(|f| f.size).sum(), files.len()))
+}
+
+/// Closest ancestor of `path` (including itself) that exists on disk.
+pub fn existing_ancestor(path: &Path) -> Option<PathBuf> {
+    path.ancestors().find(|p| p.exists()).map(|p| p.to_path_buf())
+}
+
+/// Bytes available to the current user on the volume holding `path`.
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> Result<u64, String> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let target = existing_ancestor(path).ok_or_else(|| format!("{} does not exist", path.display()))?;
+    let c_path = CString::new(target.as_os_str().as_bytes())
+        .map_err(|_| format!("Invalid path: {}", target.display()))?;
+
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) };
+    if result != 0 {
+        return Err(format!(
+            "Failed to query free space for {}: {}",
+            target.display(),
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Bytes available to the current user on the volume holding `path`.
+#[cfg(windows)]
+pub fn free_space(path: &Path) -> Result<u64, String> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows::core::PCWSTR;
+    use windows::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let target = existing_ancestor(path).ok_or_else(|| format!("{} does not exist", path.display()))?;
+    let wide: Vec<u16> = target.as_os_str().encode_wide().chain(std::iter::once(0)).collect();
+
+    let mut available: u64 = 0;
+    unsafe {
+        GetDiskFreeSpaceExW(PCWSTR(wide.as_ptr()), Some(&mut available), None, None)
+            .map_err(|e| format!("Failed to query free space for {}: {}", target.display(), e))?;
+    }
+    Ok(available)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageProblemKind {
+    /// The directory doesn't exist and couldn't be created.
+    CannotCreate,
+    /// The volume is mounted read-only.
+    ReadOnly,
+    /// The OS refused the write, as on locked-down corporate profiles.
+    PermissionDenied,
+    /// Less than MIN_FREE_BYTES left.
+    InsufficientSpace,
+    /// Any other failed write.
+    WriteFailed,
+}
+
+/// Payload of the `storage-problem` event.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StorageProblem {
+    pub kind: StorageProblemKind,
+    pub path: PathBuf,
+    pub detail: String,
+}
+
+/// Result of get_storage_status.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StorageStatus {
+    pub path: PathBuf,
+    /// Whether a file could be written there just now.
+    pub writable: bool,
+    pub available_bytes: Option<u64>,
+    /// While set the app runs degraded: settings changes last until quit and
+    /// the server won't start. Captures still save where the user chooses.
+    pub problem: Option<StorageProblem>,
+}
+
+impl StorageStatus {
+    /// For a data dir whose location couldn't even be worked out.
+    pub fn unresolved(detail: impl Into<String>) -> Self {
+        Self {
+            path: PathBuf::new(),
+            writable: false,
+            available_bytes: None,
+            problem: Some(StorageProblem {
+                kind: StorageProblemKind::CannotCreate,
+                path: PathBuf::new(),
+                detail: detail.into(),
+            }),
+        }
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.problem.is_some()
+    }
+}
+
+/// Create `dir` if needed, write and remove a probe file in it, and check
+/// that at least `min_free_bytes` are left. Blocking.
+pub fn probe(dir: &Path, min_free_bytes: u64) -> StorageStatus {
+    let problem = |kind, detail: String| StorageProblem {
+        kind,
+        path: dir.to_path_buf(),
+        detail,
+    };
+    let written = std::fs::create_dir_all(dir)
+        .map_err(|e| {
+            let kind = classify(&e, StorageProblemKind::CannotCreate);
+            problem(kind, format!("Cannot create the directory: {}", e))
+        })
+        .and_then(|()| {
+            let probe_file = dir.join(PROBE_FILE);
+            let result = std::fs::write(&probe_file, b"voicebox");
+            let _ = std::fs::remove_file(&probe_file);
+            result.map_err(|e| problem(classify(&e, StorageProblemKind::WriteFailed), format!("Cannot write: {}", e)))
+        });
+    let available_bytes = free_space(dir).ok();
+    let problem = match (written.err(), available_bytes) {
+        (Some(problem), _) => Some(problem),
+        (None, Some(available)) if available < min_free_bytes => Some(problem(
+            StorageProblemKind::InsufficientSpace,
+            format!("Only {} MB free, {} MB needed", available / (1024 * 1024), min_free_bytes / (1024 * 1024)),
+        )),
+        (None, _) => None,
+    };
+    StorageStatus {
+        path: dir.to_path_buf(),
+        writable: !problem.as_ref().is_some_and(|p| p.kind != StorageProblemKind::InsufficientSpace),
+        available_bytes,
+        problem,
+    }
+}
+
+fn classify(e: &std::io::Error, otherwise: StorageProblemKind) -> StorageProblemKind {
+    // EROFS / ERROR_WRITE_PROTECT, and ENOSPC / ERROR_DISK_FULL
+    #[cfg(unix)]
+    let (read_only, full) = (libc::EROFS, libc::ENOSPC);
+    #[cfg(windows)]
+    let (read_only, full) = (19, 112);
+    #[cfg(not(any(unix, windows)))]
+    let (read_only, full) = (-1, -1);
+
+    match e.raw_os_error() {
+        Some(code) if code == read_only => StorageProblemKind::ReadOnly,
+        Some(code) if code == full => StorageProblemKind::InsufficientSpace,
+        _ if e.kind() == std::io::ErrorKind::PermissionDenied => StorageProblemKind::PermissionDenied,
+        _ => otherwise,
+    }
+}
+
+/// Fail with InsufficientSpace unless the volume holding `path` has
+/// `required_bytes` free. Returns what is available. Blocking.
+pub fn ensure_free_space(path: &Path, required_bytes: u64) -> Result<u64, VoiceboxError> {
+    let available_bytes = free_space(path).map_err(VoiceboxError::io)?;
+    if available_bytes < required_bytes {
+        return Err(VoiceboxError::insufficient_space(path.display(), required_bytes, available_bytes));
+    }
+    Ok(available_bytes)
+}
+
+/// The data dir's status as of the last probe.
+pub struct StorageState {
+    status: Mutex<Option<StorageStatus>>,
+}
+
+impl StorageState {
+    pub fn new() -> Self {
+        Self { status: Mutex::new(None) }
+    }
+
+    pub fn get(&self) -> Option<StorageStatus> {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Store a fresh status, returning the one it replaces.
+    pub fn replace(&self, status: StorageStatus) -> Option<StorageStatus> {
+        self.status.lock().unwrap().replace(status)
+    }
+}
+
+impl Default for StorageState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
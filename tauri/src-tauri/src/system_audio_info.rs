@@ -0,0 +1,236 @@
+use crate::audio_output::{AudioOutputDevice, DeviceTransport};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// How often the watcher reads the system output. The OS doesn't tell us
+/// about these changes through anything the app already listens to.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a change has to hold before it is reported, so dragging the
+/// volume slider is one event rather than one per poll.
+pub const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// The system's default output and its volume, for get_system_output_info
+/// and the `system-output-changed` event.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SystemOutputInfo {
+    /// None when there is no default output device.
+    pub device_id: Option<String>,
+    pub device_name: Option<String>,
+    pub transport: DeviceTransport,
+    /// 0 to 1. None where the platform or the device doesn't expose it.
+    pub volume: Option<f32>,
+    pub muted: Option<bool>,
+}
+
+/// The default among `devices`, with the system volume and mute state read
+/// best-effort. Blocking: it may cross into COM or run pactl.
+pub fn read(devices: &[AudioOutputDevice]) -> SystemOutputInfo {
+    let default = devices.iter().find(|device| device.is_default);
+    let (volume, muted) = match default {
+        Some(_) => platform::read_volume(),
+        None => (None, None),
+    };
+    SystemOutputInfo {
+        device_id: default.map(|device| device.id.clone()),
+        device_name: default.map(|device| device.name.clone()),
+        transport: default.map_or(DeviceTransport::Unknown, |device| device.transport),
+        volume,
+        muted,
+    }
+}
+
+/// Turns a stream of readings, of the system output or of anything else
+/// polled alongside it such as the device list, into changes: a reading
+/// that differs from the last reported one is reported once it has held for
+/// DEBOUNCE. The first reading is the baseline and isn't reported.
+#[derive(Debug)]
+pub struct Debouncer<T = SystemOutputInfo> {
+    reported: Option<T>,
+    pending: Option<(T, Instant)>,
+}
+
+impl<T> Default for Debouncer<T> {
+    fn default() -> Self {
+        Self {
+            reported: None,
+            pending: None,
+        }
+    }
+}
+
+impl<T: Clone + PartialEq> Debouncer<T> {
+    pub fn observe(&mut self, info: T, now: Instant) -> Option<T> {
+        if self.reported.is_none() {
+            self.reported = Some(info);
+            return None;
+        }
+        if self.reported.as_ref() == Some(&info) {
+            self.pending = None;
+            return None;
+        }
+        match &self.pending {
+            Some((pending, since)) if *pending == info => {
+                if now.duration_since(*since) < DEBOUNCE {
+                    return None;
+                }
+                self.pending = None;
+                self.reported = Some(info.clone());
+                Some(info)
+            }
+            _ => {
+                self.pending = Some((info, now));
+                None
+            }
+        }
+    }
+}
+
+/// The average of the channel percentages in `pactl get-sink-volume`
+/// output, e.g. `Volume: front-left: 29491 /  45% / -20.81 dB, ...`.
+pub fn parse_pactl_volume(output: &str) -> Option<f32> {
+    let line = output.lines().find(|line| line.trim_start().starts_with("Volume:"))?;
+    let percents: Vec<f32> = line
+        .split('/')
+        .filter_map(|part| part.trim().strip_suffix('%'))
+        .filter_map(|percent| percent.trim().parse().ok())
+        .collect();
+    if percents.is_empty() {
+        return None;
+    }
+    Some(percents.iter().sum::<f32>() / percents.len() as f32 / 100.0)
+}
+
+/// `Mute: yes` or `Mute: no`, from `pactl get-sink-mute`.
+pub fn parse_pactl_mute(output: &str) -> Option<bool> {
+    match output.trim().strip_prefix("Mute:")?.trim() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// The default render endpoint's IAudioEndpointVolume.
+#[cfg(windows)]
+mod platform {
+    use tracing::debug;
+    use windows::Win32::Media::Audio::Endpoints::IAudioEndpointVolume;
+    use windows::Win32::Media::Audio::{eConsole, eRender, IMMDeviceEnumerator, MMDeviceEnumerator};
+    use windows::Win32::System::Com::{
+        CoCreateInstance, CoInitializeEx, CoUninitialize, CLSCTX_ALL, COINIT_MULTITHREADED,
+    };
+
+    pub fn read_volume() -> (Option<f32>, Option<bool>) {
+        unsafe {
+            let hr = CoInitializeEx(None, COINIT_MULTITHREADED);
+            if hr.is_err() {
+                debug!("Failed to initialize COM to read the volume: {:?}", hr);
+                return (None, None);
+            }
+        }
+        let _com_guard = scopeguard::guard((), |_| unsafe {
+            CoUninitialize();
+        });
+        let endpoint = unsafe {
+            CoCreateInstance::<_, IMMDeviceEnumerator>(&MMDeviceEnumerator, None, CLSCTX_ALL)
+                .and_then(|enumerator| enumerator.GetDefaultAudioEndpoint(eRender, eConsole))
+                .and_then(|device| device.Activate::<IAudioEndpointVolume>(CLSCTX_ALL, None))
+        };
+        let endpoint = match endpoint {
+            Ok(endpoint) => endpoint,
+            Err(e) => {
+                debug!("The default output exposes no endpoint volume: {}", e);
+                return (None, None);
+            }
+        };
+        unsafe {
+            (
+                endpoint.GetMasterVolumeLevelScalar().ok(),
+                endpoint.GetMute().ok().map(|muted| muted.as_bool()),
+            )
+        }
+    }
+}
+
+/// The default output device's volume and mute properties. Many devices
+/// have no main-element volume, only one per channel.
+#[cfg(target_os = "macos")]
+mod platform {
+    use coreaudio_sys::{
+        kAudioDevicePropertyMute, kAudioDevicePropertyVolumeScalar, kAudioHardwarePropertyDefaultOutputDevice,
+        kAudioObjectPropertyScopeGlobal, kAudioObjectPropertyScopeOutput, kAudioObjectSystemObject,
+        AudioObjectGetPropertyData, AudioObjectID, AudioObjectPropertyAddress,
+    };
+    use std::ffi::c_void;
+
+    /// kAudioObjectPropertyElementMain, renamed across SDKs.
+    const ELEMENT_MAIN: u32 = 0;
+
+    fn property<T: Default>(object: AudioObjectID, selector: u32, scope: u32, element: u32) -> Option<T> {
+        let address = AudioObjectPropertyAddress {
+            mSelector: selector,
+            mScope: scope,
+            mElement: element,
+        };
+        let mut value = T::default();
+        let mut size = std::mem::size_of::<T>() as u32;
+        let status = unsafe {
+            AudioObjectGetPropertyData(
+                object,
+                &address,
+                0,
+                std::ptr::null(),
+                &mut size,
+                &mut value as *mut T as *mut c_void,
+            )
+        };
+        (status == 0).then_some(value)
+    }
+
+    pub fn read_volume() -> (Option<f32>, Option<bool>) {
+        let Some(device) = property::<AudioObjectID>(
+            kAudioObjectSystemObject,
+            kAudioHardwarePropertyDefaultOutputDevice,
+            kAudioObjectPropertyScopeGlobal,
+            ELEMENT_MAIN,
+        ) else {
+            return (None, None);
+        };
+        let output_volume = |element| {
+            property::<f32>(device, kAudioDevicePropertyVolumeScalar, kAudioObjectPropertyScopeOutput, element)
+        };
+        let volume = output_volume(ELEMENT_MAIN).or_else(|| {
+            let channels: Vec<f32> = (1..=2).filter_map(output_volume).collect();
+            (!channels.is_empty()).then(|| channels.iter().sum::<f32>() / channels.len() as f32)
+        });
+        let muted = property::<u32>(device, kAudioDevicePropertyMute, kAudioObjectPropertyScopeOutput, ELEMENT_MAIN)
+            .map(|muted| muted != 0);
+        (volume, muted)
+    }
+}
+
+/// The default sink through pactl, which PulseAudio and PipeWire both serve.
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use std::process::{Command, Stdio};
+
+    fn pactl(args: &[&str]) -> Option<String> {
+        let output = Command::new("pactl").args(args).stdin(Stdio::null()).output().ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    pub fn read_volume() -> (Option<f32>, Option<bool>) {
+        (
+            pactl(&["get-sink-volume", "@DEFAULT_SINK@"]).and_then(|out| super::parse_pactl_volume(&out)),
+            pactl(&["get-sink-mute", "@DEFAULT_SINK@"]).and_then(|out| super::parse_pactl_mute(&out)),
+        )
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    pub fn read_volume() -> (Option<f32>, Option<bool>) {
+        (None, None)
+    }
+}
+
@@ -0,0 +1,227 @@
+use crate::audio_capture::AudioCaptureState;
+use crate::process_manager::ServerState;
+use tauri::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tauri::tray::{MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent};
+use tauri::{AppHandle, Manager, Wry};
+use tracing::warn;
+
+pub(crate) const TRAY_ID: &str = "main";
+
+/// Events after which the menu and tooltip are rebuilt from current state.
+const REFRESH_EVENTS: &[&str] = &[
+    "server-started",
+    "server-stopped",
+    "server-crashed",
+    "server-health-changed",
+    "capture-started",
+    "capture-stopped",
+];
+
+/// Menu items whose text or enabled state follows the app state.
+pub struct TrayMenu {
+    toggle_window: MenuItem<Wry>,
+    server_start: MenuItem<Wry>,
+    server_stop: MenuItem<Wry>,
+    server_restart: MenuItem<Wry>,
+    capture_start: MenuItem<Wry>,
+    capture_stop: MenuItem<Wry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TrayServerStatus {
+    Stopped,
+    Starting,
+    Running,
+    External,
+}
+
+impl TrayServerStatus {
+    pub(crate) fn current(app: &AppHandle) -> Self {
+        let state = app.state::<ServerState>();
+        // start_server holds the lock for the whole startup
+        if state.start_lock.try_lock().is_err() {
+            return TrayServerStatus::Starting;
+        }
+        if !*state.managed.lock().unwrap() {
+            return TrayServerStatus::External;
+        }
+        if state.info.lock().unwrap().is_some() {
+            TrayServerStatus::Running
+        } else {
+            TrayServerStatus::Stopped
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            TrayServerStatus::Stopped => "stopped",
+            TrayServerStatus::Starting => "starting",
+            TrayServerStatus::Running => "running",
+            TrayServerStatus::External => "external",
+        }
+    }
+}
+
+/// Create the tray icon and its menu. Called from setup.
+pub fn create(app: &AppHandle) -> tauri::Result<()> {
+    let menu_items = TrayMenu {
+        toggle_window: MenuItem::with_id(app, "toggle_window", "Hide Voicebox", true, None::<&str>)?,
+        server_start: MenuItem::with_id(app, "server_start", "Start Server", true, None::<&str>)?,
+        server_stop: MenuItem::with_id(app, "server_stop", "Stop Server", false, None::<&str>)?,
+        server_restart: MenuItem::with_id(app, "server_restart", "Restart Server", false, None::<&str>)?,
+        capture_start: MenuItem::with_id(
+            app,
+            "capture_start",
+            "Start System Audio Capture",
+            true,
+            None::<&str>,
+        )?,
+        capture_stop: MenuItem::with_id(
+            app,
+            "capture_stop",
+            "Stop System Audio Capture",
+            false,
+            None::<&str>,
+        )?,
+    };
+    let quit = MenuItem::with_id(app, "quit", "Quit Voicebox", true, None::<&str>)?;
+
+    let menu = Menu::with_items(
+        app,
+        &[
+            &menu_items.toggle_window,
+            &PredefinedMenuItem::separator(app)?,
+            &menu_items.server_start,
+            &menu_items.server_stop,
+            &menu_items.server_restart,
+            &PredefinedMenuItem::separator(app)?,
+            &menu_items.capture_start,
+            &menu_items.capture_stop,
+            &PredefinedMenuItem::separator(app)?,
+            &quit,
+        ],
+    )?;
+
+    let mut builder = TrayIconBuilder::with_id(TRAY_ID)
+        .menu(&menu)
+        .tooltip("Voicebox")
+        // Left click toggles the window; the menu stays on right click
+        .show_menu_on_left_click(false)
+        .on_menu_event(handle_menu_event)
+        .on_tray_icon_event(handle_tray_event);
+    if let Some(icon) = app.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+    builder.build(app)?;
+
+    app.manage(menu_items);
+
+    for event in REFRESH_EVENTS {
+        let handle = app.clone();
+        crate::event_bus::listen(app, *event, move |_| refresh(&handle));
+    }
+    refresh(app);
+    Ok(())
+}
+
+/// Whether the tray icon was created, e.g. to offer a way back to a hidden window.
+pub fn exists(app: &AppHandle) -> bool {
+    app.tray_by_id(TRAY_ID).is_some()
+}
+
+/// Bring the menu and tooltip in line with the server and capture state.
+pub fn refresh(app: &AppHandle) {
+    let Some(items) = app.try_state::<TrayMenu>() else {
+        return;
+    };
+    let status = TrayServerStatus::current(app);
+    let capturing = app.state::<AudioCaptureState>().is_capturing();
+    let capture_supported = crate::audio_capture::is_supported();
+
+    let window_visible = app
+        .get_webview_window("main")
+        .and_then(|window| window.is_visible().ok())
+        .unwrap_or(false);
+
+    let _ = items
+        .toggle_window
+        .set_text(if window_visible { "Hide Voicebox" } else { "Show Voicebox" });
+    let _ = items.server_start.set_enabled(status == TrayServerStatus::Stopped);
+    let _ = items.server_stop.set_enabled(status == TrayServerStatus::Running);
+    let _ = items.server_restart.set_enabled(status == TrayServerStatus::Running);
+    let _ = items.capture_start.set_enabled(capture_supported && !capturing);
+    let _ = items.capture_stop.set_enabled(capture_supported && capturing);
+
+    if let Some(tray) = app.tray_by_id(TRAY_ID) {
+        let mut tooltip = format!("Voicebox - server {}", status.label());
+        if let Some(elapsed) = crate::status_indicator::elapsed_label(app) {
+            tooltip.push_str(&format!(", recording {}", elapsed));
+        } else if capturing {
+            tooltip.push_str(", capturing audio");
+        }
+        let _ = tray.set_tooltip(Some(tooltip));
+    }
+}
+
+/// Stop and start the server again, keeping its network exposure.
+pub fn restart_server(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        crate::restart_server_from_tray(&app).await;
+    });
+}
+
+fn toggle_window(app: &AppHandle) {
+    let Some(window) = app.get_webview_window("main") else {
+        return;
+    };
+    if window.is_visible().unwrap_or(false) {
+        let _ = window.hide();
+    } else {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+    refresh(app);
+}
+
+fn handle_tray_event(tray: &TrayIcon<Wry>, event: TrayIconEvent) {
+    if let TrayIconEvent::Click {
+        button: MouseButton::Left,
+        button_state: MouseButtonState::Up,
+        ..
+    } = event
+    {
+        toggle_window(tray.app_handle());
+    }
+}
+
+fn handle_menu_event(app: &AppHandle, event: MenuEvent) {
+    match event.id().as_ref() {
+        "toggle_window" => toggle_window(app),
+        "server_start" => {
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move {
+                crate::start_server_from_tray(&app, false).await;
+            });
+        }
+        "server_stop" => {
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move {
+                crate::stop_server_from_tray(&app).await;
+            });
+        }
+        "server_restart" => restart_server(app),
+        // The captured audio belongs to whichever view started the capture, so
+        // the frontend performs the actual start/stop
+        "capture_start" | "capture_stop" => {
+            let action = if event.id().as_ref() == "capture_start" { "start" } else { "stop" };
+            if let Err(e) = crate::event_bus::emit(&app, "tray-capture-requested", serde_json::json!({ "action": action })) {
+                warn!("Failed to emit tray-capture-requested event: {}", e);
+            }
+        }
+        // Exiting goes through RunEvent::Exit, which honors keep_running_on_close
+        "quit" => app.exit(0),
+        _ => {}
+    }
+}
@@ -0,0 +1,161 @@
+use crate::progress_indicator::ProgressState;
+use crate::settings::UpdateChannel;
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Manager, Url};
+use tauri_plugin_updater::{Update, UpdaterExt};
+use tracing::info;
+
+/// Same manifest as the endpoint in tauri.conf.json.
+const STABLE_ENDPOINT: &str = "https://github.com/jamiepine/voicebox/releases/latest/download/latest.json";
+
+/// Manifest the release workflow attaches to the rolling `beta` pre-release.
+const BETA_ENDPOINT: &str = "https://github.com/jamiepine/voicebox/releases/download/beta/latest.json";
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Progress indicator source for the update download.
+const PROGRESS_SOURCE: &str = "update-download";
+
+fn endpoint(channel: UpdateChannel) -> &'static str {
+    match channel {
+        UpdateChannel::Stable => STABLE_ENDPOINT,
+        UpdateChannel::Beta => BETA_ENDPOINT,
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadProgress {
+    pub bytes_done: u64,
+    /// 0 when the server sends no Content-Length.
+    pub bytes_total: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateInfo {
+    pub available: bool,
+    pub channel: UpdateChannel,
+    pub version: Option<String>,
+    pub notes: Option<String>,
+    /// Unix seconds, if the manifest has a pub_date.
+    pub published_at: Option<i64>,
+}
+
+/// Why a check or install failed. A check that found nothing is not an error,
+/// it returns `available: false`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum UpdateError {
+    /// The update server could not be reached.
+    Offline { message: String },
+    /// The manifest is missing, malformed or has no build for this platform.
+    InvalidManifest { message: String },
+    /// The downloaded package does not match the signing key built into the app.
+    SignatureMismatch { message: String },
+    /// install_update was called without a successful check finding an update.
+    NoPendingUpdate,
+    InstallFailed { message: String },
+    Internal { message: String },
+}
+
+impl std::fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpdateError::Offline { message } => write!(f, "Could not reach the update server: {}", message),
+            UpdateError::InvalidManifest { message } => write!(f, "Invalid update manifest: {}", message),
+            UpdateError::SignatureMismatch { message } => {
+                write!(f, "The update's signature could not be verified: {}", message)
+            }
+            UpdateError::NoPendingUpdate => write!(f, "No update to install; check for updates first"),
+            UpdateError::InstallFailed { message } => write!(f, "Failed to install the update: {}", message),
+            UpdateError::Internal { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for UpdateError {
+    fn from(message: String) -> Self {
+        UpdateError::Internal { message }
+    }
+}
+
+impl From<tauri_plugin_updater::Error> for UpdateError {
+    fn from(e: tauri_plugin_updater::Error) -> Self {
+        use tauri_plugin_updater::Error;
+        let message = e.to_string();
+        match e {
+            Error::Reqwest(_) | Error::Network(_) => UpdateError::Offline { message },
+            Error::ReleaseNotFound | Error::Serialization(_) | Error::Semver(_) | Error::TargetNotFound(_) => {
+                UpdateError::InvalidManifest { message }
+            }
+            Error::Minisign(_) | Error::Base64(_) | Error::SignatureUtf8(_) => UpdateError::SignatureMismatch { message },
+            _ => UpdateError::Internal { message },
+        }
+    }
+}
+
+/// The update found by the last check, kept for install_update.
+pub struct UpdateState {
+    pending: Mutex<Option<Update>>,
+}
+
+impl UpdateState {
+    pub fn new() -> Self {
+        Self {
+            pending: Mutex::new(None),
+        }
+    }
+
+    /// Kept after an install attempt, so a failed download can be retried.
+    pub fn pending(&self) -> Option<Update> {
+        self.pending.lock().unwrap().clone()
+    }
+}
+
+/// Ask `channel`'s manifest whether a newer version exists and keep what it
+/// found for install_update. Nothing is downloaded.
+pub async fn check(app: &AppHandle, channel: UpdateChannel) -> Result<UpdateInfo, UpdateError> {
+    let url = Url::parse(endpoint(channel)).map_err(|e| format!("Bad update endpoint: {}", e))?;
+    let updater = app.updater_builder().endpoints(vec![url])?.timeout(CHECK_TIMEOUT).build()?;
+    let update = updater.check().await?;
+
+    let info = UpdateInfo {
+        available: update.is_some(),
+        channel,
+        version: update.as_ref().map(|u| u.version.clone()),
+        notes: update.as_ref().and_then(|u| u.body.clone()),
+        published_at: update.as_ref().and_then(|u| u.date).map(|d| d.unix_timestamp()),
+    };
+    *app.state::<UpdateState>().pending.lock().unwrap() = update;
+    Ok(info)
+}
+
+/// Download and verify the package, emitting `update-download-progress`.
+pub async fn download(app: &AppHandle, update: &Update) -> Result<Vec<u8>, UpdateError> {
+    let _progress = crate::progress_indicator::clear_on_drop(app, PROGRESS_SOURCE);
+    let progress_app = app.clone();
+    let last_emit = std::cell::Cell::new(None);
+    let mut bytes_done = 0u64;
+    let mut last_percent = None;
+    let bytes = update
+        .download(
+            move |chunk, total| {
+                bytes_done += chunk as u64;
+                let bytes_total = total.unwrap_or(0);
+                let progress = DownloadProgress { bytes_done, bytes_total };
+                let done = bytes_total > 0 && bytes_done >= bytes_total;
+                crate::emit_progress(&progress_app, "update-download-progress", &progress, done, &last_emit);
+                // The dock/taskbar only needs whole percents
+                let percent = (bytes_total > 0).then(|| bytes_done * 100 / bytes_total);
+                if percent.is_some() && percent != last_percent {
+                    last_percent = percent;
+                    let fraction = bytes_done as f32 / bytes_total as f32;
+                    crate::progress_indicator::set(&progress_app, PROGRESS_SOURCE, Some(fraction), ProgressState::Normal);
+                }
+            },
+            || info!("Update downloaded"),
+        )
+        .await?;
+    Ok(bytes)
+}
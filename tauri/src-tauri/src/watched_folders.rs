@@ -0,0 +1,372 @@
+use crate::audio_import::{ImportFormat, ImportTarget};
+use crate::error::VoiceboxError;
+use crate::file_import::ImportFailure;
+use crate::folder_watch::{self, FileStamp, Ledger, Settler, LEDGER_FILE, SETTLE_TIME};
+use crate::settings::{SettingsState, WatchedFolder};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+/// How often pending files are re-checked and folders looked at.
+const TICK: Duration = Duration::from_secs(1);
+
+/// Full rescan of a folder with native events, for events the OS dropped.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Scan of a folder without native events, e.g. on a network drive.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Payload of list_watched_folders and the `watched-folder-missing` and
+/// `watched-folder-restored` events.
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchedFolderStatus {
+    pub path: PathBuf,
+    pub convert: bool,
+    /// False while the folder is missing, e.g. an unplugged drive.
+    pub available: bool,
+    /// False if the OS doesn't report changes in it, so it is polled.
+    pub native_events: bool,
+}
+
+struct Folder {
+    convert: bool,
+    watcher: Option<RecommendedWatcher>,
+    /// None until the worker first looks.
+    available: Option<bool>,
+    last_scan: Option<Instant>,
+}
+
+impl Folder {
+    fn new(convert: bool) -> Self {
+        Self {
+            convert,
+            watcher: None,
+            available: None,
+            last_scan: None,
+        }
+    }
+
+    fn status(&self, path: &Path) -> WatchedFolderStatus {
+        WatchedFolderStatus {
+            path: path.to_path_buf(),
+            convert: self.convert,
+            available: self.available != Some(false),
+            native_events: self.watcher.is_some(),
+        }
+    }
+}
+
+/// Folders whose new audio files are imported as they appear.
+pub struct WatchedFoldersState {
+    folders: Mutex<BTreeMap<PathBuf, Folder>>,
+    /// From the watchers to the worker.
+    events: mpsc::UnboundedSender<PathBuf>,
+    ledger: Mutex<Ledger>,
+    /// None without a data dir, in which case the ledger isn't kept.
+    ledger_path: Option<PathBuf>,
+}
+
+impl WatchedFoldersState {
+    fn save_ledger(&self) {
+        let Some(path) = &self.ledger_path else {
+            return;
+        };
+        if let Err(e) = self.ledger.lock().unwrap().save(path) {
+            warn!("{}", e);
+        }
+    }
+
+    /// The convert flag of the folder `path` is in, if it is still watched.
+    fn convert_for(&self, path: &Path) -> Option<bool> {
+        let dir = path.parent()?;
+        self.folders.lock().unwrap().get(dir).map(|folder| folder.convert)
+    }
+}
+
+/// Native change events for `dir`. None where the OS can't watch it, which
+/// leaves the folder to the periodic scan.
+fn watch(dir: &Path, events: &mpsc::UnboundedSender<PathBuf>) -> Option<RecommendedWatcher> {
+    let events = events.clone();
+    let watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+        let Ok(event) = result else {
+            return;
+        };
+        if event.kind.is_create() || event.kind.is_modify() {
+            for path in event.paths.into_iter().filter(|path| folder_watch::is_candidate(path)) {
+                let _ = events.send(path);
+            }
+        }
+    });
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            warn!("Failed to create a watcher for {}: {}", dir.display(), e);
+            return None;
+        }
+    };
+    match watcher.watch(dir, RecursiveMode::NonRecursive) {
+        Ok(()) => Some(watcher),
+        Err(e) => {
+            info!("Polling {} instead of watching it: {}", dir.display(), e);
+            None
+        }
+    }
+}
+
+/// Load the watched folders and the ledger, and start the worker. Called from
+/// setup once the settings are managed.
+pub fn init(app: &AppHandle, data_dir: Option<&Path>) {
+    let (events, receiver) = mpsc::unbounded_channel();
+    let ledger_path = data_dir.map(|dir| dir.join(LEDGER_FILE));
+    let ledger = ledger_path.as_deref().map(Ledger::load).unwrap_or_default();
+    let folders = app
+        .state::<SettingsState>()
+        .get()
+        .watched_folders
+        .into_iter()
+        .map(|folder| (folder.path, Folder::new(folder.convert)))
+        .collect();
+    app.manage(WatchedFoldersState {
+        folders: Mutex::new(folders),
+        events,
+        ledger: Mutex::new(ledger),
+        ledger_path,
+    });
+    tauri::async_runtime::spawn(run(app.clone(), receiver));
+}
+
+/// Start watching `path`. Files already in it are left alone; only files
+/// that appear afterwards are imported. Adding a watched folder again
+/// updates its convert flag.
+pub fn add(app: &AppHandle, path: PathBuf, convert: bool) -> Result<WatchedFolderStatus, VoiceboxError> {
+    if !path.is_absolute() {
+        return Err(VoiceboxError::invalid_argument(format!(
+            "{} is not an absolute path",
+            path.display()
+        )));
+    }
+    if !path.is_dir() {
+        return Err(VoiceboxError::not_found(format!("{} is not a folder", path.display())));
+    }
+    let path = path.canonicalize()?;
+    let state = app.state::<WatchedFoldersState>();
+
+    let existing = {
+        let mut folders = state.folders.lock().unwrap();
+        folders.get_mut(&path).map(|folder| {
+            folder.convert = convert;
+            folder.status(&path)
+        })
+    };
+    let status = match existing {
+        Some(status) => status,
+        None => {
+            {
+                let mut ledger = state.ledger.lock().unwrap();
+                for file in folder_watch::scan(&path)? {
+                    if let Some(stamp) = FileStamp::of(&file) {
+                        ledger.note_handled(&file, stamp);
+                    }
+                }
+            }
+            state.save_ledger();
+            let folder = Folder {
+                convert,
+                watcher: watch(&path, &state.events),
+                available: Some(true),
+                last_scan: Some(Instant::now()),
+            };
+            let status = folder.status(&path);
+            state.folders.lock().unwrap().insert(path.clone(), folder);
+            info!("Watching {} (native events: {})", path.display(), status.native_events);
+            status
+        }
+    };
+
+    app.state::<SettingsState>().update(|s| {
+        s.watched_folders.retain(|folder| folder.path != path);
+        s.watched_folders.push(WatchedFolder {
+            path: path.clone(),
+            convert,
+        });
+    })?;
+    Ok(status)
+}
+
+/// Stop watching `path`. Files it already imported stay imported.
+pub fn remove(app: &AppHandle, path: &Path) -> Result<(), VoiceboxError> {
+    let state = app.state::<WatchedFoldersState>();
+    // A folder that has since gone missing can't be canonicalized
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if state.folders.lock().unwrap().remove(&path).is_none() {
+        return Err(VoiceboxError::not_found(format!("{} is not watched", path.display())));
+    }
+    app.state::<SettingsState>()
+        .update(|s| s.watched_folders.retain(|folder| folder.path != path))?;
+    state.ledger.lock().unwrap().forget_folder(&path);
+    state.save_ledger();
+    info!("Stopped watching {}", path.display());
+    Ok(())
+}
+
+pub fn list(app: &AppHandle) -> Vec<WatchedFolderStatus> {
+    let state = app.state::<WatchedFoldersState>();
+    let folders = state.folders.lock().unwrap();
+    folders.iter().map(|(path, folder)| folder.status(path)).collect()
+}
+
+/// Collects changed files from the watchers and the scans, waits for each to
+/// stop growing, then imports it.
+async fn run(app: AppHandle, mut events: mpsc::UnboundedReceiver<PathBuf>) {
+    let mut settler = Settler::new(SETTLE_TIME);
+    let mut tick = tokio::time::interval(TICK);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let Some(path) = event else {
+                    return;
+                };
+                observe(&app, &mut settler, path, Instant::now());
+            }
+            _ = tick.tick() => {
+                let now = Instant::now();
+                for path in check_folders(&app, now).await {
+                    observe(&app, &mut settler, path, now);
+                }
+                for path in settler.pending() {
+                    observe(&app, &mut settler, path, now);
+                }
+                for path in settler.take_ready(now) {
+                    import(&app, path).await;
+                }
+            }
+        }
+    }
+}
+
+/// Track `path` until it settles, unless it's gone or was already handled.
+fn observe(app: &AppHandle, settler: &mut Settler, path: PathBuf, now: Instant) {
+    let state = app.state::<WatchedFoldersState>();
+    match FileStamp::of(&path) {
+        Some(stamp) if !state.ledger.lock().unwrap().is_handled(&path, stamp) => settler.observe(path, stamp.len, now),
+        _ => settler.forget(&path),
+    }
+}
+
+/// Note folders that went missing or came back, and scan the ones that are
+/// due. Returns the files found.
+async fn check_folders(app: &AppHandle, now: Instant) -> Vec<PathBuf> {
+    let state = app.state::<WatchedFoldersState>();
+    let mut due = Vec::new();
+    let mut changes = Vec::new();
+    {
+        let mut folders = state.folders.lock().unwrap();
+        for (path, folder) in folders.iter_mut() {
+            let exists = path.is_dir();
+            match (folder.available, exists) {
+                (Some(false), false) | (Some(true), true) => {}
+                (_, false) => {
+                    warn!("Watched folder {} is missing", path.display());
+                    folder.watcher = None;
+                    folder.available = Some(false);
+                    changes.push(("watched-folder-missing", folder.status(path)));
+                }
+                (previous, true) => {
+                    folder.watcher = watch(path, &state.events);
+                    folder.available = Some(true);
+                    if previous == Some(false) {
+                        info!("Watched folder {} is back", path.display());
+                        changes.push(("watched-folder-restored", folder.status(path)));
+                    }
+                }
+            }
+            if !exists {
+                continue;
+            }
+            let interval = if folder.watcher.is_some() { RESCAN_INTERVAL } else { POLL_INTERVAL };
+            if folder.last_scan.is_none_or(|last| now.duration_since(last) >= interval) {
+                folder.last_scan = Some(now);
+                due.push(path.clone());
+            }
+        }
+    }
+    for (topic, status) in changes {
+        if let Err(e) = crate::event_bus::emit(app, topic, &status) {
+            warn!("Failed to emit {} event: {}", topic, e);
+        }
+    }
+
+    let scanned = tokio::task::spawn_blocking(move || {
+        due.iter()
+            .flat_map(|dir| {
+                folder_watch::scan(dir).unwrap_or_else(|e| {
+                    debug!("Skipped a scan: {}", e);
+                    Vec::new()
+                })
+            })
+            .collect()
+    })
+    .await;
+    scanned.unwrap_or_default()
+}
+
+/// Import a settled file, unless one with the same contents was imported
+/// before. Either way it isn't looked at again until it changes.
+async fn import(app: &AppHandle, path: PathBuf) {
+    let state = app.state::<WatchedFoldersState>();
+    let (Some(convert), Some(stamp)) = (state.convert_for(&path), FileStamp::of(&path)) else {
+        return;
+    };
+    let source = path.clone();
+    let hash = match tokio::task::spawn_blocking(move || folder_watch::content_hash(&source)).await {
+        Ok(Ok(hash)) => hash,
+        // Tried again on the next scan
+        Ok(Err(e)) => {
+            warn!("{}", e);
+            return;
+        }
+        Err(e) => {
+            warn!("Hashing task failed: {}", e);
+            return;
+        }
+    };
+
+    let known = state.ledger.lock().unwrap().has_hash(&hash);
+    if known {
+        debug!("Skipped {:?}: already imported", path);
+    } else {
+        let target = convert.then_some(ImportTarget {
+            format: ImportFormat::Wav,
+            sample_rate: None,
+            mono: false,
+        });
+        match crate::import_audio(app, path.clone(), target).await {
+            Ok(imported) => {
+                info!("Imported {:?} from a watched folder as {:?}", path, imported.path);
+                state.ledger.lock().unwrap().note_hash(hash);
+                if let Err(e) = crate::event_bus::emit(app, "audio-imported", &imported) {
+                    warn!("Failed to emit audio-imported event: {}", e);
+                }
+            }
+            Err(error) => {
+                warn!("Failed to import {:?} from a watched folder: {}", path, error);
+                let failure = ImportFailure {
+                    path: path.clone(),
+                    error,
+                };
+                if let Err(e) = crate::event_bus::emit(app, "audio-import-failed", &failure) {
+                    warn!("Failed to emit audio-import-failed event: {}", e);
+                }
+            }
+        }
+    }
+    state.ledger.lock().unwrap().note_handled(&path, stamp);
+    state.save_ledger();
+}
@@ -0,0 +1,162 @@
+use crate::settings::{SettingsState, WindowState};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tauri::{Manager, PhysicalPosition, PhysicalSize, WebviewWindow, Window, WindowEvent};
+use tracing::{info, warn};
+
+/// Moves and resizes arrive continuously while dragging; save once they settle.
+const SAVE_DEBOUNCE_MS: u64 = 500;
+
+/// Smallest part of the title bar that must stay on a monitor for a restored
+/// position to be used.
+const MIN_VISIBLE_PX: i32 = 100;
+
+/// Bumped on every move/resize; a pending save only runs if no newer one came in.
+pub struct WindowStateTracker {
+    generation: AtomicU64,
+}
+
+impl WindowStateTracker {
+    pub fn new() -> Self {
+        Self {
+            generation: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Apply the saved geometry to the main window. Called in setup before the
+/// window is shown.
+pub fn restore(window: &WebviewWindow) {
+    let settings = window.state::<SettingsState>().get();
+
+    #[cfg(debug_assertions)]
+    if settings.devtools_open {
+        window.open_devtools();
+    }
+
+    let Some(saved) = settings.window_state else {
+        return;
+    };
+    let monitors = window.available_monitors().unwrap_or_default();
+
+    // Prefer the monitor it was on; otherwise any monitor that still shows the title bar
+    let on_saved_monitor = saved.monitor.as_ref().and_then(|name| {
+        monitors
+            .iter()
+            .find(|m| m.name() == Some(name))
+            .filter(|m| is_visible_on(&saved, m.position(), m.size()))
+    });
+    let target = on_saved_monitor.or_else(|| {
+        monitors
+            .iter()
+            .find(|m| is_visible_on(&saved, m.position(), m.size()))
+    });
+
+    match target {
+        Some(monitor) => {
+            // The monitor may have shrunk since (e.g. a resolution change)
+            let width = saved.width.min(monitor.size().width);
+            let height = saved.height.min(monitor.size().height);
+            let _ = window.set_size(PhysicalSize::new(width, height));
+            let _ = window.set_position(PhysicalPosition::new(saved.x, saved.y));
+        }
+        None => {
+            // Saved on a display that is gone: keep the size, let the window center
+            info!("Saved window position is off-screen, centering the window");
+            if let Ok(Some(monitor)) = window.primary_monitor() {
+                let width = saved.width.min(monitor.size().width);
+                let height = saved.height.min(monitor.size().height);
+                let _ = window.set_size(PhysicalSize::new(width, height));
+            }
+            let _ = window.center();
+        }
+    }
+
+    if saved.maximized {
+        let _ = window.maximize();
+    }
+}
+
+fn is_visible_on(saved: &WindowState, origin: &PhysicalPosition<i32>, size: &PhysicalSize<u32>) -> bool {
+    let right = origin.x + size.width as i32;
+    let bottom = origin.y + size.height as i32;
+    saved.x + MIN_VISIBLE_PX <= right
+        && saved.x + saved.width as i32 - MIN_VISIBLE_PX >= origin.x
+        && saved.y >= origin.y
+        && saved.y + MIN_VISIBLE_PX <= bottom
+}
+
+/// Hook for the builder's window event handler.
+pub fn on_window_event(window: &Window, event: &WindowEvent) {
+    if window.label() != "main" {
+        return;
+    }
+    match event {
+        WindowEvent::Moved(_) | WindowEvent::Resized(_) => {
+            let tracker = window.state::<WindowStateTracker>();
+            let generation = tracker.generation.fetch_add(1, Ordering::SeqCst) + 1;
+            let window = window.clone();
+            tauri::async_runtime::spawn(async move {
+                tokio::time::sleep(tokio::time::Duration::from_millis(SAVE_DEBOUNCE_MS)).await;
+                if window.state::<WindowStateTracker>().generation.load(Ordering::SeqCst) == generation {
+                    save(&window);
+                }
+            });
+        }
+        WindowEvent::CloseRequested { .. } => save(window),
+        _ => {}
+    }
+}
+
+fn save(window: &Window) {
+    let settings = window.state::<SettingsState>();
+    let previous = settings.get().window_state;
+
+    // Minimized geometry is meaningless, and while maximized we keep the
+    // normal bounds so un-maximizing after a restart lands somewhere sensible
+    if window.is_minimized().unwrap_or(false) {
+        return;
+    }
+    let maximized = window.is_maximized().unwrap_or(false);
+    let state = match (&previous, maximized) {
+        (Some(previous), true) => WindowState {
+            maximized: true,
+            ..previous.clone()
+        },
+        _ => {
+            let (Ok(position), Ok(size)) = (window.outer_position(), window.inner_size()) else {
+                return;
+            };
+            WindowState {
+                x: position.x,
+                y: position.y,
+                width: size.width,
+                height: size.height,
+                maximized,
+                monitor: window
+                    .current_monitor()
+                    .ok()
+                    .flatten()
+                    .and_then(|m| m.name().cloned()),
+            }
+        }
+    };
+
+    #[cfg(debug_assertions)]
+    let devtools_open = window
+        .app_handle()
+        .get_webview_window(window.label())
+        .map(|w| w.is_devtools_open())
+        .unwrap_or(false);
+    #[cfg(not(debug_assertions))]
+    let devtools_open = false;
+
+    if previous.as_ref() == Some(&state) && settings.get().devtools_open == devtools_open {
+        return;
+    }
+    if let Err(e) = settings.update(|s| {
+        s.window_state = Some(state);
+        s.devtools_open = devtools_open;
+    }) {
+        warn!("Failed to save window state: {}", e);
+    }
+}
@@ -1,59 +1,1330 @@
-// NOTE: This test requires system audio to be playing during execution.
-// To run this test successfully:
-//   1. Start playing audio (music, video, etc.)
-//   2. Run: cargo test --test audio_capture_test -- --nocapture
-//   3. The test will capture audio for 5 seconds and verify the output
+// Runs the shared capture pipeline, from start to the saved WAV, against the
+// synthetic backend, so no audio device or playing audio is needed:
+//   cargo test --test audio_capture_test
 
-use voicebox::audio_capture::{AudioCaptureState, start_capture, stop_capture};
 use base64::Engine;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use voicebox::audio_capture::{
+    cancel_capture, save_capture_to_file, segment_capture, start_capture, start_capture_with, stop_capture,
+    stop_capture_as, AudioCaptureState, AutoPause, AutoStopReason, CapturableApplication, CaptureBackend,
+    CaptureChunk, CaptureLifecycle, CaptureOptions, CaptureReadiness, CaptureRequest, CaptureSummary, CaptureTarget,
+    FrameSink, NormalizeTarget, SaveAs, SaveToFile, SyntheticBackend, SyntheticEvent, SyntheticPattern, TrimSilence,
+    FINALIZED_CHUNK_BYTES, NORMALIZE_PEAK,
+};
+use voicebox::audio_util::{integrated_loudness, VadOptions, WavSampleFormat, CLIP_RUN_FRAMES};
+use voicebox::error::VoiceboxError;
+use voicebox::recording_metadata::{PauseInterval, Processing, SourceFormat};
+
+const TONE: SyntheticPattern = SyntheticPattern::Sine {
+    frequency: 440.0,
+    amplitude: 0.5,
+};
+
+fn synthetic(backend: SyntheticBackend) -> AudioCaptureState {
+    AudioCaptureState::with_backend(Arc::new(backend))
+}
+
+fn decode(base64_wav: &str) -> (hound::WavSpec, Vec<i16>) {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64_wav)
+        .expect("capture is valid base64");
+    let mut reader = hound::WavReader::new(std::io::Cursor::new(bytes)).expect("capture is a WAV");
+    let samples = reader.samples::<i16>().map(Result::unwrap).collect();
+    (reader.spec(), samples)
+}
+
+fn peak(samples: &[i16]) -> f32 {
+    samples.iter().map(|s| (*s as f32 / 32767.0).abs()).fold(0.0, f32::max)
+}
+
+#[tokio::test]
+async fn test_capture_produces_wav() {
+    let state = synthetic(SyntheticBackend::new(TONE));
+    start_capture(&state, 10).await.unwrap();
+    assert!(state.is_capturing());
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let (spec, samples) = decode(&stop_capture(&state).await.unwrap());
+    assert!(!state.is_capturing());
+    assert_eq!((spec.sample_rate, spec.channels), (48000, 2));
+    let secs = samples.len() as f32 / 2.0 / 48000.0;
+    assert!((0.2..1.0).contains(&secs), "captured {}s", secs);
+    assert!((peak(&samples) - 0.5).abs() < 0.01);
+}
+
+#[tokio::test]
+async fn test_tone_comes_back_at_its_frequency() {
+    let state = synthetic(SyntheticBackend::new(TONE));
+    let mut options = CaptureOptions::new(10);
+    options.mono = true;
+    start_capture_with(&state, options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let (spec, samples) = decode(&stop_capture(&state).await.unwrap());
+    assert_eq!((spec.sample_rate, spec.channels), (48000, 1));
+    let secs = samples.len() as f32 / 48000.0;
+    assert!((0.45..0.8).contains(&secs), "captured {}s", secs);
+    let rising = samples.windows(2).filter(|pair| pair[0] < 0 && pair[1] >= 0).count();
+    let frequency = rising as f32 / secs;
+    assert!((frequency - 440.0).abs() < 5.0, "{} Hz", frequency);
+}
+
+#[tokio::test]
+async fn test_replays_a_fixture() {
+    // A quarter second of stereo sawtooth, the right channel inverted
+    let fixture: Vec<f32> = (0..12000)
+        .flat_map(|n| {
+            let s = (n % 100) as f32 / 200.0;
+            [s, -s]
+        })
+        .collect();
+    let state = synthetic(SyntheticBackend::replaying(fixture.clone(), 48000, 2));
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(400)).await;
+
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    assert!(samples.len() > fixture.len());
+    for (captured, expected) in samples.iter().zip(&fixture) {
+        assert!((*captured as f32 / 32767.0 - expected).abs() < 1.0 / 16384.0);
+    }
+    assert!(samples[fixture.len()..].iter().all(|s| *s == 0));
+}
+
+#[tokio::test]
+async fn test_overlapping_capture_is_refused() {
+    let state = synthetic(SyntheticBackend::new(TONE));
+    start_capture(&state, 10).await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let error = start_capture(&state, 10).await.unwrap_err();
+    assert_eq!(error.code(), "busy");
+    assert!(state.is_capturing(), "the first capture keeps running");
+
+    // One unbroken tone: a second stream feeding the same buffer would
+    // interleave with the first and jump between them
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    let secs = samples.len() as f32 / 2.0 / 48000.0;
+    assert!((0.15..0.5).contains(&secs), "captured {}s", secs);
+    let largest_step = samples
+        .iter()
+        .step_by(2)
+        .zip(samples.iter().step_by(2).skip(1))
+        .map(|(a, b)| (*a as i32 - *b as i32).abs())
+        .max()
+        .unwrap();
+    assert!(largest_step < 1200, "the tone jumps by {}", largest_step);
+
+    // Starts at the same time let exactly one through
+    let (first, second) = tokio::join!(start_capture(&state, 10), start_capture(&state, 10));
+    assert!(first.is_ok() != second.is_ok());
+    assert_eq!(first.err().or(second.err()).unwrap().code(), "busy");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(stop_capture(&state).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_silence_stops_capture() {
+    let backend = SyntheticBackend::new(TONE).then(Duration::from_millis(200), SyntheticPattern::Silence);
+    let state = synthetic(backend);
+    let mut options = CaptureOptions::new(10);
+    options.silence_stop_secs = Some(0.3);
+    start_capture_with(&state, options).await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(900)).await;
+    assert!(!state.is_capturing());
+    assert_eq!(state.auto_stop(), Some(AutoStopReason::Silence));
+
+    // The tone and the silence that ended it are kept
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    let secs = samples.len() as f32 / 2.0 / 48000.0;
+    assert!((0.45..0.7).contains(&secs), "captured {}s", secs);
+}
+
+#[tokio::test]
+async fn test_silence_before_the_source_starts_is_waited_out() {
+    let backend = SyntheticBackend::new(SyntheticPattern::Silence)
+        .then(Duration::from_millis(500), TONE)
+        .then(Duration::from_millis(700), SyntheticPattern::Silence);
+    let state = synthetic(backend);
+    let mut options = CaptureOptions::new(10);
+    options.silence_stop_secs = Some(0.2);
+    start_capture_with(&state, options.clone()).await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert!(state.is_capturing());
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(state.auto_stop(), Some(AutoStopReason::Silence));
+    assert!(stop_capture(&state).await.is_ok());
+
+    options.silence_stop_secs = Some(0.0);
+    let error = start_capture_with(&state, options).await.unwrap_err();
+    assert_eq!(error.code(), "invalid_argument");
+}
+
+#[tokio::test]
+async fn test_noise_does_not_count_as_silence() {
+    let state = synthetic(SyntheticBackend::new(SyntheticPattern::Noise { amplitude: 0.05 }));
+    let mut options = CaptureOptions::new(10);
+    options.silence_stop_secs = Some(0.1);
+    start_capture_with(&state, options).await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert!(state.is_capturing());
+    assert!(stop_capture(&state).await.is_ok());
+    assert_eq!(state.auto_stop(), None);
+}
+
+#[tokio::test]
+async fn test_limit_stops_capture() {
+    let state = synthetic(SyntheticBackend::new(TONE));
+    start_capture(&state, 1).await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(1300)).await;
+    assert!(!state.is_capturing());
+    assert_eq!(state.auto_stop(), Some(AutoStopReason::Limit));
+    assert!(stop_capture(&state).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_status_follows_the_capture() {
+    let state = synthetic(SyntheticBackend::new(TONE));
+    assert_eq!(state.status().state, CaptureLifecycle::Idle);
+    start_capture(&state, 1).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let status = state.status();
+    assert_eq!(status.state, CaptureLifecycle::Recording);
+    assert!(status.capturing);
+    assert!((0.4..0.8).contains(&status.elapsed_secs), "{}s", status.elapsed_secs);
+    assert!(status.buffered_samples >= 48000 * 2 * 3 / 10, "{} samples", status.buffered_samples);
+    assert_eq!((status.sample_rate, status.channels), (48000, 2));
+    assert_eq!(status.last_error, None);
+
+    // Over its limit, it waits to be collected, its clock stopped at the limit
+    tokio::time::sleep(Duration::from_millis(800)).await;
+    let status = state.status();
+    assert_eq!(status.state, CaptureLifecycle::Stopping);
+    assert!(!status.capturing);
+    assert!((1.0..1.2).contains(&status.elapsed_secs), "{}s", status.elapsed_secs);
+    stop_capture(&state).await.unwrap();
+    assert_eq!(state.status().state, CaptureLifecycle::Idle);
+}
+
+#[tokio::test]
+async fn test_mono_downmix() {
+    let state = synthetic(SyntheticBackend::new(TONE).with_format(44100, 2));
+    let mut options = CaptureOptions::new(10);
+    options.mono = true;
+    start_capture_with(&state, options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let (spec, samples) = decode(&stop_capture(&state).await.unwrap());
+    assert_eq!((spec.sample_rate, spec.channels), (44100, 1));
+    // Both channels carry the same tone, so the average is that tone
+    assert!((peak(&samples) - 0.5).abs() < 0.01);
+}
+
+#[tokio::test]
+async fn test_normalization() {
+    let quiet = SyntheticPattern::Sine {
+        frequency: 440.0,
+        amplitude: 0.1,
+    };
+    let state = synthetic(SyntheticBackend::new(quiet));
+    let mut options = CaptureOptions::new(10);
+    options.normalize = true;
+    start_capture_with(&state, options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    assert!((peak(&samples) - NORMALIZE_PEAK).abs() < 0.01);
+}
+
+#[tokio::test]
+async fn test_normalization_leaves_silence_alone() {
+    let state = synthetic(SyntheticBackend::new(SyntheticPattern::Silence));
+    let mut options = CaptureOptions::new(10);
+    options.normalize = true;
+    start_capture_with(&state, options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    assert!(samples.iter().all(|s| *s == 0));
+}
 
 #[tokio::test]
-async fn test_system_audio_capture() {
-    // Create AudioCaptureState
-    let state = AudioCaptureState::new();
+async fn test_device_change_fails_capture() {
+    let backend = SyntheticBackend::new(TONE).with_event(
+        Duration::from_millis(100),
+        SyntheticEvent::DeviceChange {
+            sample_rate: 44100,
+            channels: 2,
+        },
+    );
+    let state = synthetic(backend);
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
 
-    println!("Starting system audio capture with 5 second max duration...");
+    assert!(!state.is_capturing());
+    assert_eq!(state.lifecycle(), CaptureLifecycle::Stopping);
+    assert!(state.status().last_error.unwrap().contains("44100 Hz"));
+    let error = stop_capture(&state).await.unwrap_err();
+    assert_eq!(error.code(), "capture");
+    assert!(error.message().contains("44100 Hz"));
+    assert_eq!(state.lifecycle(), CaptureLifecycle::Idle);
+}
+
+#[tokio::test]
+async fn test_dropout_leaves_a_gap() {
+    let backend = SyntheticBackend::new(TONE).with_event(
+        Duration::from_millis(50),
+        SyntheticEvent::Dropout {
+            duration: Duration::from_millis(200),
+        },
+    );
+    let state = synthetic(backend);
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(400)).await;
+
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    let secs = samples.len() as f32 / 2.0 / 48000.0;
+    assert!(secs < 0.35, "captured {}s despite the dropout", secs);
+}
+
+#[tokio::test]
+async fn test_device_switch_fills_the_gap_with_silence() {
+    let backend = SyntheticBackend::new(TONE).with_event(
+        Duration::from_millis(100),
+        SyntheticEvent::DeviceSwitch {
+            gap: Duration::from_millis(200),
+        },
+    );
+    let state = synthetic(backend);
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(state.is_capturing());
+    assert_eq!(state.health().device_switches, 1);
+
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    let secs = samples.len() as f32 / 2.0 / 48000.0;
+    assert!(secs > 0.45, "captured {}s, the gap wasn't filled", secs);
+    // Stereo, so 150 to 250 ms in
+    assert_eq!(peak(&samples[14400..24000]), 0.0);
+    assert!(peak(&samples[..9600]) > 0.4);
+    assert!(peak(&samples[31200..]) > 0.4);
+    assert_eq!(state.metadata().health.device_switches, 1);
+}
+
+#[tokio::test]
+async fn test_kept_recordings_carry_metadata() {
+    let backend = SyntheticBackend::new(TONE).with_event(
+        Duration::from_millis(50),
+        SyntheticEvent::Dropout {
+            duration: Duration::from_millis(50),
+        },
+    );
+    let state = synthetic(backend);
+    let mut options = CaptureOptions::new(10);
+    options.mono = true;
+    start_capture_with(&state, options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let audio = stop_capture(&state).await.unwrap();
+    state.keep_recording(7, &audio);
+
+    let metadata = state.recording_metadata(7).unwrap();
+    assert_eq!(metadata.backend.as_deref(), Some("synthetic"));
+    assert_eq!((metadata.sample_rate, metadata.channels), (48000, 1));
+    assert_eq!(metadata.dropouts, 1);
+    assert_eq!(metadata.processing, [Processing::Mono]);
+    assert_eq!(
+        metadata.source_format,
+        Some(SourceFormat {
+            sample_rate: 48000,
+            channels: 2,
+            planar: false
+        })
+    );
+    assert!(metadata.started_at.is_some());
+    let (_, samples) = decode(&audio);
+    assert_eq!(metadata.duration_ms, samples.len() as u64 * 1000 / 48000);
+    assert!(state.recording_metadata(8).is_none());
+}
+
+#[tokio::test]
+async fn test_saves_the_format_the_backend_delivers() {
+    let mut backend = SyntheticBackend::new(TONE);
+    backend.sample_rate = 44100;
+    backend.channels = 1;
+    let state = synthetic(backend);
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let (spec, samples) = decode(&stop_capture(&state).await.unwrap());
+    assert_eq!((spec.sample_rate, spec.channels), (44100, 1));
+    let secs = samples.len() as f32 / 44100.0;
+    assert!((0.15..0.3).contains(&secs), "captured {}s", secs);
+    let source = state.metadata().source_format.unwrap();
+    assert_eq!((source.sample_rate, source.channels), (44100, 1));
+}
+
+#[tokio::test]
+async fn test_level_meter_sees_clipping() {
+    let backend = SyntheticBackend::new(SyntheticPattern::Silence).with_event(Duration::ZERO, SyntheticEvent::Clip);
+    let state = synthetic(backend);
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let (_, peak) = state.level(1000);
+    assert_eq!(peak, 1.0);
+    assert_eq!(state.take_level().1, 1.0);
+    assert!(stop_capture(&state).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_running_level_covers_what_came_since_it_was_taken() {
+    let state = synthetic(SyntheticBackend::new(TONE).then(Duration::from_millis(300), SyntheticPattern::Silence));
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let (rms, peak) = state.take_level();
+    assert!((peak - 0.5).abs() < 0.01, "peak {}", peak);
+    // A sine's RMS is its amplitude over the square root of 2
+    assert!((rms - 0.5 / 2f32.sqrt()).abs() < 0.01, "rms {}", rms);
+
+    // Only the silence since then, not the tone before it
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    state.take_level();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(state.take_level(), (0.0, 0.0));
+    assert!(stop_capture(&state).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_stale_limit_leaves_the_next_capture_alone() {
+    let state = synthetic(SyntheticBackend::new(TONE));
+    start_capture(&state, 2).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert!(stop_capture(&state).await.is_ok());
+
+    // The first capture's limit passes while the second is running
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert!(state.is_capturing());
+    assert_eq!(state.auto_stop(), None);
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    assert!(samples.len() >= 2 * 48000 * 2, "{} samples", samples.len());
+}
+
+/// Keeps every sink it is given, so a test can call into a finished capture.
+#[derive(Default)]
+struct HeldSinks(Mutex<Vec<FrameSink>>);
 
-    // Start capture with 5 second max duration
-    let result = start_capture(&state, 5).await;
+impl CaptureBackend for HeldSinks {
+    fn name(&self) -> &'static str {
+        "held"
+    }
+
+    fn start(&self, sink: FrameSink, _stop: Arc<AtomicBool>) -> Result<(), VoiceboxError> {
+        sink.set_format(48000, 1);
+        self.0.lock().unwrap().push(sink);
+        Ok(())
+    }
 
-    if let Err(e) = result {
-        panic!("Failed to start capture: {}", e);
+    fn drain_time(&self) -> Duration {
+        Duration::ZERO
     }
+}
 
-    println!("Capture started, waiting 5 seconds...");
+#[tokio::test]
+async fn test_stale_sink_leaves_the_next_capture_alone() {
+    let backend = Arc::new(HeldSinks::default());
+    let state = AudioCaptureState::with_backend(backend.clone());
+    start_capture(&state, 10).await.unwrap();
+    let stale = backend.0.lock().unwrap()[0].clone();
+    stale.push(&[0.5; 480]);
+    stop_capture(&state).await.unwrap();
+    start_capture(&state, 10).await.unwrap();
 
-    // Wait 5 seconds for capture to complete
-    tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+    let current = backend.0.lock().unwrap()[1].clone();
+    stale.push(&[1.0; 480]);
+    stale.set_format(44100, 2);
+    stale.fail("the first capture's device went away");
+    assert!(state.is_capturing());
 
-    println!("Stopping capture...");
+    current.push(&[0.5; 480]);
+    let (spec, samples) = decode(&stop_capture(&state).await.unwrap());
+    assert_eq!((spec.sample_rate, spec.channels), (48000, 1));
+    assert_eq!(samples.len(), 480);
+}
 
-    // Stop capture and get the result
-    let audio_data = stop_capture(&state).await;
+#[tokio::test]
+async fn test_pre_roll_without_a_rolling_buffer() {
+    let state = synthetic(SyntheticBackend::new(TONE));
+    let mut options = CaptureOptions::new(10);
+    options.pre_roll_ms = 1000;
+    options.require_pre_roll = true;
+    let error = start_capture_with(&state, options.clone()).await.unwrap_err();
+    assert_eq!(error.code(), "unsupported");
+    assert!(!state.is_capturing());
 
-    match audio_data {
-        Ok(base64_wav) => {
-            println!("Capture stopped successfully");
+    // Not required: the capture starts without it, and says so
+    options.require_pre_roll = false;
+    start_capture_with(&state, options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    let metadata = state.metadata();
+    let pre_roll = metadata.pre_roll.unwrap();
+    assert_eq!((pre_roll.requested_ms, pre_roll.included_ms), (1000, 0));
+    assert_eq!(metadata.duration_ms, samples.len() as u64 / 2 * 1000 / 48000);
+    assert!(metadata.duration_ms < 1000);
+}
 
-            // Validate the returned base64 WAV data
-            println!("Validating base64 WAV data...");
+#[tokio::test]
+async fn test_pre_roll_carries_on_the_rolling_capture() {
+    // A ramp, so a missing or repeated sample at the splice shows as a step
+    let ramp: Vec<f32> = (0..40000).map(|n| n as f32 / 40000.0).collect();
+    let state = synthetic(SyntheticBackend::replaying(ramp, 8000, 1));
+    let mut rolling = CaptureOptions::new(30);
+    rolling.max_buffer_secs = Some(2);
+    rolling.rolling = true;
+    start_capture_with(&state, rolling).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1200)).await;
+
+    // Without pre-roll it's just another capture, refused while one runs
+    let error = start_capture_with(&state, CaptureOptions::new(10)).await.unwrap_err();
+    assert_eq!(error.code(), "busy");
+
+    let mut options = CaptureOptions::new(10);
+    options.pre_roll_ms = 500;
+    options.require_pre_roll = true;
+    let before = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    start_capture_with(&state, options).await.unwrap();
+    assert!(state.is_capturing());
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (spec, saved) = decode(&stop_capture(&state).await.unwrap());
+    assert_eq!((spec.sample_rate, spec.channels), (8000, 1));
+
+    let metadata = state.metadata();
+    let pre_roll = metadata.pre_roll.unwrap();
+    assert_eq!((pre_roll.requested_ms, pre_roll.included_ms), (500, 500));
+    assert_eq!(metadata.duration_ms, saved.len() as u64 * 1000 / 8000);
+    assert!(metadata.duration_ms >= 800, "{} ms", metadata.duration_ms);
+    assert!(metadata.started_at.unwrap() <= before);
+    assert!(state.status().elapsed_secs >= 0.8);
+
+    let (samples, _, _) = state.captured_audio();
+    assert!(samples[0] > 0.1, "the pre-roll is from well into the ramp");
+    for pair in samples.windows(2) {
+        assert!((pair[1] - pair[0] - 1.0 / 40000.0).abs() < 1e-6, "{} then {}", pair[0], pair[1]);
+    }
+}
 
-            // Decode base64 to bytes
-            let decoded_bytes = base64::engine::general_purpose::STANDARD
-                .decode(&base64_wav)
-                .expect("Failed to decode base64 data");
+/// Fails to start, but only after a while, like a device that won't open.
+struct SlowToFail;
 
-            // Verify bytes array is not empty
-            assert!(!decoded_bytes.is_empty(), "Decoded bytes array is empty");
+impl CaptureBackend for SlowToFail {
+    fn name(&self) -> &'static str {
+        "slow-to-fail"
+    }
 
-            // Confirm data has content (length > 0)
-            println!("WAV data length: {} bytes", decoded_bytes.len());
-            assert!(decoded_bytes.len() > 0, "WAV data has no content");
+    fn start(&self, _sink: FrameSink, _stop: Arc<AtomicBool>) -> Result<(), VoiceboxError> {
+        std::thread::sleep(Duration::from_millis(300));
+        Err(VoiceboxError::capture("Failed to initialize audio client"))
+    }
+}
 
-            println!("✓ Test passed: Audio capture produced valid WAV data");
+#[tokio::test]
+async fn test_a_backend_that_fails_to_start_fails_the_start() {
+    let state = AudioCaptureState::with_backend(Arc::new(SlowToFail));
+    // The test runtime has one thread, so this only ticks if the start
+    // waits off it
+    let ticks = Arc::new(AtomicU32::new(0));
+    let ticker = tokio::spawn({
+        let ticks = ticks.clone();
+        async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                ticks.fetch_add(1, Ordering::Relaxed);
+            }
         }
-        Err(e) => {
-            panic!("Failed to stop capture or get audio data: {}", e);
+    });
+
+    let error = start_capture(&state, 10).await.unwrap_err();
+    ticker.abort();
+    assert_eq!(error.code(), "capture");
+    assert!(error.message().contains("initialize"));
+    assert!(ticks.load(Ordering::Relaxed) >= 3);
+    assert!(!state.is_capturing());
+    assert_eq!(state.lifecycle(), CaptureLifecycle::Idle);
+}
+
+/// Can't record until the user allows it, like ScreenCaptureKit without
+/// Screen Recording.
+struct NeedsPermission;
+
+impl CaptureBackend for NeedsPermission {
+    fn name(&self) -> &'static str {
+        "needs-permission"
+    }
+
+    fn start(&self, _sink: FrameSink, _stop: Arc<AtomicBool>) -> Result<(), VoiceboxError> {
+        Err(VoiceboxError::permission("screen_recording", "Screen Recording permission required"))
+    }
+
+    fn readiness(&self) -> CaptureReadiness {
+        CaptureReadiness {
+            requires_permission: true,
+            permission_granted: Some(false),
+            ..CaptureReadiness::unavailable("Screen Recording permission required")
         }
     }
 }
+
+#[test]
+fn test_capabilities_say_what_stands_in_the_way() {
+    let capabilities = synthetic(SyntheticBackend::new(TONE)).capabilities();
+    assert!(capabilities.system_audio);
+    assert_eq!(capabilities.reason, None);
+    assert!(capabilities.min_os_met);
+    assert!(!capabilities.requires_permission);
+
+    let capabilities = AudioCaptureState::with_backend(Arc::new(NeedsPermission)).capabilities();
+    assert!(!capabilities.system_audio);
+    assert_eq!(capabilities.reason.as_deref(), Some("Screen Recording permission required"));
+    assert!(capabilities.min_os_met);
+    assert_eq!((capabilities.requires_permission, capabilities.permission_granted), (true, Some(false)));
+}
+
+#[tokio::test]
+async fn test_health_counters_follow_the_capture() {
+    let backend = Arc::new(HeldSinks::default());
+    let state = AudioCaptureState::with_backend(backend.clone());
+    start_capture(&state, 10).await.unwrap();
+    let first = backend.0.lock().unwrap()[0].clone();
+    first.lag(480);
+    first.lag(120);
+    first.late_wakeup();
+    first.callback_gap();
+    first.callback_gap();
+    first.push(&[0.5; 480]);
+
+    let status = state.status();
+    assert!(status.capturing);
+    assert_eq!(status.backend, "held");
+    assert_eq!(
+        (status.health.max_lag_frames, status.health.late_wakeups, status.health.callback_gaps),
+        (480, 1, 2)
+    );
+    stop_capture(&state).await.unwrap();
+    assert_eq!(state.metadata().health, status.health);
+
+    // A new capture starts from zero, and the old sink can't add to it
+    start_capture(&state, 10).await.unwrap();
+    first.lag(9600);
+    first.late_wakeup();
+    assert_eq!(state.health(), Default::default());
+    backend.0.lock().unwrap()[1].push(&[0.5; 480]);
+    stop_capture(&state).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_auto_pause_leaves_out_silence() {
+    let backend = Arc::new(HeldSinks::default());
+    let state = AudioCaptureState::with_backend(backend.clone());
+    let mut options = CaptureOptions::new(10);
+    options.auto_pause_on_source_silence = Some(AutoPause {
+        threshold_db: -40.0,
+        after_ms: 100,
+    });
+    start_capture_with(&state, options.clone()).await.unwrap();
+    let sink = backend.0.lock().unwrap()[0].clone();
+    // In 10 ms chunks, 48 samples to the millisecond
+    let push = |sink: &FrameSink, level: f32, ms: usize| {
+        for _ in 0..ms / 10 {
+            sink.push(&[level; 480]);
+        }
+    };
+    push(&sink, 0.5, 200);
+    push(&sink, 0.001, 100);
+    assert!(state.status().paused);
+    push(&sink, 0.001, 1000);
+    assert!(state.is_paused());
+    push(&sink, 0.5, 100);
+    assert!(!state.is_paused());
+
+    // The tone, the silence before the pause, the lookahead, the tone again
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    assert_eq!(samples.len(), 48 * 650);
+    assert!(samples[48 * 300..48 * 550].iter().all(|s| s.abs() < 100));
+    assert!(samples[48 * 550..].iter().any(|s| s.abs() > 10000));
+    let metadata = state.metadata();
+    assert_eq!(metadata.duration_ms, 650);
+    assert_eq!(
+        metadata.pauses,
+        [PauseInterval {
+            at_ms: 300,
+            duration_ms: 750
+        }]
+    );
+
+    // Stopped while paused: the gap at the end is recorded too
+    start_capture_with(&state, options.clone()).await.unwrap();
+    let sink = backend.0.lock().unwrap()[1].clone();
+    push(&sink, 0.5, 100);
+    push(&sink, 0.0, 300);
+    stop_capture(&state).await.unwrap();
+    assert!(!state.is_paused());
+    assert_eq!(
+        state.metadata().pauses,
+        [PauseInterval {
+            at_ms: 200,
+            duration_ms: 200
+        }]
+    );
+
+    options.auto_pause_on_source_silence = Some(AutoPause {
+        threshold_db: 6.0,
+        after_ms: 100,
+    });
+    assert_eq!(start_capture_with(&state, options).await.unwrap_err().code(), "invalid_argument");
+}
+
+#[tokio::test]
+async fn test_records_a_chosen_device() {
+    let speakers = CaptureTarget {
+        id: "speakers".to_string(),
+        name: "Speakers".to_string(),
+        is_default: true,
+    };
+    let state = synthetic(SyntheticBackend::new(TONE).with_targets(vec![speakers.clone()]));
+    assert_eq!(state.targets().unwrap(), [speakers]);
+    let mut options = CaptureOptions::new(10);
+    options.device_id = Some("speakers".to_string());
+    start_capture_with(&state, options.clone()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    assert!(!samples.is_empty());
+
+    options.device_id = Some("headphones".to_string());
+    let error = start_capture_with(&state, options.clone()).await.unwrap_err();
+    assert!(matches!(error, VoiceboxError::NotFound { .. }), "{:?}", error);
+    assert!(!state.is_capturing());
+
+    // A backend that only records the default output can't honour one
+    let state = synthetic(SyntheticBackend::new(TONE));
+    let error = start_capture_with(&state, options).await.unwrap_err();
+    assert_eq!(error.code(), "unsupported");
+    assert!(!state.is_capturing());
+}
+
+#[tokio::test]
+async fn test_records_one_application_until_it_quits() {
+    let podcast = CapturableApplication {
+        pid: 4242,
+        name: "Browser".to_string(),
+        bundle_id: Some("org.example.browser".to_string()),
+    };
+    let backend = SyntheticBackend::new(TONE)
+        .with_applications(vec![podcast.clone()])
+        .with_event(Duration::from_millis(200), SyntheticEvent::ApplicationExit);
+    let state = synthetic(backend);
+    assert_eq!(state.applications().unwrap(), [podcast]);
+    assert!(state.capabilities().application_capture);
+    let mut options = CaptureOptions::new(60);
+    options.application_pid = Some(4242);
+    start_capture_with(&state, options.clone()).await.unwrap();
+
+    // It quitting ends the capture long before the limit, keeping the audio
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert!(!state.is_capturing());
+    assert_eq!(state.auto_stop(), Some(AutoStopReason::ApplicationExited));
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    let frames = samples.len() / 2;
+    assert!((9000..=10500).contains(&frames), "{} frames", frames);
+
+    options.application_pid = Some(1);
+    let error = start_capture_with(&state, options.clone()).await.unwrap_err();
+    assert!(matches!(error, VoiceboxError::NotFound { .. }), "{:?}", error);
+    options.application_pid = Some(4242);
+    options.device_id = Some("speakers".to_string());
+    assert_eq!(start_capture_with(&state, options.clone()).await.unwrap_err().code(), "invalid_argument");
+
+    // A backend that only records whole outputs can't honour one
+    let state = synthetic(SyntheticBackend::new(TONE));
+    assert!(!state.capabilities().application_capture);
+    options.device_id = None;
+    assert_eq!(start_capture_with(&state, options).await.unwrap_err().code(), "unsupported");
+    assert!(!state.is_capturing());
+}
+
+#[tokio::test]
+async fn test_streamed_chunks_add_up_to_the_recording() {
+    let state = synthetic(SyntheticBackend::new(TONE));
+    let mut options = CaptureOptions::new(10);
+    options.stream = true;
+    options.mono = true;
+    start_capture_with(&state, options.clone()).await.unwrap();
+    let mut chunks: Vec<CaptureChunk> = Vec::new();
+    for _ in 0..3 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(state.send_chunk(|chunk| chunks.push(chunk)));
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    // What arrived since the last chunk, or while the stop drained, goes out
+    // as the last one
+    assert!(state.send_chunk(|chunk| chunks.push(chunk)));
+    assert!(!state.send_chunk(|chunk| chunks.push(chunk)));
+
+    let mut streamed = Vec::new();
+    for (sequence, chunk) in chunks.iter().enumerate() {
+        assert_eq!(chunk.sequence, sequence as u64);
+        assert_eq!(chunk.start_frame, streamed.len() as u64);
+        assert_eq!((chunk.sample_rate, chunk.channels), (48000, 1));
+        let pcm = base64::engine::general_purpose::STANDARD.decode(&chunk.pcm).unwrap();
+        assert_eq!(pcm.len() as u64, chunk.frames * 2);
+        streamed.extend(pcm.chunks(2).map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]])));
+    }
+    assert_eq!(streamed, samples);
+    let manifest = state.stream_manifest();
+    assert_eq!(manifest.total_frames, samples.len() as u64);
+    assert_eq!((manifest.sample_rate, manifest.channels, manifest.chunk_count), (48000, 1, 4));
+
+    // Chunks go out before the peak is known
+    options.normalize = true;
+    assert_eq!(start_capture_with(&state, options).await.unwrap_err().code(), "invalid_argument");
+}
+
+#[tokio::test]
+async fn test_pause_by_hand_leaves_audio_out_and_stops_the_limit() {
+    let state = synthetic(SyntheticBackend::new(TONE));
+    assert_eq!(state.pause_capture().unwrap_err().code(), "not_found");
+    start_capture(&state, 1).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    state.pause_capture().unwrap();
+    assert_eq!(state.pause_capture().unwrap_err().code(), "invalid_argument");
+    assert!(state.status().paused_by_hand);
+
+    // Past the limit by the clock, but not by the time recorded
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    assert!(state.is_capturing());
+    state.resume_capture().unwrap();
+    assert_eq!(state.resume_capture().unwrap_err().code(), "invalid_argument");
+    tokio::time::sleep(Duration::from_millis(900)).await;
+    assert!(!state.is_capturing());
+    assert_eq!(state.auto_stop(), Some(AutoStopReason::Limit));
+
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    let frames = samples.len() as u64 / 2;
+    assert!(frames.abs_diff(48000) <= 4800, "{} frames", frames);
+    let pauses = state.metadata().pauses;
+    assert_eq!(pauses.len(), 1);
+    assert!(pauses[0].at_ms.abs_diff(300) <= 50, "{:?}", pauses);
+    assert!(pauses[0].duration_ms.abs_diff(1000) <= 50, "{:?}", pauses);
+    assert_eq!(state.resume_capture().unwrap_err().code(), "not_found");
+}
+
+#[tokio::test]
+async fn test_cancel_discards_the_capture() {
+    let state = synthetic(SyntheticBackend::new(TONE));
+    assert!(!cancel_capture(&state).await);
+    start_capture(&state, 60).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    state.pause_capture().unwrap();
+    assert!(cancel_capture(&state).await);
+    assert!(!state.is_capturing());
+    assert_eq!(state.captured_audio().0.len(), 0);
+    assert!(!cancel_capture(&state).await);
+    assert_eq!(stop_capture(&state).await.unwrap_err().code(), "capture");
+
+    // The next capture starts unpaused, with only its own audio
+    start_capture(&state, 60).await.unwrap();
+    assert!(!state.status().paused_by_hand);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    let frames = samples.len() as u64 / 2;
+    assert!(frames.abs_diff(9600) <= 2400, "{} frames", frames);
+    assert!(state.metadata().pauses.is_empty());
+}
+
+#[tokio::test]
+async fn test_a_full_buffer_ends_the_capture() {
+    let state = synthetic(SyntheticBackend::new(TONE));
+    let mut options = CaptureOptions::new(60);
+    options.max_buffer_secs = Some(1);
+    start_capture_with(&state, options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1300)).await;
+    assert!(!state.is_capturing());
+    assert_eq!(state.auto_stop(), Some(AutoStopReason::BufferFull));
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    assert_eq!(samples.len(), 48000 * 2);
+}
+
+#[tokio::test]
+async fn test_rolling_keeps_the_latest_audio() {
+    let state = synthetic(SyntheticBackend::new(TONE));
+    let mut options = CaptureOptions::new(60);
+    options.rolling = true;
+    let error = start_capture_with(&state, options.clone()).await.unwrap_err();
+    assert_eq!(error.code(), "invalid_argument");
+    options.max_buffer_secs = Some(1);
+    options.stream = true;
+    let error = start_capture_with(&state, options.clone()).await.unwrap_err();
+    assert_eq!(error.code(), "invalid_argument");
+    options.stream = false;
+
+    start_capture_with(&state, options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    state.add_marker(Some("dropped".to_string())).unwrap();
+    tokio::time::sleep(Duration::from_millis(2000)).await;
+    state.add_marker(Some("kept".to_string())).unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    // Never more than the limit and the slack it trims by
+    assert!(state.status().buffered_samples <= 2 * 48000 * 2);
+    assert!(state.is_capturing());
+
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    assert_eq!(samples.len(), 48000 * 2);
+    let markers = state.markers();
+    assert_eq!(markers.len(), 1, "{:?}", markers);
+    assert_eq!(markers[0].label.as_deref(), Some("kept"));
+    assert!(markers[0].at_frames < 48000, "{:?}", markers);
+}
+
+#[tokio::test]
+async fn test_capture_to_a_file_is_whole_once_it_ends() {
+    let path = std::env::temp_dir().join(format!("voicebox-spill-{}.wav", std::process::id()));
+    let state = synthetic(SyntheticBackend::new(TONE));
+    let mut options = CaptureOptions::new(1);
+    options.spill_path = Some(path.clone());
+    options.normalize = true;
+    let error = start_capture_with(&state, options.clone()).await.unwrap_err();
+    assert_eq!(error.code(), "invalid_argument");
+    options.normalize = false;
+    options.mono = true;
+
+    start_capture_with(&state, options.clone()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1300)).await;
+    assert_eq!(state.auto_stop(), Some(AutoStopReason::Limit));
+    assert!(state.captured_audio().0.is_empty());
+    // The limit finished the file, before any stop
+    let reader = hound::WavReader::open(&path).unwrap();
+    assert_eq!((reader.spec().sample_rate, reader.spec().channels), (48000, 1));
+    let frames = reader.duration();
+    assert!(frames.abs_diff(48000) <= 4800, "{} frames", frames);
+    drop(reader);
+    assert_eq!(stop_capture(&state).await.unwrap(), path.display().to_string());
+    assert_eq!(state.metadata().duration_ms, frames as u64 * 1000 / 48000);
+
+    // A cancelled one leaves no file
+    std::fs::remove_file(&path).unwrap();
+    start_capture_with(&state, options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(path.exists());
+    assert!(cancel_capture(&state).await);
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn test_trim_silence_leaves_out_both_ends() {
+    let backend = SyntheticBackend::new(SyntheticPattern::Silence)
+        .then(Duration::from_millis(300), TONE)
+        .then(Duration::from_millis(600), SyntheticPattern::Silence);
+    let state = synthetic(backend);
+    let mut options = CaptureOptions::new(10);
+    options.trim_silence = Some(TrimSilence {
+        threshold_db: -40.0,
+        padding_ms: 50,
+    });
+    start_capture_with(&state, options.clone()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(450)).await;
+    state.add_marker(None).unwrap();
+    tokio::time::sleep(Duration::from_millis(550)).await;
+
+    let (_, samples) = decode(&stop_capture(&state).await.unwrap());
+    let frames = samples.len() as u64 / 2;
+    assert!(frames.abs_diff(48000 * 4 / 10) <= 2400, "{} frames", frames);
+    assert!(samples[..2].iter().all(|s| *s == 0));
+    assert!(samples[2 * 4800..2 * 4810].iter().any(|s| s.unsigned_abs() > 3000));
+    // 150 ms into the tone, so 200 ms into what was kept
+    let marker = state.markers()[0].at_frames;
+    assert!(marker.abs_diff(9600) <= 2400, "marker at {}", marker);
+    assert!(state.metadata().processing.contains(&Processing::TrimSilence));
+
+    // Nothing but silence is no audio at all
+    let state = synthetic(SyntheticBackend::new(SyntheticPattern::Silence));
+    start_capture_with(&state, options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(stop_capture(&state).await.unwrap_err().code(), "capture");
+}
+
+fn at_rate(target_sample_rate: u32) -> SaveAs {
+    SaveAs {
+        target_sample_rate: Some(target_sample_rate),
+        ..SaveAs::default()
+    }
+}
+
+fn in_format(output_format: WavSampleFormat) -> SaveAs {
+    SaveAs {
+        output_format: Some(output_format),
+        ..SaveAs::default()
+    }
+}
+
+#[tokio::test]
+async fn test_stop_at_another_rate_resamples() {
+    let state = synthetic(SyntheticBackend::new(TONE));
+    let mut options = CaptureOptions::new(10);
+    options.split_on_markers = true;
+    start_capture_with(&state, options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    state.add_marker(None).unwrap();
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    // A rate that can't be used leaves the capture running
+    assert_eq!(stop_capture_as(&state, at_rate(0)).await.unwrap_err().code(), "invalid_argument");
+    assert!(state.is_capturing());
+
+    let (spec, samples) = decode(&stop_capture_as(&state, at_rate(16000)).await.unwrap());
+    assert_eq!((spec.sample_rate, spec.channels), (16000, 2));
+    let frames = samples.len() as u64 / 2;
+    assert!(frames.abs_diff(8000) <= 1600, "{} frames", frames);
+    let marker = state.markers()[0].at_frames;
+    assert!(marker.abs_diff(4000) <= 1600, "marker at {}", marker);
+    let metadata = state.metadata();
+    assert_eq!(metadata.sample_rate, 16000);
+    assert!(metadata.processing.contains(&Processing::Resample));
+
+    // Not for a streamed capture, whose chunks have gone out at the rate recorded
+    let state = synthetic(SyntheticBackend::new(TONE));
+    let mut options = CaptureOptions::new(10);
+    options.stream = true;
+    start_capture_with(&state, options).await.unwrap();
+    assert_eq!(stop_capture_as(&state, at_rate(16000)).await.unwrap_err().code(), "invalid_argument");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    stop_capture(&state).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_stop_as_float_keeps_the_samples() {
+    let state = synthetic(SyntheticBackend::new(TONE));
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let audio = stop_capture_as(&state, in_format(WavSampleFormat::Float32)).await.unwrap();
+    let bytes = base64::engine::general_purpose::STANDARD.decode(audio).unwrap();
+    let mut reader = hound::WavReader::new(std::io::Cursor::new(bytes)).unwrap();
+    assert_eq!(reader.spec().sample_format, hound::SampleFormat::Float);
+    assert_eq!(reader.spec().bits_per_sample, 32);
+    let samples: Vec<f32> = reader.samples::<f32>().map(Result::unwrap).collect();
+    let (captured, _, _) = state.captured_audio();
+    assert_eq!(samples, captured);
+
+    // A capture to a file already has its format in the header
+    let path = std::env::temp_dir().join(format!("voicebox-format-test-{}.wav", std::process::id()));
+    let state = synthetic(SyntheticBackend::new(TONE));
+    let mut options = CaptureOptions::new(10);
+    options.spill_path = Some(path.clone());
+    options.output_format = WavSampleFormat::Int24;
+    start_capture_with(&state, options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let refused = stop_capture_as(&state, in_format(WavSampleFormat::Float32)).await;
+    assert_eq!(refused.unwrap_err().code(), "invalid_argument");
+    stop_capture_as(&state, in_format(WavSampleFormat::Int24)).await.unwrap();
+    assert_eq!(hound::WavReader::open(&path).unwrap().spec().bits_per_sample, 24);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_summary_matches_the_saved_audio() {
+    let state = synthetic(SyntheticBackend::new(TONE));
+    let mut options = CaptureOptions::new(10);
+    options.mono = true;
+    start_capture_with(&state, options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(state.summary(), None);
+
+    let (spec, samples) = decode(&stop_capture(&state).await.unwrap());
+    let summary = state.summary().unwrap();
+    assert_eq!((summary.sample_rate, summary.channels), (spec.sample_rate, spec.channels));
+    assert_eq!(summary.num_frames, samples.len() as u64);
+    assert!((summary.duration_secs - samples.len() as f64 / 48000.0).abs() < 1e-9);
+    assert!((summary.peak_level - peak(&samples)).abs() < 0.001, "{:?}", summary);
+    assert!(!summary.clipped);
+    assert_eq!((summary.clipped_frames, summary.clipping_detected), (0, false));
+
+    let loud = SyntheticPattern::Sine {
+        frequency: 440.0,
+        amplitude: 1.5,
+    };
+    let state = synthetic(SyntheticBackend::new(loud));
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    stop_capture(&state).await.unwrap();
+    let CaptureSummary { peak_level, clipped, .. } = state.summary().unwrap();
+    assert!(clipped && peak_level > 1.4, "peak {}", peak_level);
+
+    // Gone once the next capture starts
+    start_capture(&state, 10).await.unwrap();
+    assert_eq!(state.summary(), None);
+    cancel_capture(&state).await;
+}
+
+#[tokio::test]
+async fn test_clipping_is_counted_as_it_arrives() {
+    let loud = SyntheticPattern::Sine {
+        frequency: 440.0,
+        amplitude: 1.5,
+    };
+    let state = synthetic(SyntheticBackend::new(TONE).then(Duration::from_millis(200), loud));
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!state.clip_stats().detected(), "{:?}", state.clip_stats());
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let live = state.clip_stats();
+    assert!(live.detected(), "{:?}", live);
+
+    stop_capture(&state).await.unwrap();
+    let summary = state.summary().unwrap();
+    assert!(summary.clipping_detected, "{:?}", summary);
+    assert!(summary.clipped_frames >= live.clipped_frames);
+    assert!(summary.longest_clip_frames >= CLIP_RUN_FRAMES);
+    // Half the capture is the loud sine, which clips for about half its cycle
+    assert!((10.0..40.0).contains(&summary.clip_percentage), "{:?}", summary);
+
+    // Counted afresh, from the quiet tone the next capture starts with
+    start_capture(&state, 10).await.unwrap();
+    let restarted = state.clip_stats();
+    assert_eq!((restarted.clipped_frames, restarted.longest_run), (0, 0));
+    assert!(restarted.total_frames < live.total_frames, "{:?}", restarted);
+    cancel_capture(&state).await;
+}
+
+#[tokio::test]
+async fn test_finalized_capture_comes_back_in_chunks() {
+    // Over a megabyte a second, for more than one chunk
+    let state = synthetic(SyntheticBackend::new(TONE).with_format(192000, 8));
+    assert!(matches!(state.finalized_chunk(0), Err(VoiceboxError::NotFound { .. })));
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    let session = state.current_session();
+    let audio = stop_capture(&state).await.unwrap();
+    let finalized = state.finalize(session, &audio).unwrap();
+    let wav = base64::engine::general_purpose::STANDARD.decode(&audio).unwrap();
+    assert_eq!(finalized.total_bytes, wav.len() as u64);
+    assert_eq!(finalized.chunk_size as usize, FINALIZED_CHUNK_BYTES);
+    assert_eq!(finalized.num_chunks as usize, wav.len().div_ceil(FINALIZED_CHUNK_BYTES));
+    assert!(finalized.num_chunks > 1, "{:?}", finalized);
+    assert_eq!(finalized.sample_rate, 192000);
+    assert_eq!(finalized.duration_secs, state.summary().unwrap().duration_secs);
+    assert_eq!(state.finalized(), Some(finalized.clone()));
+
+    let mut joined = Vec::new();
+    for index in 0..finalized.num_chunks {
+        let chunk = state.finalized_chunk(index).unwrap();
+        joined.extend(base64::engine::general_purpose::STANDARD.decode(chunk).unwrap());
+    }
+    assert_eq!(joined, wav);
+    let error = state.finalized_chunk(finalized.num_chunks).unwrap_err();
+    assert!(matches!(error, VoiceboxError::InvalidArgument { .. }), "{:?}", error);
+
+    // Held through the next capture, until released
+    start_capture(&state, 10).await.unwrap();
+    assert_eq!(state.finalized(), Some(finalized));
+    cancel_capture(&state).await;
+    assert!(state.release_finalized());
+    assert!(!state.release_finalized());
+    assert_eq!(state.finalized(), None);
+    assert!(matches!(state.finalized_chunk(0), Err(VoiceboxError::NotFound { .. })));
+}
+
+#[tokio::test]
+async fn test_saves_a_stopped_capture_to_a_file() {
+    let dir = std::env::temp_dir().join(format!("voicebox-save-test-{}", std::process::id()));
+    let path = dir.join("captures").join("saved.wav");
+    let state = synthetic(SyntheticBackend::new(TONE));
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let error = save_capture_to_file(&state, path.clone(), SaveAs::default(), SaveToFile::default())
+        .await
+        .unwrap_err();
+    assert!(matches!(error, VoiceboxError::Busy { .. }), "{:?}", error);
+    let audio = stop_capture(&state).await.unwrap();
+
+    // The parent directories are only made when asked for
+    let error = save_capture_to_file(&state, path.clone(), SaveAs::default(), SaveToFile::default())
+        .await
+        .unwrap_err();
+    assert!(matches!(error, VoiceboxError::NotFound { .. }), "{:?}", error);
+    let create_dirs = SaveToFile {
+        create_dirs: true,
+        ..SaveToFile::default()
+    };
+    let saved = save_capture_to_file(&state, path.clone(), SaveAs::default(), create_dirs)
+        .await
+        .unwrap();
+    let written = std::fs::read(&path).unwrap();
+    assert_eq!(written, base64::engine::general_purpose::STANDARD.decode(&audio).unwrap());
+    assert_eq!(saved.bytes, written.len() as u64);
+    assert_eq!(saved.duration_secs, state.summary().unwrap().duration_secs);
+
+    let error = save_capture_to_file(&state, path.clone(), SaveAs::default(), create_dirs)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, VoiceboxError::InvalidArgument { .. }), "{:?}", error);
+    assert_eq!(std::fs::read(&path).unwrap(), written);
+
+    let resampled = SaveAs {
+        target_sample_rate: Some(16000),
+        ..SaveAs::default()
+    };
+    let overwrite = SaveToFile {
+        overwrite: true,
+        ..SaveToFile::default()
+    };
+    save_capture_to_file(&state, path.clone(), resampled, overwrite).await.unwrap();
+    let reader = hound::WavReader::open(&path).unwrap();
+    assert_eq!(reader.spec().sample_rate, 16000);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_segments_a_stopped_capture_into_utterances() {
+    let vad = VadOptions {
+        min_segment_secs: 0.1,
+        max_segment_secs: 10.0,
+        silence_threshold_db: -40.0,
+        min_gap_secs: 0.2,
+    };
+    let backend = SyntheticBackend::new(TONE)
+        .then(Duration::from_millis(300), SyntheticPattern::Silence)
+        .then(Duration::from_millis(800), TONE)
+        .then(Duration::from_millis(1100), SyntheticPattern::Silence);
+    let state = synthetic(backend);
+    start_capture(&state, 10).await.unwrap();
+    let error = segment_capture(&state, vad).await.unwrap_err();
+    assert!(matches!(error, VoiceboxError::Busy { .. }), "{:?}", error);
+    tokio::time::sleep(Duration::from_millis(1400)).await;
+    stop_capture(&state).await.unwrap();
+
+    let segments = segment_capture(&state, vad).await.unwrap();
+    assert_eq!(segments.len(), 2, "{:?}", segments.iter().map(|s| (s.start_secs, s.end_secs)).collect::<Vec<_>>());
+    for (index, segment) in segments.iter().enumerate() {
+        assert_eq!(segment.index, index);
+        let (spec, samples) = decode(&segment.data_base64);
+        assert_eq!((spec.sample_rate, spec.channels), (48000, 2));
+        let secs = samples.len() as f64 / 2.0 / 48000.0;
+        assert!((secs - (segment.end_secs - segment.start_secs)).abs() < 1e-3);
+        assert!((peak(&samples) - 0.5).abs() < 0.01);
+    }
+    assert!(segments[0].end_secs < 0.5 && segments[1].start_secs > 0.6, "{:?}", segments[1].start_secs);
+
+    let min_over_max = VadOptions {
+        min_segment_secs: 2.0,
+        max_segment_secs: 1.0,
+        ..vad
+    };
+    let error = segment_capture(&state, min_over_max).await.unwrap_err();
+    assert!(matches!(error, VoiceboxError::InvalidArgument { .. }), "{:?}", error);
+
+    // Nothing heard is no segments, not an error
+    let state = synthetic(SyntheticBackend::new(SyntheticPattern::Silence));
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    stop_capture(&state).await.unwrap();
+    assert!(segment_capture(&state, vad).await.unwrap().is_empty());
+}
+
+fn normalized(target: NormalizeTarget) -> SaveAs {
+    SaveAs {
+        normalize: Some(target),
+        ..SaveAs::default()
+    }
+}
+
+#[tokio::test]
+async fn test_normalize_as_it_stops() {
+    // The tone peaks at 0.5, -6 dBFS
+    let state = synthetic(SyntheticBackend::new(TONE));
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let refused = stop_capture_as(&state, normalized(NormalizeTarget::PeakDb(3.0))).await;
+    assert_eq!(refused.unwrap_err().code(), "invalid_argument");
+    let (_, samples) = decode(&stop_capture_as(&state, normalized(NormalizeTarget::PeakDb(-1.0))).await.unwrap());
+    assert!((peak(&samples) - 0.891).abs() < 0.005, "peak {}", peak(&samples));
+    let summary = state.summary().unwrap();
+    assert!((summary.gain_db - 5.02).abs() < 0.05, "gain {}", summary.gain_db);
+    assert!(state.metadata().processing.contains(&Processing::Normalize));
+
+    let state = synthetic(SyntheticBackend::new(TONE));
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    let (_, samples) = decode(&stop_capture_as(&state, normalized(NormalizeTarget::Lufs(-16.0))).await.unwrap());
+    let samples: Vec<f32> = samples.iter().map(|s| *s as f32 / 32767.0).collect();
+    let loudness = integrated_loudness(&samples, 2, 48000).unwrap();
+    assert!((loudness + 16.0).abs() < 0.1, "{} LUFS", loudness);
+    assert!(state.summary().unwrap().gain_db < -9.0);
+
+    // Raised by no more than 30 dB
+    let faint = SyntheticPattern::Sine {
+        frequency: 440.0,
+        amplitude: 0.0001,
+    };
+    let state = synthetic(SyntheticBackend::new(faint));
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    stop_capture_as(&state, normalized(NormalizeTarget::PeakDb(-1.0))).await.unwrap();
+    let summary = state.summary().unwrap();
+    assert_eq!(summary.gain_db, NormalizeTarget::MAX_GAIN_DB);
+    assert!((summary.peak_level - 0.0001 * 31.62).abs() < 0.0001, "{:?}", summary);
+
+    // Silence has no level to bring anywhere
+    let state = synthetic(SyntheticBackend::new(SyntheticPattern::Silence));
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (_, samples) = decode(&stop_capture_as(&state, normalized(NormalizeTarget::Lufs(-16.0))).await.unwrap());
+    assert!(samples.iter().all(|s| *s == 0));
+    assert_eq!(state.summary().unwrap().gain_db, 0.0);
+}
+
+#[test]
+fn test_normalize_targets_parse() {
+    let targets: Vec<NormalizeTarget> = serde_json::from_str(r#"[-1.5, "lufs:-16", "peak:-3", "-2"]"#).unwrap();
+    let expected = [
+        NormalizeTarget::PeakDb(-1.5),
+        NormalizeTarget::Lufs(-16.0),
+        NormalizeTarget::PeakDb(-3.0),
+        NormalizeTarget::PeakDb(-2.0),
+    ];
+    assert_eq!(targets, expected);
+    assert!(serde_json::from_str::<NormalizeTarget>(r#""rms:-20""#).is_err());
+    assert!(serde_json::from_str::<NormalizeTarget>(r#""lufs:loud""#).is_err());
+    assert_eq!(serde_json::to_value(NormalizeTarget::Lufs(-16.0)).unwrap(), serde_json::json!("lufs:-16"));
+}
+
+#[test]
+fn test_a_capture_request_maps_onto_its_options() {
+    let request: CaptureRequest = serde_json::from_str(r#"{"max_duration_secs": 30}"#).unwrap();
+    assert_eq!(request, CaptureRequest::new(30));
+    assert_eq!(request.options().unwrap(), CaptureOptions::new(30));
+    assert!(serde_json::from_str::<CaptureRequest>("{}").is_err());
+
+    let request: CaptureRequest = serde_json::from_str(
+        r#"{"max_duration_secs": 30, "rolling": true, "max_buffer_secs": 10, "capture_to_file": true,
+            "silence_stop_secs": 2.5, "silence_threshold_db": -40}"#,
+    )
+    .unwrap();
+    let options = request.options().unwrap();
+    assert!(options.rolling);
+    assert_eq!(options.max_buffer_secs, Some(10));
+    assert_eq!(options.silence_stop_secs, Some(2.5));
+    assert!((options.silence_threshold - 0.01).abs() < 1e-6);
+    // The app picks where the file goes
+    assert_eq!(options.spill_path, None);
+
+    let mut loud = CaptureRequest::new(30);
+    loud.silence_threshold_db = Some(6.0);
+    assert_eq!(loud.options().unwrap_err().code(), "invalid_argument");
+}
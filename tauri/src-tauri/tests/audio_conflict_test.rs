@@ -0,0 +1,79 @@
+// Checks which playback each kind of capture would record, with the default
+// pseudo-device resolved on both sides:
+//   cargo test --test audio_conflict_test
+
+use voicebox::audio_capture::CaptureScope;
+use voicebox::audio_conflict::{self, AudioConflict};
+use voicebox::audio_output::{AudioOutputDevice, DeviceState, DeviceTransport};
+
+fn devices() -> Vec<AudioOutputDevice> {
+    let device = |id: &str, name: &str, is_default| AudioOutputDevice {
+        id: id.to_string(),
+        name: name.to_string(),
+        is_default,
+        transport: DeviceTransport::Unknown,
+        state: DeviceState::Active,
+        default_sample_rate: 48000,
+        channels: 2,
+        endpoint_id: None,
+    };
+    vec![
+        device("device_speakers", "Speakers", true),
+        device("device_headphones", "Headphones", false),
+    ]
+}
+
+fn check(scope: CaptureScope, playback: &[&str]) -> Option<AudioConflict> {
+    let playback: Vec<String> = playback.iter().map(|id| id.to_string()).collect();
+    audio_conflict::check(scope, &devices(), &playback)
+}
+
+#[test]
+fn test_loopback_conflicts_only_on_the_default_device() {
+    let conflict = check(CaptureScope::DefaultOutput, &["device_headphones", "device_speakers"]).unwrap();
+    assert_eq!(conflict.capture_device_id.as_deref(), Some("device_speakers"));
+    assert_eq!(conflict.playback_device_ids, ["device_speakers"]);
+    assert!(conflict.message.contains("Speakers"), "{}", conflict.message);
+
+    assert_eq!(check(CaptureScope::DefaultOutput, &["device_headphones"]), None);
+    assert_eq!(check(CaptureScope::DefaultOutput, &[]), None);
+}
+
+#[test]
+fn test_default_resolves_to_the_concrete_device() {
+    let conflict = check(CaptureScope::DefaultOutput, &["default"]).unwrap();
+    assert_eq!(conflict.playback_device_ids, ["device_speakers"]);
+    // Named both ways, it is still one device
+    let conflict = check(CaptureScope::Desktop, &["default", "device_speakers"]).unwrap();
+    assert_eq!(conflict.playback_device_ids, ["device_speakers"]);
+
+    // Without a default output there is nothing to resolve it to
+    let mut devices = devices();
+    devices[0].is_default = false;
+    assert_eq!(audio_conflict::resolve(&devices, "default"), None);
+    let playback = ["device_speakers".to_string()];
+    assert_eq!(audio_conflict::check(CaptureScope::DefaultOutput, &devices, &playback), None);
+}
+
+#[test]
+fn test_desktop_capture_hears_every_device() {
+    let conflict = check(CaptureScope::Desktop, &["device_headphones"]).unwrap();
+    assert_eq!(conflict.capture_device_id, None);
+    assert_eq!(conflict.playback_device_ids, ["device_headphones"]);
+    let json = serde_json::to_value(&conflict).unwrap();
+    assert_eq!(json["capture_scope"], "desktop");
+
+    assert_eq!(check(CaptureScope::Nothing, &["device_speakers"]), None);
+}
+
+#[test]
+fn test_a_chosen_target_is_matched_to_its_output_by_name() {
+    let playback = ["device_headphones".to_string(), "default".to_string()];
+    let conflict = audio_conflict::check_target(&devices(), "Headphones", &playback).unwrap();
+    assert_eq!(conflict.capture_device_id.as_deref(), Some("device_headphones"));
+    assert_eq!(conflict.playback_device_ids, ["device_headphones"]);
+
+    // Playing only to the default output, which isn't the one captured
+    assert_eq!(audio_conflict::check_target(&devices(), "Headphones", &["default".to_string()]), None);
+    assert_eq!(audio_conflict::check_target(&devices(), "Line Out", &playback), None);
+}
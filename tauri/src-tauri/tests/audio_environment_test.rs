@@ -0,0 +1,110 @@
+// Lists devices into the audio environment cache and takes snapshots around
+// a synthetic capture and a NullSink playback, checking that formats are
+// only asked of new devices, that the sessions under way are described, and
+// that device names go through the diagnostics redaction:
+//   cargo test --test audio_environment_test
+
+use std::cell::RefCell;
+use std::path::Path;
+use std::sync::Arc;
+use voicebox::audio_capture::{self, AudioCaptureState, SyntheticBackend, SyntheticPattern};
+use voicebox::audio_environment::AudioEnvironment;
+use voicebox::audio_output::{
+    AudioOutputDevice, AudioOutputState, DeviceFormat, DeviceState, DeviceTransport, NullSink,
+};
+use voicebox::diagnostics::{Redactor, REDACTED};
+
+const STEREO_48K: DeviceFormat = DeviceFormat {
+    sample_rate: 48000,
+    channels: 2,
+};
+
+fn device(id: &str, name: &str, is_default: bool) -> AudioOutputDevice {
+    AudioOutputDevice {
+        id: id.to_string(),
+        name: name.to_string(),
+        is_default,
+        transport: DeviceTransport::from_name(name),
+        state: DeviceState::Active,
+        default_sample_rate: 48000,
+        channels: 2,
+        endpoint_id: None,
+    }
+}
+
+fn states() -> (AudioCaptureState, AudioOutputState) {
+    let tone = SyntheticPattern::Sine {
+        frequency: 440.0,
+        amplitude: 0.5,
+    };
+    let capture = AudioCaptureState::with_backend(Arc::new(SyntheticBackend::new(tone)));
+    let output = AudioOutputState::with_backend(Arc::new(NullSink::new(STEREO_48K).with_devices(&["speakers"])));
+    (capture, output)
+}
+
+#[test]
+fn test_asks_formats_of_new_devices_only() {
+    let (capture, output) = states();
+    let environment = AudioEnvironment::default();
+    assert_eq!(environment.snapshot(&capture, &output).devices_listed_at, None);
+
+    let asked = RefCell::new(Vec::new());
+    let format = |id: &str| {
+        asked.borrow_mut().push(id.to_string());
+        (id != "hdmi").then_some(STEREO_48K)
+    };
+    environment.update_devices(&[device("speakers", "Speakers", true), device("hdmi", "HDMI", false)], format);
+    environment.update_devices(&[device("speakers", "Speakers", false), device("usb", "USB DAC", true)], format);
+    assert_eq!(*asked.borrow(), ["speakers", "hdmi", "usb"]);
+
+    let snapshot = environment.snapshot(&capture, &output);
+    assert!(snapshot.devices_listed_at.is_some());
+    assert_eq!(snapshot.default_output_id.as_deref(), Some("usb"));
+    let formats: Vec<_> = snapshot.output_devices.iter().map(|device| (device.id.as_str(), device.format)).collect();
+    assert_eq!(formats, [("speakers", Some(STEREO_48K)), ("usb", Some(STEREO_48K))]);
+    assert_eq!(snapshot.output_devices[1].transport, DeviceTransport::Usb);
+    assert_eq!((snapshot.capture_backend, snapshot.output_backend), ("synthetic", "null"));
+}
+
+#[tokio::test]
+async fn test_describes_the_sessions_under_way() {
+    let (capture, output) = states();
+    let environment = AudioEnvironment::default();
+    let idle = environment.snapshot(&capture, &output);
+    assert!(idle.capture.is_none() && idle.playback.is_none());
+
+    audio_capture::start_capture(&capture, 10).await.unwrap();
+    output.play_samples(&[0.5; 48000], 48000, 1, &["speakers".to_string()], Default::default()).await.unwrap();
+    let snapshot = environment.snapshot(&capture, &output);
+    let capture_snapshot = snapshot.capture.unwrap();
+    assert_eq!(capture_snapshot.options.max_duration_secs, 10);
+    let playback = snapshot.playback.unwrap();
+    assert_eq!(playback.playback_id, output.current_playback().unwrap().id());
+    assert_eq!((playback.device_ids, playback.duration_ms), (vec!["speakers".to_string()], 1000));
+    assert_eq!(playback.options.gain, 1.0);
+
+    // What a failure report carries, as JSON
+    let json = serde_json::to_value(environment.snapshot(&capture, &output)).unwrap();
+    assert_eq!(json["capture"]["options"]["mono"], false);
+    assert_eq!(json["playback"]["device_ids"][0], "speakers");
+
+    audio_capture::stop_capture(&capture).await.unwrap();
+    output.stop_all_playback().unwrap();
+    let stopped = environment.snapshot(&capture, &output);
+    assert!(stopped.capture.is_none() && stopped.playback.is_none());
+}
+
+#[test]
+fn test_redacts_the_user_from_device_names() {
+    let (capture, output) = states();
+    let environment = AudioEnvironment::default();
+    let airpods = device("device_alice's_airpods", "Alice's AirPods", true);
+    environment.update_devices(&[airpods, device("hdmi", "HDMI", false)], |_| None);
+
+    let mut snapshot = environment.snapshot(&capture, &output);
+    snapshot.redact(&Redactor::new(Some(Path::new("/Users/alice")), Vec::new()));
+    assert_eq!(snapshot.output_devices[0].name, format!("{}'s AirPods", REDACTED));
+    assert_eq!(snapshot.output_devices[0].id, format!("device_{}'s_airpods", REDACTED));
+    assert_eq!(snapshot.default_output_id, Some(snapshot.output_devices[0].id.clone()));
+    assert_eq!(snapshot.output_devices[1].name, "HDMI");
+}
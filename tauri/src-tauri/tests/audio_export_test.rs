@@ -0,0 +1,55 @@
+// Re-encodes base64 WAVs the way a stopped capture is returned, and checks
+// that FLAC keeps the rate, channels and 16-bit samples and that audio that
+// isn't a WAV or isn't base64 is refused:
+//   cargo test --test audio_export_test
+
+use base64::{engine::general_purpose, Engine as _};
+use std::io::Cursor;
+use voicebox::audio_export::{transcode, transcode_base64, ExportError, ExportFormat};
+use voicebox::audio_util::{encode_wav, EncodeSpec};
+
+fn stereo_wav(sample_rate: u32, frames: usize) -> Vec<u8> {
+    let samples: Vec<f32> = (0..frames)
+        .flat_map(|n| {
+            let s = 0.5 * (std::f32::consts::TAU * 440.0 * n as f32 / sample_rate as f32).sin();
+            [s, -s / 2.0]
+        })
+        .collect();
+    encode_wav(&samples, EncodeSpec::pcm16(sample_rate, 2)).unwrap()
+}
+
+fn read_wav(wav: Vec<u8>) -> (hound::WavSpec, Vec<i16>) {
+    let mut reader = hound::WavReader::new(Cursor::new(wav)).unwrap();
+    (reader.spec(), reader.samples::<i16>().map(Result::unwrap).collect())
+}
+
+#[test]
+fn test_flac_keeps_the_rate_and_channels() {
+    for sample_rate in [22050, 44100, 48000, 96000] {
+        let wav = stereo_wav(sample_rate, sample_rate as usize / 2);
+        let flac = transcode_base64(&general_purpose::STANDARD.encode(&wav), ExportFormat::Flac).unwrap();
+        let flac = general_purpose::STANDARD.decode(flac).unwrap();
+        assert!(flac.starts_with(b"fLaC"));
+        assert!(flac.len() < wav.len());
+
+        let (spec, samples) = read_wav(transcode(flac, ExportFormat::Wav).unwrap());
+        assert_eq!((spec.sample_rate, spec.channels, spec.bits_per_sample), (sample_rate, 2, 16));
+        assert_eq!(samples, read_wav(wav).1);
+    }
+    assert_eq!(ExportFormat::Flac.mime_type(), "audio/flac");
+    assert_eq!(ExportFormat::Wav.mime_type(), "audio/wav");
+}
+
+#[test]
+fn test_wav_is_returned_as_is() {
+    let audio = general_purpose::STANDARD.encode(stereo_wav(48000, 4800));
+    assert_eq!(transcode_base64(&audio, ExportFormat::Wav).unwrap(), audio);
+}
+
+#[test]
+fn test_refuses_what_it_cant_read() {
+    let error = transcode_base64("not base64!", ExportFormat::Flac).unwrap_err();
+    assert!(matches!(error, ExportError::InvalidSource { .. }), "{:?}", error);
+    let error = transcode_base64(&general_purpose::STANDARD.encode([0u8; 64]), ExportFormat::Flac).unwrap_err();
+    assert!(matches!(error, ExportError::InvalidSource { .. }), "{:?}", error);
+}
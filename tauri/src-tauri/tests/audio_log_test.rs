@@ -0,0 +1,85 @@
+// Fills an audio activity log past its capacity and checks what a synthetic
+// capture records into the shared one:
+//   cargo test --test audio_log_test
+
+use std::sync::Arc;
+use std::time::Duration;
+use voicebox::audio_capture::{self, AudioCaptureState, SyntheticBackend, SyntheticEvent, SyntheticPattern};
+use voicebox::audio_log::{AudioEvent, AudioLog, AudioLogSource, AUDIO_LOG, CAPACITY};
+
+fn dropout(frames: u64) -> AudioEvent {
+    AudioEvent::Dropout { frames: Some(frames) }
+}
+
+#[test]
+fn test_log_keeps_the_latest_entries() {
+    let log: &'static AudioLog = Box::leak(Box::new(AudioLog::new()));
+    assert!(log.snapshot().entries.is_empty());
+
+    // In batches, so the collector keeps up with the queue
+    for batch in 0..6 {
+        for n in 0..100 {
+            log.record(dropout(batch * 100 + n));
+        }
+        log.snapshot();
+    }
+    let snapshot = log.snapshot();
+    assert_eq!(snapshot.dropped, 0);
+    assert_eq!(snapshot.entries.len(), CAPACITY);
+    let first = &snapshot.entries[0];
+    assert_eq!(first.seq, 101);
+    assert_eq!(first.fields["frames"], 100);
+    assert_eq!(snapshot.entries.last().unwrap().seq, 600);
+    assert!(snapshot.entries.windows(2).all(|pair| pair[0].at_ms <= pair[1].at_ms));
+}
+
+#[test]
+fn test_entries_are_described_by_the_collector() {
+    let log: &'static AudioLog = Box::leak(Box::new(AudioLog::new()));
+    log.record(AudioEvent::DeviceChanged {
+        from: (48000, 2),
+        to: (44100, 1),
+    });
+    log.record(AudioEvent::PermissionFailed {
+        permission: "screen_recording",
+        error: "denied".to_string(),
+    });
+
+    let entries = log.snapshot().entries;
+    assert_eq!(entries[0].kind, "device_changed");
+    assert_eq!(entries[0].source, AudioLogSource::Capture);
+    assert!(entries[0].message.contains("44100 Hz"), "{}", entries[0].message);
+    assert_eq!(entries[0].fields["to"]["channels"], 1);
+    assert_eq!(entries[1].source, AudioLogSource::Permission);
+
+    let json = serde_json::to_value(&entries[1]).unwrap();
+    assert_eq!(json["source"], "permission");
+    assert_eq!(json["fields"]["permission"], "screen_recording");
+}
+
+#[tokio::test]
+async fn test_capture_records_its_activity() {
+    let backend = SyntheticBackend::new(SyntheticPattern::Sine {
+        frequency: 440.0,
+        amplitude: 0.5,
+    })
+    .with_event(
+        Duration::from_millis(50),
+        SyntheticEvent::Dropout {
+            duration: Duration::from_millis(20),
+        },
+    );
+    let state = AudioCaptureState::with_backend(Arc::new(backend));
+    audio_capture::start_capture(&state, 60).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    audio_capture::stop_capture(&state).await.unwrap();
+
+    let entries = AUDIO_LOG.snapshot().entries;
+    let kinds: Vec<&str> = entries.iter().map(|entry| entry.kind).collect();
+    let started = kinds.iter().position(|kind| *kind == "capture_started").unwrap();
+    let stopped = kinds.iter().rposition(|kind| *kind == "capture_stopped").unwrap();
+    assert!(kinds[started..stopped].contains(&"dropout"), "{:?}", kinds);
+    assert_eq!(entries[started].fields["backend"], "synthetic");
+    assert_eq!(entries[stopped].fields["dropouts"], 1);
+    assert_eq!(entries[stopped].fields["auto_stop"], serde_json::Value::Null);
+}
@@ -0,0 +1,1419 @@
+// Runs playback into NullSink, which records what a device would have played,
+// and checks the device list and the self-test's tone detection:
+//   cargo test --test audio_output_test
+
+use base64::Engine;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use voicebox::audio_output::{
+    is_degraded_profile, AudioOutputDevice, AudioOutputState, DeviceFormat, DeviceListChange, DeviceState,
+    DeviceTransport, NullSink, PlaybackEnd, PlaybackOptions, PlaybackRequest, PlaybackState, RecordedPeriod, FADE_MS,
+};
+use voicebox::metrics::METRICS;
+use voicebox::audio_export::{transcode, ExportFormat};
+use voicebox::audio_selftest::{find_tone, TONE_HZ};
+use voicebox::audio_util::{encode_wav, EncodeSpec, WavSampleFormat};
+
+const STEREO_48K: DeviceFormat = DeviceFormat {
+    sample_rate: 48000,
+    channels: 2,
+};
+
+/// What a Bluetooth headset drops to on its hands-free profile.
+const HFP_16K: DeviceFormat = DeviceFormat {
+    sample_rate: 16000,
+    channels: 1,
+};
+
+fn no_fade() -> PlaybackOptions {
+    let mut options = PlaybackOptions::default();
+    options.fade_in_ms = 0;
+    options.fade_out_ms = 0;
+    options
+}
+
+/// Fails when any device fails to open.
+fn strict() -> PlaybackOptions {
+    let mut options = no_fade();
+    options.strict = true;
+    options
+}
+
+fn null_output(sink: NullSink) -> (Arc<NullSink>, AudioOutputState) {
+    let sink = Arc::new(sink);
+    (sink.clone(), AudioOutputState::with_backend(sink))
+}
+
+fn ids(ids: &[&str]) -> Vec<String> {
+    ids.iter().map(|id| id.to_string()).collect()
+}
+
+/// `samples` without the silence a device held while the playback's other
+/// devices opened, up to the first sound.
+fn skip_held(samples: Vec<f32>) -> Vec<f32> {
+    let start = samples.iter().position(|s| *s != 0.0).unwrap_or(samples.len());
+    samples[start..].to_vec()
+}
+
+/// Wait for the clip to play out in real time, plus a few periods.
+async fn played_out(duration: Duration) {
+    tokio::time::sleep(duration + Duration::from_millis(100)).await;
+}
+
+/// A mono 48 kHz float WAV of `samples`, for play_audio_to_devices.
+fn wav(samples: &[f32]) -> Vec<u8> {
+    encode_wav(samples, EncodeSpec::pcm16(48000, 1).with_format(WavSampleFormat::Float32)).unwrap()
+}
+
+/// `count` frames of MPEG-1 Layer III at 44.1 kHz, mono, 128 kbps, each
+/// 1152 samples of silence: side info of zeros codes no spectrum.
+fn silent_mp3(count: usize) -> Vec<u8> {
+    let mut frame = vec![0u8; 417];
+    frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0xC0]);
+    frame.repeat(count)
+}
+
+/// Write `data` to a file of its own in the temp dir, for play_file_to_devices.
+fn temp_file(name: &str, data: &[u8]) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("voicebox-playback-{}-{}", std::process::id(), name));
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+/// `samples` as a chunk for push_playback_chunk: base64 16-bit PCM.
+fn pcm_chunk(samples: &[f32]) -> String {
+    let bytes: Vec<u8> = samples
+        .iter()
+        .flat_map(|sample| ((sample * 32768.0) as i16).to_le_bytes())
+        .collect();
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+fn sine(frequency: f32, sample_rate: u32, secs: f32) -> Vec<f32> {
+    let frames = (sample_rate as f32 * secs) as usize;
+    (0..frames)
+        .map(|n| 0.5 * (std::f32::consts::TAU * frequency * n as f32 / sample_rate as f32).sin())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_plays_clip_with_gain() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let clip = [0.5, -0.5].repeat(4800);
+    let mut options = no_fade();
+    options.gain = 0.5;
+    let duration = output.play_samples(&clip, 48000, 2, &ids(&["device_null"]), options).await.unwrap();
+    assert_eq!(duration, Duration::from_millis(100));
+    played_out(duration).await;
+
+    let played = sink.samples("device_null");
+    assert_eq!(&played[..clip.len()], &[0.25, -0.25].repeat(4800)[..]);
+    // Whatever the last period pulled past the end is silence
+    assert!(played[clip.len()..].iter().all(|s| *s == 0.0));
+}
+
+#[tokio::test]
+async fn test_mono_plays_on_both_channels() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let clip: Vec<f32> = (0..480).map(|n| n as f32 / 480.0).collect();
+    let duration = output.play_samples(&clip, 48000, 1, &ids(&["device_null"]), no_fade()).await.unwrap();
+    played_out(duration).await;
+
+    let played = sink.samples("device_null");
+    for (n, frame) in played.chunks(2).take(clip.len()).enumerate() {
+        assert_eq!(frame, [clip[n], clip[n]]);
+    }
+}
+
+#[tokio::test]
+async fn test_stereo_downmixes_to_a_mono_device() {
+    let format = DeviceFormat {
+        sample_rate: 48000,
+        channels: 1,
+    };
+    let (sink, output) = null_output(NullSink::new(format));
+    let clip = [0.6, 0.2].repeat(480);
+    let duration = output.play_samples(&clip, 48000, 2, &ids(&["device_null"]), no_fade()).await.unwrap();
+    played_out(duration).await;
+
+    let played = sink.samples("device_null");
+    assert!(played[..480].iter().all(|s| (s - 0.4).abs() < 1e-6));
+}
+
+#[tokio::test]
+async fn test_resamples_to_device_rate() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let clip = sine(TONE_HZ, 24000, 0.2);
+    let duration = output.play_samples(&clip, 24000, 1, &ids(&["device_null"]), no_fade()).await.unwrap();
+    assert_eq!(duration, Duration::from_millis(200));
+    played_out(duration).await;
+
+    let played = sink.samples("device_null");
+    let nonzero_frames = played.chunks(2).rposition(|frame| frame[0] != 0.0).unwrap() + 1;
+    assert!((9590..=9600).contains(&nonzero_frames), "{} frames", nonzero_frames);
+    // Still the same pitch at the new rate
+    assert_eq!(find_tone(&played, 48000, 2, TONE_HZ), Some(0));
+}
+
+/// Frames of `samples`, at `channels`, that are all `level`.
+fn frames_at(samples: &[f32], channels: usize, level: f32) -> usize {
+    samples.chunks(channels).filter(|frame| frame.iter().all(|s| *s == level)).count()
+}
+
+#[tokio::test]
+async fn test_converts_24k_mono_to_48k_stereo() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let clip = vec![0.5; 4800];
+    let duration = output.play_samples(&clip, 24000, 1, &ids(&["device_null"]), no_fade()).await.unwrap();
+    played_out(duration).await;
+
+    assert_eq!(frames_at(&sink.samples("device_null"), 2, 0.5), 9600);
+}
+
+#[tokio::test]
+async fn test_converts_44_1k_to_48k() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let clip = sine(TONE_HZ, 44100, 0.1);
+    assert_eq!(clip.len(), 4410);
+    let duration = output.play_samples(&clip, 44100, 1, &ids(&["device_null"]), no_fade()).await.unwrap();
+    played_out(duration).await;
+
+    let played = sink.samples("device_null");
+    let frames = played.chunks(2).rposition(|frame| frame[0] != 0.0).unwrap() + 1;
+    assert!((4790..=4800).contains(&frames), "{} frames", frames);
+    assert!(played.chunks(2).all(|frame| frame[0] == frame[1]));
+    assert_eq!(find_tone(&played, 48000, 2, TONE_HZ), Some(0));
+}
+
+#[tokio::test]
+async fn test_converts_for_each_device_on_its_own() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["speakers", "cable"]));
+    let mono_44k = DeviceFormat {
+        sample_rate: 44100,
+        channels: 1,
+    };
+    sink.queue_formats("cable", &[mono_44k]);
+    let clip = vec![0.5; 4800];
+    let duration = output
+        .play_samples(&clip, 24000, 1, &ids(&["speakers", "cable"]), no_fade())
+        .await
+        .unwrap();
+    played_out(duration).await;
+
+    assert_eq!(frames_at(&sink.samples("speakers"), 2, 0.5), 9600);
+    assert_eq!(frames_at(&sink.samples("cable"), 1, 0.5), 8820);
+}
+
+#[tokio::test]
+async fn test_streams_a_file_at_another_rate() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let clip = vec![0.5; 12000];
+    let wav = encode_wav(&clip, EncodeSpec::pcm16(24000, 1).with_format(WavSampleFormat::Float32)).unwrap();
+    let path = temp_file("24k.wav", &wav);
+    let playback = output.play_file_to_devices(&path, ids(&["device_null"]), None, None).await.unwrap();
+    assert_eq!(playback.finished().await, PlaybackEnd::Completed);
+    std::fs::remove_file(&path).unwrap();
+
+    let played = sink.samples("device_null");
+    let frames = played.chunks(2).rposition(|frame| frame[0] != 0.0).unwrap() + 1;
+    assert_eq!(frames, 24000);
+    // Past the fade in, every frame is the clip on both channels
+    assert_eq!(frames_at(&played[480..frames * 2], 2, 0.5), frames - 240);
+}
+
+#[tokio::test]
+async fn test_fades_in() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let clip = vec![1.0; 4800];
+    let options = PlaybackOptions::default();
+    let duration = output.play_samples(&clip, 48000, 1, &ids(&["device_null"]), options).await.unwrap();
+    played_out(duration).await;
+
+    let left: Vec<f32> = sink.samples("device_null").chunks(2).map(|frame| frame[0]).collect();
+    assert_eq!(left[0], 0.0);
+    assert!((left[120] - 0.5).abs() < 0.01);
+    assert!(left[..240].windows(2).all(|pair| pair[1] > pair[0]));
+    assert_eq!(left[240], 1.0);
+}
+
+#[tokio::test]
+async fn test_fades_out_at_the_end_in_device_frames() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    // 5 ms of a 24 kHz clip is 240 frames once at 48 kHz
+    let clip = vec![1.0; 2400];
+    let mut options = PlaybackOptions::default();
+    options.fade_in_ms = 0;
+    let duration = output.play_samples(&clip, 24000, 1, &ids(&["device_null"]), options).await.unwrap();
+    played_out(duration).await;
+
+    let left: Vec<f32> = sink.samples("device_null").chunks(2).map(|frame| frame[0]).collect();
+    assert_eq!(left[0], 1.0);
+    assert!(left[..4560].iter().all(|s| *s == 1.0));
+    assert!(left[4560..4800].windows(2).all(|pair| pair[1] < pair[0]));
+    assert!((left[4680] - 0.5).abs() < 0.01);
+    assert!(left[4799..].iter().all(|s| *s == 0.0));
+}
+
+#[tokio::test]
+async fn test_a_clip_shorter_than_its_fades_gets_a_triangle() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let mut options = PlaybackOptions::default();
+    options.fade_in_ms = 5;
+    options.fade_out_ms = 15;
+    let duration = output.play_samples(&[1.0; 480], 48000, 1, &ids(&["device_null"]), options).await.unwrap();
+    played_out(duration).await;
+
+    // Each fade gets its share: 120 frames up, 360 down
+    let left: Vec<f32> = sink.samples("device_null").chunks(2).map(|frame| frame[0]).collect();
+    assert_eq!(left[0], 0.0);
+    assert!(left[..120].windows(2).all(|pair| pair[1] > pair[0]));
+    assert!((left[120] - 1.0).abs() < 0.01);
+    assert!(left[120..480].windows(2).all(|pair| pair[1] < pair[0]));
+    assert!(left[479..].iter().all(|s| *s == 0.0));
+}
+
+#[tokio::test]
+async fn test_stop_fades_out() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let clip = vec![1.0; 48000];
+    let options = PlaybackOptions::default();
+    output.play_samples(&clip, 48000, 1, &ids(&["device_null"]), options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    output.stop_all_playback().unwrap();
+    assert!(output.is_stopped());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let recorded = sink.recorded();
+    let periods = recorded.len();
+    assert!(periods < 30, "kept playing for {} periods after the stop", periods);
+    let left: Vec<f32> = sink.samples("device_null").chunks(2).map(|frame| frame[0]).collect();
+    let end = left.iter().rposition(|s| *s != 0.0).unwrap();
+    // A ramp down to silence over STOP_FADE_MS, not a cut
+    assert!(left[end] < 0.01);
+    assert!(left[end - 475..=end].windows(2).all(|pair| pair[1] < pair[0]));
+}
+
+#[tokio::test]
+async fn test_stop_cancels_a_long_clip_on_every_device() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["speakers", "cable"]));
+    let clip = sine(440.0, 48000, 30.0);
+    let options = PlaybackOptions::default();
+    output.play_samples(&clip, 48000, 1, &ids(&["default", "cable"]), options).await.unwrap();
+    let playback = output.current_playback().unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let started = Instant::now();
+    output.stop_all_playback().unwrap();
+    let ended = tokio::time::timeout(Duration::from_secs(1), playback.finished()).await.unwrap();
+    assert!(started.elapsed() < Duration::from_millis(100), "took {:?} to stop", started.elapsed());
+    assert_eq!(ended, PlaybackEnd::Cancelled);
+    assert!(output.current_playback().is_none());
+
+    // Both devices played, and went quiet once the period with the fade was
+    // out
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let played = |device_id: &str| sink.samples(device_id).iter().filter(|s| **s != 0.0).count();
+    let (speakers, cable) = (played("speakers"), played("cable"));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!((played("speakers"), played("cable")), (speakers, cable));
+    assert!(speakers > 0 && cable > 0);
+
+    // Stopping again, with nothing playing, does nothing
+    output.stop_all_playback().unwrap();
+}
+
+#[tokio::test]
+async fn test_a_played_out_clip_completes() {
+    let (_, output) = null_output(NullSink::new(STEREO_48K));
+    output.play_samples(&[0.5; 2400], 48000, 1, &ids(&["device_null"]), no_fade()).await.unwrap();
+    let playback = output.current_playback().unwrap();
+    let ended = tokio::time::timeout(Duration::from_secs(1), playback.finished()).await.unwrap();
+    assert_eq!(ended, PlaybackEnd::Completed);
+    output.stop_all_playback().unwrap();
+    assert!(!output.is_stopped());
+}
+
+#[tokio::test]
+async fn test_a_lost_device_fails_the_playback() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["speakers", "headset"]));
+    output.play_samples(&vec![0.5; 48000], 48000, 1, &ids(&["speakers", "headset"]), no_fade()).await.unwrap();
+    let playback = output.current_playback().unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    sink.lose_device("headset");
+
+    let ended = tokio::time::timeout(Duration::from_secs(2), playback.finished()).await.unwrap();
+    assert_eq!(ended, PlaybackEnd::Failed);
+    assert_eq!(playback.lost_devices(), ids(&["headset"]));
+    // The device still there played the clip out, its last period maybe
+    // recorded after the playback finished
+    played_out(Duration::ZERO).await;
+    assert!(sink.samples("speakers").iter().filter(|s| **s == 0.5).count() >= 96000);
+}
+
+#[tokio::test]
+async fn test_a_device_gone_from_the_list_closes_its_stream() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["speakers", "headset"]));
+    assert!(!output.drop_removed_device("headset"));
+    output.play_samples(&vec![0.5; 9600], 48000, 1, &ids(&["speakers", "headset"]), no_fade()).await.unwrap();
+    let playback = output.current_playback().unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(output.drop_removed_device("headset"));
+
+    assert_eq!(playback.finished().await, PlaybackEnd::Failed);
+    assert_eq!(playback.lost_devices(), ids(&["headset"]));
+    played_out(Duration::ZERO).await;
+    assert!(sink.samples("headset").iter().filter(|s| **s == 0.5).count() < 19200);
+    assert_eq!(sink.samples("speakers").iter().filter(|s| **s == 0.5).count(), 19200);
+}
+
+#[test]
+fn test_tells_how_the_device_list_changed() {
+    let device = |id: &str, is_default: bool| AudioOutputDevice {
+        id: id.to_string(),
+        name: id.to_string(),
+        is_default,
+        transport: DeviceTransport::from_name(id),
+        state: DeviceState::Active,
+        default_sample_rate: 48000,
+        channels: 2,
+        endpoint_id: None,
+    };
+    let before = [device("speakers", true), device("headset", false)];
+    assert!(DeviceListChange::between(&before, &before).is_empty());
+
+    let after = [device("speakers", false), device("cable", true)];
+    let change = DeviceListChange::between(&before, &after);
+    assert_eq!(change.added, ids(&["cable"]));
+    assert_eq!(change.removed, ids(&["headset"]));
+    assert!(change.default_changed);
+    let change = DeviceListChange::between(&before, &[device("speakers", true)]);
+    assert!(!change.default_changed);
+}
+
+#[test]
+fn test_a_device_that_cant_play_counts_as_gone() {
+    let device = |id: &str, state: DeviceState| AudioOutputDevice {
+        id: id.to_string(),
+        name: id.to_string(),
+        is_default: false,
+        transport: DeviceTransport::Unknown,
+        state,
+        default_sample_rate: 0,
+        channels: 0,
+        endpoint_id: None,
+    };
+    let before = [device("speakers", DeviceState::Active), device("line_out", DeviceState::Unplugged)];
+    let after = [device("speakers", DeviceState::Disabled), device("line_out", DeviceState::Active)];
+    let change = DeviceListChange::between(&before, &after);
+    assert_eq!(change.added, ids(&["line_out"]));
+    assert_eq!(change.removed, ids(&["speakers"]));
+}
+
+#[test]
+fn test_serializes_a_device_with_its_state_and_format() {
+    let device = AudioOutputDevice {
+        id: "device_cable_input".to_string(),
+        name: "CABLE Input".to_string(),
+        is_default: false,
+        transport: DeviceTransport::Unknown,
+        state: DeviceState::Disabled,
+        default_sample_rate: 44100,
+        channels: 2,
+        endpoint_id: Some("{0.0.0.00000000}.{2d1c0f4a-7e5b-4f3c-9a1e-3b8d6c5e4f21}".to_string()),
+    };
+    assert_eq!(
+        serde_json::to_value(&device).unwrap(),
+        serde_json::json!({
+            "id": "device_cable_input",
+            "name": "CABLE Input",
+            "is_default": false,
+            "transport": "unknown",
+            "state": "disabled",
+            "default_sample_rate": 44100,
+            "channels": 2,
+            "endpoint_id": "{0.0.0.00000000}.{2d1c0f4a-7e5b-4f3c-9a1e-3b8d6c5e4f21}",
+        })
+    );
+    let unplugged = AudioOutputDevice {
+        state: DeviceState::Unplugged,
+        endpoint_id: None,
+        ..device
+    };
+    let value = serde_json::to_value(&unplugged).unwrap();
+    assert_eq!(value["state"], "unplugged");
+    assert!(value["endpoint_id"].is_null());
+}
+
+#[tokio::test]
+async fn test_lists_a_disabled_device_but_plays_only_to_active_ones() {
+    let (sink, output) = null_output(
+        NullSink::new(STEREO_48K)
+            .with_devices(&["speakers", "cable"])
+            .with_device_state("cable", DeviceState::Disabled),
+    );
+    let devices = output.list_output_devices().unwrap();
+    assert_eq!(devices.len(), 2);
+    assert_eq!(devices[0].state, DeviceState::Active);
+    assert_eq!((devices[0].default_sample_rate, devices[0].channels), (48000, 2));
+    assert_eq!(devices[1].state, DeviceState::Disabled);
+    assert!(!devices[1].is_active());
+
+    let err = output.play_samples(&[0.5; 480], 48000, 1, &ids(&["cable"]), no_fade()).await.unwrap_err();
+    assert_eq!(err.code(), "not_found");
+    output.play_samples(&[0.5; 480], 48000, 1, &ids(&["speakers", "cable"]), no_fade()).await.unwrap();
+    played_out(Duration::from_millis(10)).await;
+    assert_eq!(sink.opens("speakers"), 1);
+    assert_eq!(sink.opens("cable"), 0);
+}
+
+#[tokio::test]
+async fn test_new_playback_replaces_the_current_one() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    output.play_samples(&vec![1.0; 48000], 48000, 1, &ids(&["device_null"]), no_fade()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    output.play_samples(&vec![0.5; 4800], 48000, 1, &ids(&["device_null"]), no_fade()).await.unwrap();
+    assert!(!output.is_stopped());
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let ones = sink.samples("device_null").iter().filter(|s| **s == 1.0).count();
+    assert!(ones < 48000, "the first clip played out");
+}
+
+#[tokio::test]
+async fn test_pause_holds_the_position() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let clip: Vec<f32> = (0..24000).map(|n| 0.1 + n as f32 / 48000.0).collect();
+    let mut options = PlaybackOptions::default();
+    options.fade_out_ms = 0;
+    output.play_samples(&clip, 48000, 1, &ids(&["device_null"]), options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    output.pause_playback().unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let status = output.status();
+    assert_eq!(status.state, PlaybackState::Paused);
+    assert_eq!(status.duration_ms, 500);
+    let paused_at = status.position_ms;
+    assert!((80..=200).contains(&paused_at), "paused at {} ms", paused_at);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(output.status().position_ms, paused_at);
+
+    output.resume_playback().unwrap();
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(output.status().state, PlaybackState::Idle);
+
+    let left: Vec<f32> = sink.samples("device_null").chunks(2).map(|frame| frame[0]).collect();
+    // The fade-in starts from silence, so look past the first frame
+    let silent = left[1..].iter().skip_while(|s| **s != 0.0).take_while(|s| **s == 0.0).count();
+    assert!(silent >= 4800, "only {} silent frames while paused", silent);
+    // Picks up where it left off, having played everything once
+    let last = left.iter().rposition(|s| *s != 0.0).unwrap();
+    assert!((left[last] - clip[clip.len() - 1]).abs() < 1e-6);
+    let played = left.iter().filter(|s| **s != 0.0).count();
+    assert!((23990..=24000).contains(&played), "{} frames played", played);
+}
+
+#[tokio::test]
+async fn test_seek_jumps_every_device_to_the_same_frame() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["speakers", "cable"]));
+    let clip: Vec<f32> = (0..48000).map(|n| (n + 1) as f32 / 48000.0).collect();
+    output.play_samples(&clip, 48000, 1, &ids(&["speakers", "cable"]), no_fade()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    output.seek_playback(0.75).unwrap();
+    assert!(output.status().position_ms >= 750);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    output.stop_all_playback().unwrap();
+
+    for device_id in ["speakers", "cable"] {
+        let left: Vec<f32> = sink.samples(device_id).chunks(2).map(|frame| frame[0]).collect();
+        let jump = left.iter().position(|s| *s >= 0.75).unwrap();
+        assert!(left[jump - 1] < 0.5, "{} didn't jump", device_id);
+        assert_eq!(&left[jump..jump + 960], &clip[36000..36960], "{} landed elsewhere", device_id);
+    }
+}
+
+#[tokio::test]
+async fn test_seek_past_the_end_stops() {
+    let (_, output) = null_output(NullSink::new(STEREO_48K));
+    let error = output.seek_playback(0.1).unwrap_err();
+    assert_eq!(error.code(), "not_found");
+
+    output.play_samples(&vec![0.5; 48000], 48000, 1, &ids(&["device_null"]), no_fade()).await.unwrap();
+    let playback = output.current_playback().unwrap();
+    assert_eq!(output.seek_playback(-1.0).unwrap_err().code(), "invalid_argument");
+    output.seek_playback(5.0).unwrap();
+    let ended = tokio::time::timeout(Duration::from_secs(1), playback.finished()).await.unwrap();
+    assert_eq!(ended, PlaybackEnd::Cancelled);
+    assert_eq!(output.pause_playback().unwrap_err().code(), "not_found");
+    assert_eq!(output.resume_playback().unwrap_err().code(), "not_found");
+}
+
+#[tokio::test]
+async fn test_status_follows_the_playback() {
+    let (_, output) = null_output(NullSink::new(STEREO_48K));
+    assert_eq!(output.status().state, PlaybackState::Idle);
+    assert_eq!(output.pause_playback().unwrap_err().code(), "not_found");
+
+    output.play_samples(&[0.5; 48000], 48000, 1, &ids(&["device_null"]), no_fade()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let status = output.status();
+    assert_eq!(status.state, PlaybackState::Playing);
+    assert!(status.playing && !status.paused);
+    assert!(status.position_ms >= 150, "at {} ms", status.position_ms);
+    assert!(status.position_secs >= 0.15 && status.duration_secs == 1.0);
+    assert_eq!(status.device_ids, ["device_null"]);
+
+    output.toggle_pause().unwrap();
+    let status = output.status();
+    assert_eq!(status.state, PlaybackState::Paused);
+    assert!(!status.playing && status.paused);
+    output.toggle_pause().unwrap();
+    assert_eq!(output.status().state, PlaybackState::Playing);
+    output.stop_all_playback().unwrap();
+    let status = output.status();
+    assert_eq!(status.state, PlaybackState::Idle);
+    assert!(!status.playing && !status.paused && status.device_ids.is_empty());
+    assert!(output.current_playback().is_none());
+
+    // Playing out goes back to idle too
+    let duration = output.play_samples(&[0.5; 4800], 48000, 1, &ids(&["default"]), no_fade()).await.unwrap();
+    assert!(output.status().playing);
+    played_out(duration).await;
+    let status = output.status();
+    assert!(!status.playing && status.position_secs == 0.0 && status.duration_secs == 0.0);
+}
+
+#[tokio::test]
+async fn test_plays_to_each_selected_device() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["speakers", "headset", "hdmi"]));
+    let clip = vec![0.5; 2400];
+    let duration = output.play_samples(&clip, 48000, 1, &ids(&["speakers", "hdmi"]), no_fade()).await.unwrap();
+    played_out(duration).await;
+
+    assert!(skip_held(sink.samples("speakers"))[..4800].iter().all(|s| *s == 0.5));
+    assert!(skip_held(sink.samples("hdmi"))[..4800].iter().all(|s| *s == 0.5));
+    assert!(sink.samples("headset").is_empty());
+
+    let periods = sink.recorded();
+    let first = periods.iter().find(|period| period.device_id == "speakers").unwrap();
+    let second = periods.iter().filter(|period| period.device_id == "speakers").nth(1).unwrap();
+    assert_eq!(first.frames.len(), 960);
+    assert!(second.at >= first.at + Duration::from_millis(9), "periods are paced in real time");
+}
+
+/// When `device_id` was first heard in `periods`, given its latency: the
+/// period was pulled at its pulled_at and heard that much later.
+fn first_heard(periods: &[RecordedPeriod], device_id: &str, latency: Duration) -> Instant {
+    let (period, frame) = periods
+        .iter()
+        .filter(|period| period.device_id == device_id)
+        .find_map(|period| Some((period, period.frames.chunks(2).position(|frame| frame[0] != 0.0)?)))
+        .unwrap();
+    period.pulled_at + latency + Duration::from_secs_f64(frame as f64 / 48000.0)
+}
+
+#[tokio::test]
+async fn test_devices_start_together() {
+    let cable_latency = Duration::from_millis(25);
+    let sink = NullSink::new(STEREO_48K)
+        .with_devices(&["speakers", "cable"])
+        .with_open_delay(Duration::from_millis(40))
+        .with_latency("cable", cable_latency);
+    let (sink, output) = null_output(sink);
+    // The cable has played before, so its latency is known from the start
+    output.play_samples(&[0.5; 480], 48000, 1, &ids(&["cable"]), no_fade()).await.unwrap();
+    played_out(Duration::from_millis(400)).await;
+    let before = sink.recorded().len();
+
+    let duration = output.play_samples(&[0.5; 4800], 48000, 1, &ids(&["speakers", "cable"]), no_fade()).await.unwrap();
+    played_out(duration + Duration::from_millis(100)).await;
+
+    let periods = &sink.recorded()[before..];
+    let speakers = first_heard(periods, "speakers", Duration::ZERO);
+    let cable = first_heard(periods, "cable", cable_latency);
+    let apart = if speakers > cable { speakers - cable } else { cable - speakers };
+    assert!(apart <= Duration::from_millis(3), "{:?} apart", apart);
+    for device_id in ["speakers", "cable"] {
+        let played = periods
+            .iter()
+            .filter(|period| period.device_id == device_id)
+            .flat_map(|period| period.frames.iter())
+            .filter(|s| **s == 0.5)
+            .count();
+        assert_eq!(played, 9600, "{}", device_id);
+    }
+}
+
+#[tokio::test]
+async fn test_a_device_that_fails_to_open_is_left_out() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["speakers", "cable"]));
+    // The speakers' open and its retry both fail
+    sink.fail_opens(2);
+    let playback = output
+        .play_audio_to_devices(wav(&[0.5; 4800]), ids(&["speakers", "cable"]), None, no_fade(), None)
+        .await
+        .unwrap();
+    assert_eq!(playback.device_ids(), ["cable"]);
+    let results = playback.device_results();
+    assert_eq!(results[0].device_id, "speakers");
+    assert!(results[0].error.as_deref().unwrap().contains("speakers failed to open"));
+    assert!(results[1].error.is_none());
+    assert_eq!(playback.finished().await, PlaybackEnd::Completed);
+    played_out(Duration::ZERO).await;
+    assert!(sink.samples("speakers").is_empty());
+    assert!(sink.samples("cable").iter().filter(|s| **s == 0.5).count() > 9000);
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    sink.fail_opens(2);
+    let error = output
+        .play_audio_to_devices(wav(&[0.5; 4800]), ids(&["speakers", "cable"]), None, strict(), None)
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), "playback");
+    assert_eq!(sink.opens("cable"), 1);
+
+    // Left out everywhere, it fails all the same
+    sink.fail_opens(4);
+    let error = output
+        .play_audio_to_devices(wav(&[0.5; 4800]), ids(&["speakers", "cable"]), None, no_fade(), None)
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), "playback");
+    assert!(error.message().contains("speakers") && error.message().contains("cable"), "{}", error.message());
+}
+
+#[tokio::test]
+async fn test_reports_device_latency() {
+    let latency = Duration::from_millis(12);
+    let sink = NullSink::new(STEREO_48K).with_devices(&["speakers", "cable"]).with_latency("speakers", latency);
+    let (_, output) = null_output(sink);
+    assert_eq!(output.device_latency("speakers").unwrap(), None);
+    assert_eq!(output.device_latency("device_gone").unwrap_err().code(), "not_found");
+
+    let duration = output.play_samples(&[0.5; 480], 48000, 1, &ids(&["speakers", "cable"]), no_fade()).await.unwrap();
+    played_out(duration).await;
+    assert_eq!(output.device_latency("speakers").unwrap(), Some(latency));
+    assert_eq!(output.device_latency("default").unwrap(), Some(latency));
+    assert_eq!(output.device_latency("cable").unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_default_plays_on_the_default_device() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["speakers", "headset"]));
+    let duration = output.play_samples(&[0.5; 480], 48000, 1, &ids(&["default"]), no_fade()).await.unwrap();
+    assert_eq!(output.current_playback().unwrap().device_ids(), ["speakers"]);
+    played_out(duration).await;
+    assert!(!sink.samples("speakers").is_empty());
+    assert!(sink.samples("headset").is_empty());
+}
+
+#[tokio::test]
+async fn test_unknown_device_is_not_found() {
+    let (_, output) = null_output(NullSink::new(STEREO_48K));
+    let error = output.play_samples(&[0.0; 480], 48000, 1, &ids(&["device_gone"]), no_fade()).await.unwrap_err();
+    assert_eq!(error.code(), "not_found");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_plays_share_one_stream_per_device() {
+    // Mono at the clip's rate, so preparing the clips takes next to no time
+    let format = DeviceFormat {
+        sample_rate: 48000,
+        channels: 1,
+    };
+    let sink = NullSink::new(format)
+        .with_devices(&["speakers", "headset", "hdmi"])
+        .with_open_delay(Duration::from_millis(30));
+    let (sink, output) = null_output(sink);
+    let output = Arc::new(output);
+    let plays: Vec<_> = (0..50)
+        .map(|_| {
+            let output = output.clone();
+            tokio::spawn(async move {
+                let clip = vec![0.5; 48000];
+                output.play_samples(&clip, 48000, 1, &ids(&["speakers", "headset", "hdmi"]), no_fade()).await
+            })
+        })
+        .collect();
+    for play in plays {
+        // A second open of a device while one is under way fails as busy
+        play.await.unwrap().unwrap();
+    }
+    for device in ["speakers", "headset", "hdmi"] {
+        assert_eq!(sink.opens(device), 1, "{} was opened more than once", device);
+    }
+
+    // Earlier plays were replaced while they queued; the latest one plays
+    let latest = output.current_playback().unwrap();
+    assert_eq!(latest.device_results().len(), 3);
+    assert!(latest.device_results().iter().all(|result| !result.superseded && !result.retried));
+    output.stop_all_playback().unwrap();
+}
+
+#[tokio::test]
+async fn test_next_clip_joins_the_lingering_stream() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    output.play_samples(&[0.5; 480], 48000, 1, &ids(&["device_null"]), no_fade()).await.unwrap();
+    played_out(Duration::from_millis(10)).await;
+    output.play_samples(&[0.25; 480], 48000, 1, &ids(&["device_null"]), no_fade()).await.unwrap();
+    assert!(output.current_playback().unwrap().device_results()[0].reused_stream);
+    assert_eq!(sink.opens("device_null"), 1);
+    played_out(Duration::from_millis(10)).await;
+
+    let left: Vec<f32> = sink.samples("device_null").chunks(2).map(|frame| frame[0]).collect();
+    assert_eq!(left.iter().filter(|s| **s == 0.5).count(), 480);
+    assert_eq!(left.iter().filter(|s| **s == 0.25).count(), 480);
+}
+
+#[tokio::test]
+async fn test_failed_open_is_retried_once() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    sink.fail_opens(1);
+    output.play_samples(&[0.5; 480], 48000, 1, &ids(&["device_null"]), no_fade()).await.unwrap();
+    let result = output.current_playback().unwrap().device_results()[0].clone();
+    assert!(result.retried && !result.reused_stream);
+    assert_eq!(sink.opens("device_null"), 1);
+
+    // Once the stream has closed, two failures in a row are reported
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    sink.fail_opens(2);
+    let error = output.play_samples(&[0.5; 480], 48000, 1, &ids(&["device_null"]), no_fade()).await.unwrap_err();
+    assert_eq!(error.code(), "playback");
+    assert!(output.current_playback().is_none());
+}
+
+#[tokio::test]
+async fn test_move_crossfades_to_the_other_device() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["speakers", "headphones"]));
+    // Each frame's value tells where in the clip it is
+    let clip: Vec<f32> = (0..48000).map(|n| 0.1 + n as f32 / 96000.0).collect();
+    let mut options = PlaybackOptions::default();
+    options.gain = 0.5;
+    output.play_samples(&clip, 48000, 1, &ids(&["speakers"]), options).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let playback = output.current_playback().unwrap();
+    let result = output.move_playback(playback.id(), "speakers", "headphones").await.unwrap();
+    assert_eq!(result.device_id, "headphones");
+    assert_eq!(output.current_playback().unwrap().device_ids(), ["headphones"]);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    output.stop_all_playback().unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let left = |device_id: &str| -> Vec<f32> { sink.samples(device_id).chunks(2).map(|frame| frame[0]).collect() };
+    let (source, destination) = (left("speakers"), left("headphones"));
+    // Past the crossfade the destination plays at the clip's gain; its frames say where it picked up
+    let crossfade = 4800;
+    let start = ((destination[crossfade] / 0.5 - 0.1) * 96000.0).round() as usize - crossfade;
+    assert!((8000..=14400).contains(&start), "picked up at frame {}", start);
+    assert_eq!(destination[0], 0.0);
+    assert!((destination[crossfade / 2] - 0.25 * clip[start + crossfade / 2]).abs() < 1e-3);
+    assert!((destination[crossfade + 1] - 0.5 * clip[start + crossfade + 1]).abs() < 1e-6);
+
+    // The source fades out over the same frames, then closes
+    let end = source.iter().rposition(|s| *s != 0.0).unwrap();
+    assert!(end.abs_diff(start + crossfade) <= 600, "source ended at {}, picked up at {}", end, start);
+    assert!(source[end] < 0.01);
+    assert!(source[end - 4700..=end].windows(2).all(|pair| pair[1] < pair[0]));
+    assert_eq!(sink.opens("speakers"), 1);
+}
+
+#[tokio::test]
+async fn test_failed_move_keeps_playing_on_the_source() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["speakers", "headphones"]));
+    let duration = output.play_samples(&[0.5; 24000], 48000, 1, &ids(&["speakers"]), no_fade()).await.unwrap();
+    let id = output.current_playback().unwrap().id();
+    assert_eq!(output.move_playback(id + 1, "speakers", "headphones").await.unwrap_err().code(), "not_found");
+    assert_eq!(output.move_playback(id, "headphones", "speakers").await.unwrap_err().code(), "invalid_argument");
+    assert_eq!(output.move_playback(id, "speakers", "speakers").await.unwrap_err().code(), "invalid_argument");
+    assert_eq!(output.move_playback(id, "speakers", "device_gone").await.unwrap_err().code(), "not_found");
+
+    // The open and its retry both fail
+    sink.fail_opens(2);
+    let error = output.move_playback(id, "speakers", "headphones").await.unwrap_err();
+    assert_eq!(error.code(), "playback");
+    let playback = output.current_playback().unwrap();
+    assert_eq!(playback.device_ids(), ["speakers"]);
+    played_out(duration).await;
+
+    assert!(sink.samples("headphones").is_empty());
+    let left: Vec<f32> = sink.samples("speakers").chunks(2).map(|frame| frame[0]).collect();
+    assert_eq!(left.iter().filter(|s| **s == 0.5).count(), 24000);
+    assert!(playback.is_finished());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_move_keeps_the_position_steady() {
+    let (_, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["speakers", "headphones"]));
+    output.play_samples(&[0.5; 48000], 48000, 1, &ids(&["speakers"]), no_fade()).await.unwrap();
+    let playback = output.current_playback().unwrap();
+    let follow = playback.clone();
+    let positions = tokio::spawn(async move {
+        let mut positions = Vec::new();
+        let until = Instant::now() + Duration::from_millis(500);
+        while Instant::now() < until {
+            positions.push((Instant::now(), follow.position()));
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        positions
+    });
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    output.move_playback(playback.id(), "speakers", "headphones").await.unwrap();
+
+    let positions = positions.await.unwrap();
+    for pair in positions.windows(2) {
+        let ((then, before), (now, after)) = (pair[0], pair[1]);
+        assert!(after >= before, "went back from {:?} to {:?}", before, after);
+        // Never further ahead than the time that passed, give or take a period or two
+        assert!(after - before <= now - then + Duration::from_millis(25), "{:?} to {:?}", before, after);
+    }
+    let last = positions.last().unwrap().1;
+    assert!(last >= Duration::from_millis(400), "at {:?}", last);
+}
+
+#[test]
+fn test_classifies_bluetooth_profile_drops() {
+    assert_eq!(DeviceTransport::from_name("bluez_output.AC_80_0A.a2dp-sink"), DeviceTransport::Bluetooth);
+    assert_eq!(DeviceTransport::from_name("Headphones (WH-1000XM4 Hands-Free AG Audio)"), DeviceTransport::Bluetooth);
+    assert_eq!(DeviceTransport::from_name("Jane's AirPods Pro"), DeviceTransport::Bluetooth);
+    assert_eq!(DeviceTransport::from_name("Speakers (USB Audio Device)"), DeviceTransport::Usb);
+    assert_eq!(DeviceTransport::from_name("MacBook Pro Speakers"), DeviceTransport::Unknown);
+
+    let bluetooth = DeviceTransport::Bluetooth;
+    assert!(is_degraded_profile(bluetooth, 16000, Some(48000)));
+    assert!(is_degraded_profile(bluetooth, 8000, Some(44100)));
+    assert!(!is_degraded_profile(bluetooth, 48000, Some(48000)));
+    // Nothing to compare with, or never better than this
+    assert!(!is_degraded_profile(bluetooth, 16000, None));
+    assert!(!is_degraded_profile(bluetooth, 16000, Some(16000)));
+    // Only Bluetooth devices switch profiles
+    assert!(!is_degraded_profile(DeviceTransport::Usb, 16000, Some(48000)));
+}
+
+#[tokio::test]
+async fn test_waits_for_a_bluetooth_device_to_switch_back() {
+    let device = "bluetooth headphones";
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&[device]));
+    output.device_format(device).unwrap();
+
+    // The mic is freed while the first retry waits
+    sink.queue_formats(device, &[HFP_16K]);
+    let started = Instant::now();
+    output.play_samples(&[0.5; 480], 48000, 1, &ids(&[device]), no_fade()).await.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(250));
+    assert_eq!(sink.format_queries(device), 3);
+    let result = output.current_playback().unwrap().device_results()[0].clone();
+    assert!(!result.degraded_profile);
+    played_out(Duration::from_millis(10)).await;
+    assert_eq!(sink.recorded()[0].frames.len(), 480 * 2);
+}
+
+#[tokio::test]
+async fn test_plays_degraded_when_the_profile_sticks() {
+    let device = "bluetooth headphones";
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&[device]));
+    output.device_format(device).unwrap();
+    let degraded = METRICS.playback_degraded_profiles.get();
+
+    sink.queue_formats(device, &[HFP_16K; 3]);
+    let started = Instant::now();
+    output.play_samples(&[0.5; 480], 48000, 1, &ids(&[device]), no_fade()).await.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(750));
+    // The first query and two retries
+    assert_eq!(sink.format_queries(device), 4);
+    let result = output.current_playback().unwrap().device_results()[0].clone();
+    assert!(result.degraded_profile);
+    assert!(METRICS.playback_degraded_profiles.get() > degraded);
+    played_out(Duration::from_millis(10)).await;
+    assert_eq!(sink.recorded()[0].frames.len(), 160);
+}
+
+#[tokio::test]
+async fn test_low_rates_alone_are_not_retried() {
+    // A wired device, and a Bluetooth one never seen at a higher rate
+    let (sink, output) = null_output(NullSink::new(HFP_16K).with_devices(&["usb headset", "bluetooth speaker"]));
+    output.device_format("usb headset").unwrap();
+    sink.queue_formats("usb headset", &[HFP_16K]);
+    let started = Instant::now();
+    output
+        .play_samples(&[0.5; 480], 48000, 1, &ids(&["usb headset", "bluetooth speaker"]), no_fade())
+        .await
+        .unwrap();
+    assert!(started.elapsed() < Duration::from_millis(250));
+    assert_eq!((sink.format_queries("usb headset"), sink.format_queries("bluetooth speaker")), (2, 1));
+    let results = output.current_playback().unwrap().device_results().to_vec();
+    assert!(results.iter().all(|result| !result.degraded_profile));
+}
+
+#[tokio::test]
+async fn test_volumes_apply_per_device() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["headphones", "cable"]));
+    let playback = output
+        .play_audio_to_devices(
+            wav(&[0.75; 9600]),
+            ids(&["cable", "default"]),
+            Some(vec![2.0, 0.5]),
+            no_fade(),
+            None,
+        )
+        .await
+        .unwrap();
+    played_out(playback.duration()).await;
+
+    // Past the fade-in, the monitor plays at half and the cable soft-clips
+    let headphones = skip_held(sink.samples("headphones"));
+    assert!(headphones[960..9600].iter().all(|s| *s == 0.375));
+    let cable = skip_held(sink.samples("cable"));
+    assert!(cable[960..9600].iter().all(|s| *s > 0.9 && *s < 1.0), "{:?}", &cable[960..964]);
+}
+
+#[tokio::test]
+async fn test_meters_what_each_device_is_sent() {
+    let (_, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["speakers", "cable"]));
+    let playback = output
+        .play_audio_to_devices(
+            wav(&[0.5; 24000]),
+            ids(&["speakers", "cable"]),
+            Some(vec![1.0, 0.5]),
+            no_fade(),
+            None,
+        )
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+
+    // After volume, at the device's rate and channels
+    let (rms, peak) = playback.take_level("speakers").unwrap();
+    assert_eq!(peak, 0.5);
+    assert!(rms > 0.3 && rms <= 0.5, "rms {}", rms);
+    let (rms, peak) = playback.take_level("cable").unwrap();
+    assert_eq!(peak, 0.25);
+    assert!(rms > 0.15 && rms <= 0.25, "rms {}", rms);
+    assert_eq!(playback.take_level("headphones"), None);
+
+    // Once it has played out there's nothing more to meter
+    played_out(playback.duration()).await;
+    playback.take_level("speakers");
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(playback.take_level("speakers").unwrap().1, 0.0);
+}
+
+#[tokio::test]
+async fn test_playback_volume_changes_while_it_plays() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["headphones", "cable"]));
+    assert_eq!(output.set_playback_volume("cable", 0.5).unwrap_err().code(), "not_found");
+    let options = PlaybackOptions::default();
+    output.play_audio_to_devices(wav(&[0.5; 48000]), ids(&["default"]), None, options, None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    output.set_playback_volume("default", 0.2).unwrap();
+    assert_eq!(output.set_playback_volume("cable", 0.5).unwrap_err().code(), "invalid_argument");
+    assert_eq!(output.set_playback_volume("headphones", 2.5).unwrap_err().code(), "invalid_argument");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    output.stop_all_playback().unwrap();
+
+    let left: Vec<f32> = sink.samples("headphones").chunks(2).map(|frame| frame[0]).collect();
+    let quieter = 960 + left[960..].iter().position(|s| *s == 0.1).unwrap();
+    // Ramped down rather than cut, and kept playing
+    assert!(left[quieter - 150..quieter].iter().all(|s| *s > 0.1 && *s < 0.5));
+    assert!(left[quieter..quieter + 960].iter().all(|s| *s == 0.1));
+}
+
+#[tokio::test]
+async fn test_mismatched_volumes_play_nothing() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["headphones", "cable"]));
+    let devices = ids(&["headphones", "cable"]);
+    let error = output
+        .play_audio_to_devices(wav(&[0.5; 4800]), devices.clone(), Some(vec![1.0]), no_fade(), None)
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), "invalid_argument");
+    let error = output
+        .play_audio_to_devices(wav(&[0.5; 4800]), devices, Some(vec![1.0, -0.5]), no_fade(), None)
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), "invalid_argument");
+    assert!(output.current_playback().is_none());
+    assert_eq!(sink.opens("headphones") + sink.opens("cable"), 0);
+}
+
+#[tokio::test]
+async fn test_plays_flac() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let clip: Vec<f32> = sine(440.0, 48000, 0.2);
+    let flac = transcode(wav(&clip), ExportFormat::Flac).unwrap();
+    assert!(flac.starts_with(b"fLaC"));
+    let playback = output.play_audio_to_devices(flac, ids(&["device_null"]), None, no_fade(), None).await.unwrap();
+    assert_eq!(playback.duration(), Duration::from_millis(200));
+    played_out(playback.duration()).await;
+
+    // Past the fade-in, as close as 24 bits keep it
+    let left: Vec<f32> = sink.samples("device_null").chunks(2).map(|frame| frame[0]).collect();
+    for (played, source) in left[480..clip.len()].iter().zip(&clip[480..]) {
+        assert!((played - source).abs() < 1e-4, "{} for {}", played, source);
+    }
+}
+
+#[tokio::test]
+async fn test_plays_mp3() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let playback = output
+        .play_audio_to_devices(silent_mp3(20), ids(&["device_null"]), None, no_fade(), None)
+        .await
+        .unwrap();
+    let expected = 20.0 * 1152.0 / 44100.0;
+    let duration = playback.duration().as_secs_f64();
+    // Within a frame, which a decoder may hold back
+    assert!((duration - expected).abs() < 0.03, "{} s", duration);
+    played_out(playback.duration()).await;
+    assert!(sink.samples("device_null").len() >= (duration * 0.9 * 96000.0) as usize);
+}
+
+#[tokio::test]
+async fn test_undecodable_audio_names_its_container() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let devices = ids(&["device_null"]);
+    let mut ogg = b"OggS".to_vec();
+    ogg.extend_from_slice(&[0; 60]);
+    let error = output.play_audio_to_devices(ogg, devices.clone(), None, no_fade(), None).await.unwrap_err();
+    assert_eq!(error.code(), "invalid_argument");
+    assert!(error.message().contains("Ogg"), "{}", error.message());
+
+    let mut flac = transcode(wav(&[0.5; 4800]), ExportFormat::Flac).unwrap();
+    flac.truncate(20);
+    let error = output.play_audio_to_devices(flac, devices.clone(), None, no_fade(), None).await.unwrap_err();
+    assert!(error.message().contains("FLAC"), "{}", error.message());
+
+    let error = output.play_audio_to_devices(vec![7; 256], devices, None, no_fade(), None).await.unwrap_err();
+    assert!(error.message().contains("isn't WAV"), "{}", error.message());
+    assert_eq!(sink.opens("device_null"), 0);
+}
+
+#[tokio::test]
+async fn test_streams_a_file() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["headphones", "cable"]));
+    // Longer than the decoder reads ahead, so it has to keep up
+    let clip: Vec<f32> = sine(440.0, 48000, 3.0);
+    let path = temp_file("stream.flac", &transcode(wav(&clip), ExportFormat::Flac).unwrap());
+    let playback = output
+        .play_file_to_devices(&path, ids(&["headphones", "cable"]), Some(vec![1.0, 0.5]), None)
+        .await
+        .unwrap();
+    assert!(playback.is_streamed());
+    assert_eq!(playback.duration(), Duration::from_secs(3));
+    assert_eq!(playback.finished().await, PlaybackEnd::Completed);
+    std::fs::remove_file(&path).unwrap();
+    // The two finish in the same period, the other's maybe not recorded yet
+    played_out(Duration::ZERO).await;
+
+    // The clip's first frame is silent, so what is left starts on its second
+    let left = |device_id: &str| -> Vec<f32> {
+        skip_held(sink.samples(device_id)).chunks(2).map(|frame| frame[0]).collect()
+    };
+    let (headphones, cable) = (left("headphones"), left("cable"));
+    assert!(headphones.len() >= clip.len() - 1 && cable.len() >= clip.len() - 1);
+    for n in 480..clip.len() - 1 {
+        assert!((headphones[n] - clip[n + 1]).abs() < 1e-4, "{} for {} at {}", headphones[n], clip[n + 1], n);
+        assert!((cable[n] - clip[n + 1] / 2.0).abs() < 1e-4, "{} for {} at {}", cable[n], clip[n + 1], n);
+    }
+}
+
+#[tokio::test]
+async fn test_streamed_playback_does_not_seek() {
+    let (_, output) = null_output(NullSink::new(STEREO_48K));
+    let path = temp_file("seek.wav", &wav(&[0.5; 48000]));
+    let playback = output.play_file_to_devices(&path, ids(&["device_null"]), None, None).await.unwrap();
+    assert_eq!(output.seek_playback(0.5).unwrap_err().code(), "unsupported");
+    output.stop_all_playback().unwrap();
+    assert_eq!(playback.finished().await, PlaybackEnd::Cancelled);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_unplayable_files_open_no_device() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let devices = ids(&["device_null"]);
+    let missing = std::env::temp_dir().join("voicebox-playback-missing.wav");
+    let error = output.play_file_to_devices(&missing, devices.clone(), None, None).await.unwrap_err();
+    assert_eq!(error.code(), "not_found");
+
+    let path = temp_file("junk.wav", &[7; 256]);
+    let error = output.play_file_to_devices(&path, devices, None, None).await.unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(error.code(), "invalid_argument");
+    assert!(error.message().contains("isn't WAV"), "{}", error.message());
+    assert_eq!(sink.opens("device_null"), 0);
+    assert!(output.current_playback().is_none());
+}
+
+#[tokio::test]
+async fn test_plays_pushed_chunks_as_they_arrive() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let playback = output.begin_playback_stream(ids(&["device_null"]), 24000, 1, None, None).await.unwrap();
+    assert!(playback.is_streamed());
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    output.push_playback_chunk(playback.id(), &pcm_chunk(&[0.5; 2400])).unwrap();
+    // Runs out, plays silence, then picks up again with the next
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert!(!playback.is_finished());
+    assert_eq!(playback.underruns(), 1);
+    output.push_playback_chunk(playback.id(), &pcm_chunk(&[0.25; 2400])).unwrap();
+    output.end_playback_stream(playback.id()).unwrap();
+    assert_eq!(playback.finished().await, PlaybackEnd::Completed);
+    assert_eq!(playback.underruns(), 1);
+    assert_eq!(playback.position(), Duration::from_millis(200));
+
+    // Each chunk fades in from silence, at 48 kHz. The first's last frame
+    // waits for the second, to lean toward it
+    let left: Vec<f32> = sink.samples("device_null").chunks(2).map(|frame| frame[0]).collect();
+    let first = left.iter().position(|s| *s != 0.0).unwrap() - 1;
+    assert!(left[first + 240..first + 4799].iter().all(|s| *s == 0.5));
+    assert!(left[first + 4799..first + 4899].iter().all(|s| *s == 0.0));
+    let second = first + 4799 + left[first + 4799..].iter().position(|s| *s != 0.0).unwrap() - 1;
+    assert!(left[second + 1] < 0.01);
+    assert!(left[second + 240..second + 4801].iter().all(|s| *s == 0.25));
+    assert!(left[second + 4801..].iter().all(|s| *s == 0.0));
+}
+
+#[tokio::test]
+async fn test_playback_stream_takes_no_chunks_once_over() {
+    let (_, output) = null_output(NullSink::new(STEREO_48K));
+    let devices = ids(&["device_null"]);
+    let chunk = pcm_chunk(&[0.5; 480]);
+    assert_eq!(output.push_playback_chunk(42, &chunk).unwrap_err().code(), "not_found");
+
+    let ended = output.begin_playback_stream(devices.clone(), 48000, 2, None, None).await.unwrap();
+    let error = output.push_playback_chunk(ended.id(), &pcm_chunk(&[0.5; 3])).unwrap_err();
+    assert_eq!(error.code(), "invalid_argument");
+    let error = output.push_playback_chunk(ended.id(), "not base64!").unwrap_err();
+    assert_eq!(error.code(), "invalid_argument");
+    output.push_playback_chunk(ended.id(), &chunk).unwrap();
+    output.end_playback_stream(ended.id()).unwrap();
+    assert_eq!(output.push_playback_chunk(ended.id(), &chunk).unwrap_err().code(), "not_found");
+    assert_eq!(output.end_playback_stream(ended.id()).unwrap_err().code(), "not_found");
+
+    // Stopping playback stops the stream too, as a newer playback does
+    let stopped = output.begin_playback_stream(devices.clone(), 48000, 2, None, None).await.unwrap();
+    output.push_playback_chunk(stopped.id(), &chunk).unwrap();
+    output.stop_all_playback().unwrap();
+    assert_eq!(output.push_playback_chunk(stopped.id(), &chunk).unwrap_err().code(), "not_found");
+    assert_eq!(stopped.finished().await, PlaybackEnd::Cancelled);
+    let replaced = output.begin_playback_stream(devices.clone(), 48000, 2, None, None).await.unwrap();
+    output.play_audio_to_devices(wav(&[0.5; 4800]), devices, None, no_fade(), None).await.unwrap();
+    assert_eq!(output.push_playback_chunk(replaced.id(), &chunk).unwrap_err().code(), "not_found");
+}
+
+#[tokio::test]
+async fn test_queued_clips_play_in_turn_on_one_stream() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let devices = ids(&["device_null"]);
+    let first = output.queue_audio_for_playback(wav(&[0.5; 4800]), devices.clone(), None).await.unwrap();
+    assert!(first.started);
+    assert_eq!(first.index, 0);
+    let gap = Some(Duration::from_millis(50));
+    let second = output.queue_audio_for_playback(wav(&[0.25; 4800]), devices, gap).await.unwrap();
+    assert!(!second.started);
+    assert_eq!(second.index, 1);
+    assert!(second.playback.same_as(&first.playback));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(first.playback.queue_item(), Some(0));
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(first.playback.queue_item(), Some(1));
+    assert_eq!(first.playback.finished().await, PlaybackEnd::Completed);
+
+    // The gap is silence on the stream the first opened, and the second doesn't fade in
+    assert_eq!(sink.opens("device_null"), 1);
+    let left: Vec<f32> = sink.samples("device_null").chunks(2).map(|frame| frame[0]).collect();
+    let start = left.iter().position(|s| *s != 0.0).unwrap() - 1;
+    assert!(left[start + 240..start + 4800].iter().all(|s| *s == 0.5));
+    assert!(left[start + 4800..start + 7200].iter().all(|s| *s == 0.0));
+    assert!(left[start + 7200..start + 12000].iter().all(|s| *s == 0.25));
+    assert!(left[start + 12000..].iter().all(|s| *s == 0.0));
+}
+
+#[tokio::test]
+async fn test_queue_starts_anew_once_over() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K).with_devices(&["headphones", "cable"]));
+    let devices = ids(&["headphones"]);
+    let played = output.queue_audio_for_playback(wav(&[0.5; 2400]), devices.clone(), None).await.unwrap();
+    let error = output
+        .queue_audio_for_playback(wav(&[0.5; 2400]), ids(&["cable"]), None)
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), "invalid_argument");
+    assert_eq!(played.playback.finished().await, PlaybackEnd::Completed);
+    let next = output.queue_audio_for_playback(wav(&[0.5; 2400]), devices.clone(), None).await.unwrap();
+    assert!(next.started);
+    assert_eq!(next.index, 0);
+    assert!(!next.playback.same_as(&played.playback));
+
+    // A stop drops what's queued, and the next clip starts a queue of its own
+    output.queue_audio_for_playback(wav(&[0.5; 48000]), devices.clone(), None).await.unwrap();
+    output.stop_all_playback().unwrap();
+    assert_eq!(next.playback.finished().await, PlaybackEnd::Cancelled);
+    assert!(output.queue_audio_for_playback(wav(&[0.5; 2400]), devices, None).await.unwrap().started);
+    assert_eq!(sink.opens("cable"), 0);
+}
+
+#[tokio::test]
+async fn test_clearing_the_queue_keeps_the_clip_playing() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let devices = ids(&["device_null"]);
+    assert_eq!(output.clear_playback_queue().await, 0);
+    let playing = output.queue_audio_for_playback(wav(&[0.5; 9600]), devices.clone(), None).await.unwrap();
+    for _ in 0..2 {
+        output.queue_audio_for_playback(wav(&[0.25; 9600]), devices.clone(), None).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(output.clear_playback_queue().await, 2);
+    assert_eq!(playing.playback.finished().await, PlaybackEnd::Completed);
+
+    let left: Vec<f32> = sink.samples("device_null").chunks(2).map(|frame| frame[0]).collect();
+    assert_eq!(left.iter().filter(|s| **s == 0.5).count(), 9600 - 240);
+    let end = left.iter().rposition(|s| *s == 0.5).unwrap();
+    assert!(left[end + 1..].iter().all(|s| *s == 0.0));
+}
+
+#[tokio::test]
+async fn test_loops_without_a_gap() {
+    let (sink, output) = null_output(NullSink::new(STEREO_48K));
+    let clip: Vec<f32> = (0..2400).map(|n| n as f32 / 4800.0).collect();
+    let playback = output
+        .loop_audio_to_devices(wav(&clip), ids(&["device_null"]), None, no_fade(), Some(3), None)
+        .await
+        .unwrap();
+    assert!(playback.is_looped());
+    assert_eq!(playback.duration(), Duration::from_millis(50));
+    assert_eq!(playback.finished().await, PlaybackEnd::Completed);
+    assert_eq!(playback.iteration(), 2);
+
+    // Past the fade-in, each time through follows on from the last frame of the one before
+    let left: Vec<f32> = sink.samples("device_null").chunks(2).map(|frame| frame[0]).collect();
+    for (n, played) in left.iter().enumerate().take(7200).skip(240) {
+        assert_eq!(*played, clip[n % 2400], "at {}", n);
+    }
+    assert!(left[7200..].iter().all(|s| *s == 0.0));
+}
+
+#[tokio::test]
+async fn test_loops_until_stopped() {
+    let (_, output) = null_output(NullSink::new(STEREO_48K));
+    let devices = ids(&["device_null"]);
+    let error = output
+        .loop_audio_to_devices(wav(&[0.5; 2400]), devices.clone(), None, no_fade(), Some(0), None)
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), "invalid_argument");
+
+    let playback = output
+        .loop_audio_to_devices(wav(&[0.5; 2400]), devices, None, no_fade(), None, None)
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(180)).await;
+    assert!(!playback.is_finished());
+    assert!(playback.iteration() >= 2, "{}", playback.iteration());
+    assert!(playback.position() < playback.duration());
+    // A seek stays on the time through it's on
+    let iteration = playback.iteration();
+    output.seek_playback(0.01).unwrap();
+    assert_eq!(playback.iteration(), iteration);
+    assert_eq!(playback.position(), Duration::from_millis(10));
+    output.stop_all_playback().unwrap();
+    assert_eq!(playback.finished().await, PlaybackEnd::Cancelled);
+}
+
+#[test]
+fn test_a_playback_request_maps_onto_its_options() {
+    let request: PlaybackRequest = serde_json::from_str("{}").unwrap();
+    assert_eq!(request, PlaybackRequest::default());
+    assert_eq!(request.options(), PlaybackOptions::default());
+    assert!(!request.looped());
+
+    let request: PlaybackRequest =
+        serde_json::from_str(r#"{"title": "Take 2", "repeat_count": 3, "strict": true, "fade_in_ms": 0}"#).unwrap();
+    assert_eq!(request.title.as_deref(), Some("Take 2"));
+    assert!(request.looped());
+    let options = request.options();
+    assert_eq!((options.fade_in_ms, options.fade_out_ms, options.strict), (0, FADE_MS, true));
+
+    // A file or stream only plays once, with the default fades
+    assert_eq!(request.check_plays_once("A file").unwrap_err().code(), "invalid_argument");
+    let mut faded = PlaybackRequest::default();
+    faded.fade_out_ms = Some(50);
+    assert_eq!(faded.check_plays_once("A file").unwrap_err().code(), "invalid_argument");
+    let mut once = PlaybackRequest::default();
+    once.volumes = Some(vec![0.5]);
+    once.wait = true;
+    assert!(once.check_plays_once("A file").is_ok());
+}
+
+#[tokio::test]
+async fn test_rejects_undecodable_audio() {
+    let (_, output) = null_output(NullSink::new(STEREO_48K));
+    let error = output
+        .play_audio_to_devices(b"not a wav".to_vec(), ids(&["device_null"]), None, no_fade(), None)
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), "invalid_argument");
+}
+
+#[tokio::test]
+async fn test_plays_a_test_tone_at_the_device_rate() {
+    let format = DeviceFormat {
+        sample_rate: 44100,
+        channels: 1,
+    };
+    let (sink, output) = null_output(NullSink::new(format).with_devices(&["speakers", "cable"]));
+    let end = output.play_test_tone("cable", 0.2, Some(TONE_HZ)).await.unwrap();
+    assert_eq!(end, PlaybackEnd::Completed);
+    played_out(Duration::ZERO).await;
+
+    assert!(sink.samples("speakers").is_empty());
+    let played = sink.samples("cable");
+    assert_eq!(find_tone(&played, 44100, 1, TONE_HZ), Some(0));
+    // It fades in and out rather than clicking, and stays well short of full scale
+    assert!(played[0].abs() < 1e-6 && played[8819].abs() < 0.01);
+    let peak = played.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    assert!(peak > 0.2 && peak <= 0.25, "peak {}", peak);
+    assert!(played[8820..].iter().all(|s| *s == 0.0));
+}
+
+#[tokio::test]
+async fn test_test_tone_names_a_device_it_cant_play_on() {
+    let (sink, output) = null_output(
+        NullSink::new(STEREO_48K)
+            .with_devices(&["speakers", "cable"])
+            .with_device_state("cable", DeviceState::Unplugged),
+    );
+    let e = output.play_test_tone("headset", 0.1, None).await.unwrap_err();
+    assert_eq!(e.code(), "not_found");
+    assert!(e.message().contains("headset"));
+    let e = output.play_test_tone("cable", 0.1, None).await.unwrap_err();
+    assert!(e.message().contains("cable"));
+
+    sink.fail_opens(2);
+    let e = output.play_test_tone("speakers", 0.1, None).await.unwrap_err();
+    assert_eq!(e.code(), "playback");
+    assert!(e.message().contains("speakers"));
+}
+
+#[tokio::test]
+async fn test_rejects_test_tones_that_cant_play() {
+    let (sink, output) = null_output(NullSink::new(HFP_16K));
+    for (duration, frequency) in [(0.0, None), (-1.0, None), (f32::NAN, None), (31.0, None), (0.1, Some(9000.0))] {
+        let e = output.play_test_tone("device_null", duration, frequency).await.unwrap_err();
+        assert_eq!(e.code(), "invalid_argument");
+    }
+    assert_eq!(sink.opens("device_null"), 0);
+}
+
+#[test]
+fn test_finds_tone_onset() {
+    let mut captured = vec![0.0; 4800 * 2];
+    captured.extend(sine(TONE_HZ, 48000, 0.1).iter().flat_map(|s| [*s, *s]));
+    assert_eq!(find_tone(&captured, 48000, 2, TONE_HZ), Some(4800));
+}
+
+#[test]
+fn test_ignores_other_tones_and_silence() {
+    assert_eq!(find_tone(&vec![0.0; 9600], 48000, 1, TONE_HZ), None);
+    assert_eq!(find_tone(&sine(440.0, 48000, 0.2), 48000, 1, TONE_HZ), None);
+}
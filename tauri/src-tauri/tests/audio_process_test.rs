@@ -0,0 +1,201 @@
+// Runs op lists over generated WAVs and checks each op, their ordering, the
+// progress reported and that a failing list writes nothing:
+//   cargo test --test audio_process_test
+
+use std::io::Cursor;
+use voicebox::audio_export::{ExportError, ExportFormat};
+use voicebox::audio_process::{
+    self, AudioOp, ConvertFormat, Normalize, ProcessOutput, ProcessProgress, Processed, Resample, TrimSilence,
+};
+use voicebox::audio_util::{encode_wav, EncodeSpec, WavSampleFormat};
+
+/// `silence` ms of silence, `tone` ms of a 440 Hz tone at `amplitude` on the
+/// left channel and half of it on the right, then `silence` ms again.
+fn stereo_wav(sample_rate: u32, silence: u32, tone: u32, amplitude: f32) -> Vec<u8> {
+    let frames = |ms: u32| (sample_rate * ms / 1000) as usize;
+    let mut samples = vec![0.0; 2 * frames(silence)];
+    for n in 0..frames(tone) {
+        let s = amplitude * (2.0 * std::f32::consts::PI * 440.0 * n as f32 / sample_rate as f32).sin();
+        samples.extend_from_slice(&[s, s / 2.0]);
+    }
+    samples.extend(vec![0.0; 2 * frames(silence)]);
+    let spec = EncodeSpec::pcm16(sample_rate, 2).with_format(WavSampleFormat::Float32);
+    encode_wav(&samples, spec).unwrap()
+}
+
+fn run(data: &[u8], ops: &[AudioOp]) -> Result<Processed, ExportError> {
+    audio_process::process(data, Some("wav"), ops, &mut |_| {})
+}
+
+fn read(wav: &[u8]) -> (hound::WavSpec, Vec<f32>) {
+    let mut reader = hound::WavReader::new(Cursor::new(wav)).unwrap();
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().map(Result::unwrap).collect(),
+        hound::SampleFormat::Int => reader
+            .samples::<i32>()
+            .map(|s| s.unwrap() as f32 / ((1 << (spec.bits_per_sample - 1)) - 1) as f32)
+            .collect(),
+    };
+    (spec, samples)
+}
+
+fn peak(samples: &[f32]) -> f32 {
+    samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()))
+}
+
+const TRIM: AudioOp = AudioOp::TrimSilence(TrimSilence {
+    threshold: 0.01,
+    padding_ms: 0,
+});
+
+#[test]
+fn test_ops_apply_in_order() {
+    let data = stereo_wav(48000, 250, 500, 0.25);
+    let ops = [
+        TRIM,
+        AudioOp::Downmix,
+        AudioOp::Normalize(Normalize { peak: 0.5 }),
+        AudioOp::Resample(Resample { sample_rate: 16000 }),
+    ];
+    let mut stages = Vec::new();
+    let processed = audio_process::process(&data, Some("wav"), &ops, &mut |p: ProcessProgress| {
+        assert_eq!(p.steps, 6);
+        stages.push(p.stage);
+    })
+    .unwrap();
+    assert_eq!(stages, ["decode", "trim_silence", "downmix", "normalize", "resample", "encode"]);
+    assert_eq!((processed.sample_rate, processed.channels), (16000, 1));
+    assert_eq!(processed.format, ExportFormat::Wav);
+
+    let (spec, samples) = read(&processed.data);
+    assert_eq!((spec.sample_rate, spec.channels, spec.bits_per_sample), (16000, 1, 16));
+    // Only the tone is left, a little under its half second once trimmed
+    assert!((490..=500).contains(&(processed.frames * 1000 / 16000)), "{}", processed.frames);
+    assert!((peak(&samples) - 0.5).abs() < 0.01, "peak {}", peak(&samples));
+
+    // The same list on the same audio gives the same file
+    assert_eq!(run(&data, &ops).unwrap().data, processed.data);
+}
+
+#[test]
+fn test_order_changes_the_result() {
+    // A tone under the threshold until normalized
+    let data = stereo_wav(8000, 100, 200, 0.008);
+    let normalize = AudioOp::Normalize(Normalize { peak: 1.0 });
+    let normalize_first = run(&data, &[normalize, TRIM]).unwrap();
+    assert!((1590..=1600).contains(&normalize_first.frames), "{}", normalize_first.frames);
+    // Trimmed first, it is all silence and nothing is left
+    assert!(matches!(run(&data, &[TRIM, normalize]), Err(ExportError::InvalidSource { .. })));
+}
+
+#[test]
+fn test_convert_format_quantizes_where_it_stands() {
+    let data = stereo_wav(8000, 0, 100, 0.5);
+    let to = |format, sample_format| {
+        AudioOp::ConvertFormat(ConvertFormat {
+            format,
+            sample_format: Some(sample_format),
+        })
+    };
+
+    let float = run(&data, &[to(ExportFormat::Wav, WavSampleFormat::Float32)]).unwrap();
+    let (spec, samples) = read(&float.data);
+    assert_eq!((spec.sample_format, spec.bits_per_sample), (hound::SampleFormat::Float, 32));
+    assert_eq!(float.sample_format, WavSampleFormat::Float32);
+
+    // Rounded to 16 bits, then stored as float: the last conversion wins,
+    // but the earlier one's rounding stays
+    let ops = [
+        to(ExportFormat::Wav, WavSampleFormat::Int16),
+        to(ExportFormat::Wav, WavSampleFormat::Float32),
+    ];
+    let (_, rounded) = read(&run(&data, &ops).unwrap().data);
+    assert!(rounded.iter().all(|s| (s * 32767.0 - (s * 32767.0).round()).abs() < 1e-3));
+    assert!(samples.iter().zip(&rounded).any(|(a, b)| a != b));
+
+    let int24 = run(&data, &[to(ExportFormat::Wav, WavSampleFormat::Int24)]).unwrap();
+    assert_eq!(read(&int24.data).0.bits_per_sample, 24);
+}
+
+#[test]
+fn test_bad_parameters_are_refused_up_front() {
+    let bad = [
+        AudioOp::Normalize(Normalize { peak: 0.0 }),
+        AudioOp::Normalize(Normalize { peak: f32::NAN }),
+        AudioOp::Resample(Resample { sample_rate: 0 }),
+        AudioOp::TrimSilence(TrimSilence {
+            threshold: 2.0,
+            padding_ms: 0,
+        }),
+        AudioOp::ConvertFormat(ConvertFormat {
+            format: ExportFormat::Flac,
+            sample_format: Some(WavSampleFormat::Int24),
+        }),
+    ];
+    for op in bad {
+        assert!(audio_process::validate(&[AudioOp::Downmix, op]).is_err(), "{:?}", op);
+        // Refused before the source is decoded
+        let error = run(b"not audio", &[op]).unwrap_err().to_string();
+        assert!(!error.contains("decoded"), "{}", error);
+    }
+    assert!(audio_process::validate(&[TRIM, AudioOp::Downmix]).is_ok());
+}
+
+#[test]
+fn test_ops_parse_from_json() {
+    let ops: Vec<AudioOp> = serde_json::from_value(serde_json::json!([
+        { "op": "trim_silence" },
+        { "op": "normalize", "peak": 0.5 },
+        { "op": "downmix" },
+        { "op": "convert_format", "format": "flac" },
+    ]))
+    .unwrap();
+    assert!(matches!(ops[0], AudioOp::TrimSilence(TrimSilence { padding_ms: 0, .. })));
+    assert_eq!(ops[1], AudioOp::Normalize(Normalize { peak: 0.5 }));
+    assert_eq!(
+        ops[3],
+        AudioOp::ConvertFormat(ConvertFormat {
+            format: ExportFormat::Flac,
+            sample_format: None,
+        })
+    );
+
+    let output = serde_json::json!({ "kind": "path", "path": "/tmp/x.wav" });
+    let output: ProcessOutput = serde_json::from_value(output).unwrap();
+    assert!(matches!(output, ProcessOutput::Path { .. }));
+}
+
+#[test]
+fn test_results_are_saved_whole_or_not_at_all() {
+    let scratch = tempfile::tempdir().unwrap();
+    let dir = scratch.path();
+    let data = stereo_wav(8000, 50, 100, 0.5);
+
+    let processed = run(&data, &[AudioOp::Downmix]).unwrap();
+    let saved = audio_process::save(processed.clone(), &ProcessOutput::Library, dir).unwrap();
+    assert!(saved.path.starts_with(dir.join("imports")));
+    assert_eq!(std::fs::read(&saved.path).unwrap(), processed.data);
+    assert_eq!((saved.duration_ms, saved.channels), (200, 1));
+    // The same result is the same library entry
+    let again = audio_process::save(processed, &ProcessOutput::Library, dir).unwrap();
+    assert_eq!(again.path, saved.path);
+
+    let dest = dir.join("out.wav");
+    let output = ProcessOutput::Path { path: dest.clone() };
+    let silent = stereo_wav(8000, 100, 0, 0.0);
+    assert!(run(&silent, &[AudioOp::Downmix, TRIM])
+        .and_then(|processed| audio_process::save(processed, &output, dir))
+        .is_err());
+    assert!(!dest.exists());
+    let names: Vec<_> = std::fs::read_dir(dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(names, ["imports"]);
+
+    // Into a directory that doesn't exist: an error, and no temp file
+    let missing = ProcessOutput::Path {
+        path: dir.join("missing").join("out.wav"),
+    };
+    let processed = run(&data, &[]).unwrap();
+    assert!(audio_process::save(processed, &missing, dir).is_err());
+    assert!(!dir.join("missing").exists());
+}
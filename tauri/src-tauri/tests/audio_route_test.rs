@@ -0,0 +1,124 @@
+// Routes the synthetic capture backend live into NullSink devices, and
+// drives a route's buffer directly through resampling, underruns and
+// overruns:
+//   cargo test --test audio_route_test
+
+use std::sync::Arc;
+use std::time::Duration;
+use voicebox::audio_capture::{SyntheticBackend, SyntheticPattern};
+use voicebox::audio_output::{AudioOutputState, DeviceFormat, LiveSource, NullSink};
+use voicebox::audio_route::{AudioRoutes, CaptureSourceSpec, RouteBuffer, RouteState, MAX_BUFFER_MS};
+use voicebox::audio_selftest::find_tone;
+use voicebox::error::VoiceboxError;
+
+const STEREO_48K: DeviceFormat = DeviceFormat {
+    sample_rate: 48000,
+    channels: 2,
+};
+
+fn tone_routes() -> AudioRoutes {
+    let tone = SyntheticPattern::Sine {
+        frequency: 440.0,
+        amplitude: 0.5,
+    };
+    AudioRoutes::with_backend(Arc::new(SyntheticBackend::new(tone).with_format(44100, 1)))
+}
+
+/// `device_speakers` is the default output, `device_cable` a virtual one.
+fn null_output() -> (Arc<NullSink>, AudioOutputState) {
+    let sink = Arc::new(NullSink::new(STEREO_48K).with_devices(&["device_speakers", "device_cable"]));
+    (sink.clone(), AudioOutputState::with_backend(sink))
+}
+
+#[tokio::test]
+async fn test_route_forwards_capture_with_gain() {
+    let (sink, output) = null_output();
+    let routes = tone_routes();
+    let started = routes.start(&output, CaptureSourceSpec::System, "device_cable", -6.0).unwrap();
+    assert_eq!(started.state, RouteState::Running);
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    let status = routes.status(started.route_id).unwrap();
+    assert_eq!(status.source_sample_rate, Some(44100));
+    assert_eq!(status.destination_sample_rate, 48000);
+    assert!(status.buffered_ms <= status.buffer_target_ms + 30, "{:?}", status);
+
+    let stopped = routes.stop(started.route_id).unwrap();
+    assert_eq!(stopped.state, RouteState::Running);
+    assert!(matches!(routes.status(started.route_id), Err(VoiceboxError::NotFound { .. })));
+
+    // The 44.1 kHz mono tone arrives resampled on both channels, at half scale
+    let played = sink.samples("device_cable");
+    assert!(find_tone(&played, 48000, 2, 440.0).is_some());
+    let peak = played.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    assert!((peak - 0.25).abs() < 0.02, "peak {}", peak);
+    assert!(sink.samples("device_speakers").is_empty());
+}
+
+#[tokio::test]
+async fn test_route_refuses_feedback_and_bad_arguments() {
+    let (_sink, output) = null_output();
+    let routes = tone_routes();
+    // System audio is what plays on the default device
+    let feedback = routes.start(&output, CaptureSourceSpec::System, "device_speakers", 0.0);
+    assert!(matches!(feedback, Err(VoiceboxError::InvalidArgument { .. })));
+    let missing = routes.start(&output, CaptureSourceSpec::System, "device_missing", 0.0);
+    assert!(matches!(missing, Err(VoiceboxError::NotFound { .. })));
+    for gain_db in [f32::NAN, 100.0, -100.0] {
+        assert!(routes.start(&output, CaptureSourceSpec::System, "device_cable", gain_db).is_err());
+    }
+    assert!(routes.stop(1).is_err());
+}
+
+#[test]
+fn test_buffer_resamples_and_maps_channels() {
+    let buffer = RouteBuffer::new(STEREO_48K);
+    // One second of mono 44.1 kHz in 10 ms chunks, ramping so values differ
+    for chunk in 0..100 {
+        let frames: Vec<f32> = (0..441).map(|n| (chunk * 441 + n) as f32 / 44100.0).collect();
+        buffer.push(&frames, 44100, 1);
+    }
+    let stats = buffer.stats();
+    assert_eq!(stats.source_sample_rate, Some(44100));
+    // Bounded: past the target and its headroom the oldest audio goes
+    assert!(stats.buffered_ms <= stats.target_ms + 30, "{:?}", stats);
+    assert!(stats.overruns > 0);
+
+    let mut out = vec![0.0; 2 * 480];
+    buffer.pull(&mut out);
+    assert!(out.chunks(2).all(|frame| frame[0] == frame[1]), "mono plays on both sides");
+    // The ramp continues smoothly across chunk boundaries
+    let step = 44100.0 / 48000.0 / 44100.0;
+    for pair in out.chunks(2).collect::<Vec<_>>().windows(2) {
+        assert!((pair[1][0] - pair[0][0] - step).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn test_underruns_grow_the_buffer() {
+    let buffer = RouteBuffer::new(STEREO_48K);
+    let start = buffer.stats().target_ms;
+    let mut out = vec![1.0; 2 * 480];
+
+    // Nothing played until the target is buffered
+    buffer.push(&vec![0.5; 2 * 480], 48000, 2);
+    buffer.pull(&mut out);
+    assert!(out.iter().all(|s| *s == 0.0));
+    assert_eq!(buffer.stats().underruns, 0);
+
+    let frames = 48 * start as usize;
+    buffer.push(&vec![0.5; 2 * frames], 48000, 2);
+    // Drain it, then ask for more than there is
+    for _ in 0..(frames + 480) / 480 {
+        buffer.pull(&mut out);
+    }
+    buffer.pull(&mut out);
+    let stats = buffer.stats();
+    assert!(stats.underruns >= 1);
+    assert!(stats.target_ms > start);
+
+    for _ in 0..20 {
+        buffer.pull(&mut out);
+    }
+    assert!(buffer.stats().target_ms <= MAX_BUFFER_MS);
+}
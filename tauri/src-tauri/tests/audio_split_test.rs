@@ -0,0 +1,205 @@
+// Plans cuts at split points with and without a minimum segment length, and
+// splits WAVs (a synthetic capture at its markers, and encoded ramps in each
+// sample format) to check that the segments start on the exact frames and
+// put back together into the original sample for sample:
+//   cargo test --test audio_split_test
+
+use base64::Engine;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+use voicebox::audio_capture::{start_capture, stop_capture, AudioCaptureState, SyntheticBackend, SyntheticPattern};
+use voicebox::audio_split::{self, Segment, SplitPoint};
+use voicebox::audio_util::{encode_wav, EncodeSpec, WavSampleFormat};
+use voicebox::recording_metadata::{PauseInterval, RecordingMetadata};
+
+fn point(at_frames: u64, label: Option<&str>) -> SplitPoint {
+    SplitPoint {
+        at_frames,
+        label: label.map(str::to_string),
+    }
+}
+
+fn segment(start_frames: u64, end_frames: u64, label: Option<&str>) -> Segment {
+    Segment {
+        start_frames,
+        end_frames,
+        label: label.map(str::to_string),
+    }
+}
+
+/// Every sample of `wav`, as hound reads them, scaled to f32.
+fn samples(wav: &[u8]) -> (hound::WavSpec, Vec<f32>) {
+    let mut reader = hound::WavReader::new(Cursor::new(wav)).expect("a WAV hound can read");
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().map(Result::unwrap).collect(),
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader.samples::<i32>().map(|s| s.unwrap() as f32 / scale).collect()
+        }
+    };
+    (spec, samples)
+}
+
+/// Split `wav` and collect each segment's label and WAV.
+fn split(wav: &[u8], points: &[SplitPoint], min_frames: u64) -> Vec<(Segment, String, Vec<u8>)> {
+    let mut saved = Vec::new();
+    let count = audio_split::split_wav(Cursor::new(wav), points, min_frames, "Take", |segment, label, wav| {
+        saved.push((segment.clone(), label, wav));
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(count, saved.len());
+    saved
+}
+
+#[test]
+fn test_plan_cuts_at_each_point() {
+    let points = [point(300, Some("b")), point(100, Some("a")), point(100, Some("again")), point(1000, None)];
+    assert_eq!(
+        audio_split::plan(1000, &points, 0),
+        [segment(0, 100, None), segment(100, 300, Some("a")), segment(300, 1000, Some("b"))]
+    );
+    // A point at the start names the first segment
+    assert_eq!(
+        audio_split::plan(1000, &[point(0, Some("intro")), point(500, None)], 0),
+        [segment(0, 500, Some("intro")), segment(500, 1000, None)]
+    );
+    assert_eq!(audio_split::plan(1000, &[], 0), [segment(0, 1000, None)]);
+}
+
+#[test]
+fn test_plan_merges_short_segments_forward() {
+    let points = [point(50, Some("a")), point(80, Some("b")), point(500, Some("c")), point(950, Some("d"))];
+    assert_eq!(
+        audio_split::plan(1000, &points, 100),
+        // 0..50 and 50..80 run into 80..500; the short 950..1000 joins the one before
+        [segment(0, 500, Some("a")), segment(500, 1000, Some("c"))]
+    );
+    // Too short to split at all
+    assert_eq!(audio_split::plan(150, &points, 100), [segment(0, 150, Some("a"))]);
+}
+
+#[test]
+fn test_split_wav_in_each_format() {
+    let frames = 4801;
+    let ramp: Vec<f32> = (0..frames).flat_map(|n| [n as f32 / frames as f32, -0.5]).collect();
+    let points = [point(1200, Some("second")), point(3600, None)];
+    for format in [WavSampleFormat::Int16, WavSampleFormat::Int24, WavSampleFormat::Float32] {
+        let wav = encode_wav(&ramp, EncodeSpec::pcm16(48000, 2).with_format(format)).unwrap();
+        let (_, original) = samples(&wav);
+        let saved = split(&wav, &points, 0);
+
+        let labels: Vec<&str> = saved.iter().map(|(_, label, _)| label.as_str()).collect();
+        assert_eq!(labels, ["Take (1/3)", "second", "Take (3/3)"], "{:?}", format);
+        let mut joined = Vec::new();
+        for (segment, _, segment_wav) in &saved {
+            let (spec, segment_samples) = samples(segment_wav);
+            assert_eq!((spec.sample_rate, spec.channels, spec.bits_per_sample), (48000, 2, format.bits()));
+            assert_eq!(segment_samples.len() as u64, segment.frames() * 2, "{:?}", format);
+            // Starts on its frame, left channel first
+            assert_eq!(segment_samples[..2], original[segment.start_frames as usize * 2..][..2]);
+            joined.extend(segment_samples);
+        }
+        assert_eq!(joined, original, "{:?}", format);
+    }
+}
+
+#[test]
+fn test_split_wav_refuses_what_it_cant_read() {
+    let refuse = |wav: &[u8]| {
+        audio_split::split_wav(Cursor::new(wav), &[point(10, None)], 0, "Take", |_, _, _| Ok(())).unwrap_err()
+    };
+    assert_eq!(refuse(b"not a wav").code(), "invalid_argument");
+
+    let mut wav = Vec::new();
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 8000,
+        bits_per_sample: 8,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::new(Cursor::new(&mut wav), spec).unwrap();
+    for n in 0..100 {
+        writer.write_sample(n as i8).unwrap();
+    }
+    writer.finalize().unwrap();
+    assert_eq!(refuse(&wav).code(), "unsupported");
+}
+
+#[tokio::test]
+async fn test_capture_splits_at_its_markers() {
+    let tone = SyntheticPattern::Sine {
+        frequency: 440.0,
+        amplitude: 0.5,
+    };
+    let state = AudioCaptureState::with_backend(Arc::new(SyntheticBackend::new(tone)));
+    assert!(state.add_marker(None).is_none());
+
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    let first = state.add_marker(Some("chorus".to_string())).unwrap();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    let second = state.add_marker(None).unwrap();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    let audio = stop_capture(&state).await.unwrap();
+    assert!(state.add_marker(None).is_none());
+    assert!(first.at_frames > 0 && second.at_frames > first.at_frames);
+
+    let wav = base64::engine::general_purpose::STANDARD.decode(audio).unwrap();
+    let points: Vec<SplitPoint> = state
+        .markers()
+        .into_iter()
+        .map(|marker| SplitPoint {
+            at_frames: marker.at_frames,
+            label: marker.label,
+        })
+        .collect();
+    let saved = split(&wav, &points, 0);
+    let starts: Vec<u64> = saved.iter().map(|(segment, _, _)| segment.start_frames).collect();
+    assert_eq!(starts, [0, first.at_frames, second.at_frames]);
+    assert_eq!(saved[1].1, "chorus");
+
+    let (_, original) = samples(&wav);
+    let joined: Vec<f32> = saved.iter().flat_map(|(_, _, wav)| samples(wav).1).collect();
+    assert_eq!(joined, original);
+
+    // Markers belong to one capture
+    start_capture(&state, 10).await.unwrap();
+    assert!(state.markers().is_empty());
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    stop_capture(&state).await.unwrap();
+}
+
+#[test]
+fn test_segment_metadata_keeps_its_pauses() {
+    let metadata = RecordingMetadata {
+        sample_rate: 1000,
+        channels: 2,
+        duration_ms: 10_000,
+        fingerprint: Some("AAAAAA==".to_string()),
+        pauses: vec![
+            PauseInterval {
+                at_ms: 1000,
+                duration_ms: 500,
+            },
+            PauseInterval {
+                at_ms: 6000,
+                duration_ms: 2000,
+            },
+        ],
+        ..RecordingMetadata::default()
+    };
+    let narrowed = audio_split::segment_metadata(&metadata, &segment(4000, 10_000, None), 1000);
+    assert_eq!(narrowed.duration_ms, 6000);
+    assert_eq!(narrowed.fingerprint, None);
+    assert_eq!(
+        narrowed.pauses,
+        [PauseInterval {
+            at_ms: 2000,
+            duration_ms: 2000
+        }]
+    );
+    assert_eq!((narrowed.sample_rate, narrowed.channels), (1000, 2));
+}
@@ -0,0 +1,471 @@
+// Checks the WAV encoding and the sample helpers shared by capture, import and processing:
+//   cargo test --test audio_util_test
+
+use std::io::Cursor;
+use voicebox::audio_latency;
+use voicebox::audio_util::{
+    chirp, clip_stats, correlate, decode_samples, downmix, encode_wav, integrated_loudness, quantize, resample,
+    resample_sinc, trim_silence, voice_segments, ClipStats, EncodeSpec, RawSampleFormat, VadOptions, WavEncoder,
+    WavSampleFormat, CLIP_RUN_FRAMES, CLIP_THRESHOLD, VAD_PADDING_MS,
+};
+
+/// Deterministic so a failure reproduces.
+struct Xorshift(u64);
+
+impl Xorshift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Mostly within [-1, 1], with some out-of-range samples to exercise
+    /// clamping.
+    fn sample(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32 * 2.4 - 1.2
+    }
+}
+
+fn decode(wav: &[u8]) -> (hound::WavSpec, Vec<f32>) {
+    let mut reader = hound::WavReader::new(Cursor::new(wav)).expect("a WAV hound can read");
+    let spec = reader.spec();
+    let samples = match (spec.sample_format, spec.bits_per_sample) {
+        (hound::SampleFormat::Float, _) => reader.samples::<f32>().map(Result::unwrap).collect(),
+        (hound::SampleFormat::Int, 16) => reader
+            .samples::<i16>()
+            .map(|s| s.unwrap() as f32 / i16::MAX as f32)
+            .collect(),
+        (hound::SampleFormat::Int, 24) => reader
+            .samples::<i32>()
+            .map(|s| s.unwrap() as f32 / 8_388_607.0)
+            .collect(),
+        other => panic!("unexpected format {:?}", other),
+    };
+    (spec, samples)
+}
+
+#[test]
+fn test_round_trips_random_buffers() {
+    let mut rng = Xorshift(0x2545_F491_4F6C_DD1D);
+    let formats = [
+        (WavSampleFormat::Int16, 0.5 / i16::MAX as f32),
+        (WavSampleFormat::Int24, 0.5 / 8_388_607.0),
+        (WavSampleFormat::Float32, 0.0),
+    ];
+    for _ in 0..50 {
+        let channels = 1 + (rng.next() % 8) as u16;
+        let frames = (rng.next() % 2000) as usize;
+        let samples: Vec<f32> = (0..frames * channels as usize).map(|_| rng.sample()).collect();
+        for (format, tolerance) in formats {
+            let spec = EncodeSpec::pcm16(44100, channels).with_format(format);
+            let (decoded_spec, decoded) = decode(&encode_wav(&samples, spec).unwrap());
+            assert_eq!(decoded_spec.channels, channels);
+            assert_eq!(decoded_spec.sample_rate, 44100);
+            assert_eq!(decoded_spec.bits_per_sample, format.bits());
+            assert_eq!(decoded.len(), samples.len());
+
+            for (original, decoded) in samples.iter().zip(&decoded) {
+                if format == WavSampleFormat::Float32 {
+                    assert_eq!(original.to_bits(), decoded.to_bits());
+                } else {
+                    // Out-of-range samples come back clamped
+                    let error = (original.clamp(-1.0, 1.0) - decoded).abs();
+                    assert!(error <= tolerance * 1.01, "{:?}: {} came back as {}", format, original, decoded);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_ramp_in_each_format() {
+    // Past full scale at both ends, and through values halfway between steps
+    let ramp: Vec<f32> = (-1200..=1200).map(|i| i as f32 / 1000.0).collect();
+    for format in [WavSampleFormat::Int16, WavSampleFormat::Int24, WavSampleFormat::Float32] {
+        let wav = encode_wav(&ramp, EncodeSpec::pcm16(48000, 1).with_format(format)).unwrap();
+        let mut reader = hound::WavReader::new(Cursor::new(&wav)).unwrap();
+        assert_eq!(reader.spec().bits_per_sample, format.bits());
+        match format {
+            WavSampleFormat::Float32 => {
+                assert_eq!(reader.spec().sample_format, hound::SampleFormat::Float);
+                let read: Vec<f32> = reader.samples::<f32>().map(Result::unwrap).collect();
+                // Untouched, out-of-range samples included
+                assert!(read.iter().zip(&ramp).all(|(a, b)| a.to_bits() == b.to_bits()));
+            }
+            _ => {
+                assert_eq!(reader.spec().sample_format, hound::SampleFormat::Int);
+                let scale = if format == WavSampleFormat::Int16 { 32767.0 } else { 8_388_607.0 };
+                let read: Vec<i32> = reader.samples::<i32>().map(Result::unwrap).collect();
+                let expected: Vec<i32> = ramp.iter().map(|s| (s.clamp(-1.0, 1.0) * scale).round() as i32).collect();
+                assert_eq!(read, expected, "{:?}", format);
+            }
+        }
+    }
+    // Most of a 24-bit step rounds up to it rather than truncating to 0
+    let spec = EncodeSpec::pcm16(48000, 1).with_format(WavSampleFormat::Int24);
+    let wav = encode_wav(&[0.6 / 8_388_607.0, -0.6 / 8_388_607.0], spec).unwrap();
+    let mut reader = hound::WavReader::new(Cursor::new(wav)).unwrap();
+    assert_eq!(reader.samples::<i32>().map(Result::unwrap).collect::<Vec<_>>(), vec![1, -1]);
+
+    let formats: Vec<WavSampleFormat> = serde_json::from_str(r#"["pcm16", "pcm24", "float32", "int24"]"#).unwrap();
+    let expected = [WavSampleFormat::Int16, WavSampleFormat::Int24, WavSampleFormat::Float32, WavSampleFormat::Int24];
+    assert_eq!(formats, expected);
+}
+
+#[test]
+fn test_streaming_matches_one_shot() {
+    let mut rng = Xorshift(7);
+    let samples: Vec<f32> = (0..6 * 1000).map(|_| rng.sample()).collect();
+    let spec = EncodeSpec::pcm16(48000, 6).with_format(WavSampleFormat::Int24);
+
+    // Leading bytes stand in for a file the WAV is written into the middle of
+    let after_prefix = || {
+        let mut cursor = Cursor::new(b"prefix".to_vec());
+        cursor.set_position(6);
+        cursor
+    };
+    let mut encoder = WavEncoder::new(after_prefix(), spec).unwrap();
+    encoder.write(&samples).unwrap();
+    let mut streamed = WavEncoder::new(after_prefix(), spec).unwrap();
+    for chunk in samples.chunks(7) {
+        streamed.write(chunk).unwrap();
+    }
+    let one_shot = encoder.finish().unwrap().into_inner();
+    let streamed = streamed.finish().unwrap().into_inner();
+    assert_eq!(streamed, one_shot);
+    assert_eq!(&streamed[6..], &encode_wav(&samples, spec).unwrap()[..]);
+}
+
+#[test]
+fn test_extensible_header_for_surround() {
+    // 5.1: FL, FR, FC, LFE, side left and right
+    let spec = EncodeSpec::pcm16(48000, 6).with_channel_mask(0x60F);
+    let wav = encode_wav(&[0.0; 12], spec).unwrap();
+    assert_eq!(&wav[20..22], &0xFFFEu16.to_le_bytes());
+    assert_eq!(&wav[40..44], &0x60Fu32.to_le_bytes());
+    // KSDATAFORMAT_SUBTYPE_PCM
+    assert_eq!(&wav[44..46], &1u16.to_le_bytes());
+
+    let default_mask = encode_wav(&[0.0; 12], EncodeSpec::pcm16(48000, 6)).unwrap();
+    assert_eq!(&default_mask[40..44], &0x3Fu32.to_le_bytes());
+}
+
+#[test]
+fn test_stereo_int16_uses_the_plain_header() {
+    let wav = encode_wav(&[0.25, -0.25], EncodeSpec::pcm16(44100, 2)).unwrap();
+    assert_eq!(wav.len(), 44 + 4);
+    assert_eq!(&wav[20..22], &1u16.to_le_bytes());
+}
+
+#[test]
+fn test_odd_length_data_is_padded() {
+    let spec = EncodeSpec::pcm16(8000, 1).with_format(WavSampleFormat::Int24);
+    let wav = encode_wav(&[0.5], spec).unwrap();
+    assert_eq!(wav.len() % 2, 0);
+    let riff_size = u32::from_le_bytes(wav[4..8].try_into().unwrap()) as usize;
+    assert_eq!(riff_size + 8, wav.len());
+    assert_eq!(decode(&wav).1.len(), 1);
+}
+
+#[test]
+fn test_rejects_bad_specs_and_partial_frames() {
+    assert!(encode_wav(&[], EncodeSpec::pcm16(0, 2)).is_err());
+    assert!(encode_wav(&[], EncodeSpec::pcm16(44100, 0)).is_err());
+    assert!(encode_wav(&[0.0; 2], EncodeSpec::pcm16(44100, 2).with_channel_mask(0x7)).is_err());
+    assert!(encode_wav(&[0.0; 3], EncodeSpec::pcm16(44100, 2)).is_err());
+}
+
+#[test]
+fn test_quantize_matches_the_encoder() {
+    let mut rng = Xorshift(0x5eed_2024);
+    let samples: Vec<f32> = (0..2048).map(|_| rng.sample()).collect();
+    for format in [WavSampleFormat::Int16, WavSampleFormat::Int24] {
+        let mut quantized = samples.clone();
+        quantize(&mut quantized, format);
+        let spec = EncodeSpec::pcm16(48000, 2).with_format(format);
+        assert_eq!(decode(&encode_wav(&quantized, spec).unwrap()).1, quantized);
+        assert_eq!(encode_wav(&quantized, spec).unwrap(), encode_wav(&samples, spec).unwrap());
+    }
+    let mut float = samples.clone();
+    quantize(&mut float, WavSampleFormat::Float32);
+    assert_eq!(float, samples);
+}
+
+#[test]
+fn test_trim_silence_keeps_whole_frames() {
+    let samples = [0.0, 0.0, 0.0, 0.5, 0.2, 0.0, 0.0, 0.0, 0.0, 0.0];
+    assert_eq!(trim_silence(&samples, 2, 0.1, 0), &[0.0, 0.5, 0.2, 0.0]);
+    assert_eq!(trim_silence(&samples, 2, 0.1, 1), &samples[..8]);
+    assert_eq!(trim_silence(&samples, 2, 0.1, 10), &samples);
+    assert!(trim_silence(&samples, 2, 0.6, 0).is_empty());
+    assert!(trim_silence(&[], 2, 0.1, 0).is_empty());
+}
+
+#[test]
+fn test_resample_and_downmix() {
+    let ramp: Vec<f32> = (0..8).flat_map(|n| [n as f32, -(n as f32)]).collect();
+    let up = resample(&ramp, 2, 8000, 16000);
+    assert_eq!(up.len(), 32);
+    assert_eq!(&up[..6], &[0.0, 0.0, 0.5, -0.5, 1.0, -1.0]);
+    assert_eq!(resample(&ramp, 2, 8000, 4000), [0.0, 0.0, 2.0, -2.0, 4.0, -4.0, 6.0, -6.0]);
+    assert_eq!(resample(&ramp, 2, 8000, 8000), ramp);
+    assert!(downmix(&ramp, 2).iter().all(|s| *s == 0.0));
+    assert_eq!(downmix(&[0.2, 0.4, 0.6], 3), [0.4]);
+}
+
+#[test]
+fn test_clip_stats_count_frames_and_runs() {
+    // A sine driven past full scale and clamped, the way loopback clips
+    let clipped: Vec<f32> = (0..4800)
+        .flat_map(|n| {
+            let s = (2.0 * (std::f32::consts::TAU * 100.0 * n as f32 / 48000.0).sin()).clamp(-1.0, 1.0);
+            [s, s * 0.5]
+        })
+        .collect();
+    let stats = clip_stats(&clipped, 2);
+    assert_eq!(stats.total_frames, 4800);
+    // Over half a sine at twice full scale sits at the rails
+    assert!((60.0..70.0).contains(&stats.percentage()), "{:?}", stats);
+    assert!(stats.longest_run > CLIP_RUN_FRAMES && stats.longest_run < 240, "{:?}", stats);
+    assert!(stats.detected());
+
+    // A lone full-scale peak, and one just under the threshold, aren't clipping
+    let mut peaks = vec![0.5f32; 100];
+    peaks[10] = -1.0;
+    peaks[50] = CLIP_THRESHOLD - 0.001;
+    let stats = clip_stats(&peaks, 1);
+    assert_eq!((stats.clipped_frames, stats.longest_run), (1, 1));
+    assert!(!stats.detected());
+    assert_eq!(stats.percentage(), 1.0);
+
+    // A run carries across deliveries, and unclipped frames end it
+    let mut stats = ClipStats::default();
+    stats.add(&[0.0, 1.0, 1.0], 1);
+    stats.add(&[-1.0, 0.2], 1);
+    assert_eq!((stats.clipped_frames, stats.longest_run), (3, 3));
+    stats.add(&[1.0, 1.0], 1);
+    stats.add_unclipped(10);
+    stats.add(&[1.0], 1);
+    assert_eq!((stats.total_frames, stats.clipped_frames, stats.longest_run), (18, 6, 3));
+    assert_eq!(clip_stats(&[], 2), ClipStats::default());
+    assert_eq!(ClipStats::default().percentage(), 0.0);
+}
+
+/// `reference` scaled by `gain` and starting `delay` samples in, with
+/// `after` more samples following, all under noise at `noise` of full scale.
+fn delayed(reference: &[f32], delay: usize, after: usize, gain: f32, noise: f32, rng: &mut Xorshift) -> Vec<f32> {
+    (0..delay + reference.len() + after)
+        .map(|n| {
+            let copy = n.checked_sub(delay).and_then(|i| reference.get(i)).copied().unwrap_or(0.0);
+            copy * gain + rng.sample() * noise
+        })
+        .collect()
+}
+
+#[test]
+fn test_correlate_finds_a_delayed_chirp() {
+    let reference = chirp(48000, 200, 300.0, 6000.0, 0.5);
+    assert_eq!(reference.len(), 9600);
+    assert!(reference.iter().all(|s| s.abs() <= 0.5));
+    assert_eq!((reference[0], *reference.last().unwrap()), (0.0, 0.0));
+
+    let mut rng = Xorshift(0x9e37_79b9_7f4a_7c15);
+    for (delay, gain, noise) in [(0, 1.0, 0.0), (1234, 0.3, 0.02), (7001, -0.5, 0.05), (20000, 0.05, 0.02)] {
+        let signal = delayed(&reference, delay, 3000, gain, noise, &mut rng);
+        let peak = correlate(&signal, &reference).unwrap();
+        assert_eq!(peak.lag, delay, "gain {} noise {}", gain, noise);
+        assert!(peak.correlation > 0.5, "{:?}", peak);
+        assert!(audio_latency::is_detected(&peak), "{:?}", peak);
+    }
+}
+
+#[test]
+fn test_correlate_without_the_chirp() {
+    let reference = chirp(48000, 200, 300.0, 6000.0, 0.5);
+    let mut rng = Xorshift(0x2545_f491_4f6c_dd1d);
+    let noise = delayed(&[], 0, 30000, 0.0, 0.3, &mut rng);
+    let peak = correlate(&noise, &reference).unwrap();
+    assert!(!audio_latency::is_detected(&peak), "{:?}", peak);
+
+    assert_eq!(correlate(&vec![0.0; 30000], &reference), None);
+    assert_eq!(correlate(&noise, &vec![0.0; 9600]), None);
+    assert_eq!(correlate(&noise[..9599], &reference), None);
+}
+
+fn sine(rate: u32, frequency: f32, frames: usize, channels: u16) -> Vec<f32> {
+    (0..frames)
+        .flat_map(|i| {
+            let s = 0.5 * (std::f32::consts::TAU * frequency * i as f32 / rate as f32).sin();
+            std::iter::repeat_n(s, channels as usize)
+        })
+        .collect()
+}
+
+/// Frequency of a sine from its upward zero crossings, away from the ends.
+fn frequency(samples: &[f32], rate: u32) -> f32 {
+    let middle = &samples[samples.len() / 10..samples.len() * 9 / 10];
+    let crossings: Vec<usize> = (1..middle.len()).filter(|&i| middle[i - 1] < 0.0 && middle[i] >= 0.0).collect();
+    let cycles = (crossings.len() - 1) as f32;
+    cycles * rate as f32 / (crossings[crossings.len() - 1] - crossings[0]) as f32
+}
+
+#[test]
+fn test_resample_sinc_keeps_the_pitch() {
+    for (from, to, channels) in [(44100, 24000, 1), (48000, 16000, 2), (16000, 48000, 1), (44100, 48000, 2)] {
+        let input = sine(from, 1000.0, from as usize, channels);
+        let output = resample_sinc(&input, channels, from, to);
+        let frames = output.len() / channels as usize;
+        assert!((frames as i64 - to as i64).abs() <= 1, "{} -> {}: {} frames", from, to, frames);
+
+        let left: Vec<f32> = output.iter().step_by(channels as usize).copied().collect();
+        let found = frequency(&left, to);
+        assert!((found - 1000.0).abs() < 1.0, "{} -> {}: {} Hz", from, to, found);
+        // Away from the ends the level survives
+        let peak = left[frames / 10..frames * 9 / 10].iter().fold(0.0f32, |m, s| m.max(s.abs()));
+        assert!((peak - 0.5).abs() < 0.01, "{} -> {}: peak {}", from, to, peak);
+        if channels == 2 {
+            assert!(output.chunks(2).all(|f| f[0] == f[1]));
+        }
+    }
+    assert_eq!(resample_sinc(&[0.25, -0.25], 1, 48000, 48000), vec![0.25, -0.25]);
+    assert!(resample_sinc(&[], 2, 48000, 16000).is_empty());
+}
+
+#[test]
+fn test_resample_sinc_filters_what_the_new_rate_cant_hold() {
+    // 10 kHz is above the 8 kHz a 16 kHz rate can hold, so it should go rather than fold down to 6 kHz
+    let input = sine(48000, 10000.0, 48000, 1);
+    let output = resample_sinc(&input, 1, 48000, 16000);
+    let middle = &output[1600..14400];
+    let rms = (middle.iter().map(|s| s * s).sum::<f32>() / middle.len() as f32).sqrt();
+    assert!(rms < 0.005, "rms {}", rms);
+}
+
+#[test]
+fn test_integrated_loudness_of_sines() {
+    // A full-scale 997 Hz sine in one channel is -3.01 LUFS, the reference
+    for (amplitude, channels, expected) in [(1.0, 1, -3.01), (0.1, 1, -23.01), (0.1, 2, -20.0), (0.5, 2, -6.02)] {
+        // sine is at half scale
+        let samples: Vec<f32> = sine(48000, 997.0, 48000 * 3, channels).iter().map(|s| s * 2.0 * amplitude).collect();
+        let loudness = integrated_loudness(&samples, channels, 48000).unwrap();
+        assert!((loudness - expected).abs() < 0.1, "{} x {}: {} LUFS", amplitude, channels, loudness);
+    }
+    // The same at another rate
+    let samples: Vec<f32> = sine(44100, 997.0, 44100 * 3, 1).iter().map(|s| s * 0.2).collect();
+    assert!((integrated_loudness(&samples, 1, 44100).unwrap() + 23.01).abs() < 0.1);
+
+    // Silence is gated out, so only the blocks across the end of the tone
+    // pull it down, by a fraction of a dB
+    let mut gapped = samples.clone();
+    gapped.extend(vec![0.0; 44100 * 3]);
+    let loudness = integrated_loudness(&gapped, 1, 44100).unwrap();
+    assert!(loudness < -23.01 && loudness > -23.5, "{} LUFS", loudness);
+
+    assert_eq!(integrated_loudness(&vec![0.0; 48000], 1, 48000), None);
+    assert_eq!(integrated_loudness(&samples[..10000], 1, 44100), None);
+}
+
+#[test]
+fn test_decode_raw_samples() {
+    let expected = [0.0, 0.5, -0.5, -1.0, 0.25];
+    let float: Vec<u8> = expected.iter().flat_map(|s: &f32| s.to_le_bytes()).collect();
+    let int16: Vec<u8> = [0i16, 16384, -16384, i16::MIN, 8192].iter().flat_map(|s| s.to_le_bytes()).collect();
+    let int24: Vec<u8> = [0i32, 0x40_0000, -0x40_0000, -0x80_0000, 0x20_0000]
+        .iter()
+        .flat_map(|s| s.to_le_bytes()[..3].to_vec())
+        .collect();
+    let int32: Vec<u8> = [0i32, 0x4000_0000, -0x4000_0000, i32::MIN, 0x2000_0000]
+        .iter()
+        .flat_map(|s| s.to_le_bytes())
+        .collect();
+    for (bytes, format) in [
+        (float, RawSampleFormat::Float32),
+        (int16, RawSampleFormat::Int16),
+        (int24, RawSampleFormat::Int24),
+        (int32, RawSampleFormat::Int32),
+    ] {
+        assert_eq!(bytes.len(), expected.len() * format.bytes());
+        assert_eq!(decode_samples(&bytes, format), expected, "{:?}", format);
+        // A partial sample at the end is dropped
+        assert_eq!(decode_samples(&bytes[..bytes.len() - 1], format), expected[..4], "{:?}", format);
+    }
+    // The most positive 24-bit sample stays just under full scale
+    let top = decode_samples(&[0xff, 0xff, 0x7f], RawSampleFormat::Int24)[0];
+    assert!(top < 1.0 && top > 0.9999, "{}", top);
+
+    assert_eq!(RawSampleFormat::from_wave(true, 32, 32), Some(RawSampleFormat::Float32));
+    assert_eq!(RawSampleFormat::from_wave(false, 16, 16), Some(RawSampleFormat::Int16));
+    assert_eq!(RawSampleFormat::from_wave(false, 24, 24), Some(RawSampleFormat::Int24));
+    assert_eq!(RawSampleFormat::from_wave(false, 32, 24), Some(RawSampleFormat::Int32));
+    assert_eq!(RawSampleFormat::from_wave(false, 32, 32), Some(RawSampleFormat::Int32));
+    assert_eq!(RawSampleFormat::from_wave(true, 64, 64), None);
+    assert_eq!(RawSampleFormat::from_wave(false, 8, 8), None);
+}
+
+/// Stereo bursts of tone and silence at 48 kHz, `(loud, secs)` in order.
+fn bursts(parts: &[(bool, f64)]) -> Vec<f32> {
+    parts
+        .iter()
+        .flat_map(|&(loud, secs)| {
+            let frames = (secs * 48000.0).round() as usize;
+            if loud {
+                sine(48000, 440.0, frames, 2)
+            } else {
+                vec![0.0; frames * 2]
+            }
+        })
+        .collect()
+}
+
+fn segment_secs(samples: &[f32], options: VadOptions) -> Vec<(f64, f64)> {
+    let secs = |frames: usize| (frames as f64 / 48000.0 * 100.0).round() / 100.0;
+    voice_segments(samples, 2, 48000, options)
+        .into_iter()
+        .map(|frames| (secs(frames.start), secs(frames.end)))
+        .collect()
+}
+
+#[test]
+fn test_voice_segments_of_tone_bursts() {
+    let samples = bursts(&[
+        (true, 0.5),
+        (false, 0.3),
+        (true, 0.5),
+        // Too short a gap to split on
+        (false, 0.06),
+        (true, 0.4),
+        (false, 0.3),
+        // Too short to keep, merged into the nearer burst before it
+        (true, 0.1),
+        (false, 0.5),
+        (true, 0.5),
+        (false, 0.4),
+    ]);
+    let options = VadOptions {
+        min_segment_secs: 0.25,
+        max_segment_secs: 0.0,
+        silence_threshold_db: -40.0,
+        min_gap_secs: 0.2,
+    };
+    // Padded by VAD_PADDING_MS, up to halfway to the next one
+    assert_eq!(VAD_PADDING_MS, 100);
+    assert_eq!(segment_secs(&samples, options), [(0.0, 0.6), (0.7, 2.26), (2.56, 3.26)]);
+
+    // Cut where it is quietest, at least half the longest length in
+    let limited = VadOptions {
+        max_segment_secs: 0.8,
+        ..options
+    };
+    assert_eq!(
+        segment_secs(&samples, limited),
+        [(0.0, 0.6), (0.7, 1.3), (1.3, 1.76), (1.76, 2.26), (2.56, 3.26)]
+    );
+
+    // A lone short burst has nothing to be merged into
+    let blip = bursts(&[(false, 1.0), (true, 0.1), (false, 1.0)]);
+    assert_eq!(segment_secs(&blip, options), [(0.9, 1.2)]);
+    let quiet = bursts(&[(true, 1.0)]).iter().map(|s| s * 0.001).collect::<Vec<f32>>();
+    assert!(voice_segments(&quiet, 2, 48000, options).is_empty());
+    assert!(voice_segments(&[], 2, 48000, options).is_empty());
+}
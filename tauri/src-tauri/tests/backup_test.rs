@@ -0,0 +1,111 @@
+// Backs a data dir up to a zip and restores it, replacing or merging, and
+// checks that archives that aren't whole Voicebox backups are refused:
+//   cargo test --test backup_test
+
+use std::io::Write;
+use std::path::Path;
+use voicebox::backup::{export, import, read_manifest, BACKUP_MANIFEST};
+use voicebox::process_manager::SERVER_RECORD_FILE;
+use voicebox::settings::SETTINGS_FILE;
+
+fn write(path: &Path, contents: &[u8]) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, contents).unwrap();
+}
+
+fn fill_data_dir(dir: &Path) {
+    write(&dir.join("voicebox.db"), &[1; 500]);
+    write(&dir.join("profiles").join("alice").join("sample.wav"), &[7; 3000]);
+    write(&dir.join("cache").join("mel.npy"), &[0; 100]);
+    write(&dir.join("models").join("base.bin"), &[9; 200]);
+    write(&dir.join(SETTINGS_FILE), b"{\"stale\":true}");
+    write(&dir.join(SERVER_RECORD_FILE), b"{}");
+}
+
+/// A zip holding `entries`, each a name and its contents.
+fn zip_of(path: &Path, entries: &[(&str, &[u8])]) {
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+    for (name, contents) in entries {
+        zip.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(contents).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+#[test]
+fn test_a_backup_restores_the_data_but_not_caches_or_runtime_state() {
+    let scratch = tempfile::tempdir().unwrap();
+    let data = scratch.path().join("data");
+    fill_data_dir(&data);
+    let archive = scratch.path().join("backups").join("voicebox.zip");
+
+    let backup = export(&data, &archive, false, Some("{\"theme\":\"dark\"}"), |_| {}).unwrap();
+    assert_eq!((backup.files, backup.bytes), (2, 3500));
+    assert!(!backup.include_models);
+    assert!(!archive.with_extension("zip.partial").exists());
+    assert!(!read_manifest(&archive).unwrap().include_models);
+
+    // Replacing: what the data dir had is gone, but its settings and server record stay
+    let restored = scratch.path().join("restored");
+    write(&restored.join("leftover.txt"), b"old");
+    write(&restored.join(SETTINGS_FILE), b"{}");
+    let result = import(&archive, &restored, false, |_| {}).unwrap();
+    assert!(!result.merged);
+    assert_eq!(result.settings_json.as_deref(), Some("{\"theme\":\"dark\"}"));
+    assert_eq!(std::fs::read(restored.join("voicebox.db")).unwrap(), vec![1; 500]);
+    assert_eq!(std::fs::read(restored.join("profiles").join("alice").join("sample.wav")).unwrap(), vec![7; 3000]);
+    assert!(!restored.join("leftover.txt").exists());
+    assert!(!restored.join("cache").exists() && !restored.join("models").exists());
+    assert!(!restored.join(BACKUP_MANIFEST).exists());
+    assert_eq!(std::fs::read(restored.join(SETTINGS_FILE)).unwrap(), b"{}", "the caller applies the settings");
+    assert!(!scratch.path().join("restored.restore").exists(), "the staging dir is removed");
+
+    // Merging keeps what the backup doesn't have
+    let merged = scratch.path().join("merged");
+    write(&merged.join("voicebox.db"), b"newer");
+    write(&merged.join("extra.txt"), b"kept");
+    assert!(import(&archive, &merged, true, |_| {}).unwrap().merged);
+    assert_eq!(std::fs::read(merged.join("voicebox.db")).unwrap(), vec![1; 500]);
+    assert_eq!(std::fs::read(merged.join("extra.txt")).unwrap(), b"kept");
+}
+
+#[test]
+fn test_models_are_only_backed_up_when_asked_for() {
+    let scratch = tempfile::tempdir().unwrap();
+    let data = scratch.path().join("data");
+    fill_data_dir(&data);
+    let archive = scratch.path().join("voicebox.zip");
+
+    let backup = export(&data, &archive, true, None, |_| {}).unwrap();
+    assert_eq!((backup.files, backup.bytes), (3, 3700));
+    assert!(read_manifest(&archive).unwrap().include_models);
+}
+
+#[test]
+fn test_what_isnt_a_whole_backup_is_refused() {
+    let scratch = tempfile::tempdir().unwrap();
+    let data = scratch.path().join("data");
+    write(&data.join("voicebox.db"), b"db");
+    let manifest = br#"{"app_version":"0.1.13","created_at":0,"include_models":false}"#;
+
+    // No database to back up, and nothing left that looks like a backup
+    let empty = scratch.path().join("empty");
+    std::fs::create_dir_all(&empty).unwrap();
+    let archive = scratch.path().join("none.zip");
+    assert!(export(&empty, &archive, false, None, |_| {}).is_err());
+    assert!(!archive.exists() && !archive.with_extension("zip.partial").exists());
+
+    let foreign = scratch.path().join("foreign.zip");
+    zip_of(&foreign, &[("voicebox.db", b"db")]);
+    assert!(read_manifest(&foreign).unwrap_err().contains("Not a Voicebox backup"));
+
+    let incomplete = scratch.path().join("incomplete.zip");
+    zip_of(&incomplete, &[(BACKUP_MANIFEST, manifest)]);
+    assert!(read_manifest(&incomplete).unwrap_err().contains("voicebox.db"));
+
+    let escaping = scratch.path().join("escaping.zip");
+    zip_of(&escaping, &[(BACKUP_MANIFEST, manifest), ("voicebox.db", b"db"), ("../outside.txt", b"x")]);
+    assert!(import(&escaping, &data, true, |_| {}).unwrap_err().contains("unsafe path"));
+    assert!(!scratch.path().join("outside.txt").exists());
+    assert_eq!(std::fs::read(data.join("voicebox.db")).unwrap(), b"db", "the data dir is untouched");
+}
@@ -0,0 +1,151 @@
+// Feeds combine legs that start apart and run on drifting clocks, with
+// chosen arrival times, and records both legs of a combined capture through
+// the synthetic backend, including one whose microphone can't start:
+//   cargo test --test combined_capture_test
+
+use base64::Engine;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use voicebox::audio_capture::{SyntheticBackend, SyntheticPattern, NORMALIZE_PEAK};
+use voicebox::combined_capture::{self, CombinedCapture, CombinedLayout, LegRecording, COMBINED_SAMPLE_RATE};
+use voicebox::error::VoiceboxError;
+
+/// Wide enough to survive resampling at either end.
+const CLICK_FRAMES: usize = 4;
+
+/// `seconds` of a leg delivered in 10 ms chunks from `start`, with a
+/// full-scale click on its first and last CLICK_FRAMES. The device clock
+/// runs `ppm` fast of nominal.
+fn leg(start: Instant, sample_rate: u32, channels: u16, seconds: u32, ppm: f64) -> LegRecording {
+    let mut recording = LegRecording::default();
+    let chunk_frames = sample_rate as usize / 100;
+    // How long a chunk really takes, on the monotonic clock
+    let chunk_time = Duration::from_secs_f64(0.01 / (1.0 + ppm / 1e6));
+    for chunk in 0..seconds * 100 {
+        let mut frames = vec![0.0; chunk_frames * channels as usize];
+        let click = CLICK_FRAMES * channels as usize;
+        if chunk == 0 {
+            frames[..click].fill(1.0);
+        }
+        if chunk == seconds * 100 - 1 {
+            let end = frames.len();
+            frames[end - click..].fill(1.0);
+        }
+        recording.push(&frames, sample_rate, channels, start + chunk_time * (chunk + 1));
+    }
+    recording
+}
+
+fn first_click(samples: &[f32], channels: usize, channel: usize) -> Option<usize> {
+    samples.chunks(channels).position(|frame| frame[channel] > 0.5)
+}
+
+fn last_click(samples: &[f32], channels: usize, channel: usize) -> Option<usize> {
+    samples.chunks(channels).rposition(|frame| frame[channel] > 0.5)
+}
+
+#[test]
+fn test_lines_the_legs_up_on_their_first_frames() {
+    let start = Instant::now();
+    let system = leg(start, 48000, 2, 2, 0.0);
+    // The microphone comes up 300 ms later, mono at 44.1 kHz
+    let microphone = leg(start + Duration::from_millis(300), 44100, 1, 2, 0.0);
+
+    let combined = combined_capture::combine(&system, &microphone, CombinedLayout::Separate).unwrap();
+    assert_eq!(combined.channels, 4);
+    assert_eq!(combined.microphone_offset_ms, 300);
+    assert_eq!(first_click(&combined.samples, 4, 0), Some(0));
+    let voice = first_click(&combined.samples, 4, 2).unwrap();
+    assert!((14395..=14405).contains(&voice), "the microphone's click is at frame {}", voice);
+    assert_eq!(first_click(&combined.samples, 4, 3), Some(voice));
+
+    // The other way round, the system leg is the one delayed
+    let late_system = leg(start + Duration::from_millis(300), 48000, 2, 2, 0.0);
+    let early_microphone = leg(start, 48000, 1, 2, 0.0);
+    let combined = combined_capture::combine(&late_system, &early_microphone, CombinedLayout::Mixed).unwrap();
+    assert_eq!(combined.channels, 2);
+    assert_eq!(combined.microphone_offset_ms, -300);
+    // Mixed, the microphone's click comes first and system audio's after
+    assert_eq!(first_click(&combined.samples, 2, 0), Some(0));
+    assert!(combined.samples[14395 * 2..14405 * 2].iter().any(|sample| *sample > 0.5));
+
+    // Clicks that land together would clip, so the mix is scaled down
+    let together = combined_capture::combine(&system, &leg(start, 48000, 1, 2, 0.0), CombinedLayout::Mixed).unwrap();
+    let peak = together.samples.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+    assert!((peak - NORMALIZE_PEAK).abs() < 1e-6, "peak {}", peak);
+}
+
+#[test]
+fn test_corrects_clock_drift_over_long_captures() {
+    let start = Instant::now();
+    let system = leg(start, 48000, 2, 60, 0.0);
+    // A microphone whose clock runs 500 ppm fast delivers 30 ms of extra
+    // frames over the minute; played at its nominal rate it would end late
+    let microphone = leg(start, 48000, 1, 60, 500.0);
+    assert_eq!(microphone.frames(), system.frames());
+
+    let combined = combined_capture::combine(&system, &microphone, CombinedLayout::Separate).unwrap();
+    assert_eq!(combined.system_drift_ppm, 0);
+    assert!((combined.microphone_drift_ppm - 500).abs() <= 5, "{} ppm", combined.microphone_drift_ppm);
+    // The microphone's minute took 30 ms less by the monotonic clock, so
+    // stretched to it, it ends that much before system audio
+    assert_eq!(combined.samples.len() / 4, 60 * COMBINED_SAMPLE_RATE as usize);
+    assert_eq!(last_click(&combined.samples, 4, 0), Some(60 * 48000 - 1));
+    let voice_end = last_click(&combined.samples, 4, 2).unwrap();
+    let expected = 60 * 48000 - 1 - 30 * 48;
+    assert!(voice_end.abs_diff(expected) <= 5, "the microphone ends at frame {}", voice_end);
+
+    // Too short to measure a clock: nominal rates
+    let (system, microphone) = (leg(start, 48000, 2, 2, 0.0), leg(start, 48000, 1, 2, 500.0));
+    let short = combined_capture::combine(&system, &microphone, CombinedLayout::Mixed).unwrap();
+    assert_eq!(short.microphone_drift_ppm, 0);
+}
+
+#[test]
+fn test_a_leg_without_audio_fails() {
+    let start = Instant::now();
+    let system = leg(start, 48000, 2, 1, 0.0);
+    let error = combined_capture::combine(&system, &LegRecording::default(), CombinedLayout::Mixed).unwrap_err();
+    assert_eq!(error.code(), "capture");
+    assert!(error.message().contains("microphone"));
+}
+
+#[tokio::test]
+async fn test_records_both_legs_into_one_wav() {
+    let music = SyntheticBackend::new(SyntheticPattern::Sine {
+        frequency: 440.0,
+        amplitude: 0.3,
+    });
+    let voice = SyntheticBackend::new(SyntheticPattern::Noise { amplitude: 0.2 }).with_format(44100, 1);
+    let capture = CombinedCapture::with_backends(Arc::new(music), Arc::new(voice));
+    capture.start(60, CombinedLayout::Separate).unwrap();
+    assert!(capture.is_capturing());
+    assert!(matches!(capture.start(60, CombinedLayout::Mixed), Err(VoiceboxError::Busy { .. })));
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let wav = capture.stop().await.unwrap();
+    assert!(!capture.is_capturing());
+    let bytes = base64::engine::general_purpose::STANDARD.decode(wav).unwrap();
+    let mut reader = hound::WavReader::new(std::io::Cursor::new(bytes)).unwrap();
+    assert_eq!(reader.spec().sample_rate, COMBINED_SAMPLE_RATE);
+    assert_eq!(reader.spec().channels, 4);
+    let samples: Vec<i16> = reader.samples::<i16>().map(Result::unwrap).collect();
+    let frames = samples.len() / 4;
+    assert!(frames > COMBINED_SAMPLE_RATE as usize * 4 / 10, "{} frames", frames);
+    // Both pairs carry their leg
+    for channel in [0, 2] {
+        assert!(samples.chunks(4).any(|frame| frame[channel].unsigned_abs() > 3000));
+    }
+}
+
+#[tokio::test]
+async fn test_a_microphone_that_cannot_start_fails_the_start() {
+    let music = SyntheticBackend::default();
+    let broken = SyntheticBackend::default().with_format(0, 0);
+    let capture = CombinedCapture::with_backends(Arc::new(music), Arc::new(broken));
+    let error = capture.start(60, CombinedLayout::Mixed).unwrap_err();
+    assert_eq!(error.code(), "capture");
+    assert!(error.message().contains("microphone"), "{}", error.message());
+    assert!(!capture.is_capturing());
+    assert!(matches!(capture.stop().await, Err(VoiceboxError::NotFound { .. })));
+}
@@ -0,0 +1,194 @@
+// Serves the control API on a free loopback port with a dispatcher that
+// records what it was asked, and checks the token, the routing of each
+// endpoint, how errors map to statuses, and that stopping closes the port:
+//   cargo test --test control_api_test
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use voicebox::control_api::{ControlAction, ControlApiState, Dispatch};
+use voicebox::error::VoiceboxError;
+use voicebox::metrics::METRICS;
+
+const TOKEN: &str = "0123456789abcdef0123";
+
+type Answer = fn(&ControlAction) -> Result<serde_json::Value, VoiceboxError>;
+
+/// Records each action and answers with `answer`.
+fn recorder(answer: Answer) -> (Dispatch, Arc<Mutex<Vec<ControlAction>>>) {
+    let actions = Arc::new(Mutex::new(Vec::new()));
+    let seen = actions.clone();
+    let dispatch: Dispatch = Arc::new(move |action| {
+        let result = answer(&action);
+        seen.lock().unwrap().push(action);
+        Box::pin(async move { result })
+    });
+    (dispatch, actions)
+}
+
+fn ok(_: &ControlAction) -> Result<serde_json::Value, VoiceboxError> {
+    Ok(serde_json::json!({ "ok": true }))
+}
+
+/// One HTTP/1.1 request on its own connection; the status and JSON body.
+async fn request(
+    address: SocketAddr,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+    body: &str,
+) -> (u16, serde_json::Value) {
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    let auth = token.map(|token| format!("Authorization: Bearer {}\r\n", token)).unwrap_or_default();
+    let head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+        method,
+        path,
+        address,
+        auth,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream.write_all(body.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    let status = response.split(' ').nth(1).unwrap().parse().unwrap();
+    let body = response.split_once("\r\n\r\n").map_or("", |(_, body)| body);
+    (status, serde_json::from_str(body).unwrap_or(serde_json::Value::Null))
+}
+
+#[tokio::test]
+async fn listens_on_loopback_only() {
+    let control = ControlApiState::new();
+    let (dispatch, _) = recorder(ok);
+    let address = control.start(0, TOKEN.to_string(), dispatch).await.unwrap();
+    assert!(address.ip().is_loopback());
+
+    let status = control.status().await;
+    assert!(status.running);
+    assert_eq!(status.address, Some(address.to_string()));
+    control.stop().await;
+}
+
+#[tokio::test]
+async fn refuses_requests_without_the_token() {
+    let control = ControlApiState::new();
+    let (dispatch, actions) = recorder(ok);
+    let address = control.start(0, TOKEN.to_string(), dispatch).await.unwrap();
+    let errors = METRICS.control_api_errors.get();
+
+    for token in [None, Some("wrong-token-wrong-token"), Some(&TOKEN[..TOKEN.len() - 1])] {
+        let (status, body) = request(address, "POST", "/v1/playback/stop", token, "").await;
+        assert_eq!(status, 401);
+        assert_eq!(body["code"], "permission");
+    }
+    // Unknown paths too, so they don't reveal what exists
+    let (status, _) = request(address, "GET", "/v1/nothing", None, "").await;
+    assert_eq!(status, 401);
+
+    assert!(actions.lock().unwrap().is_empty());
+    assert!(METRICS.control_api_errors.get() >= errors + 4);
+    control.stop().await;
+}
+
+#[tokio::test]
+async fn routes_each_endpoint_to_its_action() {
+    let control = ControlApiState::new();
+    let (dispatch, actions) = recorder(ok);
+    let address = control.start(0, TOKEN.to_string(), dispatch).await.unwrap();
+    let requests = METRICS.control_api_requests.get();
+
+    let calls = [
+        ("GET", "/v1/status", ""),
+        ("POST", "/v1/capture/start", r#"{"max_duration_secs": 60}"#),
+        ("POST", "/v1/capture/start", ""),
+        ("POST", "/v1/capture/marker", ""),
+        ("POST", "/v1/capture/stop", ""),
+        ("POST", "/v1/playback/recordings/1709251200-ab12cd34", r#"{"devices": ["Headphones", "default"]}"#),
+        ("POST", "/v1/playback/recordings/1709251200-ab12cd34", ""),
+        ("POST", "/v1/playback/stop", ""),
+    ];
+    for (method, path, body) in calls {
+        let (status, response) = request(address, method, path, Some(TOKEN), body).await;
+        assert_eq!(status, 200, "{} {}: {}", method, path, response);
+        assert_eq!(response["ok"], true);
+    }
+
+    let id = "1709251200-ab12cd34".to_string();
+    assert_eq!(
+        *actions.lock().unwrap(),
+        vec![
+            ControlAction::Status,
+            ControlAction::StartCapture {
+                max_duration_secs: Some(60)
+            },
+            ControlAction::StartCapture { max_duration_secs: None },
+            ControlAction::AddMarker,
+            ControlAction::StopCapture,
+            ControlAction::PlayRecording {
+                id: id.clone(),
+                devices: vec!["Headphones".to_string(), "default".to_string()],
+            },
+            ControlAction::PlayRecording { id, devices: Vec::new() },
+            ControlAction::StopPlayback,
+        ]
+    );
+    assert!(METRICS.control_api_requests.get() >= requests + calls.len() as u64);
+    control.stop().await;
+}
+
+#[tokio::test]
+async fn answers_errors_with_their_code_and_status() {
+    let control = ControlApiState::new();
+    let (dispatch, actions) = recorder(|action| match action {
+        ControlAction::PlayRecording { id, .. } => {
+            Err(VoiceboxError::not_found(format!("There is no recording {}", id)))
+        }
+        ControlAction::StartCapture { .. } => Err(VoiceboxError::busy("A capture is already running")),
+        _ => Err(VoiceboxError::capture("No capture is running")),
+    });
+    let address = control.start(0, TOKEN.to_string(), dispatch).await.unwrap();
+
+    let (status, body) = request(address, "POST", "/v1/playback/recordings/missing", Some(TOKEN), "").await;
+    assert_eq!(status, 404);
+    assert_eq!(body["code"], "not_found");
+    assert_eq!(body["message"], "There is no recording missing");
+
+    let (status, body) = request(address, "POST", "/v1/capture/start", Some(TOKEN), "").await;
+    assert_eq!((status, body["code"].as_str()), (409, Some("busy")));
+    let (status, body) = request(address, "POST", "/v1/capture/marker", Some(TOKEN), "").await;
+    assert_eq!((status, body["code"].as_str()), (500, Some("capture")));
+
+    // Refused before reaching the app
+    let dispatched = actions.lock().unwrap().len();
+    let (status, body) = request(address, "POST", "/v1/capture/start", Some(TOKEN), r#"{"max_duration": 5}"#).await;
+    assert_eq!((status, body["code"].as_str()), (400, Some("invalid_argument")));
+    let (status, body) = request(address, "GET", "/v1/capture/start", Some(TOKEN), "").await;
+    assert_eq!(status, 405, "{}", body);
+    let (status, body) = request(address, "GET", "/v1/nothing", Some(TOKEN), "").await;
+    assert_eq!((status, body["code"].as_str()), (404, Some("not_found")));
+    assert_eq!(actions.lock().unwrap().len(), dispatched);
+    control.stop().await;
+}
+
+#[tokio::test]
+async fn stopping_closes_the_port() {
+    let control = ControlApiState::new();
+    let (dispatch, _) = recorder(ok);
+    let short = control.start(0, "short".to_string(), dispatch.clone()).await.unwrap_err();
+    assert_eq!(short.code(), "invalid_argument");
+
+    let address = control.start(0, TOKEN.to_string(), dispatch.clone()).await.unwrap();
+    control.stop().await;
+    assert!(!control.status().await.running);
+    assert!(tokio::net::TcpStream::connect(address).await.is_err());
+
+    // The port is free again, and taken while serving
+    let again = control.start(address.port(), TOKEN.to_string(), dispatch.clone()).await.unwrap();
+    assert_eq!(again, address);
+    let other = ControlApiState::new();
+    let taken = other.start(address.port(), TOKEN.to_string(), dispatch).await.unwrap_err();
+    assert_eq!(taken.code(), "busy");
+    control.stop().await;
+}
@@ -0,0 +1,69 @@
+// Moves a data dir to a new location and checks what stays behind, and that
+// destinations that would lose or tangle data are refused:
+//   cargo test --test data_migration_test
+
+use std::path::Path;
+use std::sync::Mutex;
+use voicebox::data_migration::{migrate, remove_old_tree, validate_destination, MigrationProgress};
+use voicebox::settings::SETTINGS_FILE;
+
+fn fill_data_dir(dir: &Path) {
+    std::fs::create_dir_all(dir.join("profiles").join("alice")).unwrap();
+    std::fs::write(dir.join("profiles").join("alice").join("sample.wav"), vec![7u8; 3000]).unwrap();
+    std::fs::write(dir.join("voicebox.db"), vec![1u8; 500]).unwrap();
+    std::fs::write(dir.join(SETTINGS_FILE), b"{}").unwrap();
+}
+
+#[test]
+fn test_the_data_moves_and_the_settings_stay() {
+    let scratch = tempfile::tempdir().unwrap();
+    let old = scratch.path().join("old");
+    let new = scratch.path().join("new");
+    fill_data_dir(&old);
+
+    let reports: Mutex<Vec<MigrationProgress>> = Mutex::new(Vec::new());
+    let result = migrate(&old, &new, false, |progress| reports.lock().unwrap().push(progress.clone())).unwrap();
+    assert_eq!((result.files_copied, result.bytes_copied), (2, 3500));
+    assert!(result.old_removed);
+    assert_eq!(std::fs::read(new.join("profiles").join("alice").join("sample.wav")).unwrap(), vec![7u8; 3000]);
+    assert_eq!(std::fs::read(new.join("voicebox.db")).unwrap(), vec![1u8; 500]);
+    assert!(!new.join(SETTINGS_FILE).exists(), "the settings belong to the app, not the data");
+
+    let reports = reports.into_inner().unwrap();
+    let (first, last) = (reports.first().unwrap(), reports.last().unwrap());
+    assert_eq!((first.files_done, first.bytes_done), (0, 0));
+    assert_eq!((last.files_done, last.files_total, last.bytes_done, last.bytes_total), (2, 2, 3500, 3500));
+
+    // The copy leaves the original alone until it is removed
+    assert!(old.join("voicebox.db").exists());
+    remove_old_tree(&old).unwrap();
+    let left: Vec<_> = std::fs::read_dir(&old).unwrap().flatten().map(|entry| entry.file_name()).collect();
+    assert_eq!(left, [SETTINGS_FILE]);
+}
+
+#[test]
+fn test_destinations_that_would_tangle_the_data_are_refused() {
+    let scratch = tempfile::tempdir().unwrap();
+    let old = scratch.path().join("old");
+    fill_data_dir(&old);
+
+    assert!(validate_destination(&old, Path::new("relative/dir")).is_err());
+    assert!(validate_destination(&old, &old).is_err());
+    assert!(validate_destination(&old, &old.join("profiles").join("moved")).is_err());
+    assert!(validate_destination(&old, scratch.path()).is_err());
+
+    let taken = scratch.path().join("taken");
+    std::fs::create_dir_all(&taken).unwrap();
+    std::fs::write(taken.join("notes.txt"), b"mine").unwrap();
+    assert!(validate_destination(&old, &taken).unwrap_err().contains("not empty"));
+    assert!(validate_destination(&old, &taken.join("notes.txt")).is_err());
+
+    // Nothing was copied into a refused destination
+    assert!(migrate(&old, &taken, false, |_| {}).is_err());
+    assert_eq!(std::fs::read_dir(&taken).unwrap().count(), 1);
+
+    let empty = scratch.path().join("empty");
+    std::fs::create_dir_all(&empty).unwrap();
+    assert!(validate_destination(&old, &empty).is_ok());
+    assert!(validate_destination(&old, &scratch.path().join("not").join("there").join("yet")).is_ok());
+}
@@ -0,0 +1,102 @@
+// Checks that diagnostics bundles scrub tokens, the home directory and the
+// account name, and that a section that couldn't be gathered is noted rather
+// than fatal:
+//   cargo test --test diagnostics_test
+
+use std::io::Read;
+use std::path::Path;
+use voicebox::diagnostics::{DiagnosticsBundle, Redactor, MANIFEST_FILE, REDACTED};
+
+fn read_entry(archive: &Path, name: &str) -> String {
+    let mut zip = zip::ZipArchive::new(std::fs::File::open(archive).unwrap()).unwrap();
+    let mut contents = String::new();
+    zip.by_name(name).unwrap().read_to_string(&mut contents).unwrap();
+    contents
+}
+
+#[test]
+fn test_redacts_known_secrets_and_auth_markers() {
+    let redactor = Redactor::new(None, vec!["s3cr3t-t0ken".to_string(), String::new()]);
+    let redacted = redactor.redact_str("token s3cr3t-t0ken in a log line");
+    assert_eq!(redacted, format!("token {} in a log line", REDACTED));
+
+    let redacted = redactor.redact_str("Authorization: Bearer abc.def-123 sent");
+    assert_eq!(redacted, format!("Authorization: Bearer {} sent", REDACTED));
+
+    let redacted = redactor.redact_str("GET http://host/x?token=abc123&page=2");
+    assert_eq!(redacted, format!("GET http://host/x?token={}&page=2", REDACTED));
+}
+
+#[test]
+fn test_replaces_home_dir_with_tilde() {
+    let redactor = Redactor::new(Some(Path::new("/home/alice/")), Vec::new());
+    assert_eq!(
+        redactor.redact_str("Data dir: /home/alice/.local/share/voicebox"),
+        "Data dir: ~/.local/share/voicebox"
+    );
+
+    let redactor = Redactor::new(Some(Path::new(r"C:\Users\alice")), Vec::new());
+    assert_eq!(
+        redactor.redact_str(r"Opened C:\Users\alice\AppData\voicebox.db"),
+        r"Opened ~\AppData\voicebox.db"
+    );
+}
+
+#[test]
+fn test_redacts_the_account_name() {
+    let redactor = Redactor::new(Some(Path::new("/Users/alice")), Vec::new());
+    assert_eq!(
+        redactor.redact_str("Output: ALICE's AirPods (device_alice's_airpods) at /Users/alice/x"),
+        format!("Output: {0}'s AirPods (device_{0}'s_airpods) at ~/x", REDACTED)
+    );
+    // Only as a word of its own
+    assert_eq!(redactor.redact_str("Malice and alice2"), "Malice and alice2");
+
+    // Too short to tell from ordinary words
+    let redactor = Redactor::new(Some(Path::new("/home/al")), Vec::new());
+    assert_eq!(redactor.redact_str("al's speakers"), "al's speakers");
+}
+
+#[test]
+fn test_redacts_secret_keys_in_json() {
+    let redactor = Redactor::new(Some(Path::new("/home/alice")), Vec::new());
+    let mut value = serde_json::json!({
+        "auth_token": "abc",
+        "auth_token_present": true,
+        "nested": [{ "apiKey": "xyz", "data_dir": "/home/alice/voicebox" }],
+    });
+    redactor.redact_json(&mut value);
+    assert_eq!(value["auth_token"], REDACTED);
+    assert_eq!(value["auth_token_present"], true);
+    assert_eq!(value["nested"][0]["apiKey"], REDACTED);
+    assert_eq!(value["nested"][0]["data_dir"], "~/voicebox");
+}
+
+#[test]
+fn test_bundle_notes_missing_sections() {
+    let scratch = tempfile::tempdir().unwrap();
+    let dir = scratch.path();
+    let logs = dir.join("logs");
+    std::fs::create_dir_all(&logs).unwrap();
+    std::fs::write(logs.join("app.log.2024-01-01"), "server token=abc123 at /home/alice/x\n").unwrap();
+
+    let mut bundle = DiagnosticsBundle::new(Redactor::new(Some(Path::new("/home/alice")), Vec::new()));
+    bundle.add_dir("logs", Ok(logs));
+    bundle.add_json("settings", Ok(serde_json::json!({ "data_dir": "/home/alice/data" })));
+    bundle.add_json::<serde_json::Value>("server_health", Err("The server is not running".to_string()));
+    let archive = dir.join("diagnostics.zip");
+    let manifest = bundle.write(&archive, "1.2.3").unwrap();
+
+    assert_eq!(manifest.app_version, "1.2.3");
+    let health = manifest.sections.iter().find(|s| s.name == "server_health").unwrap();
+    assert_eq!(health.error.as_deref(), Some("The server is not running"));
+    assert!(health.files.is_empty());
+
+    let written: serde_json::Value = serde_json::from_str(&read_entry(&archive, MANIFEST_FILE)).unwrap();
+    assert_eq!(written["sections"].as_array().unwrap().len(), 3);
+    assert_eq!(
+        read_entry(&archive, "logs/app.log.2024-01-01"),
+        format!("server token={} at ~/x\n", REDACTED)
+    );
+    assert!(read_entry(&archive, "settings.json").contains("\"~/data\""));
+}
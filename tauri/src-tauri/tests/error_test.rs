@@ -0,0 +1,89 @@
+// Pins the serialized form of command errors. The frontend matches on
+// `code`, so a change here is a breaking change for it:
+//   cargo test --test error_test
+
+use serde_json::json;
+use voicebox::error::VoiceboxError;
+
+#[test]
+fn test_codes_are_stable() {
+    let cases = [
+        (VoiceboxError::capture("m"), json!({ "code": "capture", "message": "m" })),
+        (VoiceboxError::playback("m"), json!({ "code": "playback", "message": "m" })),
+        (VoiceboxError::server("m"), json!({ "code": "server", "message": "m" })),
+        (VoiceboxError::io("m"), json!({ "code": "io", "message": "m" })),
+        (
+            VoiceboxError::permission("microphone", "m"),
+            json!({ "code": "permission", "message": "m", "details": { "permission": "microphone" } }),
+        ),
+        (VoiceboxError::invalid_argument("m"), json!({ "code": "invalid_argument", "message": "m" })),
+        (VoiceboxError::not_found("m"), json!({ "code": "not_found", "message": "m" })),
+        (VoiceboxError::busy("m"), json!({ "code": "busy", "message": "m" })),
+        (VoiceboxError::unsupported("m"), json!({ "code": "unsupported", "message": "m" })),
+        (
+            VoiceboxError::insufficient_space("/data", 3 << 20, 1 << 20),
+            json!({
+                "code": "insufficient_space",
+                "message": "Not enough free space for /data: 3 MB needed, 1 MB available",
+                "details": { "path": "/data", "required_bytes": 3 << 20, "available_bytes": 1 << 20 },
+            }),
+        ),
+        (
+            VoiceboxError::quota_exceeded("max_items", 50, 51),
+            json!({
+                "code": "quota_exceeded",
+                "message": "The recordings library can't fit this recording within its max_items of 50 \
+                            (51 needed), even with every unpinned recording pruned",
+                "details": { "limit": "max_items", "max": 50, "required": 51 },
+            }),
+        ),
+        (
+            VoiceboxError::not_detected(3000),
+            json!({
+                "code": "not_detected",
+                "message": "The test signal wasn't heard within 3000 ms. Check that the output is looped back \
+                            into the capture",
+                "details": { "listened_ms": 3000 },
+            }),
+        ),
+        (VoiceboxError::internal("m"), json!({ "code": "internal", "message": "m" })),
+    ];
+    for (error, expected) in cases {
+        assert_eq!(serde_json::to_value(&error).unwrap(), expected);
+        assert_eq!(error.code(), expected["code"]);
+    }
+}
+
+#[test]
+fn test_serializes_message_without_details() {
+    let error = VoiceboxError::busy("The server must be stopped before restoring a backup");
+    assert_eq!(
+        serde_json::to_string(&error).unwrap(),
+        r#"{"code":"busy","message":"The server must be stopped before restoring a backup"}"#
+    );
+}
+
+#[test]
+fn test_serializes_permission_details() {
+    let error = VoiceboxError::permission("screen_recording", "Screen Recording permission is not granted.");
+    assert_eq!(
+        serde_json::to_string(&error).unwrap(),
+        r#"{"code":"permission","message":"Screen Recording permission is not granted.","details":{"permission":"screen_recording"}}"#
+    );
+}
+
+#[test]
+fn test_conversions_pick_a_code() {
+    let error: VoiceboxError = "Failed to save settings".to_string().into();
+    assert_eq!(error, VoiceboxError::internal("Failed to save settings"));
+
+    let missing = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+    assert_eq!(VoiceboxError::from(missing).code(), "not_found");
+    let denied = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "access denied");
+    let error = VoiceboxError::from(denied);
+    assert_eq!(error.code(), "io");
+    assert_eq!(error.to_string(), "access denied");
+
+    let message: String = VoiceboxError::capture("No audio samples captured").into();
+    assert_eq!(message, "No audio samples captured");
+}
@@ -0,0 +1,163 @@
+// Drives the event bus with a fake clock and a recording sink, so windows
+// open and close exactly when the test says:
+//   cargo test --test event_bus_test
+
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use voicebox::event_bus::{BusConfig, Clock, EventBus, EventSink, BATCH_EVENT};
+
+struct FakeClock {
+    start: Instant,
+    offset: Mutex<Duration>,
+}
+
+impl FakeClock {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            start: Instant::now(),
+            offset: Mutex::new(Duration::ZERO),
+        })
+    }
+
+    fn advance(&self, by: Duration) {
+        *self.offset.lock().unwrap() += by;
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.start + *self.offset.lock().unwrap()
+    }
+}
+
+#[derive(Default)]
+struct RecordingSink {
+    sent: Mutex<Vec<(String, Value)>>,
+}
+
+impl RecordingSink {
+    fn take(&self) -> Vec<(String, Value)> {
+        std::mem::take(&mut *self.sent.lock().unwrap())
+    }
+}
+
+impl EventSink for RecordingSink {
+    fn send(&self, topic: &str, payload: &Value) -> Result<(), String> {
+        self.sent.lock().unwrap().push((topic.to_string(), payload.clone()));
+        Ok(())
+    }
+}
+
+fn bus(config: BusConfig) -> (Arc<FakeClock>, Arc<RecordingSink>, EventBus) {
+    let clock = FakeClock::new();
+    let sink = Arc::new(RecordingSink::default());
+    let bus = EventBus::new(sink.clone(), clock.clone(), config);
+    (clock, sink, bus)
+}
+
+const MS: Duration = Duration::from_millis(1);
+
+#[test]
+fn test_coalesces_within_the_window() {
+    let (clock, sink, bus) = bus(BusConfig::default());
+    bus.emit("capture-level", json!({ "peak": 0.1 })).unwrap();
+    clock.advance(20 * MS);
+    bus.emit("capture-level", json!({ "peak": 0.2 })).unwrap();
+    bus.emit("setting-changed", json!({ "key": "theme" })).unwrap();
+    bus.flush_due().unwrap();
+    assert!(sink.take().is_empty(), "nothing goes out before the window closes");
+
+    clock.advance(30 * MS);
+    bus.flush_due().unwrap();
+    let sent = sink.take();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].0, BATCH_EVENT);
+    assert_eq!(
+        sent[0].1,
+        json!({
+            "events": [
+                { "topic": "capture-level", "payload": { "peak": 0.1 } },
+                { "topic": "capture-level", "payload": { "peak": 0.2 } },
+                { "topic": "setting-changed", "payload": { "key": "theme" } },
+            ],
+            "dropped": {},
+        })
+    );
+
+    // The next event opens a new window
+    bus.emit("capture-level", json!({ "peak": 0.3 })).unwrap();
+    bus.flush_due().unwrap();
+    assert!(sink.take().is_empty());
+}
+
+#[test]
+fn test_emit_after_the_window_flushes() {
+    let (clock, sink, bus) = bus(BusConfig::default().with_window(10 * MS));
+    bus.emit("progress", 1).unwrap();
+    clock.advance(10 * MS);
+    bus.emit("progress", 2).unwrap();
+    let sent = sink.take();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].1["events"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn test_drops_oldest_beyond_the_cap() {
+    let (clock, sink, bus) = bus(BusConfig::default().cap("capture-level", 2));
+    for peak in 0..5 {
+        bus.emit("capture-level", peak).unwrap();
+    }
+    bus.emit("progress", "kept").unwrap();
+    clock.advance(50 * MS);
+    bus.flush_due().unwrap();
+
+    let sent = sink.take();
+    let payloads: Vec<&Value> = sent[0].1["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| &event["payload"])
+        .collect();
+    assert_eq!(payloads, [&json!(3), &json!(4), &json!("kept")]);
+    assert_eq!(sent[0].1["dropped"], json!({ "capture-level": 3 }));
+
+    // Drops are reported per batch and totalled since launch
+    for peak in 0..3 {
+        bus.emit("capture-level", peak).unwrap();
+    }
+    bus.flush().unwrap();
+    assert_eq!(sink.take()[0].1["dropped"], json!({ "capture-level": 1 }));
+    assert_eq!(bus.dropped().get("capture-level"), Some(&4));
+}
+
+#[test]
+fn test_immediate_topics_skip_the_window() {
+    let (_, sink, bus) = bus(BusConfig::default());
+    bus.emit("capture-level", 0.5).unwrap();
+    bus.emit("capture-stopped", json!({ "session": 1 })).unwrap();
+    bus.emit("server-crashed", json!({ "pid": 42 })).unwrap();
+
+    let sent = sink.take();
+    let topics: Vec<&str> = sent.iter().map(|(topic, _)| topic.as_str()).collect();
+    // What was queued goes out first, so the level reading isn't reordered
+    // after the stop
+    assert_eq!(topics, [BATCH_EVENT, "capture-stopped", "server-crashed"]);
+    assert_eq!(sent[1].1, json!({ "session": 1 }));
+}
+
+#[test]
+fn test_topics_can_be_marked_immediate() {
+    let (_, sink, bus) = bus(BusConfig::default().immediate("hotkey-triggered"));
+    bus.emit("hotkey-triggered", json!({ "action": "toggle_capture" })).unwrap();
+    assert_eq!(sink.take()[0].0, "hotkey-triggered");
+}
+
+#[test]
+fn test_flush_with_nothing_queued_sends_nothing() {
+    let (clock, sink, bus) = bus(BusConfig::default());
+    bus.flush().unwrap();
+    clock.advance(Duration::from_secs(1));
+    bus.flush_due().unwrap();
+    assert!(sink.take().is_empty());
+}
@@ -0,0 +1,120 @@
+// Fingerprints generated melodies and checks that copies of one, offset,
+// rescaled, noisy or resampled, land above the default threshold while other
+// melodies land below it, and that the library ranks its recordings that way:
+//   cargo test --test fingerprint_test
+
+use voicebox::fingerprint::{self, DEFAULT_THRESHOLD};
+use voicebox::recording_metadata::RecordingMetadata;
+use voicebox::recordings::RecordingsState;
+use voicebox::settings::RecordingsQuota;
+
+const RATE: u32 = 44100;
+/// 2024-03-01 00:00:00 UTC
+const NOW: u64 = 1_709_251_200;
+
+/// Notes as MIDI numbers, 250 ms each.
+const TUNE: [u8; 16] = [60, 64, 67, 72, 71, 67, 64, 62, 60, 65, 69, 72, 74, 72, 67, 64];
+const OTHER_TUNE: [u8; 16] = [57, 57, 69, 68, 66, 61, 59, 62, 76, 74, 73, 71, 66, 70, 63, 58];
+
+/// `notes` as interleaved stereo at `rate`, each with a couple of harmonics,
+/// starting `lead_ms` into the first one, with noise at `noise` of full scale.
+fn melody(notes: &[u8], rate: u32, lead_ms: u32, gain: f32, noise: f32) -> Vec<f32> {
+    let note_frames = rate as usize / 4;
+    let lead = (rate * lead_ms / 1000) as usize;
+    let mut seed = 0x2545_f491u32;
+    let mut samples = Vec::new();
+    for n in lead..notes.len() * note_frames {
+        let hz = 440.0 * 2f32.powf((notes[n / note_frames] as f32 - 69.0) / 12.0);
+        let t = n as f32 / rate as f32;
+        let tone = (0..3)
+            .map(|h| (2.0 * std::f32::consts::PI * hz * (h + 1) as f32 * t).sin() / (h + 1) as f32)
+            .sum::<f32>();
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        let hiss = (seed as f32 / u32::MAX as f32 - 0.5) * 2.0 * noise;
+        let sample = tone * 0.3 * gain + hiss;
+        samples.extend([sample, sample * 0.8]);
+    }
+    samples
+}
+
+fn hashes(samples: &[f32], rate: u32) -> Vec<u32> {
+    fingerprint::compute(samples, rate, 2)
+}
+
+#[test]
+fn copies_of_a_take_are_alike() {
+    let original = hashes(&melody(&TUNE, RATE, 0, 1.0, 0.0), RATE);
+    assert!(!original.is_empty());
+    assert_eq!(fingerprint::similarity(&original, &original), 1.0);
+
+    let copies = [
+        ("a second later", hashes(&melody(&TUNE, RATE, 1032, 1.0, 0.0), RATE)),
+        ("quieter", hashes(&melody(&TUNE, RATE, 0, 0.2, 0.0), RATE)),
+        ("noisy", hashes(&melody(&TUNE, RATE, 0, 1.0, 0.02), RATE)),
+        ("at 48 kHz", hashes(&melody(&TUNE, 48000, 0, 1.0, 0.0), 48000)),
+    ];
+    for (name, copy) in &copies {
+        let similarity = fingerprint::similarity(&original, copy);
+        assert!(similarity >= DEFAULT_THRESHOLD, "{}: {}", name, similarity);
+    }
+}
+
+#[test]
+fn different_audio_is_not() {
+    let tune = hashes(&melody(&TUNE, RATE, 0, 1.0, 0.0), RATE);
+    let other = hashes(&melody(&OTHER_TUNE, RATE, 0, 1.0, 0.0), RATE);
+    let noise = hashes(&melody(&[0; 16], RATE, 0, 0.0, 0.5), RATE);
+    for (name, different) in [("other tune", &other), ("noise", &noise)] {
+        let similarity = fingerprint::similarity(&tune, different);
+        assert!(similarity < DEFAULT_THRESHOLD, "{}: {}", name, similarity);
+    }
+    // Too short to hash is never a match
+    assert!(hashes(&melody(&TUNE[..1], RATE, 0, 1.0, 0.0), RATE).is_empty());
+    assert_eq!(fingerprint::similarity(&tune, &[]), 0.0);
+}
+
+#[test]
+fn round_trips_through_the_metadata_encoding() {
+    let tune = hashes(&melody(&TUNE, RATE, 0, 1.0, 0.0), RATE);
+    assert_eq!(fingerprint::decode(&fingerprint::encode(&tune)).unwrap(), tune);
+    assert!(fingerprint::decode("not base64!").is_err());
+    // Three bytes can't be a whole hash
+    assert!(fingerprint::decode("AAAA").is_err());
+}
+
+#[test]
+fn the_library_ranks_near_duplicates() {
+    let dir = std::env::temp_dir().join(format!("voicebox-fingerprint-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let state = RecordingsState::new();
+    let library = state.open(&dir);
+    let quota = RecordingsQuota::default();
+
+    let save = |samples: Option<Vec<f32>>| {
+        let metadata = RecordingMetadata {
+            fingerprint: samples.map(|samples| fingerprint::encode(&hashes(&samples, RATE))),
+            ..RecordingMetadata::default()
+        };
+        library.add(b"RIFF", None, metadata, &quota, NOW).unwrap().0.id
+    };
+    let take = save(Some(melody(&TUNE, RATE, 0, 1.0, 0.0)));
+    let noisy = save(Some(melody(&TUNE, RATE, 0, 1.0, 0.05)));
+    let exact = save(Some(melody(&TUNE, RATE, 0, 0.5, 0.0)));
+    let _other = save(Some(melody(&OTHER_TUNE, RATE, 0, 1.0, 0.0)));
+    let unfingerprinted = save(None);
+
+    let similar = library.find_similar(&take, DEFAULT_THRESHOLD).unwrap();
+    let ids: Vec<&str> = similar.iter().map(|similar| similar.id.as_str()).collect();
+    assert_eq!(ids, vec![exact.as_str(), noisy.as_str()]);
+    assert!(similar[0].similarity >= similar[1].similarity);
+    // Everything fingerprinted, at threshold 0
+    assert_eq!(library.find_similar(&take, 0.0).unwrap().len(), 3);
+
+    assert_eq!(library.find_similar(&unfingerprinted, 0.0).unwrap_err().code(), "not_found");
+    assert_eq!(library.find_similar("missing", 0.0).unwrap_err().code(), "not_found");
+    drop(library);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,102 @@
+// Drives the settle tracker with explicit timestamps, round-trips the import
+// ledger through a temp dir and scans one for audio files:
+//   cargo test --test folder_watch_test
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use voicebox::folder_watch::{content_hash, is_candidate, scan, FileStamp, Ledger, Settler};
+
+const SETTLE: Duration = Duration::from_secs(3);
+
+#[test]
+fn test_files_wait_until_they_stop_growing() {
+    let start = Instant::now();
+    let at = |secs: u64| start + Duration::from_secs(secs);
+    let path = PathBuf::from("/watched/take.wav");
+    let mut settler = Settler::new(SETTLE);
+
+    settler.observe(path.clone(), 100, at(0));
+    settler.observe(path.clone(), 200, at(2));
+    assert!(settler.take_ready(at(4)).is_empty(), "growing restarts the wait");
+    // The same size again doesn't
+    settler.observe(path.clone(), 200, at(4));
+    assert_eq!(settler.take_ready(at(5)), [path]);
+    assert!(settler.pending().is_empty());
+}
+
+#[test]
+fn test_empty_and_forgotten_files_are_not_ready() {
+    let start = Instant::now();
+    let mut settler = Settler::new(SETTLE);
+    settler.observe(PathBuf::from("/watched/new.wav"), 0, start);
+    settler.observe(PathBuf::from("/watched/deleted.wav"), 10, start);
+    settler.forget(&PathBuf::from("/watched/deleted.wav"));
+
+    assert!(settler.take_ready(start + SETTLE * 2).is_empty());
+    assert_eq!(settler.pending(), [PathBuf::from("/watched/new.wav")]);
+}
+
+#[test]
+fn test_ledger_round_trip() {
+    let scratch = tempfile::tempdir().unwrap();
+    let dir = scratch.path();
+    let file = dir.join("take.wav");
+    std::fs::write(&file, b"RIFF").unwrap();
+    let stamp = FileStamp::of(&file).unwrap();
+    assert_eq!(stamp.len, 4);
+
+    let mut ledger = Ledger::default();
+    ledger.note_handled(&file, stamp);
+    ledger.note_hash("abc".to_string());
+    let path = dir.join("ledger.json");
+    ledger.save(&path).unwrap();
+
+    let loaded = Ledger::load(&path);
+    assert!(loaded.is_handled(&file, stamp));
+    assert!(!loaded.is_handled(&file, FileStamp { len: 5, ..stamp }), "a changed file is new again");
+    assert!(loaded.has_hash("abc"));
+
+    let mut loaded = loaded;
+    loaded.forget_folder(dir);
+    assert!(!loaded.is_handled(&file, stamp));
+    assert!(loaded.has_hash("abc"), "hashes outlive the folder");
+}
+
+#[test]
+fn test_missing_or_corrupt_ledger_starts_empty() {
+    let scratch = tempfile::tempdir().unwrap();
+    let dir = scratch.path();
+    let path = dir.join("ledger.json");
+    assert!(!Ledger::load(&path).has_hash("abc"));
+    std::fs::write(&path, "{ not json").unwrap();
+    assert!(!Ledger::load(&path).has_hash("abc"));
+}
+
+#[test]
+fn test_scan_finds_visible_audio_files() {
+    let scratch = tempfile::tempdir().unwrap();
+    let dir = scratch.path();
+    for name in ["b.mp3", "a.WAV", "notes.txt", ".partial.wav", "~$lock.wav"] {
+        std::fs::write(dir.join(name), b"x").unwrap();
+    }
+    std::fs::create_dir(dir.join("nested.wav")).unwrap();
+
+    assert_eq!(scan(dir).unwrap(), [dir.join("a.WAV"), dir.join("b.mp3")]);
+    assert!(!is_candidate(&dir.join(".partial.wav")));
+    assert!(scan(&dir.join("missing")).is_err());
+}
+
+#[test]
+fn test_content_hash_follows_contents() {
+    let scratch = tempfile::tempdir().unwrap();
+    let dir = scratch.path();
+    std::fs::write(dir.join("one.wav"), b"same").unwrap();
+    std::fs::write(dir.join("copy.wav"), b"same").unwrap();
+    std::fs::write(dir.join("other.wav"), b"different").unwrap();
+
+    let one = content_hash(&dir.join("one.wav")).unwrap();
+    assert_eq!(one.len(), 64);
+    assert_eq!(one, content_hash(&dir.join("copy.wav")).unwrap());
+    assert_ne!(one, content_hash(&dir.join("other.wav")).unwrap());
+    assert!(content_hash(&dir.join("missing.wav")).is_err());
+}
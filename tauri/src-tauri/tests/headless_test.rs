@@ -0,0 +1,119 @@
+// Exercises the command line parsing and exit codes of headless mode, plus a
+// run against a port nothing listens on, so no sidecar is spawned:
+//   cargo test --test headless_test
+
+use std::path::PathBuf;
+use voicebox::headless::{self, CliMode, HeadlessOptions};
+use voicebox::server_error::{BinaryProblemKind, ServerStartError, StartupPhase};
+use voicebox::storage::{StorageProblem, StorageProblemKind};
+
+fn parse(args: &[&str]) -> Result<CliMode, String> {
+    headless::parse_args(args.iter().map(|arg| arg.to_string()))
+}
+
+fn headless_options(args: &[&str]) -> HeadlessOptions {
+    match parse(args) {
+        Ok(CliMode::Headless(options)) => options,
+        other => panic!("expected headless mode for {:?}, got {:?}", args, other),
+    }
+}
+
+#[test]
+fn test_without_headless_flag_everything_goes_to_the_window() {
+    assert_eq!(parse(&[]), Ok(CliMode::Gui));
+    assert_eq!(parse(&["--hidden"]), Ok(CliMode::Gui));
+    // Unknown flags and file arguments stay the window's business
+    assert_eq!(parse(&["--no-such-flag", "/tmp/clip.wav"]), Ok(CliMode::Gui));
+}
+
+#[test]
+fn test_headless_flags() {
+    assert_eq!(headless_options(&["--headless"]), HeadlessOptions::default());
+
+    let options = headless_options(&[
+        "--headless",
+        "--start-server",
+        "--keep-running",
+        "--remote",
+        "--offline",
+        "--port",
+        "18000",
+        "--data-dir",
+        "/srv/voicebox",
+    ]);
+    assert!(options.start_server && options.keep_running && options.remote);
+    assert_eq!(options.offline, Some(true));
+    assert_eq!(options.port, 18000);
+    assert_eq!(options.data_dir, Some(PathBuf::from("/srv/voicebox")));
+
+    // --flag=value works too, in any position
+    let options = headless_options(&["--port=18001", "--headless", "--data-dir=/data"]);
+    assert_eq!(options.port, 18001);
+    assert_eq!(options.data_dir, Some(PathBuf::from("/data")));
+}
+
+#[test]
+fn test_headless_rejects_bad_arguments() {
+    assert!(parse(&["--headless", "--bogus"]).unwrap_err().contains("--bogus"));
+    assert!(parse(&["--headless", "--port"]).unwrap_err().contains("needs a value"));
+    assert!(parse(&["--headless", "--port", "0"]).is_err());
+    assert!(parse(&["--headless", "--port", "70000"]).is_err());
+    assert!(parse(&["--headless", "--data-dir="]).is_err());
+    assert!(parse(&["--headless", "--remote=yes"]).unwrap_err().contains("does not take a value"));
+}
+
+#[test]
+fn test_headless_help() {
+    assert_eq!(parse(&["--headless", "--help"]), Ok(CliMode::Help));
+    assert_eq!(parse(&["--headless", "-h"]), Ok(CliMode::Help));
+}
+
+#[test]
+fn test_exit_codes_distinguish_startup_failures() {
+    let errors = [
+        ServerStartError::BinaryProblem {
+            kind: BinaryProblemKind::NotFound,
+        },
+        ServerStartError::SpawnFailed {
+            os_error: "boom".to_string(),
+        },
+        ServerStartError::Timeout {
+            last_phase: StartupPhase::Launching,
+            tail_lines: Vec::new(),
+        },
+        ServerStartError::CrashedDuringStartup {
+            exit_code: Some(1),
+            tail_lines: Vec::new(),
+        },
+        ServerStartError::PortConflict {
+            occupant: "nginx".to_string(),
+        },
+        ServerStartError::Storage {
+            problem: StorageProblem {
+                kind: StorageProblemKind::ReadOnly,
+                path: PathBuf::from("/Volumes/profile/voicebox"),
+                detail: "Read-only file system".to_string(),
+            },
+        },
+        ServerStartError::Internal {
+            message: "no data dir".to_string(),
+        },
+    ];
+    let mut codes: Vec<i32> = errors.iter().map(headless::exit_code).collect();
+    assert!(!codes.contains(&headless::EXIT_OK));
+    assert!(!codes.contains(&headless::EXIT_USAGE));
+    codes.sort();
+    codes.dedup();
+    assert_eq!(codes.len(), errors.len(), "every failure kind needs its own code");
+}
+
+#[test]
+fn test_reports_no_server_without_start_server() {
+    // A port that was free a moment ago
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let options = HeadlessOptions {
+        port,
+        ..Default::default()
+    };
+    assert_eq!(headless::run(options), headless::EXIT_NOT_RUNNING);
+}
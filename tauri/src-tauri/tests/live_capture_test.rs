@@ -0,0 +1,29 @@
+// Records a few seconds of the real system audio through the platform
+// backend. It needs an audio device and something playing while it runs,
+// so it only runs when asked for:
+//   cargo test --test live_capture_test -- --ignored
+
+use base64::Engine;
+use std::time::Duration;
+use voicebox::audio_capture::{start_capture, stop_capture, AudioCaptureState};
+
+#[tokio::test]
+#[ignore = "needs an audio device and something playing"]
+async fn test_records_what_is_playing() {
+    let state = AudioCaptureState::new();
+    let capabilities = state.capabilities();
+    assert!(capabilities.system_audio, "{:?}", capabilities.reason);
+    start_capture(&state, 10).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let wav = base64::engine::general_purpose::STANDARD
+        .decode(stop_capture(&state).await.unwrap())
+        .unwrap();
+    let mut reader = hound::WavReader::new(std::io::Cursor::new(wav)).unwrap();
+    let spec = reader.spec();
+    let samples: Vec<i16> = reader.samples::<i16>().map(Result::unwrap).collect();
+    let secs = samples.len() as f32 / spec.channels as f32 / spec.sample_rate as f32;
+    assert!((2.5..3.5).contains(&secs), "captured {}s", secs);
+    let peak = samples.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0);
+    assert!(peak > 300, "nothing was heard; is something playing?");
+}
@@ -0,0 +1,87 @@
+// Checks counter and histogram snapshots and reset on local instances, since
+// the process-wide METRICS is shared with whatever else runs in the binary:
+//   cargo test --test metrics_test
+
+use voicebox::metrics::{Bucket, Histogram, Metrics};
+
+#[test]
+fn test_histogram_buckets_by_powers_of_two() {
+    let histogram = Histogram::new();
+    for value in [0, 1, 2, 3, 4, 1000, u64::MAX / 2] {
+        histogram.record(value);
+    }
+
+    let snapshot = histogram.snapshot();
+    assert_eq!(snapshot.count, 7);
+    assert_eq!(snapshot.max, u64::MAX / 2);
+    assert_eq!(
+        snapshot.buckets,
+        vec![
+            Bucket { le: 0, count: 1 },
+            Bucket { le: 1, count: 1 },
+            Bucket { le: 3, count: 2 },
+            Bucket { le: 7, count: 1 },
+            Bucket { le: 1023, count: 1 },
+            Bucket { le: u64::MAX / 2, count: 1 },
+        ]
+    );
+}
+
+#[test]
+fn test_histogram_mean() {
+    let histogram = Histogram::new();
+    assert_eq!(histogram.snapshot().mean, None);
+
+    for ms in [900, 1100, 1600] {
+        histogram.record(ms);
+    }
+    let snapshot = histogram.snapshot();
+    assert_eq!(snapshot.sum, 3600);
+    assert_eq!(snapshot.mean, Some(1200.0));
+}
+
+#[test]
+fn test_counts_from_many_threads() {
+    let metrics = Metrics::new();
+    std::thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| {
+                for _ in 0..1000 {
+                    metrics.frames_captured.add(480);
+                    metrics.ipc_payload_bytes.record(64);
+                }
+            });
+        }
+    });
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.frames_captured, 8 * 1000 * 480);
+    assert_eq!(snapshot.ipc_payload_bytes.count, 8000);
+}
+
+#[test]
+fn test_reset_clears_everything() {
+    let metrics = Metrics::new();
+    metrics.captures_started.inc();
+    metrics.server_restarts.inc();
+    metrics.server_startup_ms.record(2500);
+    assert_eq!(metrics.snapshot().reset_at, None);
+
+    metrics.reset();
+    let snapshot = metrics.snapshot();
+    assert!(snapshot.reset_at.is_some());
+    let empty = Metrics::new().snapshot();
+    assert_eq!(snapshot.captures_started, 0);
+    assert_eq!(snapshot.server_restarts, 0);
+    assert_eq!(snapshot.server_startup_ms, empty.server_startup_ms);
+}
+
+#[test]
+fn test_snapshot_serializes() {
+    let metrics = Metrics::new();
+    metrics.playback_underruns.inc();
+    let json = serde_json::to_value(metrics.snapshot()).unwrap();
+    assert_eq!(json["playback_underruns"], 1);
+    assert_eq!(json["reset_at"], serde_json::Value::Null);
+    assert_eq!(json["server_startup_ms"]["buckets"], serde_json::json!([]));
+}
@@ -0,0 +1,40 @@
+// Runs the capture-to-payload benchmark on a short clip and checks the
+// stages and sizes it reports:
+//   cargo test --test pipeline_benchmark_test
+
+use std::cell::Cell;
+use voicebox::pipeline_benchmark::{last, run, MAX_DURATION_SECS};
+
+#[test]
+fn test_reports_each_stage() {
+    let report = run(1, None).unwrap();
+    let stages: Vec<&str> = report.stages.iter().map(|stage| stage.stage).collect();
+    assert_eq!(stages, ["copy", "convert", "assemble", "base64", "serialize"]);
+
+    // One second of 48 kHz stereo 16-bit plus the 44-byte header
+    assert_eq!(report.wav_bytes, 44 + 48000 * 2 * 2);
+    assert_eq!(report.payload_bytes, report.wav_bytes.div_ceil(3) * 4);
+    assert_eq!(report.stages[1].bytes, 48000 * 2 * 4);
+    assert!(report.stages.iter().all(|stage| stage.ms >= 0.0 && stage.mb_per_sec >= 0.0));
+    assert!(report.total_ms >= report.stages.iter().map(|stage| stage.ms).sum::<f64>() * 0.99);
+
+    assert_eq!(last().unwrap().ran_at, report.ran_at);
+}
+
+#[test]
+fn test_emits_the_payload() {
+    let emitted = Cell::new(0);
+    let emit = |payload: &str| {
+        emitted.set(payload.len());
+        Ok(())
+    };
+    let report = run(1, Some(&emit)).unwrap();
+    assert_eq!(report.stages.last().unwrap().stage, "emit");
+    assert_eq!(emitted.get() as u64, report.payload_bytes);
+}
+
+#[test]
+fn test_rejects_out_of_range_durations() {
+    assert_eq!(run(0, None).unwrap_err().code(), "invalid_argument");
+    assert_eq!(run(MAX_DURATION_SECS + 1, None).unwrap_err().code(), "invalid_argument");
+}
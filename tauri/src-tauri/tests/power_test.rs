@@ -0,0 +1,103 @@
+// Exercises the wake-lock reference counting against a fake inhibitor, so no
+// real sleep assertion is taken:
+//   cargo test --test power_test
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use voicebox::power::{PowerState, SleepInhibitor};
+
+/// Counts OS-level acquires and releases; fails every acquire if `fail` is set.
+#[derive(Clone, Default)]
+struct FakeInhibitor {
+    acquired: Arc<AtomicUsize>,
+    released: Arc<AtomicUsize>,
+    fail: bool,
+}
+
+impl SleepInhibitor for FakeInhibitor {
+    fn acquire(&self, _reason: &str) -> Result<(), String> {
+        if self.fail {
+            return Err("refused".to_string());
+        }
+        self.acquired.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn release(&self) {
+        self.released.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn counts(fake: &FakeInhibitor) -> (usize, usize) {
+    (fake.acquired.load(Ordering::SeqCst), fake.released.load(Ordering::SeqCst))
+}
+
+#[test]
+fn test_assertion_follows_first_and_last_holder() {
+    let fake = FakeInhibitor::default();
+    let power = PowerState::with_inhibitor(fake.clone());
+
+    let capture = power.acquire("capture");
+    assert_eq!(counts(&fake), (1, 0));
+    let playback = power.acquire("playback");
+    assert_eq!(counts(&fake), (1, 0), "a second holder must not re-acquire");
+    assert_eq!(power.assertions().holders.len(), 2);
+
+    drop(capture);
+    assert_eq!(counts(&fake), (1, 0), "released while a holder remains");
+    assert!(power.assertions().active);
+
+    drop(playback);
+    assert_eq!(counts(&fake), (1, 1));
+    assert!(!power.assertions().active);
+    assert!(power.assertions().holders.is_empty());
+
+    // The next holder takes a fresh assertion
+    let _again = power.acquire("capture");
+    assert_eq!(counts(&fake), (2, 1));
+}
+
+#[test]
+fn test_busy_holds_end_once() {
+    let fake = FakeInhibitor::default();
+    let power = PowerState::with_inhibitor(fake.clone());
+
+    let id = power.begin_busy("batch generation");
+    assert_eq!(counts(&fake), (1, 0));
+    assert_eq!(power.assertions().holders[0].reason, "batch generation");
+
+    assert!(power.end_busy(id));
+    assert!(!power.end_busy(id), "ending twice is a no-op");
+    assert_eq!(counts(&fake), (1, 1));
+}
+
+#[test]
+fn test_release_all_clears_everything_once() {
+    let fake = FakeInhibitor::default();
+    let power = PowerState::with_inhibitor(fake.clone());
+
+    let lock = power.acquire("capture");
+    power.begin_busy("export");
+    power.release_all();
+    assert_eq!(counts(&fake), (1, 1));
+    assert!(power.assertions().holders.is_empty());
+
+    // A lock outliving release_all must not release a second time
+    drop(lock);
+    assert_eq!(counts(&fake), (1, 1));
+}
+
+#[test]
+fn test_refused_assertion_is_never_released() {
+    let fake = FakeInhibitor {
+        fail: true,
+        ..Default::default()
+    };
+    let power = PowerState::with_inhibitor(fake.clone());
+
+    let lock = power.acquire("capture");
+    assert!(!power.assertions().active);
+    assert_eq!(power.assertions().holders.len(), 1, "the holder still counts");
+    drop(lock);
+    assert_eq!(counts(&fake), (0, 0));
+}
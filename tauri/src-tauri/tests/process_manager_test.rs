@@ -0,0 +1,381 @@
+// Exercises the start_server serialization without spawning a real sidecar:
+// the launch closure stands in for the spawn and counts how often it runs.
+// The readiness probe, the wait for a freed port and the choice of a free
+// port are pointed at local listeners standing in for a starting or stopping
+// server, or another program. Graceful stops run against `sh` standing in
+// for the server.
+//   cargo test --test process_manager_test
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::time::Duration;
+use voicebox::process_manager::{
+    fetch_server_version, identify_server, is_port_bindable, is_ready, is_server_process, parse_external_url,
+    pick_port, remote_urls, start_serialized, stop_gracefully, stop_server_process_within, wait_for_exit,
+    wait_for_port_free,
+    CloseAction, ProbeFailure, RestartBudget, ServerInfo, ServerState, AUTO_RESTART_BACKOFF, AUTO_RESTART_WINDOW,
+    READY_PROBE_TIMEOUT,
+};
+
+fn fake_server_info(pid: u32) -> ServerInfo {
+    ServerInfo {
+        url: "http://127.0.0.1:17493".to_string(),
+        remote: false,
+        remote_urls: Vec::new(),
+        port: 17493,
+        pid: Some(pid),
+        adopted_existing: false,
+        started_at: 0,
+        startup_ms: None,
+        auth_token_present: false,
+        auth_token: None,
+        version: Some("0.1.13".to_string()),
+        offline: Some(false),
+        firewall_rule: None,
+        managed: true,
+        launch_options: None,
+    }
+}
+
+/// Mocked spawn: takes a while, like waiting for the server's ready line, then
+/// publishes its info the way start_server does.
+async fn fake_launch(state: &ServerState, spawns: &AtomicUsize) -> Result<ServerInfo, String> {
+    let spawn_number = spawns.fetch_add(1, Ordering::SeqCst) as u32 + 1;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    let info = fake_server_info(1000 + spawn_number);
+    *state.info.lock().unwrap() = Some(info.clone());
+    Ok(info)
+}
+
+#[tokio::test]
+async fn test_concurrent_starts_spawn_once() {
+    let state = ServerState::new();
+    let spawns = AtomicUsize::new(0);
+
+    let (first, second) = tokio::join!(
+        start_serialized(&state, |_| async { true }, || fake_launch(&state, &spawns)),
+        start_serialized(&state, |_| async { true }, || fake_launch(&state, &spawns)),
+    );
+
+    assert_eq!(
+        spawns.load(Ordering::SeqCst),
+        1,
+        "expected exactly one spawn"
+    );
+    let first = first.expect("first start failed");
+    let second = second.expect("second start failed");
+    assert_eq!(
+        first, second,
+        "second caller should get the first caller's server"
+    );
+}
+
+#[tokio::test]
+async fn test_unhealthy_server_is_restarted() {
+    let state = ServerState::new();
+    let spawns = AtomicUsize::new(0);
+
+    let first = start_serialized(&state, |_| async { true }, || fake_launch(&state, &spawns))
+        .await
+        .expect("first start failed");
+
+    // The stored server stopped answering, so the next start must not short-circuit
+    let second = start_serialized(&state, |_| async { false }, || fake_launch(&state, &spawns))
+        .await
+        .expect("restart failed");
+
+    assert_eq!(spawns.load(Ordering::SeqCst), 2, "expected a respawn");
+    assert_ne!(first.pid, second.pid);
+}
+
+/// ServerState as it looks with a sidecar we spawned still running.
+fn running_state(keep_running: bool) -> ServerState {
+    let state = ServerState::new();
+    *state.server_pid.lock().unwrap() = Some(4242);
+    *state.info.lock().unwrap() = Some(fake_server_info(4242));
+    *state.keep_running_on_close.lock().unwrap() = keep_running;
+    state
+}
+
+#[test]
+fn test_close_stops_server_unless_kept_running() {
+    assert_eq!(running_state(false).begin_close(), Some(CloseAction::StopServer));
+    assert_eq!(running_state(true).begin_close(), Some(CloseAction::KeepServer));
+
+    // Nothing of ours to stop
+    assert_eq!(ServerState::new().begin_close(), Some(CloseAction::KeepServer));
+
+    let external = running_state(false);
+    *external.managed.lock().unwrap() = false;
+    assert_eq!(external.begin_close(), Some(CloseAction::KeepServer));
+}
+
+#[test]
+fn test_repeated_close_requests_are_ignored() {
+    let state = running_state(false);
+    assert_eq!(state.begin_close(), Some(CloseAction::StopServer));
+    // A user hammering the close button while the server stops gets no second shutdown
+    for _ in 0..10 {
+        assert_eq!(state.begin_close(), None);
+    }
+    assert_eq!(*state.server_pid.lock().unwrap(), Some(4242), "deciding must not touch the server");
+}
+
+#[test]
+fn test_uptime_counts_from_the_recorded_start() {
+    let mut info = fake_server_info(4242);
+    info.started_at = 1_700_000_000;
+    assert_eq!(info.uptime_secs(1_700_000_090), 90);
+    // A clock set back since the spawn doesn't wrap around
+    assert_eq!(info.uptime_secs(1_699_999_000), 0);
+}
+
+/// A port where each connection gets `response` once its request is read,
+/// or no answer at all without one.
+async fn answering(response: Option<&'static str>) -> u16 {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut request = [0u8; 1024];
+                let _ = socket.read(&mut request).await;
+                match response {
+                    Some(response) => {
+                        let _ = socket.write_all(response.as_bytes()).await;
+                    }
+                    None => tokio::time::sleep(std::time::Duration::from_secs(10)).await,
+                }
+            });
+        }
+    });
+    port
+}
+
+#[tokio::test]
+async fn test_ready_only_once_health_answers_200() {
+    let healthy = answering(Some("HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}")).await;
+    assert!(is_ready(healthy).await);
+
+    // Bound, but the routes aren't there yet
+    let starting = answering(Some("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")).await;
+    assert!(!is_ready(starting).await);
+    let unavailable =
+        answering(Some("HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")).await;
+    assert!(!is_ready(unavailable).await);
+
+    // Nothing listening yet
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    assert!(!is_ready(closed).await);
+}
+
+#[tokio::test]
+async fn test_ready_probe_gives_up_on_a_server_that_doesnt_answer() {
+    let hung = answering(None).await;
+    let started = std::time::Instant::now();
+    assert!(!is_ready(hung).await);
+    assert!(started.elapsed() < READY_PROBE_TIMEOUT * 2, "took {:?}", started.elapsed());
+}
+
+#[tokio::test]
+async fn test_external_servers_are_told_apart_from_other_hosts() {
+    let url = |port: u16| format!("http://127.0.0.1:{}", port);
+    let voicebox = answering(Some(concat!(
+        "HTTP/1.1 200 OK\r\nContent-Length: 44\r\nConnection: close\r\n\r\n",
+        r#"{"message":"voicebox API","version":"0.2.0"}"#
+    )))
+    .await;
+    assert_eq!(identify_server(&url(voicebox), None).await, Ok(Some("0.2.0".to_string())));
+
+    let web_page = answering(Some("HTTP/1.1 200 OK\r\nContent-Length: 6\r\nConnection: close\r\n\r\n<html>")).await;
+    assert!(matches!(identify_server(&url(web_page), None).await, Err(ProbeFailure::NotVoicebox(_))));
+    let missing = answering(Some("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")).await;
+    assert!(matches!(identify_server(&url(missing), None).await, Err(ProbeFailure::NotVoicebox(_))));
+
+    let locked = answering(Some("HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")).await;
+    assert_eq!(identify_server(&url(locked), Some("wrong")).await, Err(ProbeFailure::Unauthorized));
+
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    assert!(matches!(identify_server(&url(closed), None).await, Err(ProbeFailure::Unreachable(_))));
+}
+
+#[test]
+fn test_external_urls_are_normalized_to_the_server_root() {
+    let parsed = parse_external_url;
+    assert_eq!(parsed(" http://box:8000/ "), Ok(("http://box:8000".to_string(), 8000)));
+    assert_eq!(parsed("https://gpu.lan"), Ok(("https://gpu.lan:443".to_string(), 443)));
+    assert_eq!(parsed("http://[::1]:9000"), Ok(("http://[::1]:9000".to_string(), 9000)));
+
+    // What /health is appended to must be the root
+    assert!(parsed("http://box:8000/?x=1").is_err());
+    assert!(parsed("http://box:8000/api").is_err());
+    assert!(parsed("http://box:8000/#top").is_err());
+    assert!(parsed("ftp://box:8000").is_err());
+    assert!(parsed("box:8000").is_err());
+}
+
+#[tokio::test]
+async fn test_start_server_reports_the_server_it_started() {
+    let info = serde_json::to_value(fake_server_info(4242)).unwrap();
+    assert_eq!(info["url"], "http://127.0.0.1:17493");
+    assert_eq!(info["port"], 17493);
+    assert_eq!(info["pid"], 4242);
+    assert_eq!(info["adopted_existing"], false);
+    assert_eq!(info["auth_token_present"], false);
+    assert_eq!(info["version"], "0.1.13");
+    assert_eq!(info["remote_urls"], serde_json::json!([]));
+
+    // The version is what the root endpoint says, when it says one
+    let voicebox = answering(Some(concat!(
+        "HTTP/1.1 200 OK\r\nContent-Length: 44\r\nConnection: close\r\n\r\n",
+        r#"{"message":"voicebox API","version":"0.2.0"}"#
+    )))
+    .await;
+    assert_eq!(fetch_server_version(voicebox).await.as_deref(), Some("0.2.0"));
+    let web_page = answering(Some("HTTP/1.1 200 OK\r\nContent-Length: 6\r\nConnection: close\r\n\r\n<html>")).await;
+    assert_eq!(fetch_server_version(web_page).await, None);
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    assert_eq!(fetch_server_version(closed).await, None);
+
+    // Only addresses other machines can reach, on the server's port
+    for url in remote_urls(17493) {
+        assert!(url.ends_with(":17493"), "{}", url);
+        assert!(!url.contains("127.0.0.1") && !url.contains("0.0.0.0"), "{}", url);
+    }
+}
+
+#[tokio::test]
+async fn test_waits_for_the_port_to_be_let_go() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    // Held past the timeout
+    assert!(!wait_for_port_free(port, None, Duration::from_millis(300)).await);
+
+    // Let go of partway through
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        drop(listener);
+    });
+    let started = std::time::Instant::now();
+    assert!(wait_for_port_free(port, None, Duration::from_secs(5)).await);
+    assert!(started.elapsed() >= Duration::from_millis(250), "free after {:?}", started.elapsed());
+
+    // A process that hasn't exited keeps it taken, listening or not
+    assert!(!wait_for_port_free(port, Some(std::process::id()), Duration::from_millis(300)).await);
+}
+
+#[test]
+fn test_a_taken_port_is_swapped_for_a_free_one() {
+    let localhost = std::net::IpAddr::from(std::net::Ipv4Addr::LOCALHOST);
+    let other_program = std::net::TcpListener::bind((localhost, 0)).unwrap();
+    let taken = other_program.local_addr().unwrap().port();
+    assert!(!is_port_bindable(localhost, taken));
+
+    let picked = pick_port(localhost, taken).expect("no free port");
+    assert_ne!(picked, taken);
+    assert!(is_port_bindable(localhost, picked));
+
+    // A free preferred port is kept
+    drop(other_program);
+    assert_eq!(pick_port(localhost, taken), Some(taken));
+}
+
+#[test]
+fn test_automatic_restarts_back_off_and_stop_at_a_crash_loop() {
+    let mut budget = RestartBudget::default();
+    let start = std::time::Instant::now();
+    let delays: Vec<_> = (0..4).map(|n| budget.take(start + Duration::from_secs(n * 10))).collect();
+    assert_eq!(
+        delays,
+        [Some(AUTO_RESTART_BACKOFF), Some(AUTO_RESTART_BACKOFF * 2), Some(AUTO_RESTART_BACKOFF * 4), None]
+    );
+
+    // Once the first restart is out of the window there's room for one more
+    assert!(budget.take(start + AUTO_RESTART_WINDOW).is_some());
+    assert!(budget.take(start + AUTO_RESTART_WINDOW).is_none());
+
+    // A deliberate stop starts over
+    budget.reset();
+    assert_eq!(budget.take(start + AUTO_RESTART_WINDOW), Some(AUTO_RESTART_BACKOFF));
+}
+
+/// A stand-in server in its own process group, reaped in the background so
+/// it stops counting as running once it exits.
+#[cfg(unix)]
+fn spawn_server(script: &str) -> u32 {
+    use std::os::unix::process::CommandExt;
+    let mut child = std::process::Command::new("sh")
+        .args(["-c", script])
+        .process_group(0)
+        .spawn()
+        .unwrap();
+    let pid = child.id();
+    std::thread::spawn(move || child.wait());
+    pid
+}
+
+#[cfg(unix)]
+#[test]
+fn test_a_server_gets_the_grace_period_before_being_killed() {
+    let closed_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+    // With no /shutdown to answer, SIGTERM stops it well inside the grace period
+    let pid = spawn_server("sleep 30");
+    let started = std::time::Instant::now();
+    assert!(stop_gracefully(pid, closed_port, Duration::from_secs(5)));
+    assert!(started.elapsed() < Duration::from_secs(3), "took {:?}", started.elapsed());
+
+    // One that ignores it is left running for the caller to kill, once the grace period is up
+    let pid = spawn_server("trap '' TERM; sleep 30");
+    std::thread::sleep(Duration::from_millis(200));
+    let started = std::time::Instant::now();
+    assert!(!stop_gracefully(pid, closed_port, Duration::from_millis(500)));
+    assert!(started.elapsed() >= Duration::from_millis(450), "gave up after {:?}", started.elapsed());
+    assert!(started.elapsed() < Duration::from_secs(2), "took {:?}", started.elapsed());
+    voicebox::process_manager::force_kill(pid);
+}
+
+/// A copy of `sleep` named like the sidecar, so it passes for the server.
+#[cfg(unix)]
+fn server_binary(dir: &std::path::Path) -> std::path::PathBuf {
+    let sleep = ["/bin/sleep", "/usr/bin/sleep"].into_iter().find(|path| std::path::Path::new(path).exists());
+    let server = dir.join("voicebox-server");
+    std::fs::copy(sleep.unwrap(), &server).unwrap();
+    server
+}
+
+#[cfg(unix)]
+#[test]
+fn test_a_reused_pid_is_not_taken_for_the_server() {
+    // Anything not named like the sidecar, as a PID reused after the server exited would be
+    let pid = spawn_server("sleep 30");
+    assert!(!is_server_process(pid));
+    voicebox::process_manager::force_kill(pid);
+
+    let scratch = tempfile::tempdir().unwrap();
+    let pid = spawn_server(&format!("exec {} 30", server_binary(scratch.path()).display()));
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(is_server_process(pid));
+
+    voicebox::process_manager::force_kill(pid);
+    assert!(wait_for_exit(pid, Duration::from_secs(3)));
+    assert!(!is_server_process(pid));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_quitting_stops_the_server_within_the_grace_period() {
+    let closed_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let scratch = tempfile::tempdir().unwrap();
+
+    // Ignores SIGTERM, so it has to be killed, and that comes out of the grace period too
+    let pid = spawn_server(&format!("trap '' TERM; exec {} 30", server_binary(scratch.path()).display()));
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(is_server_process(pid));
+    let budget = Duration::from_secs(1);
+    let started = std::time::Instant::now();
+    let outcome = stop_server_process_within(pid, closed_port, budget);
+    assert!(outcome.terminated && outcome.forced);
+    assert!(started.elapsed() < budget + Duration::from_millis(100), "took {:?}", started.elapsed());
+    assert!(!is_server_process(pid));
+}
@@ -0,0 +1,103 @@
+// Checks how the app finds what's listening on the server port and decides
+// whether it's a voicebox server to adopt or something else to move away from,
+// and that killing a server takes its children with it.
+//   cargo test --test process_utils_test
+
+use std::net::TcpListener;
+use voicebox::process_utils::{
+    descendants, is_running, is_voicebox_server, kill_tree, listeners_on, process_name, process_table, Listener,
+};
+
+#[test]
+fn test_voicebox_servers_are_told_apart_by_name() {
+    for name in ["voicebox-server", "voicebox-server.exe", "Voicebox-Server.EXE", "voicebox-", "voicebox"] {
+        assert!(is_voicebox_server(name), "{} should be a voicebox server", name);
+    }
+    // A dev server from Python, or whatever else took the port
+    for name in ["python3", "python.exe", "node", "nginx", ""] {
+        assert!(!is_voicebox_server(name), "{} shouldn't be a voicebox server", name);
+    }
+
+    let listener = Listener {
+        pid: 42,
+        name: "python3".to_string(),
+    };
+    assert!(!listener.is_voicebox_server());
+    assert_eq!(listener.to_string(), "python3 (PID 42)");
+    let unnamed = Listener {
+        pid: 42,
+        name: String::new(),
+    };
+    assert_eq!(unnamed.to_string(), "unknown process (PID 42)");
+}
+
+#[test]
+fn test_our_own_listener_is_found() {
+    let socket = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = socket.local_addr().unwrap().port();
+    let pid = std::process::id();
+
+    let listeners = listeners_on(port);
+    let ours = listeners.iter().find(|listener| listener.pid == pid);
+    assert!(ours.is_some(), "expected PID {} among {:?}", pid, listeners);
+    assert_eq!(Some(ours.unwrap().name.clone()), process_name(pid));
+    assert!(!ours.unwrap().is_voicebox_server());
+
+    drop(socket);
+    assert!(listeners_on(port).iter().all(|listener| listener.pid != pid));
+}
+
+#[test]
+fn test_running_processes_are_detected() {
+    assert!(is_running(std::process::id()));
+    assert!(process_name(std::process::id()).is_some_and(|name| !name.is_empty()));
+
+    let mut child = std::process::Command::new(if cfg!(windows) { "cmd" } else { "true" })
+        .args(if cfg!(windows) { &["/C", "exit"][..] } else { &[][..] })
+        .spawn()
+        .unwrap();
+    let pid = child.id();
+    child.wait().unwrap();
+    assert!(!is_running(pid));
+}
+
+#[test]
+fn test_descendants_are_found_through_the_whole_tree() {
+    // 10 started 11 and 12, 11 started 13; 20 is unrelated, 30 its own parent
+    let table = [(10, 1), (11, 10), (12, 10), (13, 11), (20, 1), (30, 30), (31, 30)];
+    assert_eq!(descendants(10, &table), [11, 12, 13]);
+    assert_eq!(descendants(13, &table), Vec::<u32>::new());
+    assert_eq!(descendants(30, &table), [31]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_kill_tree_takes_the_children_too() {
+    let mut parent = std::process::Command::new("sh")
+        .args(["-c", "sleep 30 & wait"])
+        .spawn()
+        .unwrap();
+    let pid = parent.id();
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    let sleeper = loop {
+        if let Some(&child) = descendants(pid, &process_table()).first() {
+            break child;
+        }
+        assert!(std::time::Instant::now() < deadline, "sh never started its child");
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    };
+    assert_eq!(process_name(sleeper).as_deref(), Some("sleep"));
+
+    kill_tree(pid);
+    parent.wait().unwrap();
+    // Killed, though maybe not yet reaped by whoever adopted it
+    let state = |pid: u32| {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap_or_default();
+        stat.rsplit_once(')').and_then(|(_, rest)| rest.split_whitespace().next().map(str::to_string))
+    };
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while state(sleeper).is_some_and(|state| state != "Z") {
+        assert!(std::time::Instant::now() < deadline, "the child survived");
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+}
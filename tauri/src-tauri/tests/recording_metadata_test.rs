@@ -0,0 +1,159 @@
+// Round-trips recording metadata through JSON, including entries saved before
+// it had every field, and checks the INFO and BWF bext chunks it embeds, with
+// hound as the reference parser for the WAV around them:
+//   cargo test --test recording_metadata_test
+
+use std::io::Cursor;
+use voicebox::audio_util::{encode_wav, EncodeSpec};
+use voicebox::recording_metadata::{
+    embed, CaptureHealth, MetadataChunk, PauseInterval, Processing, RecordingMetadata,
+};
+
+/// 2024-03-01 12:34:56 UTC
+const STARTED_AT: u64 = 1_709_296_496;
+
+fn metadata() -> RecordingMetadata {
+    RecordingMetadata {
+        backend: Some("wasapi".to_string()),
+        sample_rate: 48000,
+        channels: 2,
+        duration_ms: 10,
+        dropouts: 2,
+        processing: vec![Processing::Normalize],
+        started_at: Some(STARTED_AT),
+        pre_roll: None,
+        health: CaptureHealth::default(),
+        app_version: Some("1.2.3".to_string()),
+        fingerprint: None,
+        pauses: Vec::new(),
+        source_format: None,
+    }
+}
+
+fn clip() -> Vec<f32> {
+    (0..960).map(|n| (n as f32 / 960.0) - 0.5).collect()
+}
+
+/// The top-level chunks as (id, body).
+fn chunks(wav: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    let mut offset = 12;
+    while offset + 8 <= wav.len() {
+        let id: [u8; 4] = wav[offset..offset + 4].try_into().unwrap();
+        let size = u32::from_le_bytes(wav[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        chunks.push((id, &wav[offset + 8..offset + 8 + size]));
+        offset += 8 + size + size % 2;
+    }
+    chunks
+}
+
+/// hound still reads the audio, unchanged.
+fn assert_same_audio(embedded: &[u8], original: &[u8]) {
+    let read = |wav: &[u8]| {
+        let mut reader = hound::WavReader::new(Cursor::new(wav.to_vec())).expect("a WAV hound can read");
+        (reader.spec(), reader.samples::<i16>().map(Result::unwrap).collect::<Vec<_>>())
+    };
+    assert_eq!(read(embedded), read(original));
+    let riff_size = u32::from_le_bytes(embedded[4..8].try_into().unwrap()) as usize;
+    assert_eq!(riff_size + 8, embedded.len());
+}
+
+#[test]
+fn test_round_trips_through_json() {
+    let json = serde_json::to_string(&metadata()).unwrap();
+    assert_eq!(serde_json::from_str::<RecordingMetadata>(&json).unwrap(), metadata());
+    assert!(json.contains(r#""processing":["normalize"]"#));
+
+    let paused = RecordingMetadata {
+        pauses: vec![
+            PauseInterval {
+                at_ms: 4,
+                duration_ms: 1500,
+            },
+            PauseInterval {
+                at_ms: 8,
+                duration_ms: 250,
+            },
+        ],
+        ..metadata()
+    };
+    let json = serde_json::to_string(&paused).unwrap();
+    assert_eq!(serde_json::from_str::<RecordingMetadata>(&json).unwrap(), paused);
+    assert!(paused.summary().ends_with(", paused_ms=1750"), "{}", paused.summary());
+}
+
+#[test]
+fn test_older_entries_still_load() {
+    let old: RecordingMetadata = serde_json::from_str(r#"{ "backend": "screencapturekit", "sample_rate": 44100 }"#).unwrap();
+    assert_eq!(old.backend.as_deref(), Some("screencapturekit"));
+    assert_eq!(old.sample_rate, 44100);
+    assert_eq!(old.dropouts, 0);
+    assert!(old.processing.is_empty());
+    assert_eq!(old.started_at, None);
+
+    assert_eq!(serde_json::from_str::<RecordingMetadata>("{}").unwrap(), RecordingMetadata::default());
+}
+
+#[test]
+fn test_bext_chunk_layout() {
+    let wav = encode_wav(&clip(), EncodeSpec::pcm16(48000, 2)).unwrap();
+    let embedded = embed(&wav, &metadata(), MetadataChunk::Bext).unwrap();
+    assert_same_audio(&embedded, &wav);
+
+    let chunks = chunks(&embedded);
+    let ids: Vec<&[u8]> = chunks.iter().map(|(id, _)| &id[..]).collect();
+    assert_eq!(ids, [&b"fmt "[..], b"bext", b"data"]);
+    let bext = chunks[1].1;
+
+    // EBU Tech 3285 v2 field offsets
+    let text = |range: std::ops::Range<usize>| String::from_utf8(bext[range].to_vec()).unwrap();
+    let description = text(0..256);
+    assert!(description.starts_with("Voicebox capture: backend=wasapi, 48000 Hz, 2 ch, dropouts=2, processing=normalize\0"));
+    assert!(text(256..288).starts_with("Voicebox\0"));
+    assert_eq!(text(288..320), "\0".repeat(32));
+    assert_eq!(text(320..330), "2024-03-01");
+    assert_eq!(text(330..338), "12:34:56");
+    let time_reference = u32::from_le_bytes(bext[338..342].try_into().unwrap()) as u64
+        | (u32::from_le_bytes(bext[342..346].try_into().unwrap()) as u64) << 32;
+    assert_eq!(time_reference, (12 * 3600 + 34 * 60 + 56) * 48000);
+    assert_eq!(u16::from_le_bytes(bext[346..348].try_into().unwrap()), 2);
+    assert!(bext[348..412].iter().all(|b| *b == 0), "UMID");
+    for field in bext[412..422].chunks(2) {
+        assert_eq!(i16::from_le_bytes(field.try_into().unwrap()), 0x7FFF);
+    }
+    assert!(bext[422..602].iter().all(|b| *b == 0), "reserved");
+    assert_eq!(text(602..bext.len()), "A=PCM,F=48000,W=16,M=stereo,T=Voicebox 1.2.3\r\n");
+}
+
+#[test]
+fn test_info_chunk_layout() {
+    let wav = encode_wav(&clip(), EncodeSpec::pcm16(48000, 1)).unwrap();
+    let embedded = embed(&wav, &metadata(), MetadataChunk::Info).unwrap();
+    assert_same_audio(&embedded, &wav);
+
+    let chunks = chunks(&embedded);
+    let (id, list) = chunks[1];
+    assert_eq!(&id, b"LIST");
+    assert_eq!(&list[..4], b"INFO");
+    let mut entries = Vec::new();
+    let mut offset = 4;
+    while offset < list.len() {
+        let size = u32::from_le_bytes(list[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        let value = &list[offset + 8..offset + 8 + size];
+        assert_eq!(value.last(), Some(&0), "INFO strings are NUL-terminated");
+        let value = String::from_utf8(value[..size - 1].to_vec()).unwrap();
+        entries.push((String::from_utf8(list[offset..offset + 4].to_vec()).unwrap(), value));
+        offset += 8 + size + size % 2;
+    }
+    assert_eq!(entries[0], ("ISFT".to_string(), "Voicebox 1.2.3".to_string()));
+    assert_eq!(entries[1], ("ICRD".to_string(), "2024-03-01".to_string()));
+    assert_eq!(entries[2].0, "ICMT");
+    assert!(entries[2].1.contains("dropouts=2"));
+}
+
+#[test]
+fn test_embedding_needs_a_wav() {
+    assert!(embed(b"fLaC....", &metadata(), MetadataChunk::Info).is_err());
+    let header_only = &encode_wav(&[], EncodeSpec::pcm16(48000, 1)).unwrap()[..36];
+    assert!(embed(header_only, &metadata(), MetadataChunk::Bext).is_err());
+}
@@ -0,0 +1,183 @@
+// Saves into a recordings library in a temp dir under each quota limit, and
+// checks what gets pruned, that pinned recordings never are, and that a
+// failed save or prune leaves the files and the index as they were:
+//   cargo test --test recordings_test
+
+use std::path::Path;
+use voicebox::recording_metadata::RecordingMetadata;
+use voicebox::recordings::{RecordingsState, RECORDINGS_DIR};
+use voicebox::settings::RecordingsQuota;
+
+const DAY: u64 = 24 * 60 * 60;
+/// 2024-03-01 00:00:00 UTC
+const NOW: u64 = 1_709_251_200;
+
+/// Not a real WAV: the library stores bytes as given.
+fn wav(len: usize, fill: u8) -> Vec<u8> {
+    vec![fill; len]
+}
+
+/// Save `recordings` as (size, age in days), oldest first, returning their ids.
+fn fill(state: &RecordingsState, dir: &Path, recordings: &[(usize, u64)]) -> Vec<String> {
+    let library = state.open(dir);
+    recordings
+        .iter()
+        .enumerate()
+        .map(|(i, &(len, age))| {
+            let quota = RecordingsQuota::default();
+            let (entry, _) = library
+                .add(&wav(len, i as u8), None, RecordingMetadata::default(), &quota, NOW - age * DAY)
+                .unwrap();
+            entry.id
+        })
+        .collect()
+}
+
+fn files(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir.join(RECORDINGS_DIR))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_saves_and_reports_usage() {
+    let scratch = tempfile::tempdir().unwrap();
+    let dir = scratch.path();
+    let state = RecordingsState::new();
+    let ids = fill(&state, dir, &[(100, 2), (300, 1)]);
+    let library = state.open(dir);
+    let entries = library.list().unwrap();
+    assert_eq!(entries.iter().map(|e| &e.id).collect::<Vec<_>>(), ids.iter().collect::<Vec<_>>());
+    assert_eq!(std::fs::read(library.path(&entries[1])).unwrap(), wav(300, 1));
+
+    let quota = RecordingsQuota {
+        max_items: Some(10),
+        ..Default::default()
+    };
+    let usage = library.usage(&quota).unwrap();
+    assert_eq!((usage.total_bytes, usage.items, usage.pinned_items), (400, 2, 0));
+    assert_eq!(usage.oldest_created_at, Some(NOW - 2 * DAY));
+    assert_eq!(usage.quota, quota);
+    assert_eq!(files(dir).len(), 3, "two recordings and the index");
+}
+
+#[test]
+fn test_removes_a_recording_and_its_file() {
+    let scratch = tempfile::tempdir().unwrap();
+    let dir = scratch.path();
+    let state = RecordingsState::new();
+    let ids = fill(&state, dir, &[(100, 2), (300, 1)]);
+    let library = state.open(dir);
+    assert_eq!(library.remove(&ids[0]).unwrap().id, ids[0]);
+    assert_eq!(library.list().unwrap().iter().map(|e| &e.id).collect::<Vec<_>>(), [&ids[1]]);
+    assert_eq!(files(dir).len(), 2, "one recording and the index");
+    assert_eq!(library.remove(&ids[0]).unwrap_err().code(), "not_found");
+    drop(library);
+}
+
+#[test]
+fn test_prunes_the_oldest_unpinned_to_fit() {
+    let scratch = tempfile::tempdir().unwrap();
+    let dir = scratch.path();
+    let state = RecordingsState::new();
+    let ids = fill(&state, dir, &[(100, 4), (100, 3), (100, 2), (100, 1)]);
+    let library = state.open(dir);
+    assert!(library.pin(&ids[0], true).unwrap().pinned);
+
+    let quota = RecordingsQuota {
+        max_items: Some(4),
+        ..Default::default()
+    };
+    let (entry, pruned) = library.add(&wav(100, 9), None, RecordingMetadata::default(), &quota, NOW).unwrap();
+    // The pinned one is older, but kept
+    assert_eq!(pruned.removed_ids, [ids[1].clone()]);
+    assert_eq!(pruned.freed_bytes, 100);
+    let kept: Vec<String> = library.list().unwrap().into_iter().map(|e| e.id).collect();
+    assert_eq!(kept, [ids[0].clone(), ids[2].clone(), ids[3].clone(), entry.id]);
+    assert_eq!(files(dir).len(), 5, "the pruned file is deleted with its entry");
+
+    // By size: two of the three unpinned have to go
+    let quota = RecordingsQuota {
+        max_total_bytes: Some(450),
+        ..Default::default()
+    };
+    let (_, pruned) = library.add(&wav(250, 10), None, RecordingMetadata::default(), &quota, NOW).unwrap();
+    assert_eq!(pruned.removed_ids, [ids[2].clone(), ids[3].clone()]);
+    assert_eq!(library.usage(&quota).unwrap().total_bytes, 450);
+}
+
+#[test]
+fn test_prunes_expired_recordings_even_under_the_limits() {
+    let scratch = tempfile::tempdir().unwrap();
+    let dir = scratch.path();
+    let state = RecordingsState::new();
+    let ids = fill(&state, dir, &[(100, 40), (100, 31), (100, 29)]);
+    let library = state.open(dir);
+    let quota = RecordingsQuota {
+        max_age_days: Some(30),
+        ..Default::default()
+    };
+    let (_, pruned) = library.add(&wav(100, 9), None, RecordingMetadata::default(), &quota, NOW).unwrap();
+    assert_eq!(pruned.removed_ids, [ids[0].clone(), ids[1].clone()]);
+    assert_eq!(pruned.freed_bytes, 200);
+    assert_eq!(library.list().unwrap().len(), 2);
+}
+
+#[test]
+fn test_pinned_recordings_are_never_pruned() {
+    let scratch = tempfile::tempdir().unwrap();
+    let dir = scratch.path();
+    let state = RecordingsState::new();
+    let ids = fill(&state, dir, &[(100, 2), (100, 1)]);
+    let library = state.open(dir);
+    for id in &ids {
+        library.pin(id, true).unwrap();
+    }
+    let before = (library.list().unwrap(), files(dir));
+
+    let quota = RecordingsQuota {
+        max_total_bytes: Some(250),
+        max_age_days: Some(1),
+        ..Default::default()
+    };
+    let err = library.add(&wav(100, 9), None, RecordingMetadata::default(), &quota, NOW).unwrap_err();
+    assert_eq!(err.code(), "quota_exceeded");
+    assert_eq!(
+        err.details(),
+        Some(serde_json::json!({"limit": "max_total_bytes", "max": 250, "required": 300}))
+    );
+    assert_eq!((library.list().unwrap(), files(dir)), before, "nothing saved or pruned");
+
+    let err = library.pin("no-such-recording", true).unwrap_err();
+    assert_eq!(err.code(), "not_found");
+}
+
+#[test]
+fn test_a_failed_prune_puts_everything_back() {
+    let scratch = tempfile::tempdir().unwrap();
+    let dir = scratch.path();
+    let state = RecordingsState::new();
+    fill(&state, dir, &[(100, 2), (100, 1)]);
+    let library = state.open(dir);
+    let before = (library.list().unwrap(), files(dir));
+
+    // The index can't be replaced while a directory holds its temp file's name
+    std::fs::create_dir(dir.join(RECORDINGS_DIR).join("index.tmp")).unwrap();
+    let quota = RecordingsQuota {
+        max_items: Some(1),
+        ..Default::default()
+    };
+    let err = library.add(&wav(100, 9), None, RecordingMetadata::default(), &quota, NOW).unwrap_err();
+    assert_eq!(err.code(), "io");
+    std::fs::remove_dir(dir.join(RECORDINGS_DIR).join("index.tmp")).unwrap();
+    assert_eq!((library.list().unwrap(), files(dir)), before);
+
+    // A crash between the index and the deletes leaves a .pruning file behind
+    std::fs::write(dir.join(RECORDINGS_DIR).join("old.wav.pruning"), b"x").unwrap();
+    let (entry, pruned) = library.add(&wav(100, 9), None, RecordingMetadata::default(), &quota, NOW).unwrap();
+    assert_eq!(pruned.removed_ids.len(), 2);
+    assert_eq!(files(dir), [entry.file, "index.json".to_string()]);
+}
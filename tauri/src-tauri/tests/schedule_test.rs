@@ -0,0 +1,102 @@
+// Parses cron expressions and RRULEs and walks their fire times in fixed
+// time zones, including what counts as missed after a gap:
+//   cargo test --test schedule_test
+
+use chrono::{DateTime, Duration, FixedOffset, TimeZone, Utc};
+use voicebox::schedule::{Recurrence, MAX_MISSED};
+
+fn utc(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+}
+
+fn next(expression: &str, after: DateTime<Utc>) -> DateTime<Utc> {
+    Recurrence::parse(expression).unwrap().next_after(&after).unwrap()
+}
+
+#[test]
+fn test_weekly_cron_and_rrule_agree() {
+    // 2024-03-05 is a Tuesday
+    let monday = utc(2024, 3, 4, 9, 0);
+    for expression in ["0 18 * * TUE", "0 18 * * 2", "FREQ=WEEKLY;BYDAY=TU;BYHOUR=18;BYMINUTE=0"] {
+        assert_eq!(next(expression, monday), utc(2024, 3, 5, 18, 0), "{}", expression);
+        // Strictly after: firing at 18:00 moves on a week
+        assert_eq!(next(expression, utc(2024, 3, 5, 18, 0)), utc(2024, 3, 12, 18, 0), "{}", expression);
+    }
+    assert_eq!(next("RRULE:FREQ=DAILY;BYHOUR=7,19", utc(2024, 3, 4, 7, 30)), utc(2024, 3, 4, 19, 0));
+}
+
+#[test]
+fn test_cron_fields() {
+    let start = utc(2024, 1, 31, 23, 58);
+    assert_eq!(next("*/15 * * * *", start), utc(2024, 2, 1, 0, 0));
+    assert_eq!(next("5/20 9-10 * * *", start), utc(2024, 2, 1, 9, 5));
+    assert_eq!(next("0 0 29 feb *", start), utc(2024, 2, 29, 0, 0));
+    // Sunday as 7, and a range of names
+    assert_eq!(next("30 12 * * 7", start), utc(2024, 2, 4, 12, 30));
+    assert_eq!(next("0 9 * * MON-FRI", utc(2024, 2, 2, 10, 0)), utc(2024, 2, 5, 9, 0));
+    // Day of month and weekday both restricted: either matches (the 1st, or a Friday)
+    assert_eq!(next("0 6 1 * FRI", utc(2024, 2, 1, 7, 0)), utc(2024, 2, 2, 6, 0));
+}
+
+#[test]
+fn test_invalid_expressions() {
+    for expression in [
+        "",
+        "0 18 * *",
+        "60 * * * *",
+        "0 24 * * *",
+        "0 0 0 * *",
+        "0 0 * 13 *",
+        "*/0 * * * *",
+        "10-5 * * * *",
+        "FREQ=WEEKLY;BYHOUR=18",
+        "FREQ=DAILY",
+        "FREQ=HOURLY;BYHOUR=1",
+        "FREQ=DAILY;BYHOUR=1;INTERVAL=2",
+        "FREQ=WEEKLY;BYDAY=1MO;BYHOUR=1",
+        "FREQ=DAILY;BYHOUR=1;COUNT=3",
+    ] {
+        assert!(Recurrence::parse(expression).is_err(), "{:?}", expression);
+    }
+    let never = Recurrence::parse("0 0 30 2 *").unwrap();
+    assert_eq!(never.next_after(&utc(2024, 1, 1, 0, 0)), None);
+}
+
+#[test]
+fn test_fire_times_are_local() {
+    let tz = FixedOffset::east_opt(-5 * 3600).unwrap();
+    let after = tz.with_ymd_and_hms(2024, 3, 4, 20, 0, 0).unwrap();
+    let fire = Recurrence::parse("0 18 * * TUE").unwrap().next_after(&after).unwrap();
+    assert_eq!(fire, tz.with_ymd_and_hms(2024, 3, 5, 18, 0, 0).unwrap());
+    assert_eq!(fire.with_timezone(&Utc), utc(2024, 3, 5, 23, 0));
+}
+
+#[test]
+fn test_due_separates_missed_runs_from_the_current_one() {
+    let recurrence = Recurrence::parse("0 * * * *").unwrap();
+    let grace = Duration::minutes(2);
+
+    // Closed from 09:30 to 12:01: 10:00 and 11:00 were missed, 12:00 starts late
+    let due = recurrence.due(&utc(2024, 3, 4, 9, 30), &utc(2024, 3, 4, 12, 1), grace);
+    assert_eq!(due.missed, [utc(2024, 3, 4, 10, 0), utc(2024, 3, 4, 11, 0)]);
+    assert_eq!(due.fire, Some(utc(2024, 3, 4, 12, 0)));
+
+    // A normal tick
+    let due = recurrence.due(&utc(2024, 3, 4, 12, 59), &utc(2024, 3, 4, 13, 0), grace);
+    assert!(due.missed.is_empty());
+    assert_eq!(due.fire, Some(utc(2024, 3, 4, 13, 0)));
+
+    let due = recurrence.due(&utc(2024, 3, 4, 13, 0), &utc(2024, 3, 4, 13, 1), grace);
+    assert_eq!((due.fire, due.missed.len()), (None, 0));
+}
+
+#[test]
+fn test_missed_runs_are_capped() {
+    let recurrence = Recurrence::parse("* * * * *").unwrap();
+    let now = utc(2024, 3, 8, 0, 0);
+    let due = recurrence.due(&utc(2024, 3, 1, 0, 0), &now, Duration::minutes(2));
+    assert_eq!(due.missed.len(), MAX_MISSED);
+    assert_eq!(due.missed[0], utc(2024, 3, 1, 0, 1));
+    // The current run still starts past the cap
+    assert_eq!(due.fire, Some(now));
+}
@@ -0,0 +1,53 @@
+// Checks the token remote-mode servers require: generated once, kept in the
+// data dir for later starts and adoptions, and readable only by its owner.
+//   cargo test --test server_auth_test
+
+use voicebox::server_auth::{read_or_create_token, read_token, token_path, TOKEN_BYTES};
+
+#[test]
+fn test_the_token_is_created_once_and_kept() {
+    let scratch = tempfile::tempdir().unwrap();
+    let dir = scratch.path();
+    assert_eq!(read_token(dir), None);
+
+    let token = read_or_create_token(dir).unwrap();
+    assert_eq!(token.len(), TOKEN_BYTES * 2);
+    assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_eq!(read_or_create_token(dir).unwrap(), token);
+    assert_eq!(read_token(dir), Some(token.clone()));
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(token_path(dir)).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    // Another data dir gets its own
+    assert_ne!(read_or_create_token(tempfile::tempdir().unwrap().path()).unwrap(), token);
+}
+
+#[test]
+fn test_a_damaged_token_file_is_replaced() {
+    let scratch = tempfile::tempdir().unwrap();
+    let dir = scratch.path();
+    std::fs::write(token_path(dir), "short").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(token_path(dir), std::fs::Permissions::from_mode(0o644)).unwrap();
+    }
+    assert_eq!(read_token(dir), None);
+
+    let token = read_or_create_token(dir).unwrap();
+    assert_eq!(token.len(), TOKEN_BYTES * 2);
+    assert_eq!(read_token(dir), Some(token));
+
+    // Made private, though the file it replaced wasn't
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(token_path(dir)).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
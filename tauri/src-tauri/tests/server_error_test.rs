@@ -0,0 +1,52 @@
+// Checks how startup output is read into phases and reported while the app
+// waits for the server:
+//   cargo test --test server_error_test
+
+use voicebox::server_error::{StartupPhase, StartupProgress};
+use voicebox::settings::{validate_startup_timeout, MAX_STARTUP_TIMEOUT_SECS};
+
+#[test]
+fn test_startup_phases_are_read_from_server_output() {
+    let phase = |line| StartupPhase::from_log_line(line);
+    assert_eq!(phase("INFO voicebox-server starting up..."), Some(StartupPhase::ImportingModules));
+    assert_eq!(phase("INFO Importing backend.main (this may take a while)..."), Some(StartupPhase::ImportingModules));
+    assert_eq!(phase("INFO Initializing database..."), Some(StartupPhase::InitializingDatabase));
+    assert_eq!(
+        phase("Migrating story_items: adding track column"),
+        Some(StartupPhase::ApplyingMigrations)
+    );
+    assert_eq!(phase("INFO:     Waiting for application startup."), Some(StartupPhase::StartingHttpServer));
+    assert_eq!(phase("GPU available: no"), None);
+}
+
+#[test]
+fn test_progress_events_carry_the_phase_line_and_elapsed_time() {
+    let progress = StartupProgress {
+        phase: StartupPhase::ApplyingMigrations,
+        line: Some("Migrating profiles: adding avatar_path column".to_string()),
+        elapsed_secs: 42,
+    };
+    assert_eq!(
+        serde_json::to_value(&progress).unwrap(),
+        serde_json::json!({
+            "phase": "applying_migrations",
+            "line": "Migrating profiles: adding avatar_path column",
+            "elapsed_secs": 42,
+        })
+    );
+
+    let quiet = StartupProgress {
+        phase: StartupPhase::Launching,
+        line: None,
+        elapsed_secs: 4,
+    };
+    assert_eq!(serde_json::to_value(&quiet).unwrap()["line"], serde_json::Value::Null);
+}
+
+#[test]
+fn test_startup_timeout_bounds() {
+    assert!(validate_startup_timeout(120).is_ok());
+    assert!(validate_startup_timeout(MAX_STARTUP_TIMEOUT_SECS).is_ok());
+    assert!(validate_startup_timeout(0).is_err());
+    assert!(validate_startup_timeout(MAX_STARTUP_TIMEOUT_SECS + 1).is_err());
+}
@@ -0,0 +1,79 @@
+// Checks the allow-lists that launch options from the webview must pass
+// before they reach the sidecar:
+//   cargo test --test server_options_test
+
+use std::collections::HashMap;
+use voicebox::server_auth::AUTH_TOKEN_ENV;
+use voicebox::server_options::{ServerLaunchOptions, MODELS_DIR_ENV};
+
+fn with_args(args: &[&str]) -> ServerLaunchOptions {
+    ServerLaunchOptions {
+        extra_args: args.iter().map(|arg| arg.to_string()).collect(),
+        ..Default::default()
+    }
+}
+
+fn with_env(name: &str, value: &str) -> ServerLaunchOptions {
+    ServerLaunchOptions {
+        env: HashMap::from([(name.to_string(), value.to_string())]),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_only_known_flags_pass() {
+    assert!(with_args(&["--offline", "--no-access-log", "--idle-timeout-minutes=30"]).validate().is_ok());
+
+    // The app's own flags, and anything the server doesn't know
+    for arg in ["--port=9000", "--data-dir=/tmp", "--host=0.0.0.0", "--reload", "; rm -rf ~"] {
+        assert!(with_args(&[arg]).validate().is_err(), "{} was accepted", arg);
+    }
+    assert!(with_args(&["--offline=yes"]).validate().is_err());
+    assert!(with_args(&["--idle-timeout-minutes"]).validate().is_err());
+    assert!(with_args(&["--idle-timeout-minutes=0"]).validate().is_err());
+    assert!(with_args(&["--idle-timeout-minutes=soon"]).validate().is_err());
+}
+
+#[test]
+fn test_only_allowed_variables_pass() {
+    assert!(with_env("CUDA_VISIBLE_DEVICES", "1").validate().is_ok());
+    assert!(with_env("HF_HOME", "/data/hf").validate().is_ok());
+
+    // The remote-mode token is the app's to set
+    let refused = [
+        "LD_PRELOAD",
+        "DYLD_INSERT_LIBRARIES",
+        "PATH",
+        "PYTHONPATH",
+        "cuda_visible_devices",
+        "",
+        AUTH_TOKEN_ENV,
+    ];
+    for name in refused {
+        assert!(with_env(name, "x").validate().is_err(), "{:?} was accepted", name);
+    }
+    assert!(with_env("HF_HOME", "/data\0/hf").validate().is_err());
+}
+
+#[test]
+fn test_models_dir_and_log_level() {
+    let options = ServerLaunchOptions {
+        models_dir: Some(if cfg!(windows) { "D:\\models" } else { "/models" }.to_string()),
+        log_level: Some("Debug".to_string()),
+        ..Default::default()
+    };
+    assert!(options.validate().is_ok());
+    assert_eq!(options.envs(), vec![(MODELS_DIR_ENV.to_string(), options.models_dir.clone().unwrap())]);
+    assert_eq!(options.parsed_log_level().unwrap().map(|level| level.as_str()), Some("debug"));
+
+    let relative = ServerLaunchOptions {
+        models_dir: Some("models".to_string()),
+        ..Default::default()
+    };
+    assert!(relative.validate().is_err());
+    let unknown_level = ServerLaunchOptions {
+        log_level: Some("verbose".to_string()),
+        ..Default::default()
+    };
+    assert!(unknown_level.validate().is_err());
+}
@@ -0,0 +1,144 @@
+// Splits captured sidecar output (a tqdm download and a traceback with a
+// 20 KB line) the way start_server reads it, in chunks cut at awkward places,
+// and keeps it in the server log:
+//   cargo test --test server_output_test
+
+use voicebox::server_output::{self, LineSplitter, LogStream, OutputLine, ServerLog, MAX_LINE_BYTES};
+
+const TQDM: &[u8] = include_bytes!("fixtures/tqdm_download.txt");
+const TRACEBACK: &[u8] = include_bytes!("fixtures/traceback_long_line.txt");
+
+/// Every line of `data`, pushed `chunk` bytes at a time.
+fn split(data: &[u8], chunk: usize) -> Vec<OutputLine> {
+    let mut splitter = LineSplitter::default();
+    let mut lines: Vec<OutputLine> = data.chunks(chunk).flat_map(|c| splitter.push(c)).collect();
+    lines.extend(splitter.finish());
+    lines
+}
+
+#[test]
+fn test_progress_redraws_become_one_line() {
+    let lines = split(TQDM, 64 * 1024);
+    let texts: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+    assert_eq!(lines.len(), 4, "{:#?}", texts);
+    assert_eq!(lines[0].updates, 0);
+    assert!(texts[1].starts_with("model.safetensors: 100%|██████████| 1.23G/1.23G"), "{}", texts[1]);
+    assert_eq!(lines[1].updates, 51);
+    // Ended by \r\n, which is a line end rather than another redraw
+    assert!(texts[2].starts_with("Fetching 3 files: 100%"), "{}", texts[2]);
+    assert_eq!(lines[2].updates, 3);
+    assert_eq!(texts[3], "INFO:     Model loaded in 4.2s");
+    assert!(lines[1].to_string().ends_with("[progress line updated 51 times]"));
+}
+
+#[test]
+fn test_chunk_boundaries_do_not_matter() {
+    let whole = split(TQDM, TQDM.len());
+    // 1 splits every \r\n; 7 cuts the progress bar's characters apart
+    for chunk in [1, 2, 7, 1000] {
+        assert_eq!(split(TQDM, chunk), whole, "{} byte chunks", chunk);
+        assert_eq!(split(TRACEBACK, chunk), split(TRACEBACK, TRACEBACK.len()), "{} byte chunks", chunk);
+    }
+}
+
+#[test]
+fn test_long_lines_are_cut() {
+    let lines = split(TRACEBACK, 4096);
+    assert_eq!(lines.len(), 7);
+    assert_eq!(lines[0].text, "Traceback (most recent call last):");
+    let long = &lines[5];
+    assert!(long.text.starts_with("ValueError: Speaker embedding has the wrong shape: tensor(["));
+    assert_eq!(long.text.len(), MAX_LINE_BYTES);
+    let raw = TRACEBACK.split(|b| *b == b'\n').nth(5).unwrap();
+    assert_eq!(long.truncated_bytes as usize, raw.len() - MAX_LINE_BYTES);
+    assert!(long.to_string().ends_with(&format!("… [{} bytes truncated]", long.truncated_bytes)));
+    // The line after it is intact
+    assert_eq!(lines[6].text, "ERROR:    Generation failed");
+    assert_eq!(lines[6].truncated_bytes, 0);
+}
+
+#[test]
+fn test_cut_never_splits_a_character() {
+    let mut line = vec![b'a'; MAX_LINE_BYTES - 1];
+    line.extend("é and more".as_bytes());
+    line.push(b'\n');
+    let lines = split(&line, 100);
+    assert_eq!(lines[0].text.len(), MAX_LINE_BYTES - 1);
+    assert!(lines[0].text.bytes().all(|b| b == b'a'));
+    assert_eq!(lines[0].truncated_bytes as usize, line.len() - 1 - (MAX_LINE_BYTES - 1));
+}
+
+#[test]
+fn test_unterminated_output_is_kept_on_close() {
+    let mut splitter = LineSplitter::default();
+    assert!(splitter.push(b"Killed").is_empty());
+    assert_eq!(splitter.finish().unwrap().text, "Killed");
+    assert!(splitter.finish().is_none());
+
+    // A bar that never finished shows where it stopped
+    assert!(splitter.push(b"\r 10%|#\r 20%|##\r").is_empty());
+    let last = splitter.finish().unwrap();
+    assert_eq!((last.text.as_str(), last.updates), (" 20%|##", 3));
+
+    assert_eq!(split(b"\n\nok\r\n", 3).len(), 3);
+}
+
+#[test]
+fn test_tail_keeps_the_marked_lines() {
+    let mut splitter = LineSplitter::default();
+    let log = ServerLog::default();
+    log.push(LogStream::Stdout, "from an earlier start");
+    let first_line = log.next_seq();
+    for chunk in TQDM.chunks(100) {
+        server_output::take(&mut splitter, chunk, LogStream::Stderr, &log);
+    }
+    server_output::finish(&mut splitter, LogStream::Stderr, &log);
+    let kept = log.tail_since(first_line, 30);
+    assert_eq!(kept.len(), 4);
+    assert!(kept[1].contains("100%") && kept[1].ends_with("updated 51 times]"));
+    assert_eq!(log.tail_since(first_line, 2), kept[2..]);
+}
+
+#[test]
+fn test_log_keeps_the_newest_lines() {
+    let log = ServerLog::new(3);
+    for n in 0..5 {
+        log.push(if n % 2 == 0 { LogStream::Stdout } else { LogStream::Stderr }, &format!("line {}\r\n", n));
+    }
+    let lines = log.last(None);
+    let texts: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+    assert_eq!(texts, ["line 2", "line 3", "line 4"]);
+    assert_eq!(lines.iter().map(|line| line.seq).collect::<Vec<_>>(), [2, 3, 4]);
+    assert_eq!(lines[1].stream, LogStream::Stderr);
+    assert_eq!(log.last(Some(1)), lines[2..]);
+    assert_eq!(log.last(Some(10)), lines);
+    assert!(lines[0].to_string().ends_with(" [stdout] line 2"), "{}", lines[0]);
+}
+
+#[tokio::test]
+async fn test_a_slow_subscriber_never_holds_up_the_log() {
+    let log = ServerLog::new(100);
+    let mut events = log.subscribe(2);
+    // Nobody reads the events meanwhile; pushing must not wait
+    for n in 0..10 {
+        log.push(LogStream::Stdout, &format!("line {}", n));
+    }
+    assert_eq!(log.last(None).len(), 10);
+    assert_eq!(events.recv().await.unwrap().text, "line 0");
+    assert_eq!(events.recv().await.unwrap().text, "line 1");
+    assert!(events.try_recv().is_err(), "lines past the queue skip the event");
+}
+
+#[test]
+fn test_export_writes_one_line_each() {
+    let log = ServerLog::default();
+    log.push(LogStream::Stdout, "INFO:     Started server process");
+    log.push(LogStream::Stderr, "ERROR:    Generation failed");
+    let path = std::env::temp_dir().join(format!("voicebox-server-log-{}.txt", std::process::id()));
+    assert_eq!(log.export(&path), Ok(2));
+    let written = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let lines: Vec<&str> = written.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].ends_with(" [stderr] ERROR:    Generation failed"), "{}", lines[1]);
+}
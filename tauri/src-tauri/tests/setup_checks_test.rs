@@ -0,0 +1,186 @@
+// Reads the architecture out of synthetic ELF, PE and Mach-O headers, checks
+// sidecar binaries, disk space and NullSink output devices the way the setup
+// wizard does, and runs checks concurrently with one that hangs:
+//   cargo test --test setup_checks_test
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use voicebox::audio_output::{AudioOutputState, DeviceFormat, NullSink};
+use voicebox::setup_checks::{self, binary_arches, CheckStatus, Finding, SetupCheck};
+use voicebox::storage::{StorageProblem, StorageProblemKind, StorageStatus};
+
+const GB: u64 = 1024 * 1024 * 1024;
+
+fn elf(machine: u16) -> Vec<u8> {
+    let mut header = vec![0u8; 64];
+    header[..4].copy_from_slice(b"\x7fELF");
+    header[4] = 2; // 64-bit
+    header[5] = 1; // little-endian
+    header[18..20].copy_from_slice(&machine.to_le_bytes());
+    header
+}
+
+fn pe(machine: u16) -> Vec<u8> {
+    let mut header = vec![0u8; 0x100];
+    header[..2].copy_from_slice(b"MZ");
+    header[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+    header[0x80..0x84].copy_from_slice(b"PE\0\0");
+    header[0x84..0x86].copy_from_slice(&machine.to_le_bytes());
+    header
+}
+
+fn macho(cputype: u32) -> Vec<u8> {
+    let mut header = vec![0u8; 32];
+    header[..4].copy_from_slice(&0xfeed_facfu32.to_le_bytes());
+    header[4..8].copy_from_slice(&cputype.to_le_bytes());
+    header
+}
+
+fn fat(cputypes: &[u32]) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend_from_slice(&0xcafe_babeu32.to_be_bytes());
+    header.extend_from_slice(&(cputypes.len() as u32).to_be_bytes());
+    for &cputype in cputypes {
+        let mut slice = [0u8; 20];
+        slice[..4].copy_from_slice(&cputype.to_be_bytes());
+        header.extend_from_slice(&slice);
+    }
+    header
+}
+
+/// Write `header` as a binary with `mode`.
+fn binary(dir: &std::path::Path, name: &str, header: &[u8], mode: u32) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, header).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    path
+}
+
+fn storage(available_bytes: Option<u64>, problem: Option<StorageProblemKind>) -> StorageStatus {
+    let path = PathBuf::from("/data/voicebox");
+    StorageStatus {
+        writable: problem.is_none(),
+        available_bytes,
+        problem: problem.map(|kind| StorageProblem {
+            kind,
+            path: path.clone(),
+            detail: "test".to_string(),
+        }),
+        path,
+    }
+}
+
+#[test]
+fn reads_the_architecture_from_each_header_format() {
+    assert_eq!(binary_arches(&elf(0x3e)), vec!["x86_64"]);
+    assert_eq!(binary_arches(&elf(0xb7)), vec!["aarch64"]);
+    assert_eq!(binary_arches(&pe(0x8664)), vec!["x86_64"]);
+    assert_eq!(binary_arches(&pe(0xaa64)), vec!["aarch64"]);
+    assert_eq!(binary_arches(&macho(0x0100_000c)), vec!["aarch64"]);
+    assert_eq!(binary_arches(&fat(&[0x0100_0007, 0x0100_000c])), vec!["x86_64", "aarch64"]);
+
+    assert!(binary_arches(b"#!/bin/sh\n").is_empty());
+    assert!(binary_arches(&elf(0x1234)).is_empty());
+    // Truncated: the PE offset points past what was read
+    assert!(binary_arches(&pe(0x8664)[..0x60]).is_empty());
+}
+
+#[test]
+fn checks_the_server_binary() {
+    let scratch = tempfile::tempdir().unwrap();
+    let dir = scratch.path();
+
+    let missing = setup_checks::server_binary(&dir.join("voicebox-server"), "x86_64", "linux");
+    assert_eq!(missing.status, CheckStatus::Fail);
+    assert!(missing.fix_hint.is_some());
+
+    let linux = binary(dir, "linux", &elf(0x3e), 0o755);
+    assert_eq!(setup_checks::server_binary(&linux, "x86_64", "linux").status, CheckStatus::Pass);
+    let wrong_arch = setup_checks::server_binary(&linux, "aarch64", "linux");
+    assert_eq!(wrong_arch.status, CheckStatus::Fail);
+    assert!(wrong_arch.detail.contains("x86_64"), "{}", wrong_arch.detail);
+
+    // Runs under Rosetta, only slower
+    let intel = binary(dir, "intel", &macho(0x0100_0007), 0o755);
+    assert_eq!(setup_checks::server_binary(&intel, "aarch64", "macos").status, CheckStatus::Warn);
+    let universal = binary(dir, "universal", &fat(&[0x0100_0007, 0x0100_000c]), 0o755);
+    assert_eq!(setup_checks::server_binary(&universal, "aarch64", "macos").status, CheckStatus::Pass);
+
+    #[cfg(unix)]
+    {
+        let not_executable = binary(dir, "not-executable", &elf(0x3e), 0o644);
+        let finding = setup_checks::server_binary(&not_executable, "x86_64", "linux");
+        assert_eq!(finding.status, CheckStatus::Fail);
+        assert!(finding.detail.contains("executable"), "{}", finding.detail);
+    }
+}
+
+#[test]
+fn checks_room_for_the_models() {
+    let models = setup_checks::MODELS_BYTES;
+    assert_eq!(setup_checks::disk_space(&storage(Some(20 * GB), None), models).status, CheckStatus::Pass);
+    assert_eq!(setup_checks::disk_space(&storage(Some(GB), None), models).status, CheckStatus::Warn);
+    assert_eq!(setup_checks::disk_space(&storage(None, None), models).status, CheckStatus::Warn);
+
+    for kind in [StorageProblemKind::ReadOnly, StorageProblemKind::InsufficientSpace] {
+        let finding = setup_checks::disk_space(&storage(Some(0), Some(kind)), models);
+        assert_eq!(finding.status, CheckStatus::Fail);
+        assert!(finding.fix_hint.is_some());
+    }
+}
+
+#[test]
+fn checks_the_default_output_device() {
+    let format = DeviceFormat {
+        sample_rate: 48000,
+        channels: 2,
+    };
+    let output = AudioOutputState::with_backend(Arc::new(NullSink::new(format)));
+    let finding = setup_checks::output_device(&output);
+    assert_eq!(finding.status, CheckStatus::Pass);
+    assert!(finding.detail.contains("48000 Hz"), "{}", finding.detail);
+
+    let none = AudioOutputState::with_backend(Arc::new(NullSink::new(format).with_devices(&[])));
+    let finding = setup_checks::output_device(&none);
+    assert_eq!(finding.status, CheckStatus::Fail);
+    assert!(finding.fix_hint.is_some());
+}
+
+#[tokio::test]
+async fn runs_checks_at_once_and_times_out_hung_ones() {
+    let checks = [SetupCheck::ServerBinary, SetupCheck::DiskSpace, SetupCheck::OutputDevice];
+    let timeout = Duration::from_millis(300);
+    let started = Instant::now();
+    let results = setup_checks::run_all(&checks, timeout, |check| async move {
+        match check {
+            // Hangs well past the timeout
+            SetupCheck::DiskSpace => {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                Finding::pass("unreachable")
+            }
+            _ => {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                Finding::pass("ok")
+            }
+        }
+    })
+    .await;
+    let elapsed = started.elapsed();
+
+    let order: Vec<SetupCheck> = results.iter().map(|result| result.check).collect();
+    assert_eq!(order, checks);
+    assert_eq!(results[0].status, CheckStatus::Pass);
+    assert_eq!(results[1].status, CheckStatus::Warn);
+    assert!(results[1].fix_hint.is_some());
+    assert_eq!(results[2].status, CheckStatus::Pass);
+    // Concurrently: one timeout, not the sum of the checks
+    assert!(elapsed < Duration::from_millis(600), "took {:?}", elapsed);
+    assert!(results[1].duration_ms >= 300);
+}
@@ -0,0 +1,82 @@
+// Probes temp directories for the degraded-storage checks: a usable one, one
+// that can't be created and one "full" by a minimum no disk meets, plus the
+// free-space guard and settings that stop being saved meanwhile:
+//   cargo test --test storage_test
+
+use voicebox::error::VoiceboxError;
+use voicebox::settings::SettingsState;
+use voicebox::storage::{self, StorageProblemKind, StorageStatus};
+
+#[test]
+fn test_usable_dir_is_created_and_left_clean() {
+    let scratch = tempfile::tempdir().unwrap();
+    let root = scratch.path();
+    let dir = root.join("data");
+    let status = storage::probe(&dir, 0);
+    assert!(status.writable);
+    assert!(!status.is_degraded(), "{:?}", status.problem);
+    assert!(status.available_bytes.unwrap() > 0);
+    assert!(dir.is_dir());
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0, "the probe file is removed");
+}
+
+#[test]
+fn test_problems_are_classified() {
+    let scratch = tempfile::tempdir().unwrap();
+    let root = scratch.path();
+    std::fs::write(root.join("file"), b"").unwrap();
+    let blocked = storage::probe(&root.join("file").join("data"), 0);
+    assert!(!blocked.writable);
+    let problem = blocked.problem.unwrap();
+    assert_eq!(problem.kind, StorageProblemKind::CannotCreate);
+    assert_eq!(problem.path, root.join("file").join("data"));
+
+    // Writable, but short of space
+    let full = storage::probe(root, u64::MAX);
+    assert!(full.writable);
+    assert_eq!(full.problem.unwrap().kind, StorageProblemKind::InsufficientSpace);
+
+    let unresolved = StorageStatus::unresolved("No app data dir");
+    assert!(unresolved.is_degraded() && !unresolved.writable);
+    let json = serde_json::to_value(&unresolved).unwrap();
+    assert_eq!(json["problem"]["kind"], "cannot_create");
+    assert_eq!(json["problem"]["detail"], "No app data dir");
+}
+
+#[test]
+fn test_free_space_guard_reports_both_sizes() {
+    let scratch = tempfile::tempdir().unwrap();
+    let root = scratch.path();
+    let available = storage::ensure_free_space(root, 1).unwrap();
+    // A directory that doesn't exist yet is judged by its volume
+    assert!(storage::ensure_free_space(&root.join("later"), 1).is_ok());
+
+    let error = storage::ensure_free_space(root, u64::MAX).unwrap_err();
+    assert_eq!(error.code(), "insufficient_space");
+    let VoiceboxError::InsufficientSpace {
+        required_bytes,
+        available_bytes,
+        ..
+    } = &error
+    else {
+        panic!("{:?}", error);
+    };
+    assert_eq!(*required_bytes, u64::MAX);
+    assert!(available_bytes.abs_diff(available) < 1 << 30);
+    let json = serde_json::to_value(&error).unwrap();
+    assert_eq!(json["details"]["required_bytes"], u64::MAX);
+}
+
+#[test]
+fn test_settings_wait_until_they_can_be_saved() {
+    let scratch = tempfile::tempdir().unwrap();
+    let dir = scratch.path();
+    let settings = SettingsState::load(Some(dir));
+    settings.set_persistent(false).unwrap();
+    settings.update(|s| s.max_import_mb = 7).unwrap();
+    assert_eq!(settings.get().max_import_mb, 7);
+    assert_eq!(std::fs::read_dir(dir).unwrap().count(), 0);
+
+    settings.set_persistent(true).unwrap();
+    assert_eq!(SettingsState::load(Some(dir)).get().max_import_mb, 7);
+}
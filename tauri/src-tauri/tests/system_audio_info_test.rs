@@ -0,0 +1,81 @@
+// Feeds the system output debouncer readings at chosen times, and checks that
+// only settled changes are reported, once each, that the default device is
+// picked from the list, and that pactl's volume and mute output parse:
+//   cargo test --test system_audio_info_test
+
+use std::time::{Duration, Instant};
+use voicebox::audio_output::{AudioOutputDevice, DeviceState, DeviceTransport};
+use voicebox::system_audio_info::{self, Debouncer, SystemOutputInfo, DEBOUNCE};
+
+fn info(device: &str, volume: f32) -> SystemOutputInfo {
+    SystemOutputInfo {
+        device_id: Some(device.to_string()),
+        device_name: Some(device.to_string()),
+        transport: DeviceTransport::from_name(device),
+        volume: Some(volume),
+        muted: Some(false),
+    }
+}
+
+#[test]
+fn test_reports_settled_changes_once() {
+    let start = Instant::now();
+    let at = |ms: u64| start + Duration::from_millis(ms);
+    let mut debouncer = Debouncer::default();
+    // The first reading is the baseline
+    assert_eq!(debouncer.observe(info("Speakers", 0.5), at(0)), None);
+    assert_eq!(debouncer.observe(info("Speakers", 0.5), at(1000)), None);
+
+    // Dragging the slider: each reading restarts the wait
+    assert_eq!(debouncer.observe(info("Speakers", 0.4), at(2000)), None);
+    assert_eq!(debouncer.observe(info("Speakers", 0.3), at(2300)), None);
+    assert_eq!(debouncer.observe(info("Speakers", 0.3), at(2400)), None);
+    let settled = at(2300) + DEBOUNCE;
+    assert_eq!(debouncer.observe(info("Speakers", 0.3), settled), Some(info("Speakers", 0.3)));
+    assert_eq!(debouncer.observe(info("Speakers", 0.3), settled + DEBOUNCE), None);
+
+    // A blip that goes back before it settles isn't reported
+    assert_eq!(debouncer.observe(info("AirPods Pro", 0.3), at(5000)), None);
+    assert_eq!(debouncer.observe(info("Speakers", 0.3), at(5200)), None);
+    assert_eq!(debouncer.observe(info("AirPods Pro", 0.3), at(5400)), None);
+    assert_eq!(debouncer.observe(info("AirPods Pro", 0.3), at(5600)), None);
+
+    let switched = debouncer.observe(info("AirPods Pro", 0.3), at(5400) + DEBOUNCE).unwrap();
+    assert_eq!(switched.transport, DeviceTransport::Bluetooth);
+}
+
+#[test]
+fn test_reads_the_default_device() {
+    let device = |id: &str, is_default: bool| AudioOutputDevice {
+        id: id.to_string(),
+        name: format!("{} name", id),
+        is_default,
+        transport: DeviceTransport::Usb,
+        state: DeviceState::Active,
+        default_sample_rate: 48000,
+        channels: 2,
+        endpoint_id: None,
+    };
+    let read = system_audio_info::read(&[device("hdmi", false), device("usb", true)]);
+    assert_eq!(read.device_id.as_deref(), Some("usb"));
+    assert_eq!(read.device_name.as_deref(), Some("usb name"));
+    assert_eq!(read.transport, DeviceTransport::Usb);
+    // Best effort: whatever the machine reports is a level
+    assert!(read.volume.is_none_or(|volume| (0.0..=1.0).contains(&volume)));
+
+    let none = system_audio_info::read(&[device("hdmi", false)]);
+    assert_eq!((none.device_id, none.volume, none.muted), (None, None, None));
+}
+
+#[test]
+fn test_parses_pactl_output() {
+    let volume = "Volume: front-left: 29491 /  45% / -20.81 dB,   front-right: 32768 /  50% / -18.06 dB\n\
+                  \x20       balance 0.05\n";
+    assert_eq!(system_audio_info::parse_pactl_volume(volume), Some(0.475));
+    assert_eq!(system_audio_info::parse_pactl_volume("Volume: mono: 65536 / 100% / 0.00 dB"), Some(1.0));
+    assert_eq!(system_audio_info::parse_pactl_volume("No such entity"), None);
+
+    assert_eq!(system_audio_info::parse_pactl_mute("Mute: yes\n"), Some(true));
+    assert_eq!(system_audio_info::parse_pactl_mute("Mute: no"), Some(false));
+    assert_eq!(system_audio_info::parse_pactl_mute("Failed to get sink"), None);
+}
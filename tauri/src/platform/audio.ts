@@ -1,5 +1,26 @@
 import { invoke } from '@tauri-apps/api/core';
-import type { PlatformAudio, AudioDevice } from '@/platform/types';
+import { listenEvent } from '@/platform/events';
+import type {
+  PlatformAudio,
+  AudioDevice,
+  AudioDevicesChanged,
+  CaptureLevel,
+  CaptureStopped,
+  PlaybackFinished,
+  PlaybackLevel,
+  PlaybackProgress,
+  PlaybackStatus,
+} from '@/platform/types';
+
+/** What finalize_capture returns; the WAV itself comes from get_capture_chunk. */
+interface FinalizedCapture {
+  session: number;
+  total_bytes: number;
+  chunk_size: number;
+  num_chunks: number;
+  sample_rate: number;
+  duration_secs: number;
+}
 
 export const tauriAudio: PlatformAudio = {
   isSystemAudioSupported(): boolean {
@@ -9,31 +30,59 @@ export const tauriAudio: PlatformAudio = {
 
   async startSystemAudioCapture(maxDurationSecs: number): Promise<void> {
     await invoke('start_system_audio_capture', {
-      maxDurationSecs,
+      request: { max_duration_secs: maxDurationSecs },
     });
   },
 
   async stopSystemAudioCapture(): Promise<Blob> {
-    const base64Data = await invoke<string>('stop_system_audio_capture');
-
-    // Convert base64 to Blob
-    const binaryString = atob(base64Data);
-    const bytes = new Uint8Array(binaryString.length);
-    for (let i = 0; i < binaryString.length; i++) {
-      bytes[i] = binaryString.charCodeAt(i);
+    // Fetched a chunk at a time, so a long recording never crosses IPC as one string
+    const finalized = await invoke<FinalizedCapture>('finalize_capture');
+    try {
+      const parts: Uint8Array[] = [];
+      for (let index = 0; index < finalized.num_chunks; index++) {
+        const base64Data = await invoke<string>('get_capture_chunk', { index });
+        const binaryString = atob(base64Data);
+        const bytes = new Uint8Array(binaryString.length);
+        for (let i = 0; i < binaryString.length; i++) {
+          bytes[i] = binaryString.charCodeAt(i);
+        }
+        parts.push(bytes);
+      }
+      return new Blob(parts, { type: 'audio/wav' });
+    } finally {
+      await invoke('release_capture');
     }
-
-    return new Blob([bytes], { type: 'audio/wav' });
   },
 
   async listOutputDevices(): Promise<AudioDevice[]> {
     return await invoke<AudioDevice[]>('list_audio_output_devices');
   },
 
-  async playToDevices(audioData: Uint8Array, deviceIds: string[]): Promise<void> {
+  async getDeviceLatency(deviceId: string): Promise<number | null> {
+    return await invoke<number | null>('get_device_latency', { deviceId });
+  },
+
+  async playTestTone(
+    deviceId: string,
+    durationSecs: number,
+    frequencyHz?: number,
+  ): Promise<'completed' | 'cancelled'> {
+    return await invoke<'completed' | 'cancelled'>('play_test_tone', { deviceId, durationSecs, frequencyHz });
+  },
+
+  async playToDevices(audioData: Uint8Array, deviceIds: string[], title?: string): Promise<void> {
     await invoke('play_audio_to_devices', {
       audioData: Array.from(audioData),
       deviceIds,
+      request: { title: title ?? null },
+    });
+  },
+
+  async playFileToDevices(path: string, deviceIds: string[], title?: string): Promise<void> {
+    await invoke('play_audio_file_to_devices', {
+      path,
+      deviceIds,
+      request: { title: title ?? null },
     });
   },
 
@@ -42,4 +91,32 @@ export const tauriAudio: PlatformAudio = {
       console.error('Failed to stop audio playback:', error);
     });
   },
+
+  async getPlaybackStatus(): Promise<PlaybackStatus> {
+    return await invoke<PlaybackStatus>('get_playback_status');
+  },
+
+  async subscribeCaptureLevel(callback: (level: CaptureLevel) => void): Promise<() => void> {
+    return await listenEvent<CaptureLevel>('capture-level', callback);
+  },
+
+  async subscribeCaptureStopped(callback: (stopped: CaptureStopped) => void): Promise<() => void> {
+    return await listenEvent<CaptureStopped>('capture-stopped', callback);
+  },
+
+  async subscribePlaybackProgress(callback: (progress: PlaybackProgress) => void): Promise<() => void> {
+    return await listenEvent<PlaybackProgress>('playback-progress', callback);
+  },
+
+  async subscribePlaybackLevel(callback: (level: PlaybackLevel) => void): Promise<() => void> {
+    return await listenEvent<PlaybackLevel>('playback-level', callback);
+  },
+
+  async subscribePlaybackFinished(callback: (finished: PlaybackFinished) => void): Promise<() => void> {
+    return await listenEvent<PlaybackFinished>('playback-finished', callback);
+  },
+
+  async subscribeDevicesChanged(callback: (changed: AudioDevicesChanged) => void): Promise<() => void> {
+    return await listenEvent<AudioDevicesChanged>('audio-devices-changed', callback);
+  },
 };
@@ -0,0 +1,29 @@
+import { listen, type UnlistenFn } from '@tauri-apps/api/event';
+
+/** Name of the event the Rust side sends batched events as. */
+const BATCH_EVENT = 'batched-events';
+
+interface Batch {
+  events: { topic: string; payload: unknown }[];
+  dropped: Record<string, number>;
+}
+
+/**
+ * Listen for an event from the Rust side, whether it arrives on its own or
+ * inside a batch. Most events are batched; a few (capture-stopped,
+ * window-closing, ...) are sent right away.
+ */
+export async function listenEvent<T>(topic: string, callback: (payload: T) => void): Promise<UnlistenFn> {
+  const unlistenDirect = await listen<T>(topic, (event) => callback(event.payload));
+  const unlistenBatch = await listen<Batch>(BATCH_EVENT, (event) => {
+    for (const batched of event.payload.events) {
+      if (batched.topic === topic) {
+        callback(batched.payload as T);
+      }
+    }
+  });
+  return () => {
+    unlistenDirect();
+    unlistenBatch();
+  };
+}
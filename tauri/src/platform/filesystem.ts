@@ -1,4 +1,5 @@
-import type { PlatformFilesystem, FileFilter } from '@/platform/types';
+import { invoke } from '@tauri-apps/api/core';
+import type { AppFolder, PlatformFilesystem, FileFilter } from '@/platform/types';
 
 export const tauriFilesystem: PlatformFilesystem = {
   async saveFile(filename: string, blob: Blob, filters?: FileFilter[]) {
@@ -23,4 +24,8 @@ export const tauriFilesystem: PlatformFilesystem = {
     const arrayBuffer = await blob.arrayBuffer();
     await writeFile(resolvedPath, new Uint8Array(arrayBuffer));
   },
+
+  async openFolder(folder: AppFolder) {
+    return invoke<string>('reveal_path', { target: { kind: folder } });
+  },
 };
@@ -1,16 +1,77 @@
 import { invoke } from '@tauri-apps/api/core';
-import { listen, emit } from '@tauri-apps/api/event';
-import type { PlatformLifecycle } from '@/platform/types';
+import { emit } from '@tauri-apps/api/event';
+import { listenEvent } from '@/platform/events';
+import type {
+  ModelDownloadReport,
+  PlatformLifecycle,
+  ServerLaunchOptions,
+  StartupProgress,
+} from '@/platform/types';
+
+interface ServerInfo {
+  url: string;
+  remote: boolean;
+  remote_urls: string[];
+  port: number;
+  pid: number | null;
+  adopted_existing: boolean;
+  started_at: number;
+  /** Milliseconds from spawn to ready, for a server this start spawned. */
+  startup_ms: number | null;
+  auth_token_present: boolean;
+  /** Bearer token other machines must send to a remote-mode server the app started. */
+  auth_token: string | null;
+  version: string | null;
+  offline: boolean | null;
+  firewall_rule:
+    | { status: 'created' | 'existing' | 'not_applicable' }
+    | { status: 'failed'; reason: string }
+    | null;
+  managed: boolean;
+  launch_options: ServerLaunchOptions | null;
+}
+
+/** What `stop_server` did; it rejects instead if the server survived being killed. */
+interface StopOutcome {
+  terminated: boolean;
+  waited_ms: number;
+  forced: boolean;
+}
+
+/** Error payload rejected by `start_server`; branch on `kind`. */
+export interface ServerStartError {
+  kind:
+    | 'spawn_failed'
+    | 'timeout'
+    | 'crashed_during_startup'
+    | 'port_conflict'
+    | 'binary_problem'
+    | 'invalid_options'
+    | 'restart_required'
+    | 'internal';
+  message: string;
+  hints: string[];
+  os_error?: string;
+  last_phase?: StartupProgress['phase'];
+  tail_lines?: string[];
+  exit_code?: number | null;
+  occupant?: string;
+  problem?: 'not_found' | 'not_executable' | 'dev_server_not_running';
+}
 
 class TauriLifecycle implements PlatformLifecycle {
   onServerReady?: () => void;
 
-  async startServer(remote = false): Promise<string> {
+  async startServer(
+    remote = false,
+    options?: ServerLaunchOptions,
+    startupTimeoutSecs?: number,
+  ): Promise<string> {
     try {
-      const result = await invoke<string>('start_server', { remote });
-      console.log('Server started:', result);
+      const info = await invoke<ServerInfo>('start_server', { remote, options, startupTimeoutSecs });
+      console.log('Server started:', info);
       this.onServerReady?.();
-      return result;
+      return info.url;
     } catch (error) {
       console.error('Failed to start server:', error);
       throw error;
@@ -19,14 +80,38 @@ class TauriLifecycle implements PlatformLifecycle {
 
   async stopServer(): Promise<void> {
     try {
-      await invoke('stop_server');
-      console.log('Server stopped');
+      const outcome = await invoke<StopOutcome>('stop_server');
+      console.log('Server stopped:', outcome);
     } catch (error) {
       console.error('Failed to stop server:', error);
       throw error;
     }
   }
 
+  async restartServer(remote?: boolean, options?: ServerLaunchOptions): Promise<string> {
+    try {
+      const url = await invoke<string>('restart_server', { remote, options });
+      console.log('Server restarted:', url);
+      this.onServerReady?.();
+      return url;
+    } catch (error) {
+      console.error('Failed to restart server:', error);
+      throw error;
+    }
+  }
+
+  async connectToServer(url: string, authToken?: string): Promise<string> {
+    try {
+      const info = await invoke<ServerInfo>('connect_to_server', { url, authToken });
+      console.log('Connected to server:', info);
+      this.onServerReady?.();
+      return info.url;
+    } catch (error) {
+      console.error('Failed to connect to server:', error);
+      throw error;
+    }
+  }
+
   async setKeepServerRunning(keepRunning: boolean): Promise<void> {
     try {
       await invoke('set_keep_server_running', { keepRunning });
@@ -35,34 +120,51 @@ class TauriLifecycle implements PlatformLifecycle {
     }
   }
 
-  async setupWindowCloseHandler(): Promise<void> {
+  async getServerAuthToken(): Promise<string | null> {
     try {
-      // Listen for window close request from Rust
-      await listen<null>('window-close-requested', async () => {
-        // Import store here to avoid circular dependency
-        const { useServerStore } = await import('@/stores/serverStore');
-        const keepRunning = useServerStore.getState().keepServerRunningOnClose;
+      return await invoke<string | null>('get_server_auth_token');
+    } catch (error) {
+      console.error('Failed to get server auth token:', error);
+      return null;
+    }
+  }
 
-        // Check if server was started by this app instance
-        // @ts-expect-error - accessing module-level variable from another module
-        const serverStartedByApp = window.__voiceboxServerStartedByApp ?? false;
+  async setAutoRestart(enabled: boolean): Promise<void> {
+    try {
+      await invoke('set_auto_restart', { enabled });
+    } catch (error) {
+      console.error('Failed to set server auto-restart:', error);
+    }
+  }
 
-        if (!keepRunning && serverStartedByApp) {
-          // Stop server before closing (only if we started it)
-          try {
-            await this.stopServer();
-          } catch (error) {
-            console.error('Failed to stop server on close:', error);
-          }
-        }
+  async subscribeStartupProgress(callback: (progress: StartupProgress) => void): Promise<() => void> {
+    return await listenEvent<StartupProgress>('server-startup-progress', callback);
+  }
 
-        // Emit event back to Rust to allow close
-        await emit('window-close-allowed');
+  async setupWindowCloseHandler(): Promise<void> {
+    try {
+      // Rust decides whether the server stops on close; this is informational only
+      await listenEvent<{ action: 'stop_server' | 'keep_server' }>('window-closing', (payload) => {
+        console.log('Window closing, server action:', payload.action);
       });
     } catch (error) {
       console.error('Failed to setup window close handler:', error);
     }
   }
+
+  reportModelDownload({ modelName, status, fraction }: ModelDownloadReport): void {
+    // Drives the dock/taskbar progress bar and the completion notification
+    const done =
+      status === 'complete'
+        ? emit('model-download-complete', { model_name: modelName })
+        : emit('model-download-progress', {
+            model_name: modelName,
+            // Clears the bar when tracking stopped without an outcome
+            fraction: status === 'aborted' ? null : (fraction ?? null),
+            error: status === 'error',
+          });
+    done.catch((error) => console.error('Failed to report model download:', error));
+  }
 }
 
 export const tauriLifecycle = new TauriLifecycle();
@@ -1,5 +1,6 @@
 import { getVersion } from '@tauri-apps/api/app';
-import type { PlatformMetadata } from '@/platform/types';
+import { invoke } from '@tauri-apps/api/core';
+import type { DataPaths, DataPathsMode, PlatformMetadata } from '@/platform/types';
 
 export const tauriMetadata: PlatformMetadata = {
   async getVersion(): Promise<string> {
@@ -10,5 +11,11 @@ export const tauriMetadata: PlatformMetadata = {
       return '0.1.0';
     }
   },
+  async getDataPaths(): Promise<DataPaths> {
+    const paths = await invoke<{ mode: DataPathsMode; app_data_dir: string; logs_dir: string }>(
+      'get_app_paths',
+    );
+    return { mode: paths.mode, appDataDir: paths.app_data_dir, logsDir: paths.logs_dir };
+  },
   isTauri: true,
 };
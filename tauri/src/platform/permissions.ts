@@ -0,0 +1,22 @@
+import { invoke } from '@tauri-apps/api/core';
+import type { PermissionKind, PermissionStatus, PlatformPermissions } from '@/platform/types';
+
+type CapturePermissionStatus = Exclude<PermissionStatus, 'not_applicable'>;
+
+export const tauriPermissions: PlatformPermissions = {
+  async getStatus() {
+    return invoke<Record<PermissionKind, PermissionStatus>>('get_permissions_status');
+  },
+
+  async request(kind: PermissionKind) {
+    return invoke<PermissionStatus>('request_permission', { kind });
+  },
+
+  async checkCapture() {
+    return invoke<CapturePermissionStatus>('check_capture_permission');
+  },
+
+  async requestCapture() {
+    return invoke<CapturePermissionStatus>('request_capture_permission');
+  },
+};
@@ -1,4 +1,14 @@
-import type { PlatformAudio, AudioDevice } from '@/platform/types';
+import type {
+  PlatformAudio,
+  AudioDevice,
+  AudioDevicesChanged,
+  CaptureLevel,
+  CaptureStopped,
+  PlaybackFinished,
+  PlaybackLevel,
+  PlaybackProgress,
+  PlaybackStatus,
+} from '@/platform/types';
 
 export const webAudio: PlatformAudio = {
   isSystemAudioSupported(): boolean {
@@ -17,11 +27,55 @@ export const webAudio: PlatformAudio = {
     return []; // No native device routing in web
   },
 
-  async playToDevices(_audioData: Uint8Array, _deviceIds: string[]): Promise<void> {
+  async getDeviceLatency(_deviceId: string): Promise<number | null> {
+    return null;
+  },
+
+  async playTestTone(
+    _deviceId: string,
+    _durationSecs: number,
+    _frequencyHz?: number,
+  ): Promise<'completed' | 'cancelled'> {
+    throw new Error('Native audio device routing is only available in the desktop app.');
+  },
+
+  async playToDevices(_audioData: Uint8Array, _deviceIds: string[], _title?: string): Promise<void> {
+    throw new Error('Native audio device routing is only available in the desktop app.');
+  },
+
+  async playFileToDevices(_path: string, _deviceIds: string[], _title?: string): Promise<void> {
     throw new Error('Native audio device routing is only available in the desktop app.');
   },
 
   stopPlayback(): void {
     // No-op for web
   },
+
+  async getPlaybackStatus(): Promise<PlaybackStatus> {
+    return { playing: false, paused: false, title: null, position_secs: 0, duration_secs: 0, device_ids: [] };
+  },
+
+  async subscribeCaptureLevel(_callback: (level: CaptureLevel) => void): Promise<() => void> {
+    return () => {};
+  },
+
+  async subscribeCaptureStopped(_callback: (stopped: CaptureStopped) => void): Promise<() => void> {
+    return () => {};
+  },
+
+  async subscribePlaybackProgress(_callback: (progress: PlaybackProgress) => void): Promise<() => void> {
+    return () => {};
+  },
+
+  async subscribePlaybackLevel(_callback: (level: PlaybackLevel) => void): Promise<() => void> {
+    return () => {};
+  },
+
+  async subscribePlaybackFinished(_callback: (finished: PlaybackFinished) => void): Promise<() => void> {
+    return () => {};
+  },
+
+  async subscribeDevicesChanged(_callback: (changed: AudioDevicesChanged) => void): Promise<() => void> {
+    return () => {};
+  },
 };
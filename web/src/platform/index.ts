@@ -4,6 +4,7 @@ import { webUpdater } from './updater';
 import { webAudio } from './audio';
 import { webLifecycle } from './lifecycle';
 import { webMetadata } from './metadata';
+import { webPermissions } from './permissions';
 
 export const webPlatform: Platform = {
   filesystem: webFilesystem,
@@ -11,4 +12,5 @@ export const webPlatform: Platform = {
   audio: webAudio,
   lifecycle: webLifecycle,
   metadata: webMetadata,
+  permissions: webPermissions,
 };
@@ -1,9 +1,18 @@
-import type { PlatformLifecycle } from '@/platform/types';
+import type {
+  ModelDownloadReport,
+  PlatformLifecycle,
+  ServerLaunchOptions,
+  StartupProgress,
+} from '@/platform/types';
 
 class WebLifecycle implements PlatformLifecycle {
   onServerReady?: () => void;
 
-  async startServer(_remote = false): Promise<string> {
+  async startServer(
+    _remote = false,
+    _options?: ServerLaunchOptions,
+    _startupTimeoutSecs?: number,
+  ): Promise<string> {
     // Web assumes server is running externally
     // Return a default URL - this should be configured via env vars
     const serverUrl = import.meta.env.VITE_SERVER_URL || 'http://localhost:17493';
@@ -15,13 +24,41 @@ class WebLifecycle implements PlatformLifecycle {
     // No-op for web - server is managed externally
   }
 
+  async restartServer(remote?: boolean): Promise<string> {
+    // Nothing to restart - server is managed externally
+    return this.startServer(remote);
+  }
+
+  async connectToServer(_url: string, _authToken?: string): Promise<string> {
+    // The web build always talks to the server it is configured with
+    return this.startServer();
+  }
+
+  async getServerAuthToken(): Promise<string | null> {
+    // The web build doesn't start servers, so there's no token of ours
+    return null;
+  }
+
   async setKeepServerRunning(_keep: boolean): Promise<void> {
     // No-op for web
   }
 
+  async setAutoRestart(_enabled: boolean): Promise<void> {
+    // No-op for web
+  }
+
+  async subscribeStartupProgress(_callback: (progress: StartupProgress) => void): Promise<() => void> {
+    // The web build doesn't start the server
+    return () => {};
+  }
+
   async setupWindowCloseHandler(): Promise<void> {
     // No-op for web - no window close handling needed
   }
+
+  reportModelDownload(_report: ModelDownloadReport): void {
+    // No-op for web - no dock or taskbar progress
+  }
 }
 
 export const webLifecycle = new WebLifecycle();
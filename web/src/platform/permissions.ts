@@ -0,0 +1,44 @@
+import type { PermissionKind, PermissionStatus, PlatformPermissions } from '@/platform/types';
+
+async function microphoneStatus(): Promise<PermissionStatus> {
+  try {
+    const result = await navigator.permissions.query({ name: 'microphone' as PermissionName });
+    if (result.state === 'granted') return 'granted';
+    if (result.state === 'denied') return 'denied';
+    return 'not_determined';
+  } catch {
+    // Firefox doesn't support querying the microphone permission
+    return 'not_determined';
+  }
+}
+
+export const webPermissions: PlatformPermissions = {
+  async getStatus() {
+    return {
+      screen_recording: 'not_applicable',
+      microphone: await microphoneStatus(),
+      notifications: 'not_applicable',
+    };
+  },
+
+  async request(kind: PermissionKind) {
+    if (kind !== 'microphone') return 'not_applicable';
+    try {
+      // The browser only prompts when asked for a stream
+      const stream = await navigator.mediaDevices.getUserMedia({ audio: true });
+      stream.getTracks().forEach((track) => track.stop());
+    } catch {
+      // Reported through the status below
+    }
+    return microphoneStatus();
+  },
+
+  // The browser has no system audio capture to gate
+  async checkCapture() {
+    return 'granted';
+  },
+
+  async requestCapture() {
+    return 'granted';
+  },
+};